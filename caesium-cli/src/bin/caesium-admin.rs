@@ -0,0 +1,642 @@
+extern crate caesium_server;
+extern crate clap;
+extern crate serde_json;
+
+#[cfg(test)]
+extern crate caesium_core;
+
+use caesium_server::server::admin::{AdminRequest, AdminResponse};
+use caesium_server::storage::error::StorageError;
+use caesium_server::storage::store::MetricStore;
+use clap::{App, AppSettings, Arg, SubCommand};
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const TIMEOUT_MS: u64 = 10000;
+
+fn main() -> Result<(), Error> {
+    let args = parse_args(env::args_os())?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    run_command(&args, &mut out)
+}
+
+#[derive(Debug)]
+struct Args {
+    server_addr: SocketAddr,
+    auth_token: Option<String>,
+    command: Command,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    // Sent to the server's admin port
+    Request(AdminRequest),
+
+    // Runs locally, since the server must be stopped while its database is restored
+    Restore { backup_dir: String, db_dir: String },
+}
+
+fn parse_args<I, T>(cli_args: I) -> Result<Args, Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = App::new("Caesium admin tool")
+        .about("Manage a running caesium server")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("SERVER_ADDR")
+                .short("a")
+                .long("addr")
+                .takes_value(true)
+                .help("Admin address of server (defaults to $CAESIUM_SERVER_ADMIN_ADDR, then 127.0.0.1:8004)"),
+        )
+        .arg(
+            Arg::with_name("AUTH_TOKEN")
+                .long("auth-token")
+                .takes_value(true)
+                .env("CAESIUM_AUTH_TOKEN")
+                .hide_env_values(true)
+                .help("Shared secret to send before the request, for servers that require one"),
+        )
+        .subcommand(
+            SubCommand::with_name("list-metrics")
+                .about("List stored metrics")
+                .arg(
+                    Arg::with_name("PATTERN")
+                        .long("pattern")
+                        .takes_value(true)
+                        .help("Only list metrics matching this pattern, for example `foo.*`"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("delete-metric")
+                .about("Delete every window stored for a metric")
+                .arg(Arg::with_name("METRIC").index(1).required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("trigger-downsample")
+                .about("Start a downsample pass without waiting for the next interval")
+                .arg(
+                    Arg::with_name("DRY_RUN")
+                        .long("dry-run")
+                        .help("Print what a downsample pass would do as JSON, without changing any data"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trigger-compaction")
+                .about("Compact the database and wait for compaction to finish")
+                .arg(
+                    Arg::with_name("METRIC")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Only compact windows stored for this metric"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("stats").about("Show metric count and disk usage"))
+        .subcommand(
+            SubCommand::with_name("rocksdb-stats")
+                .about("Show RocksDB internal statistics as one JSON object per line"),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Back up the database to a directory under the server's --backup-root, incrementally if it has earlier backups")
+                .arg(Arg::with_name("DIR").index(1).required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restore the latest backup to a database directory. Stop the server using that directory first.")
+                .arg(Arg::with_name("BACKUP_DIR").index(1).required(true))
+                .arg(Arg::with_name("DB_DIR").index(2).required(true)),
+        )
+        .get_matches_from(cli_args);
+    let default_addr =
+        env::var("CAESIUM_SERVER_ADMIN_ADDR").unwrap_or_else(|_| "127.0.0.1:8004".to_string());
+    let server_addr = matches
+        .value_of("SERVER_ADDR")
+        .unwrap_or(&default_addr)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(Error::ArgError("Expected socket address"))?;
+    let auth_token = matches.value_of("AUTH_TOKEN").map(|s| s.to_string());
+    let request = match matches.subcommand() {
+        ("restore", Some(sub)) => {
+            return Ok(Args {
+                server_addr,
+                auth_token,
+                command: Command::Restore {
+                    backup_dir: sub.value_of("BACKUP_DIR").unwrap().to_string(),
+                    db_dir: sub.value_of("DB_DIR").unwrap().to_string(),
+                },
+            })
+        }
+        ("list-metrics", Some(sub)) => AdminRequest::ListMetrics {
+            pattern: sub.value_of("PATTERN").map(|s| s.to_string()),
+        },
+        ("delete-metric", Some(sub)) => AdminRequest::DeleteMetric {
+            metric: sub.value_of("METRIC").unwrap().to_string(),
+        },
+        ("trigger-downsample", Some(sub)) if sub.is_present("DRY_RUN") => {
+            AdminRequest::DownsampleDryRun
+        }
+        ("trigger-downsample", _) => AdminRequest::TriggerDownsample,
+        ("trigger-compaction", Some(sub)) => AdminRequest::TriggerCompaction {
+            metric: sub.value_of("METRIC").map(|s| s.to_string()),
+        },
+        ("stats", _) => AdminRequest::Stats,
+        ("rocksdb-stats", _) => AdminRequest::RocksdbStats,
+        ("backup", Some(sub)) => AdminRequest::Backup {
+            dir: sub.value_of("DIR").unwrap().to_string(),
+        },
+        _ => return Err(Error::ArgError("Expected a subcommand")),
+    };
+    Ok(Args {
+        server_addr,
+        auth_token,
+        command: Command::Request(request),
+    })
+}
+
+fn run_command<W: Write>(args: &Args, out: &mut W) -> Result<(), Error> {
+    let request = match args.command {
+        Command::Request(ref request) => request,
+        Command::Restore {
+            ref backup_dir,
+            ref db_dir,
+        } => {
+            MetricStore::restore(backup_dir, db_dir)?;
+            writeln!(out, "Restored {} from {}", db_dir, backup_dir)?;
+            return Ok(());
+        }
+    };
+    match send_request(args.server_addr, &args.auth_token, request)? {
+        AdminResponse::Metrics { metrics } => {
+            for metric in metrics.iter() {
+                writeln!(out, "{}", metric)?;
+            }
+        }
+        AdminResponse::Deleted { metric } => writeln!(out, "Deleted metric {}", metric)?,
+        AdminResponse::DownsampleTriggered => writeln!(out, "Triggered downsample")?,
+        AdminResponse::DownsampleReport { report } => {
+            writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?
+        }
+        AdminResponse::Compacted { metric } => match metric {
+            Some(metric) => writeln!(out, "Compacted metric {}", metric)?,
+            None => writeln!(out, "Compacted all metrics")?,
+        },
+        AdminResponse::Stats {
+            metric_count,
+            disk_usage_bytes,
+        } => {
+            writeln!(out, "metric_count: {}", metric_count)?;
+            writeln!(out, "disk_usage_bytes: {}", disk_usage_bytes)?;
+        }
+        AdminResponse::RocksdbStats { stats } => {
+            for stat in stats.iter() {
+                writeln!(out, "{}", serde_json::to_string(stat)?)?;
+            }
+        }
+        AdminResponse::BackedUp { dir } => writeln!(out, "Backed up to {}", dir)?,
+        AdminResponse::Authenticated => {
+            return Err(Error::ServerError("Unexpected response".to_string()))
+        }
+        AdminResponse::Error { message } => return Err(Error::ServerError(message)),
+    }
+    Ok(())
+}
+
+// Sends a single request line and reads the single response line,
+// authenticating first if given a token
+fn send_request(
+    addr: SocketAddr,
+    auth_token: &Option<String>,
+    req: &AdminRequest,
+) -> Result<AdminResponse, Error> {
+    let timeout = Duration::from_millis(TIMEOUT_MS);
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    if let Some(ref token) = *auth_token {
+        let auth_req = AdminRequest::Authenticate {
+            token: token.clone(),
+        };
+        match exchange(&mut stream, &mut reader, &auth_req)? {
+            AdminResponse::Authenticated => {}
+            resp => return Ok(resp),
+        }
+    }
+    let resp = exchange(&mut stream, &mut reader, req)?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(resp)
+}
+
+fn exchange<R: BufRead>(
+    stream: &mut TcpStream,
+    reader: &mut R,
+    req: &AdminRequest,
+) -> Result<AdminResponse, Error> {
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    let mut resp = String::new();
+    reader.read_line(&mut resp)?;
+    let resp = serde_json::from_str(&resp)?;
+    Ok(resp)
+}
+
+#[derive(Debug)]
+enum Error {
+    IOError(io::Error),
+    JsonError(serde_json::Error),
+    ArgError(&'static str),
+    ServerError(String),
+    StorageError(StorageError),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IOError(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::JsonError(err)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(err: StorageError) -> Error {
+        Error::StorageError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use caesium_core::quantile::writable::WritableSketch;
+    use caesium_core::time::window::TimeWindow;
+    use caesium_server::server::admin::AdminServer;
+    use caesium_server::server::auth::AuthToken;
+    use caesium_server::storage::store::MetricStoreOptions;
+    use std::fs;
+    use std::process;
+    use std::sync::mpsc::{sync_channel, Receiver};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn it_parses_subcommands() {
+        let args = parse_args(vec!["caesium-admin", "-a", "127.0.0.1:9000", "stats"])
+            .expect("Could not parse args");
+        assert_eq!(args.server_addr, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(args.command, Command::Request(AdminRequest::Stats));
+
+        let args = parse_args(vec!["caesium-admin", "list-metrics", "--pattern", "foo*"])
+            .expect("Could not parse args");
+        assert_eq!(
+            args.command,
+            Command::Request(AdminRequest::ListMetrics {
+                pattern: Some("foo*".to_string())
+            })
+        );
+
+        let args = parse_args(vec![
+            "caesium-admin",
+            "trigger-compaction",
+            "--metric",
+            "foo",
+        ])
+        .expect("Could not parse args");
+        assert_eq!(
+            args.command,
+            Command::Request(AdminRequest::TriggerCompaction {
+                metric: Some("foo".to_string())
+            })
+        );
+
+        let args = parse_args(vec!["caesium-admin", "trigger-downsample", "--dry-run"])
+            .expect("Could not parse args");
+        assert_eq!(
+            args.command,
+            Command::Request(AdminRequest::DownsampleDryRun)
+        );
+
+        let args = parse_args(vec!["caesium-admin", "restore", "backups", "db"])
+            .expect("Could not parse args");
+        assert_eq!(
+            args.command,
+            Command::Restore {
+                backup_dir: "backups".to_string(),
+                db_dir: "db".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn it_lists_metrics() {
+        with_test_server("list_metrics", |addr, _| {
+            let output = run(addr, AdminRequest::ListMetrics { pattern: None });
+            assert_eq!(output, "bar\nfoo\nfoo.baz\n");
+            let output = run(
+                addr,
+                AdminRequest::ListMetrics {
+                    pattern: Some("foo*".to_string()),
+                },
+            );
+            assert_eq!(output, "foo\nfoo.baz\n");
+        })
+    }
+
+    #[test]
+    fn it_deletes_metric() {
+        with_test_server("delete_metric", |addr, _| {
+            let output = run(
+                addr,
+                AdminRequest::DeleteMetric {
+                    metric: "foo".to_string(),
+                },
+            );
+            assert_eq!(output, "Deleted metric foo\n");
+            let output = run(addr, AdminRequest::ListMetrics { pattern: None });
+            assert_eq!(output, "bar\nfoo.baz\n");
+        })
+    }
+
+    #[test]
+    fn it_reports_server_errors() {
+        with_test_server("server_error", |addr, _| {
+            let args = Args {
+                server_addr: addr,
+                auth_token: None,
+                command: Command::Request(AdminRequest::DeleteMetric {
+                    metric: "".to_string(),
+                }),
+            };
+            match run_command(&args, &mut Vec::new()) {
+                Err(Error::ServerError(_)) => {}
+                r => panic!("Expected server error, got {:?}", r),
+            }
+        })
+    }
+
+    #[test]
+    fn it_triggers_downsample() {
+        with_test_server("trigger_downsample", |addr, trigger| {
+            let output = run(addr, AdminRequest::TriggerDownsample);
+            assert_eq!(output, "Triggered downsample\n");
+            assert!(trigger.try_recv().is_ok());
+        })
+    }
+
+    #[test]
+    fn it_coalesces_pending_downsample_triggers() {
+        with_test_server("coalesce_downsample", |addr, trigger| {
+            for _ in 0..3 {
+                let output = run(addr, AdminRequest::TriggerDownsample);
+                assert_eq!(output, "Triggered downsample\n");
+            }
+            assert!(trigger.try_recv().is_ok());
+            assert!(trigger.try_recv().is_err());
+        })
+    }
+
+    #[test]
+    fn it_serves_requests_while_another_connection_is_idle() {
+        with_test_server("concurrent_connections", |addr, _| {
+            let _idle = TcpStream::connect(addr).expect("Could not connect to server");
+            let output = run(addr, AdminRequest::Stats);
+            assert!(output.starts_with("metric_count: 3\n"));
+        })
+    }
+
+    #[test]
+    fn it_authenticates_with_auth_token() {
+        with_auth_test_server("auth_token", Some("secret"), |addr, _| {
+            let args = Args {
+                server_addr: addr,
+                auth_token: Some("secret".to_string()),
+                command: Command::Request(AdminRequest::Stats),
+            };
+            let mut out = Vec::new();
+            run_command(&args, &mut out).expect("Could not run admin command");
+            assert!(String::from_utf8(out)
+                .unwrap()
+                .starts_with("metric_count: 3\n"));
+
+            for token in [None, Some("public".to_string())].iter() {
+                let args = Args {
+                    server_addr: addr,
+                    auth_token: token.clone(),
+                    command: Command::Request(AdminRequest::Stats),
+                };
+                match run_command(&args, &mut Vec::new()) {
+                    Err(Error::ServerError(_)) => {}
+                    r => panic!("Expected server error for {:?}, got {:?}", token, r),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn it_reports_downsample_dry_run_as_json() {
+        with_test_server("downsample_dry_run", |addr, trigger| {
+            // Every window is older than the default tiers, so all would be discarded
+            let output = run(addr, AdminRequest::DownsampleDryRun);
+            let report: serde_json::Value =
+                serde_json::from_str(&output).expect("Could not parse report");
+            for metric in ["foo", "foo.baz", "bar"].iter() {
+                let counts = &report["metrics"][metric];
+                assert_eq!(counts["ignore"], 0);
+                assert_eq!(counts["discard"], 1);
+                assert_eq!(counts["expand_window"], 0);
+            }
+            assert!(trigger.try_recv().is_err());
+            let output = run(addr, AdminRequest::ListMetrics { pattern: None });
+            assert_eq!(output, "bar\nfoo\nfoo.baz\n");
+        })
+    }
+
+    #[test]
+    fn it_triggers_compaction() {
+        with_test_server("trigger_compaction", |addr, _| {
+            let output = run(addr, AdminRequest::TriggerCompaction { metric: None });
+            assert_eq!(output, "Compacted all metrics\n");
+            let output = run(
+                addr,
+                AdminRequest::TriggerCompaction {
+                    metric: Some("foo".to_string()),
+                },
+            );
+            assert_eq!(output, "Compacted metric foo\n");
+            let output = run(addr, AdminRequest::ListMetrics { pattern: None });
+            assert_eq!(output, "bar\nfoo\nfoo.baz\n");
+        })
+    }
+
+    #[test]
+    fn it_shows_stats() {
+        with_test_server("stats", |addr, _| {
+            let output = run(addr, AdminRequest::Stats);
+            let lines: Vec<&str> = output.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert_eq!(lines[0], "metric_count: 3");
+            assert!(lines[1].starts_with("disk_usage_bytes: "));
+        })
+    }
+
+    #[test]
+    fn it_shows_rocksdb_stats_as_ndjson() {
+        with_test_server("rocksdb_stats", |addr, _| {
+            run(addr, AdminRequest::ListMetrics { pattern: None });
+            let output = run(addr, AdminRequest::RocksdbStats);
+            assert!(!output.is_empty());
+            for line in output.lines() {
+                let stat: serde_json::Value =
+                    serde_json::from_str(line).expect("Could not parse stat");
+                assert!(stat["name"].as_str().unwrap().starts_with("rocksdb."));
+                assert!(stat["count"].is_number());
+            }
+        })
+    }
+
+    #[test]
+    fn it_backs_up_under_backup_root() {
+        with_test_server("backup", |addr, _| {
+            let dir = format!("caesium_admin_cli_test_{}_backup_dir", process::id());
+            let backup_dir = env::temp_dir().join(&dir);
+            let _ = fs::remove_dir_all(&backup_dir);
+
+            // The second backup to the same directory is incremental
+            for _ in 0..2 {
+                let output = run(addr, AdminRequest::Backup { dir: dir.clone() });
+                assert_eq!(output, format!("Backed up to {}\n", dir));
+            }
+            assert!(fs::read_dir(&backup_dir)
+                .expect("Could not read backup directory")
+                .next()
+                .is_some());
+            fs::remove_dir_all(&backup_dir).expect("Could not delete backup directory");
+        })
+    }
+
+    #[test]
+    fn it_rejects_backups_outside_backup_root() {
+        with_test_server("backup_outside_root", |addr, _| {
+            for dir in ["/tmp/elsewhere", "../elsewhere", ""].iter() {
+                let args = Args {
+                    server_addr: addr,
+                    auth_token: None,
+                    command: Command::Request(AdminRequest::Backup {
+                        dir: dir.to_string(),
+                    }),
+                };
+                match run_command(&args, &mut Vec::new()) {
+                    Err(Error::ServerError(_)) => {}
+                    r => panic!("Expected server error for {}, got {:?}", dir, r),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn it_restores_backup_to_db_dir() {
+        with_test_server("restore", |addr, _| {
+            let dir = format!("caesium_admin_cli_test_{}_restore_backup", process::id());
+            let backup_dir = env::temp_dir().join(&dir);
+            let backup_dir = backup_dir.to_str().expect("Could not convert path");
+            let db_dir = env::temp_dir().join(format!(
+                "caesium_admin_cli_test_{}_restore_db",
+                process::id()
+            ));
+            let db_dir = db_dir.to_str().expect("Could not convert path");
+            let _ = fs::remove_dir_all(backup_dir);
+            let _ = fs::remove_dir_all(db_dir);
+
+            run(addr, AdminRequest::Backup { dir: dir.clone() });
+            let args = Args {
+                server_addr: addr,
+                auth_token: None,
+                command: Command::Restore {
+                    backup_dir: backup_dir.to_string(),
+                    db_dir: db_dir.to_string(),
+                },
+            };
+            let mut out = Vec::new();
+            run_command(&args, &mut out).expect("Could not restore");
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                format!("Restored {} from {}\n", db_dir, backup_dir)
+            );
+
+            let restored = MetricStore::open(db_dir).expect("Could not open restored DB");
+            let metrics: Vec<String> = restored
+                .list_metrics()
+                .expect("Could not list metrics")
+                .collect();
+            assert_eq!(metrics, vec!["bar", "foo", "foo.baz"]);
+            drop(restored);
+            fs::remove_dir_all(backup_dir).expect("Could not delete backup directory");
+            fs::remove_dir_all(db_dir).expect("Could not delete restored DB directory");
+        })
+    }
+
+    fn run(server_addr: SocketAddr, request: AdminRequest) -> String {
+        let args = Args {
+            server_addr,
+            auth_token: None,
+            command: Command::Request(request),
+        };
+        let mut out = Vec::new();
+        run_command(&args, &mut out).expect("Could not run admin command");
+        String::from_utf8(out).expect("Could not decode output")
+    }
+
+    fn with_test_server<T>(name: &str, test: T)
+    where
+        T: FnOnce(SocketAddr, Receiver<()>),
+    {
+        with_auth_test_server(name, None, test)
+    }
+
+    fn with_auth_test_server<T>(name: &str, auth_token: Option<&str>, test: T)
+    where
+        T: FnOnce(SocketAddr, Receiver<()>),
+    {
+        let db_path =
+            env::temp_dir().join(format!("caesium_admin_cli_test_{}_{}", process::id(), name));
+        let db_path = db_path.to_str().expect("Could not convert path");
+        let _ = fs::remove_dir_all(db_path);
+        let options = MetricStoreOptions {
+            enable_statistics: true,
+            ..MetricStoreOptions::default()
+        };
+        let db = MetricStore::open_with_options(db_path, options).expect("Could not open db");
+        let db_ref = Arc::new(db);
+        for metric in ["foo", "foo.baz", "bar"].iter() {
+            let sketch = WritableSketch::from_slice(&[1, 2, 3]);
+            db_ref
+                .insert(metric, TimeWindow::new(0, 30), sketch)
+                .expect("Could not insert metric");
+        }
+
+        let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let (tx, rx) = sync_channel(1);
+        let server = AdminServer::new(&server_addr, tx, db_ref)
+            .expect("Could not start admin server")
+            .with_backup_root(Some(env::temp_dir()))
+            .with_auth_token(auth_token.map(AuthToken::new));
+        let addr = server
+            .local_addr()
+            .expect("Could not retrieve admin server addr");
+        thread::spawn(move || server.run());
+
+        test(addr, rx);
+        fs::remove_dir_all(db_path).expect("Could not delete DB directory");
+    }
+}
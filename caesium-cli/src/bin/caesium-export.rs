@@ -0,0 +1,118 @@
+extern crate caesium_server;
+extern crate clap;
+
+use caesium_server::storage::error::StorageError;
+use caesium_server::storage::export::{export_metrics, ExportError, ExportFormat};
+use caesium_server::storage::store::MetricStore;
+use clap::{App, Arg};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+fn main() -> Result<(), Error> {
+    let args = parse_args()?;
+    let db = MetricStore::open(&args.db_path)?;
+    let count = match args.output_path {
+        Some(ref path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            let count = export_metrics(&db, &args.metric_pattern, args.format, &mut writer)?;
+            writer.flush()?;
+            count
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut writer = BufWriter::new(stdout.lock());
+            let count = export_metrics(&db, &args.metric_pattern, args.format, &mut writer)?;
+            writer.flush()?;
+            count
+        }
+    };
+    eprintln!("Exported {} windows", count);
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Args {
+    db_path: String,
+    output_path: Option<String>,
+    format: ExportFormat,
+    metric_pattern: String,
+}
+
+fn parse_args() -> Result<Args, Error> {
+    let matches = App::new("Caesium export tool")
+        .about("Write every stored window to NDJSON or CSV, for backups or migrations")
+        .arg(
+            Arg::with_name("DB_PATH")
+                .long("db-path")
+                .takes_value(true)
+                .required(true)
+                .help("Path to the server's database directory"),
+        )
+        .arg(
+            Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("File to write (defaults to stdout)"),
+        )
+        .arg(
+            Arg::with_name("FORMAT")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["ndjson", "csv"])
+                .help("Output format (defaults to ndjson)"),
+        )
+        .arg(
+            Arg::with_name("METRIC_PATTERN")
+                .long("metric-pattern")
+                .takes_value(true)
+                .help("Only export metrics matching this pattern, which may contain wildcards (defaults to *)"),
+        )
+        .get_matches();
+    let db_path = matches
+        .value_of("DB_PATH")
+        .ok_or(Error::ArgError("Expected database path"))?
+        .to_string();
+    let output_path = matches.value_of("OUTPUT").map(|s| s.to_string());
+    let format = match matches.value_of("FORMAT").unwrap_or("ndjson") {
+        "csv" => ExportFormat::Csv,
+        _ => ExportFormat::Ndjson,
+    };
+    let metric_pattern = matches
+        .value_of("METRIC_PATTERN")
+        .unwrap_or("*")
+        .to_string();
+    Ok(Args {
+        db_path,
+        output_path,
+        format,
+        metric_pattern,
+    })
+}
+
+#[derive(Debug)]
+enum Error {
+    IOError(io::Error),
+    StorageError(StorageError),
+    ExportError(ExportError),
+    ArgError(&'static str),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IOError(err)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(err: StorageError) -> Error {
+        Error::StorageError(err)
+    }
+}
+
+impl From<ExportError> for Error {
+    fn from(err: ExportError) -> Error {
+        Error::ExportError(err)
+    }
+}
@@ -0,0 +1,99 @@
+extern crate caesium_server;
+extern crate clap;
+
+use caesium_server::storage::error::StorageError;
+use caesium_server::storage::export::{import_metrics, ExportError, ExportFormat};
+use caesium_server::storage::store::MetricStore;
+use clap::{App, Arg};
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+
+fn main() -> Result<(), Error> {
+    let args = parse_args()?;
+    let db = MetricStore::open(&args.db_path)?;
+    let count = match args.input_path {
+        Some(ref path) => import_metrics(&db, args.format, BufReader::new(File::open(path)?))?,
+        None => {
+            let stdin = io::stdin();
+            let reader = stdin.lock();
+            import_metrics(&db, args.format, reader)?
+        }
+    };
+    eprintln!("Imported {} windows", count);
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Args {
+    db_path: String,
+    input_path: Option<String>,
+    format: ExportFormat,
+}
+
+fn parse_args() -> Result<Args, Error> {
+    let matches = App::new("Caesium import tool")
+        .about("Insert windows written by caesium-export, merging them with any existing data")
+        .arg(
+            Arg::with_name("DB_PATH")
+                .long("db-path")
+                .takes_value(true)
+                .required(true)
+                .help("Path to the server's database directory"),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .short("i")
+                .long("input")
+                .takes_value(true)
+                .help("File to read (defaults to stdin)"),
+        )
+        .arg(
+            Arg::with_name("FORMAT")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["ndjson", "csv"])
+                .help("Input format (defaults to ndjson)"),
+        )
+        .get_matches();
+    let db_path = matches
+        .value_of("DB_PATH")
+        .ok_or(Error::ArgError("Expected database path"))?
+        .to_string();
+    let input_path = matches.value_of("INPUT").map(|s| s.to_string());
+    let format = match matches.value_of("FORMAT").unwrap_or("ndjson") {
+        "csv" => ExportFormat::Csv,
+        _ => ExportFormat::Ndjson,
+    };
+    Ok(Args {
+        db_path,
+        input_path,
+        format,
+    })
+}
+
+#[derive(Debug)]
+enum Error {
+    IOError(io::Error),
+    StorageError(StorageError),
+    ExportError(ExportError),
+    ArgError(&'static str),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IOError(err)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(err: StorageError) -> Error {
+        Error::StorageError(err)
+    }
+}
+
+impl From<ExportError> for Error {
+    fn from(err: ExportError) -> Error {
+        Error::ExportError(err)
+    }
+}
@@ -1,23 +1,33 @@
 extern crate caesium_core;
 extern crate clap;
 extern crate rand;
+extern crate rustls;
+extern crate rustls_pemfile;
 
 use caesium_core::encode::frame::FrameEncoder;
 use caesium_core::encode::EncodableError;
 use caesium_core::get_sketch_type;
-use caesium_core::protocol::messages::InsertMessage;
+use caesium_core::protocol::messages::{
+    AuthMessage, BatchInsertMessage, InsertMessage, MetricKind, WriteMessage,
+};
 use caesium_core::quantile::writable::WritableSketch;
 use caesium_core::time::timestamp::TimeStamp;
 use caesium_core::time::window::TimeWindow;
 use clap::{App, Arg};
 use rand::rngs::SmallRng;
 use rand::{FromEntropy, Rng};
+use rustls::{
+    Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerName, StreamOwned,
+};
+use std::convert::TryFrom;
 use std::env;
 use std::fs::File;
 use std::io;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::mem;
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::num::ParseIntError;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const MIN_VAL: u64 = 0;
@@ -27,8 +37,37 @@ fn main() -> Result<(), Error> {
     let args = parse_args()?;
     println!("Using sketch type {:?}", get_sketch_type());
     let insert_cmds = load_data_file(&args.data_path)?;
-    let mut socket = TcpStream::connect(&args.server_addr)?;
+    let socket = TcpStream::connect(args.server_addr)?;
+    match args.tls {
+        Some(ref tls) => {
+            let conn = ClientConnection::new(load_client_config(tls)?, server_name(tls)?)?;
+            let mut tls_socket = StreamOwned::new(conn, socket);
+            insert_all(&insert_cmds, &args, &mut tls_socket)?;
+            // Wait for the server to acknowledge the close so it reads everything we sent
+            tls_socket.conn.send_close_notify();
+            tls_socket.flush()?;
+            tls_socket.read_to_end(&mut Vec::new())?;
+            Ok(())
+        }
+        None => {
+            let mut socket = socket;
+            insert_all(&insert_cmds, &args, &mut socket)
+        }
+    }
+}
+
+fn insert_all<W: Write>(
+    insert_cmds: &[InsertCommand],
+    args: &Args,
+    socket: &mut W,
+) -> Result<(), Error> {
     let mut frame_encoder = FrameEncoder::new();
+    if let Some(ref token) = args.auth_token {
+        let msg = WriteMessage::Auth(AuthMessage {
+            token: token.clone(),
+        });
+        frame_encoder.encode_framed_msg(&msg, socket)?;
+    }
     for cmd in insert_cmds.iter() {
         println!("Inserting {:?}", cmd);
         insert_sketches(
@@ -36,13 +75,58 @@ fn main() -> Result<(), Error> {
             args.window_start,
             args.window_size,
             args.sketch_size,
-            &mut socket,
+            args.batch_size,
+            socket,
             &mut frame_encoder,
         )?;
     }
     Ok(())
 }
 
+fn load_client_config(tls: &TlsArgs) -> Result<Arc<ClientConfig>, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&tls.ca_path)? {
+        roots.add(&cert)?;
+    }
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match (&tls.cert_path, &tls.key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            builder.with_client_auth_cert(load_certs(cert_path)?, load_private_key(key_path)?)?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+fn server_name(tls: &TlsArgs) -> Result<ServerName, Error> {
+    ServerName::try_from(tls.server_name.as_str())
+        .map_err(|_| Error::ArgError("Invalid TLS server name"))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        return Err(Error::ArgError("No certificates found in PEM file"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(Error::ArgError("No private key found in PEM file"))
+}
+
 #[derive(Debug)]
 struct InsertCommand {
     num_sketches: usize,
@@ -86,23 +170,44 @@ fn parse_line(line: &str, line_num: usize) -> Option<InsertCommand> {
     }
 }
 
-fn insert_sketches(
+fn insert_sketches<W: Write>(
     cmd: &InsertCommand,
     window_start: u64,
     window_size: u64,
     sketch_size: usize,
-    socket: &mut TcpStream,
+    batch_size: usize,
+    socket: &mut W,
     frame_encoder: &mut FrameEncoder,
 ) -> Result<(), Error> {
+    let mut batch = Vec::with_capacity(batch_size);
     for i in 0..cmd.num_sketches {
         let window = window_for_idx(window_start, window_size, i);
-        let msg = InsertMessage {
+        batch.push(InsertMessage {
             metric: cmd.metric_name.clone(),
+            kind: MetricKind::Timer,
             window,
             sketch: build_sketch(sketch_size),
-        };
-        frame_encoder.encode_framed_msg(&msg, socket)?;
+        });
+        if batch.len() >= batch_size {
+            send_batch(&mut batch, socket, frame_encoder)?;
+        }
     }
+    send_batch(&mut batch, socket, frame_encoder)
+}
+
+fn send_batch<W: Write>(
+    batch: &mut Vec<InsertMessage>,
+    socket: &mut W,
+    frame_encoder: &mut FrameEncoder,
+) -> Result<(), Error> {
+    let msg = match batch.len() {
+        0 => return Ok(()),
+        1 => WriteMessage::Insert(Box::new(batch.remove(0))),
+        _ => WriteMessage::BatchInsert(BatchInsertMessage {
+            inserts: mem::take(batch),
+        }),
+    };
+    frame_encoder.encode_framed_msg(&msg, socket)?;
     Ok(())
 }
 
@@ -114,12 +219,7 @@ fn window_for_idx(window_start: u64, window_size: u64, idx: usize) -> TimeWindow
 
 fn build_sketch(size: usize) -> WritableSketch {
     let mut rng = SmallRng::from_entropy();
-    let mut sketch = WritableSketch::new();
-    for _ in 0..size {
-        let v = rng.gen_range(MIN_VAL, MAX_VAL) as u32;
-        sketch.insert(v);
-    }
-    sketch
+    (0..size).map(|_| rng.gen_range(MIN_VAL, MAX_VAL)).collect()
 }
 
 #[derive(Debug)]
@@ -129,13 +229,24 @@ struct Args {
     window_start: u64,
     window_size: u64,
     sketch_size: usize,
+    batch_size: usize,
+    tls: Option<TlsArgs>,
+    auth_token: Option<String>,
+}
+
+#[derive(Debug)]
+struct TlsArgs {
+    ca_path: String,
+    server_name: String,
+    cert_path: Option<String>,
+    key_path: Option<String>,
 }
 
 #[cfg(not(feature = "baseline"))]
-const FEATURE_STR: &'static str = "Compiled using KLL sketch implementation";
+const FEATURE_STR: &str = "Compiled using KLL sketch implementation";
 
 #[cfg(feature = "baseline")]
-const FEATURE_STR: &'static str = "Compiled using baseline sketch implementation";
+const FEATURE_STR: &str = "Compiled using baseline sketch implementation";
 
 fn parse_args() -> Result<Args, Error> {
     let matches = App::new("Sketch insert tool")
@@ -172,6 +283,52 @@ fn parse_args() -> Result<Args, Error> {
             .takes_value(true)
             .help("Number of values to insert into each sketch (default 1000)")
         )
+        .arg(
+            Arg::with_name("BATCH_SIZE")
+            .long("batch-size")
+            .takes_value(true)
+            .help("Number of sketches to send in each insert message (default 1)")
+        )
+        .arg(
+            Arg::with_name("TLS")
+            .long("tls")
+            .requires("TLS_CA")
+            .help("Connect to the server over TLS")
+        )
+        .arg(
+            Arg::with_name("TLS_CA")
+            .long("tls-ca")
+            .takes_value(true)
+            .help("Path to PEM CA certificate used to verify the server")
+        )
+        .arg(
+            Arg::with_name("TLS_SERVER_NAME")
+            .long("tls-server-name")
+            .takes_value(true)
+            .help("Name to verify in the server certificate (defaults to the server IP address)")
+        )
+        .arg(
+            Arg::with_name("TLS_CERT")
+            .long("tls-cert")
+            .takes_value(true)
+            .requires("TLS_KEY")
+            .help("Path to PEM client certificate, for servers that require mutual TLS")
+        )
+        .arg(
+            Arg::with_name("TLS_KEY")
+            .long("tls-key")
+            .takes_value(true)
+            .requires("TLS_CERT")
+            .help("Path to PEM private key for the client certificate")
+        )
+        .arg(
+            Arg::with_name("AUTH_TOKEN")
+            .long("auth-token")
+            .takes_value(true)
+            .env("CAESIUM_AUTH_TOKEN")
+            .hide_env_values(true)
+            .help("Shared secret to send before inserting, for servers that require one")
+        )
         .get_matches();
 
     let data_path = matches
@@ -208,12 +365,40 @@ fn parse_args() -> Result<Args, Error> {
         .unwrap_or("1000")
         .parse::<usize>()?;
 
+    let batch_size = matches
+        .value_of("BATCH_SIZE")
+        .unwrap_or("1")
+        .parse::<usize>()?;
+
+    if batch_size < 1 {
+        return Err(Error::ArgError("Batch size must be at least one"));
+    }
+
+    let tls = if matches.is_present("TLS") {
+        Some(TlsArgs {
+            ca_path: matches.value_of("TLS_CA").unwrap().to_string(),
+            server_name: matches
+                .value_of("TLS_SERVER_NAME")
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| server_addr.ip().to_string()),
+            cert_path: matches.value_of("TLS_CERT").map(|s| s.to_string()),
+            key_path: matches.value_of("TLS_KEY").map(|s| s.to_string()),
+        })
+    } else {
+        None
+    };
+
+    let auth_token = matches.value_of("AUTH_TOKEN").map(|s| s.to_string());
+
     Ok(Args {
         data_path,
         server_addr,
         window_start,
         window_size,
         sketch_size,
+        batch_size,
+        tls,
+        auth_token,
     })
 }
 
@@ -222,6 +407,7 @@ enum Error {
     IOError(io::Error),
     EncodableError(EncodableError),
     ParseIntError(ParseIntError),
+    TlsError(rustls::Error),
     ArgError(&'static str),
 }
 
@@ -242,3 +428,9 @@ impl From<ParseIntError> for Error {
         Error::ParseIntError(err)
     }
 }
+
+impl From<rustls::Error> for Error {
+    fn from(err: rustls::Error) -> Error {
+        Error::TlsError(err)
+    }
+}
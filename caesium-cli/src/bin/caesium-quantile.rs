@@ -122,7 +122,7 @@ fn read_data_file(path: &str) -> Result<Vec<u32>, Error> {
         .lines()
         .filter_map(|result| {
             result
-                .map_err(|e| Error::IOError(e))
+                .map_err(Error::IOError)
                 .and_then(|l| l.parse::<u32>().map_err(From::from))
                 .ok()
         })
@@ -133,7 +133,7 @@ fn read_data_file(path: &str) -> Result<Vec<u32>, Error> {
 fn choose_merge_partitions(data_len: usize, num_merges: usize) -> Vec<usize> {
     let mut candidates: Vec<usize> = (0..data_len).collect();
     rand::thread_rng().shuffle(&mut candidates);
-    let mut partitions: Vec<usize> = candidates.iter().take(num_merges).map(|x| *x).collect();
+    let mut partitions: Vec<usize> = candidates.iter().take(num_merges).copied().collect();
     partitions.push(data_len - 1);
     partitions.sort_unstable();
     partitions
@@ -161,7 +161,7 @@ fn build_sketch(data: &[u32], partitions: &[usize], timer: &mut Timer) -> Writab
     // Merges
     let mut merged = WritableSketch::new();
     for s in sketches {
-        merged = merged.merge(s)
+        merged.merge_from(s).expect("Could not merge sketches");
     }
 
     let duration = timer.stop().unwrap();
@@ -187,10 +187,7 @@ fn summarize_error(calc: &ErrorCalculator, sketch: &mut ReadableSketch) {
         let phi = (i as f64) / 10.0;
         let q = sketch.query(phi).expect("Could not query sketch");
         let err = calc.calculate_error(phi, q.approx_value);
-        println!(
-            "phi={}, approx={}, lower={}, upper={}, err={}",
-            phi, q.approx_value, q.lower_bound, q.upper_bound, err
-        );
+        println!("phi={}, quantile={}, err={}", phi, q, err);
     }
 }
 
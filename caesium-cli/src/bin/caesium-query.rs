@@ -1,34 +1,68 @@
 extern crate clap;
+extern crate rand;
 extern crate rustyline;
+extern crate serde_json;
+
+#[cfg(test)]
+extern crate caesium_core;
+#[cfg(test)]
+extern crate caesium_server;
 
 use clap::{App, Arg};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::env;
+use std::ffi::OsString;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Write};
 use std::net::{AddrParseError, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
 use std::time::Duration;
 
 const READ_TIMEOUT_MS: u64 = 10000;
-const HISTORY_FILE: &'static str = &".caesium-query-history";
+const HISTORY_FILE: &str = ".caesium-query-history";
+const STREAM_HEADER: &str = "stream: true\n";
+const REQUEST_ID_HEADER: &str = "request-id: ";
+const AUTH_HEADER: &str = "auth: ";
+const PROMPT: &str = "caesium> ";
+const ERROR_PREFIX: &str = "[ERROR] ";
+const TABLE_COLUMNS: [&str; 6] = [
+    "window_start",
+    "window_end",
+    "phi",
+    "value",
+    "lower",
+    "upper",
+];
+const TABLE_COLUMN_WIDTH: usize = 14;
 
 fn main() -> Result<(), Error> {
-    let args = parse_args()?;
+    let args = parse_args(env::args_os())?;
+    let stdout = io::stdout();
+    match args.query {
+        Some(ref q) => query_with_new_id(&args, q.trim(), &mut stdout.lock()),
+        None => run_repl(&args),
+    }
+}
+
+fn run_repl(args: &Args) -> Result<(), Error> {
     println!("Server address: {}", args.server_addr);
     let mut rl = Editor::<()>::new();
     rl.load_history(HISTORY_FILE).unwrap_or_else(|_e| {});
     loop {
         let result = rl
-            .readline(">> ")
-            .map_err(|err| Error::from(err))
+            .readline(PROMPT)
+            .map_err(Error::from)
             .and_then(|line| {
                 rl.add_history_entry(&line);
                 Ok(line)
             })
-            .and_then(|line| handle_query(&args.server_addr, line.trim()));
+            .and_then(|line| {
+                let stdout = io::stdout();
+                let mut out = stdout.lock();
+                query_with_new_id(args, line.trim(), &mut out)
+            });
         match result {
-            Ok(output) => print!("{}", output),
+            Ok(_) => {}
             Err(Error::ReadlineError(ReadlineError::Eof))
             | Err(Error::ReadlineError(ReadlineError::Interrupted)) => {
                 break;
@@ -43,9 +77,24 @@ fn main() -> Result<(), Error> {
 #[derive(Debug)]
 struct Args {
     server_addr: SocketAddr,
+    stream: bool,
+    query: Option<String>,
+    format: OutputFormat,
+    auth_token: Option<String>,
+    request_id: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum OutputFormat {
+    Table,
+    Json,
 }
 
-fn parse_args() -> Result<Args, Error> {
+fn parse_args<I, T>(cli_args: I) -> Result<Args, Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
     let matches = App::new("Caesium query tool")
         .about("Query for metric data")
         .arg(
@@ -55,7 +104,45 @@ fn parse_args() -> Result<Args, Error> {
                 .takes_value(true)
                 .help("Network address of server (defaults to $CAESIUM_SERVER_QUERY_ADDR, then 127.0.0.1:8000)"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("STREAM")
+                .long("stream")
+                .help("Print each result as soon as the server sends it"),
+        )
+        .arg(
+            Arg::with_name("QUERY")
+                .short("q")
+                .long("query")
+                .takes_value(true)
+                .help("Run a single query and exit, instead of reading queries interactively"),
+        )
+        .arg(
+            Arg::with_name("QUERY_ARG")
+                .index(1)
+                .conflicts_with("QUERY")
+                .help("Same as --query"),
+        )
+        .arg(
+            Arg::with_name("FORMAT")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["table", "json"])
+                .help("Output format for results (defaults to table)"),
+        )
+        .arg(
+            Arg::with_name("NO_REQUEST_ID")
+                .long("no-request-id")
+                .help("Send queries without a request ID, for servers that don't support the request-id header"),
+        )
+        .arg(
+            Arg::with_name("AUTH_TOKEN")
+                .long("auth-token")
+                .takes_value(true)
+                .env("CAESIUM_AUTH_TOKEN")
+                .hide_env_values(true)
+                .help("Shared secret to send with each query, for servers that require one"),
+        )
+        .get_matches_from(cli_args);
     let default_addr =
         env::var("CAESIUM_SERVER_QUERY_ADDR").unwrap_or_else(|_| "127.0.0.1:8000".to_string());
     let server_addr = matches
@@ -64,21 +151,268 @@ fn parse_args() -> Result<Args, Error> {
         .to_socket_addrs()?
         .next()
         .ok_or(Error::ArgError("Expected socket address"))?;
-    Ok(Args { server_addr })
+    let stream = matches.is_present("STREAM");
+    let query = matches
+        .value_of("QUERY")
+        .or(matches.value_of("QUERY_ARG"))
+        .map(|s| s.to_string());
+    let format = match matches.value_of("FORMAT").unwrap_or("table") {
+        "json" => OutputFormat::Json,
+        _ => OutputFormat::Table,
+    };
+    let auth_token = matches.value_of("AUTH_TOKEN").map(|s| s.to_string());
+    let request_id = !matches.is_present("NO_REQUEST_ID");
+    Ok(Args {
+        server_addr,
+        stream,
+        query,
+        format,
+        auth_token,
+        request_id,
+    })
 }
 
-fn handle_query(addr: &SocketAddr, q: &str) -> Result<String, Error> {
+// Tags the query with a random request ID, printed to stderr
+// so it can be matched with the server's logs
+fn query_with_new_id<W: Write>(args: &Args, q: &str, out: &mut W) -> Result<(), Error> {
     if q.is_empty() {
-        return Ok("".to_string());
+        return Ok(());
+    }
+    let request_id = if args.request_id {
+        let id = rand::random::<u64>();
+        eprintln!("Request ID: {}", id);
+        Some(id)
+    } else {
+        None
+    };
+    handle_query(args, q, request_id, out)
+}
+
+fn handle_query<W: Write>(
+    args: &Args,
+    q: &str,
+    request_id: Option<u64>,
+    out: &mut W,
+) -> Result<(), Error> {
+    if q.is_empty() {
+        return Ok(());
     }
 
     let timeout = Duration::from_millis(READ_TIMEOUT_MS);
-    let mut stream = TcpStream::connect_timeout(addr, timeout)?;
+    let mut stream = TcpStream::connect_timeout(&args.server_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    if args.stream {
+        stream.write_all(STREAM_HEADER.as_bytes())?;
+    }
+    if let Some(id) = request_id {
+        writeln!(stream, "{}{}", REQUEST_ID_HEADER, id)?;
+    }
+    if let Some(ref token) = args.auth_token {
+        writeln!(stream, "{}{}", AUTH_HEADER, token)?;
+    }
     stream.write_all(q.as_bytes())?;
     stream.shutdown(Shutdown::Write)?;
-    let mut resp = String::new();
-    stream.read_to_string(&mut resp)?;
-    Ok(resp)
+    let mut reader = BufReader::new(stream);
+    // Servers only echo the ID when the request has one
+    if let Some(id) = request_id {
+        if read_request_id(&mut reader)? != id {
+            return Err(Error::StreamError(
+                "Response request ID did not match the request",
+            ));
+        }
+    }
+    let mut printer = ResultPrinter::new(args.format, out);
+    if args.stream {
+        print_streamed_results(reader, &mut printer)
+    } else {
+        // The server writes each result as a line as soon as it is ready
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(msg) = line.strip_prefix(ERROR_PREFIX) {
+                printer.print_error(msg)?;
+            } else {
+                printer.print_result(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The server echoes the request ID on the first line, followed by a blank line
+fn read_request_id<R: BufRead>(reader: &mut R) -> Result<u64, Error> {
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let mut blank = String::new();
+    reader.read_line(&mut blank)?;
+    if !header.starts_with(REQUEST_ID_HEADER) || !blank.trim().is_empty() {
+        return Err(Error::StreamError("Response is missing the request ID"));
+    }
+    header[REQUEST_ID_HEADER.len()..]
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| Error::StreamError("Response has an invalid request ID"))
+}
+
+// The server sends each result as a server-sent event,
+// ending the stream with either an `end` or `error` event.
+fn print_streamed_results<R: BufRead, W: Write>(
+    reader: R,
+    printer: &mut ResultPrinter<W>,
+) -> Result<(), Error> {
+    let mut event = String::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(name) = line.strip_prefix("event: ") {
+            event = name.to_string();
+        } else if let Some(data) = line.strip_prefix("data:") {
+            let data = data.trim();
+            match event.as_str() {
+                "end" => return Ok(()),
+                "error" => return printer.print_error(data),
+                _ => printer.print_result(data)?,
+            }
+        } else if line.is_empty() {
+            event.clear();
+        }
+    }
+    Err(Error::StreamError(
+        "Stream ended before the query completed",
+    ))
+}
+
+// Prints results as they arrive. In table format, quantile results are
+// aligned in columns under a header printed before the first row;
+// other results are printed as the server sent them.
+// In JSON format, each result is printed as a JSON object on its own line.
+struct ResultPrinter<'a, W: Write + 'a> {
+    format: OutputFormat,
+    out: &'a mut W,
+    printed_header: bool,
+}
+
+impl<'a, W: Write> ResultPrinter<'a, W> {
+    fn new(format: OutputFormat, out: &'a mut W) -> ResultPrinter<'a, W> {
+        ResultPrinter {
+            format,
+            out,
+            printed_header: false,
+        }
+    }
+
+    fn print_result(&mut self, line: &str) -> Result<(), Error> {
+        let fields = parse_fields(line);
+        match self.format {
+            OutputFormat::Table => match quantile_row(&fields) {
+                Some(row) => {
+                    if !self.printed_header {
+                        let header: Vec<&str> = TABLE_COLUMNS.to_vec();
+                        writeln!(self.out, "{}", format_row(&header))?;
+                        self.printed_header = true;
+                    }
+                    writeln!(self.out, "{}", format_row(&row))?;
+                }
+                None => writeln!(self.out, "{}", line)?,
+            },
+            OutputFormat::Json => writeln!(self.out, "{}", format_json(line, &fields))?,
+        }
+        Ok(())
+    }
+
+    fn print_error(&mut self, msg: &str) -> Result<(), Error> {
+        match self.format {
+            OutputFormat::Table => writeln!(self.out, "{}{}", ERROR_PREFIX, msg)?,
+            OutputFormat::Json => writeln!(self.out, "{{\"error\":{}}}", json_string(msg))?,
+        }
+        Ok(())
+    }
+}
+
+// Splits a result line like `start=0, end=30, phi=0.5` into key-value pairs.
+// Lines that are not in this form, such as metric names, have no fields.
+fn parse_fields(line: &str) -> Vec<(&str, &str)> {
+    let mut fields = Vec::new();
+    for part in line.split(", ") {
+        let mut kv = part.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) if is_field_key(key) => fields.push((key, value)),
+            _ => return Vec::new(),
+        }
+    }
+    fields
+}
+
+fn is_field_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+fn field<'a>(fields: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    fields.iter().find(|&&(k, _)| k == key).map(|&(_, v)| v)
+}
+
+fn quantile_row<'a>(fields: &[(&str, &'a str)]) -> Option<Vec<&'a str>> {
+    Some(vec![
+        field(fields, "start")?,
+        field(fields, "end")?,
+        field(fields, "phi")?,
+        field(fields, "approx")?,
+        field(fields, "lower")?,
+        field(fields, "upper")?,
+    ])
+}
+
+fn format_row(values: &[&str]) -> String {
+    let cells: Vec<String> = values
+        .iter()
+        .map(|v| format!("{:>width$}", v, width = TABLE_COLUMN_WIDTH))
+        .collect();
+    cells.join(" ").trim_end().to_string()
+}
+
+fn format_json(line: &str, fields: &[(&str, &str)]) -> String {
+    if fields.is_empty() {
+        return format!("{{\"type\":\"metric\",\"name\":{}}}", json_string(line));
+    }
+    let result_type = match field(fields, "buckets") {
+        Some(_) => "histogram",
+        None => "quantile",
+    };
+    let mut items = vec![format!("\"type\":\"{}\"", result_type)];
+    for &(key, value) in fields {
+        let json_value = if key == "buckets" {
+            format_json_buckets(value)
+        } else if value.parse::<f64>().is_ok() {
+            value.to_string()
+        } else {
+            json_string(value)
+        };
+        items.push(format!("{}:{}", json_string(key), json_value));
+    }
+    format!("{{{}}}", items.join(","))
+}
+
+// Buckets are sent as `<upper edge>:<count>` separated by spaces,
+// where the final bucket's edge is `+Inf`
+fn format_json_buckets(s: &str) -> String {
+    let buckets: Vec<String> = s
+        .split_whitespace()
+        .filter_map(|bucket| {
+            let mut parts = bucket.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some("+Inf"), Some(count)) => {
+                    Some(format!("{{\"le\":\"+Inf\",\"count\":{}}}", count))
+                }
+                (Some(edge), Some(count)) => {
+                    Some(format!("{{\"le\":{},\"count\":{}}}", edge, count))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+    format!("[{}]", buckets.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    serde_json::to_string(s).expect("Could not serialize string")
 }
 
 #[derive(Debug)]
@@ -87,6 +421,7 @@ enum Error {
     IOError(io::Error),
     ArgError(&'static str),
     ReadlineError(ReadlineError),
+    StreamError(&'static str),
 }
 
 impl From<AddrParseError> for Error {
@@ -106,3 +441,209 @@ impl From<ReadlineError> for Error {
         Error::ReadlineError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use caesium_core::quantile::writable::WritableSketch;
+    use caesium_core::time::window::TimeWindow;
+    use caesium_server::server::metrics::ServerMetrics;
+    use caesium_server::server::read::ReadServer;
+    use caesium_server::storage::store::MetricStore;
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn it_parses_result_fields() {
+        assert_eq!(
+            parse_fields("start=0, end=30, phi=0.5"),
+            vec![("start", "0"), ("end", "30"), ("phi", "0.5")]
+        );
+        assert_eq!(parse_fields("foo;region=us"), vec![]);
+        assert_eq!(parse_fields("foo"), vec![]);
+    }
+
+    #[test]
+    fn it_parses_query_argument() {
+        let q = "quantile(fetch(\"foo\"), 0.5)";
+        let args =
+            parse_args(vec!["caesium-query", "--format", "json", q]).expect("Could not parse args");
+        assert_eq!(args.query, Some(q.to_string()));
+        assert_eq!(args.format, OutputFormat::Json);
+
+        let args = parse_args(vec!["caesium-query", "-q", q]).expect("Could not parse args");
+        assert_eq!(args.query, Some(q.to_string()));
+        assert_eq!(args.format, OutputFormat::Table);
+
+        let args = parse_args(vec!["caesium-query"]).expect("Could not parse args");
+        assert_eq!(args.query, None);
+    }
+
+    #[test]
+    fn it_formats_histograms_as_json() {
+        let line = "start=0, end=30, buckets=50:50 +Inf:100";
+        assert_eq!(
+            format_json(line, &parse_fields(line)),
+            "{\"type\":\"histogram\",\"start\":0,\"end\":30,\"buckets\":[{\"le\":50,\"count\":50},{\"le\":\"+Inf\",\"count\":100}]}"
+        );
+    }
+
+    #[test]
+    fn it_queries_server_as_table() {
+        with_test_server("table", |addr| {
+            let args = build_args(addr, false, OutputFormat::Table);
+            let output = run_query(&args, "quantile(fetch(\"foo\"), 0.5)");
+            let lines: Vec<&str> = output.lines().collect();
+            assert_eq!(lines.len(), 3);
+            assert_eq!(
+                split_row(lines[0]),
+                vec![
+                    "window_start",
+                    "window_end",
+                    "phi",
+                    "value",
+                    "lower",
+                    "upper"
+                ]
+            );
+            assert_eq!(split_row(lines[1])[..3], ["0", "30", "0.5"]);
+            assert_eq!(split_row(lines[2])[..3], ["30", "60", "0.5"]);
+        })
+    }
+
+    #[test]
+    fn it_queries_server_as_json() {
+        with_test_server("json", |addr| {
+            for &stream in [false, true].iter() {
+                let args = build_args(addr, stream, OutputFormat::Json);
+                let output = run_query(&args, "quantile(fetch(\"foo\"), 0.5)");
+                let lines: Vec<&str> = output.lines().collect();
+                assert_eq!(lines.len(), 2);
+                assert!(lines[0].starts_with("{\"type\":\"quantile\",\"start\":0,\"end\":30,"));
+                assert!(lines[1].starts_with("{\"type\":\"quantile\",\"start\":30,\"end\":60,"));
+
+                let output = run_query(&args, "search(\"*\")");
+                assert_eq!(output, "{\"type\":\"metric\",\"name\":\"foo\"}\n");
+            }
+        })
+    }
+
+    #[test]
+    fn it_matches_response_request_id() {
+        with_test_server("request_id", |addr| {
+            for &stream in [false, true].iter() {
+                let args = build_args(addr, stream, OutputFormat::Table);
+                let mut out = Vec::new();
+                handle_query(&args, "search(\"*\")", Some(u64::MAX), &mut out)
+                    .expect("Could not execute query");
+                assert_eq!(String::from_utf8(out).unwrap(), "foo\n");
+            }
+        })
+    }
+
+    #[test]
+    fn it_queries_without_request_id() {
+        with_test_server("no_request_id", |addr| {
+            for &stream in [false, true].iter() {
+                let args = build_args(addr, stream, OutputFormat::Table);
+                let mut out = Vec::new();
+                handle_query(&args, "search(\"*\")", None, &mut out)
+                    .expect("Could not execute query");
+                assert_eq!(String::from_utf8(out).unwrap(), "foo\n");
+            }
+        })
+    }
+
+    #[test]
+    fn it_reads_request_id_header() {
+        let mut resp = &b"request-id: 42\n\nfoo\n"[..];
+        assert_eq!(read_request_id(&mut resp).unwrap(), 42);
+        assert_eq!(resp, b"foo\n");
+
+        let mut resp = &b"foo\n"[..];
+        match read_request_id(&mut resp) {
+            Err(Error::StreamError(_)) => {}
+            r => panic!("Expected stream error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn it_prints_query_errors() {
+        with_test_server("errors", |addr| {
+            for &stream in [false, true].iter() {
+                let args = build_args(addr, stream, OutputFormat::Table);
+                let output = run_query(&args, "quantile(fetch(\"foo\"), 2.0)");
+                assert!(output.starts_with("[ERROR] phi_out_of_range: "));
+
+                let args = build_args(addr, stream, OutputFormat::Json);
+                let output = run_query(&args, "quantile(fetch(\"foo\"), 2.0)");
+                assert!(output.starts_with("{\"error\":\"phi_out_of_range: "));
+            }
+        })
+    }
+
+    fn build_args(server_addr: SocketAddr, stream: bool, format: OutputFormat) -> Args {
+        Args {
+            server_addr,
+            stream,
+            query: None,
+            format,
+            auth_token: None,
+            request_id: true,
+        }
+    }
+
+    fn run_query(args: &Args, q: &str) -> String {
+        let mut out = Vec::new();
+        handle_query(args, q, Some(1234), &mut out).expect("Could not execute query");
+        String::from_utf8(out).expect("Could not decode output")
+    }
+
+    fn split_row(line: &str) -> Vec<&str> {
+        line.split_whitespace().collect()
+    }
+
+    fn with_test_server<T>(name: &str, test: T)
+    where
+        T: FnOnce(SocketAddr),
+    {
+        let db_path =
+            env::temp_dir().join(format!("caesium_query_cli_test_{}_{}", process::id(), name));
+        let db_path = db_path.to_str().expect("Could not convert path");
+        let _ = fs::remove_dir_all(db_path);
+        let db_ref = Arc::new(MetricStore::open(db_path).expect("Could not open db"));
+        for &(start, end) in [(0, 30), (30, 60)].iter() {
+            let sketch = WritableSketch::from_slice(&[1, 2, 3]);
+            db_ref
+                .insert("foo", TimeWindow::new(start, end), sketch)
+                .expect("Could not insert metric");
+        }
+
+        let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let metrics = Arc::new(ServerMetrics::new());
+        let server = ReadServer::new(
+            &server_addr,
+            1,
+            16,
+            None,
+            Duration::from_secs(1),
+            None,
+            None,
+            shutdown,
+            metrics,
+            db_ref,
+        )
+        .expect("Could not start read server");
+        let addr = server
+            .local_addr()
+            .expect("Could not retrieve read server addr");
+        thread::spawn(move || server.run());
+
+        test(addr);
+        fs::remove_dir_all(db_path).expect("Could not delete DB directory");
+    }
+}
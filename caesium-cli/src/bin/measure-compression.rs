@@ -47,13 +47,13 @@ fn run_trial(trial: usize, d: usize) {
 fn measure_encode_time_ns(t: &mut Timer, s: &WritableSketch, buf: &mut Vec<u8>) -> u64 {
     t.start();
     s.encode(buf).expect("Could not encode sketch");
-    t.stop().map(|d| convert_to_ns(d)).unwrap()
+    t.stop().map(convert_to_ns).unwrap()
 }
 
 fn measure_decode_time_ns(t: &mut Timer, buf: &[u8]) -> u64 {
     t.start();
     WritableSketch::decode(&mut &buf[..]).expect("Could not decode sketch");
-    t.stop().map(|d| convert_to_ns(d)).unwrap()
+    t.stop().map(convert_to_ns).unwrap()
 }
 
 fn convert_to_ns(d: Duration) -> u64 {
@@ -37,7 +37,7 @@ fn run_trial(record: bool, trial: usize) {
         let s2 = s.clone();
 
         t.start();
-        let _merged = s1.merge(s2);
+        let _merged = s1.merge(s2).expect("Could not merge sketches");
         let d = t.stop().unwrap();
 
         if record {
@@ -1,7 +1,6 @@
 extern crate caesium_core;
 extern crate rand;
 
-use caesium_core::encode::Encodable;
 use caesium_core::quantile::writable::WritableSketch;
 use rand::rngs::SmallRng;
 use rand::{FromEntropy, Rng};
@@ -31,19 +30,15 @@ fn run_trial(record: bool, trial: usize) {
     for i in 0..NUM_INSERTS {
         let v = rng.gen_range(MIN_VAL, MAX_VAL) as u32;
         s.insert(v);
-        let (decoded_sz, encoded_sz) = calculate_size(&s);
+        let (decoded_sz, encoded_sz) = calculate_size(&mut s);
         if record {
             println!("{},{},{},{}", i, trial, decoded_sz, encoded_sz);
         }
     }
 }
 
-fn calculate_size(s: &WritableSketch) -> (usize, usize) {
+fn calculate_size(s: &mut WritableSketch) -> (usize, usize) {
     let decoded_sz = s.size() * mem::size_of::<u32>();
-
-    let mut buf = Vec::new();
-    s.encode(&mut buf).expect("Could not encode sketch");
-    let encoded_sz = buf.len();
-
+    let encoded_sz = s.serialized_len();
     (decoded_sz, encoded_sz)
 }
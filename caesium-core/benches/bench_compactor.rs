@@ -0,0 +1,57 @@
+#[macro_use]
+extern crate criterion;
+extern crate caesium_core;
+extern crate rand;
+
+use caesium_core::quantile::compactor::Compactor;
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput};
+use rand::Rng;
+
+// Number of values in the compactor before compacting
+const COMPACTOR_SIZES: [(&str, usize); 3] =
+    [("small", 1_000), ("medium", 100_000), ("large", 10_000_000)];
+
+fn build_compactor(n: usize, sorted: bool) -> Compactor {
+    let mut rng = rand::thread_rng();
+    let mut values: Vec<u32> = (0..n).map(|_| rng.gen::<u32>()).collect();
+    let mut c = Compactor::new();
+    if sorted {
+        values.sort_unstable();
+        c.insert_sorted(&values);
+    } else {
+        values.into_iter().for_each(|v| c.insert(v));
+    }
+    c
+}
+
+fn bench_compact(c: &mut Criterion, group_name: &str, sorted: bool) {
+    let mut group = c.benchmark_group(group_name);
+    group.sample_size(10);
+    for &(name, n) in COMPACTOR_SIZES.iter() {
+        let compactor = build_compactor(n, sorted);
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::new(name, n), &compactor, |b, compactor| {
+            b.iter_batched(
+                || (compactor.clone(), Vec::with_capacity(n / 2)),
+                |(mut c, mut overflow)| {
+                    c.compact(&mut overflow);
+                    overflow
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// Compacting unsorted values includes the cost of sorting them first
+fn bench_compact_unsorted(c: &mut Criterion) {
+    bench_compact(c, "compactor_compact_unsorted", false);
+}
+
+fn bench_compact_sorted(c: &mut Criterion) {
+    bench_compact(c, "compactor_compact_sorted", true);
+}
+
+criterion_group!(benches, bench_compact_unsorted, bench_compact_sorted);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+#[macro_use]
+extern crate criterion;
+extern crate caesium_core;
+extern crate rand;
+
+use caesium_core::encode::{Decodable, Encodable};
+use caesium_core::quantile::kll::KllSketch;
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput};
+use rand::Rng;
+
+// Number of values inserted into each sketch before measuring
+const SKETCH_SIZES: [(&str, usize); 3] =
+    [("small", 1_000), ("medium", 100_000), ("large", 10_000_000)];
+
+fn random_values(n: usize) -> Vec<u32> {
+    let mut rng = rand::thread_rng();
+    (0..n).map(|_| rng.gen::<u32>()).collect()
+}
+
+fn build_sketch(n: usize) -> KllSketch {
+    let mut s = KllSketch::new();
+    for v in random_values(n) {
+        s.insert(v);
+    }
+    s
+}
+
+fn bench_insert_one(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kll_insert_one");
+    group.throughput(Throughput::Elements(1));
+    let empty = KllSketch::new();
+    group.bench_function("empty", |b| {
+        b.iter_batched(|| empty.clone(), |mut s| s.insert(1), BatchSize::SmallInput)
+    });
+    let warm = build_sketch(10_000);
+    group.bench_function("after_10000_inserts", |b| {
+        b.iter_batched(|| warm.clone(), |mut s| s.insert(1), BatchSize::SmallInput)
+    });
+    group.finish();
+}
+
+fn bench_insert_many(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kll_insert_many");
+    group.sample_size(10);
+    for &(name, n) in SKETCH_SIZES.iter() {
+        let values = random_values(n);
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::new(name, n), &values, |b, values| {
+            b.iter(|| {
+                let mut s = KllSketch::new();
+                values.iter().for_each(|v| s.insert(*v));
+                s
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kll_merge");
+    group.sample_size(10);
+    for &(name, n) in SKETCH_SIZES.iter() {
+        let sketches = (build_sketch(n), build_sketch(n));
+        group.bench_with_input(BenchmarkId::new(name, n), &sketches, |b, sketches| {
+            b.iter_batched(
+                || sketches.clone(),
+                |(s1, s2)| s1.merge(s2),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_to_readable(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kll_to_readable");
+    group.sample_size(10);
+    for &(name, n) in SKETCH_SIZES.iter() {
+        let sketch = build_sketch(n);
+        group.bench_with_input(BenchmarkId::new(name, n), &sketch, |b, sketch| {
+            b.iter_batched(
+                || sketch.clone(),
+                |s| s.to_readable(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kll_encode_decode");
+    group.sample_size(10);
+    for &(name, n) in SKETCH_SIZES.iter() {
+        let sketch = build_sketch(n);
+        group.bench_with_input(BenchmarkId::new(name, n), &sketch, |b, sketch| {
+            b.iter(|| {
+                let mut buf = Vec::new();
+                sketch.encode(&mut buf).expect("Could not encode sketch");
+                KllSketch::decode(&mut &buf[..]).expect("Could not decode sketch")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_insert_one,
+    bench_insert_many,
+    bench_merge,
+    bench_to_readable,
+    bench_encode_decode
+);
+criterion_main!(benches);
@@ -16,7 +16,7 @@ fn insert_sequential(sketch: &mut WritableSketch, n: usize) {
 
 fn insert_random(sketch: &mut WritableSketch, n: usize) {
     for v in random_values(n) {
-        sketch.insert(v as u32);
+        sketch.insert(v);
     }
 }
 
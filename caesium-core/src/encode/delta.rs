@@ -2,11 +2,11 @@
 // Information Processing Letters 130 (2018): 1-6.
 
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
-use encode::EncodableError;
+use encode::{read_bytes, EncodableError};
 use std::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_shuffle_epi8, _mm_storeu_si128};
 use std::cmp::max;
 use std::io::{Read, Write};
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 
 const BLOCK_SIZE: usize = 4;
 const MAX_DATA_LEN: usize = 256000000; // 256 MB, should be enough for anything we need to encode
@@ -20,7 +20,7 @@ where
     let num_blocks = n / BLOCK_SIZE;
 
     let mut ctrl_bytes = Vec::with_capacity(num_blocks);
-    let mut data_bytes = Vec::with_capacity(n * size_of::<u32>());
+    let mut data_bytes = Vec::with_capacity(size_of_val(data));
     let mut x0 = 0;
     let mut block = [0u8; 128];
     for block_idx in 0..num_blocks {
@@ -42,7 +42,7 @@ where
         let l3 = encoded_len(d3);
 
         ctrl_bytes.push(encode_ctrl(l0, l1, l2, l3));
-        let block_len = encode_block(d0, l0, d1, l1, d2, l2, d3, l3, &mut block);
+        let block_len = encode_block([d0, d1, d2, d3], [l0, l1, l2, l3], &mut block);
         data_bytes.extend_from_slice(&block[..block_len]);
 
         x0 = x4;
@@ -59,6 +59,19 @@ where
     Ok(())
 }
 
+// Number of bytes `delta_encode` writes for `data`, which *must* be sorted ascending
+pub fn delta_encoded_len(data: &[u32]) -> usize {
+    let num_blocks = data.len() / BLOCK_SIZE;
+    let num_leftover = data.len() - num_blocks * BLOCK_SIZE;
+    let mut len = size_of::<u64>() + num_blocks + num_leftover * size_of::<u32>();
+    let mut prev = 0;
+    for &v in &data[..num_blocks * BLOCK_SIZE] {
+        len += encoded_len(v - prev);
+        prev = v;
+    }
+    len
+}
+
 pub fn delta_decode<R>(reader: &mut R) -> Result<Vec<u32>, EncodableError>
 where
     R: Read,
@@ -68,9 +81,9 @@ where
         return Err(EncodableError::LengthTooLong(n));
     }
 
+    // Reading the control bytes first bounds the result by the actual input length
     let num_blocks = n / BLOCK_SIZE;
-    let mut ctrl_bytes = vec![0u8; num_blocks];
-    reader.read_exact(&mut ctrl_bytes)?;
+    let ctrl_bytes = read_bytes(reader, num_blocks)?;
 
     let mut x0 = 0;
     let mut result = vec![0u32; n];
@@ -84,7 +97,7 @@ where
             let src = _mm_loadu_si128(buf.as_ptr() as *const __m128i);
             let mask = _mm_loadu_si128(mask_bytes.as_ptr() as *const __m128i);
             let deltas = _mm_shuffle_epi8(src, mask);
-            let dst = (&mut result[i..i + BLOCK_SIZE]).as_ptr() as *mut __m128i;
+            let dst = result[i..i + BLOCK_SIZE].as_ptr() as *mut __m128i;
             _mm_storeu_si128(dst, deltas);
         }
 
@@ -95,8 +108,8 @@ where
         x0 = result[i + 3];
     }
 
-    for i in num_blocks * BLOCK_SIZE..n {
-        result[i] = reader.read_u32::<LittleEndian>()?;
+    for v in &mut result[num_blocks * BLOCK_SIZE..n] {
+        *v = reader.read_u32::<LittleEndian>()?;
     }
 
     Ok(result)
@@ -113,48 +126,18 @@ fn encode_ctrl(l0: usize, l1: usize, l2: usize, l3: usize) -> u8 {
     ((l0 - 1) | (l1 - 1) << 2 | (l2 - 1) << 4 | (l3 - 1) << 6) as u8
 }
 
-fn encode_block(
-    v0: u32,
-    l0: usize,
-    v1: u32,
-    l1: usize,
-    v2: u32,
-    l2: usize,
-    v3: u32,
-    l3: usize,
-    out: &mut [u8],
-) -> usize {
+fn encode_block(deltas: [u32; 4], lens: [usize; 4], out: &mut [u8]) -> usize {
     let mut buf = [0u8; 4];
     let mut i = 0;
-
-    LittleEndian::write_u32(&mut buf, v0);
-    for j in 0..l0 {
-        out[i] = buf[j];
-        i += 1;
-    }
-
-    LittleEndian::write_u32(&mut buf, v1);
-    for j in 0..l1 {
-        out[i] = buf[j];
-        i += 1;
-    }
-
-    LittleEndian::write_u32(&mut buf, v2);
-    for j in 0..l2 {
-        out[i] = buf[j];
-        i += 1;
-    }
-
-    LittleEndian::write_u32(&mut buf, v3);
-    for j in 0..l3 {
-        out[i] = buf[j];
-        i += 1;
+    for (&v, &len) in deltas.iter().zip(lens.iter()) {
+        LittleEndian::write_u32(&mut buf, v);
+        out[i..i + len].copy_from_slice(&buf[..len]);
+        i += len;
     }
-
     i
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 const CTRL_TBL: [(usize, [u8; 16]); 256] = [
     (4, [0, 128, 128, 128, 1, 128, 128, 128, 2, 128, 128, 128, 3, 128, 128, 128]),
     (5, [0, 1, 128, 128, 2, 128, 128, 128, 3, 128, 128, 128, 4, 128, 128, 128]),
@@ -438,6 +421,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_rejects_length_prefix_longer_than_input() {
+        let mut buf = Vec::new();
+        buf.write_u64::<LittleEndian>(MAX_DATA_LEN as u64).unwrap();
+        buf.extend_from_slice(&[0; 8]);
+        match delta_decode(&mut &buf[..]) {
+            Err(EncodableError::IOError(_)) => {}
+            r => panic!("Expected IO error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn it_calculates_encoded_len_without_encoding() {
+        for n in 0..10 {
+            let data: Vec<u32> = (0..n).map(|x| (1 << (x * 3)) as u32).collect();
+            let mut buf = Vec::new();
+            delta_encode(&data, &mut buf).expect("Could not encode data");
+            assert_eq!(delta_encoded_len(&data), buf.len());
+        }
+    }
+
     #[test]
     fn it_encodes_and_decodes_every_length_permutation() {
         for l0 in 0..4 {
@@ -464,7 +468,7 @@ mod tests {
 
     fn assert_encodes_and_decodes(data: &[u32]) {
         let mut buf = Vec::<u8>::new();
-        delta_encode(&data, &mut buf).expect("Could not encode data vec");
+        delta_encode(data, &mut buf).expect("Could not encode data vec");
         let decoded = delta_decode(&mut &buf[..]).expect("Could not decode data vec");
         assert_eq!(decoded, data);
     }
@@ -39,4 +39,47 @@ mod tests {
         let decoded = f64::decode(&mut &buf[..]).expect("Could not decode float");
         assert_eq!(f, decoded);
     }
+
+    #[test]
+    fn it_encodes_and_decodes_f64_edge_values() {
+        let values = [
+            0.0,
+            -0.0,
+            f64::MAX,
+            f64::MIN,
+            f64::MIN_POSITIVE,
+            f64::MIN_POSITIVE / 2.0, // subnormal
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+        ];
+        for f in values.iter() {
+            let mut buf = Vec::<u8>::new();
+            f.encode(&mut buf).expect("Could not encode float");
+            let decoded = f64::decode(&mut &buf[..]).expect("Could not decode float");
+            assert_eq!(f.to_bits(), decoded.to_bits());
+        }
+    }
+
+    #[test]
+    fn it_encodes_and_decodes_f32_edge_values() {
+        let values = [
+            0.0,
+            -0.0,
+            f32::MAX,
+            f32::MIN,
+            f32::MIN_POSITIVE,
+            f32::MIN_POSITIVE / 2.0, // subnormal
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+        ];
+        for f in values.iter() {
+            let mut buf = Vec::<u8>::new();
+            f.encode(&mut buf).expect("Could not encode float");
+            assert_eq!(buf.len(), 4);
+            let decoded = f32::decode(&mut &buf[..]).expect("Could not decode float");
+            assert_eq!(f.to_bits(), decoded.to_bits());
+        }
+    }
 }
@@ -1,6 +1,14 @@
+use crc32fast::Hasher;
 use encode::{Decodable, Encodable, EncodableError};
+use protocol::PROTOCOL_VERSION;
 use std::io::Write;
-use std::mem::size_of;
+
+// Frames have the form [version: u8][msg_len: u64][checksum: u32][msg],
+// where the checksum is the CRC32 of the message bytes.
+
+const VERSION_LEN: usize = 1;
+const LENGTH_LEN: usize = 8;
+const CHECKSUM_LEN: usize = 4;
 
 pub struct FrameEncoder {
     buf: Vec<u8>,
@@ -18,38 +26,78 @@ impl FrameEncoder {
     {
         self.buf.clear();
         msg.encode(&mut self.buf)?;
+        PROTOCOL_VERSION.encode(dst)?;
         self.buf.len().encode(dst)?;
-        dst.write(&self.buf)?;
+        checksum(&self.buf).encode(dst)?;
+        dst.write_all(&self.buf)?;
         Ok(())
     }
 }
 
+impl Default for FrameEncoder {
+    fn default() -> FrameEncoder {
+        FrameEncoder::new()
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct FrameInfo {
+    pub version: u8,
     pub prefix_len: usize,
     pub msg_len: usize,
+    pub checksum: u32,
 }
 
 impl FrameInfo {
-    pub fn from_bytes(buf: &[u8]) -> Option<FrameInfo> {
-        let prefix_len = size_of::<u64>();
+    // Returns `Ok(None)` until `buf` holds the whole frame prefix.
+    // Fails if the frame length does not fit in a usize.
+    pub fn from_bytes(buf: &[u8]) -> Result<Option<FrameInfo>, EncodableError> {
+        let prefix_len = VERSION_LEN + LENGTH_LEN + CHECKSUM_LEN;
         if buf.len() < prefix_len {
-            None
+            return Ok(None);
+        }
+        let mut prefix = &buf[..prefix_len];
+        let version = u8::decode(&mut prefix)?;
+        let msg_len = usize::decode(&mut prefix)?;
+        let checksum = u32::decode(&mut prefix)?;
+        if prefix_len.checked_add(msg_len).is_none() {
+            return Err(EncodableError::LengthTooLong(msg_len));
+        }
+        Ok(Some(FrameInfo {
+            version,
+            prefix_len,
+            msg_len,
+            checksum,
+        }))
+    }
+
+    pub fn check_version(&self) -> Result<(), EncodableError> {
+        if self.version == PROTOCOL_VERSION {
+            Ok(())
         } else {
-            let msg_len = usize::decode(&mut &buf[..prefix_len])
-                .expect("Could not decode message length from frame");
-            let f = FrameInfo {
-                prefix_len,
-                msg_len,
-            };
-            Some(f)
+            Err(EncodableError::UnsupportedVersion(self.version))
         }
     }
+
+    pub fn frame_len(&self) -> usize {
+        self.prefix_len + self.msg_len
+    }
+
+    pub fn verify_checksum(&self, msg: &[u8]) -> bool {
+        msg.len() == self.msg_len && checksum(msg) == self.checksum
+    }
+}
+
+fn checksum(msg: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(msg);
+    hasher.finalize()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::mem::size_of;
 
     #[test]
     fn it_encodes_and_decodes_frame() {
@@ -59,21 +107,92 @@ mod tests {
         encoder
             .encode_framed_msg(&msg, &mut buf)
             .expect("Could not encode");
-        assert_eq!(buf.len(), size_of::<usize>() + size_of::<u64>());
-        let frame_info = FrameInfo::from_bytes(&buf).expect("Could not decode frame info");
-        assert_eq!(frame_info.prefix_len, size_of::<usize>());
+        let prefix_len = size_of::<u8>() + size_of::<usize>() + size_of::<u32>();
+        assert_eq!(buf.len(), prefix_len + size_of::<u64>());
+        let frame_info = decode_frame_info(&buf);
+        assert_eq!(frame_info.version, PROTOCOL_VERSION);
+        assert!(frame_info.check_version().is_ok());
+        assert_eq!(frame_info.prefix_len, prefix_len);
         assert_eq!(frame_info.msg_len, size_of::<u64>());
+        assert_eq!(frame_info.frame_len(), buf.len());
+        assert!(frame_info.verify_checksum(&buf[prefix_len..]));
+    }
+
+    #[test]
+    fn it_detects_bit_flipped_frame() {
+        let mut encoder = FrameEncoder::new();
+        let mut buf = Vec::new();
+        encoder
+            .encode_framed_msg(&123456u64, &mut buf)
+            .expect("Could not encode");
+        let frame_info = decode_frame_info(&buf);
+        let n = buf.len();
+        buf[n - 1] ^= 0x01;
+        assert!(!frame_info.verify_checksum(&buf[frame_info.prefix_len..]));
+    }
+
+    #[test]
+    fn it_detects_truncated_frame() {
+        let mut encoder = FrameEncoder::new();
+        let mut buf = Vec::new();
+        encoder
+            .encode_framed_msg(&123456u64, &mut buf)
+            .expect("Could not encode");
+        let frame_info = decode_frame_info(&buf);
+        let n = buf.len();
+        assert!(!frame_info.verify_checksum(&buf[frame_info.prefix_len..n - 1]));
+        assert_incomplete(&buf[..frame_info.prefix_len - 1]);
+    }
+
+    #[test]
+    fn it_rejects_unsupported_version() {
+        let mut encoder = FrameEncoder::new();
+        let mut buf = Vec::new();
+        encoder
+            .encode_framed_msg(&123456u64, &mut buf)
+            .expect("Could not encode");
+        buf[0] = PROTOCOL_VERSION + 1;
+        let frame_info = decode_frame_info(&buf);
+        match frame_info.check_version() {
+            Err(EncodableError::UnsupportedVersion(v)) => assert_eq!(v, PROTOCOL_VERSION + 1),
+            _ => panic!("Expected unsupported version error"),
+        }
     }
 
     #[test]
     fn it_handles_empty_byte_array() {
         let buf = Vec::new();
-        assert_eq!(FrameInfo::from_bytes(&buf), None);
+        assert_incomplete(&buf);
     }
 
     #[test]
     fn it_handles_byte_array_with_fewer_than_8_bytes() {
         let buf: Vec<u8> = vec![1, 2, 3, 4, 5, 6, 7];
-        assert_eq!(FrameInfo::from_bytes(&buf), None);
+        assert_incomplete(&buf);
+    }
+
+    #[test]
+    fn it_rejects_frame_length_overflow() {
+        let mut buf = Vec::new();
+        PROTOCOL_VERSION.encode(&mut buf).expect("Could not encode");
+        (usize::MAX - 1).encode(&mut buf).expect("Could not encode");
+        0u32.encode(&mut buf).expect("Could not encode");
+        match FrameInfo::from_bytes(&buf) {
+            Err(EncodableError::LengthTooLong(len)) => assert_eq!(len, usize::MAX - 1),
+            _ => panic!("Expected length too long error"),
+        }
+    }
+
+    fn decode_frame_info(buf: &[u8]) -> FrameInfo {
+        FrameInfo::from_bytes(buf)
+            .expect("Could not decode frame info")
+            .expect("Frame prefix is incomplete")
+    }
+
+    fn assert_incomplete(buf: &[u8]) {
+        match FrameInfo::from_bytes(buf) {
+            Ok(None) => {}
+            _ => panic!("Expected incomplete frame prefix"),
+        }
     }
 }
@@ -93,7 +93,7 @@ mod tests {
     fn it_errors_if_not_enough_bytes() {
         let mut buf = Vec::new();
         let data = [0u8; 1];
-        buf.write(&data).unwrap();
+        buf.write_all(&data).unwrap();
         match u64::decode(&mut &buf[..]) {
             Err(err) => match err {
                 EncodableError::IOError(err) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
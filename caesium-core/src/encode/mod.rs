@@ -4,20 +4,27 @@ pub mod frame;
 pub mod int;
 pub mod slice;
 pub mod string;
+pub mod vbyte;
 
 #[macro_use]
 pub mod vec;
 
+use std::cmp::min;
 use std::io::Error as IOError;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::string::FromUtf8Error;
 
+// Decoders reserve at most this many items up front, so a corrupt length prefix
+// can't force a huge allocation before the input runs out
+pub const MAX_PREALLOC_LEN: usize = 4096;
+
 #[derive(Debug)]
 pub enum EncodableError {
     IOError(IOError),
     FromUtf8Error(FromUtf8Error),
     FormatError(&'static str),
     LengthTooLong(usize),
+    UnsupportedVersion(u8),
 }
 
 impl From<IOError> for EncodableError {
@@ -36,12 +43,26 @@ pub trait Encodable<W>
 where
     W: Write,
 {
-    fn encode(&self, &mut W) -> Result<(), EncodableError>;
+    fn encode(&self, writer: &mut W) -> Result<(), EncodableError>;
 }
 
 pub trait Decodable<T, R>
 where
     R: Read,
 {
-    fn decode(&mut R) -> Result<T, EncodableError>;
+    fn decode(reader: &mut R) -> Result<T, EncodableError>;
+}
+
+// Reads exactly `n` bytes, growing the buffer only as bytes arrive
+pub fn read_bytes<R>(reader: &mut R, n: usize) -> Result<Vec<u8>, EncodableError>
+where
+    R: Read,
+{
+    let mut data = Vec::with_capacity(min(n, MAX_PREALLOC_LEN));
+    reader.by_ref().take(n as u64).read_to_end(&mut data)?;
+    if data.len() < n {
+        let err = IOError::new(ErrorKind::UnexpectedEof, "Input ended before expected length");
+        return Err(EncodableError::IOError(err));
+    }
+    Ok(data)
 }
@@ -0,0 +1,214 @@
+// Variable-byte ("varint") encoding: each byte holds seven bits of the value,
+// least significant group first, and the high bit is set on every byte except the last.
+// Small values take a single byte, so this works well for deltas between sorted values.
+
+use encode::{Decodable, EncodableError, MAX_PREALLOC_LEN};
+use std::cmp::{max, min};
+use std::io::{Read, Write};
+
+const MAX_DATA_LEN: usize = 256000000; // 256 MB, should be enough for anything we need to encode
+const MAX_ENCODED_LEN: usize = 5; // ceil(32 / 7)
+
+pub fn vbyte_encode<W>(mut v: u32, writer: &mut W) -> Result<(), EncodableError>
+where
+    W: Write,
+{
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let mut i = 0;
+    while v >= 0x80 {
+        buf[i] = (v as u8 & 0x7f) | 0x80;
+        v >>= 7;
+        i += 1;
+    }
+    buf[i] = v as u8;
+    writer.write_all(&buf[..i + 1])?;
+    Ok(())
+}
+
+// Number of bytes `vbyte_encode` writes for `v`
+pub fn vbyte_len(v: u32) -> usize {
+    let num_bits = 32 - v.leading_zeros() as usize;
+    max(1, num_bits.div_ceil(7)) // at least one byte, even for value 0
+}
+
+pub fn vbyte_decode<R>(reader: &mut R) -> Result<u32, EncodableError>
+where
+    R: Read,
+{
+    let mut result = 0u32;
+    for i in 0..MAX_ENCODED_LEN {
+        let b = u8::decode(reader)?;
+        let bits = (b & 0x7f) as u32;
+        if i == MAX_ENCODED_LEN - 1 && bits > 0x0f {
+            return Err(EncodableError::FormatError("vbyte value overflows u32"));
+        }
+        result |= bits << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    Err(EncodableError::FormatError("vbyte value too long"))
+}
+
+// Data *must* be sorted ascending
+pub fn delta_vbyte_encode<W>(data: &[u32], writer: &mut W) -> Result<(), EncodableError>
+where
+    W: Write,
+{
+    let n = data.len();
+    if n > MAX_DATA_LEN {
+        return Err(EncodableError::LengthTooLong(n));
+    }
+
+    // Buffer so that each small value doesn't cost a separate write call
+    let mut buf = Vec::with_capacity(n + MAX_ENCODED_LEN);
+    vbyte_encode(n as u32, &mut buf)?;
+    let mut prev = 0;
+    for &v in data {
+        debug_assert!(v >= prev);
+        vbyte_encode(v - prev, &mut buf)?;
+        prev = v;
+    }
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+// Number of bytes `delta_vbyte_encode` writes for `data`, which *must* be sorted ascending
+pub fn delta_vbyte_len(data: &[u32]) -> usize {
+    let mut len = vbyte_len(data.len() as u32);
+    let mut prev = 0;
+    for &v in data {
+        len += vbyte_len(v - prev);
+        prev = v;
+    }
+    len
+}
+
+pub fn delta_vbyte_decode<R>(reader: &mut R) -> Result<Vec<u32>, EncodableError>
+where
+    R: Read,
+{
+    delta_vbyte_decode_with_limit(reader, MAX_DATA_LEN)
+}
+
+// Fails with `LengthTooLong` before reading any values if there are more than `max_len`
+pub fn delta_vbyte_decode_with_limit<R>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Vec<u32>, EncodableError>
+where
+    R: Read,
+{
+    let n = vbyte_decode(reader)? as usize;
+    if n > min(max_len, MAX_DATA_LEN) {
+        return Err(EncodableError::LengthTooLong(n));
+    }
+
+    let mut result = Vec::with_capacity(min(n, MAX_PREALLOC_LEN));
+    let mut prev = 0u32;
+    for _ in 0..n {
+        let delta = vbyte_decode(reader)?;
+        prev = prev
+            .checked_add(delta)
+            .ok_or(EncodableError::FormatError("vbyte delta overflows u32"))?;
+        result.push(prev);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_and_decodes_single_values() {
+        for &v in [0, 1, 127, 128, 16383, 16384, 1 << 21, 1 << 28, u32::MAX].iter() {
+            let mut buf = Vec::new();
+            vbyte_encode(v, &mut buf).expect("Could not encode value");
+            let decoded = vbyte_decode(&mut &buf[..]).expect("Could not decode value");
+            assert_eq!(decoded, v);
+        }
+    }
+
+    #[test]
+    fn it_uses_one_byte_per_seven_bits() {
+        assert_eq!(encoded_len(0), 1);
+        assert_eq!(encoded_len(127), 1);
+        assert_eq!(encoded_len(128), 2);
+        assert_eq!(encoded_len(16383), 2);
+        assert_eq!(encoded_len(16384), 3);
+        assert_eq!(encoded_len(u32::MAX), 5);
+    }
+
+    #[test]
+    fn it_calculates_encoded_len_without_encoding() {
+        for &v in [0, 1, 127, 128, 16383, 16384, 1 << 21, 1 << 28, u32::MAX].iter() {
+            assert_eq!(vbyte_len(v), encoded_len(v));
+        }
+        for data in [vec![], vec![0, 0, 1], vec![5, 300, 70000, u32::MAX]].iter() {
+            let mut buf = Vec::new();
+            delta_vbyte_encode(data, &mut buf).expect("Could not encode data");
+            assert_eq!(delta_vbyte_len(data), buf.len());
+        }
+    }
+
+    #[test]
+    fn it_rejects_value_overflowing_u32() {
+        let buf = [0xff, 0xff, 0xff, 0xff, 0x1f];
+        match vbyte_decode(&mut &buf[..]) {
+            Err(EncodableError::FormatError(_)) => {}
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn it_rejects_unterminated_value() {
+        let buf = [0x80, 0x80];
+        assert!(vbyte_decode(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn it_delta_encodes_and_decodes_empty() {
+        assert_delta_encodes_and_decodes(&[]);
+    }
+
+    #[test]
+    fn it_delta_encodes_and_decodes_sorted_values() {
+        let data: Vec<u32> = (0..32).map(|x| (1 << x) as u32).collect();
+        assert_delta_encodes_and_decodes(&data);
+        assert_delta_encodes_and_decodes(&[0, 0, 5, 5, 5, 200, 70000, u32::MAX]);
+    }
+
+    #[test]
+    fn it_rejects_delta_len_above_limit() {
+        let mut buf = Vec::new();
+        delta_vbyte_encode(&[1, 2, 3], &mut buf).unwrap();
+        assert!(delta_vbyte_decode_with_limit(&mut &buf[..], 3).is_ok());
+        match delta_vbyte_decode_with_limit(&mut &buf[..], 2) {
+            Err(EncodableError::LengthTooLong(3)) => {}
+            r => panic!("Expected length too long, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn it_rejects_delta_overflowing_u32() {
+        let mut buf = Vec::new();
+        vbyte_encode(2, &mut buf).unwrap();
+        vbyte_encode(u32::MAX, &mut buf).unwrap();
+        vbyte_encode(1, &mut buf).unwrap();
+        assert!(delta_vbyte_decode(&mut &buf[..]).is_err());
+    }
+
+    fn encoded_len(v: u32) -> usize {
+        let mut buf = Vec::new();
+        vbyte_encode(v, &mut buf).expect("Could not encode value");
+        buf.len()
+    }
+
+    fn assert_delta_encodes_and_decodes(data: &[u32]) {
+        let mut buf = Vec::new();
+        delta_vbyte_encode(data, &mut buf).expect("Could not encode data");
+        let decoded = delta_vbyte_decode(&mut &buf[..]).expect("Could not decode data");
+        assert_eq!(decoded, data);
+    }
+}
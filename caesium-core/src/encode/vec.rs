@@ -1,4 +1,5 @@
-use encode::{Decodable, Encodable, EncodableError};
+use encode::{read_bytes, Decodable, Encodable, EncodableError, MAX_PREALLOC_LEN};
+use std::cmp::min;
 use std::io::{Read, Write};
 
 const MAX_VEC_LEN: usize = 256000000; // 256 MB, should be enough for anything we need to encode
@@ -33,7 +34,7 @@ macro_rules! build_encodable_vec_type {
                     return Err(EncodableError::LengthTooLong(len));
                 }
 
-                let mut result = Vec::<$type>::with_capacity(len);
+                let mut result = Vec::<$type>::with_capacity(min(len, MAX_PREALLOC_LEN));
                 for _ in 0..len {
                     let v = <$type>::decode(reader)?;
                     result.push(v);
@@ -58,7 +59,7 @@ where
             return Err(EncodableError::LengthTooLong(len));
         }
         len.encode(writer)?;
-        writer.write_all(&self)?;
+        writer.write_all(self)?;
         Ok(())
     }
 }
@@ -72,9 +73,7 @@ where
         if n > MAX_VEC_LEN {
             return Err(EncodableError::LengthTooLong(n));
         }
-        let mut data = vec![0; n];
-        reader.read_exact(&mut data)?;
-        Ok(data)
+        read_bytes(reader, n)
     }
 }
 
@@ -103,11 +102,22 @@ mod tests {
 
     #[test]
     fn it_rejects_decoding_vec_u8_len_greater_than_limit() {
-        let buf = vec![0xFF; 20];
+        let buf = [0xFF; 20];
         if let Err(EncodableError::LengthTooLong(_)) = Vec::<u8>::decode(&mut &buf[..]) {
             // Expected error
         } else {
-            assert!(false, "Expected error b/c length is too long");
+            panic!("Expected error b/c length is too long");
+        }
+    }
+
+    #[test]
+    fn it_rejects_decoding_vec_u8_shorter_than_len() {
+        let mut buf = Vec::new();
+        (MAX_VEC_LEN - 1).encode(&mut buf).unwrap();
+        buf.extend_from_slice(&[1, 2, 3]);
+        match Vec::<u8>::decode(&mut &buf[..]) {
+            Err(EncodableError::IOError(_)) => {}
+            r => panic!("Expected IO error, got {:?}", r),
         }
     }
 
@@ -124,7 +134,7 @@ mod tests {
     #[test]
     fn it_encodes_and_decodes_u64_vec() {
         let mut buf = Vec::new();
-        let data = vec![1u64, 2u64, u64::max_value() - 1];
+        let data = vec![1u64, 2u64, u64::MAX - 1];
         data.encode(&mut buf).expect("Could not encode Vec<u64>");
         let decoded = Vec::<u64>::decode(&mut &buf[..]).expect("Could not decode Vec<u64>");
         assert_eq!(data, decoded);
@@ -132,11 +142,11 @@ mod tests {
 
     #[test]
     fn it_rejects_decoding_vec_u64_len_greater_than_limit() {
-        let buf = vec![0xFF; 20];
+        let buf = [0xFF; 20];
         if let Err(EncodableError::LengthTooLong(_)) = Vec::<u64>::decode(&mut &buf[..]) {
             // Expected error
         } else {
-            assert!(false, "Expected error b/c length is too long");
+            panic!("Expected error b/c length is too long");
         }
     }
 }
@@ -1,9 +1,15 @@
 extern crate byteorder;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+extern crate crc32fast;
+#[cfg(test)]
+extern crate proptest;
 extern crate rand;
 extern crate slab;
 
 #[macro_use]
 pub mod encode;
+pub mod metric;
 pub mod protocol;
 pub mod quantile;
 pub mod time;
@@ -15,6 +21,16 @@ pub enum SketchType {
     KllWithSampler,
 }
 
+impl SketchType {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SketchType::Baseline => "baseline",
+            SketchType::KllNoSampler => "kll_nosampler",
+            SketchType::KllWithSampler => "kll",
+        }
+    }
+}
+
 pub fn get_sketch_type() -> SketchType {
     if cfg!(feature = "baseline") {
         SketchType::Baseline
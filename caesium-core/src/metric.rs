@@ -0,0 +1,191 @@
+use std::fmt;
+
+// Metric names have the form `base;key1=value1;key2=value2`.
+// The canonical form lists each tag key at most once, sorted by key,
+// so the same series always maps to the same name.
+pub const TAG_SEPARATOR: char = ';';
+pub const TAG_ASSIGN: char = '=';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricName {
+    base: String,
+    tags: Vec<(String, String)>,
+}
+
+impl MetricName {
+    pub fn parse(s: &str) -> Option<MetricName> {
+        let mut parts = s.split(TAG_SEPARATOR);
+        let base = parts.next()?;
+        if !is_valid_identifier(base) {
+            return None;
+        }
+        let mut tags = Vec::new();
+        for part in parts {
+            tags.push(parse_tag(part)?);
+        }
+        tags.sort();
+        for i in 1..tags.len() {
+            if tags[i - 1].0 == tags[i].0 {
+                return None;
+            }
+        }
+        Some(MetricName {
+            base: base.to_string(),
+            tags,
+        })
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl fmt::Display for MetricName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.base)?;
+        for (key, value) in self.tags.iter() {
+            write!(f, "{}{}{}{}", TAG_SEPARATOR, key, TAG_ASSIGN, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Returns the canonical form of a metric name, or `None` if the name is invalid
+pub fn canonicalize(s: &str) -> Option<String> {
+    MetricName::parse(s).map(|m| m.to_string())
+}
+
+fn parse_tag(s: &str) -> Option<(String, String)> {
+    let (key, value) = s.split_once(TAG_ASSIGN)?;
+    if is_valid_identifier(key) && is_valid_tag_value(value) {
+        Some((key.to_string(), value.to_string()))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "unicode-names"))]
+fn is_valid_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+        }
+        _ => false,
+    }
+}
+
+// With the `unicode-names` feature, identifiers may contain any Unicode letters and digits
+#[cfg(feature = "unicode-names")]
+fn is_valid_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() => {
+            chars.all(|c| c.is_alphanumeric() || c == '.' || c == '_' || c == '-')
+        }
+        _ => false,
+    }
+}
+
+fn is_valid_tag_value(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-' || c == '/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_name_without_tags() {
+        let m = MetricName::parse("http.latency").expect("Could not parse name");
+        assert_eq!(m.base(), "http.latency");
+        assert!(m.tags().is_empty());
+        assert_eq!(m.to_string(), "http.latency");
+    }
+
+    #[test]
+    fn it_parses_name_with_tags() {
+        let m = MetricName::parse("http.latency;endpoint=/login;region=us")
+            .expect("Could not parse name");
+        assert_eq!(m.base(), "http.latency");
+        assert_eq!(m.tag("endpoint"), Some("/login"));
+        assert_eq!(m.tag("region"), Some("us"));
+        assert_eq!(m.tag("host"), None);
+    }
+
+    #[test]
+    fn it_sorts_tags_by_key() {
+        assert_eq!(
+            canonicalize("foo;region=us;endpoint=/login;az=b"),
+            Some("foo;az=b;endpoint=/login;region=us".to_string())
+        );
+    }
+
+    #[test]
+    fn it_rejects_duplicate_tag_keys() {
+        assert_eq!(canonicalize("foo;region=us;region=eu"), None);
+    }
+
+    #[test]
+    fn it_rejects_invalid_names() {
+        assert_eq!(canonicalize(""), None);
+        assert_eq!(canonicalize("1foo"), None);
+        assert_eq!(canonicalize("!foo"), None);
+        assert_eq!(canonicalize("foo;"), None);
+        assert_eq!(canonicalize("foo;region"), None);
+        assert_eq!(canonicalize("foo;region="), None);
+        assert_eq!(canonicalize("foo;=us"), None);
+        assert_eq!(canonicalize("foo;1region=us"), None);
+        assert_eq!(canonicalize("foo;region=u*s"), None);
+        assert_eq!(canonicalize("foo;region=us=eu"), None);
+    }
+
+    #[test]
+    fn it_rejects_emoji_names() {
+        assert_eq!(canonicalize("\u{1F600}"), None);
+        assert_eq!(canonicalize("foo\u{1F600}"), None);
+        assert_eq!(canonicalize("foo;\u{1F600}=us"), None);
+    }
+
+    #[cfg(not(feature = "unicode-names"))]
+    #[test]
+    fn it_rejects_unicode_names() {
+        assert_eq!(canonicalize("レイテンシ"), None);
+        assert_eq!(canonicalize("زمن.الاستجابة"), None);
+        assert_eq!(canonicalize("foo;地域=東京"), None);
+    }
+
+    #[cfg(feature = "unicode-names")]
+    #[test]
+    fn it_accepts_unicode_names() {
+        assert_eq!(canonicalize("レイテンシ"), Some("レイテンシ".to_string()));
+        assert_eq!(
+            canonicalize("زمن.الاستجابة_2"),
+            Some("زمن.الاستجابة_2".to_string())
+        );
+        assert_eq!(
+            canonicalize("レイテンシ;地域=us;az=b"),
+            Some("レイテンシ;az=b;地域=us".to_string())
+        );
+    }
+
+    #[cfg(feature = "unicode-names")]
+    #[test]
+    fn it_rejects_unicode_names_starting_with_digit() {
+        assert_eq!(canonicalize("٣foo"), None);
+        assert_eq!(canonicalize("1レイテンシ"), None);
+    }
+}
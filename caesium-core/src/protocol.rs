@@ -1,11 +1,59 @@
+// Version of the wire protocol, sent at the start of each frame.
+// Increment this whenever the frame or message encoding changes.
+pub const PROTOCOL_VERSION: u8 = 3;
+
 pub mod messages {
-    use encode::{Decodable, Encodable, EncodableError};
+    use encode::{Decodable, Encodable, EncodableError, MAX_PREALLOC_LEN};
     use quantile::writable::WritableSketch;
+    use std::cmp::min;
     use std::io::{Read, Write};
     use time::window::TimeWindow;
 
+    const TIMER_KIND: u8 = 0;
+    const COUNTER_KIND: u8 = 1;
+    const GAUGE_KIND: u8 = 2;
+
+    // How the values for a metric were aggregated within a window.
+    // Timers are summarized by a quantile sketch; counters and gauges
+    // carry a single aggregated value (the sum or the last value).
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum MetricKind {
+        Timer,
+        Counter,
+        Gauge,
+    }
+
+    impl<W> Encodable<W> for MetricKind
+    where
+        W: Write,
+    {
+        fn encode(&self, writer: &mut W) -> Result<(), EncodableError> {
+            let kind = match self {
+                MetricKind::Timer => TIMER_KIND,
+                MetricKind::Counter => COUNTER_KIND,
+                MetricKind::Gauge => GAUGE_KIND,
+            };
+            kind.encode(writer)
+        }
+    }
+
+    impl<R> Decodable<MetricKind, R> for MetricKind
+    where
+        R: Read,
+    {
+        fn decode(mut reader: &mut R) -> Result<MetricKind, EncodableError> {
+            match u8::decode(&mut reader)? {
+                TIMER_KIND => Ok(MetricKind::Timer),
+                COUNTER_KIND => Ok(MetricKind::Counter),
+                GAUGE_KIND => Ok(MetricKind::Gauge),
+                _ => Err(EncodableError::FormatError("Unrecognized metric kind")),
+            }
+        }
+    }
+
     pub struct InsertMessage {
         pub metric: String,
+        pub kind: MetricKind,
         pub window: TimeWindow,
         pub sketch: WritableSketch,
     }
@@ -16,6 +64,7 @@ pub mod messages {
     {
         fn encode(&self, writer: &mut W) -> Result<(), EncodableError> {
             self.metric.encode(writer)?;
+            self.kind.encode(writer)?;
             self.window.encode(writer)?;
             self.sketch.encode(writer)?;
             Ok(())
@@ -28,16 +77,142 @@ pub mod messages {
     {
         fn decode(mut reader: &mut R) -> Result<InsertMessage, EncodableError> {
             let metric = String::decode(&mut reader)?;
+            let kind = MetricKind::decode(&mut reader)?;
             let window = TimeWindow::decode(&mut reader)?;
             let sketch = WritableSketch::decode(&mut reader)?;
             Ok(InsertMessage {
                 metric,
+                kind,
                 window,
                 sketch,
             })
         }
     }
 
+    const MAX_BATCH_LEN: usize = 65536;
+
+    pub struct BatchInsertMessage {
+        pub inserts: Vec<InsertMessage>,
+    }
+
+    impl<W> Encodable<W> for BatchInsertMessage
+    where
+        W: Write,
+    {
+        fn encode(&self, writer: &mut W) -> Result<(), EncodableError> {
+            let len = self.inserts.len();
+            if len > MAX_BATCH_LEN {
+                return Err(EncodableError::LengthTooLong(len));
+            }
+            len.encode(writer)?;
+            for msg in self.inserts.iter() {
+                msg.encode(writer)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl<R> Decodable<BatchInsertMessage, R> for BatchInsertMessage
+    where
+        R: Read,
+    {
+        fn decode(mut reader: &mut R) -> Result<BatchInsertMessage, EncodableError> {
+            let len = usize::decode(&mut reader)?;
+            if len > MAX_BATCH_LEN {
+                return Err(EncodableError::LengthTooLong(len));
+            }
+            let mut inserts = Vec::with_capacity(min(len, MAX_PREALLOC_LEN));
+            for _ in 0..len {
+                inserts.push(InsertMessage::decode(&mut reader)?);
+            }
+            Ok(BatchInsertMessage { inserts })
+        }
+    }
+
+    // Sent as the first message on a connection when the server requires
+    // a shared-secret token
+    pub struct AuthMessage {
+        pub token: String,
+    }
+
+    impl<W> Encodable<W> for AuthMessage
+    where
+        W: Write,
+    {
+        fn encode(&self, writer: &mut W) -> Result<(), EncodableError> {
+            self.token.encode(writer)
+        }
+    }
+
+    impl<R> Decodable<AuthMessage, R> for AuthMessage
+    where
+        R: Read,
+    {
+        fn decode(mut reader: &mut R) -> Result<AuthMessage, EncodableError> {
+            let token = String::decode(&mut reader)?;
+            Ok(AuthMessage { token })
+        }
+    }
+
+    const INSERT_MSG_TYPE: u8 = 0;
+    const BATCH_INSERT_MSG_TYPE: u8 = 1;
+    const AUTH_MSG_TYPE: u8 = 2;
+
+    // Messages sent to the write server, prefixed by a byte identifying the message type.
+    // Single inserts are boxed, so the enum isn't the size of a sketch for every message.
+    pub enum WriteMessage {
+        Insert(Box<InsertMessage>),
+        BatchInsert(BatchInsertMessage),
+        Auth(AuthMessage),
+    }
+
+    impl<W> Encodable<W> for WriteMessage
+    where
+        W: Write,
+    {
+        fn encode(&self, writer: &mut W) -> Result<(), EncodableError> {
+            match self {
+                WriteMessage::Insert(msg) => {
+                    INSERT_MSG_TYPE.encode(writer)?;
+                    msg.encode(writer)
+                }
+                WriteMessage::BatchInsert(msg) => {
+                    BATCH_INSERT_MSG_TYPE.encode(writer)?;
+                    msg.encode(writer)
+                }
+                WriteMessage::Auth(msg) => {
+                    AUTH_MSG_TYPE.encode(writer)?;
+                    msg.encode(writer)
+                }
+            }
+        }
+    }
+
+    impl<R> Decodable<WriteMessage, R> for WriteMessage
+    where
+        R: Read,
+    {
+        fn decode(mut reader: &mut R) -> Result<WriteMessage, EncodableError> {
+            match u8::decode(&mut reader)? {
+                INSERT_MSG_TYPE => {
+                    let msg = InsertMessage::decode(&mut reader)?;
+                    Ok(WriteMessage::Insert(Box::new(msg)))
+                }
+                BATCH_INSERT_MSG_TYPE => {
+                    let msg = BatchInsertMessage::decode(&mut reader)?;
+                    Ok(WriteMessage::BatchInsert(msg))
+                }
+                AUTH_MSG_TYPE => {
+                    let msg = AuthMessage::decode(&mut reader)?;
+                    Ok(WriteMessage::Auth(msg))
+                }
+                _ => Err(EncodableError::FormatError(
+                    "Unrecognized write message type",
+                )),
+            }
+        }
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
@@ -46,6 +221,7 @@ pub mod messages {
         fn it_encodes_and_decodes_insert_msg() {
             let msg = InsertMessage {
                 metric: "foo".to_string(),
+                kind: MetricKind::Counter,
                 window: TimeWindow::new(2, 3),
                 sketch: WritableSketch::new(),
             };
@@ -54,9 +230,78 @@ pub mod messages {
             let decoded =
                 InsertMessage::decode(&mut &buf[..]).expect("Could not decode insert msg");
             assert_eq!(decoded.metric, "foo");
+            assert_eq!(decoded.kind, MetricKind::Counter);
             assert_eq!(decoded.window.start(), 2);
             assert_eq!(decoded.window.end(), 3);
             assert_eq!(decoded.sketch.size(), 0);
         }
+
+        #[test]
+        fn it_rejects_unrecognized_metric_kind() {
+            let buf = [99u8];
+            match MetricKind::decode(&mut &buf[..]) {
+                Err(EncodableError::FormatError(_)) => {}
+                _ => panic!("Expected format error"),
+            }
+        }
+
+        #[test]
+        fn it_encodes_and_decodes_batch_insert_msg() {
+            let msg = BatchInsertMessage {
+                inserts: vec![build_insert_msg("foo", 2, 3), build_insert_msg("bar", 4, 5)],
+            };
+            let mut buf = Vec::new();
+            msg.encode(&mut buf).expect("Could not encode batch msg");
+            let decoded =
+                BatchInsertMessage::decode(&mut &buf[..]).expect("Could not decode batch msg");
+            let metrics: Vec<&str> = decoded.inserts.iter().map(|m| m.metric.as_str()).collect();
+            assert_eq!(metrics, vec!["foo", "bar"]);
+            assert_eq!(decoded.inserts[1].window.start(), 4);
+            assert_eq!(decoded.inserts[1].window.end(), 5);
+        }
+
+        #[test]
+        fn it_encodes_and_decodes_write_msg() {
+            let msg = WriteMessage::BatchInsert(BatchInsertMessage {
+                inserts: vec![build_insert_msg("foo", 2, 3)],
+            });
+            let mut buf = Vec::new();
+            msg.encode(&mut buf).expect("Could not encode write msg");
+            match WriteMessage::decode(&mut &buf[..]) {
+                Ok(WriteMessage::BatchInsert(batch)) => assert_eq!(batch.inserts.len(), 1),
+                _ => panic!("Expected batch insert msg"),
+            }
+        }
+
+        #[test]
+        fn it_encodes_and_decodes_auth_msg() {
+            let msg = WriteMessage::Auth(AuthMessage {
+                token: "secret".to_string(),
+            });
+            let mut buf = Vec::new();
+            msg.encode(&mut buf).expect("Could not encode auth msg");
+            match WriteMessage::decode(&mut &buf[..]) {
+                Ok(WriteMessage::Auth(auth)) => assert_eq!(auth.token, "secret"),
+                _ => panic!("Expected auth msg"),
+            }
+        }
+
+        #[test]
+        fn it_rejects_unrecognized_write_msg_type() {
+            let buf = [99u8];
+            match WriteMessage::decode(&mut &buf[..]) {
+                Err(EncodableError::FormatError(_)) => {}
+                _ => panic!("Expected format error"),
+            }
+        }
+
+        fn build_insert_msg(metric: &str, start: u64, end: u64) -> InsertMessage {
+            InsertMessage {
+                metric: metric.to_string(),
+                kind: MetricKind::Timer,
+                window: TimeWindow::new(start, end),
+                sketch: WritableSketch::new(),
+            }
+        }
     }
 }
@@ -1,11 +1,21 @@
-use encode::delta::{delta_decode, delta_encode};
+use encode::delta::{delta_decode, delta_encode, delta_encoded_len};
 use encode::{Decodable, Encodable, EncodableError};
 use quantile::query::UnweightedQuerySketch;
+use quantile::scale::{to_fixed, to_sketch_value, MAX_SCALE};
 use std::io::{Read, Write};
+use std::iter::FromIterator;
+use std::mem::size_of;
+
+// Sketches encoded before the format was versioned start with the number of values,
+// which can never be this large, so the marker distinguishes the two formats.
+// Version 1 added the scale.
+const FORMAT_MARKER: u64 = u64::MAX;
+const FORMAT_VERSION: u8 = 1;
 
 #[derive(Clone)]
 pub struct BaselineSketch {
     is_sorted: bool,
+    scale: u32,
     data: Vec<u32>,
 }
 
@@ -13,26 +23,82 @@ impl BaselineSketch {
     pub fn new() -> BaselineSketch {
         BaselineSketch {
             is_sorted: true,
+            scale: 0,
             data: Vec::new(),
         }
     }
 
+    pub fn from_slice(values: &[u64]) -> BaselineSketch {
+        values.iter().cloned().collect()
+    }
+
+    // Remove all values so the sketch can be reused, keeping the allocated buffer
+    pub fn reset(&mut self) {
+        self.is_sorted = true;
+        self.scale = 0;
+        self.data.clear();
+    }
+
+    // Integer values inserted into a sketch of decimal values are converted to its scale,
+    // so `insert(v)` always records the value `v`.
     pub fn insert(&mut self, val: u32) {
+        let val = self.to_sketch_scale(val);
         self.is_sorted = false;
         self.data.push(val);
     }
 
-    pub fn merge(mut self, other: BaselineSketch) -> BaselineSketch {
+    // Insert a value that counts as `weight` observations
+    pub fn insert_weighted(&mut self, val: u32, weight: usize) {
+        assert!(weight > 0);
+        let val = self.to_sketch_scale(val);
+        self.is_sorted = false;
+        self.data.extend((0..weight).map(|_| val));
+    }
+
+    // Fails if the sketch already has values at another scale, including integers
+    // inserted with `insert` into an unscaled sketch.
+    pub fn insert_f64(&mut self, val: f64, scale: u32) -> Result<(), EncodableError> {
+        if scale > MAX_SCALE {
+            return Err(EncodableError::FormatError("Scale value too large"));
+        }
+        if self.data.is_empty() {
+            self.scale = scale;
+        } else if self.scale != scale {
+            return Err(EncodableError::FormatError(
+                "Inserted value scale does not match sketch",
+            ));
+        }
+        self.is_sorted = false;
+        self.data.push(to_fixed(val, scale));
+        Ok(())
+    }
+
+    // Fails if the sketches have different scales
+    pub fn merge(mut self, other: BaselineSketch) -> Result<BaselineSketch, EncodableError> {
+        self.merge_from(other)?;
+        Ok(self)
+    }
+
+    // Merge `other` into this sketch in place, so callers can keep accumulating into it.
+    // Fails without changing this sketch if the sketches have different scales.
+    pub fn merge_from(&mut self, other: BaselineSketch) -> Result<(), EncodableError> {
+        if self.data.is_empty() {
+            self.scale = other.scale;
+        } else if !other.data.is_empty() && self.scale != other.scale {
+            return Err(EncodableError::FormatError(
+                "Cannot merge sketches with different scales",
+            ));
+        }
         self.is_sorted = false;
         self.data.extend_from_slice(&other.data);
-        self
+        Ok(())
     }
 
     pub fn to_readable(mut self) -> UnweightedQuerySketch {
         if !self.is_sorted {
             self.data.sort_unstable();
         }
-        UnweightedQuerySketch::new(self.data)
+        UnweightedQuerySketch::new(self.data).with_scale(self.scale)
     }
 
     pub fn count(&self) -> usize {
@@ -42,6 +108,56 @@ impl BaselineSketch {
     pub fn size(&self) -> usize {
         self.data.len()
     }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    // Number of bytes written by `encode`, without encoding the sketch.
+    // Unsorted data is sorted in place first, since the encoded deltas depend on the order.
+    pub fn serialized_len(&mut self) -> usize {
+        if !self.is_sorted {
+            self.data.sort_unstable();
+            self.is_sorted = true;
+        }
+        size_of::<u64>() // format marker
+            + size_of::<u8>() // format version
+            + size_of::<u32>() // scale
+            + delta_encoded_len(&self.data)
+    }
+
+    fn to_sketch_scale(&self, val: u32) -> u32 {
+        if self.scale == 0 {
+            val
+        } else {
+            to_fixed(f64::from(val), self.scale)
+        }
+    }
+}
+
+impl Default for BaselineSketch {
+    fn default() -> BaselineSketch {
+        BaselineSketch::new()
+    }
+}
+
+impl FromIterator<u64> for BaselineSketch {
+    fn from_iter<I>(iter: I) -> BaselineSketch
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let mut s = BaselineSketch::new();
+        for v in iter {
+            s.insert(to_sketch_value(v));
+        }
+        s
+    }
+}
+
+impl From<Vec<u64>> for BaselineSketch {
+    fn from(values: Vec<u64>) -> BaselineSketch {
+        values.into_iter().collect()
+    }
 }
 
 impl<W> Encodable<W> for BaselineSketch
@@ -57,7 +173,10 @@ where
             tmp.sort_unstable();
             &tmp
         };
-        delta_encode(&data, writer)?;
+        FORMAT_MARKER.encode(writer)?;
+        FORMAT_VERSION.encode(writer)?;
+        self.scale.encode(writer)?;
+        delta_encode(data, writer)?;
         Ok(())
     }
 }
@@ -67,9 +186,31 @@ where
     R: Read,
 {
     fn decode(reader: &mut R) -> Result<BaselineSketch, EncodableError> {
+        let prefix = u64::decode(reader)?;
+        if prefix != FORMAT_MARKER {
+            // The legacy format is just the values, and the prefix was their length
+            let mut prefix_bytes = Vec::with_capacity(size_of::<u64>());
+            prefix.encode(&mut prefix_bytes)?;
+            let data = delta_decode(&mut (&prefix_bytes[..]).chain(reader))?;
+            return Ok(BaselineSketch {
+                is_sorted: true,
+                scale: 0,
+                data,
+            });
+        }
+        if u8::decode(reader)? != FORMAT_VERSION {
+            return Err(EncodableError::FormatError(
+                "Unsupported sketch format version",
+            ));
+        }
+        let scale = u32::decode(reader)?;
+        if scale > MAX_SCALE {
+            return Err(EncodableError::FormatError("Scale value too large"));
+        }
         let data = delta_decode(reader)?;
         Ok(BaselineSketch {
             is_sorted: true,
+            scale,
             data,
         })
     }
@@ -88,6 +229,27 @@ mod tests {
         assert_query(s, 10, 5);
     }
 
+    #[test]
+    fn it_inserts_weighted_values() {
+        let mut s = BaselineSketch::new();
+        s.insert(1);
+        s.insert_weighted(9, 4);
+        assert_eq!(s.count(), 5);
+        assert_query(s, 5, 9);
+    }
+
+    #[test]
+    fn it_resets_to_empty_sketch() {
+        let mut s = BaselineSketch::new();
+        s.insert_f64(1.5, 1).expect("Could not insert value");
+        s.insert(3);
+        s.reset();
+        assert_eq!(s.count(), 0);
+        assert_eq!(s.scale(), 0);
+        s.insert(5);
+        assert_query(s, 1, 5);
+    }
+
     #[test]
     fn it_merges() {
         let mut s1 = BaselineSketch::new();
@@ -96,10 +258,18 @@ mod tests {
             s1.insert(i as u32);
             s2.insert((i + 10) as u32);
         }
-        let s = s1.merge(s2);
+        let s = s1.merge(s2).expect("Could not merge sketches");
         assert_query(s, 20, 10);
     }
 
+    #[test]
+    fn it_merges_in_place() {
+        let mut s1 = BaselineSketch::from_slice(&[1, 2, 3]);
+        let s2 = BaselineSketch::from_slice(&[4, 5, 6, 7]);
+        s1.merge_from(s2).expect("Could not merge sketches");
+        assert_query(s1, 7, 4);
+    }
+
     #[test]
     fn it_encodes_and_decodes() {
         let mut s = BaselineSketch::new();
@@ -121,6 +291,20 @@ mod tests {
         assert_query(decoded, 10, 5);
     }
 
+    #[test]
+    fn it_calculates_serialized_len() {
+        let unsorted = BaselineSketch::from(vec![9, 70000, 1, 300, 2]);
+        let merged = unsorted
+            .clone()
+            .merge(BaselineSketch::from(vec![4, 4]))
+            .expect("Could not merge sketches");
+        for s in [BaselineSketch::new(), unsorted, merged].iter_mut() {
+            let mut buf = Vec::new();
+            s.encode(&mut buf).expect("Could not encode sketch");
+            assert_eq!(s.serialized_len(), buf.len());
+        }
+    }
+
     #[test]
     fn it_encodes_and_decodes_after_merge() {
         let mut s1 = BaselineSketch::new();
@@ -131,10 +315,79 @@ mod tests {
         }
         let d1 = encode_and_decode(s1);
         let d2 = encode_and_decode(s2);
-        let s = encode_and_decode(d2.merge(d1));
+        let s = encode_and_decode(d2.merge(d1).expect("Could not merge sketches"));
         assert_query(s, 20, 10);
     }
 
+    #[test]
+    fn it_inserts_f64_values() {
+        let mut s = BaselineSketch::new();
+        for v in &[1.25, 1.50, 1.75] {
+            s.insert_f64(*v, 2).expect("Could not insert value");
+        }
+        let decoded = encode_and_decode(s);
+        assert_eq!(decoded.scale(), 2);
+        let q = decoded.to_readable().query(0.5).expect("Could not query");
+        assert_eq!(q.approx_value_f64(), 1.5);
+    }
+
+    #[test]
+    fn it_rejects_f64_values_with_different_scale() {
+        let mut s = BaselineSketch::new();
+        s.insert_f64(1.25, 2).expect("Could not insert value");
+        assert!(s.insert_f64(1.5, 1).is_err());
+        assert!(s.insert_f64(1.5, MAX_SCALE + 1).is_err());
+        assert_eq!(s.count(), 1);
+
+        let mut unscaled = BaselineSketch::new();
+        unscaled.insert(3);
+        assert!(unscaled.insert_f64(1.5, 1).is_err());
+        assert_eq!(unscaled.count(), 1);
+        assert_eq!(unscaled.scale(), 0);
+    }
+
+    #[test]
+    fn it_converts_integer_values_to_sketch_scale() {
+        let mut s = BaselineSketch::new();
+        s.insert_f64(1.25, 2).expect("Could not insert value");
+        s.insert(2);
+        s.insert_weighted(3, 2);
+        let r = s.to_readable();
+        let q = r.query(0.0).expect("Could not query min");
+        assert_eq!(q.approx_value_f64(), 1.25);
+        let q = r.query(1.0).expect("Could not query max");
+        assert_eq!(q.approx_value, 300);
+    }
+
+    #[test]
+    fn it_decodes_legacy_format() {
+        // Only the values, without a format version or scale
+        let mut buf = Vec::<u8>::new();
+        delta_encode(&[1, 2, 3], &mut buf).expect("Could not encode values");
+        let decoded = BaselineSketch::decode(&mut &buf[..]).expect("Could not decode sketch");
+        assert_eq!(decoded.scale(), 0);
+        assert_query(decoded, 3, 2);
+    }
+
+    #[test]
+    fn it_rejects_unknown_format_version() {
+        let mut buf = Vec::<u8>::new();
+        BaselineSketch::from_slice(&[1, 2, 3])
+            .encode(&mut buf)
+            .expect("Could not encode sketch");
+        buf[size_of::<u64>()] = FORMAT_VERSION + 1;
+        assert!(BaselineSketch::decode(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn it_fails_to_merge_sketches_with_different_scales() {
+        let mut s1 = BaselineSketch::new();
+        let mut s2 = BaselineSketch::new();
+        s1.insert_f64(1.25, 2).expect("Could not insert value");
+        s2.insert_f64(1.5, 1).expect("Could not insert value");
+        assert!(s1.merge(s2).is_err());
+    }
+
     fn encode_and_decode(s: BaselineSketch) -> BaselineSketch {
         let mut buf = Vec::<u8>::new();
         s.encode(&mut buf).expect("Could not encode sketch");
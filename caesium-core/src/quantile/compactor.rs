@@ -1,9 +1,15 @@
-use encode::delta::{delta_decode, delta_encode};
+use encode::delta::delta_decode;
+use encode::vbyte::{delta_vbyte_decode_with_limit, delta_vbyte_encode, delta_vbyte_len};
 use encode::{Decodable, Encodable, EncodableError};
+use quantile::kll::{CAPACITY_AT_DEPTH, LEVEL_LIMIT};
 use rand;
 use std::io::{Read, Write};
 use std::slice::Iter;
 
+// A compressed sketch holds at most its total capacity, which is no more than
+// the largest per-level capacity at every level
+const MAX_DECODED_LEN: usize = LEVEL_LIMIT as usize * CAPACITY_AT_DEPTH[0];
+
 #[derive(Clone)]
 pub struct Compactor {
     data: Vec<u32>,
@@ -18,6 +24,19 @@ impl Compactor {
         }
     }
 
+    // Number of bytes written by `encode`. Unsorted data is sorted in place first,
+    // since the encoded deltas depend on the sorted order.
+    pub fn serialized_len(&mut self) -> usize {
+        self.ensure_sorted();
+        delta_vbyte_len(&self.data)
+    }
+
+    // Remove all values, keeping the allocated buffer
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.is_sorted = true;
+    }
+
     pub fn insert(&mut self, value: u32) {
         self.data.push(value);
         self.is_sorted = false;
@@ -38,34 +57,40 @@ impl Compactor {
     // On output, overflow is sorted (asc by value)
     pub fn compact(&mut self, overflow: &mut Vec<u32>) {
         debug_assert!(overflow.is_empty());
-        self.ensure_sorted();
-
         let n = self.data.len();
 
-        let leftover = if n % 2 != 0 {
+        // Keep every other value from an even-length prefix, starting at a random offset
+        let offset = rand::random::<bool>() as usize;
+        overflow.extend(self.iter_sorted().take(n - n % 2).skip(offset).step_by(2));
+
+        // The largest value is left over if there are an odd number of values
+        let leftover = if !n.is_multiple_of(2) {
             Some(self.data[n - 1])
         } else {
             None
         };
 
-        let mut idx = rand::random::<bool>() as usize;
-        while idx < n {
-            self.data[idx / 2] = self.data[idx];
-            idx += 2;
-        }
-
-        overflow.extend_from_slice(&self.data[..n / 2]);
-
         self.data.clear();
         if let Some(v) = leftover {
             self.data.push(v);
         }
     }
 
-    pub fn iter_values(&self) -> Iter<u32> {
+    // Values may be unsorted, see `iter_sorted`
+    pub fn iter_values(&self) -> Iter<'_, u32> {
+        self.data.iter()
+    }
+
+    // Sorts the values first if necessary
+    pub fn iter_sorted(&mut self) -> Iter<'_, u32> {
+        self.ensure_sorted();
         self.data.iter()
     }
 
+    pub fn is_sorted(&self) -> bool {
+        self.is_sorted
+    }
+
     pub fn size(&self) -> usize {
         self.data.len()
     }
@@ -96,6 +121,12 @@ impl Compactor {
     }
 }
 
+impl Default for Compactor {
+    fn default() -> Compactor {
+        Compactor::new()
+    }
+}
+
 impl<W> Encodable<W> for Compactor
 where
     W: Write,
@@ -111,7 +142,7 @@ where
             tmp.sort_unstable();
             &tmp
         };
-        delta_encode(&data, writer)?;
+        delta_vbyte_encode(data, writer)?;
         Ok(())
     }
 }
@@ -121,7 +152,32 @@ where
     R: Read,
 {
     fn decode(reader: &mut R) -> Result<Compactor, EncodableError> {
+        let data = match delta_vbyte_decode_with_limit(reader, MAX_DECODED_LEN) {
+            Err(EncodableError::LengthTooLong(_)) => {
+                return Err(EncodableError::FormatError(
+                    "Compactor has more values than any sketch can hold",
+                ))
+            }
+            result => result?,
+        };
+        let compactor = Compactor {
+            data,
+            is_sorted: true,
+        };
+        Ok(compactor)
+    }
+}
+
+impl Compactor {
+    // Decodes a compactor from a sketch encoded before sketches had a format version,
+    // when compactors used the stream vbyte delta encoding
+    pub fn decode_legacy<R: Read>(reader: &mut R) -> Result<Compactor, EncodableError> {
         let data = delta_decode(reader)?;
+        if data.len() > MAX_DECODED_LEN {
+            return Err(EncodableError::FormatError(
+                "Compactor has more values than any sketch can hold",
+            ));
+        }
         let compactor = Compactor {
             data,
             is_sorted: true,
@@ -133,6 +189,8 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use encode::delta::delta_encode;
+    use encode::vbyte::vbyte_encode;
     use std::collections::HashSet;
 
     #[test]
@@ -141,37 +199,65 @@ mod tests {
         c.insert(1);
         c.insert(2);
         c.insert(3);
-        assert_values(&c, &vec![1, 2, 3]);
+        assert_values(&c, &[1, 2, 3]);
     }
 
     #[test]
     fn it_inserts_sorted() {
         let mut c = Compactor::new();
-        c.insert_sorted(&vec![2, 4, 8]);
-        c.insert_sorted(&vec![1, 5, 7, 9]);
-        assert_values(&c, &vec![1, 2, 4, 5, 7, 8, 9]);
+        c.insert_sorted(&[2, 4, 8]);
+        c.insert_sorted(&[1, 5, 7, 9]);
+        assert_values(&c, &[1, 2, 4, 5, 7, 8, 9]);
     }
 
     #[test]
     fn it_inserts_from_other_unsorted() {
         let mut c1 = Compactor::new();
         let mut c2 = Compactor::new();
-        c1.insert_sorted(&vec![2, 4, 6, 8]);
+        c1.insert_sorted(&[2, 4, 6, 8]);
         c2.insert(7);
         c2.insert(3);
         c2.insert(9);
         c1.insert_from_other(&mut c2);
-        assert_values(&c1, &vec![2, 3, 4, 6, 7, 8, 9]);
+        assert_values(&c1, &[2, 3, 4, 6, 7, 8, 9]);
     }
 
     #[test]
     fn it_inserts_from_other_sorted() {
         let mut c1 = Compactor::new();
         let mut c2 = Compactor::new();
-        c1.insert_sorted(&vec![2, 4, 6, 8]);
-        c2.insert_sorted(&vec![3, 7, 9]);
+        c1.insert_sorted(&[2, 4, 6, 8]);
+        c2.insert_sorted(&[3, 7, 9]);
         c1.insert_from_other(&mut c2);
-        assert_values(&c1, &vec![2, 3, 4, 6, 7, 8, 9]);
+        assert_values(&c1, &[2, 3, 4, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn it_iterates_sorted() {
+        let mut c = Compactor::new();
+        c.insert(3);
+        c.insert(1);
+        c.insert(2);
+        assert!(!c.is_sorted());
+        let values: Vec<u32> = c.iter_sorted().cloned().collect();
+        assert_eq!(values, vec![1, 2, 3]);
+        assert!(c.is_sorted());
+    }
+
+    #[test]
+    fn it_compacts_unsorted() {
+        let mut c = Compactor::new();
+        for v in [6, 2, 5, 1, 4, 3, 7].iter() {
+            c.insert(*v);
+        }
+        let mut overflow = Vec::new();
+        c.compact(&mut overflow);
+        match overflow.first() {
+            Some(1) => assert_eq!(overflow, vec![1, 3, 5]),
+            Some(2) => assert_eq!(overflow, vec![2, 4, 6]),
+            _ => panic!("Unexpected value in overflow"),
+        }
+        assert_eq!(c.data, vec![7]);
     }
 
     #[test]
@@ -186,7 +272,7 @@ mod tests {
     #[test]
     fn it_compacts_even_num_items() {
         let mut c = Compactor::new();
-        c.insert_sorted(&vec![1, 2, 3, 4, 5, 6]);
+        c.insert_sorted(&[1, 2, 3, 4, 5, 6]);
         let mut overflow = Vec::new();
         c.compact(&mut overflow);
         assert_eq!(c.size(), 0);
@@ -201,7 +287,7 @@ mod tests {
     #[test]
     fn it_compacts_odd_num_items() {
         let mut c = Compactor::new();
-        c.insert_sorted(&vec![1, 2, 3, 4, 5]);
+        c.insert_sorted(&[1, 2, 3, 4, 5]);
         let mut overflow = Vec::new();
         c.compact(&mut overflow);
         assert_eq!(c.size(), 1);
@@ -247,9 +333,73 @@ mod tests {
         assert_eq!(s1, s2);
     }
 
+    #[test]
+    fn it_calculates_serialized_len_sorted_and_unsorted() {
+        let mut c = Compactor::new();
+        for v in [300, 2, 70000, 2, 1].iter() {
+            c.insert(*v);
+        }
+        assert_serialized_len(&mut c);
+        c.insert_sorted(&[1, 5, 9]);
+        assert_serialized_len(&mut c);
+        assert_serialized_len(&mut Compactor::new());
+    }
+
+    #[test]
+    fn it_rejects_decoding_absurd_length_prefix() {
+        for &len in [MAX_DECODED_LEN as u32 + 1, u32::MAX].iter() {
+            let mut buf = Vec::new();
+            vbyte_encode(len, &mut buf).unwrap();
+            match Compactor::decode(&mut &buf[..]) {
+                Err(EncodableError::FormatError(_)) => {}
+                Err(err) => panic!("Expected format error, got {:?}", err),
+                Ok(_) => panic!("Expected format error"),
+            }
+        }
+    }
+
+    #[test]
+    fn it_encodes_sequential_data_smaller_than_legacy_format() {
+        let mut c = Compactor::new();
+        let values: Vec<u32> = (1_000_000..1_001_000).collect();
+        for v in values.iter().rev() {
+            c.insert(*v);
+        }
+
+        let mut buf = Vec::<u8>::new();
+        c.encode(&mut buf).expect("Could not encode compactor");
+        let mut legacy_buf = Vec::<u8>::new();
+        delta_encode(&values, &mut legacy_buf).expect("Could not encode legacy format");
+
+        // One byte per delta, plus a few bytes for the count and first value,
+        // while the legacy format also spends a control byte on every four deltas
+        assert!(buf.len() < values.len() + 8);
+        assert!(buf.len() < legacy_buf.len());
+
+        let decoded = Compactor::decode(&mut &buf[..]).expect("Could not decode compactor");
+        let actual: Vec<u32> = decoded.iter_values().copied().collect();
+        assert_eq!(actual, values);
+    }
+
+    #[test]
+    fn it_decodes_legacy_format() {
+        let values: Vec<u32> = vec![1, 5, 5, 9, 300, 70000, 4000000];
+        let mut buf = Vec::<u8>::new();
+        delta_encode(&values, &mut buf).expect("Could not encode legacy format");
+        let decoded =
+            Compactor::decode_legacy(&mut &buf[..]).expect("Could not decode legacy compactor");
+        assert_values(&decoded, &values);
+    }
+
     fn assert_values(c: &Compactor, expected: &[u32]) {
-        let actual: Vec<u32> = c.iter_values().map(|v| *v).collect();
+        let actual: Vec<u32> = c.iter_values().copied().collect();
         assert_eq!(c.size(), expected.len());
         assert_eq!(actual, expected);
     }
+
+    fn assert_serialized_len(c: &mut Compactor) {
+        let mut buf = Vec::new();
+        c.encode(&mut buf).expect("Could not encode compactor");
+        assert_eq!(c.serialized_len(), buf.len());
+    }
 }
@@ -8,10 +8,10 @@ pub struct ErrorCalculator {
 
 impl ErrorCalculator {
     pub fn new(data: &[u32]) -> ErrorCalculator {
-        let value_range_map = ErrorCalculator::create_value_range_map(&data);
+        let value_range_map = ErrorCalculator::create_value_range_map(data);
         ErrorCalculator {
             count: data.len(),
-            value_range_map: value_range_map,
+            value_range_map,
         }
     }
 
@@ -103,8 +103,8 @@ mod tests {
         assert_eq!(calc.calculate_error(0.5, 9), 0.4);
     }
 
-    fn shuffle(mut data: &mut [u32]) {
+    fn shuffle(data: &mut [u32]) {
         let mut rng = rand::thread_rng();
-        rng.shuffle(&mut data);
+        rng.shuffle(data);
     }
 }
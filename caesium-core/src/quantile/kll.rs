@@ -6,19 +6,28 @@ use quantile::compactor::Compactor;
 use quantile::minmax::MinMax;
 use quantile::query::{WeightedQuerySketch, WeightedValue};
 use quantile::sampler::Sampler;
+use quantile::scale::{to_fixed, to_sketch_value, MAX_SCALE};
 use slab::Slab;
 use std::cmp::min;
 use std::io::{Read, Write};
+use std::iter::FromIterator;
+use std::mem::{self, size_of};
 use std::ops::RangeInclusive;
 
-const LEVEL_LIMIT: u8 = 64;
+pub const LEVEL_LIMIT: u8 = 64;
+
+// Sketches encoded before the format was versioned start with the count,
+// which can never be this large, so the marker distinguishes the two formats.
+// Version 1 added the scale and switched compactors to the vbyte delta encoding.
+const FORMAT_MARKER: u64 = u64::MAX;
+const FORMAT_VERSION: u8 = 1;
 
 // Capacities calculated using:
 // * failure probability (delta) = 1e-8
 // * maximum normalized rank error (epsilon) = 1.5e-2
 // * top levels (s) = log(log(1/delta)) ~= 5
 // * top capacity (k) = (1 / epsilon) * s ~= 200
-const CAPACITY_AT_DEPTH: [usize; LEVEL_LIMIT as usize] = [
+pub const CAPACITY_AT_DEPTH: [usize; LEVEL_LIMIT as usize] = [
     200, 200, 200, 200, 200, 27, 18, 12, 8, 6, 4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
     2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
     2, 2, 2, 2, 2,
@@ -26,6 +35,7 @@ const CAPACITY_AT_DEPTH: [usize; LEVEL_LIMIT as usize] = [
 
 pub struct KllSketch {
     count: usize,
+    scale: u32,
     level: u8,
     size: usize,
     capacity: usize,
@@ -44,6 +54,7 @@ impl KllSketch {
         compactor_map[0] = Some(cid);
         KllSketch {
             count: 0,
+            scale: 0,
             level: 0,
             size: 0,
             capacity: CAPACITY_AT_DEPTH[0],
@@ -55,8 +66,13 @@ impl KllSketch {
         }
     }
 
+    pub fn from_slice(values: &[u64]) -> KllSketch {
+        values.iter().cloned().collect()
+    }
+
     fn from_parts(
         count: usize,
+        scale: u32,
         level: u8,
         minmax: MinMax,
         sampler: Sampler,
@@ -75,6 +91,7 @@ impl KllSketch {
 
         let mut s = KllSketch {
             count,
+            scale,
             level,
             size: 0,
             capacity: 0,
@@ -89,7 +106,41 @@ impl KllSketch {
         s
     }
 
+    // Empty the sketch so it can be reused. The lowest level's compactor becomes the new
+    // level-zero compactor, so its buffer and the slab's capacity aren't reallocated.
+    pub fn reset(&mut self) {
+        let first_cid = self.get_compactor_id(self.level);
+        for level in self.compactor_level_range() {
+            let cid = self.compactor_map[level as usize]
+                .take()
+                .expect("Could not find compactor ID to remove");
+            if cid != first_cid {
+                self.compactor_slab.remove(cid);
+            }
+        }
+        self.compactor_slab
+            .get_mut(first_cid)
+            .expect("Could not retrieve compactor")
+            .clear();
+        self.compactor_map[0] = Some(first_cid);
+        self.compactor_count = 1;
+        self.count = 0;
+        self.scale = 0;
+        self.level = 0;
+        self.size = 0;
+        self.capacity = CAPACITY_AT_DEPTH[0];
+        self.minmax = MinMax::new();
+        self.sampler.reset();
+    }
+
+    // Integer values inserted into a sketch of decimal values are converted to its scale,
+    // so `insert(v)` always records the value `v`.
     pub fn insert(&mut self, val: u32) {
+        let val = self.to_sketch_scale(val);
+        self.insert_fixed(val);
+    }
+
+    fn insert_fixed(&mut self, val: u32) {
         self.count += 1;
         self.minmax.update(val);
         if let Some(val) = self.sampler.sample(val) {
@@ -103,13 +154,81 @@ impl KllSketch {
         }
     }
 
-    pub fn merge(self, other: KllSketch) -> KllSketch {
-        let (mut survivor, mut victim) = if self.level > other.level {
-            (self, other)
-        } else {
-            (other, self)
+    // Insert a value that counts as `weight` observations, as if it were inserted `weight` times.
+    // Each power of two in the weight at or above the sampler's level goes directly into
+    // the compactor for that level, and the remainder goes through the sampler.
+    pub fn insert_weighted(&mut self, val: u32, weight: usize) {
+        assert!(weight > 0);
+        let val = self.to_sketch_scale(val);
+        self.count += weight;
+        self.minmax.update(val);
+
+        let sampler_weight = weight & ((1 << self.level) - 1);
+        if sampler_weight > 0 {
+            if let Some(val) = self.sampler.sample_weighted(val, sampler_weight) {
+                let level = self.level;
+                self.get_mut_compactor(level).insert(val);
+            }
+        }
+
+        for level in self.level..LEVEL_LIMIT {
+            if (weight >> level) & 1 == 0 {
+                continue;
+            }
+            while self.top_level() < level {
+                self.add_compactor();
+            }
+            self.get_mut_compactor(level).insert(val);
+        }
+
+        self.size = self.calculate_size();
+        self.compress()
+    }
+
+    // Insert a floating point value as a fixed-point integer with `scale` decimal digits.
+    // Fails if the sketch already has values at another scale, including integers
+    // inserted with `insert` into an unscaled sketch.
+    pub fn insert_f64(&mut self, val: f64, scale: u32) -> Result<(), EncodableError> {
+        if scale > MAX_SCALE {
+            return Err(EncodableError::FormatError("Scale value too large"));
+        }
+        if self.count == 0 {
+            self.scale = scale;
+        } else if self.scale != scale {
+            return Err(EncodableError::FormatError(
+                "Inserted value scale does not match sketch",
+            ));
+        }
+        self.insert_fixed(to_fixed(val, scale));
+        Ok(())
+    }
+
+    // Fails if the sketches have different scales
+    pub fn merge(mut self, other: KllSketch) -> Result<KllSketch, EncodableError> {
+        self.merge_from(other)?;
+        Ok(self)
+    }
+
+    // Merge `other` into this sketch in place, so callers can keep accumulating into it.
+    // Fails without changing this sketch if the sketches have different scales.
+    pub fn merge_from(&mut self, mut other: KllSketch) -> Result<(), EncodableError> {
+        let scale = match (self.count, other.count) {
+            (0, _) => other.scale,
+            (_, 0) => self.scale,
+            _ if self.scale == other.scale => self.scale,
+            _ => {
+                return Err(EncodableError::FormatError(
+                    "Cannot merge sketches with different scales",
+                ))
+            }
         };
 
+        // The sketch with the higher level survives, so swap it into place if necessary
+        if other.level > self.level {
+            mem::swap(self, &mut other);
+        }
+        let (survivor, mut victim) = (self, other);
+
         let mut values = Vec::new();
 
         // Absorb victim sampler stored value into survivor sampler
@@ -152,19 +271,20 @@ impl KllSketch {
             survivor.add_compactor();
         }
         for level in survivor.level..=victim.top_level() {
-            let mut victim_compactor = victim.get_mut_compactor(level);
-            let mut survivor_compactor = survivor.get_mut_compactor(level);
-            survivor_compactor.insert_from_other(&mut victim_compactor);
+            let victim_compactor = victim.get_mut_compactor(level);
+            let survivor_compactor = survivor.get_mut_compactor(level);
+            survivor_compactor.insert_from_other(victim_compactor);
         }
 
         survivor.minmax.update_from_other(&victim.minmax);
         survivor.count += victim.count;
+        survivor.scale = scale;
 
         // Inserted values may have exceeded capacity, so compress
         survivor.size = survivor.calculate_size();
         survivor.compress();
 
-        survivor
+        Ok(())
     }
 
     pub fn to_readable(self) -> WeightedQuerySketch {
@@ -184,17 +304,48 @@ impl KllSketch {
             }
         }
 
-        WeightedQuerySketch::new(self.count, self.minmax, data)
+        WeightedQuerySketch::new(self.count, self.minmax, data).with_scale(self.scale)
     }
 
     pub fn count(&self) -> usize {
         self.count
     }
 
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
     pub fn size(&self) -> usize {
         self.size
     }
 
+    // Number of bytes written by `encode`, without encoding the sketch.
+    // Sorts each compactor in place, since the encoded deltas depend on the sorted order.
+    // Counts are encoded as u64, regardless of the platform's usize.
+    pub fn serialized_len(&mut self) -> usize {
+        let mut compactors_len = 0;
+        for level in self.compactor_level_range() {
+            compactors_len += self.get_mut_compactor(level).serialized_len();
+        }
+        size_of::<u64>() // format marker
+            + size_of::<u8>() // format version
+            + size_of::<u64>() // count
+            + size_of::<u32>() // scale
+            + size_of::<u8>() // level
+            + self.minmax.serialized_len()
+            + self.sampler.serialized_len()
+            + size_of::<u64>() // number of compactors
+            + compactors_len
+    }
+
+    fn to_sketch_scale(&self, val: u32) -> u32 {
+        if self.scale == 0 {
+            val
+        } else {
+            to_fixed(f64::from(val), self.scale)
+        }
+    }
+
     fn get_compactor_id(&self, level: u8) -> usize {
         self.compactor_map[level as usize].expect("Could not retrieve compactor ID")
     }
@@ -246,7 +397,7 @@ impl KllSketch {
 
     fn add_compactor(&mut self) {
         let new_level = self.top_level() + 1;
-        assert!(new_level < LEVEL_LIMIT as u8);
+        assert!(new_level < LEVEL_LIMIT);
         let compactor = Compactor::new();
         let cid = self.compactor_slab.insert(compactor);
         self.compactor_map[new_level as usize] = Some(cid);
@@ -255,10 +406,17 @@ impl KllSketch {
     }
 
     fn compress(&mut self) {
-        while self.size > self.capacity {
-            self.compact_levels();
+        loop {
+            while self.size > self.capacity {
+                self.compact_levels();
+            }
+            self.absorb_lower_levels_into_sampler();
+
+            // Absorbing a level lowers the capacity, which may require another compaction
+            if self.size <= self.capacity {
+                break;
+            }
         }
-        self.absorb_lower_levels_into_sampler();
     }
 
     fn compact_levels(&mut self) {
@@ -267,7 +425,7 @@ impl KllSketch {
         for level in self.compactor_level_range() {
             let capacity = self.capacity_at_level(level);
             let c = self.get_mut_compactor(level);
-            if overflow.len() > 0 {
+            if !overflow.is_empty() {
                 c.insert_sorted(&overflow);
                 overflow.clear();
                 break;
@@ -279,7 +437,7 @@ impl KllSketch {
         }
 
         // Add a new level for surviving values if necessary
-        if overflow.len() > 0 {
+        if !overflow.is_empty() {
             self.add_compactor();
             let level = self.top_level();
             let c = self.get_mut_compactor(level);
@@ -307,6 +465,7 @@ impl KllSketch {
                     .expect("Could not find compactor ID to remove");
                 self.compactor_slab.remove(cid);
                 self.size = self.calculate_size();
+                self.capacity = self.calculate_capacity();
                 self.sampler.set_max_weight(1 << self.level);
             } else {
                 break;
@@ -315,6 +474,31 @@ impl KllSketch {
     }
 }
 
+impl Default for KllSketch {
+    fn default() -> KllSketch {
+        KllSketch::new()
+    }
+}
+
+impl FromIterator<u64> for KllSketch {
+    fn from_iter<I>(iter: I) -> KllSketch
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let mut s = KllSketch::new();
+        for v in iter {
+            s.insert(to_sketch_value(v));
+        }
+        s
+    }
+}
+
+impl From<Vec<u64>> for KllSketch {
+    fn from(values: Vec<u64>) -> KllSketch {
+        values.into_iter().collect()
+    }
+}
+
 impl Clone for KllSketch {
     fn clone(&self) -> Self {
         let mut compactor_slab = Slab::new();
@@ -327,6 +511,7 @@ impl Clone for KllSketch {
 
         KllSketch {
             count: self.count,
+            scale: self.scale,
             level: self.level,
             size: self.size,
             capacity: self.capacity,
@@ -344,7 +529,10 @@ where
     W: Write,
 {
     fn encode(&self, writer: &mut W) -> Result<(), EncodableError> {
+        FORMAT_MARKER.encode(writer)?;
+        FORMAT_VERSION.encode(writer)?;
         self.count.encode(writer)?;
+        self.scale.encode(writer)?;
         self.level.encode(writer)?;
         self.minmax.encode(writer)?;
         self.sampler.encode(writer)?;
@@ -362,7 +550,18 @@ where
     R: Read,
 {
     fn decode(reader: &mut R) -> Result<KllSketch, EncodableError> {
-        let count = usize::decode(reader)?;
+        let prefix = u64::decode(reader)?;
+        let is_legacy = prefix != FORMAT_MARKER;
+        let (count, scale) = if is_legacy {
+            (prefix as usize, 0)
+        } else {
+            if u8::decode(reader)? != FORMAT_VERSION {
+                return Err(EncodableError::FormatError(
+                    "Unsupported sketch format version",
+                ));
+            }
+            (usize::decode(reader)?, u32::decode(reader)?)
+        };
         let level = u8::decode(reader)?;
         let minmax = MinMax::decode(reader)?;
         let sampler = Sampler::decode(reader)?;
@@ -372,6 +571,10 @@ where
             return Err(EncodableError::FormatError("Level value too large"));
         }
 
+        if scale > MAX_SCALE {
+            return Err(EncodableError::FormatError("Scale value too large"));
+        }
+
         if num_compactors < 1 {
             return Err(EncodableError::FormatError(
                 "Must have at least one compactor",
@@ -380,10 +583,14 @@ where
 
         let mut compactors = Vec::new();
         for _ in 0..num_compactors {
-            let c = Compactor::decode(reader)?;
+            let c = if is_legacy {
+                Compactor::decode_legacy(reader)?
+            } else {
+                Compactor::decode(reader)?
+            };
             compactors.push(c);
         }
-        let s = KllSketch::from_parts(count, level, minmax, sampler, compactors);
+        let s = KllSketch::from_parts(count, scale, level, minmax, sampler, compactors);
         Ok(s)
     }
 }
@@ -391,6 +598,12 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use encode::delta::delta_encode;
+    use proptest::prelude::*;
+    use std::cmp;
+
+    const EPSILON: f64 = 0.01;
+    const MAX_INPUT_LEN: usize = 5000;
 
     #[test]
     fn it_sketches_quantiles_no_compression() {
@@ -414,7 +627,7 @@ mod tests {
             s1.insert(i as u32);
             s2.insert(i as u32);
         }
-        let merged = s1.merge(s2);
+        let merged = s1.merge(s2).expect("Could not merge sketches");
         let median = merged
             .to_readable()
             .query(0.5)
@@ -433,6 +646,72 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_inserts_weighted_without_exceeding_capacity() {
+        let mut s = KllSketch::new();
+        let n = CAPACITY_AT_DEPTH[0] * LEVEL_LIMIT as usize;
+        let mut total = 0;
+        for i in 0..n {
+            let weight = i % 1000 + 1;
+            s.insert_weighted(i as u32, weight);
+            total += weight;
+            assert!(s.calculate_size() <= s.calculate_capacity());
+        }
+        assert_eq!(s.count(), total);
+    }
+
+    #[test]
+    fn it_inserts_weight_above_sampler_level() {
+        let mut s = KllSketch::new();
+        for i in 0..100 {
+            s.insert(i as u32);
+        }
+        s.insert_weighted(1000, 1 << 20);
+        assert_eq!(s.count(), 100 + (1 << 20));
+        let median = s
+            .to_readable()
+            .query(0.5)
+            .map(|q| q.approx_value)
+            .expect("Could not query median");
+        assert_eq!(median, 1000);
+    }
+
+    #[test]
+    fn it_merges_in_place_no_compression() {
+        let mut s = KllSketch::from_slice(&[5, 1, 3]);
+        s.merge_from(KllSketch::from_slice(&[4, 2]))
+            .expect("Could not merge sketches");
+        assert_eq!(s.count(), 5);
+        assert_eq!(s.compactor_count, 1);
+        let mut values: Vec<u32> = s.get_compactor(s.level).iter_values().cloned().collect();
+        values.sort_unstable();
+        assert_eq!(values, vec![1, 2, 3, 4, 5]);
+        let expected = KllSketch::from_slice(&[1, 2, 3, 4, 5]);
+        assert_eq!(encode_to_vec(&s), encode_to_vec(&expected));
+    }
+
+    #[test]
+    fn it_merges_in_place_from_higher_level_sketch() {
+        let n = CAPACITY_AT_DEPTH[0] * LEVEL_LIMIT as usize;
+        let large = KllSketch::from_iter(0..n as u64);
+        let mut s = KllSketch::from_slice(&[1, 2, 3]);
+        s.merge_from(large).expect("Could not merge sketches");
+        assert_eq!(s.count(), n + 3);
+        assert!(s.compactor_count > 1);
+        assert!(s.calculate_size() <= s.calculate_capacity());
+    }
+
+    #[test]
+    fn it_fails_to_merge_in_place_with_different_scales() {
+        let mut s1 = KllSketch::new();
+        let mut s2 = KllSketch::new();
+        s1.insert_f64(1.25, 2).expect("Could not insert value");
+        s2.insert_f64(1.5, 1).expect("Could not insert value");
+        assert!(s1.merge_from(s2).is_err());
+        assert_eq!(s1.count(), 1);
+        assert_eq!(s1.scale(), 2);
+    }
+
     #[test]
     fn it_merges_without_exceeding_capacity() {
         let mut s1 = KllSketch::new();
@@ -442,10 +721,55 @@ mod tests {
             s1.insert(i as u32);
             s2.insert(i as u32);
         }
-        let merged = s1.merge(s2);
+        let merged = s1.merge(s2).expect("Could not merge sketches");
         assert!(merged.calculate_size() <= merged.calculate_capacity());
     }
 
+    #[test]
+    fn it_resets_to_empty_sketch() {
+        let mut s = KllSketch::new();
+        for i in 0..(CAPACITY_AT_DEPTH[0] * 100) {
+            s.insert_f64(i as f64, 2).expect("Could not insert value");
+        }
+        assert!(s.compactor_count > 1);
+        s.reset();
+        assert_eq!(s.count(), 0);
+        assert_eq!(s.scale(), 0);
+        assert_eq!(s.size(), 0);
+        assert_eq!(encode_to_vec(&s), encode_to_vec(&KllSketch::new()));
+
+        // Below capacity every value is kept, so the reset sketch matches a new one exactly
+        let mut fresh = KllSketch::new();
+        for i in 0..100 {
+            s.insert(i as u32);
+            fresh.insert(i as u32);
+        }
+        assert_eq!(encode_to_vec(&s), encode_to_vec(&fresh));
+        let median = s
+            .to_readable()
+            .query(0.5)
+            .map(|q| q.approx_value)
+            .expect("Could not query median");
+        assert_eq!(median, 50);
+    }
+
+    #[test]
+    fn it_resets_repeatedly_without_leaking_levels() {
+        let mut s = KllSketch::new();
+        let n = CAPACITY_AT_DEPTH[0] * LEVEL_LIMIT as usize;
+        for _ in 0..10 {
+            for i in 0..n {
+                s.insert(i as u32);
+            }
+            assert!(s.compactor_count > 1);
+            s.reset();
+            assert_eq!(s.compactor_count, 1);
+            assert_eq!(s.compactor_slab.len(), 1);
+            assert_eq!(s.compactor_map.iter().filter(|c| c.is_some()).count(), 1);
+            assert_eq!(s.capacity, s.calculate_capacity());
+        }
+    }
+
     #[test]
     fn it_encodes_and_decodes() {
         let mut s = KllSketch::new();
@@ -464,4 +788,246 @@ mod tests {
             decoded.compactor_map.iter().filter_map(|v| *v).collect();
         assert_eq!(original_compactors, decoded_compactors);
     }
+
+    #[test]
+    fn it_decodes_legacy_format() {
+        // count, level, minmax, sampler, and compactors, without a format version or scale
+        let mut buf = Vec::<u8>::new();
+        3usize.encode(&mut buf).expect("Could not encode count");
+        0u8.encode(&mut buf).expect("Could not encode level");
+        MinMax::from_values(&[1, 2, 3])
+            .encode(&mut buf)
+            .expect("Could not encode minmax");
+        Sampler::new()
+            .encode(&mut buf)
+            .expect("Could not encode sampler");
+        1usize
+            .encode(&mut buf)
+            .expect("Could not encode compactor count");
+        delta_encode(&[1, 2, 3], &mut buf).expect("Could not encode compactor");
+
+        let decoded = KllSketch::decode(&mut &buf[..]).expect("Could not decode sketch");
+        assert_eq!(decoded.count(), 3);
+        assert_eq!(decoded.scale(), 0);
+        let q = decoded
+            .to_readable()
+            .query(0.5)
+            .expect("Could not query median");
+        assert_eq!(q.approx_value, 2);
+    }
+
+    #[test]
+    fn it_rejects_unknown_format_version() {
+        let mut buf = Vec::<u8>::new();
+        KllSketch::from_slice(&[1, 2, 3])
+            .encode(&mut buf)
+            .expect("Could not encode sketch");
+        buf[size_of::<u64>()] = FORMAT_VERSION + 1;
+        assert!(KllSketch::decode(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn it_calculates_serialized_len() {
+        let mut weighted = KllSketch::new();
+        weighted.insert_weighted(7, 1000);
+        let mut scaled = KllSketch::new();
+        scaled.insert_f64(1.25, 2).expect("Could not insert value");
+        let large = KllSketch::from_slice(&(0..100_000).collect::<Vec<u64>>());
+        let merged = KllSketch::from_slice(&[5, 1, 3])
+            .merge(KllSketch::from_slice(&[u32::MAX as u64; 500]))
+            .expect("Could not merge sketches");
+        let mut sketches = [
+            KllSketch::new(),
+            KllSketch::from_slice(&[3, 1, 2]),
+            weighted,
+            scaled,
+            large,
+            merged,
+        ];
+        for s in sketches.iter_mut() {
+            let mut buf = Vec::new();
+            s.encode(&mut buf).expect("Could not encode sketch");
+            assert_eq!(s.serialized_len(), buf.len());
+        }
+    }
+
+    #[test]
+    fn it_inserts_f64_values() {
+        let mut s = KllSketch::new();
+        for v in &[1.25, 1.50, 1.75] {
+            s.insert_f64(*v, 2).expect("Could not insert value");
+        }
+        assert_eq!(s.scale(), 2);
+        let q = s.to_readable().query(0.5).expect("Could not query median");
+        assert_eq!(q.approx_value, 150);
+        assert_eq!(q.approx_value_f64(), 1.5);
+    }
+
+    #[test]
+    fn it_rejects_f64_values_with_different_scale() {
+        let mut s = KllSketch::new();
+        s.insert_f64(1.25, 2).expect("Could not insert value");
+        assert!(s.insert_f64(1.5, 1).is_err());
+        assert!(s.insert_f64(1.5, MAX_SCALE + 1).is_err());
+        assert_eq!(s.count(), 1);
+
+        let mut unscaled = KllSketch::new();
+        unscaled.insert(3);
+        assert!(unscaled.insert_f64(1.5, 1).is_err());
+        assert_eq!(unscaled.count(), 1);
+        assert_eq!(unscaled.scale(), 0);
+    }
+
+    #[test]
+    fn it_converts_integer_values_to_sketch_scale() {
+        let mut s = KllSketch::new();
+        s.insert_f64(1.25, 2).expect("Could not insert value");
+        s.insert(2);
+        s.insert_weighted(3, 2);
+        let r = s.to_readable();
+        let q = r.query(0.0).expect("Could not query min");
+        assert_eq!(q.approx_value_f64(), 1.25);
+        let q = r.query(1.0).expect("Could not query max");
+        assert_eq!(q.approx_value, 300);
+    }
+
+    #[test]
+    fn it_encodes_and_decodes_scale() {
+        let mut s = KllSketch::new();
+        s.insert_f64(1.25, 2).expect("Could not insert value");
+        let mut buf = Vec::<u8>::new();
+        s.encode(&mut buf).expect("Could not encode sketch");
+        let decoded = KllSketch::decode(&mut &buf[..]).expect("Could not decode sketch");
+        assert_eq!(decoded.scale(), 2);
+        let q = decoded
+            .to_readable()
+            .query(0.5)
+            .expect("Could not query median");
+        assert_eq!(q.approx_value_f64(), 1.25);
+    }
+
+    #[test]
+    fn it_merges_sketches_with_same_scale() {
+        let mut s1 = KllSketch::new();
+        let mut s2 = KllSketch::new();
+        s1.insert_f64(1.25, 2).expect("Could not insert value");
+        s2.insert_f64(2.5, 2).expect("Could not insert value");
+        let merged = s1.merge(s2).expect("Could not merge sketches");
+        assert_eq!(merged.scale(), 2);
+        assert_eq!(merged.count(), 2);
+    }
+
+    #[test]
+    fn it_merges_empty_sketch_with_scaled_sketch() {
+        let mut s = KllSketch::new();
+        s.insert_f64(1.25, 2).expect("Could not insert value");
+        let merged = KllSketch::new().merge(s).expect("Could not merge sketches");
+        assert_eq!(merged.scale(), 2);
+    }
+
+    #[test]
+    fn it_fails_to_merge_sketches_with_different_scales() {
+        let mut s1 = KllSketch::new();
+        let mut s2 = KllSketch::new();
+        s1.insert_f64(1.25, 2).expect("Could not insert value");
+        s2.insert_f64(1.5, 1).expect("Could not insert value");
+        match s1.merge(s2) {
+            Err(EncodableError::FormatError(_)) => {}
+            _ => panic!("Expected format error"),
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn it_merges_commutatively(a in sorted_values(), b in sorted_values()) {
+            let (s1, s2) = (build_sketch(&a), build_sketch(&b));
+            let left = s1.clone().merge(s2.clone()).expect("Could not merge sketches");
+            let right = s2.merge(s1).expect("Could not merge sketches");
+            assert_quantiles_agree(left, right, &[&a, &b]);
+        }
+
+        #[test]
+        fn it_merges_associatively(
+            a in sorted_values(),
+            b in sorted_values(),
+            c in sorted_values()
+        ) {
+            let s1 = build_sketch(&a);
+            let s2 = build_sketch(&b);
+            let s3 = build_sketch(&c);
+            let left = s1
+                .clone()
+                .merge(s2.clone())
+                .and_then(|s| s.merge(s3.clone()))
+                .expect("Could not merge sketches");
+            let right = s2
+                .merge(s3)
+                .and_then(|s| s1.merge(s))
+                .expect("Could not merge sketches");
+            assert_quantiles_agree(left, right, &[&a, &b, &c]);
+        }
+
+        #[test]
+        fn it_merges_in_place_like_merge(a in sorted_values(), b in sorted_values()) {
+            let (s1, s2) = (build_sketch(&a), build_sketch(&b));
+            let merged = s1.clone().merge(s2.clone()).expect("Could not merge sketches");
+            let mut in_place = s1;
+            in_place.merge_from(s2).expect("Could not merge sketches");
+            assert_quantiles_agree(merged, in_place, &[&a, &b]);
+        }
+    }
+
+    fn build_sketch(values: &[u32]) -> KllSketch {
+        values.iter().map(|&v| u64::from(v)).collect()
+    }
+
+    fn sorted_values() -> impl Strategy<Value = Vec<u32>> {
+        prop::collection::vec(any::<u32>(), 1..MAX_INPUT_LEN).prop_map(|mut values| {
+            values.sort_unstable();
+            values
+        })
+    }
+
+    // The answers from both sketches must be within `2 * EPSILON`
+    // of each other by normalized rank in the combined input.
+    fn assert_quantiles_agree(left: KllSketch, right: KllSketch, inputs: &[&[u32]]) {
+        let mut combined: Vec<u32> = inputs.iter().flat_map(|v| v.iter().cloned()).collect();
+        combined.sort_unstable();
+        assert_eq!(left.count(), combined.len());
+        assert_eq!(right.count(), combined.len());
+        let (left, right) = (left.to_readable(), right.to_readable());
+        for phi in [0.1, 0.5, 0.9, 0.99].iter() {
+            let l = left.query(*phi).expect("Could not query left sketch");
+            let r = right.query(*phi).expect("Could not query right sketch");
+            let distance = rank_distance(&combined, l.approx_value, r.approx_value);
+            assert!(
+                distance <= EPSILON * 2.0,
+                "phi={}, left={}, right={}, distance={}",
+                phi,
+                l.approx_value,
+                r.approx_value,
+                distance
+            );
+        }
+    }
+
+    // Normalized distance between the ranges of ranks covered by two values
+    fn rank_distance(sorted: &[u32], v1: u32, v2: u32) -> f64 {
+        let rank_range = |v: u32| {
+            let start = sorted.partition_point(|x| *x < v);
+            let end = sorted.partition_point(|x| *x <= v);
+            (start, end)
+        };
+        let ((s1, e1), (s2, e2)) = (rank_range(v1), rank_range(v2));
+        let gap = cmp::max(s1.saturating_sub(e2), s2.saturating_sub(e1));
+        gap as f64 / sorted.len() as f64
+    }
+
+    fn encode_to_vec(s: &KllSketch) -> Vec<u8> {
+        let mut buf = Vec::<u8>::new();
+        s.encode(&mut buf).expect("Could not encode sketch");
+        buf
+    }
 }
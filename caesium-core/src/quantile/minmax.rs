@@ -1,6 +1,7 @@
 use encode::{Decodable, Encodable, EncodableError};
 use std::cmp::{max, min};
 use std::io::{Read, Write};
+use std::mem::size_of;
 
 #[derive(Clone)]
 pub struct MinMax {
@@ -11,7 +12,7 @@ pub struct MinMax {
 impl MinMax {
     pub fn new() -> MinMax {
         MinMax {
-            min: u32::max_value(),
+            min: u32::MAX,
             max: 0u32,
         }
     }
@@ -50,6 +51,10 @@ impl MinMax {
         }
     }
 
+    pub fn serialized_len(&self) -> usize {
+        size_of::<u32>() * 2
+    }
+
     fn has_minmax(&self) -> bool {
         self.min <= self.max
     }
@@ -1,10 +1,13 @@
 pub mod baseline;
-mod compactor;
+// Public only so the benches can build compactors directly
+#[doc(hidden)]
+pub mod compactor;
 pub mod error;
 pub mod kll;
 mod minmax;
 pub mod query;
 mod sampler;
+pub mod scale;
 
 pub mod writable {
     #[cfg(not(feature = "baseline"))]
@@ -1,8 +1,14 @@
 use quantile::minmax::MinMax;
+use quantile::scale::from_fixed;
+use std::cmp::min;
+use std::fmt;
 
 // Estimated empirically, depends on sketch size
 const EPSILON: f32 = 0.015;
 
+// Number of evenly spaced phi values compared by `approx_eq`
+const APPROX_EQ_GRID_SIZE: usize = 20;
+
 #[derive(Copy, Clone, Debug)]
 pub struct WeightedValue {
     weight: usize,
@@ -22,6 +28,43 @@ pub struct ApproxQuantile {
     pub approx_value: u32,
     pub lower_bound: u32,
     pub upper_bound: u32,
+    pub scale: u32,
+}
+
+impl ApproxQuantile {
+    pub fn approx_value_f64(&self) -> f64 {
+        from_fixed(self.approx_value, self.scale)
+    }
+
+    pub fn lower_bound_f64(&self) -> f64 {
+        from_fixed(self.lower_bound, self.scale)
+    }
+
+    pub fn upper_bound_f64(&self) -> f64 {
+        from_fixed(self.upper_bound, self.scale)
+    }
+
+    // Formats the approximate value or one of its bounds, as an integer if the sketch
+    // is unscaled and as a decimal otherwise, so clients never see fixed-point values.
+    pub fn format_value(&self, value: u32) -> String {
+        if self.scale == 0 {
+            value.to_string()
+        } else {
+            from_fixed(value, self.scale).to_string()
+        }
+    }
+}
+
+impl fmt::Display for ApproxQuantile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "~{} [{}, {}]",
+            self.format_value(self.approx_value),
+            self.format_value(self.lower_bound),
+            self.format_value(self.upper_bound)
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -36,6 +79,7 @@ pub struct WeightedQuerySketch {
     minmax: MinMax,
     count: usize,
     total_weight: usize,
+    scale: u32,
 }
 
 impl WeightedQuerySketch {
@@ -56,16 +100,29 @@ impl WeightedQuerySketch {
             total_weight,
             minmax,
             data,
+            scale: 0,
         }
     }
 
+    pub fn with_scale(mut self, scale: u32) -> WeightedQuerySketch {
+        self.scale = scale;
+        self
+    }
+
     pub fn size(&self) -> usize {
         self.data.len()
     }
 
+    // Returns the exact min for `phi=0.0` and the exact max for `phi=1.0`
     pub fn query(&self, phi: f64) -> Option<ApproxQuantile> {
-        assert!(0.0 < phi && phi < 1.0);
-        if self.count > 0 {
+        assert!((0.0..=1.0).contains(&phi));
+        if self.count == 0 {
+            None
+        } else if phi == 0.0 {
+            self.minmax.min().map(|v| self.exact_quantile(v))
+        } else if phi == 1.0 {
+            self.minmax.max().map(|v| self.exact_quantile(v))
+        } else {
             let target_rank = (self.total_weight as f64 * phi) as usize;
             let idx = self.binary_search(target_rank);
             let approx_value = self.data[idx].value;
@@ -79,17 +136,67 @@ impl WeightedQuerySketch {
                 approx_value,
                 lower_bound,
                 upper_bound,
+                scale: self.scale,
             };
             Some(result)
-        } else {
-            None
+        }
+    }
+
+    pub fn cdf(&self, num_points: usize) -> Vec<(f64, u64)> {
+        cdf_points(num_points, |phi| self.query(phi))
+    }
+
+    pub fn pdf(&self, boundaries: &[u64]) -> Vec<(u64, f64)> {
+        pdf_buckets(boundaries, |b| self.fraction_at_most(b))
+    }
+
+    pub fn approx_eq(&self, other: &WeightedQuerySketch, epsilon: f64) -> bool {
+        self.count == other.count
+            && quantiles_within_rank_error(
+                |phi| self.query(phi),
+                |v| other.fraction_at_most(v),
+                epsilon,
+            )
+            && quantiles_within_rank_error(
+                |phi| other.query(phi),
+                |v| self.fraction_at_most(v),
+                epsilon,
+            )
+    }
+
+    // Approximate fraction of values less than or equal to `value`
+    fn fraction_at_most(&self, value: u64) -> f64 {
+        if self.total_weight == 0 {
+            return 0.0;
+        }
+        let idx = match self
+            .data
+            .binary_search_by(|sv| (sv.value as u64).cmp(&value))
+        {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        let rank = match self.data.get(idx) {
+            Some(sv) => sv.lowest_rank,
+            None => self.total_weight,
+        };
+        rank as f64 / self.total_weight as f64
+    }
+
+    fn exact_quantile(&self, value: u32) -> ApproxQuantile {
+        ApproxQuantile {
+            count: self.count,
+            approx_value: value,
+            lower_bound: value,
+            upper_bound: value,
+            scale: self.scale,
         }
     }
 
     fn calculate_stored_values(mut weighted_values: Vec<WeightedValue>) -> Vec<StoredValue> {
         let mut result = Vec::<StoredValue>::with_capacity(weighted_values.len());
         let mut rank = 0;
-        weighted_values.sort_unstable_by(|x, y| x.value.cmp(&y.value));
+        weighted_values.sort_unstable_by_key(|x| x.value);
         for wv in weighted_values.iter() {
             let n = result.len();
             if n > 0 && result[n - 1].value == wv.value {
@@ -167,7 +274,7 @@ impl WeightedQuerySketch {
             }
 
             let sv = &self.data[idx + 1];
-            if sv.lowest_rank - max_rank_error < rank && sv.value >= approx_value {
+            if sv.lowest_rank < rank + max_rank_error && sv.value >= approx_value {
                 return sv.value;
             }
 
@@ -178,29 +285,133 @@ impl WeightedQuerySketch {
 
 pub struct UnweightedQuerySketch {
     sorted_data: Vec<u32>,
+    scale: u32,
 }
 
 impl UnweightedQuerySketch {
     pub fn new(sorted_data: Vec<u32>) -> UnweightedQuerySketch {
-        UnweightedQuerySketch { sorted_data }
+        UnweightedQuerySketch {
+            sorted_data,
+            scale: 0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: u32) -> UnweightedQuerySketch {
+        self.scale = scale;
+        self
     }
 
+    // Returns the exact min for `phi=0.0` and the exact max for `phi=1.0`
     pub fn query(&self, phi: f64) -> Option<ApproxQuantile> {
+        assert!((0.0..=1.0).contains(&phi));
         let n = self.sorted_data.len();
 
         if n == 0 {
             return None;
         }
 
-        let target_rank = (phi * (n as f64)) as usize;
+        let target_rank = min((phi * (n as f64)) as usize, n - 1);
         let quantile = self.sorted_data[target_rank];
         Some(ApproxQuantile {
             count: n,
             approx_value: quantile,
             lower_bound: quantile,
             upper_bound: quantile,
+            scale: self.scale,
         })
     }
+
+    pub fn cdf(&self, num_points: usize) -> Vec<(f64, u64)> {
+        cdf_points(num_points, |phi| self.query(phi))
+    }
+
+    pub fn pdf(&self, boundaries: &[u64]) -> Vec<(u64, f64)> {
+        pdf_buckets(boundaries, |b| self.fraction_at_most(b))
+    }
+
+    pub fn approx_eq(&self, other: &UnweightedQuerySketch, epsilon: f64) -> bool {
+        self.sorted_data.len() == other.sorted_data.len()
+            && quantiles_within_rank_error(
+                |phi| self.query(phi),
+                |v| other.fraction_at_most(v),
+                epsilon,
+            )
+            && quantiles_within_rank_error(
+                |phi| other.query(phi),
+                |v| self.fraction_at_most(v),
+                epsilon,
+            )
+    }
+
+    fn fraction_at_most(&self, value: u64) -> f64 {
+        let n = self.sorted_data.len();
+        if n == 0 {
+            return 0.0;
+        }
+        let count = match self
+            .sorted_data
+            .binary_search_by(|&v| (v as u64).cmp(&value).then(::std::cmp::Ordering::Less))
+        {
+            Ok(i) | Err(i) => i,
+        };
+        count as f64 / n as f64
+    }
+}
+
+// Queries `num_points` evenly spaced phi values, each at the midpoint of an interval
+// of width `1 / num_points`, so every phi is strictly between 0 and 1.
+// Returns no points if the sketch is empty.
+fn cdf_points<F>(num_points: usize, query: F) -> Vec<(f64, u64)>
+where
+    F: Fn(f64) -> Option<ApproxQuantile>,
+{
+    let mut result = Vec::with_capacity(num_points);
+    for i in 0..num_points {
+        let phi = (i as f64 + 0.5) / num_points as f64;
+        match query(phi) {
+            Some(q) => result.push((phi, q.approx_value as u64)),
+            None => break,
+        }
+    }
+    result
+}
+
+// Checks that each quantile from one sketch falls within `epsilon` of the same rank
+// in another sketch, given by the fraction of the other sketch's values at most each value.
+fn quantiles_within_rank_error<Q, F>(query: Q, fraction_at_most: F, epsilon: f64) -> bool
+where
+    Q: Fn(f64) -> Option<ApproxQuantile>,
+    F: Fn(u64) -> f64,
+{
+    for i in 1..APPROX_EQ_GRID_SIZE {
+        let phi = i as f64 / APPROX_EQ_GRID_SIZE as f64;
+        if let Some(q) = query(phi) {
+            let v = q.approx_value as u64;
+            let below = if v == 0 { 0.0 } else { fraction_at_most(v - 1) };
+            if below > phi + epsilon || fraction_at_most(v) < phi - epsilon {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Each bucket is labeled by its upper boundary and holds the approximate fraction of values
+// greater than the previous boundary and less than or equal to its own.
+// Boundaries *must* be sorted ascending; values above the last boundary are not counted.
+fn pdf_buckets<F>(boundaries: &[u64], fraction_at_most: F) -> Vec<(u64, f64)>
+where
+    F: Fn(u64) -> f64,
+{
+    let mut result = Vec::with_capacity(boundaries.len());
+    let mut prev = 0.0;
+    for &b in boundaries {
+        debug_assert!(result.last().is_none_or(|&(last, _)| last <= b));
+        let cumulative = fraction_at_most(b);
+        result.push((b, cumulative - prev));
+        prev = cumulative;
+    }
+    result
 }
 
 #[cfg(test)]
@@ -261,6 +472,66 @@ mod tests {
         assert_queries(data);
     }
 
+    #[test]
+    fn it_queries_min_and_max() {
+        let data: Vec<WeightedValue> = (10..110).map(|v| WeightedValue::new(1, v as u32)).collect();
+        let minmax = MinMax::from_values(&[10, 109]);
+        let s = WeightedQuerySketch::new(100, minmax, data);
+        let expected_min = ApproxQuantile {
+            count: 100,
+            approx_value: 10,
+            lower_bound: 10,
+            upper_bound: 10,
+            scale: 0,
+        };
+        let expected_max = ApproxQuantile {
+            approx_value: 109,
+            lower_bound: 109,
+            upper_bound: 109,
+            ..expected_min
+        };
+        assert_eq!(s.query(0.0), Some(expected_min));
+        assert_eq!(s.query(1.0), Some(expected_max));
+    }
+
+    #[test]
+    fn it_queries_min_and_max_empty() {
+        let s = WeightedQuerySketch::new(0, MinMax::new(), vec![]);
+        assert_eq!(s.query(0.0), None);
+        assert_eq!(s.query(1.0), None);
+    }
+
+    #[test]
+    fn it_queries_just_inside_min_and_max() {
+        let data: Vec<WeightedValue> = (0..100).map(|v| WeightedValue::new(1, v as u32)).collect();
+        let minmax = MinMax::from_values(&[0, 99]);
+        let s = WeightedQuerySketch::new(100, minmax, data);
+        let low = s.query(0.001).expect("Could not query sketch");
+        assert_eq!(low.approx_value, 0);
+        assert_eq!(low.lower_bound, 0);
+        let high = s.query(0.999).expect("Could not query sketch");
+        assert_eq!(high.approx_value, 99);
+        assert_eq!(high.upper_bound, 99);
+    }
+
+    #[test]
+    fn it_queries_unweighted_min_and_max() {
+        let s = UnweightedQuerySketch::new((10..110).collect());
+        let min = s.query(0.0).expect("Could not query sketch");
+        assert_eq!(min.approx_value, 10);
+        let max = s.query(1.0).expect("Could not query sketch");
+        assert_eq!(max.count, 100);
+        assert_eq!(max.approx_value, 109);
+        assert_eq!(max.upper_bound, 109);
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_rejects_phi_above_one() {
+        let s = WeightedQuerySketch::new(0, MinMax::new(), vec![]);
+        s.query(1.001);
+    }
+
     #[test]
     fn it_handles_count_not_equal_total_weight() {
         let data = vec![
@@ -283,7 +554,7 @@ mod tests {
     #[test]
     fn it_calculates_upper_and_lower_bounds_single_value() {
         let data = vec![WeightedValue::new(1, 1)];
-        let minmax = MinMax::from_values(&vec![1]);
+        let minmax = MinMax::from_values(&[1]);
         let s = WeightedQuerySketch::new(1, minmax, data);
         let q = s.query(0.5);
         let lower = q.map(|q| q.lower_bound);
@@ -319,6 +590,41 @@ mod tests {
         assert!(upper < 64);
     }
 
+    #[test]
+    fn it_unscales_quantile_values() {
+        let data = vec![WeightedValue::new(1, 125)];
+        let minmax = MinMax::from_values(&[125]);
+        let s = WeightedQuerySketch::new(1, minmax, data).with_scale(2);
+        let q = s.query(0.5).expect("Could not query sketch");
+        assert_eq!(q.approx_value_f64(), 1.25);
+        assert_eq!(q.lower_bound_f64(), 1.25);
+        assert_eq!(q.upper_bound_f64(), 1.25);
+    }
+
+    #[test]
+    fn it_displays_quantile() {
+        let q = ApproxQuantile {
+            count: 10,
+            approx_value: 5,
+            lower_bound: 4,
+            upper_bound: 6,
+            scale: 0,
+        };
+        assert_eq!(format!("{}", q), "~5 [4, 6]");
+    }
+
+    #[test]
+    fn it_displays_scaled_quantile() {
+        let q = ApproxQuantile {
+            count: 10,
+            approx_value: 125,
+            lower_bound: 100,
+            upper_bound: 150,
+            scale: 2,
+        };
+        assert_eq!(format!("{}", q), "~1.25 [1, 1.5]");
+    }
+
     fn assert_queries(data: Vec<WeightedValue>) {
         let count = data.iter().map(|v| v.weight).sum();
         let values: Vec<u32> = data.iter().map(|v| v.value).collect();
@@ -341,7 +647,7 @@ mod tests {
             }
         }
         values.sort_unstable();
-        if values.len() > 0 {
+        if !values.is_empty() {
             let k = (values.len() as f64 * phi) as usize;
             Some(values[k])
         } else {
@@ -2,6 +2,7 @@ use encode::{Decodable, Encodable, EncodableError};
 use rand::rngs::SmallRng;
 use rand::{FromEntropy, RngCore};
 use std::io::{Read, Write};
+use std::mem::size_of;
 
 #[derive(Clone)]
 pub struct Sampler {
@@ -21,6 +22,13 @@ impl Sampler {
         }
     }
 
+    // Discard the stored value and return to max weight one, keeping the random generator
+    pub fn reset(&mut self) {
+        self.weight = 0;
+        self.max_weight = 1;
+        self.val = 0;
+    }
+
     pub fn set_max_weight(&mut self, max_weight: usize) {
         assert!(max_weight >= self.max_weight, "Cannot decrease max weight");
         self.max_weight = max_weight;
@@ -54,6 +62,11 @@ impl Sampler {
         self.weight
     }
 
+    // Weights are encoded as u64, regardless of the platform's usize
+    pub fn serialized_len(&self) -> usize {
+        size_of::<u64>() * 2 + size_of::<u32>()
+    }
+
     fn reservoir_sample_no_overflow(
         &mut self,
         val: u32,
@@ -61,7 +74,7 @@ impl Sampler {
         combined_weight: usize,
     ) -> Option<u32> {
         // Replace stored item with probability = weight / combined_weight
-        let cutoff = usize::max_value() / combined_weight * weight;
+        let cutoff = usize::MAX / combined_weight * weight;
         let r = self.generator.next_u64() as usize;
         if r <= cutoff {
             self.val = val;
@@ -87,7 +100,7 @@ impl Sampler {
 
         // output with probability = (heavier_weight / self.max_weight)
         assert!(heavier_weight <= self.max_weight);
-        let cutoff = (usize::max_value() / self.max_weight) * heavier_weight;
+        let cutoff = (usize::MAX / self.max_weight) * heavier_weight;
         let r = self.generator.next_u64() as usize;
         if r <= cutoff {
             Some(heavier_val)
@@ -0,0 +1,59 @@
+// Floating point values are stored in sketches as fixed-point integers,
+// multiplied by 10^scale and rounded to the nearest integer.
+// 10^9 is the largest power of ten that fits in a u32.
+pub const MAX_SCALE: u32 = 9;
+
+pub fn to_fixed(val: f64, scale: u32) -> u32 {
+    assert!(scale <= MAX_SCALE);
+    let scaled = (val * 10f64.powi(scale as i32)).round();
+    if scaled <= 0.0 {
+        0
+    } else if scaled >= u32::MAX as f64 {
+        u32::MAX
+    } else {
+        scaled as u32
+    }
+}
+
+// Sketch values are u32, so larger values saturate at `u32::MAX`
+pub fn to_sketch_value(val: u64) -> u32 {
+    if val >= u32::MAX as u64 {
+        u32::MAX
+    } else {
+        val as u32
+    }
+}
+
+pub fn from_fixed(val: u32, scale: u32) -> f64 {
+    assert!(scale <= MAX_SCALE);
+    val as f64 / 10f64.powi(scale as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_converts_to_and_from_fixed() {
+        assert_eq!(to_fixed(1.25, 2), 125);
+        assert_eq!(from_fixed(125, 2), 1.25);
+    }
+
+    #[test]
+    fn it_rounds_to_nearest_fixed() {
+        assert_eq!(to_fixed(1.255, 1), 13);
+        assert_eq!(to_fixed(1.24, 1), 12);
+    }
+
+    #[test]
+    fn it_clamps_out_of_range_values() {
+        assert_eq!(to_fixed(-1.5, 2), 0);
+        assert_eq!(to_fixed(1e20, 2), u32::MAX);
+    }
+
+    #[test]
+    fn it_saturates_large_sketch_values() {
+        assert_eq!(to_sketch_value(7), 7);
+        assert_eq!(to_sketch_value(u64::MAX), u32::MAX);
+    }
+}
@@ -13,7 +13,7 @@ const LARGE_SIZE: usize = SMALL_SIZE * 100;
 fn it_handles_query_with_no_values() {
     let input = Vec::new();
     let s = build_readable_sketch(&input);
-    if let Some(_) = s.query(0.1) {
+    if s.query(0.1).is_some() {
         panic!("expected no result!");
     }
 }
@@ -66,7 +66,10 @@ fn it_merges_two_sketches_without_increasing_error() {
     let input = random_distinct_values(n);
     let s1 = build_writable_sketch(&input[..n / 2]);
     let s2 = build_writable_sketch(&input[n / 2..n]);
-    let mut result = s2.merge(s1).to_readable();
+    let mut result = s2
+        .merge(s1)
+        .expect("Could not merge sketches")
+        .to_readable();
     check_error_bound(&mut result, &input);
 }
 
@@ -80,12 +83,183 @@ fn it_merges_many_sketches_without_increasing_error() {
         let start = i * sketch_size;
         let end = start + sketch_size;
         let new_sketch = build_writable_sketch(&input[start..end]);
-        s = s.merge(new_sketch);
+        s = s.merge(new_sketch).expect("Could not merge sketches");
     }
     let mut result = s.to_readable();
     check_error_bound(&mut result, &input);
 }
 
+#[test]
+fn it_builds_sketch_from_iter() {
+    let input = random_distinct_values(MEDIUM_SIZE);
+    let s1 = build_writable_sketch(&input);
+    let s2: WritableSketch = input.iter().map(|&v| u64::from(v)).collect();
+    assert_eq!(s1.count(), s2.count());
+    check_error_bound(&mut s1.to_readable(), &input);
+    check_error_bound(&mut s2.to_readable(), &input);
+}
+
+#[test]
+fn it_builds_sketch_from_vec_and_slice() {
+    let input = random_distinct_values(MEDIUM_SIZE);
+    let values: Vec<u64> = input.iter().map(|&v| u64::from(v)).collect();
+    let s1 = WritableSketch::from_slice(&values);
+    let s2 = WritableSketch::from(values);
+    assert_eq!(s1.count(), input.len());
+    assert_eq!(s2.count(), input.len());
+    check_error_bound(&mut s1.to_readable(), &input);
+    check_error_bound(&mut s2.to_readable(), &input);
+}
+
+#[test]
+fn it_saturates_values_too_large_for_sketch() {
+    let s = WritableSketch::from_slice(&[u64::MAX]);
+    let q = s.to_readable().query(0.5).expect("Could not query sketch");
+    assert_eq!(q.approx_value, u32::MAX);
+}
+
+#[test]
+fn it_clones_sketch_independently() {
+    let mut s1 = build_writable_sketch(&sequential_values(SMALL_SIZE));
+    let mut s2 = s1.clone();
+    for _ in 0..SMALL_SIZE {
+        s1.insert(0);
+        s2.insert(SMALL_SIZE as u32);
+    }
+    let q1 = s1
+        .to_readable()
+        .query(0.5)
+        .expect("Could not query first sketch");
+    let q2 = s2
+        .to_readable()
+        .query(0.5)
+        .expect("Could not query second sketch");
+    assert_eq!(q1.count, SMALL_SIZE * 2);
+    assert_eq!(q2.count, SMALL_SIZE * 2);
+    assert!(q1.approx_value < (SMALL_SIZE / 4) as u32);
+    assert!(q2.approx_value > (SMALL_SIZE * 3 / 4) as u32);
+}
+
+#[test]
+fn it_clones_sketch_during_compaction() {
+    let input = sequential_values(MEDIUM_SIZE);
+    let mut s = WritableSketch::new();
+    for v in input.iter() {
+        s.insert(*v);
+        let cloned = s.clone();
+        assert_eq!(cloned.count(), s.count());
+        assert_eq!(cloned.size(), s.size());
+    }
+    check_error_bound(&mut s.clone().to_readable(), &input);
+}
+
+#[test]
+fn it_calculates_monotonic_cdf() {
+    let input = random_duplicate_values(LARGE_SIZE);
+    let s = build_readable_sketch(&input);
+    let cdf = s.cdf(100);
+    assert_eq!(cdf.len(), 100);
+    for pair in cdf.windows(2) {
+        let ((phi1, v1), (phi2, v2)) = (pair[0], pair[1]);
+        assert!(0.0 < phi1 && phi1 < phi2 && phi2 < 1.0);
+        assert!(v1 <= v2);
+    }
+}
+
+#[test]
+fn it_calculates_cdf_with_no_values() {
+    let s = build_readable_sketch(&[]);
+    assert!(s.cdf(100).is_empty());
+}
+
+#[test]
+fn it_calculates_pdf() {
+    let input = sequential_values(LARGE_SIZE);
+    let s = build_readable_sketch(&input);
+    let n = LARGE_SIZE as u64;
+    let pdf = s.pdf(&[n / 10, n / 2, n]);
+    let expected = [0.1, 0.4, 0.5];
+    assert_eq!(pdf.len(), expected.len());
+    for (&(_, mass), &e) in pdf.iter().zip(expected.iter()) {
+        assert!(
+            (mass - e).abs() <= EPSILON * 2.0,
+            "mass {} expected {}",
+            mass,
+            e
+        );
+    }
+    let total: f64 = pdf.iter().map(|&(_, mass)| mass).sum();
+    assert!((total - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_calculates_pdf_with_no_values() {
+    let s = build_readable_sketch(&[]);
+    assert_eq!(s.pdf(&[10, 20]), vec![(10, 0.0), (20, 0.0)]);
+}
+
+#[test]
+fn it_compares_sketches_from_same_input() {
+    let input = random_distinct_values(LARGE_SIZE);
+    let s1 = build_readable_sketch(&input);
+    let s2 = build_readable_sketch(&sequential_values(LARGE_SIZE));
+    assert!(s1.approx_eq(&s2, EPSILON * 5.0));
+    assert!(s2.approx_eq(&s1, EPSILON * 5.0));
+}
+
+#[test]
+fn it_matches_unweighted_inserts_with_weighted_inserts() {
+    let values = random_distinct_values(SMALL_SIZE);
+    let mut rng = rand::thread_rng();
+    let weights: Vec<usize> = values.iter().map(|_| rng.gen_range(1, 50)).collect();
+    let mut expanded = Vec::new();
+    let mut weighted = WritableSketch::new();
+    for (v, w) in values.iter().zip(weights.iter()) {
+        weighted.insert_weighted(*v, *w);
+        for _ in 0..*w {
+            expanded.push(*v);
+        }
+    }
+    assert_eq!(weighted.count(), expanded.len());
+    let unweighted = build_readable_sketch(&expanded);
+    let mut weighted = weighted.to_readable();
+    check_error_bound(&mut weighted, &expanded);
+    assert!(weighted.approx_eq(&unweighted, EPSILON * 5.0));
+}
+
+#[test]
+fn it_shifts_quantiles_with_weighted_insert() {
+    let input = sequential_values(SMALL_SIZE);
+    let mut s = build_writable_sketch(&input);
+    s.insert_weighted(SMALL_SIZE as u32, SMALL_SIZE);
+    let mut expected = input.clone();
+    expected.extend(vec![SMALL_SIZE as u32; SMALL_SIZE]);
+    check_error_bound(&mut s.to_readable(), &expected);
+}
+
+#[test]
+fn it_compares_empty_sketches() {
+    let s1 = build_readable_sketch(&[]);
+    let s2 = build_readable_sketch(&[]);
+    assert!(s1.approx_eq(&s2, EPSILON));
+}
+
+#[test]
+fn it_compares_sketches_from_different_input() {
+    let s1 = build_readable_sketch(&sequential_values(LARGE_SIZE));
+    let shifted: Vec<u32> = sequential_values(LARGE_SIZE)
+        .iter()
+        .map(|v| v + (LARGE_SIZE / 2) as u32)
+        .collect();
+    let s2 = build_readable_sketch(&shifted);
+    assert!(!s1.approx_eq(&s2, EPSILON * 5.0));
+
+    // Same distribution, but different counts
+    let s3 = build_readable_sketch(&sequential_values(SMALL_SIZE));
+    let s4 = build_readable_sketch(&sequential_values(SMALL_SIZE + 1));
+    assert!(!s3.approx_eq(&s4, 1.0));
+}
+
 fn sequential_values(n: usize) -> Vec<u32> {
     let mut result: Vec<u32> = Vec::with_capacity(n);
     for v in 0..n {
@@ -131,7 +305,7 @@ fn build_writable_sketch(input: &[u32]) -> WritableSketch {
 }
 
 fn check_error_bound(sketch: &mut ReadableSketch, input: &[u32]) {
-    let calc = ErrorCalculator::new(&input);
+    let calc = ErrorCalculator::new(input);
     for i in 1..10 {
         let phi = i as f64 / 10.0;
         let approx = sketch
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::time::{SystemTime, UNIX_EPOCH};
 use time::timestamp::TimeStamp;
 
@@ -13,6 +14,12 @@ impl SystemClock {
     }
 }
 
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
 impl Clock for SystemClock {
     fn now(&self) -> TimeStamp {
         SystemTime::now()
@@ -22,22 +29,24 @@ impl Clock for SystemClock {
     }
 }
 
+// Advances only when ticked. Ticking takes `&self`, so the clock can advance
+// while borrowed by the code under test.
 pub struct MockClock {
-    ts: TimeStamp,
+    ts: Cell<TimeStamp>,
 }
 
 impl MockClock {
     pub fn new(ts: TimeStamp) -> MockClock {
-        MockClock { ts }
+        MockClock { ts: Cell::new(ts) }
     }
 
-    pub fn tick(&mut self, seconds: u64) {
-        self.ts += seconds;
+    pub fn tick(&self, seconds: u64) {
+        self.ts.set(self.ts.get() + seconds);
     }
 }
 
 impl Clock for MockClock {
     fn now(&self) -> TimeStamp {
-        self.ts
+        self.ts.get()
     }
 }
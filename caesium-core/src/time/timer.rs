@@ -17,3 +17,9 @@ impl Timer {
         self.start.map(|start| Instant::now().duration_since(start))
     }
 }
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
@@ -1,8 +1,9 @@
 // Unix timestamp = seconds since 1970-01-01T00:00:00Z
 pub type TimeStamp = u64;
 
-const SECONDS_PER_HOUR: u64 = 3600;
-const SECONDS_PER_DAY: u64 = SECONDS_PER_HOUR * 24;
+pub const SECONDS_PER_HOUR: u64 = 3600;
+pub const SECONDS_PER_DAY: u64 = SECONDS_PER_HOUR * 24;
+pub const SECONDS_PER_WEEK: u64 = SECONDS_PER_DAY * 7;
 
 pub fn hours(ts: TimeStamp) -> u64 {
     ts / SECONDS_PER_HOUR
@@ -11,3 +12,8 @@ pub fn hours(ts: TimeStamp) -> u64 {
 pub fn days(ts: TimeStamp) -> u64 {
     ts / SECONDS_PER_DAY
 }
+
+// Weeks since the epoch, so each week starts on a Thursday
+pub fn weeks(ts: TimeStamp) -> u64 {
+    ts / SECONDS_PER_WEEK
+}
@@ -1,4 +1,8 @@
+#[cfg(feature = "chrono")]
+use chrono::{TimeZone, Utc};
 use encode::{Decodable, Encodable, EncodableError};
+use std::cmp::{max, min};
+use std::fmt;
 use std::io::{Read, Write};
 use time::timestamp::TimeStamp;
 
@@ -8,10 +12,23 @@ pub struct TimeWindow {
     end: TimeStamp,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum TimeWindowError {
+    InvalidRange,
+}
+
 impl TimeWindow {
+    // Panics if `start` is not strictly less than `end`
     pub fn new(start: TimeStamp, end: TimeStamp) -> TimeWindow {
-        assert!(start <= end);
-        TimeWindow { start, end }
+        TimeWindow::try_new(start, end).expect("Window start must be less than end")
+    }
+
+    pub fn try_new(start: TimeStamp, end: TimeStamp) -> Result<TimeWindow, TimeWindowError> {
+        if start < end {
+            Ok(TimeWindow { start, end })
+        } else {
+            Err(TimeWindowError::InvalidRange)
+        }
     }
 
     pub fn start(&self) -> TimeStamp {
@@ -33,6 +50,65 @@ impl TimeWindow {
     pub fn disjoint(&self, other: &TimeWindow) -> bool {
         self.end <= other.start || self.start >= other.end
     }
+
+    pub fn intersection(&self, other: &TimeWindow) -> Option<TimeWindow> {
+        if self.overlaps(other) {
+            let start = max(self.start, other.start);
+            let end = min(self.end, other.end);
+            Some(TimeWindow::new(start, end))
+        } else {
+            None
+        }
+    }
+
+    // Divides the window into `n` sub-windows of equal duration.
+    // Panics if `n` is zero or does not evenly divide the window duration.
+    pub fn split(&self, n: usize) -> Vec<TimeWindow> {
+        self.try_split(n)
+            .expect("Window duration must be evenly divisible by n")
+    }
+
+    pub fn try_split(&self, n: usize) -> Option<Vec<TimeWindow>> {
+        let n = n as u64;
+        if n == 0 || !self.duration().is_multiple_of(n) {
+            return None;
+        }
+        // Each split window spans a whole step, so it is valid whenever this window is
+        let step = self.duration() / n;
+        let windows = (0..n)
+            .map(|i| {
+                let start = self.start + i * step;
+                TimeWindow {
+                    start,
+                    end: start + step,
+                }
+            })
+            .collect();
+        Some(windows)
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        fmt_timestamp(self.start, f)?;
+        write!(f, ", ")?;
+        fmt_timestamp(self.end, f)?;
+        write!(f, ")")
+    }
+}
+
+#[cfg(feature = "chrono")]
+fn fmt_timestamp(ts: TimeStamp, f: &mut fmt::Formatter) -> fmt::Result {
+    match Utc.timestamp_opt(ts as i64, 0).single() {
+        Some(dt) => write!(f, "{}", dt.format("%Y-%m-%dT%H:%M:%SZ")),
+        None => write!(f, "{}", ts),
+    }
+}
+
+#[cfg(not(feature = "chrono"))]
+fn fmt_timestamp(ts: TimeStamp, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", ts)
 }
 
 impl<W> Encodable<W> for TimeWindow
@@ -53,12 +129,160 @@ where
     fn decode(reader: &mut R) -> Result<TimeWindow, EncodableError> {
         let start = TimeStamp::decode(reader)?;
         let end = TimeStamp::decode(reader)?;
-        if start > end {
-            Err(EncodableError::FormatError(
-                "TimeWindow start must be less than end",
-            ))
-        } else {
-            Ok(TimeWindow::new(start, end))
+        TimeWindow::try_new(start, end)
+            .map_err(|_| EncodableError::FormatError("TimeWindow start must be less than end"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_overlapping_windows() {
+        let w = TimeWindow::new(10, 20);
+        assert!(w.overlaps(&TimeWindow::new(10, 20)));
+        assert!(w.overlaps(&TimeWindow::new(5, 15)));
+        assert!(w.overlaps(&TimeWindow::new(15, 25)));
+        assert!(w.overlaps(&TimeWindow::new(12, 18)));
+        assert!(w.overlaps(&TimeWindow::new(0, 30)));
+    }
+
+    #[test]
+    fn it_checks_windows_touching_at_boundary() {
+        let w = TimeWindow::new(10, 20);
+        assert!(!w.overlaps(&TimeWindow::new(0, 10)));
+        assert!(!w.overlaps(&TimeWindow::new(20, 30)));
+        assert!(!w.overlaps(&TimeWindow::new(0, 5)));
+        assert!(!w.overlaps(&TimeWindow::new(25, 30)));
+    }
+
+    #[test]
+    fn it_computes_intersection() {
+        let w = TimeWindow::new(10, 20);
+        assert_eq!(
+            w.intersection(&TimeWindow::new(5, 15)),
+            Some(TimeWindow::new(10, 15))
+        );
+        assert_eq!(
+            w.intersection(&TimeWindow::new(15, 25)),
+            Some(TimeWindow::new(15, 20))
+        );
+        assert_eq!(
+            w.intersection(&TimeWindow::new(12, 18)),
+            Some(TimeWindow::new(12, 18))
+        );
+        assert_eq!(
+            w.intersection(&TimeWindow::new(0, 30)),
+            Some(TimeWindow::new(10, 20))
+        );
+    }
+
+    #[test]
+    fn it_computes_empty_intersection_at_boundary() {
+        let w = TimeWindow::new(10, 20);
+        assert_eq!(w.intersection(&TimeWindow::new(0, 10)), None);
+        assert_eq!(w.intersection(&TimeWindow::new(20, 30)), None);
+        assert_eq!(w.intersection(&TimeWindow::new(30, 40)), None);
+    }
+
+    #[test]
+    fn it_splits_window() {
+        let w = TimeWindow::new(10, 40);
+        assert_eq!(
+            w.split(3),
+            vec![
+                TimeWindow::new(10, 20),
+                TimeWindow::new(20, 30),
+                TimeWindow::new(30, 40),
+            ]
+        );
+        assert_eq!(w.split(1), vec![w]);
+    }
+
+    #[test]
+    fn it_splits_window_into_unit_windows() {
+        let w = TimeWindow::new(0, 3);
+        assert_eq!(
+            w.split(3),
+            vec![
+                TimeWindow::new(0, 1),
+                TimeWindow::new(1, 2),
+                TimeWindow::new(2, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_does_not_split_into_zero_windows() {
+        assert_eq!(TimeWindow::new(0, 10).try_split(0), None);
+    }
+
+    #[test]
+    fn it_does_not_split_uneven_windows() {
+        assert_eq!(TimeWindow::new(0, 10).try_split(3), None);
+        assert_eq!(TimeWindow::new(0, 10).try_split(20), None);
+    }
+
+    #[test]
+    fn it_splits_empty_window() {
+        let w = TimeWindow { start: 10, end: 10 };
+        assert_eq!(w.try_split(2), Some(vec![w, w]));
+    }
+
+    #[test]
+    fn it_constructs_valid_window() {
+        let w = TimeWindow::try_new(10, 11).expect("Could not construct window");
+        assert_eq!((w.start(), w.end()), (10, 11));
+    }
+
+    #[test]
+    fn it_rejects_empty_window() {
+        assert_eq!(
+            TimeWindow::try_new(10, 10),
+            Err(TimeWindowError::InvalidRange)
+        );
+    }
+
+    #[test]
+    fn it_rejects_window_with_start_after_end() {
+        assert_eq!(
+            TimeWindow::try_new(20, 10),
+            Err(TimeWindowError::InvalidRange)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_panics_on_invalid_window() {
+        TimeWindow::new(10, 10);
+    }
+
+    #[test]
+    fn it_rejects_decoding_empty_window() {
+        let mut buf = Vec::new();
+        (10 as TimeStamp).encode(&mut buf).unwrap();
+        (10 as TimeStamp).encode(&mut buf).unwrap();
+        match TimeWindow::decode(&mut &buf[..]) {
+            Err(EncodableError::FormatError(_)) => {}
+            r => panic!("Expected format error, got {:?}", r),
         }
     }
+
+    #[cfg(not(feature = "chrono"))]
+    #[test]
+    fn it_displays_window_as_seconds() {
+        let w = TimeWindow::new(10, 20);
+        assert_eq!(format!("{}", w), "[10, 20)");
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn it_displays_window_as_iso8601() {
+        let w = TimeWindow::new(0, 3600);
+        assert_eq!(
+            format!("{}", w),
+            "[1970-01-01T00:00:00Z, 1970-01-01T01:00:00Z)"
+        );
+    }
 }
@@ -1,6 +1,6 @@
 use caesium_core::encode::frame::FrameEncoder;
 use caesium_core::encode::EncodableError;
-use caesium_core::protocol::messages::InsertMessage;
+use caesium_core::protocol::messages::{AuthMessage, WriteMessage};
 use std::io;
 use std::net::{TcpStream, ToSocketAddrs};
 use std::time::Duration;
@@ -11,6 +11,7 @@ pub struct Client {
     addr: String,
     socket_opt: Option<TcpStream>,
     frame_encoder: FrameEncoder,
+    auth_token: Option<String>,
 }
 
 impl Client {
@@ -19,12 +20,24 @@ impl Client {
             addr,
             socket_opt: None,
             frame_encoder: FrameEncoder::new(),
+            auth_token: None,
         }
     }
 
-    pub fn send(&mut self, msg: &InsertMessage) -> Result<(), ClientError> {
+    // Sends the token at the start of each new connection,
+    // for servers that require authentication
+    pub fn with_auth_token(mut self, auth_token: Option<String>) -> Client {
+        self.auth_token = auth_token;
+        self
+    }
+
+    pub fn send(&mut self, msg: &WriteMessage) -> Result<(), ClientError> {
         let mut socket = match self.socket_opt.take() {
-            None => self.connect()?,
+            None => {
+                let mut socket = self.connect()?;
+                self.authenticate(&mut socket)?;
+                socket
+            }
             Some(s) => s,
         };
         self.frame_encoder.encode_framed_msg(msg, &mut socket)?;
@@ -32,6 +45,16 @@ impl Client {
         Ok(())
     }
 
+    fn authenticate(&mut self, socket: &mut TcpStream) -> Result<(), ClientError> {
+        if let Some(ref token) = self.auth_token {
+            let msg = WriteMessage::Auth(AuthMessage {
+                token: token.clone(),
+            });
+            self.frame_encoder.encode_framed_msg(&msg, socket)?;
+        }
+        Ok(())
+    }
+
     fn connect(&mut self) -> Result<TcpStream, ClientError> {
         let timeout = Duration::from_millis(TIMEOUT_MS);
         for addr in self.addr.to_socket_addrs()? {
@@ -0,0 +1,97 @@
+use caesium_core::get_sketch_type;
+use std::io;
+use std::net::SocketAddr;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const HEALTH_PATH: &str = "/healthz";
+const CONTENT_TYPE: &str = "application/json";
+
+// Serves `GET /healthz` for load balancers and orchestrators.
+// Runs on its own thread, so a slow probe never blocks the listener.
+pub struct HealthServer {
+    server: Server,
+}
+
+impl HealthServer {
+    pub fn new(addr: &SocketAddr) -> Result<HealthServer, io::Error> {
+        let server = Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(HealthServer { server })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr()
+    }
+
+    pub fn run(self) {
+        info!("Serving health checks on {}", self.local_addr());
+        for request in self.server.incoming_requests() {
+            handle_request(request);
+        }
+    }
+}
+
+fn handle_request(request: Request) {
+    let result = if request.url() != HEALTH_PATH {
+        request.respond(Response::empty(404))
+    } else if *request.method() != Method::Get {
+        request.respond(Response::empty(405))
+    } else {
+        let header = Header::from_bytes(&b"Content-Type"[..], CONTENT_TYPE.as_bytes())
+            .expect("Could not construct content type header");
+        let body = format!(
+            "{{\"status\":\"ok\",\"sketch_type\":\"{}\"}}",
+            get_sketch_type().name()
+        );
+        request.respond(Response::from_string(body).with_header(header))
+    };
+    if let Err(err) = result {
+        error!("Could not send response to health check client: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    #[test]
+    fn it_serves_health_check() {
+        let response = send_request(b"GET /healthz HTTP/1.0\r\n\r\n");
+        assert!(response.contains("200 OK"));
+        assert!(response.contains("application/json"));
+        let expected = format!(
+            "{{\"status\":\"ok\",\"sketch_type\":\"{}\"}}",
+            get_sketch_type().name()
+        );
+        assert!(response.ends_with(&expected));
+    }
+
+    #[test]
+    fn it_rejects_unknown_path() {
+        let response = send_request(b"GET /readyz HTTP/1.0\r\n\r\n");
+        assert!(response.contains("404 Not Found"));
+    }
+
+    #[test]
+    fn it_rejects_non_get_method() {
+        let response = send_request(b"POST /healthz HTTP/1.0\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.contains("405 Method Not Allowed"));
+    }
+
+    fn send_request(request: &[u8]) -> String {
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server = HealthServer::new(&addr).expect("Could not start health server");
+        let addr = server.local_addr();
+        thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).expect("Could not connect to health server");
+        stream.write_all(request).expect("Could not send request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .expect("Could not read response");
+        response
+    }
+}
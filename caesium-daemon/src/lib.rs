@@ -1,6 +1,8 @@
 extern crate caesium_core;
+extern crate ctrlc;
 extern crate regex;
 extern crate slab;
+extern crate tiny_http;
 
 #[macro_use]
 extern crate lazy_static;
@@ -10,35 +12,132 @@ extern crate log;
 
 mod circuit;
 mod client;
+mod health;
 mod listener;
+mod metrics;
 mod processor;
 mod sender;
+mod wal;
 mod window;
 
+pub use listener::Protocol;
+pub use sender::SenderConfig;
+
 use circuit::CircuitState;
 use client::Client;
-use listener::listener_thread;
+use health::HealthServer;
+use listener::{listener_thread, tcp_listener_thread};
+use metrics::{metrics_reporter_thread, DaemonMetrics, MetricsServer};
 use processor::processor_thread;
 use sender::sender_thread;
 use std::io;
-use std::net::UdpSocket;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::Duration;
+use wal::Wal;
 
+// Runs until `shutdown` is set, then flushes buffered metrics to the backend before returning.
 pub fn run_daemon(
     listen_addr: String,
+    tcp_listen_addr: Option<String>,
     publish_addr: String,
+    auth_token: Option<String>,
     window_size: u64,
+    protocol: Protocol,
+    wal_path: Option<String>,
+    metrics_interval: Duration,
+    metrics_addr: Option<SocketAddr>,
+    health_addr: Option<SocketAddr>,
+    sender_config: SenderConfig,
+    shutdown: Arc<AtomicBool>,
 ) -> Result<(), io::Error> {
     let socket = UdpSocket::bind(&listen_addr)?;
-    let client = Client::new(publish_addr);
+    let tcp_listener = match tcp_listen_addr {
+        Some(addr) => Some(TcpListener::bind(&addr)?),
+        None => None,
+    };
+    let wal = match wal_path {
+        Some(path) => Some(Wal::open(Path::new(&path))?),
+        None => None,
+    };
+    let metrics = Arc::new(DaemonMetrics::new());
+    if let Some(addr) = metrics_addr {
+        let server = MetricsServer::new(&addr, metrics.clone())?;
+        thread::spawn(move || server.run());
+    }
+    if let Some(addr) = health_addr {
+        let server = HealthServer::new(&addr)?;
+        thread::spawn(move || server.run());
+    }
+    let reporter_metrics = metrics.clone();
+    let reporter_shutdown = shutdown.clone();
+    thread::spawn(move || {
+        metrics_reporter_thread(reporter_metrics, metrics_interval, reporter_shutdown)
+    });
+    let client = Client::new(publish_addr).with_auth_token(auth_token);
     let (circuit_ref1, circuit_ref2) = shared_circuit();
     let (listener_out, processor_in) = channel();
     let (processor_out, sender_in) = channel();
-    thread::spawn(move || processor_thread(processor_in, processor_out, circuit_ref1));
-    thread::spawn(move || sender_thread(client, sender_in, circuit_ref2));
-    listener_thread(socket, listener_out, window_size)
+    let processor_metrics = metrics.clone();
+    let processor = thread::spawn(move || {
+        processor_thread(
+            processor_in,
+            processor_out,
+            circuit_ref1,
+            wal,
+            processor_metrics,
+        )
+    });
+    let sender_metrics = metrics.clone();
+    let sender_shutdown = shutdown.clone();
+    let sender = thread::spawn(move || {
+        sender_thread(
+            client,
+            sender_in,
+            circuit_ref2,
+            sender_config,
+            sender_metrics,
+            sender_shutdown,
+        )
+    });
+    if let Some(listener) = tcp_listener {
+        let tcp_out = listener_out.clone();
+        let tcp_metrics = metrics.clone();
+        thread::spawn(move || {
+            if let Err(err) = tcp_listener_thread(listener, tcp_out, protocol, tcp_metrics) {
+                error!("Error running TCP listener: {:?}", err);
+            }
+        });
+    }
+    listener_thread(
+        socket,
+        listener_out,
+        window_size,
+        protocol,
+        metrics,
+        shutdown,
+    )?;
+
+    // The processor exits after flushing, which closes the sender's input channel
+    if let Err(err) = processor.join() {
+        error!("Error joining processor thread: {:?}", err);
+    }
+    if let Err(err) = sender.join() {
+        error!("Error joining sender thread: {:?}", err);
+    }
+    Ok(())
+}
+
+// Sets the shutdown flag when the process receives SIGINT, SIGTERM, or SIGHUP
+pub fn install_signal_handler(shutdown: Arc<AtomicBool>) -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(move || {
+        info!("Received shutdown signal");
+        shutdown.store(true, Ordering::SeqCst);
+    })
 }
 
 fn shared_circuit() -> (Arc<RwLock<CircuitState>>, Arc<RwLock<CircuitState>>) {
@@ -1,28 +1,57 @@
+use caesium_core::encode::Decodable;
+use caesium_core::metric::canonicalize;
+use caesium_core::protocol::messages::MetricKind;
 use caesium_core::time::clock::SystemClock;
+use metrics::DaemonMetrics;
 use processor::ProcessorCommand;
 use regex::Regex;
 use std::io;
-use std::net::UdpSocket;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream, UdpSocket};
 use std::str;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 use window::WindowTracker;
 
 const MAX_MSG_LEN: usize = 1024;
 const READ_TIMEOUT_MS: u64 = 1000;
+const MAX_TCP_PAYLOAD_LEN: usize = 1 << 20;
+const MAX_TCP_CONNECTIONS: usize = 256;
+const TCP_READ_TIMEOUT_MS: u64 = 60000;
+
+// Sampled values are inserted once per represented observation,
+// so bound the number of inserts a single datagram can trigger.
+const MAX_SAMPLE_COUNT: u32 = 10_000;
+
+// Line protocol for metrics received by the daemon.
+// The caesium protocol accepts only integer values and metric names
+// the server can store; the statsd protocol also accepts fractional values,
+// trailing newlines, and extra sections such as tags, and skips metric
+// types the daemon does not support.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    Caesium,
+    Statsd,
+}
 
 pub fn listener_thread(
     socket: UdpSocket,
     out: Sender<ProcessorCommand>,
     window_size: u64,
+    protocol: Protocol,
+    metrics: Arc<DaemonMetrics>,
+    shutdown: Arc<AtomicBool>,
 ) -> Result<(), io::Error> {
     let clock = SystemClock::new();
     let mut window_tracker = WindowTracker::new(window_size, &clock);
     let mut buf = [0; MAX_MSG_LEN];
     socket.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)))?;
-    loop {
+    while !shutdown.load(Ordering::SeqCst) {
         match socket.recv(&mut buf) {
-            Ok(n) => handle_datagram(&buf[..n], &out),
+            Ok(n) => handle_datagram(&buf[..n], &out, protocol, &metrics),
             Err(err) => match err.kind() {
                 io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {}
                 _ => error!("Error receving msg: {:?}", err),
@@ -34,67 +63,284 @@ pub fn listener_thread(
                 .expect("Could not send command to processor thread");
         }
     }
+
+    info!("Stopped listening, flushing buffered metrics");
+    out.send(ProcessorCommand::Shutdown(window_tracker.current_window()))
+        .expect("Could not send command to processor thread");
+    Ok(())
 }
 
-fn handle_datagram(buf: &[u8], out: &Sender<ProcessorCommand>) {
+// Accepts TCP connections that send payloads of the form [len: u32][payload],
+// where the payload contains one metric per line.
+// Window boundaries are still tracked by the UDP listener thread.
+pub fn tcp_listener_thread(
+    listener: TcpListener,
+    out: Sender<ProcessorCommand>,
+    protocol: Protocol,
+    metrics: Arc<DaemonMetrics>,
+) -> Result<(), io::Error> {
+    serve_tcp_connections(
+        listener,
+        out,
+        protocol,
+        metrics,
+        MAX_TCP_CONNECTIONS,
+        Duration::from_millis(TCP_READ_TIMEOUT_MS),
+    )
+}
+
+// Each connection gets its own thread, so connections beyond `max_connections` are
+// closed immediately, and connections idle for longer than `read_timeout` are closed.
+fn serve_tcp_connections(
+    listener: TcpListener,
+    out: Sender<ProcessorCommand>,
+    protocol: Protocol,
+    metrics: Arc<DaemonMetrics>,
+    max_connections: usize,
+    read_timeout: Duration,
+) -> Result<(), io::Error> {
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream_result in listener.incoming() {
+        match stream_result {
+            Ok(stream) => {
+                let slot = ConnectionSlot::new(active.clone());
+                if slot.count > max_connections {
+                    warn!("Rejecting TCP connection, too many connections are open");
+                    continue;
+                }
+                if let Err(err) = stream.set_read_timeout(Some(read_timeout)) {
+                    error!("Could not configure TCP connection: {:?}", err);
+                    continue;
+                }
+                let out = out.clone();
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    handle_tcp_connection(stream, out, protocol, &metrics);
+                    drop(slot);
+                });
+            }
+            Err(err) => error!("Error accepting TCP connection: {:?}", err),
+        }
+    }
+    Ok(())
+}
+
+// Counts a connection as open until dropped
+struct ConnectionSlot {
+    active: Arc<AtomicUsize>,
+    count: usize,
+}
+
+impl ConnectionSlot {
+    fn new(active: Arc<AtomicUsize>) -> ConnectionSlot {
+        let count = active.fetch_add(1, Ordering::SeqCst) + 1;
+        ConnectionSlot { active, count }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle_tcp_connection(
+    mut stream: TcpStream,
+    out: Sender<ProcessorCommand>,
+    protocol: Protocol,
+    metrics: &DaemonMetrics,
+) {
+    let mut buf = Vec::new();
+    loop {
+        let len = match u32::decode(&mut stream) {
+            Ok(len) => len as usize,
+            Err(_) => {
+                debug!("Closing TCP connection");
+                break;
+            }
+        };
+        if len > MAX_TCP_PAYLOAD_LEN {
+            warn!("Closing TCP connection with payload length {}", len);
+            break;
+        }
+        buf.resize(len, 0);
+        if let Err(err) = stream.read_exact(&mut buf) {
+            warn!("Could not read TCP payload: {:?}", err);
+            break;
+        }
+        handle_datagram(&buf, &out, protocol, metrics);
+    }
+}
+
+// Statsd clients often pack several newline-separated metrics into one datagram.
+// Each line is parsed separately, so a malformed line doesn't discard the others.
+fn handle_datagram(
+    buf: &[u8],
+    out: &Sender<ProcessorCommand>,
+    protocol: Protocol,
+    metrics: &DaemonMetrics,
+) {
+    for line in buf.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+        metrics.record_received();
+        if !handle_line(line, out, protocol) {
+            metrics.record_dropped(1);
+        }
+    }
+}
+
+// Returns whether the line was parsed and sent to the processor
+fn handle_line(buf: &[u8], out: &Sender<ProcessorCommand>, protocol: Protocol) -> bool {
     match str::from_utf8(buf) {
         Ok(s) => {
             trace!("Received input: {}", &s);
-            match parse_metric_str(&s) {
+            let parsed = match protocol {
+                Protocol::Caesium => parse_metric_str(&s),
+                Protocol::Statsd => parse_statsd(&s),
+            };
+            match parsed {
                 Some(cmd) => {
                     out.send(cmd)
                         .expect("Could not send command to processor thread");
+                    true
+                }
+                None => {
+                    info!("Could not parse string as cmd: {}", &s);
+                    false
                 }
-                None => info!("Could not parse string as cmd: {}", &s),
             }
         }
         Err(err) => {
             warn!("Could not parse input as string: {:?}", err);
+            false
         }
     }
 }
 
+#[cfg(not(feature = "unicode-names"))]
+const INSERT_CMD_PATTERN: &str = "^(?P<metric>[a-zA-Z][a-zA-Z0-9._-]*(;[a-zA-Z][a-zA-Z0-9._-]*=[a-zA-Z0-9._/-]+)*):(?P<value>[0-9]+)[|](?P<kind>ms|c|g)([|]@(?P<rate>[0-9]+([.][0-9]+)?))?$";
+
+#[cfg(feature = "unicode-names")]
+const INSERT_CMD_PATTERN: &str = "^(?P<metric>\\p{L}[\\p{L}\\p{N}._-]*(;\\p{L}[\\p{L}\\p{N}._-]*=[a-zA-Z0-9._/-]+)*):(?P<value>[0-9]+)[|](?P<kind>ms|c|g)([|]@(?P<rate>[0-9]+([.][0-9]+)?))?$";
+
 fn parse_metric_str(s: &str) -> Option<ProcessorCommand> {
     lazy_static! {
-        static ref INSERT_CMD_RE: Regex = Regex::new(
-            "^(?P<metric>[a-zA-Z][a-zA-Z0-9._-]*):(?P<value>[0-9]+)[|]ms([|]@[0-9]+[.][0-9]+)?$"
-        )
-        .expect("Could not compile regex");
+        static ref INSERT_CMD_RE: Regex =
+            Regex::new(INSERT_CMD_PATTERN).expect("Could not compile regex");
     }
 
-    INSERT_CMD_RE
-        .captures(s)
-        .and_then(|c| match (c.name("metric"), c.name("value")) {
-            (Some(metric_match), Some(value_match)) => {
+    INSERT_CMD_RE.captures(s).and_then(|c| {
+        match (c.name("metric"), c.name("value"), c.name("kind")) {
+            (Some(metric_match), Some(value_match), Some(kind_match)) => {
+                let sample_count = match c.name("rate") {
+                    Some(rate_match) => parse_sample_rate(rate_match.as_str())?,
+                    None => 1,
+                };
+                let metric_name = canonicalize(metric_match.as_str())?;
                 value_match.as_str().parse::<u32>().ok().map(|value| {
-                    let metric_name = metric_match.as_str().to_string();
-                    ProcessorCommand::InsertMetric(metric_name, value)
+                    let kind = parse_kind(kind_match.as_str());
+                    ProcessorCommand::InsertMetric(metric_name, kind, value, sample_count)
                 })
             }
             _ => None,
-        })
+        }
+    })
+}
+
+// Parses a statsd metric of the form `name:value|type[|@rate][|#tags]`
+fn parse_statsd(s: &str) -> Option<ProcessorCommand> {
+    let line = s.trim_end_matches(['\n', '\r']);
+    let mut sections = line.split('|');
+    let (metric_name, value_str) = sections.next()?.split_once(':')?;
+    let kind = match sections.next()? {
+        "ms" => MetricKind::Timer,
+        "c" => MetricKind::Counter,
+        "g" => MetricKind::Gauge,
+        other => {
+            debug!("Skipping unsupported statsd metric type: {}", other);
+            return None;
+        }
+    };
+
+    let metric_name = canonicalize(metric_name)?;
+
+    // Signed gauges are relative updates, which the daemon does not support
+    if value_str.starts_with('+') || value_str.starts_with('-') {
+        debug!("Skipping signed statsd value: {}", value_str);
+        return None;
+    }
+
+    let value = value_str.parse::<f64>().ok()?;
+    if !value.is_finite() || value > f64::from(u32::MAX) {
+        return None;
+    }
+
+    let mut sample_count = 1;
+    for section in sections {
+        if let Some(rate) = section.strip_prefix('@') {
+            sample_count = parse_sample_rate(rate)?;
+        }
+    }
+
+    Some(ProcessorCommand::InsertMetric(
+        metric_name,
+        kind,
+        value.round() as u32,
+        sample_count,
+    ))
+}
+
+// Converts a sample rate in (0, 1] to the number of observations
+// each sampled value represents.
+fn parse_sample_rate(s: &str) -> Option<u32> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate <= 1.0 => {
+            let sample_count = (1.0 / rate).round();
+            if sample_count <= f64::from(MAX_SAMPLE_COUNT) {
+                Some(sample_count as u32)
+            } else {
+                warn!("Sample rate {} is too small", rate);
+                None
+            }
+        }
+        _ => {
+            warn!("Sample rate must be in the range (0, 1], got {}", s);
+            None
+        }
+    }
+}
+
+fn parse_kind(s: &str) -> MetricKind {
+    match s {
+        "c" => MetricKind::Counter,
+        "g" => MetricKind::Gauge,
+        _ => MetricKind::Timer,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use caesium_core::encode::Encodable;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
     use std::time::Duration;
 
     #[test]
     fn it_parses_commands() {
         let data = "foo:1234|ms".as_bytes();
         let (tx, rx) = channel();
-        handle_datagram(&data, &tx);
+        handle_datagram(&data, &tx, Protocol::Caesium, &DaemonMetrics::new());
         match rx.recv_timeout(Duration::from_millis(1000)) {
             Ok(cmd) => match cmd {
-                ProcessorCommand::InsertMetric(metric, value) => {
+                ProcessorCommand::InsertMetric(metric, kind, value, _) => {
                     assert_eq!(metric, "foo");
+                    assert_eq!(kind, MetricKind::Timer);
                     assert_eq!(value, 1234);
                 }
-                _ => assert!(false, "Unexpected processor command type"),
+                _ => panic!("Unexpected processor command type"),
             },
-            Err(err) => assert!(false, "Error receiving result: {}", err),
+            Err(err) => panic!("Error receiving result: {}", err),
         }
     }
 
@@ -102,23 +348,275 @@ mod tests {
     fn it_ignores_invalid_commands() {
         let data = "invalid".as_bytes();
         let (tx, rx) = channel();
-        handle_datagram(&data, &tx);
+        handle_datagram(&data, &tx, Protocol::Caesium, &DaemonMetrics::new());
         match rx.recv_timeout(Duration::from_millis(500)) {
             Err(RecvTimeoutError::Timeout) => {}
-            _ => assert!(false, "Expected timeout error"),
+            _ => panic!("Expected timeout error"),
         }
     }
 
+    #[test]
+    fn it_parses_multiple_metrics_per_datagram() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").expect("Could not bind UDP socket");
+        let addr = receiver
+            .local_addr()
+            .expect("Could not retrieve local addr");
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            listener_thread(
+                receiver,
+                tx,
+                30,
+                Protocol::Caesium,
+                Arc::new(DaemonMetrics::new()),
+                Arc::new(AtomicBool::new(false)),
+            )
+        });
+
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("Could not bind UDP socket");
+        sender
+            .send_to("foo:1|ms\nbar:2|c\nbaz:3|g\n".as_bytes(), addr)
+            .expect("Could not send datagram");
+        assert_eq!(
+            recv_inserts(&rx, 3),
+            vec![
+                ("foo".to_string(), MetricKind::Timer, 1),
+                ("bar".to_string(), MetricKind::Counter, 2),
+                ("baz".to_string(), MetricKind::Gauge, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_skips_malformed_lines_in_datagram() {
+        let data = "foo:1|ms\ninvalid\n\nbar:2|ms".as_bytes();
+        let (tx, rx) = channel();
+        let metrics = DaemonMetrics::new();
+        handle_datagram(&data, &tx, Protocol::Caesium, &metrics);
+        assert_eq!(
+            recv_inserts(&rx, 2),
+            vec![
+                ("foo".to_string(), MetricKind::Timer, 1),
+                ("bar".to_string(), MetricKind::Timer, 2),
+            ]
+        );
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_received, 3);
+        assert_eq!(snapshot.messages_dropped, 1);
+        match rx.recv_timeout(Duration::from_millis(500)) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => panic!("Expected timeout error"),
+        }
+    }
+
+    #[test]
+    fn it_requests_flush_on_shutdown() {
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("Could not bind UDP socket");
+        let (tx, rx) = channel();
+        let shutdown = Arc::new(AtomicBool::new(true));
+        listener_thread(
+            socket,
+            tx,
+            30,
+            Protocol::Caesium,
+            Arc::new(DaemonMetrics::new()),
+            shutdown,
+        )
+        .expect("Could not run listener");
+        match rx.recv_timeout(Duration::from_millis(1000)) {
+            Ok(ProcessorCommand::Shutdown(window)) => assert_eq!(window.end() - window.start(), 30),
+            _ => panic!("Expected shutdown command"),
+        }
+    }
+
+    #[test]
+    fn it_receives_metrics_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind TCP listener");
+        let addr = listener
+            .local_addr()
+            .expect("Could not retrieve local addr");
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            tcp_listener_thread(
+                listener,
+                tx,
+                Protocol::Statsd,
+                Arc::new(DaemonMetrics::new()),
+            )
+        });
+
+        let mut stream = TcpStream::connect(addr).expect("Could not connect");
+        write_payload(&mut stream, "foo:1|ms\nbar:2|c\n");
+        write_payload(&mut stream, "baz:3|g");
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            match rx.recv_timeout(Duration::from_millis(1000)) {
+                Ok(ProcessorCommand::InsertMetric(metric, kind, value, _)) => {
+                    received.push((metric, kind, value))
+                }
+                _ => panic!("Expected insert metric command"),
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                ("foo".to_string(), MetricKind::Timer, 1),
+                ("bar".to_string(), MetricKind::Counter, 2),
+                ("baz".to_string(), MetricKind::Gauge, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_closes_tcp_connection_with_oversized_payload() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind TCP listener");
+        let addr = listener
+            .local_addr()
+            .expect("Could not retrieve local addr");
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            tcp_listener_thread(
+                listener,
+                tx,
+                Protocol::Statsd,
+                Arc::new(DaemonMetrics::new()),
+            )
+        });
+
+        let mut stream = TcpStream::connect(addr).expect("Could not connect");
+        ((MAX_TCP_PAYLOAD_LEN + 1) as u32)
+            .encode(&mut stream)
+            .expect("Could not write length");
+        let mut buf = [0; 1];
+        let n = stream.read(&mut buf).expect("Could not read from stream");
+        assert_eq!(n, 0);
+        match rx.recv_timeout(Duration::from_millis(500)) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => panic!("Expected timeout error"),
+        }
+    }
+
+    #[test]
+    fn it_closes_idle_tcp_connections() {
+        let (addr, _rx) = start_tcp_listener(2, Duration::from_millis(100));
+        let mut stream = TcpStream::connect(addr).expect("Could not connect");
+        assert_closed(&mut stream);
+    }
+
+    #[test]
+    fn it_rejects_tcp_connections_over_limit() {
+        let (addr, rx) = start_tcp_listener(1, Duration::from_millis(5000));
+        let mut first = TcpStream::connect(addr).expect("Could not connect");
+        write_payload(&mut first, "foo:1|ms");
+        recv_inserts(&rx, 1);
+
+        let mut second = TcpStream::connect(addr).expect("Could not connect");
+        assert_closed(&mut second);
+
+        // The first connection is still served
+        write_payload(&mut first, "bar:2|ms");
+        assert_eq!(
+            recv_inserts(&rx, 1),
+            vec![("bar".to_string(), MetricKind::Timer, 2)]
+        );
+    }
+
+    fn start_tcp_listener(
+        max_connections: usize,
+        read_timeout: Duration,
+    ) -> (SocketAddr, Receiver<ProcessorCommand>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind TCP listener");
+        let addr = listener
+            .local_addr()
+            .expect("Could not retrieve local addr");
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            serve_tcp_connections(
+                listener,
+                tx,
+                Protocol::Statsd,
+                Arc::new(DaemonMetrics::new()),
+                max_connections,
+                read_timeout,
+            )
+        });
+        (addr, rx)
+    }
+
+    fn assert_closed(stream: &mut TcpStream) {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(2000)))
+            .expect("Could not set read timeout");
+        let mut buf = [0; 1];
+        let n = stream.read(&mut buf).expect("Could not read from stream");
+        assert_eq!(n, 0);
+    }
+
+    fn recv_inserts(rx: &Receiver<ProcessorCommand>, n: usize) -> Vec<(String, MetricKind, u32)> {
+        let mut received = Vec::new();
+        while received.len() < n {
+            match rx.recv_timeout(Duration::from_millis(1000)) {
+                Ok(ProcessorCommand::InsertMetric(metric, kind, value, _)) => {
+                    received.push((metric, kind, value))
+                }
+                Ok(_) => {}
+                Err(err) => panic!("Expected insert metric command: {:?}", err),
+            }
+        }
+        received
+    }
+
+    fn write_payload(stream: &mut TcpStream, payload: &str) {
+        (payload.len() as u32)
+            .encode(stream)
+            .expect("Could not write length");
+        stream
+            .write_all(payload.as_bytes())
+            .expect("Could not write payload");
+    }
+
     #[test]
     fn it_parses_insert_cmd() {
         assert_cmd("foo:12345|ms", "foo", 12345);
     }
 
     #[test]
-    fn it_ignores_sample_rate() {
+    fn it_parses_counter_cmd() {
+        assert_cmd_kind("foo:12|c", "foo", MetricKind::Counter, 12);
+    }
+
+    #[test]
+    fn it_parses_gauge_cmd() {
+        assert_cmd_kind("foo:12|g", "foo", MetricKind::Gauge, 12);
+    }
+
+    #[test]
+    fn it_rejects_unknown_metric_type() {
+        assert_invalid("foo:12|h");
+        assert_invalid("foo:12|s");
+    }
+
+    #[test]
+    fn it_parses_value_with_sample_rate() {
         assert_cmd("foo:12345|ms|@0.1", "foo", 12345);
     }
 
+    #[test]
+    fn it_parses_sample_rate() {
+        assert_sample_count(parse_metric_str("foo:1|ms|@0.1"), 10);
+        assert_sample_count(parse_metric_str("foo:1|c|@0.5"), 2);
+        assert_sample_count(parse_metric_str("foo:1|c|@1"), 1);
+        assert_sample_count(parse_metric_str("foo:1|c"), 1);
+    }
+
+    #[test]
+    fn it_rejects_sample_rate_out_of_range() {
+        assert_invalid("foo:1|ms|@0.0");
+        assert_invalid("foo:1|ms|@1.5");
+        assert_invalid("foo:1|ms|@0.00001");
+    }
+
     #[test]
     fn it_accepts_metric_name_with_numbers() {
         assert_cmd("foo123:12345|ms", "foo123", 12345);
@@ -148,6 +646,28 @@ mod tests {
         assert_cmd("FooBar:12345|ms", "FooBar", 12345);
     }
 
+    #[test]
+    fn it_accepts_metric_name_with_tags() {
+        assert_cmd(
+            "http.latency;region=us;endpoint=/login:12|ms",
+            "http.latency;endpoint=/login;region=us",
+            12,
+        );
+        assert_statsd(
+            "http.latency;region=us;endpoint=/login:12|ms",
+            "http.latency;endpoint=/login;region=us",
+            MetricKind::Timer,
+            12,
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_tags() {
+        assert_invalid("foo;region:12|ms");
+        assert_invalid("foo;region=us;region=eu:12|ms");
+        assert!(parse_statsd("foo;region=us;region=eu:12|ms").is_none());
+    }
+
     #[test]
     fn it_rejects_metric_name_starting_with_nonalpha() {
         assert_invalid(&"1foo:bar|ms");
@@ -156,6 +676,27 @@ mod tests {
         assert_invalid(&"_foo:bar|ms");
     }
 
+    #[test]
+    fn it_rejects_metric_name_with_emoji() {
+        assert_invalid("\u{1F600}:12|ms");
+        assert_invalid("foo\u{1F600}:12|ms");
+    }
+
+    #[cfg(not(feature = "unicode-names"))]
+    #[test]
+    fn it_rejects_unicode_metric_name() {
+        assert_invalid("レイテンシ:12|ms");
+        assert_invalid("زمن.الاستجابة:12|ms");
+    }
+
+    #[cfg(feature = "unicode-names")]
+    #[test]
+    fn it_accepts_unicode_metric_name() {
+        assert_cmd("レイテンシ:12|ms", "レイテンシ", 12);
+        assert_cmd("زمن.الاستجابة:12|ms", "زمن.الاستجابة", 12);
+        assert_cmd("レイテンシ;地域=us:12|ms", "レイテンシ;地域=us", 12);
+    }
+
     #[test]
     fn it_rejects_partial_match() {
         assert_invalid("&&&&||||||foo:123|ms||||||&&&&");
@@ -175,15 +716,110 @@ mod tests {
         assert_invalid(&"foo|123|ms");
     }
 
+    #[test]
+    fn it_parses_statsd_datagram() {
+        let data = "foo.bar:12.6|ms\n".as_bytes();
+        let (tx, rx) = channel();
+        handle_datagram(&data, &tx, Protocol::Statsd, &DaemonMetrics::new());
+        match rx.recv_timeout(Duration::from_millis(1000)) {
+            Ok(ProcessorCommand::InsertMetric(metric, kind, value, _)) => {
+                assert_eq!(metric, "foo.bar");
+                assert_eq!(kind, MetricKind::Timer);
+                assert_eq!(value, 13);
+            }
+            _ => panic!("Expected insert metric command"),
+        }
+    }
+
+    #[test]
+    fn it_parses_statsd_metric_types() {
+        assert_statsd("foo:12|ms", "foo", MetricKind::Timer, 12);
+        assert_statsd("foo:12|c", "foo", MetricKind::Counter, 12);
+        assert_statsd("foo:12|g", "foo", MetricKind::Gauge, 12);
+    }
+
+    #[test]
+    fn it_parses_statsd_fractional_values() {
+        assert_statsd("foo:12.4|ms", "foo", MetricKind::Timer, 12);
+        assert_statsd("foo:0.5|g", "foo", MetricKind::Gauge, 1);
+    }
+
+    #[test]
+    fn it_ignores_statsd_trailing_sections() {
+        assert_statsd("foo:12|c|@0.5", "foo", MetricKind::Counter, 12);
+        assert_statsd("foo:12|ms|#env:prod", "foo", MetricKind::Timer, 12);
+        assert_statsd("foo:12|g\r\n", "foo", MetricKind::Gauge, 12);
+    }
+
+    #[test]
+    fn it_parses_statsd_sample_rate() {
+        assert_sample_count(parse_statsd("foo:1|ms|@0.25|#env:prod"), 4);
+        assert_sample_count(parse_statsd("foo:1|c|#env:prod|@0.1"), 10);
+        assert!(parse_statsd("foo:1|c|@0").is_none());
+        assert!(parse_statsd("foo:1|c|@2").is_none());
+        assert!(parse_statsd("foo:1|c|@abc").is_none());
+    }
+
+    #[test]
+    fn it_skips_unsupported_statsd_types() {
+        assert!(parse_statsd("foo:12|h").is_none());
+        assert!(parse_statsd("foo:12|s").is_none());
+        assert!(parse_statsd("foo:12|d").is_none());
+    }
+
+    #[test]
+    fn it_skips_invalid_statsd_metrics() {
+        assert!(parse_statsd("").is_none());
+        assert!(parse_statsd("foo").is_none());
+        assert!(parse_statsd("foo:12").is_none());
+        assert!(parse_statsd("foo:bar|ms").is_none());
+        assert!(parse_statsd(":12|ms").is_none());
+        assert!(parse_statsd("1foo:12|ms").is_none());
+        assert!(parse_statsd("foo:-12|g").is_none());
+        assert!(parse_statsd("foo:+12|g").is_none());
+        assert!(parse_statsd("foo:inf|ms").is_none());
+        assert!(parse_statsd("foo:99999999999|ms").is_none());
+    }
+
+    fn assert_sample_count(cmd: Option<ProcessorCommand>, expected_count: u32) {
+        match cmd {
+            Some(ProcessorCommand::InsertMetric(_, _, _, sample_count)) => {
+                assert_eq!(sample_count, expected_count);
+            }
+            _ => panic!("Expected insert metric command"),
+        }
+    }
+
+    fn assert_statsd(s: &str, expected_metric: &str, expected_kind: MetricKind, expected_val: u32) {
+        match parse_statsd(s) {
+            Some(ProcessorCommand::InsertMetric(metric, kind, value, _)) => {
+                assert_eq!(metric, expected_metric);
+                assert_eq!(kind, expected_kind);
+                assert_eq!(value, expected_val);
+            }
+            _ => panic!("Expected insert metric command for '{}'", s),
+        }
+    }
+
     fn assert_cmd(s: &str, expected_metric: &str, expected_val: u32) {
+        assert_cmd_kind(s, expected_metric, MetricKind::Timer, expected_val);
+    }
+
+    fn assert_cmd_kind(
+        s: &str,
+        expected_metric: &str,
+        expected_kind: MetricKind,
+        expected_val: u32,
+    ) {
         println!("Checking that '{}' is a valid insert command", s);
         let cmd = parse_metric_str(s).expect("Could not parse cmd");
         match cmd {
-            ProcessorCommand::InsertMetric(metric, value) => {
+            ProcessorCommand::InsertMetric(metric, kind, value, _) => {
                 assert_eq!(metric, expected_metric);
+                assert_eq!(kind, expected_kind);
                 assert_eq!(value, expected_val);
             }
-            _ => assert!(false, "Expected insert metric command"),
+            _ => panic!("Expected insert metric command"),
         }
     }
 
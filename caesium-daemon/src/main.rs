@@ -1,32 +1,56 @@
 extern crate caesium_core;
 extern crate caesium_daemon;
 extern crate clap;
+extern crate ctrlc;
 extern crate stackdriver_logger;
 
 #[macro_use]
 extern crate log;
 
 use caesium_core::get_sketch_type;
-use caesium_daemon::run_daemon;
+use caesium_daemon::{install_signal_handler, run_daemon, Protocol, SenderConfig};
 use clap::{App, Arg};
 use std::env;
 use std::io;
+use std::net::{AddrParseError, SocketAddr};
 use std::num::ParseIntError;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 
 fn main() -> Result<(), Error> {
     init_logger();
     let args = parse_args()?;
     info!("Using sketch type {:?}", get_sketch_type());
     info!(
-        "Listening on {}, publishing to {}, window size is {}",
-        args.listen_addr, args.publish_addr, args.window_size
+        "Listening on {} using {:?} protocol, publishing to {}, window size is {}",
+        args.listen_addr, args.protocol, args.publish_addr, args.window_size
     );
-    run_daemon(args.listen_addr, args.publish_addr, args.window_size)?;
+    if let Some(ref addr) = args.tcp_listen_addr {
+        info!("Listening for TCP connections on {}", addr);
+    }
+    let shutdown = Arc::new(AtomicBool::new(false));
+    install_signal_handler(shutdown.clone())?;
+    run_daemon(
+        args.listen_addr,
+        args.tcp_listen_addr,
+        args.publish_addr,
+        args.auth_token,
+        args.window_size,
+        args.protocol,
+        args.wal_path,
+        args.metrics_interval,
+        args.metrics_addr,
+        args.health_addr,
+        args.sender_config,
+        shutdown,
+    )?;
+    info!("Shutdown complete");
     Ok(())
 }
 
 fn init_logger() {
-    if let Err(_) = env::var("RUST_LOG") {
+    if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "caesium=debug");
     }
     stackdriver_logger::init();
@@ -35,8 +59,16 @@ fn init_logger() {
 #[derive(Debug)]
 struct Args {
     listen_addr: String,
+    tcp_listen_addr: Option<String>,
     publish_addr: String,
+    auth_token: Option<String>,
     window_size: u64,
+    protocol: Protocol,
+    wal_path: Option<String>,
+    metrics_interval: Duration,
+    metrics_addr: Option<SocketAddr>,
+    health_addr: Option<SocketAddr>,
+    sender_config: SenderConfig,
 }
 
 fn parse_args() -> Result<Args, Error> {
@@ -48,12 +80,26 @@ fn parse_args() -> Result<Args, Error> {
                 .takes_value(true)
                 .help("IP address and port to receive metric data (defaults to 127.0.0.1:8001)"),
         )
+        .arg(
+            Arg::with_name("TCP_LISTEN_ADDR")
+                .long("tcp-listen-addr")
+                .takes_value(true)
+                .help("IP address and port to receive length-prefixed metric data over TCP (disabled by default)"),
+        )
         .arg(
             Arg::with_name("PUBLISH_ADDR")
                 .long("publish-addr")
                 .takes_value(true)
                 .help("IP address and port of backend server (defaults to 127.0.0.1:8000)"),
         )
+        .arg(
+            Arg::with_name("AUTH_TOKEN")
+                .long("auth-token")
+                .takes_value(true)
+                .env("CAESIUM_AUTH_TOKEN")
+                .hide_env_values(true)
+                .help("Shared secret to send to the backend server, if it requires one"),
+        )
         .arg(
             Arg::with_name("WINDOW_SIZE")
                 .long("window-size")
@@ -61,6 +107,55 @@ fn parse_args() -> Result<Args, Error> {
                 .takes_value(true)
                 .help("Size of aggregation windows in seconds (defaults to 10)"),
         )
+        .arg(
+            Arg::with_name("PROTOCOL")
+                .long("protocol")
+                .takes_value(true)
+                .possible_values(&["caesium", "statsd"])
+                .help("Format of incoming metric data (defaults to caesium)"),
+        )
+        .arg(
+            Arg::with_name("WAL_PATH")
+                .long("wal-path")
+                .takes_value(true)
+                .help("Directory for a write-ahead log of buffered metrics, replayed on startup (disabled by default)"),
+        )
+        .arg(
+            Arg::with_name("METRICS_INTERVAL_SECS")
+                .long("metrics-interval-secs")
+                .takes_value(true)
+                .help("Interval in seconds between logged summaries of daemon metrics (defaults to 60)"),
+        )
+        .arg(
+            Arg::with_name("METRICS_ADDR")
+                .long("metrics-addr")
+                .takes_value(true)
+                .help("IP address and port to serve daemon metrics at /metrics over HTTP (disabled by default)"),
+        )
+        .arg(
+            Arg::with_name("HEALTH_ADDR")
+                .long("health-addr")
+                .takes_value(true)
+                .help("IP address and port to serve health checks at /healthz over HTTP (disabled by default)"),
+        )
+        .arg(
+            Arg::with_name("SEND_MAX_RETRIES")
+                .long("send-max-retries")
+                .takes_value(true)
+                .help("Number of times to retry a failed send to the backend before opening the circuit (defaults to 3)"),
+        )
+        .arg(
+            Arg::with_name("SEND_RETRY_DELAY_MS")
+                .long("send-retry-delay-ms")
+                .takes_value(true)
+                .help("Delay in milliseconds before the first retry, doubled for each retry after that (defaults to 100)"),
+        )
+        .arg(
+            Arg::with_name("SEND_MAX_DELAY_MS")
+                .long("send-max-delay-ms")
+                .takes_value(true)
+                .help("Maximum delay in milliseconds between retries (defaults to 30000)"),
+        )
         .get_matches();
 
     let listen_addr = matches
@@ -68,11 +163,15 @@ fn parse_args() -> Result<Args, Error> {
         .unwrap_or("127.0.0.1:8001")
         .to_string();
 
+    let tcp_listen_addr = matches.value_of("TCP_LISTEN_ADDR").map(|s| s.to_string());
+
     let publish_addr = matches
         .value_of("PUBLISH_ADDR")
         .unwrap_or("127.0.0.1:8001")
         .to_string();
 
+    let auth_token = matches.value_of("AUTH_TOKEN").map(|s| s.to_string());
+
     let window_size = matches
         .value_of("WINDOW_SIZE")
         .unwrap_or("10")
@@ -82,17 +181,76 @@ fn parse_args() -> Result<Args, Error> {
         return Err(Error::ArgError("Window size must be >= 1"));
     }
 
+    let protocol = match matches.value_of("PROTOCOL").unwrap_or("caesium") {
+        "statsd" => Protocol::Statsd,
+        "caesium" => Protocol::Caesium,
+        _ => return Err(Error::ArgError("Protocol must be either caesium or statsd")),
+    };
+
+    let wal_path = matches.value_of("WAL_PATH").map(|s| s.to_string());
+
+    let metrics_interval_secs = matches
+        .value_of("METRICS_INTERVAL_SECS")
+        .unwrap_or("60")
+        .parse::<u64>()?;
+
+    if metrics_interval_secs < 1 {
+        return Err(Error::ArgError("Metrics interval must be >= 1"));
+    }
+
+    let metrics_addr = match matches.value_of("METRICS_ADDR") {
+        Some(s) => Some(s.parse::<SocketAddr>()?),
+        None => None,
+    };
+
+    let health_addr = match matches.value_of("HEALTH_ADDR") {
+        Some(s) => Some(s.parse::<SocketAddr>()?),
+        None => None,
+    };
+
+    let default_sender_config = SenderConfig::default();
+    let sender_config = SenderConfig {
+        max_retries: match matches.value_of("SEND_MAX_RETRIES") {
+            Some(s) => s.parse::<usize>()?,
+            None => default_sender_config.max_retries,
+        },
+        retry_delay_ms: match matches.value_of("SEND_RETRY_DELAY_MS") {
+            Some(s) => s.parse::<u64>()?,
+            None => default_sender_config.retry_delay_ms,
+        },
+        max_delay_ms: match matches.value_of("SEND_MAX_DELAY_MS") {
+            Some(s) => s.parse::<u64>()?,
+            None => default_sender_config.max_delay_ms,
+        },
+    };
+
+    if sender_config.retry_delay_ms > sender_config.max_delay_ms {
+        return Err(Error::ArgError(
+            "Send retry delay must be <= the maximum send delay",
+        ));
+    }
+
     Ok(Args {
         listen_addr,
+        tcp_listen_addr,
         publish_addr,
+        auth_token,
         window_size,
+        protocol,
+        wal_path,
+        metrics_interval: Duration::from_secs(metrics_interval_secs),
+        metrics_addr,
+        health_addr,
+        sender_config,
     })
 }
 
 #[derive(Debug)]
 enum Error {
     ParseIntError(ParseIntError),
+    AddrParseError(AddrParseError),
     IOError(io::Error),
+    SignalError(ctrlc::Error),
     ArgError(&'static str),
 }
 
@@ -102,8 +260,20 @@ impl From<ParseIntError> for Error {
     }
 }
 
+impl From<AddrParseError> for Error {
+    fn from(err: AddrParseError) -> Error {
+        Error::AddrParseError(err)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::IOError(err)
     }
 }
+
+impl From<ctrlc::Error> for Error {
+    fn from(err: ctrlc::Error) -> Error {
+        Error::SignalError(err)
+    }
+}
@@ -0,0 +1,280 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const SHUTDOWN_CHECK_MS: u64 = 500;
+
+// Counters describing the daemon's own behavior, shared by the listener,
+// processor, and sender threads. Counters are reset each time a summary
+// is reported; `active_metrics` is a gauge and is never reset.
+pub struct DaemonMetrics {
+    messages_received: AtomicU64,
+    messages_dropped: AtomicU64,
+    windows_flushed: AtomicU64,
+    circuit_open_count: AtomicU64,
+    active_metrics: AtomicUsize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MetricsSnapshot {
+    pub messages_received: u64,
+    pub messages_dropped: u64,
+    pub windows_flushed: u64,
+    pub circuit_open_count: u64,
+    pub active_metrics: usize,
+}
+
+impl DaemonMetrics {
+    pub fn new() -> DaemonMetrics {
+        DaemonMetrics {
+            messages_received: AtomicU64::new(0),
+            messages_dropped: AtomicU64::new(0),
+            windows_flushed: AtomicU64::new(0),
+            circuit_open_count: AtomicU64::new(0),
+            active_metrics: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self, count: u64) {
+        self.messages_dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_window_flushed(&self) {
+        self.windows_flushed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_circuit_open(&self) {
+        self.circuit_open_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_active_metrics(&self, count: usize) {
+        self.active_metrics.store(count, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+            windows_flushed: self.windows_flushed.load(Ordering::Relaxed),
+            circuit_open_count: self.circuit_open_count.load(Ordering::Relaxed),
+            active_metrics: self.active_metrics.load(Ordering::Relaxed),
+        }
+    }
+
+    // Returns the current values and resets the counters to zero.
+    // Each counter is swapped atomically, so no increment is lost.
+    pub fn take_snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            messages_received: self.messages_received.swap(0, Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.swap(0, Ordering::Relaxed),
+            windows_flushed: self.windows_flushed.swap(0, Ordering::Relaxed),
+            circuit_open_count: self.circuit_open_count.swap(0, Ordering::Relaxed),
+            active_metrics: self.active_metrics.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    pub fn summary(&self) -> String {
+        format!(
+            "messages_received={} messages_dropped={} windows_flushed={} circuit_open_count={} active_metrics={}",
+            self.messages_received,
+            self.messages_dropped,
+            self.windows_flushed,
+            self.circuit_open_count,
+            self.active_metrics
+        )
+    }
+
+    // Renders the snapshot in the Prometheus text exposition format.
+    // Counters cover the time since the last summary was logged, so they are exposed as gauges.
+    pub fn render(&self) -> String {
+        let values = [
+            ("messages_received", self.messages_received),
+            ("messages_dropped", self.messages_dropped),
+            ("windows_flushed", self.windows_flushed),
+            ("circuit_open_count", self.circuit_open_count),
+            ("active_metrics", self.active_metrics as u64),
+        ];
+        let mut out = String::new();
+        for &(name, value) in values.iter() {
+            out.push_str(&format!(
+                "# TYPE caesium_daemon_{} gauge\ncaesium_daemon_{} {}\n",
+                name, name, value
+            ));
+        }
+        out
+    }
+}
+
+// Logs a summary of the counters every `interval`, then resets them
+pub fn metrics_reporter_thread(
+    metrics: Arc<DaemonMetrics>,
+    interval: Duration,
+    shutdown: Arc<AtomicBool>,
+) {
+    let mut last_report = Instant::now();
+    while !shutdown.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(SHUTDOWN_CHECK_MS));
+        if last_report.elapsed() >= interval {
+            info!("Daemon metrics: {}", metrics.take_snapshot().summary());
+            last_report = Instant::now();
+        }
+    }
+}
+
+// Serves the current counters at `GET /metrics`
+pub struct MetricsServer {
+    server: Server,
+    metrics: Arc<DaemonMetrics>,
+}
+
+impl MetricsServer {
+    pub fn new(addr: &SocketAddr, metrics: Arc<DaemonMetrics>) -> Result<MetricsServer, io::Error> {
+        let server = Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(MetricsServer { server, metrics })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr()
+    }
+
+    pub fn run(self) {
+        info!("Serving daemon metrics on {}", self.local_addr());
+        for request in self.server.incoming_requests() {
+            self.handle_request(request);
+        }
+    }
+
+    fn handle_request(&self, request: Request) {
+        let result = if request.url() != METRICS_PATH {
+            request.respond(Response::empty(404))
+        } else if *request.method() != Method::Get {
+            request.respond(Response::empty(405))
+        } else {
+            let header = Header::from_bytes(&b"Content-Type"[..], CONTENT_TYPE.as_bytes())
+                .expect("Could not construct content type header");
+            let body = self.metrics.snapshot().render();
+            request.respond(Response::from_string(body).with_header(header))
+        };
+        if let Err(err) = result {
+            error!("Could not send response to metrics client: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn it_counts_concurrent_updates() {
+        let metrics = Arc::new(DaemonMetrics::new());
+        let threads: Vec<thread::JoinHandle<()>> = (0..8)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        metrics.record_received();
+                        metrics.record_dropped(2);
+                        metrics.record_window_flushed();
+                        metrics.record_circuit_open();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().expect("Could not join thread");
+        }
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_received, 8000);
+        assert_eq!(snapshot.messages_dropped, 16000);
+        assert_eq!(snapshot.windows_flushed, 8000);
+        assert_eq!(snapshot.circuit_open_count, 8000);
+    }
+
+    #[test]
+    fn it_does_not_lose_updates_when_reset_concurrently() {
+        let metrics = Arc::new(DaemonMetrics::new());
+        let writer_metrics = metrics.clone();
+        let writer = thread::spawn(move || {
+            for _ in 0..100_000 {
+                writer_metrics.record_received();
+            }
+        });
+        let mut total = 0;
+        for _ in 0..100 {
+            total += metrics.take_snapshot().messages_received;
+        }
+        writer.join().expect("Could not join thread");
+        total += metrics.take_snapshot().messages_received;
+        assert_eq!(total, 100_000);
+    }
+
+    #[test]
+    fn it_resets_counters_but_not_active_metrics() {
+        let metrics = DaemonMetrics::new();
+        metrics.record_received();
+        metrics.record_dropped(3);
+        metrics.set_active_metrics(5);
+        let snapshot = metrics.take_snapshot();
+        assert_eq!(snapshot.messages_received, 1);
+        assert_eq!(snapshot.messages_dropped, 3);
+        assert_eq!(
+            metrics.snapshot(),
+            MetricsSnapshot {
+                messages_received: 0,
+                messages_dropped: 0,
+                windows_flushed: 0,
+                circuit_open_count: 0,
+                active_metrics: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn it_formats_summary() {
+        let metrics = DaemonMetrics::new();
+        metrics.record_received();
+        metrics.record_window_flushed();
+        metrics.set_active_metrics(2);
+        assert_eq!(
+            metrics.snapshot().summary(),
+            "messages_received=1 messages_dropped=0 windows_flushed=1 circuit_open_count=0 active_metrics=2"
+        );
+    }
+
+    #[test]
+    fn it_serves_metrics_over_http() {
+        let metrics = Arc::new(DaemonMetrics::new());
+        metrics.record_received();
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server = MetricsServer::new(&addr, metrics).expect("Could not start metrics server");
+        let addr = server.local_addr();
+        thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).expect("Could not connect to metrics server");
+        stream
+            .write_all(b"GET /metrics HTTP/1.0\r\n\r\n")
+            .expect("Could not send request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .expect("Could not read response");
+        assert!(response.contains("200 OK"));
+        assert!(response.contains("caesium_daemon_messages_received 1\n"));
+        assert!(response.contains("caesium_daemon_active_metrics 0\n"));
+    }
+}
@@ -1,22 +1,41 @@
-use caesium_core::protocol::messages::InsertMessage;
+use caesium_core::protocol::messages::{InsertMessage, MetricKind};
 use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::clock::{Clock, SystemClock};
 use caesium_core::time::timestamp::TimeStamp;
 use caesium_core::time::window::TimeWindow;
 use circuit::CircuitState;
+use metrics::DaemonMetrics;
 use slab::Slab;
+use std::cmp::min;
 use std::collections::HashMap;
+use std::mem;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, RwLock};
+use wal::{Wal, WalEntry};
 
 pub fn processor_thread(
     input: Receiver<ProcessorCommand>,
     output: Sender<InsertMessage>,
     circuit_lock: Arc<RwLock<CircuitState>>,
+    wal: Option<Wal>,
+    metrics: Arc<DaemonMetrics>,
 ) {
-    let mut p = Processor::new(&output, &circuit_lock);
+    let clock = SystemClock::new();
+    let mut p = Processor::new(&output, &circuit_lock, &metrics);
+    if let Some(wal) = wal {
+        p.recover_from_wal(wal);
+    }
     loop {
         match input.recv() {
-            Ok(cmd) => p.process_cmd(cmd),
+            Ok(ProcessorCommand::Shutdown(window)) => {
+                p.flush(window);
+                info!("Flushed buffered metrics, stopping processing thread");
+                break;
+            }
+            Ok(cmd) => {
+                p.write_wal_entry(&cmd, clock.now());
+                p.process_cmd(cmd)
+            }
             Err(_) => {
                 info!("Channel closed, stopping processing thread");
                 break;
@@ -27,8 +46,13 @@ pub fn processor_thread(
 
 #[derive(Debug)]
 pub enum ProcessorCommand {
-    InsertMetric(String, u32),
+    // Metric name, kind, value, and the number of observations the value
+    // represents (greater than one for sampled metrics)
+    InsertMetric(String, MetricKind, u32, u32),
     CloseWindow(TimeWindow),
+    // Flush every buffered metric for the current window, even if the circuit is open,
+    // then stop processing.
+    Shutdown(TimeWindow),
 }
 
 struct Processor<'a> {
@@ -36,70 +60,145 @@ struct Processor<'a> {
     metric_name_idx: HashMap<String, usize>, // metric name to slab ID
     output: &'a Sender<InsertMessage>,
     circuit_lock: &'a Arc<RwLock<CircuitState>>,
+    metrics: &'a DaemonMetrics,
     window_start: Option<TimeStamp>,
+    wal: Option<Wal>,
 }
 
 impl<'a> Processor<'a> {
     pub fn new(
         output: &'a Sender<InsertMessage>,
         circuit_lock: &'a Arc<RwLock<CircuitState>>,
+        metrics: &'a DaemonMetrics,
     ) -> Processor<'a> {
         Processor {
             metric_name_idx: HashMap::new(),
             metric_states: Slab::new(),
             output,
             circuit_lock,
+            metrics,
             window_start: None,
+            wal: None,
+        }
+    }
+
+    // Restores metrics logged before the daemon last stopped, then logs
+    // subsequent inserts to the same WAL. The next flushed window starts
+    // at the earliest replayed insert.
+    pub fn recover_from_wal(&mut self, wal: Wal) {
+        match wal.replay_wal() {
+            Ok(entries) => {
+                info!("Replaying {} metrics from WAL", entries.len());
+                for entry in entries {
+                    let window_start = self.window_start.map_or(entry.ts, |ts| min(ts, entry.ts));
+                    self.window_start = Some(window_start);
+                    self.process_cmd(ProcessorCommand::InsertMetric(
+                        entry.metric,
+                        entry.kind,
+                        entry.value,
+                        entry.sample_count,
+                    ));
+                }
+            }
+            Err(err) => error!("Could not replay WAL: {:?}", err),
+        }
+        self.wal = Some(wal);
+    }
+
+    pub fn write_wal_entry(&mut self, cmd: &ProcessorCommand, ts: TimeStamp) {
+        if let (Some(wal), ProcessorCommand::InsertMetric(metric, kind, value, sample_count)) =
+            (self.wal.as_mut(), cmd)
+        {
+            let entry = WalEntry {
+                ts,
+                metric: metric.to_string(),
+                kind: *kind,
+                value: *value,
+                sample_count: *sample_count,
+            };
+            if let Err(err) = wal.write_wal_entry(&entry) {
+                error!("Could not write WAL entry: {:?}", err);
+            }
         }
     }
 
     pub fn process_cmd(&mut self, cmd: ProcessorCommand) {
         trace!("Processing {:?}", cmd);
         match cmd {
-            ProcessorCommand::InsertMetric(metric_name, value) => {
+            ProcessorCommand::InsertMetric(metric_name, kind, value, sample_count) => {
                 match self.metric_name_idx.get(&metric_name) {
-                    None => self.insert(&metric_name, value),
-                    Some(&metric_id) => self.update(metric_id, value),
+                    None => self.insert(&metric_name, kind, value, sample_count),
+                    Some(&metric_id) => self.update(metric_id, kind, value, sample_count),
                 }
             }
             ProcessorCommand::CloseWindow(window) => self.process_close_cmd(window),
+            ProcessorCommand::Shutdown(window) => self.flush(window),
         }
     }
 
-    fn insert(&mut self, metric_name: &str, value: u32) {
-        let metric_state = MetricState::new(metric_name, value);
+    fn insert(&mut self, metric_name: &str, kind: MetricKind, value: u32, sample_count: u32) {
+        let mut metric_state = MetricState::new(metric_name, kind);
+        metric_state.update(value, sample_count);
         let metric_id = self.metric_states.insert(metric_state);
         self.metric_name_idx
             .insert(metric_name.to_string(), metric_id);
+        self.metrics.set_active_metrics(self.metric_name_idx.len());
     }
 
-    fn update(&mut self, metric_id: usize, value: u32) {
+    fn update(&mut self, metric_id: usize, kind: MetricKind, value: u32, sample_count: u32) {
         let metric_state = self
             .metric_states
             .get_mut(metric_id)
             .expect("Could not retrieve metric state from slab");
-        metric_state.sketch.insert(value);
+        if metric_state.kind() == kind {
+            metric_state.update(value, sample_count);
+        } else {
+            warn!(
+                "Ignoring {:?} value for metric {} with type {:?}",
+                kind,
+                metric_state.metric_name,
+                metric_state.kind()
+            );
+        }
     }
 
     fn process_close_cmd(&mut self, window: TimeWindow) {
         if self.is_circuit_closed() {
-            let window_start = self.window_start.unwrap_or(window.start());
-            let window = TimeWindow::new(window_start, window.end());
-            for &metric_id in self.metric_name_idx.values() {
-                let state = self.metric_states.remove(metric_id);
-                let msg = InsertMessage {
-                    metric: state.metric_name,
-                    window,
-                    sketch: state.sketch,
-                };
-                self.output
-                    .send(msg)
-                    .expect("Could not output message from processor");
-            }
-            self.window_start = Some(window.end());
-            self.metric_name_idx.clear();
+            self.flush(window);
         } else {
             self.window_start = self.window_start.or(Some(window.start()));
+            for (_, state) in self.metric_states.iter_mut() {
+                state.carry_over();
+            }
+        }
+    }
+
+    fn flush(&mut self, window: TimeWindow) {
+        let window_start = self.window_start.unwrap_or(window.start());
+        let window = match TimeWindow::try_new(window_start, window.end()) {
+            Ok(w) => w,
+            Err(err) => {
+                warn!(
+                    "Could not extend window {} to start at {}: {:?}",
+                    window, window_start, err
+                );
+                window
+            }
+        };
+        for &metric_id in self.metric_name_idx.values() {
+            let msg = self.metric_states.remove(metric_id).into_message(window);
+            self.output
+                .send(msg)
+                .expect("Could not output message from processor");
+        }
+        self.window_start = Some(window.end());
+        self.metric_name_idx.clear();
+        self.metrics.set_active_metrics(0);
+        self.metrics.record_window_flushed();
+        if let Some(ref mut wal) = self.wal {
+            if let Err(err) = wal.clear() {
+                error!("Could not clear WAL after flush: {:?}", err);
+            }
         }
     }
 
@@ -117,34 +216,120 @@ impl<'a> Processor<'a> {
 
 struct MetricState {
     metric_name: String,
-    sketch: WritableSketch,
+    aggregate: Aggregate,
+
+    // Timer values from windows that closed while the circuit was open
+    carried: WritableSketch,
 }
 
 impl MetricState {
-    fn new(metric_name: &str, value: u32) -> MetricState {
-        let mut sketch = WritableSketch::new();
-        sketch.insert(value);
+    fn new(metric_name: &str, kind: MetricKind) -> MetricState {
         MetricState {
             metric_name: metric_name.to_string(),
+            aggregate: Aggregate::new(kind),
+            carried: WritableSketch::new(),
+        }
+    }
+
+    // Merges the closed window's timer values into the values carried over from
+    // earlier windows, so the next flush sends all of them in one sketch.
+    // Counters and gauges already span every window since the last flush.
+    // The daemon only inserts integers, so its sketches always share a scale and merge.
+    fn carry_over(&mut self) {
+        if let Aggregate::Timer(ref mut sketch) = self.aggregate {
+            let window_sketch = mem::replace(&mut **sketch, WritableSketch::new());
+            self.carried
+                .merge_from(window_sketch)
+                .expect("Could not merge carried over sketch");
+        }
+    }
+
+    fn into_message(self, window: TimeWindow) -> InsertMessage {
+        let kind = self.kind();
+        let mut sketch = self.aggregate.into_sketch();
+        sketch
+            .merge_from(self.carried)
+            .expect("Could not merge carried over sketch");
+        InsertMessage {
+            metric: self.metric_name,
+            kind,
+            window,
             sketch,
         }
     }
+
+    fn kind(&self) -> MetricKind {
+        match self.aggregate {
+            Aggregate::Timer(_) => MetricKind::Timer,
+            Aggregate::Counter(_) => MetricKind::Counter,
+            Aggregate::Gauge(_) => MetricKind::Gauge,
+        }
+    }
+
+    fn update(&mut self, value: u32, sample_count: u32) {
+        match self.aggregate {
+            Aggregate::Timer(ref mut sketch) => {
+                if sample_count > 0 {
+                    sketch.insert_weighted(value, sample_count as usize);
+                }
+            }
+            Aggregate::Counter(ref mut sum) => {
+                *sum = sum.saturating_add(value.saturating_mul(sample_count))
+            }
+            Aggregate::Gauge(ref mut last) => *last = value,
+        }
+    }
+}
+
+// Timers keep every value in a quantile sketch, counters are summed,
+// and gauges keep only the last value written in the window.
+// Timer sketches are boxed, so counters and gauges don't pay for a sketch's size.
+enum Aggregate {
+    Timer(Box<WritableSketch>),
+    Counter(u32),
+    Gauge(u32),
+}
+
+impl Aggregate {
+    fn new(kind: MetricKind) -> Aggregate {
+        match kind {
+            MetricKind::Timer => Aggregate::Timer(Box::new(WritableSketch::new())),
+            MetricKind::Counter => Aggregate::Counter(0),
+            MetricKind::Gauge => Aggregate::Gauge(0),
+        }
+    }
+
+    fn into_sketch(self) -> WritableSketch {
+        match self {
+            Aggregate::Timer(sketch) => *sketch,
+            Aggregate::Counter(value) | Aggregate::Gauge(value) => {
+                let mut sketch = WritableSketch::new();
+                sketch.insert(value);
+                sketch
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
     use std::sync::mpsc::channel;
+    use std::thread;
 
     #[test]
     fn it_inserts_new_metrics() {
         let commands = vec![
             (
-                ProcessorCommand::InsertMetric("foo".to_string(), 1),
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
                 CircuitState::Closed,
             ),
             (
-                ProcessorCommand::InsertMetric("bar".to_string(), 2),
+                ProcessorCommand::InsertMetric("bar".to_string(), MetricKind::Timer, 2, 1),
                 CircuitState::Closed,
             ),
             (
@@ -159,15 +344,35 @@ mod tests {
         assert_processor(commands, expected);
     }
 
+    #[test]
+    fn it_keeps_closed_window_if_previous_flush_ended_after_start() {
+        let commands = vec![
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+        ];
+        let expected = vec![("foo".to_string(), TimeWindow::new(30, 60), 1)];
+        assert_processor(commands, expected);
+    }
+
     #[test]
     fn it_updates_existing_metrics() {
         let commands = vec![
             (
-                ProcessorCommand::InsertMetric("foo".to_string(), 1),
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
                 CircuitState::Closed,
             ),
             (
-                ProcessorCommand::InsertMetric("foo".to_string(), 2),
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 2, 1),
                 CircuitState::Closed,
             ),
             (
@@ -183,11 +388,11 @@ mod tests {
     fn it_flushes_metrics_on_window_close() {
         let commands = vec![
             (
-                ProcessorCommand::InsertMetric("foo".to_string(), 1),
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
                 CircuitState::Closed,
             ),
             (
-                ProcessorCommand::InsertMetric("bar".to_string(), 2),
+                ProcessorCommand::InsertMetric("bar".to_string(), MetricKind::Timer, 2, 1),
                 CircuitState::Closed,
             ),
             (
@@ -195,11 +400,11 @@ mod tests {
                 CircuitState::Closed,
             ),
             (
-                ProcessorCommand::InsertMetric("baz".to_string(), 3),
+                ProcessorCommand::InsertMetric("baz".to_string(), MetricKind::Timer, 3, 1),
                 CircuitState::Closed,
             ),
             (
-                ProcessorCommand::InsertMetric("bat".to_string(), 4),
+                ProcessorCommand::InsertMetric("bat".to_string(), MetricKind::Timer, 4, 1),
                 CircuitState::Closed,
             ),
             (
@@ -220,15 +425,47 @@ mod tests {
         assert_processor(commands, expected);
     }
 
+    #[test]
+    fn it_records_active_metrics_and_flushed_windows() {
+        let (tx, _rx) = channel();
+        let metrics = DaemonMetrics::new();
+        let circuit_lock = Arc::new(RwLock::new(CircuitState::Closed));
+        let mut p = Processor::new(&tx, &circuit_lock, &metrics);
+        p.process_cmd(ProcessorCommand::InsertMetric(
+            "foo".to_string(),
+            MetricKind::Timer,
+            1,
+            1,
+        ));
+        p.process_cmd(ProcessorCommand::InsertMetric(
+            "foo".to_string(),
+            MetricKind::Timer,
+            2,
+            1,
+        ));
+        p.process_cmd(ProcessorCommand::InsertMetric(
+            "bar".to_string(),
+            MetricKind::Timer,
+            3,
+            1,
+        ));
+        assert_eq!(metrics.snapshot().active_metrics, 2);
+        assert_eq!(metrics.snapshot().windows_flushed, 0);
+
+        p.process_cmd(ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)));
+        assert_eq!(metrics.snapshot().active_metrics, 0);
+        assert_eq!(metrics.snapshot().windows_flushed, 1);
+    }
+
     #[test]
     fn it_does_not_flush_if_circuit_open() {
         let commands = vec![
             (
-                ProcessorCommand::InsertMetric("foo".to_string(), 1),
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
                 CircuitState::Open,
             ),
             (
-                ProcessorCommand::InsertMetric("bar".to_string(), 2),
+                ProcessorCommand::InsertMetric("bar".to_string(), MetricKind::Timer, 2, 1),
                 CircuitState::Open,
             ),
             (
@@ -236,11 +473,11 @@ mod tests {
                 CircuitState::Open,
             ),
             (
-                ProcessorCommand::InsertMetric("baz".to_string(), 3),
+                ProcessorCommand::InsertMetric("baz".to_string(), MetricKind::Timer, 3, 1),
                 CircuitState::Open,
             ),
             (
-                ProcessorCommand::InsertMetric("bat".to_string(), 4),
+                ProcessorCommand::InsertMetric("bat".to_string(), MetricKind::Timer, 4, 1),
                 CircuitState::Open,
             ),
             (
@@ -256,15 +493,63 @@ mod tests {
         assert_processor(commands, expected);
     }
 
+    #[test]
+    fn it_flushes_buffered_metrics_on_shutdown() {
+        let (cmd_tx, cmd_rx) = channel();
+        let (out_tx, out_rx) = channel();
+        let circuit_lock = Arc::new(RwLock::new(CircuitState::Open));
+        let t = thread::spawn(move || {
+            processor_thread(
+                cmd_rx,
+                out_tx,
+                circuit_lock,
+                None,
+                Arc::new(DaemonMetrics::new()),
+            )
+        });
+        cmd_tx
+            .send(ProcessorCommand::InsertMetric(
+                "foo".to_string(),
+                MetricKind::Timer,
+                1,
+                1,
+            ))
+            .unwrap();
+        cmd_tx
+            .send(ProcessorCommand::InsertMetric(
+                "bar".to_string(),
+                MetricKind::Counter,
+                2,
+                1,
+            ))
+            .unwrap();
+        cmd_tx
+            .send(ProcessorCommand::Shutdown(TimeWindow::new(30, 60)))
+            .unwrap();
+        t.join().expect("Could not join processor thread");
+        let mut output: Vec<(String, TimeWindow, usize)> = out_rx
+            .iter()
+            .map(|msg| (msg.metric.to_string(), msg.window, msg.sketch.count()))
+            .collect();
+        output.sort_unstable();
+        assert_eq!(
+            output,
+            vec![
+                ("bar".to_string(), TimeWindow::new(30, 60), 1),
+                ("foo".to_string(), TimeWindow::new(30, 60), 1),
+            ]
+        );
+    }
+
     #[test]
     fn it_flushes_when_circuit_closes() {
         let commands = vec![
             (
-                ProcessorCommand::InsertMetric("foo".to_string(), 1),
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
                 CircuitState::Open,
             ),
             (
-                ProcessorCommand::InsertMetric("bar".to_string(), 2),
+                ProcessorCommand::InsertMetric("bar".to_string(), MetricKind::Timer, 2, 1),
                 CircuitState::Open,
             ),
             (
@@ -272,11 +557,11 @@ mod tests {
                 CircuitState::Open,
             ),
             (
-                ProcessorCommand::InsertMetric("baz".to_string(), 3),
+                ProcessorCommand::InsertMetric("baz".to_string(), MetricKind::Timer, 3, 1),
                 CircuitState::Open,
             ),
             (
-                ProcessorCommand::InsertMetric("bat".to_string(), 4),
+                ProcessorCommand::InsertMetric("bat".to_string(), MetricKind::Timer, 4, 1),
                 CircuitState::Open,
             ),
             (
@@ -297,14 +582,266 @@ mod tests {
         assert_processor(commands, expected);
     }
 
+    #[test]
+    fn it_sums_counters() {
+        let commands = vec![
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Counter, 1, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Counter, 2, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Counter, 3, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+        ];
+        let expected = vec![("foo".to_string(), MetricKind::Counter, 6)];
+        assert_processor_values(commands, expected);
+    }
+
+    #[test]
+    fn it_keeps_last_gauge_value() {
+        let commands = vec![
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Gauge, 5, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Gauge, 2, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+        ];
+        let expected = vec![("foo".to_string(), MetricKind::Gauge, 2)];
+        assert_processor_values(commands, expected);
+    }
+
+    #[test]
+    fn it_sketches_timers() {
+        let commands = vec![
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 2, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 3, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+        ];
+        let expected = vec![("foo".to_string(), MetricKind::Timer, 2)];
+        assert_processor_values(commands, expected);
+    }
+
+    #[test]
+    fn it_merges_timer_values_across_windows_while_circuit_open() {
+        let commands = vec![
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 1),
+                CircuitState::Open,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Open,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 2, 1),
+                CircuitState::Open,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(60, 90)),
+                CircuitState::Open,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 3, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(90, 120)),
+                CircuitState::Closed,
+            ),
+        ];
+        assert_processor(
+            commands,
+            vec![("foo".to_string(), TimeWindow::new(30, 120), 3)],
+        );
+    }
+
+    #[test]
+    fn it_inserts_sampled_timer_values() {
+        let commands = vec![
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 1, 10),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 2, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+        ];
+        let expected = vec![("foo".to_string(), TimeWindow::new(30, 60), 11)];
+        assert_processor(commands, expected);
+    }
+
+    #[test]
+    fn it_scales_sampled_counters() {
+        let commands = vec![
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Counter, 2, 10),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Counter, 1, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+        ];
+        let expected = vec![("foo".to_string(), MetricKind::Counter, 21)];
+        assert_processor_values(commands, expected);
+    }
+
+    #[test]
+    fn it_ignores_values_with_mismatched_kind() {
+        let commands = vec![
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Counter, 1, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Gauge, 7, 1),
+                CircuitState::Closed,
+            ),
+            (
+                ProcessorCommand::CloseWindow(TimeWindow::new(30, 60)),
+                CircuitState::Closed,
+            ),
+        ];
+        let expected = vec![("foo".to_string(), MetricKind::Counter, 1)];
+        assert_processor_values(commands, expected);
+    }
+
+    #[test]
+    fn it_recovers_metrics_from_wal() {
+        let dir = test_wal_dir("recover");
+        {
+            // Simulate a crash: entries are logged, but the window is never flushed
+            let mut wal = Wal::open(&dir).expect("Could not open WAL");
+            for (ts, metric, value) in [(35, "foo", 1), (32, "bar", 2), (38, "foo", 3)] {
+                let entry = WalEntry {
+                    ts,
+                    metric: metric.to_string(),
+                    kind: MetricKind::Timer,
+                    value,
+                    sample_count: 1,
+                };
+                wal.write_wal_entry(&entry)
+                    .expect("Could not write WAL entry");
+            }
+        }
+
+        let (tx, rx) = channel();
+        let metrics = DaemonMetrics::new();
+        let circuit_lock = Arc::new(RwLock::new(CircuitState::Closed));
+        {
+            let mut p = Processor::new(&tx, &circuit_lock, &metrics);
+            p.recover_from_wal(Wal::open(&dir).expect("Could not reopen WAL"));
+            let cmd = ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Timer, 4, 1);
+            p.write_wal_entry(&cmd, 50);
+            p.process_cmd(cmd);
+            p.process_cmd(ProcessorCommand::CloseWindow(TimeWindow::new(50, 60)));
+        }
+        drop(tx);
+        let mut output: Vec<(String, TimeWindow, usize)> = rx
+            .iter()
+            .map(|msg| (msg.metric.to_string(), msg.window, msg.sketch.count()))
+            .collect();
+        output.sort_unstable();
+        assert_eq!(
+            output,
+            vec![
+                ("bar".to_string(), TimeWindow::new(32, 60), 1),
+                ("foo".to_string(), TimeWindow::new(32, 60), 3),
+            ]
+        );
+
+        // The flush clears the WAL, so nothing is replayed on the next start
+        let wal = Wal::open(&dir).expect("Could not reopen WAL");
+        assert!(wal.replay_wal().expect("Could not replay WAL").is_empty());
+        fs::remove_dir_all(&dir).expect("Could not delete WAL directory");
+    }
+
+    #[test]
+    fn it_logs_inserts_until_flush() {
+        let dir = test_wal_dir("log");
+        let (tx, _rx) = channel();
+        let metrics = DaemonMetrics::new();
+        let circuit_lock = Arc::new(RwLock::new(CircuitState::Open));
+        let mut p = Processor::new(&tx, &circuit_lock, &metrics);
+        p.recover_from_wal(Wal::open(&dir).expect("Could not open WAL"));
+        let cmd = ProcessorCommand::InsertMetric("foo".to_string(), MetricKind::Counter, 7, 2);
+        p.write_wal_entry(&cmd, 10);
+        p.process_cmd(cmd);
+        // The circuit is open, so the window is not flushed and the entry stays logged
+        p.process_cmd(ProcessorCommand::CloseWindow(TimeWindow::new(0, 30)));
+
+        let wal = Wal::open(&dir).expect("Could not reopen WAL");
+        let entries = wal.replay_wal().expect("Could not replay WAL");
+        assert_eq!(
+            entries,
+            vec![WalEntry {
+                ts: 10,
+                metric: "foo".to_string(),
+                kind: MetricKind::Counter,
+                value: 7,
+                sample_count: 2,
+            }]
+        );
+        fs::remove_dir_all(&dir).expect("Could not delete WAL directory");
+    }
+
+    fn test_wal_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "caesium_processor_wal_test_{}_{}",
+            process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
     fn assert_processor(
         mut commands: Vec<(ProcessorCommand, CircuitState)>,
         mut expected: Vec<(String, TimeWindow, usize)>,
     ) {
         let (tx, rx) = channel();
+        let metrics = DaemonMetrics::new();
         let circuit_lock = Arc::new(RwLock::new(CircuitState::Closed));
         {
-            let mut p = Processor::new(&tx, &circuit_lock);
+            let mut p = Processor::new(&tx, &circuit_lock, &metrics);
             for (cmd, circuit_state) in commands.drain(..) {
                 {
                     let mut cs = circuit_lock.write().unwrap();
@@ -322,4 +859,40 @@ mod tests {
         output.sort_unstable();
         assert_eq!(output, expected);
     }
+
+    // Checks the kind and median value of each flushed metric
+    fn assert_processor_values(
+        mut commands: Vec<(ProcessorCommand, CircuitState)>,
+        mut expected: Vec<(String, MetricKind, u32)>,
+    ) {
+        let (tx, rx) = channel();
+        let metrics = DaemonMetrics::new();
+        let circuit_lock = Arc::new(RwLock::new(CircuitState::Closed));
+        {
+            let mut p = Processor::new(&tx, &circuit_lock, &metrics);
+            for (cmd, circuit_state) in commands.drain(..) {
+                {
+                    let mut cs = circuit_lock.write().unwrap();
+                    *cs = circuit_state;
+                }
+                p.process_cmd(cmd);
+            }
+        }
+        drop(tx);
+        let mut output: Vec<(String, MetricKind, u32)> = rx
+            .iter()
+            .map(|msg| {
+                let median = msg
+                    .sketch
+                    .to_readable()
+                    .query(0.5)
+                    .expect("Could not query sketch")
+                    .approx_value;
+                (msg.metric.to_string(), msg.kind, median)
+            })
+            .collect();
+        expected.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        output.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(output, expected);
+    }
 }
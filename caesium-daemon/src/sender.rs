@@ -1,20 +1,70 @@
-use caesium_core::protocol::messages::InsertMessage;
+use caesium_core::protocol::messages::{BatchInsertMessage, InsertMessage, WriteMessage};
 use circuit::CircuitState;
 use client::Client;
+use metrics::DaemonMetrics;
 use std::cmp::min;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 
+const MAX_BATCH_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SenderConfig {
+    // Failed sends to retry before opening the circuit
+    pub max_retries: usize,
+
+    // Delay before the first retry, doubled for each retry after that
+    pub retry_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for SenderConfig {
+    fn default() -> SenderConfig {
+        SenderConfig {
+            max_retries: 3,
+            retry_delay_ms: 100,
+            max_delay_ms: 30000,
+        }
+    }
+}
+
+impl SenderConfig {
+    fn retry_delay(&self, retry_count: usize) -> Duration {
+        let factor = 1u64
+            .checked_shl(min(retry_count, 63) as u32)
+            .unwrap_or(u64::MAX);
+        let delay_ms = min(
+            self.retry_delay_ms.saturating_mul(factor),
+            self.max_delay_ms,
+        );
+        Duration::from_millis(delay_ms)
+    }
+}
+
 pub fn sender_thread(
     mut client: Client,
     input: Receiver<InsertMessage>,
     circuit: Arc<RwLock<CircuitState>>,
+    config: SenderConfig,
+    metrics: Arc<DaemonMetrics>,
+    shutdown: Arc<AtomicBool>,
 ) {
     loop {
         match input.recv() {
-            Ok(msg) => send_until_success(msg, &mut client, &circuit),
+            Ok(msg) => {
+                let write_msg = build_write_msg(msg, &input);
+                send_until_success(
+                    write_msg,
+                    |m| send_to_backend(m, &mut client),
+                    &circuit,
+                    &config,
+                    &metrics,
+                    &shutdown,
+                )
+            }
             Err(_) => {
                 info!("Channel closed, stopping sender thread");
                 break;
@@ -23,30 +73,68 @@ pub fn sender_thread(
     }
 }
 
+// Combine any inserts already waiting in the channel into a single batch
+fn build_write_msg(first: InsertMessage, input: &Receiver<InsertMessage>) -> WriteMessage {
+    let mut inserts = vec![first];
+    while inserts.len() < MAX_BATCH_LEN {
+        match input.try_recv() {
+            Ok(msg) => inserts.push(msg),
+            Err(_) => break,
+        }
+    }
+
+    if inserts.len() == 1 {
+        WriteMessage::Insert(Box::new(
+            inserts.pop().expect("Could not retrieve insert msg"),
+        ))
+    } else {
+        WriteMessage::BatchInsert(BatchInsertMessage { inserts })
+    }
+}
+
 enum SendResult {
     Success,
     RetryLater,
 }
 
-fn send_until_success(
-    msg: InsertMessage,
-    mut client: &mut Client,
+// Holds `msg` until it is sent. The circuit opens once `max_retries` retries have failed,
+// but the sender keeps retrying at the maximum delay until the backend recovers.
+fn send_until_success<F>(
+    msg: WriteMessage,
+    mut send: F,
     circuit_lock: &Arc<RwLock<CircuitState>>,
-) {
+    config: &SenderConfig,
+    metrics: &DaemonMetrics,
+    shutdown: &AtomicBool,
+) where
+    F: FnMut(&WriteMessage) -> SendResult,
+{
     let mut retry_count = 0usize;
     loop {
-        match send_to_backend(&msg, &mut client) {
+        match send(&msg) {
             SendResult::Success => {
-                debug!("Sent insert message to backend for metric {:?}", msg.metric);
-                set_circuit_state(circuit_lock, CircuitState::Closed);
+                debug!("Sent {} insert message(s) to backend", count_inserts(&msg));
+                set_circuit_state(circuit_lock, CircuitState::Closed, metrics);
                 break;
             }
             SendResult::RetryLater => {
-                set_circuit_state(circuit_lock, CircuitState::Open);
+                if retry_count >= config.max_retries {
+                    set_circuit_state(circuit_lock, CircuitState::Open, metrics);
+                }
             }
         }
 
-        let delay = retry_delay(retry_count);
+        // Don't block shutdown indefinitely if the backend is unavailable
+        if shutdown.load(Ordering::SeqCst) {
+            error!(
+                "Dropping {} insert message(s) that could not be sent before shutdown",
+                count_inserts(&msg)
+            );
+            metrics.record_dropped(count_inserts(&msg) as u64);
+            break;
+        }
+
+        let delay = config.retry_delay(retry_count);
         retry_count += 1;
         info!(
             "Retry request to backend in {:?} (attempt {})",
@@ -56,7 +144,7 @@ fn send_until_success(
     }
 }
 
-fn send_to_backend(msg: &InsertMessage, client: &mut Client) -> SendResult {
+fn send_to_backend(msg: &WriteMessage, client: &mut Client) -> SendResult {
     match client.send(&msg) {
         Ok(_) => SendResult::Success,
         Err(err) => {
@@ -66,15 +154,218 @@ fn send_to_backend(msg: &InsertMessage, client: &mut Client) -> SendResult {
     }
 }
 
-fn retry_delay(retry_count: usize) -> Duration {
-    const MAX_DELAY_EXPONENT: usize = 12;
-    let exponent = min(retry_count, MAX_DELAY_EXPONENT);
-    Duration::from_millis(10 * (1 << exponent))
+fn count_inserts(msg: &WriteMessage) -> usize {
+    match msg {
+        WriteMessage::Insert(_) => 1,
+        WriteMessage::BatchInsert(batch) => batch.inserts.len(),
+        WriteMessage::Auth(_) => 0,
+    }
 }
 
-fn set_circuit_state(circuit_lock: &Arc<RwLock<CircuitState>>, new_state: CircuitState) {
+fn set_circuit_state(
+    circuit_lock: &Arc<RwLock<CircuitState>>,
+    new_state: CircuitState,
+    metrics: &DaemonMetrics,
+) {
     let mut state_mut = circuit_lock
         .write()
         .expect("Could not acquire write lock on circuit");
+    if let (CircuitState::Closed, CircuitState::Open) = (&*state_mut, &new_state) {
+        metrics.record_circuit_open();
+    }
     *state_mut = new_state;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use caesium_core::encode::frame::FrameInfo;
+    use caesium_core::encode::Decodable;
+    use caesium_core::protocol::messages::MetricKind;
+    use caesium_core::quantile::writable::WritableSketch;
+    use caesium_core::time::window::TimeWindow;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpListener};
+
+    #[test]
+    fn it_doubles_retry_delay_up_to_max() {
+        let config = SenderConfig {
+            max_retries: 3,
+            retry_delay_ms: 100,
+            max_delay_ms: 500,
+        };
+        let delays: Vec<Duration> = (0..5).map(|i| config.retry_delay(i)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(500),
+                Duration::from_millis(500),
+            ]
+        );
+        assert_eq!(config.retry_delay(1000), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn it_retries_without_opening_circuit() {
+        let (attempts, circuit, metrics) = send_with_failures(3, 3);
+        assert_eq!(attempts, 4);
+        assert_eq!(*circuit.read().unwrap(), CircuitState::Closed);
+        assert_eq!(metrics.snapshot().circuit_open_count, 0);
+    }
+
+    #[test]
+    fn it_opens_circuit_after_max_retries() {
+        let (attempts, circuit, metrics) = send_with_failures(4, 3);
+        assert_eq!(attempts, 5);
+        assert_eq!(metrics.snapshot().circuit_open_count, 1);
+
+        // The circuit closes again once the message is sent
+        assert_eq!(*circuit.read().unwrap(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn it_delivers_message_after_backend_recovers() {
+        let addr = unused_addr();
+        let mut client = Client::new(addr.to_string());
+        let circuit = Arc::new(RwLock::new(CircuitState::Closed));
+        let metrics = DaemonMetrics::new();
+        let mut attempts = 0;
+        let mut listener = None;
+        send_until_success(
+            WriteMessage::Insert(Box::new(build_insert("foo"))),
+            |m| {
+                // Fail the first two attempts by not listening yet
+                attempts += 1;
+                if attempts == 3 {
+                    listener = Some(TcpListener::bind(addr).expect("Could not bind listener"));
+                }
+                send_to_backend(m, &mut client)
+            },
+            &circuit,
+            &test_config(5),
+            &metrics,
+            &AtomicBool::new(false),
+        );
+        assert_eq!(attempts, 3);
+
+        let listener = listener.expect("Listener was not started");
+        let (mut stream, _) = listener.accept().expect("Could not accept connection");
+        drop(client);
+        let mut buf = Vec::new();
+        stream
+            .read_to_end(&mut buf)
+            .expect("Could not read from connection");
+        let frame = FrameInfo::from_bytes(&buf)
+            .expect("Could not decode frame")
+            .expect("Frame is incomplete");
+        let msg = WriteMessage::decode(&mut &buf[frame.prefix_len..frame.frame_len()])
+            .expect("Could not decode message");
+        match msg {
+            WriteMessage::Insert(insert) => assert_eq!(insert.metric, "foo"),
+            _ => panic!("Expected insert message"),
+        }
+    }
+
+    #[test]
+    fn it_sends_auth_token_before_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let addr = listener
+            .local_addr()
+            .expect("Could not retrieve listener addr");
+        let mut client = Client::new(addr.to_string()).with_auth_token(Some("secret".to_string()));
+        client
+            .send(&WriteMessage::Insert(Box::new(build_insert("foo"))))
+            .expect("Could not send message");
+        let (mut stream, _) = listener.accept().expect("Could not accept connection");
+        drop(client);
+        let mut buf = Vec::new();
+        stream
+            .read_to_end(&mut buf)
+            .expect("Could not read from connection");
+        let auth_frame = FrameInfo::from_bytes(&buf)
+            .expect("Could not decode frame")
+            .expect("Frame is incomplete");
+        let auth_msg =
+            WriteMessage::decode(&mut &buf[auth_frame.prefix_len..auth_frame.frame_len()])
+                .expect("Could not decode message");
+        match auth_msg {
+            WriteMessage::Auth(auth) => assert_eq!(auth.token, "secret"),
+            _ => panic!("Expected auth message"),
+        }
+        let rest = &buf[auth_frame.frame_len()..];
+        let frame = FrameInfo::from_bytes(rest)
+            .expect("Could not decode frame")
+            .expect("Frame is incomplete");
+        let msg = WriteMessage::decode(&mut &rest[frame.prefix_len..frame.frame_len()])
+            .expect("Could not decode message");
+        match msg {
+            WriteMessage::Insert(insert) => assert_eq!(insert.metric, "foo"),
+            _ => panic!("Expected insert message"),
+        }
+    }
+
+    #[test]
+    fn it_drops_message_on_shutdown() {
+        let circuit = Arc::new(RwLock::new(CircuitState::Closed));
+        let metrics = DaemonMetrics::new();
+        send_until_success(
+            WriteMessage::Insert(Box::new(build_insert("foo"))),
+            |_| SendResult::RetryLater,
+            &circuit,
+            &test_config(0),
+            &metrics,
+            &AtomicBool::new(true),
+        );
+        assert_eq!(metrics.snapshot().messages_dropped, 1);
+    }
+
+    fn send_with_failures(
+        num_failures: usize,
+        max_retries: usize,
+    ) -> (usize, Arc<RwLock<CircuitState>>, DaemonMetrics) {
+        let circuit = Arc::new(RwLock::new(CircuitState::Closed));
+        let metrics = DaemonMetrics::new();
+        let mut attempts = 0;
+        send_until_success(
+            WriteMessage::Insert(Box::new(build_insert("foo"))),
+            |_| {
+                attempts += 1;
+                if attempts > num_failures {
+                    SendResult::Success
+                } else {
+                    SendResult::RetryLater
+                }
+            },
+            &circuit,
+            &test_config(max_retries),
+            &metrics,
+            &AtomicBool::new(false),
+        );
+        (attempts, circuit, metrics)
+    }
+
+    fn test_config(max_retries: usize) -> SenderConfig {
+        SenderConfig {
+            max_retries,
+            retry_delay_ms: 1,
+            max_delay_ms: 10,
+        }
+    }
+
+    fn build_insert(metric: &str) -> InsertMessage {
+        InsertMessage {
+            metric: metric.to_string(),
+            kind: MetricKind::Timer,
+            window: TimeWindow::new(0, 30),
+            sketch: WritableSketch::new(),
+        }
+    }
+
+    fn unused_addr() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        listener.local_addr().expect("Could not retrieve address")
+    }
+}
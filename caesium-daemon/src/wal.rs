@@ -0,0 +1,181 @@
+use caesium_core::encode::{Decodable, Encodable, EncodableError};
+use caesium_core::protocol::messages::MetricKind;
+use caesium_core::time::timestamp::TimeStamp;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+const WAL_FILE_NAME: &str = "daemon.wal";
+
+// An insert received by the daemon, logged so it can be replayed after a crash
+#[derive(Debug, PartialEq)]
+pub struct WalEntry {
+    pub ts: TimeStamp,
+    pub metric: String,
+    pub kind: MetricKind,
+    pub value: u32,
+    pub sample_count: u32,
+}
+
+impl<W> Encodable<W> for WalEntry
+where
+    W: Write,
+{
+    fn encode(&self, writer: &mut W) -> Result<(), EncodableError> {
+        self.ts.encode(writer)?;
+        self.metric.encode(writer)?;
+        self.kind.encode(writer)?;
+        self.value.encode(writer)?;
+        self.sample_count.encode(writer)?;
+        Ok(())
+    }
+}
+
+impl<R> Decodable<WalEntry, R> for WalEntry
+where
+    R: Read,
+{
+    fn decode(reader: &mut R) -> Result<WalEntry, EncodableError> {
+        Ok(WalEntry {
+            ts: TimeStamp::decode(reader)?,
+            metric: String::decode(reader)?,
+            kind: MetricKind::decode(reader)?,
+            value: u32::decode(reader)?,
+            sample_count: u32::decode(reader)?,
+        })
+    }
+}
+
+// Append-only log of inserts for the current window.
+// The log is cleared each time the processor flushes its metrics.
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Wal {
+    // Opens the log in `dir`, creating the directory and file if they do not exist
+    pub fn open(dir: &Path) -> Result<Wal, io::Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(WAL_FILE_NAME);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Wal { path, file })
+    }
+
+    pub fn write_wal_entry(&mut self, entry: &WalEntry) -> Result<(), EncodableError> {
+        // Encode into a buffer first so each entry is appended with a single write
+        let mut buf = Vec::new();
+        entry.encode(&mut buf)?;
+        self.file.write_all(&buf)?;
+        Ok(())
+    }
+
+    // Returns every complete entry in the log. If the daemon crashed while
+    // writing an entry, the partial entry at the end is skipped.
+    pub fn replay_wal(&self) -> Result<Vec<WalEntry>, io::Error> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut entries = Vec::new();
+        while !reader.fill_buf()?.is_empty() {
+            match WalEntry::decode(&mut reader) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => {
+                    warn!("Skipping partial entry at end of WAL: {:?}", err);
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn clear(&mut self) -> Result<(), io::Error> {
+        self.file.set_len(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn it_replays_empty_wal() {
+        with_test_dir("empty", |dir| {
+            let wal = Wal::open(dir).expect("Could not open WAL");
+            assert_eq!(wal.replay_wal().expect("Could not replay WAL"), vec![]);
+        })
+    }
+
+    #[test]
+    fn it_replays_written_entries() {
+        with_test_dir("written", |dir| {
+            let entries = vec![
+                build_entry(10, "foo", MetricKind::Timer, 1),
+                build_entry(11, "bar;region=us", MetricKind::Counter, 2),
+                build_entry(12, "baz", MetricKind::Gauge, 3),
+            ];
+            {
+                let mut wal = Wal::open(dir).expect("Could not open WAL");
+                for entry in entries.iter() {
+                    wal.write_wal_entry(entry).expect("Could not write entry");
+                }
+            }
+            let wal = Wal::open(dir).expect("Could not reopen WAL");
+            assert_eq!(wal.replay_wal().expect("Could not replay WAL"), entries);
+        })
+    }
+
+    #[test]
+    fn it_skips_partial_entry() {
+        with_test_dir("partial", |dir| {
+            let entry = build_entry(10, "foo", MetricKind::Timer, 1);
+            let mut buf = Vec::new();
+            entry.encode(&mut buf).expect("Could not encode entry");
+            let len = buf.len();
+            entry.encode(&mut buf).expect("Could not encode entry");
+            buf.truncate(len + 5);
+            fs::create_dir_all(dir).expect("Could not create WAL directory");
+            fs::write(dir.join(WAL_FILE_NAME), &buf).expect("Could not write WAL file");
+
+            let wal = Wal::open(dir).expect("Could not open WAL");
+            assert_eq!(wal.replay_wal().expect("Could not replay WAL"), vec![entry]);
+        })
+    }
+
+    #[test]
+    fn it_clears_entries() {
+        with_test_dir("clear", |dir| {
+            let mut wal = Wal::open(dir).expect("Could not open WAL");
+            wal.write_wal_entry(&build_entry(10, "foo", MetricKind::Timer, 1))
+                .expect("Could not write entry");
+            wal.clear().expect("Could not clear WAL");
+            assert_eq!(wal.replay_wal().expect("Could not replay WAL"), vec![]);
+
+            let entry = build_entry(20, "bar", MetricKind::Timer, 2);
+            wal.write_wal_entry(&entry).expect("Could not write entry");
+            assert_eq!(wal.replay_wal().expect("Could not replay WAL"), vec![entry]);
+        })
+    }
+
+    fn build_entry(ts: TimeStamp, metric: &str, kind: MetricKind, value: u32) -> WalEntry {
+        WalEntry {
+            ts,
+            metric: metric.to_string(),
+            kind,
+            value,
+            sample_count: 1,
+        }
+    }
+
+    fn with_test_dir<T>(name: &str, test: T)
+    where
+        T: FnOnce(&Path),
+    {
+        let dir = env::temp_dir().join(format!("caesium_wal_test_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        test(&dir);
+        fs::remove_dir_all(&dir).expect("Could not delete test directory");
+    }
+}
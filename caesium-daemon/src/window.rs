@@ -16,6 +16,10 @@ impl WindowTracker {
         }
     }
 
+    pub fn current_window(&self) -> TimeWindow {
+        self.window
+    }
+
     pub fn update(&mut self, clock: &Clock) -> Option<TimeWindow> {
         let now = clock.now();
         if now >= self.window.end() {
@@ -41,7 +45,7 @@ mod tests {
 
     #[test]
     fn it_tracks_closed_time_windows() {
-        let mut clock = MockClock::new(0);
+        let clock = MockClock::new(0);
         let mut tracker = WindowTracker::new(30, &clock);
         assert!(tracker.update(&clock).is_none());
         clock.tick(29);
@@ -54,9 +58,21 @@ mod tests {
         assert_eq!(tracker.update(&clock), Some(TimeWindow::new(30, 60)));
     }
 
+    #[test]
+    fn it_closes_windows_at_configured_size() {
+        let clock = MockClock::new(0);
+        let mut tracker = WindowTracker::new(5, &clock);
+        clock.tick(4);
+        assert!(tracker.update(&clock).is_none());
+        clock.tick(1);
+        assert_eq!(tracker.update(&clock), Some(TimeWindow::new(0, 5)));
+        clock.tick(5);
+        assert_eq!(tracker.update(&clock), Some(TimeWindow::new(5, 10)));
+    }
+
     #[test]
     fn it_aligns_time_windows() {
-        let mut clock = MockClock::new(12); // not aligned to window size
+        let clock = MockClock::new(12); // not aligned to window size
         let mut tracker = WindowTracker::new(30, &clock);
         clock.tick(18);
         assert_eq!(tracker.update(&clock), Some(TimeWindow::new(0, 30)));
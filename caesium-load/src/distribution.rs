@@ -0,0 +1,130 @@
+use rand::distributions::{Distribution, LogNormal, Normal};
+use rand::Rng;
+
+// Distribution of the values written by the load generator.
+// Samples are rounded and clamped to the range of a u32.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueDistribution {
+    // Uniform over [min, max)
+    Uniform { min: u32, max: u32 },
+
+    Normal { mean: f64, std_dev: f64 },
+
+    // `mu` and `sigma` are the mean and standard deviation of the
+    // underlying normal distribution, so the median is e^mu
+    LogNormal { mu: f64, sigma: f64 },
+}
+
+impl ValueDistribution {
+    pub fn uniform(min: u32, max: u32) -> ValueDistribution {
+        assert!(min < max);
+        ValueDistribution::Uniform { min, max }
+    }
+
+    pub fn normal(mean: f64, std_dev: f64) -> ValueDistribution {
+        assert!(std_dev >= 0.0);
+        ValueDistribution::Normal { mean, std_dev }
+    }
+
+    pub fn lognormal(mu: f64, sigma: f64) -> ValueDistribution {
+        assert!(sigma >= 0.0);
+        ValueDistribution::LogNormal { mu, sigma }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> u32 {
+        match *self {
+            ValueDistribution::Uniform { min, max } => rng.gen_range(min, max),
+            ValueDistribution::Normal { mean, std_dev } => {
+                clamp_to_u32(Normal::new(mean, std_dev).sample(rng))
+            }
+            ValueDistribution::LogNormal { mu, sigma } => {
+                clamp_to_u32(LogNormal::new(mu, sigma).sample(rng))
+            }
+        }
+    }
+}
+
+fn clamp_to_u32(v: f64) -> u32 {
+    if v <= 0.0 {
+        0
+    } else if v >= u32::MAX as f64 {
+        u32::MAX
+    } else {
+        v.round() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::FromEntropy;
+
+    const NUM_SAMPLES: usize = 10000;
+
+    #[test]
+    fn it_samples_uniform() {
+        let samples = draw_samples(ValueDistribution::uniform(100, 200));
+        assert!(samples.iter().all(|&v| (100..200).contains(&v)));
+        assert_within(mean(&samples), 150.0, 2.0);
+    }
+
+    #[test]
+    fn it_samples_normal() {
+        let samples = draw_samples(ValueDistribution::normal(1000.0, 100.0));
+        assert_within(mean(&samples), 1000.0, 5.0);
+        assert_within(std_dev(&samples), 100.0, 5.0);
+    }
+
+    #[test]
+    fn it_samples_lognormal() {
+        let samples = draw_samples(ValueDistribution::lognormal(5.0, 0.5));
+        let mut sorted = samples.clone();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2] as f64;
+        assert_within(median, 5.0_f64.exp(), 5.0);
+
+        // Long right tail: the mean is e^(mu + sigma^2 / 2), above the median
+        assert_within(mean(&samples), (5.0_f64 + 0.125).exp(), 5.0);
+        assert!(sorted[sorted.len() * 99 / 100] as f64 > 2.0 * median);
+    }
+
+    #[test]
+    fn it_clamps_samples_to_u32() {
+        assert_eq!(clamp_to_u32(-10.0), 0);
+        assert_eq!(clamp_to_u32(1.6), 2);
+        assert_eq!(clamp_to_u32(1.0e12), u32::MAX);
+        let samples = draw_samples(ValueDistribution::normal(0.0, 10.0));
+        assert!(samples.contains(&0));
+    }
+
+    fn draw_samples(d: ValueDistribution) -> Vec<u32> {
+        let mut rng = SmallRng::from_entropy();
+        (0..NUM_SAMPLES).map(|_| d.sample(&mut rng)).collect()
+    }
+
+    fn mean(samples: &[u32]) -> f64 {
+        samples.iter().map(|&v| v as f64).sum::<f64>() / samples.len() as f64
+    }
+
+    fn std_dev(samples: &[u32]) -> f64 {
+        let m = mean(samples);
+        let var = samples
+            .iter()
+            .map(|&v| (v as f64 - m) * (v as f64 - m))
+            .sum::<f64>()
+            / samples.len() as f64;
+        var.sqrt()
+    }
+
+    fn assert_within(actual: f64, expected: f64, tolerance_pct: f64) {
+        let diff = (actual - expected).abs();
+        assert!(
+            diff <= expected * tolerance_pct / 100.0,
+            "Expected {} to be within {}% of {}",
+            actual,
+            tolerance_pct,
+            expected
+        );
+    }
+}
@@ -7,36 +7,48 @@ extern crate uuid;
 #[macro_use]
 extern crate log;
 
+pub mod distribution;
 pub mod error;
 mod rate;
 mod report;
 mod worker;
 
 use caesium_core::time::clock::SystemClock;
+use distribution::ValueDistribution;
 use error::Error;
 use mio::{Events, Poll, Token};
+use rate::{RateLimiter, SharedRateLimiter};
 use report::event::Event;
 use report::reporter::Reporter;
-use report::sink::LogSink;
+use report::sink::{CsvSink, JsonSink, LogSink, ReportSink};
 use std::fs::File;
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::BufWriter;
 use std::net::SocketAddr;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use worker::daemon_writer::DaemonWriter;
 use worker::server_reader::ServerReader;
 use worker::server_writer::ServerWriter;
 use worker::Worker;
 
+pub enum ReportOutput {
+    Log,
+    Csv(String),
+    Json(String),
+}
+
 pub struct DaemonWriterConfig {
     pub addr: SocketAddr,
     pub num_workers: usize,
     pub rate_limit: Option<usize>,
     pub num_metrics: usize,
+    pub value_distribution: ValueDistribution,
+    pub ramp_up_secs: u64,
 }
 
 pub struct ServerReaderConfig {
@@ -44,39 +56,61 @@ pub struct ServerReaderConfig {
     pub num_workers: usize,
     pub query_file_path: String,
     pub rate_limit: Option<usize>,
+    pub ramp_up_secs: u64,
 }
 
 pub struct ServerWriterConfig {
     pub addr: SocketAddr,
     pub num_workers: usize,
     pub sketch_size: usize,
+    pub value_distribution: ValueDistribution,
     pub rate_limit: Option<usize>,
+    pub ramp_up_secs: u64,
 }
 
 pub fn generate_load(
     report_sample_interval: u64,
+    report_output: ReportOutput,
+    duration: Option<Duration>,
+    total_rate_limit: Option<usize>,
     daemon_writer_config: DaemonWriterConfig,
     server_reader_config: ServerReaderConfig,
     server_writer_config: ServerWriterConfig,
 ) -> Result<(), Error> {
     let (tx, rx) = channel();
-    start_reporter_thread(rx, report_sample_interval);
+    match report_output {
+        ReportOutput::Log => start_reporter_thread(rx, report_sample_interval, LogSink::new()),
+        ReportOutput::Csv(path) => {
+            let sink = CsvSink::new(BufWriter::new(File::create(path)?));
+            start_reporter_thread(rx, report_sample_interval, sink)
+        }
+        ReportOutput::Json(path) => {
+            let out = BufWriter::new(File::create(path)?);
+            let sink = JsonSink::new(out, SystemClock::new());
+            start_reporter_thread(rx, report_sample_interval, sink)
+        }
+    }
 
     let poll = Poll::new()?;
+    let mut schedule = Vec::new();
+    let shared_limit = total_rate_limit.map(SharedRateLimiter::new);
     let mut workers = init_workers(
         daemon_writer_config,
         server_reader_config,
         server_writer_config,
+        shared_limit,
         tx.clone(),
-        &poll,
+        &mut schedule,
     )?;
-    run_event_loop(&poll, &mut workers)
+    run_event_loop(&poll, &mut workers, schedule, duration, tx)
 }
 
-fn start_reporter_thread(rx: Receiver<Event>, sample_interval: u64) {
+fn start_reporter_thread<T>(rx: Receiver<Event>, sample_interval: u64, sink: T)
+where
+    T: ReportSink + Send + 'static,
+{
     thread::spawn(move || {
         let reporter = Reporter::new(rx, sample_interval);
-        let sink = LogSink::new();
         let sink_mutex = Arc::new(Mutex::new(sink));
         reporter.run(sink_mutex);
     });
@@ -86,23 +120,43 @@ fn init_workers(
     daemon_writer_config: DaemonWriterConfig,
     server_reader_config: ServerReaderConfig,
     server_writer_config: ServerWriterConfig,
+    shared_limit: Option<SharedRateLimiter>,
     tx: Sender<Event>,
-    poll: &Poll,
+    schedule: &mut Vec<Duration>,
 ) -> Result<Vec<Box<Worker>>, Error> {
     let num_workers = daemon_writer_config.num_workers
         + server_reader_config.num_workers
         + server_writer_config.num_workers;
     let mut workers = Vec::with_capacity(num_workers);
-    init_daemon_writers(&mut workers, daemon_writer_config, tx.clone())?;
-    init_server_readers(&mut workers, server_reader_config, tx.clone())?;
-    init_server_writers(&mut workers, server_writer_config, tx.clone())?;
-    register_workers(poll, &mut workers)?;
+    init_daemon_writers(
+        &mut workers,
+        schedule,
+        daemon_writer_config,
+        &shared_limit,
+        tx.clone(),
+    )?;
+    init_server_readers(
+        &mut workers,
+        schedule,
+        server_reader_config,
+        &shared_limit,
+        tx.clone(),
+    )?;
+    init_server_writers(
+        &mut workers,
+        schedule,
+        server_writer_config,
+        &shared_limit,
+        tx.clone(),
+    )?;
     Ok(workers)
 }
 
 fn init_daemon_writers(
     workers: &mut Vec<Box<Worker>>,
+    schedule: &mut Vec<Duration>,
     config: DaemonWriterConfig,
+    shared_limit: &Option<SharedRateLimiter>,
     tx: Sender<Event>,
 ) -> Result<(), io::Error> {
     assert!(config.num_metrics > 0);
@@ -112,17 +166,21 @@ fn init_daemon_writers(
             &config.addr,
             metric_id,
             config.num_metrics,
-            config.rate_limit,
+            RateLimiter::new(config.rate_limit).with_shared(shared_limit.clone()),
+            config.value_distribution,
             tx.clone(),
         )?;
         workers.push(Box::new(w));
+        schedule.push(ramp_up_delay(i, config.num_workers, config.ramp_up_secs));
     }
     Ok(())
 }
 
 fn init_server_readers(
     workers: &mut Vec<Box<Worker>>,
+    schedule: &mut Vec<Duration>,
     config: ServerReaderConfig,
+    shared_limit: &Option<SharedRateLimiter>,
     tx: Sender<Event>,
 ) -> Result<(), Error> {
     let queries = load_queries(&config.query_file_path)?;
@@ -138,39 +196,44 @@ fn init_server_readers(
             &config.addr,
             &queries,
             query_idx,
-            config.rate_limit,
+            RateLimiter::new(config.rate_limit).with_shared(shared_limit.clone()),
             tx.clone(),
         );
         workers.push(Box::new(w));
+        schedule.push(ramp_up_delay(i, config.num_workers, config.ramp_up_secs));
     }
     Ok(())
 }
 
 fn init_server_writers(
     workers: &mut Vec<Box<Worker>>,
+    schedule: &mut Vec<Duration>,
     config: ServerWriterConfig,
+    shared_limit: &Option<SharedRateLimiter>,
     tx: Sender<Event>,
 ) -> Result<(), Error> {
     let clock = SystemClock::new();
-    for _ in 0..config.num_workers {
+    for i in 0..config.num_workers {
         let w = ServerWriter::new(
             &config.addr,
             config.sketch_size,
-            config.rate_limit,
+            config.value_distribution,
+            RateLimiter::new(config.rate_limit).with_shared(shared_limit.clone()),
             &clock,
             tx.clone(),
         );
         workers.push(Box::new(w));
+        schedule.push(ramp_up_delay(i, config.num_workers, config.ramp_up_secs));
     }
     Ok(())
 }
 
+// Partitions the range [0, num_values) evenly among the workers,
+// so each worker starts at a different offset. If there are more
+// workers than values, consecutive workers share a starting value.
 fn choose_start_for_worker(worker_idx: usize, num_workers: usize, num_values: usize) -> usize {
-    if num_values <= num_workers {
-        worker_idx % num_workers
-    } else {
-        (num_workers * worker_idx) / num_workers
-    }
+    assert!(worker_idx < num_workers);
+    (worker_idx * num_values) / num_workers
 }
 
 fn load_queries(path: &str) -> Result<Vec<String>, io::Error> {
@@ -182,18 +245,60 @@ fn load_queries(path: &str) -> Result<Vec<String>, io::Error> {
     Ok(queries)
 }
 
-fn register_workers(poll: &Poll, workers: &mut [Box<Worker>]) -> Result<(), io::Error> {
-    for (idx, w) in workers.iter_mut().enumerate() {
-        let token = Token(idx);
-        w.register(token, poll)?;
+// Spreads registration of a group of workers evenly across the ramp-up period,
+// so they don't all start sending at once
+fn ramp_up_delay(worker_idx: usize, num_workers: usize, ramp_up_secs: u64) -> Duration {
+    let interval_ms = (ramp_up_secs * 1000) / (num_workers as u64);
+    Duration::from_millis(interval_ms * worker_idx as u64)
+}
+
+// Registers each worker once its scheduled delay has elapsed.
+// Returns the time until the next pending registration, if any.
+fn register_due_workers(
+    poll: &Poll,
+    workers: &mut [Box<Worker>],
+    pending: &mut Vec<(Duration, usize)>,
+    elapsed: Duration,
+) -> Result<Option<Duration>, io::Error> {
+    while let Some(&(delay, idx)) = pending.last() {
+        if delay > elapsed {
+            return Ok(Some(delay - elapsed));
+        }
+        pending.pop();
+        debug!("Registering worker {} after {:?}", idx, elapsed);
+        workers[idx].register(Token(idx), poll)?;
     }
-    Ok(())
+    Ok(None)
 }
 
-fn run_event_loop(poll: &Poll, workers: &mut [Box<Worker>]) -> Result<(), Error> {
+// Runs until `duration` elapses, or forever if no duration is given
+fn run_event_loop(
+    poll: &Poll,
+    workers: &mut [Box<Worker>],
+    schedule: Vec<Duration>,
+    duration: Option<Duration>,
+    tx: Sender<Event>,
+) -> Result<(), Error> {
+    let start = Instant::now();
     let mut events = Events::with_capacity(1024);
+
+    // Sorted so the next worker to register is at the end
+    let mut pending: Vec<(Duration, usize)> = schedule.into_iter().zip(0..).collect();
+    pending.sort_by(|a, b| b.cmp(a));
+
     loop {
-        poll.poll(&mut events, Some(Duration::from_millis(1000)))?;
+        if let Some(d) = duration {
+            if start.elapsed() >= d {
+                info!("Load test finished after {:?}", d);
+                return Ok(());
+            }
+        }
+        let next_registration = register_due_workers(poll, workers, &mut pending, start.elapsed())?;
+        let timeout = match next_registration {
+            Some(t) if t < poll_timeout(start, duration) => t,
+            _ => poll_timeout(start, duration),
+        };
+        poll.poll(&mut events, Some(timeout))?;
         for event in events.iter() {
             match event.token() {
                 Token(t) if t < workers.len() => {
@@ -201,10 +306,12 @@ fn run_event_loop(poll: &Poll, workers: &mut [Box<Worker>]) -> Result<(), Error>
                     if event.readiness().is_writable() {
                         if let Err(err) = w.write() {
                             error!("Worker {} error while writing: {:?}", t, err);
+                            report_worker_error(&tx, t, err);
                         }
                     } else if event.readiness().is_readable() {
                         if let Err(err) = w.read() {
                             error!("Worker {} error while reading: {:?}", t, err);
+                            report_worker_error(&tx, t, err);
                         }
                     }
                     w.register(Token(t), poll)?;
@@ -214,3 +321,164 @@ fn run_event_loop(poll: &Poll, workers: &mut [Box<Worker>]) -> Result<(), Error>
         }
     }
 }
+
+// Wake up no later than the end of the load test
+fn poll_timeout(start: Instant, duration: Option<Duration>) -> Duration {
+    let max_timeout = Duration::from_millis(1000);
+    match duration {
+        Some(d) => {
+            let remaining = d
+                .checked_sub(start.elapsed())
+                .unwrap_or(Duration::from_millis(0));
+            if remaining < max_timeout {
+                remaining
+            } else {
+                max_timeout
+            }
+        }
+        None => max_timeout,
+    }
+}
+
+fn report_worker_error(tx: &Sender<Event>, worker_id: usize, err: io::Error) {
+    let event = Event::worker_error_event(worker_id, format!("{:?}", err.kind()));
+    tx.send(event).expect("Could not send worker error event");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_spreads_worker_starts_across_values() {
+        let starts: Vec<usize> = (0..3).map(|i| choose_start_for_worker(i, 3, 10)).collect();
+        assert_eq!(starts, vec![0, 3, 6]);
+    }
+
+    #[test]
+    fn it_spreads_worker_starts_across_large_range() {
+        let starts: Vec<usize> = (0..4)
+            .map(|i| choose_start_for_worker(i, 4, 1000))
+            .collect();
+        assert_eq!(starts, vec![0, 250, 500, 750]);
+    }
+
+    #[test]
+    fn it_shares_starts_when_workers_exceed_values() {
+        let starts: Vec<usize> = (0..4).map(|i| choose_start_for_worker(i, 4, 2)).collect();
+        assert_eq!(starts, vec![0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn it_stops_event_loop_after_duration() {
+        let poll = Poll::new().expect("Could not create poll");
+        let mut workers: Vec<Box<Worker>> = Vec::new();
+        let (tx, _rx) = channel();
+        let start = Instant::now();
+        run_event_loop(
+            &poll,
+            &mut workers,
+            Vec::new(),
+            Some(Duration::from_millis(200)),
+            tx,
+        )
+        .expect("Could not run event loop");
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn it_limits_poll_timeout_to_remaining_duration() {
+        let start = Instant::now();
+        assert_eq!(poll_timeout(start, None), Duration::from_millis(1000));
+        assert_eq!(
+            poll_timeout(start, Some(Duration::from_secs(60))),
+            Duration::from_millis(1000)
+        );
+        assert!(
+            poll_timeout(start, Some(Duration::from_millis(100))) <= Duration::from_millis(100)
+        );
+        assert_eq!(
+            poll_timeout(start, Some(Duration::from_millis(0))),
+            Duration::from_millis(0)
+        );
+    }
+
+    #[test]
+    fn it_spreads_worker_registration_across_ramp_up() {
+        let delays: Vec<Duration> = (0..4).map(|i| ramp_up_delay(i, 4, 2)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(0),
+                Duration::from_millis(500),
+                Duration::from_millis(1000),
+                Duration::from_millis(1500),
+            ]
+        );
+        assert!(delays.iter().any(|d| *d >= Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn it_registers_workers_on_schedule() {
+        let poll = Poll::new().expect("Could not create poll");
+        let registered = Arc::new(Mutex::new(Vec::new()));
+        let mut workers: Vec<Box<Worker>> = (0..4)
+            .map(|_| Box::new(MockWorker::new(registered.clone())) as Box<Worker>)
+            .collect();
+        let schedule: Vec<Duration> = (0..4).map(|i| ramp_up_delay(i, 4, 2)).collect();
+        let mut pending: Vec<(Duration, usize)> = schedule.into_iter().zip(0..).collect();
+        pending.sort_by(|a, b| b.cmp(a));
+
+        let next = register_due_workers(
+            &poll,
+            &mut workers,
+            &mut pending,
+            Duration::from_millis(499),
+        )
+        .expect("Could not register workers");
+        assert_eq!(*registered.lock().unwrap(), vec![0]);
+        assert_eq!(next, Some(Duration::from_millis(1)));
+
+        let next = register_due_workers(
+            &poll,
+            &mut workers,
+            &mut pending,
+            Duration::from_millis(1200),
+        )
+        .expect("Could not register workers");
+        assert_eq!(*registered.lock().unwrap(), vec![0, 1, 2]);
+        assert_eq!(next, Some(Duration::from_millis(300)));
+
+        let next = register_due_workers(&poll, &mut workers, &mut pending, Duration::from_secs(2))
+            .expect("Could not register workers");
+        assert_eq!(*registered.lock().unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(next, None);
+    }
+
+    struct MockWorker {
+        registered: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl MockWorker {
+        fn new(registered: Arc<Mutex<Vec<usize>>>) -> MockWorker {
+            MockWorker { registered }
+        }
+    }
+
+    impl Worker for MockWorker {
+        fn register(&mut self, token: Token, _poll: &Poll) -> Result<(), io::Error> {
+            self.registered.lock().unwrap().push(token.0);
+            Ok(())
+        }
+
+        fn write(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+
+        fn read(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+}
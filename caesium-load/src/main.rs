@@ -2,17 +2,24 @@ extern crate caesium_load;
 extern crate clap;
 extern crate stackdriver_logger;
 
+use caesium_load::distribution::ValueDistribution;
 use caesium_load::error::Error;
-use caesium_load::{generate_load, DaemonWriterConfig, ServerReaderConfig, ServerWriterConfig};
+use caesium_load::{
+    generate_load, DaemonWriterConfig, ReportOutput, ServerReaderConfig, ServerWriterConfig,
+};
 use clap::{App, Arg, ArgMatches};
 use std::env;
 use std::net::ToSocketAddrs;
+use std::time::Duration;
 
 fn main() -> Result<(), Error> {
     init_logger();
     let args = parse_args()?;
     generate_load(
         args.report_sample_interval,
+        args.report_output,
+        args.duration,
+        args.total_rate_limit,
         args.daemon_writer_config,
         args.server_reader_config,
         args.server_writer_config,
@@ -21,7 +28,7 @@ fn main() -> Result<(), Error> {
 }
 
 fn init_logger() {
-    if let Err(_) = env::var("RUST_LOG") {
+    if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "caesium=debug");
     }
     stackdriver_logger::init();
@@ -29,6 +36,9 @@ fn init_logger() {
 
 struct Args {
     report_sample_interval: u64,
+    report_output: ReportOutput,
+    duration: Option<Duration>,
+    total_rate_limit: Option<usize>,
     daemon_writer_config: DaemonWriterConfig,
     server_reader_config: ServerReaderConfig,
     server_writer_config: ServerWriterConfig,
@@ -43,6 +53,30 @@ fn parse_args() -> Result<Args, Error> {
                 .takes_value(true)
                 .help("Interval in seconds for reporting insert rate and query durations (default 60)")
         )
+        .arg(
+            Arg::with_name("REPORT_OUTPUT")
+                .long("report-output")
+                .takes_value(true)
+                .help("Where to write reports: `log`, `csv:<path>`, or `json:<path>` (default log)")
+        )
+        .arg(
+            Arg::with_name("DURATION_SECS")
+                .long("duration-secs")
+                .takes_value(true)
+                .help("Number of seconds to generate load before exiting (default is to run forever)")
+        )
+        .arg(
+            Arg::with_name("TOTAL_RATE")
+                .long("total-rate")
+                .takes_value(true)
+                .help("Maximum number of operations per second across all workers, in addition to each worker's own limit (default is no limit)")
+        )
+        .arg(
+            Arg::with_name("VALUE_DISTRIBUTION")
+                .long("value-distribution")
+                .takes_value(true)
+                .help("Distribution of written values: `uniform:<min>,<max>`, `normal:<mean>,<stddev>`, or `lognormal:<mu>,<sigma>` (default uniform over 0-5000 for the daemon and 0-10000 for the server)")
+        )
         .arg(
             Arg::with_name("DAEMON_WRITE_ADDR")
                 .long("daemon-write-addr")
@@ -67,6 +101,12 @@ fn parse_args() -> Result<Args, Error> {
                 .takes_value(true)
                 .help("Maximum number of inserts per second per write worker (default is no limit)"),
         )
+        .arg(
+            Arg::with_name("DAEMON_WRITE_RAMP_UP_SECS")
+                .long("daemon-write-ramp-up-secs")
+                .takes_value(true)
+                .help("Number of seconds over which to start the workers, one at a time (default 0)"),
+        )
         .arg(
             Arg::with_name("SERVER_QUERY_ADDR")
                 .long("server-query-addr")
@@ -91,6 +131,12 @@ fn parse_args() -> Result<Args, Error> {
                 .takes_value(true)
                 .help("Maximum number of queries per second per read worker (default is no limit)"),
         )
+        .arg(
+            Arg::with_name("SERVER_QUERY_RAMP_UP_SECS")
+                .long("server-query-ramp-up-secs")
+                .takes_value(true)
+                .help("Number of seconds over which to start the workers, one at a time (default 0)"),
+        )
         .arg(
             Arg::with_name("SERVER_WRITE_ADDR")
                 .long("server-write-addr")
@@ -115,6 +161,12 @@ fn parse_args() -> Result<Args, Error> {
                 .takes_value(true)
                 .help("Maximum number of sketches to insert per second per worker (default is no limit)"),
         )
+        .arg(
+            Arg::with_name("SERVER_WRITE_RAMP_UP_SECS")
+                .long("server-write-ramp-up-secs")
+                .takes_value(true)
+                .help("Number of seconds over which to start the workers, one at a time (default 0)"),
+        )
         .get_matches();
 
     let report_sample_interval = matches
@@ -122,19 +174,96 @@ fn parse_args() -> Result<Args, Error> {
         .unwrap_or("60")
         .parse::<u64>()?;
 
-    let daemon_writer_config = parse_daemon_writer_args(&matches)?;
+    let report_output = parse_report_output(matches.value_of("REPORT_OUTPUT").unwrap_or("log"))?;
+
+    let duration = match matches.value_of("DURATION_SECS").map(|d| d.parse::<u64>()) {
+        None => None,
+        Some(Ok(d)) => Some(Duration::from_secs(d)),
+        Some(Err(err)) => return Err(From::from(err)),
+    };
+
+    let total_rate_limit = match matches.value_of("TOTAL_RATE").map(|r| r.parse::<usize>()) {
+        None => None,
+        Some(Ok(0)) => return Err(Error::ArgError("TOTAL_RATE must be > 0")),
+        Some(Ok(r)) => Some(r),
+        Some(Err(err)) => return Err(From::from(err)),
+    };
+
+    let value_distribution = match matches.value_of("VALUE_DISTRIBUTION") {
+        None => None,
+        Some(s) => Some(parse_value_distribution(s)?),
+    };
+
+    let daemon_writer_config = parse_daemon_writer_args(&matches, value_distribution)?;
     let server_reader_config = parse_server_reader_args(&matches)?;
-    let server_writer_config = parse_server_writer_args(&matches)?;
+    let server_writer_config = parse_server_writer_args(&matches, value_distribution)?;
 
     Ok(Args {
         report_sample_interval,
+        report_output,
+        duration,
+        total_rate_limit,
         daemon_writer_config,
         server_reader_config,
         server_writer_config,
     })
 }
 
-fn parse_daemon_writer_args(matches: &ArgMatches) -> Result<DaemonWriterConfig, Error> {
+fn parse_report_output(s: &str) -> Result<ReportOutput, Error> {
+    if s == "log" {
+        Ok(ReportOutput::Log)
+    } else if s.starts_with("csv:") && s.len() > "csv:".len() {
+        Ok(ReportOutput::Csv(s["csv:".len()..].to_string()))
+    } else if s.starts_with("json:") && s.len() > "json:".len() {
+        Ok(ReportOutput::Json(s["json:".len()..].to_string()))
+    } else {
+        Err(Error::ArgError(
+            "REPORT_OUTPUT must be `log`, `csv:<path>`, or `json:<path>`",
+        ))
+    }
+}
+
+fn parse_value_distribution(s: &str) -> Result<ValueDistribution, Error> {
+    let err = Error::ArgError(
+        "VALUE_DISTRIBUTION must be `uniform:<min>,<max>`, `normal:<mean>,<stddev>`, or `lognormal:<mu>,<sigma>`",
+    );
+    let mut parts = s.splitn(2, ':');
+    let name = parts.next().unwrap_or("");
+    let params: Vec<&str> = match parts.next() {
+        Some(p) => p.split(',').collect(),
+        None => return Err(err),
+    };
+    if params.len() != 2 {
+        return Err(err);
+    }
+    match name {
+        "uniform" => {
+            let min = params[0].parse::<u32>()?;
+            let max = params[1].parse::<u32>()?;
+            if min >= max {
+                return Err(Error::ArgError("Uniform min must be less than max"));
+            }
+            Ok(ValueDistribution::uniform(min, max))
+        }
+        "normal" | "lognormal" => {
+            let (a, b) = match (params[0].parse::<f64>(), params[1].parse::<f64>()) {
+                (Ok(a), Ok(b)) if a.is_finite() && b.is_finite() && b >= 0.0 => (a, b),
+                _ => return Err(err),
+            };
+            if name == "normal" {
+                Ok(ValueDistribution::normal(a, b))
+            } else {
+                Ok(ValueDistribution::lognormal(a, b))
+            }
+        }
+        _ => Err(err),
+    }
+}
+
+fn parse_daemon_writer_args(
+    matches: &ArgMatches,
+    value_distribution: Option<ValueDistribution>,
+) -> Result<DaemonWriterConfig, Error> {
     let addr = matches
         .value_of("DAEMON_WRITE_ADDR")
         .unwrap_or(&"127.0.0.1:8001".to_string())
@@ -164,11 +293,18 @@ fn parse_daemon_writer_args(matches: &ArgMatches) -> Result<DaemonWriterConfig,
         Some(Err(err)) => return Err(From::from(err)),
     };
 
+    let ramp_up_secs = matches
+        .value_of("DAEMON_WRITE_RAMP_UP_SECS")
+        .unwrap_or("0")
+        .parse::<u64>()?;
+
     Ok(DaemonWriterConfig {
         addr,
         num_workers,
         num_metrics,
+        value_distribution: value_distribution.unwrap_or(ValueDistribution::uniform(0, 5000)),
         rate_limit,
+        ramp_up_secs,
     })
 }
 
@@ -199,15 +335,24 @@ fn parse_server_reader_args(matches: &ArgMatches) -> Result<ServerReaderConfig,
         Some(Err(err)) => return Err(From::from(err)),
     };
 
+    let ramp_up_secs = matches
+        .value_of("SERVER_QUERY_RAMP_UP_SECS")
+        .unwrap_or("0")
+        .parse::<u64>()?;
+
     Ok(ServerReaderConfig {
         addr,
         num_workers,
         query_file_path,
         rate_limit,
+        ramp_up_secs,
     })
 }
 
-fn parse_server_writer_args(matches: &ArgMatches) -> Result<ServerWriterConfig, Error> {
+fn parse_server_writer_args(
+    matches: &ArgMatches,
+    value_distribution: Option<ValueDistribution>,
+) -> Result<ServerWriterConfig, Error> {
     let addr = matches
         .value_of("SERVER_WRITE_ADDR")
         .unwrap_or(&"127.0.0.1:8001".to_string())
@@ -234,10 +379,17 @@ fn parse_server_writer_args(matches: &ArgMatches) -> Result<ServerWriterConfig,
         Some(Err(err)) => return Err(From::from(err)),
     };
 
+    let ramp_up_secs = matches
+        .value_of("SERVER_WRITE_RAMP_UP_SECS")
+        .unwrap_or("0")
+        .parse::<u64>()?;
+
     Ok(ServerWriterConfig {
         addr,
         num_workers,
         sketch_size,
+        value_distribution: value_distribution.unwrap_or(ValueDistribution::uniform(0, 10000)),
         rate_limit,
+        ramp_up_secs,
     })
 }
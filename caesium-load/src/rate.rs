@@ -1,9 +1,15 @@
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+// Tokens accumulate for at most this fraction of a second,
+// so an idle period is not followed by a large burst
+const MAX_BURST_SECS: f64 = 0.1;
 
 pub struct RateLimiter {
     limit: Option<usize>,
     count: usize,
     start: SystemTime,
+    shared: Option<SharedRateLimiter>,
 }
 
 impl RateLimiter {
@@ -12,10 +18,33 @@ impl RateLimiter {
             limit,
             count: 0,
             start: SystemTime::now(),
+            shared: None,
         }
     }
 
-    pub fn increment(&mut self) {
+    // Also limit by a rate shared with other workers.
+    // This worker's own limit still applies as a ceiling.
+    pub fn with_shared(mut self, shared: Option<SharedRateLimiter>) -> RateLimiter {
+        self.shared = shared;
+        self
+    }
+
+    // Returns true and counts the operation if it is allowed by both
+    // this worker's limit and the shared limit
+    pub fn try_acquire(&mut self) -> bool {
+        if !self.is_within_limit() {
+            return false;
+        }
+        if let Some(ref shared) = self.shared {
+            if !shared.try_acquire() {
+                return false;
+            }
+        }
+        self.increment();
+        true
+    }
+
+    fn increment(&mut self) {
         if self.is_within_window() {
             self.count += 1;
         } else {
@@ -24,7 +53,7 @@ impl RateLimiter {
         }
     }
 
-    pub fn is_within_limit(&self) -> bool {
+    fn is_within_limit(&self) -> bool {
         match self.limit {
             None => true,
             Some(limit) => self.count < limit || !self.is_within_window(),
@@ -38,3 +67,134 @@ impl RateLimiter {
         }
     }
 }
+
+// Token bucket shared by all workers to enforce a total rate
+#[derive(Clone)]
+pub struct SharedRateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl SharedRateLimiter {
+    pub fn new(rate_per_sec: usize) -> SharedRateLimiter {
+        SharedRateLimiter {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(rate_per_sec, Instant::now()))),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        self.bucket
+            .lock()
+            .expect("Could not acquire lock on token bucket")
+            .try_take(now)
+    }
+}
+
+struct TokenBucket {
+    rate_per_sec: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_per_sec: usize, now: Instant) -> TokenBucket {
+        assert!(rate_per_sec > 0);
+        let rate_per_sec = rate_per_sec as f64;
+        TokenBucket {
+            rate_per_sec,
+            capacity: (rate_per_sec * MAX_BURST_SECS).max(1.0),
+            tokens: 0.0,
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        if now > self.last_refill {
+            let elapsed = now - self.last_refill;
+            let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+            self.tokens = (self.tokens + secs * self.rate_per_sec).min(self.capacity);
+            self.last_refill = now;
+        }
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn it_converges_on_total_rate_across_workers() {
+        let start = Instant::now();
+        let shared = SharedRateLimiter {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(1000, start))),
+        };
+        let mut counts = [0; 4];
+        for ms in 1..=2000 {
+            let now = start + Duration::from_millis(ms);
+            // Vary which worker goes first, as the event loop would
+            for i in 0..counts.len() {
+                let idx = (ms as usize + i) % counts.len();
+                if shared.try_acquire_at(now) {
+                    counts[idx] += 1;
+                }
+            }
+        }
+        let total: usize = counts.iter().sum();
+        assert!((1990..=2000).contains(&total), "total was {}", total);
+        for &c in counts.iter() {
+            assert!((450..=550).contains(&c), "worker count was {}", c);
+        }
+    }
+
+    #[test]
+    fn it_limits_burst_after_idle_period() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        let now = start + Duration::from_secs(10);
+        let taken = (0..1000).filter(|_| bucket.try_take(now)).count();
+        assert_eq!(taken, 100);
+    }
+
+    #[test]
+    fn it_allows_low_rates() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1, start);
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+        assert!(bucket.try_take(start + Duration::from_millis(1000)));
+        assert!(!bucket.try_take(start + Duration::from_millis(1001)));
+    }
+
+    #[test]
+    fn it_applies_worker_limit_as_ceiling() {
+        let shared = SharedRateLimiter::new(1_000_000);
+        let mut limiter = RateLimiter::new(Some(5)).with_shared(Some(shared));
+        thread_sleep_ms(10);
+        let acquired = (0..100).filter(|_| limiter.try_acquire()).count();
+        assert_eq!(acquired, 5);
+    }
+
+    #[test]
+    fn it_applies_shared_limit_to_unlimited_worker() {
+        let shared = SharedRateLimiter::new(100);
+        let mut limiter = RateLimiter::new(None).with_shared(Some(shared));
+        assert!(!limiter.try_acquire());
+        thread_sleep_ms(50);
+        let acquired = (0..100).filter(|_| limiter.try_acquire()).count();
+        assert!((4..=10).contains(&acquired), "acquired {}", acquired);
+    }
+
+    fn thread_sleep_ms(ms: u64) {
+        ::std::thread::sleep(Duration::from_millis(ms));
+    }
+}
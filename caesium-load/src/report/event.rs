@@ -10,6 +10,11 @@ pub enum Event {
     ErrorEvent {
         event_ts: Timespec,
     },
+    WorkerErrorEvent {
+        event_ts: Timespec,
+        worker_id: usize,
+        error_kind: String,
+    },
     QuerySentEvent {
         event_ts: Timespec,
         worker_id: usize,
@@ -41,6 +46,14 @@ impl Event {
         }
     }
 
+    pub fn worker_error_event(worker_id: usize, error_kind: String) -> Event {
+        Event::WorkerErrorEvent {
+            event_ts: get_time(),
+            worker_id,
+            error_kind,
+        }
+    }
+
     pub fn query_sent_event(worker_id: usize, query_id: usize) -> Event {
         Event::QuerySentEvent {
             event_ts: get_time(),
@@ -62,6 +75,11 @@ impl Event {
             Event::MetricSentEvent { event_ts } => *event_ts,
             Event::SketchSentEvent { event_ts } => *event_ts,
             Event::ErrorEvent { event_ts } => *event_ts,
+            Event::WorkerErrorEvent {
+                event_ts,
+                worker_id: _,
+                error_kind: _,
+            } => *event_ts,
             Event::QuerySentEvent {
                 event_ts,
                 worker_id: _,
@@ -1,6 +1,6 @@
 use report::event::Event;
 use report::sink::ReportSink;
-use report::tracker::{CountTracker, QueryTracker, RateTracker};
+use report::tracker::{CountTracker, ErrorTracker, QueryTracker, RateTracker};
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
 use time::Timespec;
@@ -10,6 +10,7 @@ pub struct Reporter {
     metric_insert_tracker: RateTracker,
     sketch_insert_tracker: RateTracker,
     error_tracker: CountTracker,
+    worker_error_tracker: ErrorTracker,
     query_tracker: QueryTracker,
     sample_interval_sec: u64,
     last_flush_ts: Option<Timespec>,
@@ -21,12 +22,14 @@ impl Reporter {
         let metric_insert_tracker = RateTracker::new("Metric".to_string());
         let sketch_insert_tracker = RateTracker::new("Sketch".to_string());
         let error_tracker = CountTracker::new("Error".to_string());
+        let worker_error_tracker = ErrorTracker::new(sample_interval_sec);
         let query_tracker = QueryTracker::new();
         Reporter {
             rx,
             metric_insert_tracker,
             sketch_insert_tracker,
             error_tracker,
+            worker_error_tracker,
             query_tracker,
             sample_interval_sec,
             last_flush_ts: None,
@@ -53,7 +56,7 @@ impl Reporter {
         T: ReportSink,
     {
         let event_ts = event.get_ts();
-        if let None = self.last_flush_ts {
+        if self.last_flush_ts.is_none() {
             self.set_last_flush_ts(event_ts);
         }
 
@@ -62,7 +65,11 @@ impl Reporter {
             self.metric_insert_tracker.flush(&mut *sink);
             self.sketch_insert_tracker.flush(&mut *sink);
             self.error_tracker.flush(&mut *sink);
+            self.worker_error_tracker.flush(&mut *sink);
             self.query_tracker.flush(&mut *sink);
+            if let Err(err) = sink.flush() {
+                error!("Could not flush report sink: {:?}", err);
+            }
             self.set_last_flush_ts(event_ts);
         }
 
@@ -76,6 +83,14 @@ impl Reporter {
             Event::ErrorEvent { event_ts: _ } => {
                 self.error_tracker.increment();
             }
+            Event::WorkerErrorEvent {
+                event_ts: _,
+                worker_id,
+                error_kind,
+            } => {
+                debug!("Worker {} reported error: {}", worker_id, error_kind);
+                self.worker_error_tracker.track_error(worker_id);
+            }
             Event::QuerySentEvent {
                 event_ts,
                 worker_id,
@@ -116,7 +131,8 @@ impl Reporter {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use report::sink::MemorySink;
+    use caesium_core::time::clock::MockClock;
+    use report::sink::{CsvSink, JsonSink, MemorySink};
     use std::sync::mpsc::channel;
     use std::thread;
 
@@ -165,6 +181,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_flushes_worker_error_report_at_end_of_interval() {
+        let (tx, rx) = channel();
+        let r = Reporter::new(rx, 1);
+        let sink = Arc::new(Mutex::new(MemorySink::new()));
+        let sink_ref = sink.clone();
+        let thread = thread::spawn(|| r.run(sink_ref));
+        for &(secs, worker_id) in [(0, 1), (0, 1), (0, 2), (1, 2)].iter() {
+            tx.send(Event::WorkerErrorEvent {
+                event_ts: Timespec::new(secs, 0),
+                worker_id,
+                error_kind: "ConnectionReset".to_string(),
+            })
+            .unwrap();
+        }
+        tx.send(Event::MetricSentEvent {
+            event_ts: Timespec::new(2, 0),
+        })
+        .unwrap();
+        drop(tx);
+        thread.join().expect("Could not join thread");
+
+        {
+            let s = sink.lock().expect("Could not acquire lock on sink");
+            let measurements = s.get_worker_error_measurements();
+            assert_eq!(measurements, &[(1, 2.0), (2, 1.0), (2, 1.0)]);
+        }
+    }
+
     #[test]
     fn it_flushes_query_report_at_end_of_interval() {
         let (tx, rx) = channel();
@@ -225,4 +270,91 @@ mod tests {
             assert_eq!(sample_counts, &[1, 2]);
         }
     }
+
+    #[test]
+    fn it_writes_csv_report_at_end_of_interval() {
+        let (tx, rx) = channel();
+        let r = Reporter::new(rx, 1);
+        let sink = Arc::new(Mutex::new(CsvSink::new(Vec::new())));
+        let sink_ref = sink.clone();
+        let thread = thread::spawn(|| r.run(sink_ref));
+        tx.send(Event::MetricSentEvent {
+            event_ts: Timespec::new(0, 0),
+        })
+        .unwrap();
+        tx.send(Event::QuerySentEvent {
+            event_ts: Timespec::new(0, 10),
+            worker_id: 0,
+            query_id: 0,
+        })
+        .unwrap();
+        tx.send(Event::QueryBytesReceivedEvent {
+            event_ts: Timespec::new(0, 20),
+            worker_id: 0,
+            query_id: 0,
+        })
+        .unwrap();
+        tx.send(Event::SketchSentEvent {
+            event_ts: Timespec::new(1, 0),
+        })
+        .unwrap();
+        tx.send(Event::MetricSentEvent {
+            event_ts: Timespec::new(2, 0),
+        })
+        .unwrap();
+        drop(tx);
+        thread.join().expect("Could not join thread");
+
+        {
+            let s = sink.lock().expect("Could not acquire lock on sink");
+            let out = String::from_utf8(s.get_ref().clone()).expect("Could not decode CSV");
+            let rows: Vec<Vec<&str>> = out.lines().map(|l| l.split(',').collect()).collect();
+            let header_len = rows[0].len();
+            assert!(rows.iter().all(|r| r.len() == header_len));
+            let measurements: Vec<(&str, &str, &str)> =
+                rows[1..].iter().map(|r| (r[0], r[1], r[2])).collect();
+            assert_eq!(
+                measurements,
+                vec![
+                    ("0", "rate", "Metric"),
+                    ("0", "count", "Error"),
+                    ("0", "query_duration", "0"),
+                    ("1", "rate", "Sketch"),
+                    ("1", "count", "Error"),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn it_writes_json_report_at_end_of_interval() {
+        let (tx, rx) = channel();
+        let r = Reporter::new(rx, 1);
+        let sink = Arc::new(Mutex::new(JsonSink::new(Vec::new(), MockClock::new(60))));
+        let sink_ref = sink.clone();
+        let thread = thread::spawn(|| r.run(sink_ref));
+        tx.send(Event::MetricSentEvent {
+            event_ts: Timespec::new(0, 0),
+        })
+        .unwrap();
+        tx.send(Event::SketchSentEvent {
+            event_ts: Timespec::new(1, 0),
+        })
+        .unwrap();
+        tx.send(Event::MetricSentEvent {
+            event_ts: Timespec::new(2, 0),
+        })
+        .unwrap();
+        drop(tx);
+        thread.join().expect("Could not join thread");
+
+        {
+            let s = sink.lock().expect("Could not acquire lock on sink");
+            let out = String::from_utf8(s.get_ref().clone()).expect("Could not decode JSON");
+            let lines: Vec<&str> = out.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].starts_with("{\"timestamp\":60,\"interval\":0,\"rates\":{\"Metric\":"));
+            assert!(lines[1].starts_with("{\"timestamp\":60,\"interval\":1,\"rates\":{\"Sketch\":"));
+        }
+    }
 }
@@ -1,10 +1,19 @@
+use caesium_core::time::clock::Clock;
 use report::summary::StatSummary;
+use std::io;
+use std::io::Write;
 use time::Duration;
 
 pub trait ReportSink {
     fn write_rate(&mut self, name: &str, num_per_sec: f64);
     fn write_count(&mut self, name: &str, count: usize);
+    fn write_worker_error_rate(&mut self, worker_id: usize, errors_per_sec: f64);
     fn write_query_duration(&mut self, query_id: usize, summary: StatSummary<Duration>);
+
+    // Called by the reporter after all measurements for a sample interval are written
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
 }
 
 pub struct LogSink {}
@@ -24,18 +33,218 @@ impl ReportSink for LogSink {
         info!("{} count was {}", name, count);
     }
 
+    fn write_worker_error_rate(&mut self, worker_id: usize, errors_per_sec: f64) {
+        info!("Worker {} error rate: {}/s", worker_id, errors_per_sec);
+    }
+
     fn write_query_duration(&mut self, query_id: usize, summary: StatSummary<Duration>) {
+        let note = if summary.is_small_sample() && summary.sample_count() > 0 {
+            " (too few samples for reliable p95/p99)"
+        } else {
+            ""
+        };
         info!(
-            "Query {} time-to-first-byte summary: sample_count={}, median={:?}, 95th={:?}, min={:?}, max={:?}",
-            query_id, summary.sample_count(), summary.median(), summary.ninety_fifth_percentile(), summary.min(), summary.max()
+            "Query {} time-to-first-byte summary: sample_count={}, median={:?}, p95={:?}, p99={:?}, min={:?}, max={:?}{}",
+            query_id, summary.sample_count(), summary.median(), summary.p95(), summary.p99(), summary.min(), summary.max(), note
         );
     }
 }
 
+const CSV_HEADER: &str =
+    "interval,measurement,name,value,sample_count,median_us,p95_us,p99_us,min_us,max_us";
+
+// Writes measurements as CSV rows, one per measurement.
+// Rows are buffered until the end of each sample interval,
+// and the header is written on the first flush.
+pub struct CsvSink<W: Write> {
+    out: W,
+    interval: usize,
+    rows: Vec<String>,
+    wrote_header: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(out: W) -> CsvSink<W> {
+        CsvSink {
+            out,
+            interval: 0,
+            rows: Vec::new(),
+            wrote_header: false,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    fn push_row(&mut self, measurement: &str, name: &str, value: &str, durations: &str) {
+        self.rows.push(format!(
+            "{},{},{},{},{}",
+            self.interval, measurement, name, value, durations
+        ));
+    }
+
+    fn format_durations(summary: &StatSummary<Duration>) -> String {
+        let fmt = |d: Option<Duration>| {
+            d.and_then(|d| d.num_microseconds())
+                .map(|us| us.to_string())
+                .unwrap_or_default()
+        };
+        format!(
+            "{},{},{},{},{},{}",
+            summary.sample_count(),
+            fmt(summary.median()),
+            fmt(summary.p95()),
+            fmt(summary.p99()),
+            fmt(summary.min()),
+            fmt(summary.max())
+        )
+    }
+}
+
+impl<W: Write> ReportSink for CsvSink<W> {
+    fn write_rate(&mut self, name: &str, num_per_sec: f64) {
+        self.push_row("rate", name, &num_per_sec.to_string(), ",,,,,");
+    }
+
+    fn write_count(&mut self, name: &str, count: usize) {
+        self.push_row("count", name, &count.to_string(), ",,,,,");
+    }
+
+    fn write_worker_error_rate(&mut self, worker_id: usize, errors_per_sec: f64) {
+        self.push_row(
+            "worker_error_rate",
+            &worker_id.to_string(),
+            &errors_per_sec.to_string(),
+            ",,,,,",
+        );
+    }
+
+    fn write_query_duration(&mut self, query_id: usize, summary: StatSummary<Duration>) {
+        let durations = CsvSink::<W>::format_durations(&summary);
+        self.push_row("query_duration", &query_id.to_string(), "", &durations);
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        if !self.wrote_header {
+            writeln!(self.out, "{}", CSV_HEADER)?;
+            self.wrote_header = true;
+        }
+        for row in self.rows.drain(..) {
+            writeln!(self.out, "{}", row)?;
+        }
+        self.interval += 1;
+        self.out.flush()
+    }
+}
+
+// Writes one JSON object per sample interval, on its own line, with the
+// time of the flush and every measurement written during the interval.
+pub struct JsonSink<W: Write, C: Clock> {
+    out: W,
+    clock: C,
+    interval: usize,
+    rates: Vec<String>,
+    counts: Vec<String>,
+    worker_error_rates: Vec<String>,
+    query_durations: Vec<String>,
+}
+
+impl<W: Write, C: Clock> JsonSink<W, C> {
+    pub fn new(out: W, clock: C) -> JsonSink<W, C> {
+        JsonSink {
+            out,
+            clock,
+            interval: 0,
+            rates: Vec::new(),
+            counts: Vec::new(),
+            worker_error_rates: Vec::new(),
+            query_durations: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    fn format_durations(summary: &StatSummary<Duration>) -> String {
+        let fmt = |d: Option<Duration>| {
+            d.and_then(|d| d.num_microseconds())
+                .map(|us| us.to_string())
+                .unwrap_or_else(|| "null".to_string())
+        };
+        format!(
+            "{{\"sample_count\":{},\"median_us\":{},\"p95_us\":{},\"p99_us\":{},\"min_us\":{},\"max_us\":{}}}",
+            summary.sample_count(),
+            fmt(summary.median()),
+            fmt(summary.p95()),
+            fmt(summary.p99()),
+            fmt(summary.min()),
+            fmt(summary.max())
+        )
+    }
+}
+
+impl<W: Write, C: Clock> ReportSink for JsonSink<W, C> {
+    fn write_rate(&mut self, name: &str, num_per_sec: f64) {
+        self.rates
+            .push(format!("\"{}\":{}", name, format_json_number(num_per_sec)));
+    }
+
+    fn write_count(&mut self, name: &str, count: usize) {
+        self.counts.push(format!("\"{}\":{}", name, count));
+    }
+
+    fn write_worker_error_rate(&mut self, worker_id: usize, errors_per_sec: f64) {
+        self.worker_error_rates.push(format!(
+            "\"{}\":{}",
+            worker_id,
+            format_json_number(errors_per_sec)
+        ));
+    }
+
+    fn write_query_duration(&mut self, query_id: usize, summary: StatSummary<Duration>) {
+        let durations = JsonSink::<W, C>::format_durations(&summary);
+        self.query_durations
+            .push(format!("\"{}\":{}", query_id, durations));
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        writeln!(
+            self.out,
+            "{{\"timestamp\":{},\"interval\":{},\"rates\":{{{}}},\"counts\":{{{}}},\"worker_error_rates\":{{{}}},\"query_durations\":{{{}}}}}",
+            self.clock.now(),
+            self.interval,
+            self.rates.join(","),
+            self.counts.join(","),
+            self.worker_error_rates.join(","),
+            self.query_durations.join(",")
+        )?;
+        self.rates.clear();
+        self.counts.clear();
+        self.worker_error_rates.clear();
+        self.query_durations.clear();
+        self.interval += 1;
+        self.out.flush()
+    }
+}
+
+// JSON has no representation for NaN or infinity
+fn format_json_number(v: f64) -> String {
+    if v.is_finite() {
+        v.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
 #[cfg(test)]
 pub struct MemorySink {
     rate_measurements: Vec<f64>,
     count_measurements: Vec<usize>,
+    worker_error_measurements: Vec<(usize, f64)>,
     query_measurements: Vec<(usize, StatSummary<Duration>)>,
 }
 
@@ -45,6 +254,7 @@ impl MemorySink {
         MemorySink {
             rate_measurements: Vec::new(),
             count_measurements: Vec::new(),
+            worker_error_measurements: Vec::new(),
             query_measurements: Vec::new(),
         }
     }
@@ -57,6 +267,10 @@ impl MemorySink {
         &self.count_measurements
     }
 
+    pub fn get_worker_error_measurements(&self) -> &[(usize, f64)] {
+        &self.worker_error_measurements
+    }
+
     pub fn get_query_measurements(&self) -> &[(usize, StatSummary<Duration>)] {
         &self.query_measurements
     }
@@ -72,7 +286,83 @@ impl ReportSink for MemorySink {
         self.count_measurements.push(count);
     }
 
+    fn write_worker_error_rate(&mut self, worker_id: usize, errors_per_sec: f64) {
+        self.worker_error_measurements
+            .push((worker_id, errors_per_sec));
+    }
+
     fn write_query_duration(&mut self, query_id: usize, summary: StatSummary<Duration>) {
         self.query_measurements.push((query_id, summary))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use caesium_core::time::clock::MockClock;
+
+    #[test]
+    fn it_writes_header_on_first_flush() {
+        let mut s = CsvSink::new(Vec::new());
+        s.write_rate("Metric", 2.0);
+        assert!(s.get_ref().is_empty());
+        s.flush().expect("Could not flush");
+        s.write_count("Error", 3);
+        s.flush().expect("Could not flush");
+        let out = String::from_utf8(s.get_ref().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            vec![CSV_HEADER, "0,rate,Metric,2,,,,,,", "1,count,Error,3,,,,,,"]
+        );
+    }
+
+    #[test]
+    fn it_writes_json_object_per_flush() {
+        let mut s = JsonSink::new(Vec::new(), MockClock::new(1000));
+        s.write_rate("Metric", 2.5);
+        s.write_count("Error", 3);
+        s.write_worker_error_rate(1, 0.5);
+        let summary = StatSummary::new(vec![Duration::milliseconds(1), Duration::milliseconds(3)]);
+        s.write_query_duration(7, summary);
+        s.flush().expect("Could not flush");
+        s.write_query_duration(7, StatSummary::new(Vec::new()));
+        s.flush().expect("Could not flush");
+        let out = String::from_utf8(s.get_ref().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                concat!(
+                    "{\"timestamp\":1000,\"interval\":0,\"rates\":{\"Metric\":2.5},",
+                    "\"counts\":{\"Error\":3},\"worker_error_rates\":{\"1\":0.5},",
+                    "\"query_durations\":{\"7\":{\"sample_count\":2,\"median_us\":3000,",
+                    "\"p95_us\":3000,\"p99_us\":3000,\"min_us\":1000,\"max_us\":3000}}}"
+                ),
+                concat!(
+                    "{\"timestamp\":1000,\"interval\":1,\"rates\":{},\"counts\":{},",
+                    "\"worker_error_rates\":{},\"query_durations\":{\"7\":{\"sample_count\":0,",
+                    "\"median_us\":null,\"p95_us\":null,\"p99_us\":null,\"min_us\":null,",
+                    "\"max_us\":null}}}"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_writes_non_finite_rates_as_null() {
+        assert_eq!(format_json_number(1.0 / 0.0), "null");
+        assert_eq!(format_json_number(2.0), "2");
+    }
+
+    #[test]
+    fn it_writes_query_duration_summary() {
+        let mut s = CsvSink::new(Vec::new());
+        let summary = StatSummary::new(vec![Duration::milliseconds(1), Duration::milliseconds(3)]);
+        s.write_query_duration(7, summary);
+        s.flush().expect("Could not flush");
+        let out = String::from_utf8(s.get_ref().clone()).unwrap();
+        let row = out.lines().nth(1).expect("Expected query duration row");
+        assert_eq!(row, "0,query_duration,7,,2,3000,3000,3000,1000,3000");
+    }
+}
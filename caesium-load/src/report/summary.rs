@@ -1,9 +1,11 @@
+use std::cmp::min;
+
+// Tail percentiles of fewer samples than this are close to or equal to the max,
+// so reports flag them as unreliable.
+pub const MIN_TAIL_SAMPLE_COUNT: usize = 20;
+
 pub struct StatSummary<T> {
-    sample_count: usize,
-    median: Option<T>,
-    ninety_fifth_percentile: Option<T>,
-    min: Option<T>,
-    max: Option<T>,
+    sorted_samples: Vec<T>,
 }
 
 impl<T> StatSummary<T>
@@ -13,51 +15,51 @@ where
     pub fn new(mut samples: Vec<T>) -> StatSummary<T> {
         samples.sort_unstable();
         StatSummary {
-            sample_count: samples.len(),
-            median: StatSummary::calculate_quantile(&samples, 0.5),
-            ninety_fifth_percentile: StatSummary::calculate_quantile(&samples, 0.95),
-            min: StatSummary::calculate_min(&samples),
-            max: StatSummary::calculate_max(&samples),
+            sorted_samples: samples,
         }
     }
 
     pub fn sample_count(&self) -> usize {
-        self.sample_count
+        self.sorted_samples.len()
+    }
+
+    // Whether there are too few samples for tail percentiles to be meaningful
+    pub fn is_small_sample(&self) -> bool {
+        self.sample_count() < MIN_TAIL_SAMPLE_COUNT
     }
 
     pub fn median(&self) -> Option<T> {
-        self.median
+        self.percentile(0.5)
     }
 
-    pub fn ninety_fifth_percentile(&self) -> Option<T> {
-        self.ninety_fifth_percentile
+    pub fn p95(&self) -> Option<T> {
+        self.percentile(0.95)
+    }
+
+    pub fn p99(&self) -> Option<T> {
+        self.percentile(0.99)
     }
 
     pub fn min(&self) -> Option<T> {
-        self.min
+        self.sorted_samples.first().copied()
     }
 
     pub fn max(&self) -> Option<T> {
-        self.max
+        self.sorted_samples.last().copied()
     }
 
-    fn calculate_quantile(sorted_samples: &[T], phi: f64) -> Option<T> {
-        assert!(phi > 0.0 && phi < 1.0);
-        if sorted_samples.len() == 0 {
+    // Returns the sample at rank `phi * sample_count`, where `phi` is between 0 and 1 (inclusive).
+    // With few samples, high percentiles are the max sample.
+    pub fn percentile(&self, phi: f64) -> Option<T> {
+        assert!((0.0..=1.0).contains(&phi));
+        let n = self.sorted_samples.len();
+        if n == 0 {
             None
         } else {
-            let idx = (sorted_samples.len() as f64 * phi) as usize;
-            Some(sorted_samples[idx])
+            let idx = min((n as f64 * phi) as usize, n - 1);
+            Some(self.sorted_samples[idx])
         }
     }
-
-    fn calculate_min(sorted_samples: &[T]) -> Option<T> {
-        sorted_samples.iter().next().map(|s| *s)
-    }
-
-    fn calculate_max(sorted_samples: &[T]) -> Option<T> {
-        sorted_samples.iter().last().map(|s| *s)
-    }
 }
 
 #[cfg(test)]
@@ -65,6 +67,7 @@ mod tests {
     use super::*;
     use rand;
     use rand::Rng;
+    use std::time::Duration;
 
     #[test]
     fn it_summarizes_empty_set() {
@@ -72,7 +75,8 @@ mod tests {
         let s = StatSummary::<u32>::new(values);
         assert_eq!(s.sample_count(), 0);
         assert_eq!(s.median(), None);
-        assert_eq!(s.ninety_fifth_percentile(), None);
+        assert_eq!(s.p95(), None);
+        assert_eq!(s.p99(), None);
         assert_eq!(s.min(), None);
         assert_eq!(s.max(), None);
     }
@@ -83,7 +87,8 @@ mod tests {
         let s = StatSummary::<u32>::new(values);
         assert_eq!(s.sample_count(), 1);
         assert_eq!(s.median(), Some(5));
-        assert_eq!(s.ninety_fifth_percentile(), Some(5));
+        assert_eq!(s.p95(), Some(5));
+        assert_eq!(s.p99(), Some(5));
         assert_eq!(s.min(), Some(5));
         assert_eq!(s.max(), Some(5));
     }
@@ -98,8 +103,64 @@ mod tests {
         let s = StatSummary::<u32>::new(values);
         assert_eq!(s.sample_count(), 100);
         assert_eq!(s.median(), Some(50));
-        assert_eq!(s.ninety_fifth_percentile(), Some(95));
+        assert_eq!(s.p95(), Some(95));
+        assert_eq!(s.p99(), Some(99));
         assert_eq!(s.min(), Some(0));
         assert_eq!(s.max(), Some(99));
     }
+
+    #[test]
+    fn it_summarizes_tail_percentiles() {
+        let mut values: Vec<u32> = (1..=1000).collect();
+        rand::thread_rng().shuffle(&mut values);
+        let s = StatSummary::<u32>::new(values);
+        assert_eq!(s.p95(), Some(951));
+        assert_eq!(s.p99(), Some(991));
+    }
+
+    #[test]
+    fn it_summarizes_arbitrary_percentiles() {
+        let values: Vec<Duration> = vec![12, 3, 7, 1, 20, 15, 9, 4, 18, 6]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect();
+        let s = StatSummary::new(values);
+        assert_eq!(s.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(s.percentile(0.25), Some(Duration::from_millis(4)));
+        assert_eq!(s.percentile(0.5), Some(Duration::from_millis(9)));
+        assert_eq!(s.percentile(0.75), Some(Duration::from_millis(15)));
+        assert_eq!(s.percentile(0.9), Some(Duration::from_millis(20)));
+        assert_eq!(s.percentile(1.0), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn it_summarizes_small_samples() {
+        let values: Vec<u32> = (1..=10).collect();
+        let s = StatSummary::<u32>::new(values);
+        assert!(s.is_small_sample());
+        assert_eq!(s.p95(), Some(10));
+        assert_eq!(s.p99(), Some(10));
+
+        let s = StatSummary::<u32>::new((1..=MIN_TAIL_SAMPLE_COUNT as u32).collect());
+        assert!(!s.is_small_sample());
+        assert_eq!(s.p95(), Some(20));
+        assert_eq!(s.percentile(0.9), Some(19));
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_rejects_out_of_range_percentile() {
+        StatSummary::<u32>::new(vec![1]).percentile(1.5);
+    }
+
+    #[test]
+    fn it_summarizes_tail_percentiles_with_outliers() {
+        let mut values = vec![10; 98];
+        values.push(500);
+        values.push(1000);
+        let s = StatSummary::<u32>::new(values);
+        assert_eq!(s.median(), Some(10));
+        assert_eq!(s.p95(), Some(10));
+        assert_eq!(s.p99(), Some(1000));
+    }
 }
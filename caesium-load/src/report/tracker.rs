@@ -1,7 +1,7 @@
 use report::sink::ReportSink;
 use report::summary::StatSummary;
 use std::cmp::{max, min};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use time::{Duration, Timespec};
 
 pub struct RateTracker {
@@ -82,6 +82,39 @@ impl CountTracker {
     }
 }
 
+// Counts errors reported by each worker during a sample interval
+pub struct ErrorTracker {
+    sample_interval_sec: u64,
+
+    // Key is worker_id
+    count_map: BTreeMap<usize, u64>,
+}
+
+impl ErrorTracker {
+    pub fn new(sample_interval_sec: u64) -> ErrorTracker {
+        assert!(sample_interval_sec > 0);
+        ErrorTracker {
+            sample_interval_sec,
+            count_map: BTreeMap::new(),
+        }
+    }
+
+    pub fn track_error(&mut self, worker_id: usize) {
+        *self.count_map.entry(worker_id).or_insert(0) += 1;
+    }
+
+    pub fn flush<T>(&mut self, sink: &mut T)
+    where
+        T: ReportSink,
+    {
+        for (worker_id, count) in self.count_map.iter() {
+            let error_rate = (*count as f64) / (self.sample_interval_sec as f64);
+            sink.write_worker_error_rate(*worker_id, error_rate);
+        }
+        self.count_map.clear();
+    }
+}
+
 pub struct QueryTracker {
     // Key is (worker_id, query_id)
     sent_ts_map: HashMap<(usize, usize), Timespec>,
@@ -117,7 +150,7 @@ impl QueryTracker {
     where
         T: ReportSink,
     {
-        for (query_id, mut durations) in self.duration_map.drain() {
+        for (query_id, durations) in self.duration_map.drain() {
             let summary = StatSummary::new(durations);
             sink.write_query_duration(query_id, summary);
         }
@@ -160,6 +193,39 @@ mod tests {
         assert_eq!(s.get_count_measurements(), &[2]);
     }
 
+    #[test]
+    fn it_tracks_worker_errors_no_data() {
+        let mut s = MemorySink::new();
+        let mut t = ErrorTracker::new(1);
+        t.flush(&mut s);
+        assert_eq!(s.get_worker_error_measurements().len(), 0);
+    }
+
+    #[test]
+    fn it_tracks_worker_error_rates() {
+        let mut s = MemorySink::new();
+        let mut t = ErrorTracker::new(2);
+        t.track_error(3);
+        t.track_error(0);
+        t.track_error(3);
+        t.track_error(3);
+        t.track_error(3);
+        t.flush(&mut s);
+        assert_eq!(s.get_worker_error_measurements(), &[(0, 0.5), (3, 2.0)]);
+    }
+
+    #[test]
+    fn it_resets_worker_errors_after_flush() {
+        let mut s = MemorySink::new();
+        let mut t = ErrorTracker::new(1);
+        t.track_error(0);
+        t.flush(&mut s);
+        t.track_error(1);
+        t.track_error(1);
+        t.flush(&mut s);
+        assert_eq!(s.get_worker_error_measurements(), &[(0, 1.0), (1, 2.0)]);
+    }
+
     #[test]
     fn it_tracks_query_ttfb_no_data() {
         let mut s = MemorySink::new();
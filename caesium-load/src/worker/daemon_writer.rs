@@ -1,7 +1,8 @@
+use distribution::ValueDistribution;
 use mio::net::UdpSocket;
 use mio::{Poll, PollOpt, Ready, Token};
 use rand::rngs::SmallRng;
-use rand::{FromEntropy, Rng};
+use rand::FromEntropy;
 use rate::RateLimiter;
 use report::event::Event;
 use std::io;
@@ -9,9 +10,6 @@ use std::net::SocketAddr;
 use std::sync::mpsc::Sender;
 use worker::Worker;
 
-const MIN_VAL: u64 = 0;
-const MAX_VAL: u64 = 5000;
-
 pub struct DaemonWriter {
     registered: bool,
     dst_addr: SocketAddr,
@@ -21,6 +19,7 @@ pub struct DaemonWriter {
     socket: UdpSocket,
     buf: Vec<u8>,
     num_written: usize,
+    distribution: ValueDistribution,
     rng: SmallRng,
     tx: Sender<Event>,
 }
@@ -30,12 +29,12 @@ impl DaemonWriter {
         dst_addr: &SocketAddr,
         metric_id: usize,
         num_metrics: usize,
-        rate_limit: Option<usize>,
+        rate_limiter: RateLimiter,
+        distribution: ValueDistribution,
         tx: Sender<Event>,
     ) -> Result<DaemonWriter, io::Error> {
         let dst_addr = dst_addr.clone();
         let addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
-        let rate_limiter = RateLimiter::new(rate_limit);
         let w = DaemonWriter {
             registered: false,
             dst_addr,
@@ -45,6 +44,7 @@ impl DaemonWriter {
             socket: UdpSocket::bind(&addr)?,
             buf: Vec::new(),
             num_written: 0,
+            distribution,
             rng: SmallRng::from_entropy(),
             tx,
         };
@@ -52,7 +52,7 @@ impl DaemonWriter {
     }
 
     fn fill_buffer(&mut self) {
-        let value: u64 = self.rng.gen_range(MIN_VAL, MAX_VAL);
+        let value = self.distribution.sample(&mut self.rng);
         let s = format!("caesium-load.{}:{}|ms", self.metric_id, value);
         self.buf.extend_from_slice(s.as_bytes());
     }
@@ -89,17 +89,15 @@ impl Worker for DaemonWriter {
     }
 
     fn write(&mut self) -> Result<(), io::Error> {
-        if !self.rate_limiter.is_within_limit() && self.buf.is_empty() {
-            return Ok(());
-        }
-
         if self.buf.is_empty() {
+            if !self.rate_limiter.try_acquire() {
+                return Ok(());
+            }
             self.fill_buffer();
         }
 
         self.num_written += self.send_until_blocked()?;
         if self.num_written == self.buf.len() {
-            self.rate_limiter.increment();
             self.tx
                 .send(Event::metric_inserted_event())
                 .expect("Could not send insert metric event");
@@ -30,7 +30,7 @@ impl ServerReader {
         dst_addr: &SocketAddr,
         queries_slice: &[String],
         query_idx: usize,
-        rate_limit: Option<usize>,
+        rate_limiter: RateLimiter,
         tx: Sender<Event>,
     ) -> ServerReader {
         assert!(queries_slice.len() > 0);
@@ -38,7 +38,6 @@ impl ServerReader {
         let dst_addr = dst_addr.clone();
         let mut queries = Vec::with_capacity(queries_slice.len());
         queries.extend_from_slice(queries_slice);
-        let rate_limiter = RateLimiter::new(rate_limit);
         ServerReader {
             id,
             dst_addr,
@@ -69,7 +68,7 @@ impl ServerReader {
                 }
             }
         }
-        return Ok(num_written);
+        Ok(num_written)
     }
 
     fn read_until_done_or_blocked(s: &mut TcpStream) -> Result<bool, io::Error> {
@@ -105,8 +104,7 @@ impl Worker for ServerReader {
             }
             Some(State::Connected(s)) => {
                 poll.reregister(&s, token, Ready::writable(), PollOpt::edge())?;
-                if self.rate_limiter.is_within_limit() {
-                    self.rate_limiter.increment();
+                if self.rate_limiter.try_acquire() {
                     Some(State::Writing(s, 0))
                 } else {
                     Some(State::Connected(s))
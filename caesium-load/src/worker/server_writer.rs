@@ -1,12 +1,13 @@
 use caesium_core::encode::frame::FrameEncoder;
-use caesium_core::protocol::messages::InsertMessage;
+use caesium_core::protocol::messages::{InsertMessage, MetricKind, WriteMessage};
 use caesium_core::quantile::writable::WritableSketch;
 use caesium_core::time::clock::Clock;
 use caesium_core::time::window::TimeWindow;
+use distribution::ValueDistribution;
 use mio::net::TcpStream;
 use mio::{Poll, PollOpt, Ready, Token};
 use rand::rngs::SmallRng;
-use rand::{FromEntropy, Rng};
+use rand::FromEntropy;
 use rate::RateLimiter;
 use report::event::Event;
 use std::io;
@@ -17,8 +18,6 @@ use uuid::Uuid;
 use worker::Worker;
 
 const WINDOW_DURATION: u64 = 10;
-const MIN_VAL: u64 = 0;
-const MAX_VAL: u64 = 10000;
 
 enum ConnectionState {
     Connected(TcpStream),
@@ -41,16 +40,16 @@ impl ServerWriter {
     pub fn new(
         dst_addr: &SocketAddr,
         sketch_size: usize,
-        rate_limit: Option<usize>,
+        distribution: ValueDistribution,
+        rate_limiter: RateLimiter,
         clock: &Clock,
         tx: Sender<Event>,
     ) -> ServerWriter {
-        let rate_limiter = RateLimiter::new(rate_limit);
         let frame_encoder = FrameEncoder::new();
         let start_ts = clock.now();
         let metric = format!("caesium-load-{}", Uuid::new_v4());
         let window = TimeWindow::new(start_ts, start_ts + WINDOW_DURATION);
-        let sketch = ServerWriter::build_sketch(sketch_size);
+        let sketch = ServerWriter::build_sketch(sketch_size, &distribution);
         ServerWriter {
             dst_addr: dst_addr.clone(),
             rate_limiter,
@@ -64,23 +63,23 @@ impl ServerWriter {
         }
     }
 
-    fn build_sketch(size: usize) -> WritableSketch {
+    fn build_sketch(size: usize, distribution: &ValueDistribution) -> WritableSketch {
         let mut rng = SmallRng::from_entropy();
         let mut sketch = WritableSketch::new();
         for _ in 0..size {
-            let v = rng.gen_range(MIN_VAL, MAX_VAL) as u32;
-            sketch.insert(v);
+            sketch.insert(distribution.sample(&mut rng));
         }
         sketch
     }
 
     fn fill_buffer(&mut self) {
         assert!(self.buf.is_empty());
-        let msg = InsertMessage {
+        let msg = WriteMessage::Insert(Box::new(InsertMessage {
             window: self.window.clone(),
             metric: self.metric.clone(),
+            kind: MetricKind::Timer,
             sketch: self.sketch.clone(),
-        };
+        }));
         self.frame_encoder
             .encode_framed_msg(&msg, &mut self.buf)
             .expect("Could not encode framed insert message");
@@ -116,8 +115,7 @@ impl Worker for ServerWriter {
             }
             Some(ConnectionState::Connected(s)) => {
                 poll.reregister(&s, token, Ready::writable(), PollOpt::edge())?;
-                if self.rate_limiter.is_within_limit() {
-                    self.rate_limiter.increment();
+                if self.rate_limiter.try_acquire() {
                     self.buf.clear();
                     self.fill_buffer();
                     Some(ConnectionState::Writing(s, 0))
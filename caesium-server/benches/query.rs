@@ -26,7 +26,7 @@ fn insert(db: &mut MockDataSource, metric: &str, start: u64, end: u64, count: us
 fn bench_quantile_query_single_row(bench: &mut Bencher) {
     let mut db = MockDataSource::new();
     insert(&mut db, "foo", 0, 30, 2048);
-    bench.iter(|| execute_query(&"quantile(fetch(\"foo\"), 0.5)", &db))
+    bench.iter(|| execute_query(&"quantile(fetch(\"foo\"), 0.5)", &db, None))
 }
 
 fn bench_quantile_query_many_rows(bench: &mut Bencher) {
@@ -36,13 +36,13 @@ fn bench_quantile_query_many_rows(bench: &mut Bencher) {
         let end = start + 30;
         insert(&mut db, "foo", start, end, 2048);
     }
-    bench.iter(|| execute_query(&"quantile(fetch(\"foo\"), 0.5)", &db))
+    bench.iter(|| execute_query(&"quantile(fetch(\"foo\"), 0.5)", &db, None))
 }
 
 fn bench_coalesce_query_single_row(bench: &mut Bencher) {
     let mut db = MockDataSource::new();
     insert(&mut db, "foo", 0, 30, 2048);
-    bench.iter(|| execute_query(&"quantile(coalesce(fetch(\"foo\")), 0.5)", &db))
+    bench.iter(|| execute_query(&"quantile(coalesce(fetch(\"foo\")), 0.5)", &db, None))
 }
 
 fn bench_coalesce_query_many_rows(bench: &mut Bencher) {
@@ -52,7 +52,7 @@ fn bench_coalesce_query_many_rows(bench: &mut Bencher) {
         let end = start + 30;
         insert(&mut db, "foo", start, end, 2048);
     }
-    bench.iter(|| execute_query(&"quantile(coalesce(fetch(\"foo\")), 0.5)", &db))
+    bench.iter(|| execute_query(&"quantile(coalesce(fetch(\"foo\")), 0.5)", &db, None))
 }
 
 fn bench_combine_query_single_row(bench: &mut Bencher) {
@@ -63,6 +63,7 @@ fn bench_combine_query_single_row(bench: &mut Bencher) {
         execute_query(
             &"quantile(combine(fetch(\"foo\"), fetch(\"bar\")), 0.5)",
             &db,
+            None,
         )
     })
 }
@@ -79,6 +80,7 @@ fn bench_combine_query_many_rows(bench: &mut Bencher) {
         execute_query(
             &"quantile(combine(fetch(\"foo\"), fetch(\"bar\")), 0.5)",
             &db,
+            None,
         )
     })
 }
@@ -1,17 +1,41 @@
+extern crate base64;
 extern crate bytes;
 extern crate caesium_core;
+extern crate chrono;
+extern crate ctrlc;
+extern crate flate2;
+extern crate libc;
+extern crate librocksdb_sys;
+extern crate lz4_flex;
 extern crate mio;
-extern crate regex;
+#[cfg(feature = "otel")]
+extern crate opentelemetry;
+#[cfg(feature = "otel")]
+extern crate opentelemetry_otlp;
+#[cfg(feature = "otel")]
+extern crate opentelemetry_sdk;
+extern crate prost;
 extern crate rocksdb;
+extern crate rustls;
+extern crate rustls_pemfile;
+extern crate serde;
+extern crate serde_json;
 extern crate slab;
+extern crate snap;
+extern crate tiny_http;
 extern crate uuid;
 
 #[macro_use]
-extern crate lazy_static;
+extern crate prost_derive;
+
+#[macro_use]
+extern crate serde_derive;
 
 #[macro_use]
 extern crate log;
 
+mod json;
+pub mod otel;
 pub mod query;
 pub mod server;
 pub mod storage;
@@ -1,83 +1,297 @@
 extern crate caesium_core;
 extern crate caesium_server;
 extern crate clap;
+extern crate ctrlc;
+extern crate rustls;
+extern crate serde;
 extern crate stackdriver_logger;
+extern crate toml;
+
+#[macro_use]
+extern crate serde_derive;
 
 #[macro_use]
 extern crate log;
 
 use caesium_core::get_sketch_type;
-use caesium_core::time::clock::{Clock, SystemClock};
+use caesium_core::quantile::scale::MAX_SCALE;
+use caesium_core::time::clock::SystemClock;
+use caesium_server::server::admin::AdminServer;
+use caesium_server::server::auth::AuthToken;
+use caesium_server::server::health::HealthServer;
+use caesium_server::server::http::HttpQueryServer;
+use caesium_server::server::metrics::{MetricsServer, ServerMetrics};
+use caesium_server::server::prometheus::PrometheusWriteServer;
 use caesium_server::server::read::ReadServer;
+use caesium_server::server::scrape::PrometheusScrapeServer;
+use caesium_server::server::shutdown::install_signal_handler;
+use caesium_server::server::tls::load_server_config;
 use caesium_server::server::write::WriteServer;
 use caesium_server::storage::downsample::strategies::DefaultStrategy;
+use caesium_server::storage::downsample::{
+    parse_retention_policy, DownsampleStrategy, RetentionTier,
+};
 use caesium_server::storage::error::StorageError;
-use caesium_server::storage::store::MetricStore;
-use clap::{App, Arg};
+use caesium_server::storage::store::{MetricStore, MetricStoreOptions, ValueCompression};
+use clap::{App, Arg, ArgMatches};
+use rustls::ServerConfig;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use std::collections::HashMap;
 use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
 use std::io;
 use std::net::{AddrParseError, SocketAddr, ToSocketAddrs};
 use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, SyncSender};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+const SHUTDOWN_POLL_INTERVAL_MS: u64 = 100;
+const DEFAULT_CONFIG_PATH: &str = "caesium.toml";
+
 fn main() -> Result<(), Error> {
     init_logger();
     info!("Using sketch type {:?}", get_sketch_type());
-    let args = parse_args()?;
-    let db = MetricStore::open(&args.db_path)?;
+    let args = parse_args(env::args_os())?;
+    // Flushes any buffered spans when main returns
+    #[cfg(feature = "otel")]
+    let _tracing = match args.otel_endpoint {
+        Some(ref endpoint) => Some(caesium_server::otel::init_otlp_tracing(endpoint)?),
+        None => None,
+    };
+    let db_options = MetricStoreOptions {
+        max_name_len: args.max_metric_name_len,
+        compression: args.value_compression,
+        enable_statistics: args.expose_rocksdb_stats,
+    };
+    let db = MetricStore::open_with_options(&args.db_path, db_options)?;
     let db_ref = Arc::new(db);
-    let threads = vec![
-        start_downsample_thread(args.downsample_interval, db_ref.clone()),
+    let tls_config = match args.tls {
+        Some(ref tls) => Some(load_server_config(
+            &tls.cert_path,
+            &tls.key_path,
+            tls.ca_path.as_deref(),
+        )?),
+        None => None,
+    };
+    let shutdown = Arc::new(AtomicBool::new(false));
+    install_signal_handler(shutdown.clone())?;
+    let metrics = Arc::new(ServerMetrics::new());
+
+    // The read and write servers drain in-flight requests on shutdown,
+    // so only those threads are joined before exiting.
+    let server_threads = vec![
         start_read_server_thread(
             &args.query_addr,
             args.num_read_workers,
             args.query_buffer_len,
+            args.query_timeout,
+            args.slow_query_threshold,
+            tls_config.clone(),
+            args.auth_token.clone(),
+            shutdown.clone(),
+            metrics.clone(),
             db_ref.clone(),
         )?,
         start_write_server_thread(
             &args.insert_addr,
             args.num_write_workers,
             args.insert_buffer_len,
+            args.max_connections_per_ip,
+            args.idle_timeout,
+            args.max_insert_frame_bytes,
+            tls_config.clone(),
+            args.auth_token.clone(),
+            shutdown.clone(),
+            metrics.clone(),
             db_ref.clone(),
         )?,
     ];
-    for t in threads {
-        if let Err(err) = t.join() {
-            error!("Error joining thread: {:?}", err);
-        }
+
+    // Kept open until exit, so the downsample thread never sees a closed channel.
+    // Holds at most one pending trigger, so repeated admin requests coalesce.
+    let (downsample_trigger, downsample_trigger_rx) = sync_channel(1);
+    start_downsample_thread(
+        args.downsample_interval,
+        args.downsample_tiers.clone(),
+        args.retention_overrides.clone(),
+        args.downsample_max_keys,
+        downsample_trigger_rx,
+        metrics.clone(),
+        db_ref.clone(),
+    );
+    if let Some(addr) = args.prometheus_write_addr {
+        start_prometheus_write_server_thread(
+            &addr,
+            args.prometheus_window_size,
+            args.prometheus_scale,
+            db_ref.clone(),
+        )?;
     }
+    if let Some(addr) = args.prometheus_scrape_addr {
+        start_prometheus_scrape_server_thread(
+            &addr,
+            args.prometheus_scrape_lookback,
+            db_ref.clone(),
+        )?;
+    }
+    if let Some(addr) = args.http_query_addr {
+        start_http_query_server_thread(&addr, args.query_timeout, metrics.clone(), db_ref.clone())?;
+    }
+    if let Some(addr) = args.metrics_addr {
+        start_metrics_server_thread(&addr, metrics.clone(), db_ref.clone())?;
+    }
+    if let Some(addr) = args.health_addr {
+        start_health_server_thread(&addr, db_ref.clone())?;
+    }
+    if let Some(addr) = args.admin_addr {
+        start_admin_server_thread(
+            &addr,
+            downsample_trigger.clone(),
+            args.downsample_tiers.clone(),
+            args.retention_overrides.clone(),
+            args.backup_root.clone(),
+            args.auth_token.clone(),
+            db_ref.clone(),
+        )?;
+    }
+
+    while !shutdown.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(SHUTDOWN_POLL_INTERVAL_MS));
+    }
+    info!(
+        "Shutting down, waiting up to {:?} for servers to drain",
+        args.shutdown_timeout
+    );
+    if !join_with_timeout(server_threads, args.shutdown_timeout) {
+        warn!("Servers did not drain before shutdown timeout, forcing exit");
+        process::exit(1);
+    }
+    info!("Shutdown complete");
     Ok(())
 }
 
+fn join_with_timeout(threads: Vec<thread::JoinHandle<()>>, timeout: Duration) -> bool {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for t in threads {
+            if let Err(err) = t.join() {
+                error!("Error joining thread: {:?}", err);
+            }
+        }
+        let _ = tx.send(());
+    });
+    rx.recv_timeout(timeout).is_ok()
+}
+
 fn init_logger() {
-    if let Err(_) = env::var("RUST_LOG") {
+    if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "caesium=debug");
     }
     stackdriver_logger::init();
 }
 
-fn start_downsample_thread(interval: Duration, db_ref: Arc<MetricStore>) -> thread::JoinHandle<()> {
-    let clock = SystemClock::new();
-    thread::spawn(move || loop {
-        thread::sleep(interval);
-        info!("Starting downsample background task");
-        let strategy = DefaultStrategy::new(clock.now());
-        match db_ref.downsample(&strategy) {
-            Ok(_) => info!("Finished downsample background task"),
-            Err(err) => error!("Error during downsample background task: {:?}", err),
+fn start_downsample_thread(
+    interval: Duration,
+    tiers: Option<Vec<RetentionTier>>,
+    retention_overrides: Vec<(String, Vec<RetentionTier>)>,
+    max_keys: usize,
+    trigger: Receiver<()>,
+    metrics: Arc<ServerMetrics>,
+    db_ref: Arc<MetricStore>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let clock = SystemClock::new();
+        let mut strategy = DefaultStrategy::new(&clock);
+        if let Some(rules) = tiers {
+            strategy = strategy.with_tiers(rules);
+        }
+        let strategy = strategy.with_overrides(retention_overrides);
+        loop {
+            // Run a pass every interval, or sooner when requested by an admin
+            match trigger.recv_timeout(interval) {
+                Ok(_) => info!("Downsample triggered by admin request"),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            info!("Starting downsample background task");
+            match run_downsample_pass(&db_ref, &strategy, max_keys) {
+                Ok(_) => info!("Finished downsample background task"),
+                Err(err) => {
+                    error!("Error during downsample background task: {:?}", err);
+                    metrics.record_downsample_error(&err);
+                }
+            }
+            metrics.record_downsample_run();
         }
     })
 }
 
+// Downsamples in chunks of at most `max_keys`, so no single call holds a snapshot
+// over the entire store. An interrupted pass resumes from its bookmark.
+fn run_downsample_pass<T>(
+    db: &MetricStore,
+    strategy: &T,
+    max_keys: usize,
+) -> Result<(), StorageError>
+where
+    T: DownsampleStrategy,
+{
+    while !db.downsample_incremental(strategy, max_keys)? {}
+    Ok(())
+}
+
+fn start_admin_server_thread(
+    addr: &SocketAddr,
+    downsample_trigger: SyncSender<()>,
+    downsample_tiers: Option<Vec<RetentionTier>>,
+    retention_overrides: Vec<(String, Vec<RetentionTier>)>,
+    backup_root: Option<PathBuf>,
+    auth_token: Option<AuthToken>,
+    db_ref: Arc<MetricStore>,
+) -> Result<thread::JoinHandle<()>, io::Error> {
+    let server = AdminServer::new(addr, downsample_trigger, db_ref)?
+        .with_downsample_rules(downsample_tiers, retention_overrides)
+        .with_backup_root(backup_root)
+        .with_auth_token(auth_token);
+    let thread = thread::spawn(move || {
+        if let Err(err) = server.run() {
+            error!("Error running admin server: {:?}", err);
+        }
+    });
+    Ok(thread)
+}
+
 fn start_read_server_thread(
     addr: &SocketAddr,
     num_read_workers: usize,
     buffer_len: usize,
+    query_timeout: Option<Duration>,
+    slow_query_threshold: Duration,
+    tls_config: Option<Arc<ServerConfig>>,
+    auth_token: Option<AuthToken>,
+    shutdown: Arc<AtomicBool>,
+    metrics: Arc<ServerMetrics>,
     db_ref: Arc<MetricStore>,
 ) -> Result<thread::JoinHandle<()>, io::Error> {
-    let server = ReadServer::new(addr, num_read_workers, buffer_len, db_ref)?;
+    let server = ReadServer::new(
+        addr,
+        num_read_workers,
+        buffer_len,
+        query_timeout,
+        slow_query_threshold,
+        tls_config,
+        auth_token,
+        shutdown,
+        metrics,
+        db_ref,
+    )?;
     let thread = thread::spawn(move || {
         if let Err(err) = server.run() {
             error!("Error running read server: {:?}", err);
@@ -90,9 +304,28 @@ fn start_write_server_thread(
     addr: &SocketAddr,
     num_write_workers: usize,
     buffer_len: usize,
+    max_connections_per_ip: usize,
+    idle_timeout: Duration,
+    max_frame_len: usize,
+    tls_config: Option<Arc<ServerConfig>>,
+    auth_token: Option<AuthToken>,
+    shutdown: Arc<AtomicBool>,
+    metrics: Arc<ServerMetrics>,
     db_ref: Arc<MetricStore>,
 ) -> Result<thread::JoinHandle<()>, io::Error> {
-    let server = WriteServer::new(addr, num_write_workers, buffer_len, db_ref)?;
+    let server = WriteServer::new(
+        addr,
+        num_write_workers,
+        buffer_len,
+        max_connections_per_ip,
+        idle_timeout,
+        max_frame_len,
+        tls_config,
+        auth_token,
+        shutdown,
+        metrics,
+        db_ref,
+    )?;
     let thread = thread::spawn(move || {
         if let Err(err) = server.run() {
             error!("Error running write server: {:?}", err);
@@ -101,26 +334,347 @@ fn start_write_server_thread(
     Ok(thread)
 }
 
+fn start_prometheus_write_server_thread(
+    addr: &SocketAddr,
+    window_size: u64,
+    scale: u32,
+    db_ref: Arc<MetricStore>,
+) -> Result<thread::JoinHandle<()>, io::Error> {
+    let server = PrometheusWriteServer::new(addr, window_size, scale, db_ref)?;
+    let thread = thread::spawn(move || {
+        if let Err(err) = server.run() {
+            error!("Error running Prometheus write server: {:?}", err);
+        }
+    });
+    Ok(thread)
+}
+
+fn start_prometheus_scrape_server_thread(
+    addr: &SocketAddr,
+    lookback_secs: u64,
+    db_ref: Arc<MetricStore>,
+) -> Result<thread::JoinHandle<()>, io::Error> {
+    let server = PrometheusScrapeServer::new(addr, lookback_secs, db_ref)?;
+    let thread = thread::spawn(move || {
+        if let Err(err) = server.run() {
+            error!("Error running Prometheus scrape server: {:?}", err);
+        }
+    });
+    Ok(thread)
+}
+
+fn start_http_query_server_thread(
+    addr: &SocketAddr,
+    query_timeout: Option<Duration>,
+    metrics: Arc<ServerMetrics>,
+    db_ref: Arc<MetricStore>,
+) -> Result<thread::JoinHandle<()>, io::Error> {
+    let server = HttpQueryServer::new(addr, query_timeout, metrics, db_ref)?;
+    let thread = thread::spawn(move || {
+        if let Err(err) = server.run() {
+            error!("Error running HTTP query server: {:?}", err);
+        }
+    });
+    Ok(thread)
+}
+
+fn start_metrics_server_thread(
+    addr: &SocketAddr,
+    metrics: Arc<ServerMetrics>,
+    db_ref: Arc<MetricStore>,
+) -> Result<thread::JoinHandle<()>, io::Error> {
+    let server = MetricsServer::new(addr, metrics, db_ref)?;
+    let thread = thread::spawn(move || {
+        if let Err(err) = server.run() {
+            error!("Error running metrics server: {:?}", err);
+        }
+    });
+    Ok(thread)
+}
+
+fn start_health_server_thread(
+    addr: &SocketAddr,
+    db_ref: Arc<MetricStore>,
+) -> Result<thread::JoinHandle<()>, io::Error> {
+    let server = HealthServer::new(addr, db_ref)?;
+    let thread = thread::spawn(move || {
+        if let Err(err) = server.run() {
+            error!("Error running health check server: {:?}", err);
+        }
+    });
+    Ok(thread)
+}
+
 #[derive(Debug)]
 struct Args {
     db_path: String,
+    max_metric_name_len: usize,
+    value_compression: ValueCompression,
+    expose_rocksdb_stats: bool,
     num_read_workers: usize,
     num_write_workers: usize,
     query_buffer_len: usize,
     insert_buffer_len: usize,
+    max_connections_per_ip: usize,
+    idle_timeout: Duration,
+    max_insert_frame_bytes: usize,
+    query_timeout: Option<Duration>,
+    slow_query_threshold: Duration,
     query_addr: SocketAddr,
     insert_addr: SocketAddr,
+    prometheus_write_addr: Option<SocketAddr>,
+    prometheus_window_size: u64,
+    prometheus_scale: u32,
+    prometheus_scrape_addr: Option<SocketAddr>,
+    prometheus_scrape_lookback: u64,
+    http_query_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    health_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    backup_root: Option<PathBuf>,
     downsample_interval: Duration,
+    downsample_tiers: Option<Vec<RetentionTier>>,
+    retention_overrides: Vec<(String, Vec<RetentionTier>)>,
+    downsample_max_keys: usize,
+    shutdown_timeout: Duration,
+    tls: Option<TlsArgs>,
+    auth_token: Option<AuthToken>,
+    #[cfg_attr(not(feature = "otel"), allow(dead_code))]
+    otel_endpoint: Option<String>,
+}
+
+#[derive(Debug)]
+struct TlsArgs {
+    cert_path: String,
+    key_path: String,
+    ca_path: Option<String>,
+}
+
+// Settings read from a TOML config file. Keys match the command line flags,
+// with underscores instead of dashes.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    db_path: Option<String>,
+    max_metric_name_len: Option<usize>,
+    value_compression: Option<String>,
+    expose_rocksdb_stats: Option<bool>,
+    num_read_workers: Option<usize>,
+    num_write_workers: Option<usize>,
+    query_buffer_len: Option<usize>,
+    insert_buffer_len: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    idle_connection_timeout_secs: Option<u64>,
+    max_insert_frame_bytes: Option<usize>,
+    query_timeout_secs: Option<u64>,
+    slow_query_threshold_ms: Option<u64>,
+    query_addr: Option<String>,
+    insert_addr: Option<String>,
+    prometheus_write_addr: Option<String>,
+    prometheus_window_size: Option<u64>,
+    prometheus_scale: Option<u32>,
+    prometheus_scrape_addr: Option<String>,
+    prometheus_scrape_lookback: Option<u64>,
+    http_query_addr: Option<String>,
+    metrics_addr: Option<String>,
+    health_addr: Option<String>,
+    admin_addr: Option<String>,
+    backup_root: Option<String>,
+    downsample_interval: Option<u64>,
+    downsample_tiers: Option<String>,
+    downsample_max_keys: Option<usize>,
+    shutdown_timeout_secs: Option<u64>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_ca: Option<String>,
+    auth_token: Option<String>,
+    otel_endpoint: Option<String>,
+
+    // Retention policies keyed by metric pattern, in the order they appear in the file
+    #[serde(default, deserialize_with = "deserialize_ordered_table")]
+    retention_overrides: Vec<(String, String)>,
+}
+
+impl ConfigFile {
+    // Reads the config file at `path`, or `caesium.toml` in the current directory if it exists
+    fn load(path: Option<&str>) -> Result<ConfigFile, Error> {
+        let path = match path {
+            Some(p) => p,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => DEFAULT_CONFIG_PATH,
+            None => return Ok(ConfigFile::default()),
+        };
+        info!("Reading config from {}", path);
+        let contents = fs::read_to_string(path)?;
+        let config = toml::from_str(&contents)?;
+        Ok(config)
+    }
+
+    // Converts settings to strings keyed by argument name, so they can be parsed like flags
+    fn into_arg_values(self) -> HashMap<&'static str, String> {
+        let mut values = HashMap::new();
+        insert_arg_value(&mut values, "DB_PATH", self.db_path);
+        insert_arg_value(&mut values, "MAX_METRIC_NAME_LEN", self.max_metric_name_len);
+        insert_arg_value(&mut values, "VALUE_COMPRESSION", self.value_compression);
+        insert_arg_value(
+            &mut values,
+            "EXPOSE_ROCKSDB_STATS",
+            self.expose_rocksdb_stats,
+        );
+        insert_arg_value(&mut values, "NUM_READ_WORKERS", self.num_read_workers);
+        insert_arg_value(&mut values, "NUM_WRITE_WORKERS", self.num_write_workers);
+        insert_arg_value(&mut values, "QUERY_BUFFER_LEN", self.query_buffer_len);
+        insert_arg_value(&mut values, "INSERT_BUFFER_LEN", self.insert_buffer_len);
+        insert_arg_value(
+            &mut values,
+            "MAX_CONNECTIONS_PER_IP",
+            self.max_connections_per_ip,
+        );
+        insert_arg_value(
+            &mut values,
+            "IDLE_CONNECTION_TIMEOUT_SECS",
+            self.idle_connection_timeout_secs,
+        );
+        insert_arg_value(
+            &mut values,
+            "MAX_INSERT_FRAME_BYTES",
+            self.max_insert_frame_bytes,
+        );
+        insert_arg_value(&mut values, "QUERY_TIMEOUT_SECS", self.query_timeout_secs);
+        insert_arg_value(
+            &mut values,
+            "SLOW_QUERY_THRESHOLD_MS",
+            self.slow_query_threshold_ms,
+        );
+        insert_arg_value(&mut values, "QUERY_ADDR", self.query_addr);
+        insert_arg_value(&mut values, "INSERT_ADDR", self.insert_addr);
+        insert_arg_value(
+            &mut values,
+            "PROMETHEUS_WRITE_ADDR",
+            self.prometheus_write_addr,
+        );
+        insert_arg_value(
+            &mut values,
+            "PROMETHEUS_WINDOW_SIZE",
+            self.prometheus_window_size,
+        );
+        insert_arg_value(&mut values, "PROMETHEUS_SCALE", self.prometheus_scale);
+        insert_arg_value(
+            &mut values,
+            "PROMETHEUS_SCRAPE_ADDR",
+            self.prometheus_scrape_addr,
+        );
+        insert_arg_value(
+            &mut values,
+            "PROMETHEUS_SCRAPE_LOOKBACK",
+            self.prometheus_scrape_lookback,
+        );
+        insert_arg_value(&mut values, "HTTP_QUERY_ADDR", self.http_query_addr);
+        insert_arg_value(&mut values, "METRICS_ADDR", self.metrics_addr);
+        insert_arg_value(&mut values, "HEALTH_ADDR", self.health_addr);
+        insert_arg_value(&mut values, "ADMIN_ADDR", self.admin_addr);
+        insert_arg_value(&mut values, "BACKUP_ROOT", self.backup_root);
+        insert_arg_value(&mut values, "DOWNSAMPLE_INTERVAL", self.downsample_interval);
+        insert_arg_value(&mut values, "DOWNSAMPLE_TIERS", self.downsample_tiers);
+        insert_arg_value(&mut values, "DOWNSAMPLE_MAX_KEYS", self.downsample_max_keys);
+        insert_arg_value(
+            &mut values,
+            "SHUTDOWN_TIMEOUT_SECS",
+            self.shutdown_timeout_secs,
+        );
+        insert_arg_value(&mut values, "TLS_CERT", self.tls_cert);
+        insert_arg_value(&mut values, "TLS_KEY", self.tls_key);
+        insert_arg_value(&mut values, "TLS_CA", self.tls_ca);
+        insert_arg_value(&mut values, "AUTH_TOKEN", self.auth_token);
+        insert_arg_value(&mut values, "OTEL_ENDPOINT", self.otel_endpoint);
+        values
+    }
+}
+
+fn deserialize_ordered_table<'de, D>(deserializer: D) -> Result<Vec<(String, String)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct OrderedTableVisitor;
+
+    impl<'de> Visitor<'de> for OrderedTableVisitor {
+        type Value = Vec<(String, String)>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a table of string values")
+        }
+
+        fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
+        where
+            M: MapAccess<'de>,
+        {
+            let mut entries = Vec::new();
+            while let Some(entry) = access.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(OrderedTableVisitor)
+}
+
+fn insert_arg_value<T: ToString>(
+    values: &mut HashMap<&'static str, String>,
+    name: &'static str,
+    value: Option<T>,
+) {
+    if let Some(v) = value {
+        values.insert(name, v.to_string());
+    }
+}
+
+// Looks up each argument from the command line first, then from the config file
+struct ArgValues<'a> {
+    matches: ArgMatches<'a>,
+    config: HashMap<&'static str, String>,
+}
+
+impl<'a> ArgValues<'a> {
+    fn value_of(&self, name: &str) -> Option<&str> {
+        self.matches
+            .value_of(name)
+            .or_else(|| self.config.get(name).map(|s| s.as_str()))
+    }
+
+    fn is_present(&self, name: &str) -> bool {
+        self.matches.is_present(name) || self.config.get(name).is_some_and(|s| s == "true")
+    }
 }
 
-fn parse_args() -> Result<Args, Error> {
+fn parse_args<I, T>(cli_args: I) -> Result<Args, Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
     let matches = App::new("Caesium server")
         .about("Backend server for storing and querying metric data")
+        .arg(Arg::with_name("CONFIG")
+            .short("c")
+            .long("config")
+            .takes_value(true)
+            .help("Path to a TOML config file with keys matching these flags, e.g. `query_addr = \"127.0.0.1:8000\"`. Flags override values from the file (defaults to caesium.toml in the current directory, if it exists)"))
         .arg(Arg::with_name("DB_PATH")
             .short("d")
             .long("db-path")
             .takes_value(true)
             .help("Path to the database directory.  The directory will be created if it doesn't exist."))
+        .arg(Arg::with_name("MAX_METRIC_NAME_LEN")
+            .long("max-metric-name-len")
+            .takes_value(true)
+            .help("Maximum length in bytes of metric names (default 256)"))
+        .arg(Arg::with_name("VALUE_COMPRESSION")
+            .long("value-compression")
+            .takes_value(true)
+            .possible_values(&["none", "lz4", "gzip"])
+            .help("Compression for sketches written to the database (default none).  Values written with any compression can still be read."))
+        .arg(Arg::with_name("EXPOSE_ROCKSDB_STATS")
+            .long("expose-rocksdb-stats")
+            .help("Collect RocksDB internal statistics and serve them to caesium-admin rocksdb-stats"))
         .arg(Arg::with_name("NUM_READ_WORKERS")
             .long("num-read-workers")
             .takes_value(true)
@@ -137,6 +691,26 @@ fn parse_args() -> Result<Args, Error> {
             .long("insert-buffer-len")
             .takes_value(true)
             .help("Number of inserts to enqueue before blocking (default 4096)"))
+        .arg(Arg::with_name("MAX_CONNECTIONS_PER_IP")
+            .long("max-connections-per-ip")
+            .takes_value(true)
+            .help("Maximum number of open insert connections from a single IP address (default 10)"))
+        .arg(Arg::with_name("IDLE_CONNECTION_TIMEOUT_SECS")
+            .long("idle-connection-timeout-secs")
+            .takes_value(true)
+            .help("Close insert connections with no activity for this many seconds (default 300)"))
+        .arg(Arg::with_name("MAX_INSERT_FRAME_BYTES")
+            .long("max-insert-frame-bytes")
+            .takes_value(true)
+            .help("Close insert connections that send a frame longer than this many bytes, which also limits how much each connection buffers (default 67108864)"))
+        .arg(Arg::with_name("QUERY_TIMEOUT_SECS")
+            .long("query-timeout-secs")
+            .takes_value(true)
+            .help("Maximum number of seconds to execute a query before aborting (defaults to no timeout)"))
+        .arg(Arg::with_name("SLOW_QUERY_THRESHOLD_MS")
+            .long("slow-query-threshold-ms")
+            .takes_value(true)
+            .help("Log queries that take longer than this many milliseconds, with their execution time (default 1000)"))
         .arg(Arg::with_name("QUERY_ADDR")
             .long("query-addr")
             .takes_value(true)
@@ -145,15 +719,115 @@ fn parse_args() -> Result<Args, Error> {
             .long("insert-addr")
             .takes_value(true)
             .help("Network address for inserts (defaults to 127.0.0.1:8001)"))
+        .arg(Arg::with_name("PROMETHEUS_WRITE_ADDR")
+            .long("prometheus-write-addr")
+            .takes_value(true)
+            .help("Network address for Prometheus remote writes (disabled by default)"))
+        .arg(Arg::with_name("PROMETHEUS_WINDOW_SIZE")
+            .long("prometheus-window-size")
+            .takes_value(true)
+            .help("Size in seconds of windows for Prometheus samples (default 10)"))
+        .arg(Arg::with_name("PROMETHEUS_SCALE")
+            .long("prometheus-scale")
+            .takes_value(true)
+            .help("Number of decimal digits kept for Prometheus sample values, up to 9 (default 0)"))
+        .arg(Arg::with_name("PROMETHEUS_SCRAPE_ADDR")
+            .long("prometheus-scrape-addr")
+            .takes_value(true)
+            .help("Network address to expose quantiles for Prometheus scrapes (disabled by default)"))
+        .arg(Arg::with_name("PROMETHEUS_SCRAPE_LOOKBACK")
+            .long("prometheus-scrape-lookback")
+            .takes_value(true)
+            .help("Number of seconds of recent data to summarize on each Prometheus scrape (default 300)"))
+        .arg(Arg::with_name("HTTP_QUERY_ADDR")
+            .long("http-query-addr")
+            .takes_value(true)
+            .help("Network address to accept queries over HTTP with JSON results (disabled by default)"))
+        .arg(Arg::with_name("METRICS_ADDR")
+            .long("metrics-addr")
+            .takes_value(true)
+            .help("Network address to serve the server's own metrics at /metrics over HTTP (disabled by default)"))
+        .arg(Arg::with_name("HEALTH_ADDR")
+            .long("health-addr")
+            .takes_value(true)
+            .help("Network address to serve /healthz and /readyz over HTTP (disabled by default)"))
+        .arg(Arg::with_name("ADMIN_ADDR")
+            .long("admin-addr")
+            .takes_value(true)
+            .help("Network address to accept admin requests from caesium-admin (disabled by default)"))
+        .arg(Arg::with_name("BACKUP_ROOT")
+            .long("backup-root")
+            .takes_value(true)
+            .help("Directory on the server for backups requested with caesium-admin backup, which may only write to directories under it (backups are disabled if omitted)"))
         .arg(Arg::with_name("DOWNSAMPLE_INTERVAL")
             .long("downsample-interval")
             .takes_value(true)
             .help("Number of seconds between downsample background tasks (default 600)"))
-        .get_matches();
+        .arg(Arg::with_name("DOWNSAMPLE_TIERS")
+            .long("downsample-tiers")
+            .takes_value(true)
+            .help("Comma-separated AGE:WINDOW rules, e.g. `1h:10s,1d:1m`, with durations in seconds unless followed by s, m, h, d, or w. Windows younger than AGE are downsampled to WINDOW, and windows older than every AGE are discarded (defaults to built-in tiers up to 365 days)"))
+        .arg(Arg::with_name("DOWNSAMPLE_MAX_KEYS")
+            .long("downsample-max-keys")
+            .takes_value(true)
+            .help("Maximum number of windows to downsample at a time before resuming from a bookmark (default 10000)"))
+        .arg(Arg::with_name("SHUTDOWN_TIMEOUT_SECS")
+            .long("shutdown-timeout-secs")
+            .takes_value(true)
+            .help("Number of seconds to wait for in-flight queries and inserts on shutdown before exiting (default 30)"))
+        .arg(Arg::with_name("TLS_CERT")
+            .long("tls-cert")
+            .takes_value(true)
+            .requires("TLS_KEY")
+            .help("Path to PEM certificate chain for serving queries and inserts over TLS (plain TCP if omitted)"))
+        .arg(Arg::with_name("TLS_KEY")
+            .long("tls-key")
+            .takes_value(true)
+            .requires("TLS_CERT")
+            .help("Path to PEM private key for the TLS certificate"))
+        .arg(Arg::with_name("TLS_CA")
+            .long("tls-ca")
+            .takes_value(true)
+            .requires("TLS_CERT")
+            .help("Path to PEM CA certificate used to verify client certificates (enables mutual TLS)"))
+        .arg(Arg::with_name("AUTH_TOKEN")
+            .long("auth-token")
+            .takes_value(true)
+            .env("CAESIUM_AUTH_TOKEN")
+            .hide_env_values(true)
+            .help("Shared secret that clients must send before inserting, querying, or sending admin requests (no authentication if omitted)"))
+        .arg(Arg::with_name("OTEL_ENDPOINT")
+            .long("otel-endpoint")
+            .takes_value(true)
+            .help("OTLP/HTTP endpoint for exporting query and storage traces, e.g. `http://localhost:4318/v1/traces` (requires the `otel` feature; disabled by default)"))
+        .get_matches_from(cli_args);
 
-    let db_path = matches.value_of("DB_PATH").unwrap_or("db").to_string();
+    let config = ConfigFile::load(matches.value_of("CONFIG"))?;
+    let retention_overrides = parse_retention_overrides(&config.retention_overrides)?;
+    let values = ArgValues {
+        matches,
+        config: config.into_arg_values(),
+    };
 
-    let num_read_workers = matches
+    let db_path = values.value_of("DB_PATH").unwrap_or("db").to_string();
+
+    let max_metric_name_len = values
+        .value_of("MAX_METRIC_NAME_LEN")
+        .unwrap_or("256")
+        .parse::<usize>()?;
+    if max_metric_name_len == 0 {
+        return Err(Error::ArgError("MAX_METRIC_NAME_LEN must be > 0"));
+    }
+
+    let value_compression = values
+        .value_of("VALUE_COMPRESSION")
+        .unwrap_or("none")
+        .parse::<ValueCompression>()
+        .map_err(Error::ArgError)?;
+
+    let expose_rocksdb_stats = values.is_present("EXPOSE_ROCKSDB_STATS");
+
+    let num_read_workers = values
         .value_of("NUM_READ_WORKERS")
         .unwrap_or("1")
         .parse::<usize>()?;
@@ -161,7 +835,7 @@ fn parse_args() -> Result<Args, Error> {
         return Err(Error::ArgError("Must have at least one read worker"));
     }
 
-    let num_write_workers = matches
+    let num_write_workers = values
         .value_of("NUM_WRITE_WORKERS")
         .unwrap_or("1")
         .parse::<usize>()?;
@@ -169,54 +843,254 @@ fn parse_args() -> Result<Args, Error> {
         return Err(Error::ArgError("Must have at least one write worker"));
     }
 
-    let query_buffer_len = matches
+    let query_buffer_len = values
         .value_of("QUERY_BUFFER_LEN")
         .unwrap_or("4096")
         .parse::<usize>()?;
 
-    let insert_buffer_len = matches
+    let insert_buffer_len = values
         .value_of("INSERT_BUFFER_LEN")
         .unwrap_or("4096")
         .parse::<usize>()?;
 
-    let query_addr = matches
+    let max_connections_per_ip = values
+        .value_of("MAX_CONNECTIONS_PER_IP")
+        .unwrap_or("10")
+        .parse::<usize>()?;
+    if max_connections_per_ip == 0 {
+        return Err(Error::ArgError("Must allow at least one connection per IP"));
+    }
+
+    let idle_timeout_secs = values
+        .value_of("IDLE_CONNECTION_TIMEOUT_SECS")
+        .unwrap_or("300")
+        .parse::<u64>()?;
+    if idle_timeout_secs == 0 {
+        return Err(Error::ArgError(
+            "Idle connection timeout must be at least one second",
+        ));
+    }
+    let idle_timeout = Duration::from_secs(idle_timeout_secs);
+
+    let max_insert_frame_bytes = values
+        .value_of("MAX_INSERT_FRAME_BYTES")
+        .unwrap_or("67108864")
+        .parse::<usize>()?;
+    if max_insert_frame_bytes == 0 {
+        return Err(Error::ArgError("MAX_INSERT_FRAME_BYTES must be > 0"));
+    }
+
+    let query_timeout = match values.value_of("QUERY_TIMEOUT_SECS") {
+        Some(s) => Some(s.parse::<u64>().map(Duration::from_secs)?),
+        None => None,
+    };
+
+    let slow_query_threshold = values
+        .value_of("SLOW_QUERY_THRESHOLD_MS")
+        .unwrap_or("1000")
+        .parse::<u64>()
+        .map(Duration::from_millis)?;
+
+    let query_addr = values
         .value_of("QUERY_ADDR")
         .unwrap_or("127.0.0.1:8000")
         .to_socket_addrs()?
         .next()
         .ok_or(Error::ArgError("Expected socket address"))?;
 
-    let insert_addr = matches
+    let insert_addr = values
         .value_of("INSERT_ADDR")
         .unwrap_or("127.0.0.1:8001")
         .to_socket_addrs()?
         .next()
         .ok_or(Error::ArgError("Expected socket address"))?;
 
-    let downsample_interval = matches
+    let prometheus_write_addr = match values.value_of("PROMETHEUS_WRITE_ADDR") {
+        Some(s) => Some(
+            s.to_socket_addrs()?
+                .next()
+                .ok_or(Error::ArgError("Expected socket address"))?,
+        ),
+        None => None,
+    };
+
+    let prometheus_window_size = values
+        .value_of("PROMETHEUS_WINDOW_SIZE")
+        .unwrap_or("10")
+        .parse::<u64>()?;
+    if prometheus_window_size == 0 {
+        return Err(Error::ArgError("Prometheus window size must be >= 1"));
+    }
+
+    let prometheus_scale = values
+        .value_of("PROMETHEUS_SCALE")
+        .unwrap_or("0")
+        .parse::<u32>()?;
+    if prometheus_scale > MAX_SCALE {
+        return Err(Error::ArgError("Prometheus scale must be <= 9"));
+    }
+
+    let prometheus_scrape_addr = match values.value_of("PROMETHEUS_SCRAPE_ADDR") {
+        Some(s) => Some(
+            s.to_socket_addrs()?
+                .next()
+                .ok_or(Error::ArgError("Expected socket address"))?,
+        ),
+        None => None,
+    };
+
+    let prometheus_scrape_lookback = values
+        .value_of("PROMETHEUS_SCRAPE_LOOKBACK")
+        .unwrap_or("300")
+        .parse::<u64>()?;
+
+    let http_query_addr = match values.value_of("HTTP_QUERY_ADDR") {
+        Some(s) => Some(
+            s.to_socket_addrs()?
+                .next()
+                .ok_or(Error::ArgError("Expected socket address"))?,
+        ),
+        None => None,
+    };
+
+    let metrics_addr = match values.value_of("METRICS_ADDR") {
+        Some(s) => Some(
+            s.to_socket_addrs()?
+                .next()
+                .ok_or(Error::ArgError("Expected socket address"))?,
+        ),
+        None => None,
+    };
+
+    let health_addr = match values.value_of("HEALTH_ADDR") {
+        Some(s) => Some(
+            s.to_socket_addrs()?
+                .next()
+                .ok_or(Error::ArgError("Expected socket address"))?,
+        ),
+        None => None,
+    };
+
+    let admin_addr = match values.value_of("ADMIN_ADDR") {
+        Some(s) => Some(
+            s.to_socket_addrs()?
+                .next()
+                .ok_or(Error::ArgError("Expected socket address"))?,
+        ),
+        None => None,
+    };
+
+    let backup_root = values.value_of("BACKUP_ROOT").map(PathBuf::from);
+
+    let downsample_interval = values
         .value_of("DOWNSAMPLE_INTERVAL")
         .unwrap_or("600")
         .parse::<u64>()
-        .map(|secs| Duration::from_secs(secs))?;
+        .map(Duration::from_secs)?;
+
+    let downsample_tiers = match values.value_of("DOWNSAMPLE_TIERS") {
+        Some(s) => Some(parse_retention_policy(s).map_err(Error::ArgError)?),
+        None => None,
+    };
+
+    let downsample_max_keys = values
+        .value_of("DOWNSAMPLE_MAX_KEYS")
+        .unwrap_or("10000")
+        .parse::<usize>()?;
+    if downsample_max_keys == 0 {
+        return Err(Error::ArgError("DOWNSAMPLE_MAX_KEYS must be > 0"));
+    }
+
+    let shutdown_timeout = values
+        .value_of("SHUTDOWN_TIMEOUT_SECS")
+        .unwrap_or("30")
+        .parse::<u64>()
+        .map(Duration::from_secs)?;
+
+    let tls = match (values.value_of("TLS_CERT"), values.value_of("TLS_KEY")) {
+        (Some(cert_path), Some(key_path)) => Some(TlsArgs {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            ca_path: values.value_of("TLS_CA").map(|s| s.to_string()),
+        }),
+        (None, None) if values.value_of("TLS_CA").is_none() => None,
+        _ => {
+            return Err(Error::ArgError(
+                "TLS_CERT and TLS_KEY must be set together, and are required for TLS_CA",
+            ))
+        }
+    };
+
+    let auth_token = match values.value_of("AUTH_TOKEN") {
+        Some("") => return Err(Error::ArgError("AUTH_TOKEN must not be empty")),
+        Some(token) => Some(AuthToken::new(token)),
+        None => None,
+    };
+
+    let otel_endpoint = values.value_of("OTEL_ENDPOINT").map(|s| s.to_string());
+    if otel_endpoint.is_some() && !cfg!(feature = "otel") {
+        return Err(Error::ArgError(
+            "OTEL_ENDPOINT requires building with the otel feature",
+        ));
+    }
 
     Ok(Args {
         db_path,
+        max_metric_name_len,
+        value_compression,
+        expose_rocksdb_stats,
         num_read_workers,
         num_write_workers,
         query_buffer_len,
         insert_buffer_len,
+        max_connections_per_ip,
+        idle_timeout,
+        max_insert_frame_bytes,
+        query_timeout,
+        slow_query_threshold,
         query_addr,
         insert_addr,
+        prometheus_write_addr,
+        prometheus_window_size,
+        prometheus_scale,
+        prometheus_scrape_addr,
+        prometheus_scrape_lookback,
+        http_query_addr,
+        metrics_addr,
+        health_addr,
+        admin_addr,
+        backup_root,
         downsample_interval,
+        downsample_tiers,
+        retention_overrides,
+        downsample_max_keys,
+        shutdown_timeout,
+        tls,
+        auth_token,
+        otel_endpoint,
     })
 }
 
+fn parse_retention_overrides(
+    overrides: &[(String, String)],
+) -> Result<Vec<(String, Vec<RetentionTier>)>, Error> {
+    overrides
+        .iter()
+        .map(|(pattern, policy)| {
+            let tiers = parse_retention_policy(policy).map_err(Error::ArgError)?;
+            Ok((pattern.clone(), tiers))
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 enum Error {
     AddrParseError(AddrParseError),
     IOError(io::Error),
     StorageError(StorageError),
     ParseIntError(ParseIntError),
+    SignalError(ctrlc::Error),
+    ConfigError(toml::de::Error),
     ArgError(&'static str),
 }
 
@@ -243,3 +1117,264 @@ impl From<ParseIntError> for Error {
         Error::ParseIntError(err)
     }
 }
+
+impl From<ctrlc::Error> for Error {
+    fn from(err: ctrlc::Error) -> Error {
+        Error::SignalError(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Error {
+        Error::ConfigError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_all_fields_from_config_file() {
+        let path = write_config(
+            "all_fields",
+            r#"
+            db_path = "/tmp/caesium_db"
+            max_metric_name_len = 128
+            value_compression = "lz4"
+            expose_rocksdb_stats = true
+            num_read_workers = 2
+            num_write_workers = 3
+            query_buffer_len = 100
+            insert_buffer_len = 200
+            max_connections_per_ip = 5
+            idle_connection_timeout_secs = 60
+            max_insert_frame_bytes = 1048576
+            query_timeout_secs = 10
+            slow_query_threshold_ms = 250
+            query_addr = "127.0.0.1:9000"
+            insert_addr = "127.0.0.1:9001"
+            prometheus_write_addr = "127.0.0.1:9002"
+            prometheus_window_size = 30
+            prometheus_scale = 3
+            prometheus_scrape_addr = "127.0.0.1:9003"
+            prometheus_scrape_lookback = 120
+            http_query_addr = "127.0.0.1:9004"
+            metrics_addr = "127.0.0.1:9007"
+            health_addr = "127.0.0.1:9006"
+            admin_addr = "127.0.0.1:9005"
+            backup_root = "/var/backups/caesium"
+            downsample_interval = 900
+            downsample_tiers = "86400:10,604800:600"
+            downsample_max_keys = 500
+            shutdown_timeout_secs = 15
+            tls_cert = "cert.pem"
+            tls_key = "key.pem"
+            tls_ca = "ca.pem"
+            auth_token = "secret"
+
+            [retention_overrides]
+            "payment.*" = "30d:1m, 90d:1h"
+            "debug.*" = "1d:10s"
+            "*" = "7d:1m"
+            "#,
+        );
+        let args = parse_args(vec!["caesium-server", "--config", &path]).unwrap();
+        assert_eq!(args.db_path, "/tmp/caesium_db");
+        assert_eq!(args.max_metric_name_len, 128);
+        assert_eq!(args.value_compression, ValueCompression::Lz4);
+        assert!(args.expose_rocksdb_stats);
+        assert_eq!(args.num_read_workers, 2);
+        assert_eq!(args.num_write_workers, 3);
+        assert_eq!(args.query_buffer_len, 100);
+        assert_eq!(args.insert_buffer_len, 200);
+        assert_eq!(args.max_connections_per_ip, 5);
+        assert_eq!(args.idle_timeout, Duration::from_secs(60));
+        assert_eq!(args.max_insert_frame_bytes, 1048576);
+        assert_eq!(args.query_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(args.slow_query_threshold, Duration::from_millis(250));
+        assert_eq!(args.query_addr, addr("127.0.0.1:9000"));
+        assert_eq!(args.insert_addr, addr("127.0.0.1:9001"));
+        assert_eq!(args.prometheus_write_addr, Some(addr("127.0.0.1:9002")));
+        assert_eq!(args.prometheus_window_size, 30);
+        assert_eq!(args.prometheus_scale, 3);
+        assert_eq!(args.prometheus_scrape_addr, Some(addr("127.0.0.1:9003")));
+        assert_eq!(args.prometheus_scrape_lookback, 120);
+        assert_eq!(args.http_query_addr, Some(addr("127.0.0.1:9004")));
+        assert_eq!(args.metrics_addr, Some(addr("127.0.0.1:9007")));
+        assert_eq!(args.health_addr, Some(addr("127.0.0.1:9006")));
+        assert_eq!(args.admin_addr, Some(addr("127.0.0.1:9005")));
+        assert_eq!(
+            args.backup_root,
+            Some(PathBuf::from("/var/backups/caesium"))
+        );
+        assert_eq!(args.downsample_interval, Duration::from_secs(900));
+        assert_eq!(
+            args.downsample_tiers,
+            Some(vec![(86400, 10), (604800, 600)])
+        );
+        assert_eq!(args.downsample_max_keys, 500);
+        assert_eq!(args.shutdown_timeout, Duration::from_secs(15));
+        let tls = args.tls.expect("Expected TLS args");
+        assert_eq!(tls.cert_path, "cert.pem");
+        assert_eq!(tls.key_path, "key.pem");
+        assert_eq!(tls.ca_path, Some("ca.pem".to_string()));
+        let auth_token = args.auth_token.expect("Expected auth token");
+        assert!(auth_token.verify(b"secret"));
+        assert_eq!(
+            args.retention_overrides,
+            vec![
+                (
+                    "payment.*".to_string(),
+                    vec![(30 * 86400, 60), (90 * 86400, 3600)]
+                ),
+                ("debug.*".to_string(), vec![(86400, 10)]),
+                ("*".to_string(), vec![(7 * 86400, 60)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_overrides_config_file_with_flags() {
+        let path = write_config(
+            "overrides",
+            r#"
+            db_path = "/tmp/caesium_db"
+            num_read_workers = 2
+            query_addr = "127.0.0.1:9000"
+            "#,
+        );
+        let cli_args = vec![
+            "caesium-server",
+            "--config",
+            &path,
+            "--num-read-workers",
+            "4",
+            "--query-addr",
+            "127.0.0.1:7000",
+            "--expose-rocksdb-stats",
+        ];
+        let args = parse_args(cli_args).unwrap();
+        assert_eq!(args.num_read_workers, 4);
+        assert_eq!(args.query_addr, addr("127.0.0.1:7000"));
+        assert!(args.expose_rocksdb_stats);
+
+        // Values from the file are used when not overridden, and defaults otherwise
+        assert_eq!(args.db_path, "/tmp/caesium_db");
+        assert_eq!(args.num_write_workers, 1);
+    }
+
+    #[test]
+    fn it_parses_example_config_file() {
+        let contents = include_str!("../../config.toml.example");
+        let path = write_config("example", contents);
+        let args = parse_args(vec!["caesium-server", "--config", &path]).unwrap();
+        assert_eq!(args.query_addr, addr("127.0.0.1:8000"));
+        assert!(args.tls.is_none());
+        assert!(args.auth_token.is_none());
+    }
+
+    #[test]
+    fn it_rejects_invalid_config_file() {
+        let path = write_config("unknown_field", "query_adr = \"127.0.0.1:9000\"");
+        match parse_args(vec!["caesium-server", "--config", &path]) {
+            Err(Error::ConfigError(_)) => {}
+            r => panic!("Expected config error, got {:?}", r),
+        }
+
+        let path = write_config("wrong_type", "num_read_workers = \"two\"");
+        match parse_args(vec!["caesium-server", "--config", &path]) {
+            Err(Error::ConfigError(_)) => {}
+            r => panic!("Expected config error, got {:?}", r),
+        }
+
+        let path = write_config("tls_cert_only", "tls_cert = \"cert.pem\"");
+        match parse_args(vec!["caesium-server", "--config", &path]) {
+            Err(Error::ArgError(_)) => {}
+            r => panic!("Expected arg error, got {:?}", r),
+        }
+
+        let path = write_config("empty_auth_token", "auth_token = \"\"");
+        match parse_args(vec!["caesium-server", "--config", &path]) {
+            Err(Error::ArgError(_)) => {}
+            r => panic!("Expected arg error, got {:?}", r),
+        }
+
+        let path = write_config("prometheus_scale_too_large", "prometheus_scale = 10");
+        match parse_args(vec!["caesium-server", "--config", &path]) {
+            Err(Error::ArgError(_)) => {}
+            r => panic!("Expected arg error, got {:?}", r),
+        }
+
+        let path = write_config(
+            "invalid_retention_override",
+            "[retention_overrides]\n\"foo\" = \"30d\"",
+        );
+        match parse_args(vec!["caesium-server", "--config", &path]) {
+            Err(Error::ArgError(_)) => {}
+            r => panic!("Expected arg error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn it_parses_downsample_tiers_with_units() {
+        let args = parse_args(vec![
+            "caesium-server",
+            "--downsample-tiers",
+            "1d:10s, 7d:600",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.downsample_tiers,
+            Some(vec![(86400, 10), (604800, 600)])
+        );
+
+        match parse_args(vec!["caesium-server", "--downsample-tiers", "1d:1h,7d:1m"]) {
+            Err(Error::ArgError(_)) => {}
+            r => panic!("Expected arg error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn it_parses_otel_endpoint() {
+        let endpoint = "http://localhost:4318/v1/traces";
+        let result = parse_args(vec!["caesium-server", "--otel-endpoint", endpoint]);
+        if cfg!(feature = "otel") {
+            assert_eq!(result.unwrap().otel_endpoint, Some(endpoint.to_string()));
+        } else {
+            match result {
+                Err(Error::ArgError(_)) => {}
+                r => panic!("Expected arg error, got {:?}", r),
+            }
+        }
+
+        let args = parse_args(vec!["caesium-server"]).unwrap();
+        assert!(args.otel_endpoint.is_none());
+    }
+
+    #[test]
+    fn it_rejects_missing_config_file() {
+        match parse_args(vec![
+            "caesium-server",
+            "--config",
+            "/nonexistent/caesium.toml",
+        ]) {
+            Err(Error::IOError(_)) => {}
+            r => panic!("Expected IO error, got {:?}", r),
+        }
+    }
+
+    fn write_config(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!(
+            "caesium_server_config_test_{}_{}.toml",
+            process::id(),
+            name
+        ));
+        fs::write(&path, contents).expect("Could not write config file");
+        path.to_string_lossy().into_owned()
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+}
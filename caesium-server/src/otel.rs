@@ -0,0 +1,95 @@
+// Optional OpenTelemetry tracing for queries and storage, enabled by the `otel` feature.
+// Without the feature, spans are no-ops, so instrumented code needs no cfg attributes.
+
+#[cfg(feature = "otel")]
+pub use self::enabled::{init_otlp_tracing, Span, TracingGuard};
+
+#[cfg(not(feature = "otel"))]
+pub use self::disabled::Span;
+
+#[cfg(feature = "otel")]
+mod enabled {
+    use opentelemetry::global::{self, BoxedSpan};
+    use opentelemetry::trace::{Span as OtelSpan, Tracer};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use std::io;
+
+    const TRACER_NAME: &str = "caesium";
+    const SERVICE_NAME: &str = "caesium-server";
+
+    // Ends when dropped, so a span covers the scope (or iterator) that owns it
+    pub struct Span {
+        inner: BoxedSpan,
+    }
+
+    impl Span {
+        pub fn start(name: &'static str) -> Span {
+            Span {
+                inner: global::tracer(TRACER_NAME).start(name),
+            }
+        }
+
+        pub fn set_str(&mut self, key: &'static str, value: &str) {
+            self.inner
+                .set_attribute(KeyValue::new(key, value.to_string()));
+        }
+
+        pub fn set_i64(&mut self, key: &'static str, value: i64) {
+            self.inner.set_attribute(KeyValue::new(key, value));
+        }
+
+        pub fn set_f64(&mut self, key: &'static str, value: f64) {
+            self.inner.set_attribute(KeyValue::new(key, value));
+        }
+    }
+
+    // Flushes buffered spans to the exporter when dropped
+    pub struct TracingGuard {
+        provider: SdkTracerProvider,
+    }
+
+    impl Drop for TracingGuard {
+        fn drop(&mut self) {
+            if let Err(err) = self.provider.shutdown() {
+                error!("Could not flush traces: {:?}", err);
+            }
+        }
+    }
+
+    // Exports spans in batches over OTLP/HTTP, for example to `http://localhost:4318/v1/traces`
+    pub fn init_otlp_tracing(endpoint: &str) -> Result<TracingGuard, io::Error> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build();
+        global::set_tracer_provider(provider.clone());
+        info!("Exporting traces to {}", endpoint);
+        Ok(TracingGuard { provider })
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod disabled {
+    pub struct Span {}
+
+    impl Span {
+        pub fn start(_name: &'static str) -> Span {
+            Span {}
+        }
+
+        pub fn set_str(&mut self, _key: &'static str, _value: &str) {}
+
+        pub fn set_i64(&mut self, _key: &'static str, _value: i64) {}
+
+        pub fn set_f64(&mut self, _key: &'static str, _value: f64) {}
+    }
+}
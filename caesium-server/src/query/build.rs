@@ -1,14 +1,21 @@
 use query::error::QueryError;
+use query::ops::annotate::AnnotateOp;
 use query::ops::coalesce::CoalesceOp;
 use query::ops::combine::CombineOp;
-use query::ops::fetch::FetchOp;
+use query::ops::fetch::{FetchGlobOp, FetchOp};
+use query::ops::fill::{FillMode, FillOp};
 use query::ops::group::{GroupOp, GroupType};
+use query::ops::histogram::HistogramOp;
+use query::ops::moving_average::MovingAverageOp;
 use query::ops::quantile::QuantileOp;
-use query::ops::search::SearchOp;
+use query::ops::resample::ResampleOp;
+use query::ops::search::{SearchMode, SearchOp};
+use query::ops::topk::TopKOp;
 use query::ops::QueryOp;
 use query::parser::ast::Expression;
 use query::parser::parse::parse;
 use storage::datasource::DataSource;
+use storage::wildcard::has_wildcard;
 
 pub fn build_query<'a>(
     query: &str,
@@ -27,16 +34,33 @@ fn map_func_to_query_op<'a>(
     source: &'a DataSource,
 ) -> Result<Box<QueryOp + 'a>, QueryError> {
     match name {
+        "annotate" => build_annotate_op(args, source),
         "coalesce" => build_coalesce_op(args, source),
         "combine" => build_combine_op(args, source),
         "fetch" => build_fetch_op(args, source),
+        "fill" => build_fill_op(args, source),
         "group" => build_group_op(args, source),
+        "histogram" => build_histogram_op(args, source),
+        "moving_avg" => build_moving_avg_op(args, source),
+        "moving_average" => build_moving_average_op(args, source),
         "quantile" => build_quantile_op(args, source),
+        "resample" => build_resample_op(args, source),
         "search" => build_search_op(args, source),
+        "topk" => build_topk_op(args, source),
         f => Err(QueryError::UnrecognizedFunction(f.to_string())),
     }
 }
 
+fn build_annotate_op<'a>(
+    args: &[Box<Expression>],
+    source: &'a DataSource,
+) -> Result<Box<QueryOp + 'a>, QueryError> {
+    let label = get_string_arg(args, 0)?;
+    let input = get_func_arg(args, 1, source)?;
+    let op = AnnotateOp::new(label, input);
+    Ok(Box::new(op))
+}
+
 fn build_coalesce_op<'a>(
     args: &[Box<Expression>],
     source: &'a DataSource,
@@ -50,6 +74,9 @@ fn build_combine_op<'a>(
     args: &[Box<Expression>],
     source: &'a DataSource,
 ) -> Result<Box<QueryOp + 'a>, QueryError> {
+    if args.is_empty() {
+        return Err(QueryError::MissingArg);
+    }
     let mut inputs = Vec::new();
     for i in 0..args.len() {
         inputs.push(get_func_arg(args, i, source)?);
@@ -65,10 +92,24 @@ fn build_fetch_op<'a>(
     let metric = get_string_arg(args, 0)?;
     let start_ts = get_optional_arg(get_int_arg, args, 1)?;
     let end_ts = get_optional_arg(get_int_arg, args, 2)?;
+    if has_wildcard(&metric) {
+        let op = FetchGlobOp::new(metric, source, start_ts, end_ts);
+        return Ok(Box::new(op));
+    }
     let op = FetchOp::new(metric, source, start_ts, end_ts)?;
     Ok(Box::new(op))
 }
 
+fn build_fill_op<'a>(
+    args: &[Box<Expression>],
+    source: &'a DataSource,
+) -> Result<Box<QueryOp + 'a>, QueryError> {
+    let mode = FillMode::from_str(&get_string_arg(args, 0)?)?;
+    let input = get_func_arg(args, 1, source)?;
+    let op = FillOp::new(mode, input);
+    Ok(Box::new(op))
+}
+
 fn build_group_op<'a>(
     args: &[Box<Expression>],
     source: &'a DataSource,
@@ -83,6 +124,37 @@ fn build_group_op<'a>(
     Ok(Box::new(op))
 }
 
+fn build_histogram_op<'a>(
+    args: &[Box<Expression>],
+    source: &'a DataSource,
+) -> Result<Box<QueryOp + 'a>, QueryError> {
+    let input = get_func_arg(args, 0, source)?;
+    let bucket_count = get_int_arg(args, 1)?;
+    let op = HistogramOp::new(input, bucket_count)?;
+    Ok(Box::new(op))
+}
+
+fn build_moving_avg_op<'a>(
+    args: &[Box<Expression>],
+    source: &'a DataSource,
+) -> Result<Box<QueryOp + 'a>, QueryError> {
+    let window_count = get_int_arg(args, 0)?;
+    let input = get_func_arg(args, 1, source)?;
+    let op = MovingAverageOp::averaging_quantiles(window_count, input)?;
+    Ok(Box::new(op))
+}
+
+fn build_moving_average_op<'a>(
+    args: &[Box<Expression>],
+    source: &'a DataSource,
+) -> Result<Box<QueryOp + 'a>, QueryError> {
+    let window_count = get_int_arg(args, 0)?;
+    let input = get_func_arg(args, 1, source)?;
+    let phi = get_float_arg(args, 2)?;
+    let op = MovingAverageOp::new(window_count, input, phi)?;
+    Ok(Box::new(op))
+}
+
 fn build_quantile_op<'a>(
     args: &[Box<Expression>],
     source: &'a DataSource,
@@ -99,12 +171,45 @@ fn build_quantile_op<'a>(
     Ok(Box::new(op))
 }
 
+fn build_resample_op<'a>(
+    args: &[Box<Expression>],
+    source: &'a DataSource,
+) -> Result<Box<QueryOp + 'a>, QueryError> {
+    let bucket_secs = get_int_arg(args, 0)?;
+    let input = get_func_arg(args, 1, source)?;
+    let op = ResampleOp::new(bucket_secs, input)?;
+    Ok(Box::new(op))
+}
+
 fn build_search_op<'a>(
     args: &[Box<Expression>],
     source: &'a DataSource,
 ) -> Result<Box<QueryOp + 'a>, QueryError> {
-    let pattern = get_string_arg(args, 0)?;
-    let op = SearchOp::new(pattern, source)?;
+    // A single argument is the pattern, otherwise the first argument is the mode
+    if args.len() <= 1 {
+        let pattern = get_string_arg(args, 0)?;
+        let op = SearchOp::new(SearchMode::Any, vec![pattern], source)?;
+        return Ok(Box::new(op));
+    }
+    let mode = SearchMode::from_str(&get_string_arg(args, 0)?)?;
+    let mut patterns = Vec::new();
+    for i in 1..args.len() {
+        patterns.push(get_string_arg(args, i)?);
+    }
+    let op = SearchOp::new(mode, patterns, source)?;
+    Ok(Box::new(op))
+}
+
+fn build_topk_op<'a>(
+    args: &[Box<Expression>],
+    source: &'a DataSource,
+) -> Result<Box<QueryOp + 'a>, QueryError> {
+    let n = get_int_arg(args, 0)?;
+    let phi = get_float_arg(args, 1)?;
+    let input = get_func_arg(args, 2, source)?;
+    let start_ts = get_optional_arg(get_int_arg, args, 3)?;
+    let end_ts = get_optional_arg(get_int_arg, args, 4)?;
+    let op = TopKOp::new(n, phi, input, source, start_ts, end_ts)?;
     Ok(Box::new(op))
 }
 
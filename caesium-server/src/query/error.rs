@@ -1,5 +1,6 @@
 use caesium_core::encode::EncodableError;
 use query::parser::parse::ParseError;
+use std::fmt;
 use storage::error::StorageError;
 
 #[derive(Debug)]
@@ -13,11 +14,58 @@ pub enum QueryError {
     InvalidArgValue(&'static str),
     PhiOutOfRange(f64),
     InvalidWindowSize(u64),
+    Timeout,
     EncodableError(EncodableError),
     ParseError(ParseError),
     StorageError(StorageError),
 }
 
+impl QueryError {
+    // Stable identifier for the kind of error, so clients can handle errors
+    // without parsing the message
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QueryError::InvalidInput => "invalid_input",
+            QueryError::InvalidExpressionType => "invalid_expression_type",
+            QueryError::UnrecognizedFunction(_) => "unrecognized_function",
+            QueryError::InvalidOutputType => "invalid_output_type",
+            QueryError::MissingArg => "missing_arg",
+            QueryError::InvalidArgType => "invalid_arg_type",
+            QueryError::InvalidArgValue(_) => "invalid_arg_value",
+            QueryError::PhiOutOfRange(_) => "phi_out_of_range",
+            QueryError::InvalidWindowSize(_) => "invalid_window_size",
+            QueryError::Timeout => "timeout",
+            QueryError::EncodableError(_) => "encodable_error",
+            QueryError::ParseError(_) => "parse_error",
+            QueryError::StorageError(_) => "storage_error",
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::InvalidInput => write!(f, "Invalid input to query function"),
+            QueryError::InvalidExpressionType => write!(f, "Query must be a function call"),
+            QueryError::UnrecognizedFunction(name) => {
+                write!(f, "Unrecognized function `{}`", name)
+            }
+            QueryError::InvalidOutputType => write!(f, "Query produced an invalid output type"),
+            QueryError::MissingArg => write!(f, "Missing function argument"),
+            QueryError::InvalidArgType => write!(f, "Invalid function argument type"),
+            QueryError::InvalidArgValue(msg) => write!(f, "{}", msg),
+            QueryError::PhiOutOfRange(phi) => {
+                write!(f, "Phi must be between 0 and 1 (inclusive), got {}", phi)
+            }
+            QueryError::InvalidWindowSize(size) => write!(f, "Invalid window size {}", size),
+            QueryError::Timeout => write!(f, "Query timed out"),
+            QueryError::EncodableError(err) => write!(f, "{:?}", err),
+            QueryError::ParseError(err) => write!(f, "{}", err),
+            QueryError::StorageError(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
 impl From<EncodableError> for QueryError {
     fn from(err: EncodableError) -> QueryError {
         QueryError::EncodableError(err)
@@ -1,35 +1,107 @@
 use caesium_core::quantile::query::ApproxQuantile;
 use caesium_core::time::window::TimeWindow;
+use otel::Span;
 use query::build::build_query;
 use query::error::QueryError;
+pub use query::ops::histogram::OVERFLOW_BUCKET_EDGE;
 use query::ops::OpOutput;
+use std::time::{Duration, Instant};
 use storage::datasource::DataSource;
 
 #[derive(Debug)]
 pub enum QueryResult {
     QuantileWindow(TimeWindow, f64, ApproxQuantile),
+    HistogramWindow(TimeWindow, Vec<(u32, usize)>),
     MetricName(String),
+    Labeled(String, Box<QueryResult>),
 }
 
-pub fn execute_query<'a>(query: &str, source: &DataSource) -> Result<Vec<QueryResult>, QueryError> {
-    let mut pipeline = build_query(query, source)?;
+impl QueryResult {
+    // Label added by an `annotate` op, if any
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            QueryResult::Labeled(label, _) => Some(label),
+            _ => None,
+        }
+    }
+}
+
+pub fn execute_query(
+    query: &str,
+    source: &DataSource,
+    timeout: Option<Duration>,
+) -> Result<Vec<QueryResult>, QueryError> {
     let mut results = Vec::<QueryResult>::new();
+    execute_query_streaming(query, source, timeout, |r| -> Result<(), QueryError> {
+        results.push(r);
+        Ok(())
+    })?;
+    Ok(results)
+}
+
+// Passes each result to `emit` as soon as the query pipeline produces it,
+// so callers can send partial results before the query finishes.
+// Execution stops at the first error returned by `emit`.
+pub fn execute_query_streaming<F, E>(
+    query: &str,
+    source: &DataSource,
+    timeout: Option<Duration>,
+    mut emit: F,
+) -> Result<(), E>
+where
+    F: FnMut(QueryResult) -> Result<(), E>,
+    E: From<QueryError>,
+{
+    let mut span = Span::start("caesium.query");
+    span.set_str("query.string", query);
+    let start = Instant::now();
+    let mut result_count = 0;
+    let result = stream_results(query, source, timeout, |r| {
+        result_count += 1;
+        emit(r)
+    });
+    let elapsed = start.elapsed();
+    span.set_i64("query.result_count", result_count);
+    span.set_f64(
+        "query.duration_ms",
+        elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 / 1e6,
+    );
+    result
+}
+
+fn stream_results<F, E>(
+    query: &str,
+    source: &DataSource,
+    timeout: Option<Duration>,
+    mut emit: F,
+) -> Result<(), E>
+where
+    F: FnMut(QueryResult) -> Result<(), E>,
+    E: From<QueryError>,
+{
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut pipeline = build_query(query, source)?;
     loop {
-        let output = pipeline.get_next()?;
-        match output {
-            OpOutput::End => break,
-            OpOutput::Quantile(window, phi, q_opt) => {
-                if let Some(q) = q_opt {
-                    let r = QueryResult::QuantileWindow(window, phi, q);
-                    results.push(r);
-                }
+        if let Some(d) = deadline {
+            if Instant::now() > d {
+                return Err(E::from(QueryError::Timeout));
             }
-            OpOutput::MetricName(metric) => {
-                let r = QueryResult::MetricName(metric);
-                results.push(r);
-            }
-            _ => return Err(QueryError::InvalidOutputType),
+        }
+        let (label, output) = pipeline.get_next()?.unlabel();
+        let result = match output {
+            OpOutput::End => break,
+            OpOutput::Quantile(window, phi, q_opt) => match q_opt {
+                Some(q) => QueryResult::QuantileWindow(window, phi, q),
+                None => continue,
+            },
+            OpOutput::Histogram(window, buckets) => QueryResult::HistogramWindow(window, buckets),
+            OpOutput::MetricName(metric) => QueryResult::MetricName(metric),
+            _ => return Err(E::from(QueryError::InvalidOutputType)),
+        };
+        match label {
+            Some(label) => emit(QueryResult::Labeled(label, Box::new(result)))?,
+            None => emit(result)?,
         }
     }
-    Ok(results)
+    Ok(())
 }
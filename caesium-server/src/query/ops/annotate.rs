@@ -0,0 +1,22 @@
+use query::error::QueryError;
+use query::ops::{OpOutput, QueryOp};
+
+// Tags every output from the input with a label, replacing any label
+// from an inner `annotate`.
+pub struct AnnotateOp<'a> {
+    label: String,
+    input: Box<QueryOp + 'a>,
+}
+
+impl<'a> AnnotateOp<'a> {
+    pub fn new(label: String, input: Box<QueryOp + 'a>) -> AnnotateOp<'a> {
+        AnnotateOp { label, input }
+    }
+}
+
+impl<'a> QueryOp for AnnotateOp<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        let (_, output) = self.input.get_next()?.unlabel();
+        Ok(output.with_label(Some(self.label.clone())))
+    }
+}
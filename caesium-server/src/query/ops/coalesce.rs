@@ -1,24 +1,24 @@
 use caesium_core::quantile::writable::WritableSketch;
 use caesium_core::time::window::TimeWindow;
 use query::error::QueryError;
-use query::ops::{OpOutput, QueryOp};
+use query::ops::{merge_sketches, OpOutput, QueryOp, UnlabeledInput};
 use std::cmp::{max, min};
 
 pub struct CoalesceOp<'a> {
-    input: Box<QueryOp + 'a>,
+    input: UnlabeledInput<'a>,
     done: bool,
 }
 
 impl<'a> CoalesceOp<'a> {
     pub fn new(input: Box<QueryOp + 'a>) -> CoalesceOp {
         CoalesceOp {
-            input: input,
+            input: UnlabeledInput::new(input),
             done: false,
         }
     }
 
     fn coalesce_inputs(&mut self) -> Result<OpOutput, QueryError> {
-        let mut min_start = u64::max_value();
+        let mut min_start = u64::MAX;
         let mut max_end = 0;
         let mut tmp = None;
 
@@ -32,12 +32,16 @@ impl<'a> CoalesceOp<'a> {
                 Ok(OpOutput::Sketch(window, sketch)) => {
                     min_start = min(min_start, window.start());
                     max_end = max(max_end, window.end());
-                    tmp = Some(merged.merge(sketch));
+                    tmp = Some(merge_sketches(merged, sketch));
+                }
+                Ok(OpOutput::EmptyWindow(_)) => {
+                    tmp = Some(merged);
                 }
                 Ok(OpOutput::End) => {
                     if merged.size() > 0 {
                         let window = TimeWindow::new(min_start, max_end);
-                        return Ok(OpOutput::Sketch(window, merged));
+                        let output = OpOutput::Sketch(window, merged);
+                        return Ok(output.with_label(self.input.label()));
                     } else {
                         return Ok(OpOutput::End);
                     }
@@ -1,7 +1,7 @@
 use caesium_core::quantile::writable::WritableSketch;
 use caesium_core::time::window::TimeWindow;
 use query::error::QueryError;
-use query::ops::{OpOutput, QueryOp};
+use query::ops::{merge_sketches, OpOutput, QueryOp};
 use std::cmp::{max, min, Ordering};
 use std::collections::BinaryHeap;
 use std::ops::DerefMut;
@@ -33,8 +33,8 @@ impl<'a> QueryOp for CombineOp<'a> {
                 Action::OutputEnd => {
                     return Ok(OpOutput::End);
                 }
-                Action::OutputSketch(window, sketch) => {
-                    return Ok(OpOutput::Sketch(window, sketch));
+                Action::OutputSketch(window, sketch, label) => {
+                    return Ok(OpOutput::Sketch(window, sketch).with_label(label));
                 }
             }
         }
@@ -45,24 +45,23 @@ struct HeapItem {
     input_idx: usize,
     window: TimeWindow,
     sketch: WritableSketch,
+    label: Option<String>,
 }
 
 impl HeapItem {
-    fn from_input<'a>(
-        input_idx: usize,
-        input: &'a mut QueryOp,
-    ) -> Result<Option<HeapItem>, QueryError> {
-        match input.get_next()? {
-            OpOutput::Sketch(window, sketch) => {
+    fn from_input(input_idx: usize, input: &mut QueryOp) -> Result<Option<HeapItem>, QueryError> {
+        match input.get_next()?.empty_as_sketch().unlabel() {
+            (label, OpOutput::Sketch(window, sketch)) => {
                 let item = HeapItem {
                     input_idx,
                     window,
                     sketch,
+                    label,
                 };
                 Ok(Some(item))
             }
-            OpOutput::End => Ok(None),
-            _ => return Err(QueryError::InvalidInput),
+            (_, OpOutput::End) => Ok(None),
+            _ => Err(QueryError::InvalidInput),
         }
     }
 
@@ -76,8 +75,24 @@ impl HeapItem {
         HeapItem {
             input_idx: self.input_idx,
             window: TimeWindow::new(min_start, max_end),
-            sketch: self.sketch.merge(other.sketch),
+            sketch: merge_sketches(self.sketch, other.sketch),
+            label: merge_labels(self.label, other.label),
+        }
+    }
+}
+
+// Windows merged from differently labeled inputs get every label, joined with "+"
+fn merge_labels(l1: Option<String>, l2: Option<String>) -> Option<String> {
+    match (l1, l2) {
+        (Some(l1), Some(l2)) => {
+            if l1.split('+').any(|l| l == l2) {
+                Some(l1)
+            } else {
+                Some(format!("{}+{}", l1, l2))
+            }
         }
+        (l1, None) => l1,
+        (None, l2) => l2,
     }
 }
 
@@ -105,7 +120,7 @@ impl PartialEq for HeapItem {
 enum Action {
     NoOutput,
     OutputEnd,
-    OutputSketch(TimeWindow, WritableSketch),
+    OutputSketch(TimeWindow, WritableSketch, Option<String>),
 }
 
 enum State {
@@ -173,12 +188,17 @@ impl State {
                     Ok((next_state, Action::NoOutput))
                 } else {
                     let next_state = State::Combining(item, heap);
-                    let action = Action::OutputSketch(stored_item.window, stored_item.sketch);
+                    let action = Action::OutputSketch(
+                        stored_item.window,
+                        stored_item.sketch,
+                        stored_item.label,
+                    );
                     Ok((next_state, action))
                 }
             }
             None => {
-                let action = Action::OutputSketch(stored_item.window, stored_item.sketch);
+                let action =
+                    Action::OutputSketch(stored_item.window, stored_item.sketch, stored_item.label);
                 Ok((State::Done, action))
             }
         }
@@ -1,5 +1,6 @@
 use caesium_core::time::timestamp::TimeStamp;
 use query::error::QueryError;
+use query::ops::combine::CombineOp;
 use query::ops::{OpOutput, QueryOp};
 use storage::datasource::{DataRow, DataSource};
 
@@ -27,3 +28,53 @@ impl<'a> QueryOp for FetchOp<'a> {
         }
     }
 }
+
+// Fetches every metric matching a wildcard pattern, merging overlapping windows
+// across metrics the same way as `combine`.  Matching metrics are looked up
+// on the first call to `get_next`, not when the op is built.
+pub struct FetchGlobOp<'a> {
+    pattern: String,
+    source: &'a DataSource,
+    start_ts: Option<TimeStamp>,
+    end_ts: Option<TimeStamp>,
+    combined: Option<CombineOp<'a>>,
+}
+
+impl<'a> FetchGlobOp<'a> {
+    pub fn new(
+        pattern: String,
+        source: &'a DataSource,
+        start_ts: Option<TimeStamp>,
+        end_ts: Option<TimeStamp>,
+    ) -> FetchGlobOp<'a> {
+        FetchGlobOp {
+            pattern,
+            source,
+            start_ts,
+            end_ts,
+            combined: None,
+        }
+    }
+
+    fn build_combined(&self) -> Result<CombineOp<'a>, QueryError> {
+        let metrics = self.source.search(self.pattern.clone())?;
+        let mut inputs: Vec<Box<QueryOp + 'a>> = Vec::new();
+        for metric in metrics {
+            let op = FetchOp::new(metric, self.source, self.start_ts, self.end_ts)?;
+            inputs.push(Box::new(op));
+        }
+        Ok(CombineOp::new(inputs))
+    }
+}
+
+impl<'a> QueryOp for FetchGlobOp<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        if self.combined.is_none() {
+            self.combined = Some(self.build_combined()?);
+        }
+        match self.combined {
+            Some(ref mut op) => op.get_next(),
+            None => Ok(OpOutput::End),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use caesium_core::quantile::query::ApproxQuantile;
+use query::error::QueryError;
+use query::ops::{OpOutput, QueryOp};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillMode {
+    // Leave empty windows without a value
+    None,
+
+    // Emit zero for empty windows
+    Zero,
+
+    // Emit the last value for the same phi, if there was one
+    Previous,
+}
+
+impl FillMode {
+    pub fn from_str(s: &str) -> Result<FillMode, QueryError> {
+        match s {
+            "none" => Ok(FillMode::None),
+            "zero" => Ok(FillMode::Zero),
+            "previous" => Ok(FillMode::Previous),
+            _ => Err(QueryError::InvalidArgValue(
+                "Fill must be either none, zero, or previous",
+            )),
+        }
+    }
+}
+
+// Fills in quantiles for empty windows, such as the gaps emitted by `resample`.
+// Other outputs pass through unchanged.
+pub struct FillOp<'a> {
+    mode: FillMode,
+    input: Box<QueryOp + 'a>,
+    previous: Vec<(f64, ApproxQuantile)>,
+}
+
+impl<'a> FillOp<'a> {
+    pub fn new(mode: FillMode, input: Box<QueryOp + 'a>) -> FillOp<'a> {
+        FillOp {
+            mode,
+            input,
+            previous: Vec::new(),
+        }
+    }
+
+    fn fill(&mut self, phi: f64, quantile: Option<ApproxQuantile>) -> Option<ApproxQuantile> {
+        match (quantile, self.mode) {
+            (Some(q), _) => {
+                match self.previous.iter_mut().find(|(p, _)| *p == phi) {
+                    Some(prev) => prev.1 = q,
+                    None => self.previous.push((phi, q)),
+                }
+                Some(q)
+            }
+            (None, FillMode::None) => None,
+            (None, FillMode::Zero) => Some(ApproxQuantile {
+                count: 0,
+                approx_value: 0,
+                lower_bound: 0,
+                upper_bound: 0,
+                scale: 0,
+            }),
+            (None, FillMode::Previous) => self
+                .previous
+                .iter()
+                .find(|(p, _)| *p == phi)
+                .map(|&(_, q)| q),
+        }
+    }
+}
+
+impl<'a> QueryOp for FillOp<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        match self.input.get_next()?.unlabel() {
+            (label, OpOutput::Quantile(window, phi, quantile)) => {
+                let filled = self.fill(phi, quantile);
+                Ok(OpOutput::Quantile(window, phi, filled).with_label(label))
+            }
+            (label, output) => Ok(output.with_label(label)),
+        }
+    }
+}
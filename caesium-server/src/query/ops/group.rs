@@ -1,12 +1,15 @@
 use caesium_core::quantile::writable::WritableSketch;
-use caesium_core::time::timestamp::{days, hours};
+use caesium_core::time::timestamp::{
+    days, hours, weeks, TimeStamp, SECONDS_PER_DAY, SECONDS_PER_HOUR, SECONDS_PER_WEEK,
+};
 use caesium_core::time::window::TimeWindow;
+use chrono::{Datelike, TimeZone, Utc};
 use query::error::QueryError;
-use query::ops::{OpOutput, QueryOp};
+use query::ops::{merge_sketches, OpOutput, QueryOp, UnlabeledInput};
 use std::cmp::{max, min};
 
 pub struct GroupOp<'a> {
-    input: Box<QueryOp + 'a>,
+    input: UnlabeledInput<'a>,
     group_type: GroupType,
     state: Option<State>,
 }
@@ -15,7 +18,7 @@ impl<'a> GroupOp<'a> {
     pub fn new(group_type: GroupType, input: Box<QueryOp + 'a>) -> Result<GroupOp<'a>, QueryError> {
         let op = GroupOp {
             group_type,
-            input,
+            input: UnlabeledInput::new(input),
             state: Some(State::initial()),
         };
         Ok(op)
@@ -27,7 +30,7 @@ impl<'a> QueryOp for GroupOp<'a> {
         let group_type = self.group_type;
         loop {
             let state = self.state.take().expect("Expected state to be nonempty");
-            let (next_state, action) = state.transition(group_type, &mut *self.input)?;
+            let (next_state, action) = state.transition(group_type, &mut self.input)?;
             self.state = Some(next_state);
             match action {
                 Action::NoOutput => {
@@ -37,7 +40,7 @@ impl<'a> QueryOp for GroupOp<'a> {
                     return Ok(OpOutput::End);
                 }
                 Action::OutputSketch(window, sketch) => {
-                    return Ok(OpOutput::Sketch(window, sketch));
+                    return Ok(OpOutput::Sketch(window, sketch).with_label(self.input.label()));
                 }
             }
         }
@@ -49,9 +52,12 @@ pub enum GroupType {
     Seconds,
     Hours,
     Days,
+    Weeks,
+    Months,
 }
 
-type GroupId = u64;
+// Start of the bucket containing the group's windows
+type GroupId = TimeStamp;
 
 impl GroupType {
     pub fn from_str(s: &str) -> Result<GroupType, QueryError> {
@@ -59,20 +65,43 @@ impl GroupType {
             "seconds" => Ok(GroupType::Seconds),
             "hours" => Ok(GroupType::Hours),
             "days" => Ok(GroupType::Days),
+            "weeks" => Ok(GroupType::Weeks),
+            "months" => Ok(GroupType::Months),
             _ => Err(QueryError::InvalidArgValue(
-                "Group must be either seconds, hours, or days",
+                "Group must be one of seconds, hours, days, weeks, or months",
             )),
         }
     }
 
     fn calculate_group_id(&self, window: TimeWindow) -> GroupId {
-        let start_ts = window.start();
-        match self {
-            GroupType::Seconds => start_ts,
-            GroupType::Hours => hours(start_ts),
-            GroupType::Days => days(start_ts),
-        }
+        bucket_start(window.start(), *self)
+    }
+}
+
+// Hours, days, and weeks are fixed multiples of seconds since the epoch,
+// while months start at midnight UTC on the first day of each calendar month.
+pub fn bucket_start(ts: TimeStamp, group_type: GroupType) -> TimeStamp {
+    match group_type {
+        GroupType::Seconds => ts,
+        GroupType::Hours => hours(ts) * SECONDS_PER_HOUR,
+        GroupType::Days => days(ts) * SECONDS_PER_DAY,
+        GroupType::Weeks => weeks(ts) * SECONDS_PER_WEEK,
+        GroupType::Months => month_start(ts),
+    }
+}
+
+// Timestamps too large for a calendar date each get their own bucket
+fn month_start(ts: TimeStamp) -> TimeStamp {
+    if ts > i64::MAX as u64 {
+        return ts;
     }
+    Utc.timestamp_opt(ts as i64, 0)
+        .single()
+        .and_then(|dt| {
+            Utc.with_ymd_and_hms(dt.year(), dt.month(), 1, 0, 0, 0)
+                .single()
+        })
+        .map_or(ts, |start| start.timestamp() as TimeStamp)
 }
 
 enum Action {
@@ -92,10 +121,10 @@ impl State {
         State::Empty
     }
 
-    fn transition<'a>(
+    fn transition(
         self,
         group_type: GroupType,
-        input: &'a mut QueryOp,
+        input: &mut QueryOp,
     ) -> Result<(State, Action), QueryError> {
         match self {
             State::Empty => State::transition_empty(group_type, input),
@@ -106,11 +135,11 @@ impl State {
         }
     }
 
-    fn transition_empty<'a>(
+    fn transition_empty(
         group_type: GroupType,
-        input: &'a mut QueryOp,
+        input: &mut QueryOp,
     ) -> Result<(State, Action), QueryError> {
-        match input.get_next()? {
+        match input.get_next()?.empty_as_sketch() {
             OpOutput::End => Ok((State::Done, Action::OutputEnd)),
             OpOutput::Sketch(window, sketch) => {
                 let group_id = group_type.calculate_group_id(window);
@@ -121,14 +150,14 @@ impl State {
         }
     }
 
-    fn transition_merging<'a>(
+    fn transition_merging(
         prev_group_id: GroupId,
         prev_window: TimeWindow,
         prev_sketch: WritableSketch,
         group_type: GroupType,
-        input: &'a mut QueryOp,
+        input: &mut QueryOp,
     ) -> Result<(State, Action), QueryError> {
-        match input.get_next()? {
+        match input.get_next()?.empty_as_sketch() {
             OpOutput::End => {
                 let action = Action::OutputSketch(prev_window, prev_sketch);
                 Ok((State::Done, action))
@@ -139,8 +168,8 @@ impl State {
                     let min_start = min(window.start(), prev_window.start());
                     let max_end = max(window.end(), prev_window.end());
                     let merged_window = TimeWindow::new(min_start, max_end);
-                    let next_state =
-                        State::Merging(next_group_id, merged_window, sketch.merge(prev_sketch));
+                    let merged_sketch = merge_sketches(prev_sketch, sketch);
+                    let next_state = State::Merging(next_group_id, merged_window, merged_sketch);
                     Ok((next_state, Action::NoOutput))
                 } else {
                     let next_state = State::Merging(next_group_id, window, sketch);
@@ -0,0 +1,63 @@
+use caesium_core::quantile::writable::WritableSketch;
+use query::error::QueryError;
+use query::ops::{OpOutput, QueryOp};
+
+const MAX_BUCKET_COUNT: u64 = 1000;
+
+// Upper edge of the last bucket, which contains every value
+pub const OVERFLOW_BUCKET_EDGE: u32 = u32::MAX;
+
+// Emits a cumulative histogram for each window, similar to Prometheus histograms.
+// The first `bucket_count - 1` upper edges are quantiles at evenly spaced phi values,
+// and the last bucket has edge `OVERFLOW_BUCKET_EDGE` with the total count.
+// Cumulative counts are estimated from each bucket's phi value.
+pub struct HistogramOp<'a> {
+    input: Box<QueryOp + 'a>,
+    bucket_count: u64,
+}
+
+impl<'a> HistogramOp<'a> {
+    pub fn new(input: Box<QueryOp + 'a>, bucket_count: u64) -> Result<HistogramOp, QueryError> {
+        if bucket_count == 0 || bucket_count > MAX_BUCKET_COUNT {
+            return Err(QueryError::InvalidArgValue(
+                "Bucket count must be between 1 and 1000",
+            ));
+        }
+        Ok(HistogramOp {
+            input,
+            bucket_count,
+        })
+    }
+
+    fn build_buckets(&self, sketch: WritableSketch) -> Vec<(u32, usize)> {
+        let count = sketch.count();
+        let readable = sketch.to_readable();
+        let mut buckets: Vec<(u32, usize)> = Vec::with_capacity(self.bucket_count as usize);
+        for i in 1..self.bucket_count {
+            let phi = i as f64 / self.bucket_count as f64;
+            if let Some(q) = readable.query(phi) {
+                let cumulative_count = (phi * count as f64).round() as usize;
+                match buckets.last_mut() {
+                    // Collapse buckets with the same edge, keeping the larger count
+                    Some(last) if last.0 == q.approx_value => last.1 = cumulative_count,
+                    _ => buckets.push((q.approx_value, cumulative_count)),
+                }
+            }
+        }
+        buckets.push((OVERFLOW_BUCKET_EDGE, count));
+        buckets
+    }
+}
+
+impl<'a> QueryOp for HistogramOp<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        match self.input.get_next()?.empty_as_sketch().unlabel() {
+            (label, OpOutput::Sketch(window, sketch)) => {
+                let buckets = self.build_buckets(sketch);
+                Ok(OpOutput::Histogram(window, buckets).with_label(label))
+            }
+            (_, OpOutput::End) => Ok(OpOutput::End),
+            _ => Err(QueryError::InvalidInput),
+        }
+    }
+}
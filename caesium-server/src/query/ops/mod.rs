@@ -6,8 +6,54 @@ use query::error::QueryError;
 pub enum OpOutput {
     End,
     Sketch(TimeWindow, WritableSketch),
+
+    // Window with no data, for ops that emit a contiguous series of windows
+    EmptyWindow(TimeWindow),
     Quantile(TimeWindow, f64, Option<ApproxQuantile>),
+    Histogram(TimeWindow, Vec<(u32, usize)>),
     MetricName(String),
+
+    // Output from within an `annotate` op, tagged with the annotation's label
+    Labeled(String, Box<OpOutput>),
+}
+
+impl OpOutput {
+    // Splits off the label, if any, so ops can process the inner output
+    pub fn unlabel(self) -> (Option<String>, OpOutput) {
+        match self {
+            OpOutput::Labeled(label, output) => (Some(label), *output),
+            output => (None, output),
+        }
+    }
+
+    // Treats an empty window as a window with an empty sketch,
+    // for ops that don't need to distinguish the two
+    pub fn empty_as_sketch(self) -> OpOutput {
+        match self {
+            OpOutput::EmptyWindow(window) => OpOutput::Sketch(window, WritableSketch::new()),
+            OpOutput::Labeled(label, output) => {
+                OpOutput::Labeled(label, Box::new(output.empty_as_sketch()))
+            }
+            output => output,
+        }
+    }
+
+    pub fn with_label(self, label: Option<String>) -> OpOutput {
+        match (label, self) {
+            (_, OpOutput::End) => OpOutput::End,
+            (Some(label), output) => OpOutput::Labeled(label, Box::new(output)),
+            (None, output) => output,
+        }
+    }
+}
+
+// Merges `other` into `sketch`. Sketches with different scales can't be merged,
+// so `other` is logged and skipped instead of failing the query.
+pub fn merge_sketches(mut sketch: WritableSketch, other: WritableSketch) -> WritableSketch {
+    if let Err(err) = sketch.merge_from(other) {
+        error!("Skipping sketch that could not be merged: {:?}", err);
+    }
+    sketch
 }
 
 pub trait QueryOp {
@@ -15,9 +61,42 @@ pub trait QueryOp {
     fn get_next(&mut self) -> Result<OpOutput, QueryError>;
 }
 
+// Strips labels from an input's outputs, remembering the last label seen,
+// so ops that merge several inputs into one output can label it the same way
+pub struct UnlabeledInput<'a> {
+    input: Box<QueryOp + 'a>,
+    label: Option<String>,
+}
+
+impl<'a> UnlabeledInput<'a> {
+    pub fn new(input: Box<QueryOp + 'a>) -> UnlabeledInput<'a> {
+        UnlabeledInput { input, label: None }
+    }
+
+    pub fn label(&self) -> Option<String> {
+        self.label.clone()
+    }
+}
+
+impl<'a> QueryOp for UnlabeledInput<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        let (label, output) = self.input.get_next()?.unlabel();
+        if label.is_some() {
+            self.label = label;
+        }
+        Ok(output)
+    }
+}
+
+pub mod annotate;
 pub mod coalesce;
 pub mod combine;
 pub mod fetch;
+pub mod fill;
 pub mod group;
+pub mod histogram;
+pub mod moving_average;
 pub mod quantile;
+pub mod resample;
 pub mod search;
+pub mod topk;
@@ -0,0 +1,154 @@
+use caesium_core::quantile::query::ApproxQuantile;
+use caesium_core::quantile::writable::WritableSketch;
+use query::error::QueryError;
+use query::ops::{merge_sketches, OpOutput, QueryOp};
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+
+// Queries each window merged with up to `window_count - 1` preceding windows.
+// Until `window_count` windows have been seen, merges every window so far.
+//
+// Without a phi, the input must be quantiles instead, and each quantile value is
+// averaged with up to `window_count - 1` preceding values for the same phi and label.
+// The bounds of an averaged quantile span the bounds of every quantile averaged into it.
+// Windows without a quantile pass through and are not averaged.
+pub struct MovingAverageOp<'a> {
+    input: Box<QueryOp + 'a>,
+    window_count: usize,
+    phi: Option<f64>,
+    buffer: VecDeque<WritableSketch>,
+    series: Vec<SeriesBuffer>,
+}
+
+struct SeriesBuffer {
+    label: Option<String>,
+    phi: f64,
+    quantiles: VecDeque<ApproxQuantile>,
+}
+
+impl<'a> MovingAverageOp<'a> {
+    pub fn new(
+        window_count: u64,
+        input: Box<QueryOp + 'a>,
+        phi: f64,
+    ) -> Result<MovingAverageOp<'a>, QueryError> {
+        if !(0.0..=1.0).contains(&phi) {
+            return Err(QueryError::PhiOutOfRange(phi));
+        }
+        MovingAverageOp::build(window_count, input, Some(phi))
+    }
+
+    pub fn averaging_quantiles(
+        window_count: u64,
+        input: Box<QueryOp + 'a>,
+    ) -> Result<MovingAverageOp<'a>, QueryError> {
+        MovingAverageOp::build(window_count, input, None)
+    }
+
+    fn build(
+        window_count: u64,
+        input: Box<QueryOp + 'a>,
+        phi: Option<f64>,
+    ) -> Result<MovingAverageOp<'a>, QueryError> {
+        if window_count == 0 {
+            return Err(QueryError::InvalidArgValue(
+                "Moving average window count must be at least 1",
+            ));
+        }
+        let window_count = window_count as usize;
+        Ok(MovingAverageOp {
+            input,
+            window_count,
+            phi,
+            buffer: VecDeque::with_capacity(window_count),
+            series: Vec::new(),
+        })
+    }
+
+    fn merge_buffer(&self) -> WritableSketch {
+        self.buffer
+            .iter()
+            .fold(WritableSketch::new(), |acc, sketch| {
+                merge_sketches(acc, sketch.clone())
+            })
+    }
+
+    fn next_merged(&mut self, phi: f64) -> Result<OpOutput, QueryError> {
+        match self.input.get_next()?.empty_as_sketch().unlabel() {
+            (label, OpOutput::Sketch(window, sketch)) => {
+                if self.buffer.len() == self.window_count {
+                    self.buffer.pop_front();
+                }
+                self.buffer.push_back(sketch);
+                let quantile = self.merge_buffer().to_readable().query(phi);
+                Ok(OpOutput::Quantile(window, phi, quantile).with_label(label))
+            }
+            (_, OpOutput::End) => Ok(OpOutput::End),
+            _ => Err(QueryError::InvalidInput),
+        }
+    }
+
+    fn next_averaged(&mut self) -> Result<OpOutput, QueryError> {
+        match self.input.get_next()?.unlabel() {
+            (label, OpOutput::Quantile(window, phi, Some(q))) => {
+                let averaged = self.average(&label, phi, q);
+                Ok(OpOutput::Quantile(window, phi, Some(averaged)).with_label(label))
+            }
+            (label, OpOutput::Quantile(window, phi, None)) => {
+                Ok(OpOutput::Quantile(window, phi, None).with_label(label))
+            }
+            (_, OpOutput::End) => Ok(OpOutput::End),
+            _ => Err(QueryError::InvalidInput),
+        }
+    }
+
+    fn average(&mut self, label: &Option<String>, phi: f64, q: ApproxQuantile) -> ApproxQuantile {
+        let window_count = self.window_count;
+        let idx = match self
+            .series
+            .iter()
+            .position(|s| s.phi == phi && s.label == *label)
+        {
+            Some(idx) => idx,
+            None => {
+                self.series.push(SeriesBuffer {
+                    label: label.clone(),
+                    phi,
+                    quantiles: VecDeque::with_capacity(window_count),
+                });
+                self.series.len() - 1
+            }
+        };
+
+        let quantiles = &mut self.series[idx].quantiles;
+        if quantiles.len() == window_count {
+            quantiles.pop_front();
+        }
+        quantiles.push_back(q);
+
+        let n = quantiles.len() as u64;
+        let sum: u64 = quantiles.iter().map(|q| q.approx_value as u64).sum();
+        let lower_bound = quantiles
+            .iter()
+            .fold(q.lower_bound, |b, q| min(b, q.lower_bound));
+        let upper_bound = quantiles
+            .iter()
+            .fold(q.upper_bound, |b, q| max(b, q.upper_bound));
+        ApproxQuantile {
+            count: q.count,
+            approx_value: ((sum + n / 2) / n) as u32,
+            lower_bound,
+            upper_bound,
+            scale: q.scale,
+        }
+    }
+}
+
+impl<'a> QueryOp for MovingAverageOp<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        match self.phi {
+            Some(phi) => self.next_merged(phi),
+            None => self.next_averaged(),
+        }
+    }
+}
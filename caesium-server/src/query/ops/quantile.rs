@@ -13,7 +13,7 @@ pub struct QuantileOp<'a> {
 impl<'a> QuantileOp<'a> {
     pub fn new(input: Box<QueryOp + 'a>, phi_vec: Vec<f64>) -> Result<QuantileOp, QueryError> {
         for &phi in phi_vec.iter() {
-            if phi <= 0.0 || phi >= 1.0 {
+            if !(0.0..=1.0).contains(&phi) {
                 return Err(QueryError::PhiOutOfRange(phi));
             }
         }
@@ -24,11 +24,16 @@ impl<'a> QuantileOp<'a> {
         })
     }
 
-    fn fill_output_queue(&mut self, window: TimeWindow, sketch: WritableSketch) {
+    fn fill_output_queue(
+        &mut self,
+        window: TimeWindow,
+        sketch: WritableSketch,
+        label: Option<String>,
+    ) {
         let readable = sketch.to_readable();
         for &phi in self.phi_vec.iter() {
             let quantile = readable.query(phi);
-            let output = OpOutput::Quantile(window, phi, quantile);
+            let output = OpOutput::Quantile(window, phi, quantile).with_label(label.clone());
             self.output_queue.push_back(output);
         }
     }
@@ -37,9 +42,11 @@ impl<'a> QuantileOp<'a> {
 impl<'a> QueryOp for QuantileOp<'a> {
     fn get_next(&mut self) -> Result<OpOutput, QueryError> {
         if self.output_queue.is_empty() {
-            match self.input.get_next()? {
-                OpOutput::Sketch(window, sketch) => self.fill_output_queue(window, sketch),
-                OpOutput::End => return Ok(OpOutput::End),
+            match self.input.get_next()?.empty_as_sketch().unlabel() {
+                (label, OpOutput::Sketch(window, sketch)) => {
+                    self.fill_output_queue(window, sketch, label)
+                }
+                (_, OpOutput::End) => return Ok(OpOutput::End),
                 _ => return Err(QueryError::InvalidInput),
             }
         }
@@ -0,0 +1,194 @@
+use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::timestamp::TimeStamp;
+use caesium_core::time::window::TimeWindow;
+use query::error::QueryError;
+use query::ops::{merge_sketches, OpOutput, QueryOp, UnlabeledInput};
+
+// Limits the empty windows emitted for gaps in the input, so a small interval
+// over a sparse metric can't produce an unbounded number of windows
+const MAX_EMPTY_WINDOWS: u64 = 100_000;
+
+pub struct ResampleOp<'a> {
+    input: UnlabeledInput<'a>,
+    bucket_secs: u64,
+    state: Option<State>,
+    empty_count: u64,
+}
+
+impl<'a> ResampleOp<'a> {
+    pub fn new(bucket_secs: u64, input: Box<QueryOp + 'a>) -> Result<ResampleOp<'a>, QueryError> {
+        if bucket_secs == 0 {
+            return Err(QueryError::InvalidWindowSize(bucket_secs));
+        }
+        let op = ResampleOp {
+            input: UnlabeledInput::new(input),
+            bucket_secs,
+            state: Some(State::initial()),
+            empty_count: 0,
+        };
+        Ok(op)
+    }
+}
+
+impl<'a> QueryOp for ResampleOp<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        let bucket_secs = self.bucket_secs;
+        loop {
+            let state = self.state.take().expect("Expected state to be nonempty");
+            let (next_state, action) = state.transition(bucket_secs, &mut self.input)?;
+            if let (&State::Filling(gap_start, next_start, _), &Action::OutputSketch(..)) =
+                (&next_state, &action)
+            {
+                // Entering a gap, so check its size before emitting any of it
+                self.empty_count += (next_start - gap_start) / bucket_secs;
+                if self.empty_count > MAX_EMPTY_WINDOWS {
+                    return Err(QueryError::InvalidArgValue(
+                        "Resample would emit too many empty windows, use a larger interval",
+                    ));
+                }
+            }
+            self.state = Some(next_state);
+            let output = match action {
+                Action::NoOutput => {
+                    continue;
+                }
+                Action::OutputEnd => {
+                    return Ok(OpOutput::End);
+                }
+                Action::OutputSketch(window, sketch) => {
+                    if sketch.count() == 0 {
+                        OpOutput::EmptyWindow(window)
+                    } else {
+                        OpOutput::Sketch(window, *sketch)
+                    }
+                }
+                Action::OutputEmpty(window) => OpOutput::EmptyWindow(window),
+            };
+            return Ok(output.with_label(self.input.label()));
+        }
+    }
+}
+
+type BucketStart = TimeStamp;
+
+fn bucket_start(bucket_secs: u64, window: TimeWindow) -> BucketStart {
+    window.start() - (window.start() % bucket_secs)
+}
+
+// Output windows always span exactly one bucket.  Input windows are assigned
+// to the bucket containing their start, even if they extend past its end.
+fn bucket_window(bucket_secs: u64, start: BucketStart) -> TimeWindow {
+    TimeWindow::new(start, start.saturating_add(bucket_secs))
+}
+
+// The sketch is boxed so actions without one stay small
+enum Action {
+    NoOutput,
+    OutputEnd,
+    OutputSketch(TimeWindow, Box<WritableSketch>),
+    OutputEmpty(TimeWindow),
+}
+
+enum State {
+    Empty,
+    Merging(BucketStart, WritableSketch),
+
+    // Emitting empty buckets for a gap in the input, up to the bucket
+    // with the next input sketch
+    Filling(BucketStart, BucketStart, WritableSketch),
+    Done,
+}
+
+impl State {
+    fn initial() -> State {
+        State::Empty
+    }
+
+    fn transition(
+        self,
+        bucket_secs: u64,
+        input: &mut QueryOp,
+    ) -> Result<(State, Action), QueryError> {
+        match self {
+            State::Empty => State::transition_empty(bucket_secs, input),
+            State::Merging(start, sketch) => {
+                State::transition_merging(start, sketch, bucket_secs, input)
+            }
+            State::Filling(gap_start, next_start, next_sketch) => Ok(State::transition_filling(
+                gap_start,
+                next_start,
+                next_sketch,
+                bucket_secs,
+            )),
+            State::Done => Ok((State::Done, Action::OutputEnd)),
+        }
+    }
+
+    fn transition_empty(
+        bucket_secs: u64,
+        input: &mut QueryOp,
+    ) -> Result<(State, Action), QueryError> {
+        match input.get_next()?.empty_as_sketch() {
+            OpOutput::End => Ok((State::Done, Action::OutputEnd)),
+            OpOutput::Sketch(window, sketch) => {
+                let start = bucket_start(bucket_secs, window);
+                Ok((State::Merging(start, sketch), Action::NoOutput))
+            }
+            _ => Err(QueryError::InvalidInput),
+        }
+    }
+
+    fn transition_merging(
+        prev_start: BucketStart,
+        prev_sketch: WritableSketch,
+        bucket_secs: u64,
+        input: &mut QueryOp,
+    ) -> Result<(State, Action), QueryError> {
+        match input.get_next()?.empty_as_sketch() {
+            OpOutput::End => {
+                let action = Action::OutputSketch(
+                    bucket_window(bucket_secs, prev_start),
+                    Box::new(prev_sketch),
+                );
+                Ok((State::Done, action))
+            }
+            OpOutput::Sketch(window, sketch) => {
+                let next_start = bucket_start(bucket_secs, window);
+                if next_start <= prev_start {
+                    let next_state =
+                        State::Merging(prev_start, merge_sketches(prev_sketch, sketch));
+                    return Ok((next_state, Action::NoOutput));
+                }
+
+                let gap_start = prev_start.saturating_add(bucket_secs);
+                let next_state = if gap_start < next_start {
+                    State::Filling(gap_start, next_start, sketch)
+                } else {
+                    State::Merging(next_start, sketch)
+                };
+                let action = Action::OutputSketch(
+                    bucket_window(bucket_secs, prev_start),
+                    Box::new(prev_sketch),
+                );
+                Ok((next_state, action))
+            }
+            _ => Err(QueryError::InvalidInput),
+        }
+    }
+
+    fn transition_filling(
+        gap_start: BucketStart,
+        next_start: BucketStart,
+        next_sketch: WritableSketch,
+        bucket_secs: u64,
+    ) -> (State, Action) {
+        let next_gap_start = gap_start + bucket_secs;
+        let next_state = if next_gap_start < next_start {
+            State::Filling(next_gap_start, next_start, next_sketch)
+        } else {
+            State::Merging(next_start, next_sketch)
+        };
+        let action = Action::OutputEmpty(bucket_window(bucket_secs, gap_start));
+        (next_state, action)
+    }
+}
@@ -1,14 +1,63 @@
 use query::error::QueryError;
 use query::ops::{OpOutput, QueryOp};
+use std::collections::BTreeSet;
 use storage::datasource::DataSource;
+use storage::wildcard::metric_match;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    // Metrics matching at least one pattern
+    Any,
+
+    // Metrics matching every pattern
+    All,
+}
+
+impl SearchMode {
+    pub fn from_str(s: &str) -> Result<SearchMode, QueryError> {
+        match s {
+            "any" => Ok(SearchMode::Any),
+            "all" => Ok(SearchMode::All),
+            _ => Err(QueryError::InvalidArgValue(
+                "Search mode must be either any or all",
+            )),
+        }
+    }
+}
 
 pub struct SearchOp<'a> {
     metric_iter: Box<Iterator<Item = String> + 'a>,
 }
 
 impl<'a> SearchOp<'a> {
-    pub fn new(pattern: String, source: &'a DataSource) -> Result<SearchOp<'a>, QueryError> {
-        let metric_iter = source.search(pattern)?;
+    pub fn new(
+        mode: SearchMode,
+        mut patterns: Vec<String>,
+        source: &'a DataSource,
+    ) -> Result<SearchOp<'a>, QueryError> {
+        if patterns.is_empty() {
+            return Err(QueryError::MissingArg);
+        }
+        let first = patterns.remove(0);
+        let metric_iter: Box<Iterator<Item = String> + 'a> = if patterns.is_empty() {
+            source.search(first)?
+        } else {
+            match mode {
+                SearchMode::Any => {
+                    // Metrics matching more than one pattern are returned once, in sorted order
+                    let mut metrics = BTreeSet::new();
+                    metrics.extend(source.search(first)?);
+                    for pattern in patterns {
+                        metrics.extend(source.search(pattern)?);
+                    }
+                    Box::new(metrics.into_iter())
+                }
+                SearchMode::All => {
+                    let iter = source.search(first)?;
+                    Box::new(iter.filter(move |m| patterns.iter().all(|p| metric_match(m, p))))
+                }
+            }
+        };
         Ok(SearchOp { metric_iter })
     }
 }
@@ -0,0 +1,96 @@
+use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::timestamp::TimeStamp;
+use query::error::QueryError;
+use query::ops::{merge_sketches, OpOutput, QueryOp, UnlabeledInput};
+use std::cmp::Ordering;
+use std::vec::IntoIter;
+use storage::datasource::DataSource;
+
+// Ranks the metric names from the input by the quantile at `phi` over all
+// their windows in the time range, then emits the `n` highest, largest first.
+// Metrics without any data in the range are skipped.
+pub struct TopKOp<'a> {
+    n: usize,
+    phi: f64,
+    input: UnlabeledInput<'a>,
+    source: &'a DataSource,
+    start_ts: Option<TimeStamp>,
+    end_ts: Option<TimeStamp>,
+    output: Option<IntoIter<String>>,
+}
+
+impl<'a> TopKOp<'a> {
+    pub fn new(
+        n: u64,
+        phi: f64,
+        input: Box<QueryOp + 'a>,
+        source: &'a DataSource,
+        start_ts: Option<TimeStamp>,
+        end_ts: Option<TimeStamp>,
+    ) -> Result<TopKOp<'a>, QueryError> {
+        if n == 0 {
+            return Err(QueryError::InvalidArgValue("N must be greater than zero"));
+        }
+        if !(0.0..=1.0).contains(&phi) {
+            return Err(QueryError::PhiOutOfRange(phi));
+        }
+        Ok(TopKOp {
+            n: n as usize,
+            phi,
+            input: UnlabeledInput::new(input),
+            source,
+            start_ts,
+            end_ts,
+            output: None,
+        })
+    }
+
+    fn rank_metrics(&mut self) -> Result<Vec<String>, QueryError> {
+        let mut ranked = Vec::new();
+        loop {
+            match self.input.get_next()? {
+                OpOutput::MetricName(metric) => {
+                    if let Some(value) = self.metric_quantile(&metric)? {
+                        ranked.push((value, metric));
+                    }
+                }
+                OpOutput::End => break,
+                _ => return Err(QueryError::InvalidInput),
+            }
+        }
+        // Sort descending by value, breaking ties by name so the output is stable
+        ranked.sort_by(|a, b| match b.0.cmp(&a.0) {
+            Ordering::Equal => a.1.cmp(&b.1),
+            ord => ord,
+        });
+        ranked.truncate(self.n);
+        Ok(ranked.into_iter().map(|(_, metric)| metric).collect())
+    }
+
+    fn metric_quantile(&self, metric: &str) -> Result<Option<u32>, QueryError> {
+        let rows = self
+            .source
+            .fetch(metric.to_string(), self.start_ts, self.end_ts)?;
+        let merged = rows.fold(WritableSketch::new(), |acc, row| {
+            merge_sketches(acc, row.sketch)
+        });
+        if merged.size() == 0 {
+            return Ok(None);
+        }
+        let value = merged.to_readable().query(self.phi).map(|q| q.approx_value);
+        Ok(value)
+    }
+}
+
+impl<'a> QueryOp for TopKOp<'a> {
+    fn get_next(&mut self) -> Result<OpOutput, QueryError> {
+        if self.output.is_none() {
+            let ranked = self.rank_metrics()?;
+            self.output = Some(ranked.into_iter());
+        }
+        match self.output.as_mut().and_then(|iter| iter.next()) {
+            Some(metric) => Ok(OpOutput::MetricName(metric).with_label(self.input.label())),
+            None => Ok(OpOutput::End),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use query::parser::ast::Expression;
 use query::parser::tokenize::{tokenize, Token, TokenizeError};
+use std::fmt;
 
 #[derive(Debug)]
 pub enum ParseError {
@@ -8,6 +9,16 @@ pub enum ParseError {
     UnexpectedEnd,
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::TokenizeError(err) => write!(f, "{}", err),
+            ParseError::UnexpectedToken(t) => write!(f, "Unexpected token `{}`", t),
+            ParseError::UnexpectedEnd => write!(f, "Unexpected end of query"),
+        }
+    }
+}
+
 impl From<TokenizeError> for ParseError {
     fn from(err: TokenizeError) -> ParseError {
         ParseError::TokenizeError(err)
@@ -244,8 +255,23 @@ mod tests {
         assert_rejects("foo(,");
     }
 
+    #[test]
+    fn it_describes_parse_errors() {
+        assert_error_message("quantile(fetch(\"foo\")", "Unexpected end of query");
+        assert_error_message("foo(bar))", "Unexpected token `)`");
+        assert_error_message("foo(\"bar\" \"baz\")", "Unexpected token `\"baz\"`");
+        assert_error_message("foo(#)", "Unexpected character `#`");
+    }
+
+    fn assert_error_message(input: &str, expected: &str) {
+        match parse(input) {
+            Err(err) => assert_eq!(err.to_string(), expected),
+            Ok(_) => panic!("Expected parse error"),
+        }
+    }
+
     fn assert_rejects(input: &str) {
-        if let Ok(_) = parse(input) {
+        if parse(input).is_ok() {
             panic!("Expected parse error");
         }
     }
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::num::{ParseFloatError, ParseIntError};
 
 #[derive(Debug, PartialEq, Clone)]
@@ -19,6 +20,31 @@ pub enum TokenizeError {
     ParseFloatError(ParseFloatError),
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Symbol(s) => write!(f, "{}", s),
+            Token::String(s) => write!(f, "\"{}\"", s),
+            Token::Int(i) => write!(f, "{}", i),
+            Token::Float(x) => write!(f, "{}", x),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenizeError::UnexpectedChar(c) => write!(f, "Unexpected character `{}`", c),
+            TokenizeError::UnexpectedEnd => write!(f, "Unexpected end of query"),
+            TokenizeError::ParseIntError(err) => write!(f, "Invalid integer: {}", err),
+            TokenizeError::ParseFloatError(err) => write!(f, "Invalid float: {}", err),
+        }
+    }
+}
+
 impl From<ParseIntError> for TokenizeError {
     fn from(err: ParseIntError) -> TokenizeError {
         TokenizeError::ParseIntError(err)
@@ -125,7 +151,7 @@ fn tokenize_symbol(s: &str, tokens: &mut Vec<Token>) -> Result<usize, TokenizeEr
     });
     let mut i = 0;
     for c in s.chars() {
-        if c.is_ascii_alphanumeric() {
+        if c.is_ascii_alphanumeric() || c == '_' {
             i += 1;
         } else if is_separator(c) {
             break;
@@ -162,6 +188,15 @@ mod tests {
         assert_tokenize(&"server1234", vec![Token::Symbol("server1234".to_string())]);
     }
 
+    #[test]
+    fn it_tokenizes_symbols_with_underscores() {
+        assert_tokenize(
+            &"moving_average",
+            vec![Token::Symbol("moving_average".to_string())],
+        );
+        assert_error(&"_foo");
+    }
+
     #[test]
     fn it_tokenizes_string_literals() {
         assert_tokenize(&"\"\"", vec![Token::String("".to_string())]);
@@ -227,7 +262,7 @@ mod tests {
 
     fn assert_error(input: &str) {
         match tokenize(input) {
-            Ok(_) => assert!(false, "Expected error"),
+            Ok(_) => panic!("Expected error"),
             Err(_) => {}
         }
     }
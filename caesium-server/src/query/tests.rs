@@ -1,7 +1,10 @@
 use caesium_core::quantile::writable::WritableSketch;
 use caesium_core::time::timestamp::TimeStamp;
 use caesium_core::time::window::TimeWindow;
-use query::execute::{execute_query, QueryResult};
+use query::error::QueryError;
+use query::execute::{execute_query, execute_query_streaming, QueryResult, OVERFLOW_BUCKET_EDGE};
+use query::ops::group::{bucket_start, GroupType};
+use std::time::{Duration, Instant};
 use storage::datasource::DataRow;
 use storage::mock::MockDataSource;
 
@@ -44,7 +47,7 @@ fn it_queries_quantile_by_metric() {
     source.add_row("foo", build_data_row(TimeWindow::new(2, 3)));
     source.add_row("bar", build_data_row(TimeWindow::new(3, 4)));
     let query = "quantile(fetch(\"foo\"), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(1, 2, 0.5, 50), (2, 3, 0.5, 50)]);
 }
 
@@ -56,7 +59,7 @@ fn it_queries_multiple_quantiles() {
     source.add_row("foo", build_data_row(TimeWindow::new(30, 40)));
     source.add_row("foo", build_data_row(TimeWindow::new(40, 50)));
     let query = "quantile(fetch(\"foo\"), 0.1, 0.5, 0.9)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(
         &results,
         &vec![
@@ -76,6 +79,25 @@ fn it_queries_multiple_quantiles() {
     );
 }
 
+#[test]
+fn it_queries_min_and_max_quantiles() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    let query = "quantile(fetch(\"foo\"), 0.0, 1.0)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![(10, 20, 0.0, 0), (10, 20, 1.0, 99)]);
+}
+
+#[test]
+fn it_rejects_quantile_phi_outside_range() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    match execute_query("quantile(fetch(\"foo\"), 1.01)", &mut source, None) {
+        Err(QueryError::PhiOutOfRange(_)) => {}
+        r => panic!("Expected phi out of range error, got {:?}", r),
+    }
+}
+
 #[test]
 fn it_queries_quantile_select_time_range() {
     let mut source = MockDataSource::new();
@@ -84,7 +106,7 @@ fn it_queries_quantile_select_time_range() {
     source.add_row("foo", build_data_row(TimeWindow::new(30, 40)));
     source.add_row("foo", build_data_row(TimeWindow::new(40, 50)));
     let query = "quantile(fetch(\"foo\", 20, 40), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(20, 30, 0.5, 50), (30, 40, 0.5, 50)]);
 }
 
@@ -93,7 +115,7 @@ fn it_queries_quantile_metric_not_found() {
     let mut source = MockDataSource::new();
     source.add_row("foo", build_data_row(TimeWindow::new(1, 2)));
     let query = "quantile(fetch(\"bar\"), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![]);
 }
 
@@ -106,7 +128,7 @@ fn it_queries_quantile_group_by_hour() {
     source.add_row("foo", build_data_row(TimeWindow::new(40, 50)));
     source.add_row("foo", build_data_row(TimeWindow::new(4000, 4500)));
     let query = "quantile(group(\"hours\", fetch(\"foo\", 0, 10000)), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(10, 50, 0.5, 50), (4000, 4500, 0.5, 50)]);
 }
 
@@ -118,20 +140,279 @@ fn it_queries_quantile_group_by_day() {
     source.add_row("foo", build_data_row(TimeWindow::new(7000, 8000)));
     source.add_row("foo", build_data_row(TimeWindow::new(90000, 91000)));
     let query = "quantile(group(\"days\", fetch(\"foo\", 0, 100000)), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(
         &results,
         &vec![(10, 8000, 0.5, 50), (90000, 91000, 0.5, 50)],
     );
 }
 
+#[test]
+fn it_queries_quantile_group_by_week() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    source.add_row("foo", build_data_row(TimeWindow::new(500000, 500100)));
+    source.add_row("foo", build_data_row(TimeWindow::new(604800, 604900)));
+    let query = "quantile(group(\"weeks\", fetch(\"foo\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![(10, 500100, 0.5, 50), (604800, 604900, 0.5, 50)],
+    );
+}
+
+#[test]
+fn it_queries_quantile_group_by_month() {
+    let mut source = MockDataSource::new();
+    // 2021-01-01, 2021-01-31T23:59, 2021-02-01, and 2021-03-01
+    source.add_row(
+        "foo",
+        build_data_row(TimeWindow::new(1609459200, 1609459260)),
+    );
+    source.add_row(
+        "foo",
+        build_data_row(TimeWindow::new(1612137540, 1612137600)),
+    );
+    source.add_row(
+        "foo",
+        build_data_row(TimeWindow::new(1612137600, 1612137660)),
+    );
+    source.add_row(
+        "foo",
+        build_data_row(TimeWindow::new(1614556800, 1614556860)),
+    );
+    let query = "quantile(group(\"months\", fetch(\"foo\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![
+            (1609459200, 1612137600, 0.5, 50),
+            (1612137600, 1612137660, 0.5, 50),
+            (1614556800, 1614556860, 0.5, 50),
+        ],
+    );
+}
+
+#[test]
+fn it_calculates_month_bucket_starts() {
+    // 2024-02-01, 2024-02-29, and 2024-03-01 in a leap year
+    assert_eq!(bucket_start(1706745600, GroupType::Months), 1706745600);
+    assert_eq!(bucket_start(1709164800, GroupType::Months), 1706745600);
+    assert_eq!(bucket_start(1709251199, GroupType::Months), 1706745600);
+    assert_eq!(bucket_start(1709251200, GroupType::Months), 1709251200);
+    assert_eq!(bucket_start(0, GroupType::Months), 0);
+    assert_eq!(bucket_start(u64::MAX, GroupType::Months), u64::MAX);
+}
+
+#[test]
+fn it_calculates_fixed_bucket_starts() {
+    assert_eq!(bucket_start(3725, GroupType::Seconds), 3725);
+    assert_eq!(bucket_start(3725, GroupType::Hours), 3600);
+    assert_eq!(bucket_start(90000, GroupType::Days), 86400);
+    assert_eq!(bucket_start(700000, GroupType::Weeks), 604800);
+}
+
+#[test]
+fn it_rejects_unknown_group_type() {
+    let mut source = MockDataSource::new();
+    let query = "quantile(group(\"years\", fetch(\"foo\")), 0.5)";
+    match execute_query(&query, &mut source, None) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg error, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_resamples_into_aligned_buckets() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    source.add_row("foo", build_data_row(TimeWindow::new(20, 30)));
+    source.add_row("foo", build_data_row(TimeWindow::new(60, 70)));
+    source.add_row("foo", build_data_row(TimeWindow::new(110, 120)));
+    source.add_row("foo", build_data_row(TimeWindow::new(130, 140)));
+    let query = "quantile(resample(60, fetch(\"foo\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![(0, 60, 0.5, 50), (60, 120, 0.5, 50), (120, 180, 0.5, 50)],
+    );
+}
+
+#[test]
+fn it_resamples_and_merges_sketches() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    source.add_row("foo", build_data_row(TimeWindow::new(20, 30)));
+    let query = "quantile(resample(3600, fetch(\"foo\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    let counts: Vec<usize> = results
+        .iter()
+        .filter_map(|r| match r {
+            QueryResult::QuantileWindow(_, _, q) => Some(q.count),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(counts, vec![300]);
+    assert_windows(&results, &vec![(0, 3600, 0.5, 50)]);
+}
+
+#[test]
+fn it_resamples_window_longer_than_bucket() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 90)));
+    let query = "quantile(resample(60, fetch(\"foo\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+
+    // Assigned to the bucket containing the window start, but the output window
+    // still spans exactly one bucket
+    assert_windows(&results, &vec![(0, 60, 0.5, 50)]);
+}
+
+#[test]
+fn it_resamples_misaligned_windows() {
+    let mut source = MockDataSource::new();
+    source.add_row(
+        "foo",
+        build_row_with_values(TimeWindow::new(10, 20), 0, 100),
+    );
+    source.add_row(
+        "foo",
+        build_row_with_values(TimeWindow::new(15, 35), 100, 200),
+    );
+    source.add_row(
+        "foo",
+        build_row_with_values(TimeWindow::new(35, 50), 200, 300),
+    );
+    source.add_row(
+        "foo",
+        build_row_with_values(TimeWindow::new(45, 65), 300, 400),
+    );
+    let query = "quantile(resample(30, fetch(\"foo\")), 0.0, 1.0)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![
+            (0, 30, 0.0, 0),
+            (0, 30, 1.0, 199),
+            (30, 60, 0.0, 200),
+            (30, 60, 1.0, 399),
+        ],
+    );
+}
+
+#[test]
+fn it_resamples_gaps_as_empty_windows() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(5, 10)));
+    source.add_row("foo", build_data_row(TimeWindow::new(95, 100)));
+    let query = "histogram(resample(30, fetch(\"foo\")), 1)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_histograms(
+        &results,
+        &[
+            (TimeWindow::new(0, 30), vec![(OVERFLOW_BUCKET_EDGE, 100)]),
+            (TimeWindow::new(30, 60), vec![(OVERFLOW_BUCKET_EDGE, 0)]),
+            (TimeWindow::new(60, 90), vec![(OVERFLOW_BUCKET_EDGE, 0)]),
+            (TimeWindow::new(90, 120), vec![(OVERFLOW_BUCKET_EDGE, 100)]),
+        ],
+    );
+
+    // Quantiles are undefined for empty windows, so they are omitted
+    let query = "quantile(resample(30, fetch(\"foo\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![(0, 30, 0.5, 50), (90, 120, 0.5, 50)]);
+}
+
+#[test]
+fn it_fills_empty_windows_with_zero() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(5, 10)));
+    source.add_row("foo", build_data_row(TimeWindow::new(95, 100)));
+    let query = "fill(\"zero\", quantile(resample(30, fetch(\"foo\")), 0.5))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![
+            (0, 30, 0.5, 50),
+            (30, 60, 0.5, 0),
+            (60, 90, 0.5, 0),
+            (90, 120, 0.5, 50),
+        ],
+    );
+}
+
+#[test]
+fn it_fills_empty_windows_with_previous_value() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(5, 10)));
+    source.add_row("foo", build_data_row(TimeWindow::new(95, 100)));
+    let query = "fill(\"previous\", quantile(resample(30, fetch(\"foo\")), 0.1, 0.9))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![
+            (0, 30, 0.1, 10),
+            (0, 30, 0.9, 90),
+            (30, 60, 0.1, 10),
+            (30, 60, 0.9, 90),
+            (60, 90, 0.1, 10),
+            (60, 90, 0.9, 90),
+            (90, 120, 0.1, 10),
+            (90, 120, 0.9, 90),
+        ],
+    );
+}
+
+#[test]
+fn it_fills_empty_windows_with_none() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(5, 10)));
+    source.add_row("foo", build_data_row(TimeWindow::new(95, 100)));
+    let query = "fill(\"none\", quantile(resample(30, fetch(\"foo\")), 0.5))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![(0, 30, 0.5, 50), (90, 120, 0.5, 50)]);
+}
+
+#[test]
+fn it_rejects_fill_with_invalid_mode() {
+    let mut source = MockDataSource::new();
+    let query = "fill(\"linear\", quantile(fetch(\"foo\"), 0.5))";
+    match execute_query(&query, &mut source, None) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg error, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_rejects_resample_with_too_many_empty_windows() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 1)));
+    source.add_row("foo", build_data_row(TimeWindow::new(1_000_000, 1_000_001)));
+    let query = "quantile(resample(1, fetch(\"foo\")), 0.5)";
+    match execute_query(&query, &mut source, None) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg error, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_rejects_resample_with_zero_bucket_size() {
+    let mut source = MockDataSource::new();
+    let query = "quantile(resample(0, fetch(\"foo\")), 0.5)";
+    match execute_query(&query, &mut source, None) {
+        Err(QueryError::InvalidWindowSize(0)) => {}
+        r => panic!("Expected invalid window size error, got {:?}", r),
+    }
+}
+
 #[test]
 fn it_coalesces_adjacent_time_windows() {
     let mut source = MockDataSource::new();
     source.add_row("foo", build_data_row(TimeWindow::new(0, 30)));
     source.add_row("foo", build_data_row(TimeWindow::new(30, 60)));
     let query = "quantile(coalesce(fetch(\"foo\")), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(0, 60, 0.5, 50)]);
 }
 
@@ -141,7 +422,7 @@ fn it_coalesces_overlapping_time_windows() {
     source.add_row("foo", build_data_row(TimeWindow::new(30, 60)));
     source.add_row("foo", build_data_row(TimeWindow::new(15, 35)));
     let query = "quantile(coalesce(fetch(\"foo\")), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(15, 60, 0.5, 50)]);
 }
 
@@ -151,17 +432,37 @@ fn it_coalesces_nonadjacent_time_windows() {
     source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
     source.add_row("foo", build_data_row(TimeWindow::new(40, 90)));
     let query = "quantile(coalesce(fetch(\"foo\")), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(10, 90, 0.5, 50)]);
 }
 
+#[test]
+fn it_skips_sketches_with_different_scales_when_coalescing() {
+    let mut source = MockDataSource::new();
+    let mut scaled = WritableSketch::new();
+    scaled
+        .insert_f64(1000.0, 2)
+        .expect("Could not insert value");
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    source.add_row(
+        "foo",
+        DataRow {
+            window: TimeWindow::new(10, 20),
+            sketch: scaled,
+        },
+    );
+    let query = "quantile(coalesce(fetch(\"foo\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![(0, 20, 0.5, 50)]);
+}
+
 #[test]
 fn it_coalesces_idempotent() {
     let mut source = MockDataSource::new();
     source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
     source.add_row("foo", build_data_row(TimeWindow::new(40, 90)));
     let query = "quantile(coalesce(coalesce(fetch(\"foo\"))), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(10, 90, 0.5, 50)]);
 }
 
@@ -174,7 +475,7 @@ fn it_combines_time_series() {
     source.add_row("bar", build_data_row(TimeWindow::new(30, 60)));
 
     let query = "quantile(combine(fetch(\"foo\"), fetch(\"bar\")), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(0, 30, 0.5, 50), (30, 60, 0.5, 50)]);
 }
 
@@ -182,7 +483,7 @@ fn it_combines_time_series() {
 fn it_combines_empty_inputs() {
     let mut source = MockDataSource::new();
     let query = "quantile(combine(fetch(\"foo\"), fetch(\"bar\")), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![]);
 }
 
@@ -191,7 +492,7 @@ fn it_combines_single_input() {
     let mut source = MockDataSource::new();
     source.add_row("foo", build_data_row(TimeWindow::new(0, 30)));
     let query = "quantile(combine(fetch(\"foo\"), fetch(\"bar\")), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(&results, &vec![(0, 30, 0.5, 50)]);
 }
 
@@ -208,7 +509,7 @@ fn it_combines_multiple_inputs() {
     source.add_row("bar", build_data_row(TimeWindow::new(69, 80)));
     source.add_row("bar", build_data_row(TimeWindow::new(90, 100)));
     let query = "quantile(combine(fetch(\"foo\"), fetch(\"bar\")), 0.5)";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_windows(
         &results,
         &vec![
@@ -221,6 +522,51 @@ fn it_combines_multiple_inputs() {
     );
 }
 
+#[test]
+fn it_combines_three_inputs() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_row_with_values(TimeWindow::new(0, 30), 0, 30));
+    source.add_row("bar", build_row_with_values(TimeWindow::new(0, 30), 30, 60));
+    source.add_row("baz", build_row_with_values(TimeWindow::new(0, 30), 60, 90));
+    source.add_row("foo", build_row_with_values(TimeWindow::new(30, 60), 0, 10));
+    source.add_row(
+        "baz",
+        build_row_with_values(TimeWindow::new(30, 60), 100, 130),
+    );
+    let query = "quantile(combine(fetch(\"foo\"), fetch(\"bar\"), fetch(\"baz\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![(0, 30, 0.5, 45), (30, 60, 0.5, 110)]);
+}
+
+#[test]
+fn it_combines_overlapping_windows_across_three_inputs() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_row_with_values(TimeWindow::new(0, 30), 0, 10));
+    source.add_row(
+        "bar",
+        build_row_with_values(TimeWindow::new(20, 50), 10, 20),
+    );
+    source.add_row(
+        "baz",
+        build_row_with_values(TimeWindow::new(45, 60), 20, 30),
+    );
+    source.add_row("foo", build_row_with_values(TimeWindow::new(70, 80), 0, 10));
+    let query = "quantile(combine(fetch(\"foo\"), fetch(\"bar\"), fetch(\"baz\")), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+
+    // Overlaps chain together, so bar joins foo and baz into a single window
+    assert_windows(&results, &vec![(0, 60, 0.5, 15), (70, 80, 0.5, 5)]);
+}
+
+#[test]
+fn it_rejects_combine_without_inputs() {
+    let mut source = MockDataSource::new();
+    match execute_query(&"quantile(combine(), 0.5)", &mut source, None) {
+        Err(QueryError::MissingArg) => {}
+        r => panic!("Expected missing arg error, got {:?}", r),
+    }
+}
+
 #[test]
 fn it_searches_metric_names() {
     let mut source = MockDataSource::new();
@@ -230,6 +576,674 @@ fn it_searches_metric_names() {
     source.add_row("bazbar", build_data_row(TimeWindow::new(50, 60)));
     source.add_row("bazfoobar", build_data_row(TimeWindow::new(50, 60)));
     let query = "search(\"*foo*r\")";
-    let results = execute_query(&query, &mut source).expect("Could not execute query");
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
     assert_metrics(&results, &vec!["bazfoobar", "foobar"]);
 }
+
+#[test]
+fn it_searches_metric_names_by_tag() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo;region=us", build_data_row(TimeWindow::new(10, 20)));
+    source.add_row("foo;region=eu", build_data_row(TimeWindow::new(10, 20)));
+    source.add_row("bar;region=us", build_data_row(TimeWindow::new(10, 20)));
+    let query = "search(\"*;region=us\")";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_metrics(&results, &vec!["bar;region=us", "foo;region=us"]);
+}
+
+#[test]
+fn it_searches_metric_names_matching_any_pattern() {
+    let source =
+        build_source_with_metrics(&["web.latency", "web.errors", "db.latency", "cache.latency"]);
+    let query = "search(\"any\", \"web.*\", \"db.*\")";
+    let results = execute_query(&query, &source, None).expect("Could not execute query");
+    assert_metrics(&results, &vec!["db.latency", "web.errors", "web.latency"]);
+}
+
+#[test]
+fn it_deduplicates_metric_names_matching_several_patterns() {
+    let source = build_source_with_metrics(&["web.latency", "web.errors", "db.latency"]);
+    let query = "search(\"any\", \"web.*\", \"*.latency\", \"web.latency\")";
+    let results = execute_query(&query, &source, None).expect("Could not execute query");
+    assert_metrics(&results, &vec!["db.latency", "web.errors", "web.latency"]);
+}
+
+#[test]
+fn it_searches_metric_names_matching_all_patterns() {
+    let source = build_source_with_metrics(&[
+        "prod.web.latency",
+        "prod.web.errors",
+        "staging.web.latency",
+        "prod.db.latency;region=us",
+        "prod.db.latency;region=eu",
+    ]);
+    let query = "search(\"all\", \"*.latency\", \"prod.*\")";
+    let results = execute_query(&query, &source, None).expect("Could not execute query");
+    assert_metrics(
+        &results,
+        &vec![
+            "prod.db.latency;region=eu",
+            "prod.db.latency;region=us",
+            "prod.web.latency",
+        ],
+    );
+
+    let query = "search(\"all\", \"*.latency\", \"prod.*\", \"*;region=us\")";
+    let results = execute_query(&query, &source, None).expect("Could not execute query");
+    assert_metrics(&results, &vec!["prod.db.latency;region=us"]);
+}
+
+#[test]
+fn it_searches_metric_names_with_one_pattern_in_either_mode() {
+    let source = build_source_with_metrics(&["web.latency", "db.latency"]);
+    for mode in ["any", "all"].iter() {
+        let query = format!("search(\"{}\", \"web.*\")", mode);
+        let results = execute_query(&query, &source, None).expect("Could not execute query");
+        assert_metrics(&results, &vec!["web.latency"]);
+    }
+}
+
+#[test]
+fn it_rejects_invalid_search_mode() {
+    let source = build_source_with_metrics(&["web.latency"]);
+    match execute_query(&"search(\"some\", \"web.*\", \"db.*\")", &source, None) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg value error, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_times_out_slow_query() {
+    let mut source = MockDataSource::new();
+    source.set_row_delay(Duration::from_millis(50));
+    for i in 0..10 {
+        source.add_row("foo", build_data_row(TimeWindow::new(i * 10, i * 10 + 10)));
+    }
+    let query = "quantile(fetch(\"foo\"), 0.5)";
+    let timeout = Some(Duration::from_millis(100));
+    match execute_query(&query, &mut source, timeout) {
+        Err(QueryError::Timeout) => {}
+        r => panic!("Expected timeout error, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_completes_query_within_timeout() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    let query = "quantile(fetch(\"foo\"), 0.5)";
+    let timeout = Some(Duration::from_secs(10));
+    let results = execute_query(&query, &mut source, timeout).expect("Could not execute query");
+    assert_windows(&results, &vec![(0, 10, 0.5, 50)]);
+}
+
+#[test]
+fn it_streams_results_before_query_finishes() {
+    let mut source = MockDataSource::new();
+    source.set_row_delay(Duration::from_millis(50));
+    for i in 0..4 {
+        source.add_row("foo", build_data_row(TimeWindow::new(i * 10, i * 10 + 10)));
+    }
+    let query = "quantile(fetch(\"foo\"), 0.5)";
+    let mut emitted = Vec::new();
+    execute_query_streaming(&query, &mut source, None, |r| -> Result<(), QueryError> {
+        emitted.push((Instant::now(), r));
+        Ok(())
+    })
+    .expect("Could not execute query");
+    let finished = Instant::now();
+    assert_eq!(emitted.len(), 4);
+    let (first_emitted, _) = emitted[0];
+    assert!(finished.duration_since(first_emitted) >= Duration::from_millis(100));
+    let results = emitted.drain(..).map(|(_, r)| r).collect();
+    assert_windows(
+        &results,
+        &vec![
+            (0, 10, 0.5, 50),
+            (10, 20, 0.5, 50),
+            (20, 30, 0.5, 50),
+            (30, 40, 0.5, 50),
+        ],
+    );
+}
+
+#[test]
+fn it_stops_streaming_when_emit_fails() {
+    let mut source = MockDataSource::new();
+    for i in 0..4 {
+        source.add_row("foo", build_data_row(TimeWindow::new(i * 10, i * 10 + 10)));
+    }
+    let query = "quantile(fetch(\"foo\"), 0.5)";
+    let mut num_emitted = 0;
+    let result = execute_query_streaming(&query, &mut source, None, |_| {
+        num_emitted += 1;
+        Err(QueryError::InvalidOutputType)
+    });
+    match result {
+        Err(QueryError::InvalidOutputType) => {}
+        r => panic!("Expected emit error, got {:?}", r),
+    }
+    assert_eq!(num_emitted, 1);
+}
+
+#[test]
+fn it_queries_histogram() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    let query = "histogram(fetch(\"foo\"), 4)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    let expected_buckets = vec![(25, 25), (50, 50), (75, 75), (OVERFLOW_BUCKET_EDGE, 100)];
+    assert_histograms(
+        &results,
+        &[
+            (TimeWindow::new(0, 10), expected_buckets.clone()),
+            (TimeWindow::new(10, 20), expected_buckets),
+        ],
+    );
+}
+
+#[test]
+fn it_queries_histogram_with_increasing_edges_and_counts() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    let query = "histogram(fetch(\"foo\"), 20)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    match results.as_slice() {
+        [QueryResult::HistogramWindow(_, buckets)] => {
+            assert_eq!(buckets.len(), 20);
+            for i in 1..buckets.len() {
+                assert!(buckets[i - 1].0 < buckets[i].0);
+                assert!(buckets[i - 1].1 < buckets[i].1);
+            }
+            assert_eq!(buckets[0], (5, 5));
+            assert_eq!(buckets[buckets.len() - 1], (OVERFLOW_BUCKET_EDGE, 100));
+        }
+        r => panic!("Expected one histogram, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_collapses_histogram_buckets_with_same_edge() {
+    let mut source = MockDataSource::new();
+    let mut sketch = WritableSketch::new();
+    for _ in 0..10 {
+        sketch.insert(7);
+    }
+    source.add_row(
+        "foo",
+        DataRow {
+            window: TimeWindow::new(0, 10),
+            sketch,
+        },
+    );
+    let query = "histogram(fetch(\"foo\"), 5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_histograms(
+        &results,
+        &[(
+            TimeWindow::new(0, 10),
+            vec![(7, 8), (OVERFLOW_BUCKET_EDGE, 10)],
+        )],
+    );
+}
+
+#[test]
+fn it_rejects_histogram_with_zero_buckets() {
+    let mut source = MockDataSource::new();
+    let query = "histogram(fetch(\"foo\"), 0)";
+    match execute_query(&query, &mut source, None) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg error, got {:?}", r),
+    }
+}
+
+fn assert_histograms(rows: &[QueryResult], expected: &[(TimeWindow, Vec<(u32, usize)>)]) {
+    let actual: Vec<(TimeWindow, Vec<(u32, usize)>)> = rows
+        .iter()
+        .filter_map(|r| match r {
+            QueryResult::HistogramWindow(window, buckets) => Some((*window, buckets.clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(actual, *expected);
+}
+
+fn build_row_with_values(window: TimeWindow, start: u32, end: u32) -> DataRow {
+    let mut sketch = WritableSketch::new();
+    for i in start..end {
+        sketch.insert(i);
+    }
+    DataRow { window, sketch }
+}
+
+fn build_source_with_metrics(metrics: &[&str]) -> MockDataSource {
+    let mut source = MockDataSource::new();
+    for metric in metrics.iter() {
+        source.add_row(metric, build_data_row(TimeWindow::new(0, 10)));
+    }
+    source
+}
+
+fn assert_medians(rows: &[QueryResult]) -> Vec<u32> {
+    rows.iter()
+        .map(|r| match r {
+            &QueryResult::QuantileWindow(_, phi, quantile) => {
+                assert_eq!(phi, 0.5);
+                quantile.approx_value
+            }
+            r => panic!("Expected quantile result, got {:?}", r),
+        })
+        .collect()
+}
+
+#[test]
+fn it_smooths_step_with_moving_average() {
+    let mut source = MockDataSource::new();
+    for i in 0..8 {
+        let window = TimeWindow::new(i * 10, (i + 1) * 10);
+        let (start, end) = if i < 4 { (0, 100) } else { (1000, 1100) };
+        source.add_row("foo", build_row_with_values(window, start, end));
+    }
+    let query = "moving_average(3, fetch(\"foo\"), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    let windows: Vec<TimeWindow> = results
+        .iter()
+        .filter_map(|r| match r {
+            &QueryResult::QuantileWindow(window, _, _) => Some(window),
+            _ => None,
+        })
+        .collect();
+    let expected_windows: Vec<TimeWindow> = (0..8)
+        .map(|i| TimeWindow::new(i * 10, (i + 1) * 10))
+        .collect();
+    assert_eq!(windows, expected_windows);
+
+    // Before the step and once the step fills the buffer, the value is unchanged
+    let medians = assert_medians(&results);
+    for &m in medians[..4].iter() {
+        assert!((45..=55).contains(&m), "median was {}", m);
+    }
+    for &m in medians[6..].iter() {
+        assert!((1045..=1055).contains(&m), "median was {}", m);
+    }
+
+    // The windows just after the step blend the two levels
+    assert!(
+        medians[4] > 55 && medians[4] < 100,
+        "median was {}",
+        medians[4]
+    );
+    assert!(
+        medians[5] > 1000 && medians[5] < 1045,
+        "median was {}",
+        medians[5]
+    );
+}
+
+#[test]
+fn it_averages_available_windows_at_leading_edge() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_row_with_values(TimeWindow::new(0, 10), 0, 100));
+    source.add_row(
+        "foo",
+        build_row_with_values(TimeWindow::new(10, 20), 100, 200),
+    );
+    let query = "moving_average(5, fetch(\"foo\"), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    let medians = assert_medians(&results);
+    assert_eq!(medians.len(), 2);
+    assert!(
+        medians[0] >= 45 && medians[0] <= 55,
+        "median was {}",
+        medians[0]
+    );
+    assert!(
+        medians[1] >= 90 && medians[1] <= 110,
+        "median was {}",
+        medians[1]
+    );
+}
+
+#[test]
+fn it_rejects_moving_average_with_invalid_args() {
+    let mut source = MockDataSource::new();
+    match execute_query("moving_average(0, fetch(\"foo\"), 0.5)", &mut source, None) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg error, got {:?}", r),
+    }
+    match execute_query("moving_average(3, fetch(\"foo\"), 1.5)", &mut source, None) {
+        Err(QueryError::PhiOutOfRange(_)) => {}
+        r => panic!("Expected phi out of range error, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_smooths_quantiles_with_moving_avg() {
+    let mut source = MockDataSource::new();
+    for (i, &v) in [10, 20, 30, 40, 50, 60].iter().enumerate() {
+        let window = TimeWindow::new(i as u64 * 10, (i as u64 + 1) * 10);
+        source.add_row("foo", build_row_with_values(window, v, v + 1));
+    }
+
+    // Partial averages are emitted until the buffer fills
+    let query = "moving_avg(3, quantile(fetch(\"foo\"), 0.5))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![
+            (0, 10, 0.5, 10),
+            (10, 20, 0.5, 15),
+            (20, 30, 0.5, 20),
+            (30, 40, 0.5, 30),
+            (40, 50, 0.5, 40),
+            (50, 60, 0.5, 50),
+        ],
+    );
+}
+
+#[test]
+fn it_smooths_each_phi_separately_with_moving_avg() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    source.add_row(
+        "foo",
+        build_row_with_values(TimeWindow::new(10, 20), 100, 200),
+    );
+    let query = "moving_avg(2, quantile(fetch(\"foo\"), 0.1, 0.9))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![
+            (0, 10, 0.1, 10),
+            (0, 10, 0.9, 90),
+            (10, 20, 0.1, 60),
+            (10, 20, 0.9, 140),
+        ],
+    );
+}
+
+#[test]
+fn it_keeps_bounds_of_every_averaged_quantile_with_moving_avg() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_row_with_values(TimeWindow::new(0, 10), 10, 11));
+    source.add_row(
+        "foo",
+        build_row_with_values(TimeWindow::new(10, 20), 50, 51),
+    );
+    let query = "moving_avg(2, quantile(fetch(\"foo\"), 0.5))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    match results[1] {
+        QueryResult::QuantileWindow(_, _, q) => {
+            assert_eq!(q.approx_value, 30);
+            assert_eq!(q.lower_bound, 10);
+            assert_eq!(q.upper_bound, 50);
+        }
+        ref r => panic!("Expected quantile result, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_rejects_moving_avg_with_invalid_args() {
+    let mut source = MockDataSource::new();
+    match execute_query(
+        "moving_avg(0, quantile(fetch(\"foo\"), 0.5))",
+        &mut source,
+        None,
+    ) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg error, got {:?}", r),
+    }
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    match execute_query("moving_avg(3, fetch(\"foo\"))", &mut source, None) {
+        Err(QueryError::InvalidInput) => {}
+        r => panic!("Expected invalid input error, got {:?}", r),
+    }
+}
+
+fn assert_labels(rows: &[QueryResult], expected: &[(TimeStamp, TimeStamp, &str)]) {
+    let actual: Vec<(TimeStamp, TimeStamp, &str)> = rows
+        .iter()
+        .map(|r| match r {
+            QueryResult::Labeled(label, result) => match **result {
+                QueryResult::QuantileWindow(window, _, _) => {
+                    assert_eq!(r.label(), Some(label.as_str()));
+                    (window.start(), window.end(), label.as_str())
+                }
+                ref r => panic!("Expected quantile result, got {:?}", r),
+            },
+            r => panic!("Expected labeled result, got {:?}", r),
+        })
+        .collect();
+    assert_eq!(actual, *expected);
+}
+
+#[test]
+fn it_annotates_results() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 30)));
+    source.add_row("foo", build_data_row(TimeWindow::new(30, 60)));
+    let query = "annotate(\"p99-latency\", quantile(fetch(\"foo\"), 0.99))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_labels(&results, &[(0, 30, "p99-latency"), (30, 60, "p99-latency")]);
+}
+
+#[test]
+fn it_keeps_labels_through_combine() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 30)));
+    source.add_row("foo", build_data_row(TimeWindow::new(60, 90)));
+    source.add_row("bar", build_data_row(TimeWindow::new(30, 60)));
+    source.add_row("bar", build_data_row(TimeWindow::new(60, 90)));
+    let query = "quantile(combine(annotate(\"foo\", fetch(\"foo\")), \
+                 annotate(\"bar\", fetch(\"bar\"))), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_labels(
+        &results,
+        &[(0, 30, "foo"), (30, 60, "bar"), (60, 90, "foo+bar")],
+    );
+}
+
+#[test]
+fn it_replaces_inner_annotation() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 30)));
+    let query = "annotate(\"outer\", quantile(annotate(\"inner\", fetch(\"foo\")), 0.5))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_labels(&results, &[(0, 30, "outer")]);
+}
+
+#[test]
+fn it_leaves_results_unlabeled_without_annotation() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 30)));
+    let query = "quantile(fetch(\"foo\"), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].label(), None);
+}
+
+#[test]
+fn it_keeps_labels_through_group() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    source.add_row("foo", build_data_row(TimeWindow::new(20, 30)));
+    source.add_row("foo", build_data_row(TimeWindow::new(4000, 4500)));
+    let query = "quantile(group(\"hours\", annotate(\"foo\", fetch(\"foo\"))), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_labels(&results, &[(10, 30, "foo"), (4000, 4500, "foo")]);
+}
+
+#[test]
+fn it_keeps_labels_through_resample() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 10)));
+    source.add_row("foo", build_data_row(TimeWindow::new(10, 20)));
+    source.add_row("foo", build_data_row(TimeWindow::new(60, 70)));
+    let query = "quantile(resample(30, annotate(\"foo\", fetch(\"foo\"))), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_labels(&results, &[(0, 30, "foo"), (60, 90, "foo")]);
+}
+
+#[test]
+fn it_keeps_labels_through_coalesce() {
+    let mut source = MockDataSource::new();
+    source.add_row("foo", build_data_row(TimeWindow::new(0, 30)));
+    source.add_row("foo", build_data_row(TimeWindow::new(30, 60)));
+    let query = "quantile(coalesce(annotate(\"foo\", fetch(\"foo\"))), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_labels(&results, &[(0, 60, "foo")]);
+}
+
+#[test]
+fn it_keeps_labels_through_topk() {
+    let mut source = build_topk_source();
+    let query = "topk(2, 0.5, annotate(\"top\", search(\"*\")))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    let actual: Vec<(Option<&str>, &str)> = results
+        .iter()
+        .map(|r| match r {
+            QueryResult::Labeled(label, result) => match **result {
+                QueryResult::MetricName(ref metric) => (Some(label.as_str()), metric.as_str()),
+                ref r => panic!("Expected metric name, got {:?}", r),
+            },
+            r => panic!("Expected labeled result, got {:?}", r),
+        })
+        .collect();
+    assert_eq!(actual, vec![(Some("top"), "high"), (Some("top"), "mid")]);
+}
+
+fn build_topk_source() -> MockDataSource {
+    let mut source = MockDataSource::new();
+    source.add_row("low", build_row_with_values(TimeWindow::new(0, 10), 0, 100));
+    source.add_row(
+        "high",
+        build_row_with_values(TimeWindow::new(0, 10), 200, 300),
+    );
+    source.add_row(
+        "mid",
+        build_row_with_values(TimeWindow::new(0, 10), 100, 200),
+    );
+    source
+}
+
+#[test]
+fn it_queries_topk_metrics() {
+    let mut source = build_topk_source();
+    let query = "topk(3, 0.5, search(\"*\"))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_metrics(&results, &vec!["high", "mid", "low"]);
+}
+
+#[test]
+fn it_queries_topk_with_fewer_results_than_metrics() {
+    let mut source = build_topk_source();
+    let query = "topk(2, 0.5, search(\"*\"))";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_metrics(&results, &vec!["high", "mid"]);
+}
+
+#[test]
+fn it_queries_topk_in_time_range() {
+    let mut source = build_topk_source();
+    source.add_row(
+        "low",
+        build_row_with_values(TimeWindow::new(20, 30), 500, 600),
+    );
+    let query = "topk(3, 0.5, search(\"*\"), 20, 30)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_metrics(&results, &vec!["low"]);
+}
+
+#[test]
+fn it_rejects_topk_with_invalid_args() {
+    let mut source = build_topk_source();
+    match execute_query("topk(0, 0.5, search(\"*\"))", &mut source, None) {
+        Err(QueryError::InvalidArgValue(_)) => {}
+        r => panic!("Expected invalid arg error, got {:?}", r),
+    }
+    match execute_query("topk(3, 1.5, search(\"*\"))", &mut source, None) {
+        Err(QueryError::PhiOutOfRange(_)) => {}
+        r => panic!("Expected phi out of range error, got {:?}", r),
+    }
+}
+
+#[test]
+fn it_fetches_wildcard_metrics() {
+    let mut source = MockDataSource::new();
+    source.add_row(
+        "web.a",
+        build_row_with_values(TimeWindow::new(0, 30), 0, 100),
+    );
+    source.add_row(
+        "web.a",
+        build_row_with_values(TimeWindow::new(30, 60), 0, 100),
+    );
+    source.add_row(
+        "web.b",
+        build_row_with_values(TimeWindow::new(0, 30), 100, 200),
+    );
+    source.add_row(
+        "web.b",
+        build_row_with_values(TimeWindow::new(30, 60), 200, 300),
+    );
+    source.add_row(
+        "db.a",
+        build_row_with_values(TimeWindow::new(0, 30), 500, 600),
+    );
+    let query = "quantile(fetch(\"web.*\"), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![(0, 30, 0.5, 100), (30, 60, 0.5, 200)]);
+}
+
+#[test]
+fn it_fetches_wildcard_metrics_in_time_range() {
+    let mut source = MockDataSource::new();
+    source.add_row(
+        "web.a",
+        build_row_with_values(TimeWindow::new(0, 30), 0, 100),
+    );
+    source.add_row(
+        "web.a",
+        build_row_with_values(TimeWindow::new(30, 60), 0, 100),
+    );
+    source.add_row(
+        "web.b",
+        build_row_with_values(TimeWindow::new(30, 60), 100, 200),
+    );
+    let query = "quantile(fetch(\"web.*\", 30, 60), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![(30, 60, 0.5, 100)]);
+}
+
+#[test]
+fn it_fetches_wildcard_metrics_in_timestamp_order() {
+    let mut source = MockDataSource::new();
+    source.add_row(
+        "web.a",
+        build_row_with_values(TimeWindow::new(0, 30), 0, 100),
+    );
+    source.add_row(
+        "web.a",
+        build_row_with_values(TimeWindow::new(60, 90), 0, 100),
+    );
+    source.add_row(
+        "web.b",
+        build_row_with_values(TimeWindow::new(30, 60), 100, 200),
+    );
+    let query = "quantile(fetch(\"web.*\"), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(
+        &results,
+        &vec![(0, 30, 0.5, 50), (30, 60, 0.5, 150), (60, 90, 0.5, 50)],
+    );
+}
+
+#[test]
+fn it_fetches_wildcard_with_no_matches() {
+    let mut source = MockDataSource::new();
+    source.add_row("db.a", build_data_row(TimeWindow::new(0, 30)));
+    let query = "quantile(fetch(\"web.*\"), 0.5)";
+    let results = execute_query(&query, &mut source, None).expect("Could not execute query");
+    assert_windows(&results, &vec![]);
+}
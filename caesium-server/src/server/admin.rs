@@ -0,0 +1,518 @@
+use caesium_core::time::clock::SystemClock;
+use serde_json;
+use server::auth::AuthToken;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use storage::downsample::strategies::DefaultStrategy;
+use storage::downsample::{DownsampleReport, RetentionTier};
+use storage::error::StorageError;
+use storage::store::MetricStore;
+use storage::wildcard::metric_match;
+
+const READ_TIMEOUT_MS: u64 = 10000;
+const WRITE_TIMEOUT_MS: u64 = 10000;
+const MAX_CONNECTIONS: usize = 4;
+
+// Admin clients send one JSON request per line, for example
+// `{"command": "list_metrics", "pattern": "foo.*"}`,
+// and the server replies to each with one JSON response per line.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AdminRequest {
+    Authenticate {
+        token: String,
+    },
+    ListMetrics {
+        #[serde(default)]
+        pattern: Option<String>,
+    },
+    DeleteMetric {
+        metric: String,
+    },
+    TriggerDownsample,
+    DownsampleDryRun,
+    TriggerCompaction {
+        #[serde(default)]
+        metric: Option<String>,
+    },
+    Stats,
+    RocksdbStats,
+    Backup {
+        dir: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminResponse {
+    Authenticated,
+    Metrics {
+        metrics: Vec<String>,
+    },
+    Deleted {
+        metric: String,
+    },
+    DownsampleTriggered,
+    DownsampleReport {
+        report: DownsampleReport,
+    },
+    Compacted {
+        metric: Option<String>,
+    },
+    Stats {
+        metric_count: usize,
+        disk_usage_bytes: u64,
+    },
+    RocksdbStats {
+        stats: Vec<RocksDbStat>,
+    },
+    BackedUp {
+        dir: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+// One line of the RocksDB statistics dump. Tickers have only a `count`,
+// while histograms also have percentiles like `p50` and a `sum`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RocksDbStat {
+    pub name: String,
+    #[serde(flatten)]
+    pub values: BTreeMap<String, f64>,
+}
+
+// Serves each admin connection on its own thread, up to `MAX_CONNECTIONS` at once,
+// so a slow request like compaction doesn't block the others.
+// If the server has an auth token, the first request on each connection must be
+// `{"command": "authenticate", "token": "..."}`.
+pub struct AdminServer {
+    listener: TcpListener,
+    downsample_trigger: SyncSender<()>,
+    auth_token: Option<AuthToken>,
+    downsample_tiers: Option<Vec<RetentionTier>>,
+    retention_overrides: Vec<(String, Vec<RetentionTier>)>,
+    backup_root: Option<PathBuf>,
+    db_ref: Arc<MetricStore>,
+}
+
+impl AdminServer {
+    pub fn new(
+        addr: &SocketAddr,
+        downsample_trigger: SyncSender<()>,
+        db_ref: Arc<MetricStore>,
+    ) -> Result<AdminServer, io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(AdminServer {
+            listener,
+            downsample_trigger,
+            auth_token: None,
+            downsample_tiers: None,
+            retention_overrides: Vec::new(),
+            backup_root: None,
+            db_ref,
+        })
+    }
+
+    // Dry runs use the same rules as the downsample thread, so pass the server's
+    // tiers and overrides here (the default tiers are used otherwise)
+    pub fn with_downsample_rules(
+        mut self,
+        tiers: Option<Vec<RetentionTier>>,
+        overrides: Vec<(String, Vec<RetentionTier>)>,
+    ) -> AdminServer {
+        self.downsample_tiers = tiers;
+        self.retention_overrides = overrides;
+        self
+    }
+
+    // Backups are written only to directories under `root`, and are disabled without one
+    pub fn with_backup_root(mut self, root: Option<PathBuf>) -> AdminServer {
+        self.backup_root = root;
+        self
+    }
+
+    pub fn with_auth_token(mut self, auth_token: Option<AuthToken>) -> AdminServer {
+        self.auth_token = auth_token;
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(self) -> Result<(), io::Error> {
+        info!("Listening for admin requests on {}", self.local_addr()?);
+        let server = Arc::new(self);
+        let active = Arc::new(AtomicUsize::new(0));
+        for stream in server.listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("Error accepting admin connection: {:?}", err);
+                    continue;
+                }
+            };
+            let slot = ConnectionSlot::new(active.clone());
+            if slot.count > MAX_CONNECTIONS {
+                warn!("Rejecting admin connection, too many connections are open");
+                let resp = AdminResponse::Error {
+                    message: "Too many admin connections".to_string(),
+                };
+                if let Err(err) = write_response(&mut stream, &resp) {
+                    error!("Error rejecting admin connection: {:?}", err);
+                }
+                continue;
+            }
+            let server = server.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(err) = server.handle_connection(stream) {
+                    error!("Error handling admin connection: {:?}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> Result<(), io::Error> {
+        stream.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)))?;
+        stream.set_write_timeout(Some(Duration::from_millis(WRITE_TIMEOUT_MS)))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut authenticated = self.auth_token.is_none();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let resp = match serde_json::from_str(&line) {
+                Ok(AdminRequest::Authenticate { token }) => {
+                    authenticated = self.verify_token(&token);
+                    if authenticated {
+                        AdminResponse::Authenticated
+                    } else {
+                        warn!("Rejecting admin connection with an invalid auth token");
+                        AdminResponse::Error {
+                            message: "Invalid auth token".to_string(),
+                        }
+                    }
+                }
+                Ok(_) if !authenticated => {
+                    warn!("Rejecting admin request without an auth token");
+                    AdminResponse::Error {
+                        message: "Authentication required".to_string(),
+                    }
+                }
+                Ok(req) => self.handle_request(req),
+                Err(err) => AdminResponse::Error {
+                    message: format!("Invalid request: {}", err),
+                },
+            };
+            write_response(&mut stream, &resp)?;
+            if !authenticated {
+                // Close the connection, so clients can't keep guessing tokens on it
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_token(&self, token: &str) -> bool {
+        match self.auth_token {
+            Some(ref expected) => expected.verify(token.as_bytes()),
+            None => true,
+        }
+    }
+
+    fn handle_request(&self, req: AdminRequest) -> AdminResponse {
+        debug!("Handling admin request {:?}", req);
+        let result = match req {
+            AdminRequest::Authenticate { .. } => Ok(AdminResponse::Authenticated),
+            AdminRequest::ListMetrics { pattern } => self.list_metrics(pattern),
+            AdminRequest::DeleteMetric { metric } => self.delete_metric(metric),
+            AdminRequest::TriggerDownsample => self.trigger_downsample(),
+            AdminRequest::DownsampleDryRun => self.downsample_dry_run(),
+            AdminRequest::TriggerCompaction { metric } => self.trigger_compaction(metric),
+            AdminRequest::Stats => self.stats(),
+            AdminRequest::RocksdbStats => self.rocksdb_stats(),
+            AdminRequest::Backup { dir } => self.backup(dir),
+        };
+        result.unwrap_or_else(|err| AdminResponse::Error {
+            message: format!("{:?}", err),
+        })
+    }
+
+    fn list_metrics(&self, pattern: Option<String>) -> Result<AdminResponse, StorageError> {
+        let metrics = self
+            .db_ref
+            .list_metrics()?
+            .filter(|m| match pattern {
+                Some(ref p) => metric_match(m, p),
+                None => true,
+            })
+            .collect();
+        Ok(AdminResponse::Metrics { metrics })
+    }
+
+    fn delete_metric(&self, metric: String) -> Result<AdminResponse, StorageError> {
+        info!("Deleting metric {} by admin request", metric);
+        self.db_ref.delete_metric(&metric)?;
+        Ok(AdminResponse::Deleted { metric })
+    }
+
+    // Triggers made while a pass is already pending are coalesced into that pass
+    fn trigger_downsample(&self) -> Result<AdminResponse, StorageError> {
+        match self.downsample_trigger.try_send(()) {
+            Ok(_) | Err(TrySendError::Full(_)) => Ok(AdminResponse::DownsampleTriggered),
+            // The downsample thread exits only when the server shuts down
+            Err(TrySendError::Disconnected(_)) => Err(StorageError::InternalError(
+                "Downsample thread is not running",
+            )),
+        }
+    }
+
+    fn downsample_dry_run(&self) -> Result<AdminResponse, StorageError> {
+        let clock = SystemClock::new();
+        let mut strategy = DefaultStrategy::new(&clock);
+        if let Some(ref rules) = self.downsample_tiers {
+            strategy = strategy.with_tiers(rules.clone());
+        }
+        let strategy = strategy.with_overrides(self.retention_overrides.clone());
+        let report = self.db_ref.downsample_dry_run(&strategy)?;
+        Ok(AdminResponse::DownsampleReport { report })
+    }
+
+    // Responds only after compaction finishes, which can take a while for a large store
+    fn trigger_compaction(&self, metric: Option<String>) -> Result<AdminResponse, StorageError> {
+        info!("Compacting by admin request");
+        self.db_ref.compact_range(metric.as_deref())?;
+        Ok(AdminResponse::Compacted { metric })
+    }
+
+    fn stats(&self) -> Result<AdminResponse, StorageError> {
+        Ok(AdminResponse::Stats {
+            metric_count: self.db_ref.metric_count()?,
+            disk_usage_bytes: self.db_ref.disk_usage_bytes()?,
+        })
+    }
+
+    // The directory is relative to the server's backup root
+    fn backup(&self, dir: String) -> Result<AdminResponse, StorageError> {
+        let root = self
+            .backup_root
+            .as_ref()
+            .ok_or(StorageError::InternalError(
+                "Backups are disabled, restart the server with --backup-root",
+            ))?;
+        let path = backup_path(root, &dir)?;
+        let path = path.to_str().ok_or(StorageError::InternalError(
+            "Backup path is not valid UTF-8",
+        ))?;
+        self.db_ref.backup(path)?;
+        Ok(AdminResponse::BackedUp { dir })
+    }
+
+    fn rocksdb_stats(&self) -> Result<AdminResponse, StorageError> {
+        let stats = self.db_ref.get_statistics()?;
+        if stats.is_empty() {
+            return Err(StorageError::InternalError(
+                "RocksDB statistics are disabled, restart the server with --expose-rocksdb-stats",
+            ));
+        }
+        Ok(AdminResponse::RocksdbStats {
+            stats: parse_statistics(&stats),
+        })
+    }
+}
+
+fn write_response(stream: &mut TcpStream, resp: &AdminResponse) -> Result<(), io::Error> {
+    let mut resp_line = serde_json::to_string(resp).map_err(io::Error::other)?;
+    resp_line.push('\n');
+    stream.write_all(resp_line.as_bytes())
+}
+
+// Counts an open admin connection until dropped
+struct ConnectionSlot {
+    active: Arc<AtomicUsize>,
+    count: usize,
+}
+
+impl ConnectionSlot {
+    fn new(active: Arc<AtomicUsize>) -> ConnectionSlot {
+        let count = active.fetch_add(1, Ordering::SeqCst) + 1;
+        ConnectionSlot { active, count }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Joins `dir` to `root`, rejecting absolute paths and `..` so that clients
+// can't write backups anywhere else on the server
+fn backup_path(root: &Path, dir: &str) -> Result<PathBuf, StorageError> {
+    let dir = Path::new(dir);
+    let is_relative = dir
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !is_relative || dir.file_name().is_none() {
+        return Err(StorageError::InternalError(
+            "Backup directory must be a relative path under the backup root",
+        ));
+    }
+    Ok(root.join(dir))
+}
+
+// Parses lines like `rocksdb.block.cache.miss COUNT : 12` or
+// `rocksdb.db.get.micros P50 : 1.5 P95 : 4.0 ... COUNT : 10 SUM : 30`,
+// skipping any line that doesn't match
+pub fn parse_statistics(stats: &str) -> Vec<RocksDbStat> {
+    stats.lines().filter_map(parse_stat_line).collect()
+}
+
+fn parse_stat_line(line: &str) -> Option<RocksDbStat> {
+    let mut tokens = line.split_whitespace();
+    let name = tokens.next()?.to_string();
+    let mut values = BTreeMap::new();
+    while let Some(key) = tokens.next() {
+        let key = key.to_lowercase();
+        if tokens.next() != Some(":") {
+            return None;
+        }
+        let value = tokens.next()?.parse::<f64>().ok()?;
+        values.insert(key, value);
+    }
+    if values.is_empty() {
+        return None;
+    }
+    Some(RocksDbStat { name, values })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_admin_requests() {
+        assert_eq!(
+            parse_request(r#"{"command": "authenticate", "token": "secret"}"#),
+            AdminRequest::Authenticate {
+                token: "secret".to_string()
+            }
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "list_metrics"}"#),
+            AdminRequest::ListMetrics { pattern: None }
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "list_metrics", "pattern": "foo.*"}"#),
+            AdminRequest::ListMetrics {
+                pattern: Some("foo.*".to_string())
+            }
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "delete_metric", "metric": "foo"}"#),
+            AdminRequest::DeleteMetric {
+                metric: "foo".to_string()
+            }
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "trigger_downsample"}"#),
+            AdminRequest::TriggerDownsample
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "downsample_dry_run"}"#),
+            AdminRequest::DownsampleDryRun
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "trigger_compaction"}"#),
+            AdminRequest::TriggerCompaction { metric: None }
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "trigger_compaction", "metric": "foo"}"#),
+            AdminRequest::TriggerCompaction {
+                metric: Some("foo".to_string())
+            }
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "stats"}"#),
+            AdminRequest::Stats
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "rocksdb_stats"}"#),
+            AdminRequest::RocksdbStats
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "backup", "dir": "daily"}"#),
+            AdminRequest::Backup {
+                dir: "daily".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_admin_command() {
+        let result: Result<AdminRequest, _> = serde_json::from_str(r#"{"command": "drop_all"}"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_serializes_admin_responses() {
+        let resp = AdminResponse::Stats {
+            metric_count: 2,
+            disk_usage_bytes: 1024,
+        };
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"type":"stats","metric_count":2,"disk_usage_bytes":1024}"#
+        );
+    }
+
+    #[test]
+    fn it_parses_rocksdb_statistics() {
+        let stats = parse_statistics(
+            "rocksdb.block.cache.miss COUNT : 12\n\
+             rocksdb.db.get.micros P50 : 1.5 P95 : 4.0 P99 : 9.0 P100 : 12.0 COUNT : 10 SUM : 30\n\
+             not a stat\n",
+        );
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            serde_json::to_string(&stats[0]).unwrap(),
+            r#"{"name":"rocksdb.block.cache.miss","count":12.0}"#
+        );
+        assert_eq!(stats[1].name, "rocksdb.db.get.micros");
+        assert_eq!(stats[1].values["p95"], 4.0);
+        assert_eq!(stats[1].values["sum"], 30.0);
+    }
+
+    #[test]
+    fn it_keeps_backups_under_backup_root() {
+        let root = Path::new("/var/backups/caesium");
+        assert_eq!(
+            backup_path(root, "daily").unwrap(),
+            PathBuf::from("/var/backups/caesium/daily")
+        );
+        assert_eq!(
+            backup_path(root, "2024/01").unwrap(),
+            PathBuf::from("/var/backups/caesium/2024/01")
+        );
+        for dir in ["", ".", "/etc", "../etc", "daily/../../etc"].iter() {
+            assert!(backup_path(root, dir).is_err(), "accepted {}", dir);
+        }
+    }
+
+    fn parse_request(s: &str) -> AdminRequest {
+        serde_json::from_str(s).expect("Could not parse admin request")
+    }
+}
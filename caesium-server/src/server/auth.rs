@@ -0,0 +1,62 @@
+use std::fmt;
+
+// Shared-secret token that clients must present before the server
+// processes any inserts or queries on a connection.
+#[derive(Clone)]
+pub struct AuthToken {
+    token: Vec<u8>,
+}
+
+impl AuthToken {
+    pub fn new(token: &str) -> AuthToken {
+        assert!(!token.is_empty());
+        AuthToken {
+            token: token.as_bytes().to_vec(),
+        }
+    }
+
+    // Compares every byte of the token regardless of where the first mismatch is,
+    // so the time taken doesn't reveal how much of the candidate was correct.
+    pub fn verify(&self, candidate: &[u8]) -> bool {
+        let mut diff = (self.token.len() ^ candidate.len()) as u64;
+        for (i, &b) in self.token.iter().enumerate() {
+            let c = candidate.get(i).cloned().unwrap_or(0);
+            diff |= (b ^ c) as u64;
+        }
+        diff == 0
+    }
+}
+
+// Never print the token itself, since server args are logged for debugging
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AuthToken(<redacted>)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_accepts_matching_token() {
+        let token = AuthToken::new("secret");
+        assert!(token.verify(b"secret"));
+    }
+
+    #[test]
+    fn it_rejects_different_token() {
+        let token = AuthToken::new("secret");
+        assert!(!token.verify(b"secreT"));
+        assert!(!token.verify(b"public"));
+    }
+
+    #[test]
+    fn it_rejects_prefix_and_extension_of_token() {
+        let token = AuthToken::new("secret");
+        assert!(!token.verify(b""));
+        assert!(!token.verify(b"secre"));
+        assert!(!token.verify(b"secret\0"));
+        assert!(!token.verify(b"secrets"));
+    }
+}
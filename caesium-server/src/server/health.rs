@@ -0,0 +1,79 @@
+use caesium_core::get_sketch_type;
+use server::http::url_path;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use storage::store::MetricStore;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const HEALTH_PATH: &str = "/healthz";
+const READY_PATH: &str = "/readyz";
+const CONTENT_TYPE: &str = "application/json";
+
+// Serves `GET /healthz` and `GET /readyz` for load balancers and orchestrators.
+// Liveness only means the process is up; readiness also requires a successful read
+// from the database, so traffic is held back until storage is usable.
+pub struct HealthServer {
+    server: Server,
+    db_ref: Arc<MetricStore>,
+}
+
+impl HealthServer {
+    pub fn new(addr: &SocketAddr, db_ref: Arc<MetricStore>) -> Result<HealthServer, io::Error> {
+        let server = Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(HealthServer { server, db_ref })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        Ok(self.server.server_addr())
+    }
+
+    pub fn run(self) -> Result<(), io::Error> {
+        info!("Serving health checks on {}", self.local_addr()?);
+        for request in self.server.incoming_requests() {
+            self.handle_request(request);
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) {
+        let path = url_path(request.url());
+        let result = if path != HEALTH_PATH && path != READY_PATH {
+            request.respond(Response::empty(404))
+        } else if *request.method() != Method::Get {
+            request.respond(Response::empty(405))
+        } else {
+            let (status, body) = if path == READY_PATH {
+                self.check_ready()
+            } else {
+                (200, format_ok())
+            };
+            let header = Header::from_bytes(&b"Content-Type"[..], CONTENT_TYPE.as_bytes())
+                .expect("Could not construct content type header");
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(header);
+            request.respond(response)
+        };
+        if let Err(err) = result {
+            error!("Could not send response to health check client: {:?}", err);
+        }
+    }
+
+    fn check_ready(&self) -> (u16, String) {
+        match self.db_ref.check_readable() {
+            Ok(()) => (200, format_ok()),
+            Err(err) => {
+                error!("Readiness check could not read from database: {:?}", err);
+                (503, "{\"status\":\"unavailable\"}".to_string())
+            }
+        }
+    }
+}
+
+fn format_ok() -> String {
+    format!(
+        "{{\"status\":\"ok\",\"sketch_type\":\"{}\"}}",
+        get_sketch_type().name()
+    )
+}
@@ -0,0 +1,310 @@
+use caesium_core::time::timer::Timer;
+use json::json_string;
+use query::error::QueryError;
+use query::execute::{execute_query, QueryResult, OVERFLOW_BUCKET_EDGE};
+use server::metrics::ServerMetrics;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use storage::store::MetricStore;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const QUERY_PATH: &str = "/query";
+const QUERY_PARAM: &str = "q";
+const CONTENT_TYPE: &str = "application/json";
+
+// Runs queries from `GET /query?q=<query>` and returns the results as JSON,
+// for clients like dashboards that cannot speak the read server protocol.
+// Queries count towards the same server metrics as queries on the read server.
+pub struct HttpQueryServer {
+    server: Server,
+    query_timeout: Option<Duration>,
+    metrics: Arc<ServerMetrics>,
+    db_ref: Arc<MetricStore>,
+}
+
+impl HttpQueryServer {
+    pub fn new(
+        addr: &SocketAddr,
+        query_timeout: Option<Duration>,
+        metrics: Arc<ServerMetrics>,
+        db_ref: Arc<MetricStore>,
+    ) -> Result<HttpQueryServer, io::Error> {
+        let server = Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(HttpQueryServer {
+            server,
+            query_timeout,
+            metrics,
+            db_ref,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        Ok(self.server.server_addr())
+    }
+
+    pub fn run(self) -> Result<(), io::Error> {
+        info!("Listening for HTTP queries on {}", self.local_addr()?);
+        for request in self.server.incoming_requests() {
+            self.handle_request(request);
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) {
+        let (path, query_str) = split_url(request.url());
+        let result = if path != QUERY_PATH {
+            request.respond(Response::empty(404))
+        } else if *request.method() != Method::Get {
+            request.respond(Response::empty(405))
+        } else {
+            let (status, body) = match parse_query_param(query_str) {
+                Some(query) => self.run_query(&query),
+                None => (400, format_error("Missing query parameter `q`")),
+            };
+            let header = Header::from_bytes(&b"Content-Type"[..], CONTENT_TYPE.as_bytes())
+                .expect("Could not construct content type header");
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(header);
+            request.respond(response)
+        };
+        if let Err(err) = result {
+            error!("Could not send response to HTTP query client: {:?}", err);
+        }
+    }
+
+    fn run_query(&self, query: &str) -> (u16, String) {
+        debug!("Executing HTTP query: {}", query);
+        let mut timer = Timer::new();
+        timer.start();
+        let result = execute_query(query, &*self.db_ref, self.query_timeout);
+        match result {
+            Ok(_) => self.metrics.record_query(timer.stop().unwrap()),
+            Err(ref err) => self.metrics.record_query_error(err),
+        }
+        match result {
+            Ok(results) => (200, format_results(&results)),
+            Err(err @ QueryError::StorageError(_)) => {
+                error!("Could not execute HTTP query: {:?}", err);
+                (500, format_query_error(&err))
+            }
+            Err(err) => (400, format_query_error(&err)),
+        }
+    }
+}
+
+// Routes match on the path alone, so clients can append query parameters
+// like cache busters without getting a 404
+pub fn url_path(url: &str) -> &str {
+    split_url(url).0
+}
+
+fn split_url(url: &str) -> (&str, &str) {
+    let mut parts = url.splitn(2, '?');
+    let path = parts.next().unwrap_or("");
+    let query_str = parts.next().unwrap_or("");
+    (path, query_str)
+}
+
+fn parse_query_param(query_str: &str) -> Option<String> {
+    query_str
+        .split('&')
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) if key == QUERY_PARAM => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+        .and_then(percent_decode)
+}
+
+// Decodes a URL-encoded query parameter, treating `+` as a space
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hi = hex_value(iter.next()?)?;
+                let lo = hex_value(iter.next()?)?;
+                bytes.push(hi << 4 | lo);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn format_results(results: &[QueryResult]) -> String {
+    let items: Vec<String> = results.iter().map(format_result).collect();
+    format!("{{\"results\":[{}]}}", items.join(","))
+}
+
+fn format_result(r: &QueryResult) -> String {
+    match r {
+        QueryResult::QuantileWindow(window, phi, quantile) => format!(
+            "{{\"type\":\"quantile\",\"start\":{},\"end\":{},\"phi\":{},\"count\":{},\"approx_value\":{},\"lower_bound\":{},\"upper_bound\":{}}}",
+            window.start(),
+            window.end(),
+            phi,
+            quantile.count,
+            quantile.format_value(quantile.approx_value),
+            quantile.format_value(quantile.lower_bound),
+            quantile.format_value(quantile.upper_bound)
+        ),
+        QueryResult::HistogramWindow(window, buckets) => {
+            let buckets: Vec<String> = buckets
+                .iter()
+                .map(|&(edge, count)| {
+                    if edge == OVERFLOW_BUCKET_EDGE {
+                        format!("{{\"le\":\"+Inf\",\"count\":{}}}", count)
+                    } else {
+                        format!("{{\"le\":{},\"count\":{}}}", edge, count)
+                    }
+                })
+                .collect();
+            format!(
+                "{{\"type\":\"histogram\",\"start\":{},\"end\":{},\"buckets\":[{}]}}",
+                window.start(),
+                window.end(),
+                buckets.join(",")
+            )
+        }
+        QueryResult::MetricName(metric) => {
+            format!("{{\"type\":\"metric\",\"name\":{}}}", json_string(metric))
+        }
+        QueryResult::Labeled(label, result) => {
+            // Every result formats as a JSON object, so add the label as its first field
+            let inner = format_result(result);
+            format!("{{\"label\":{},{}", json_string(label), &inner[1..])
+        }
+    }
+}
+
+fn format_error(msg: &str) -> String {
+    format!("{{\"error\":{}}}", json_string(msg))
+}
+
+fn format_query_error(err: &QueryError) -> String {
+    format!(
+        "{{\"error\":{},\"kind\":{}}}",
+        json_string(&err.to_string()),
+        json_string(err.kind())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use caesium_core::quantile::query::ApproxQuantile;
+    use caesium_core::time::window::TimeWindow;
+
+    #[test]
+    fn it_strips_query_string_from_path() {
+        assert_eq!(url_path("/health"), "/health");
+        assert_eq!(url_path("/health?x=1"), "/health");
+        assert_eq!(url_path("/api/v1/write?"), "/api/v1/write");
+    }
+
+    #[test]
+    fn it_parses_query_param() {
+        let (path, query_str) = split_url("/query?q=quantile(fetch(%22foo%22),+0.5)");
+        assert_eq!(path, "/query");
+        assert_eq!(
+            parse_query_param(query_str),
+            Some("quantile(fetch(\"foo\"), 0.5)".to_string())
+        );
+    }
+
+    #[test]
+    fn it_finds_query_param_among_others() {
+        assert_eq!(
+            parse_query_param("x=1&q=search(%22*%22)&y=2"),
+            Some("search(\"*\")".to_string())
+        );
+    }
+
+    #[test]
+    fn it_rejects_missing_or_invalid_query_param() {
+        assert_eq!(parse_query_param(""), None);
+        assert_eq!(parse_query_param("query=foo"), None);
+        assert_eq!(parse_query_param("q=%2"), None);
+        assert_eq!(parse_query_param("q=%zz"), None);
+    }
+
+    #[test]
+    fn it_formats_results_as_json() {
+        let quantile = ApproxQuantile {
+            count: 100,
+            approx_value: 50,
+            lower_bound: 48,
+            upper_bound: 52,
+            scale: 0,
+        };
+        let results = vec![
+            QueryResult::QuantileWindow(TimeWindow::new(0, 30), 0.5, quantile),
+            QueryResult::HistogramWindow(
+                TimeWindow::new(0, 30),
+                vec![(50, 50), (OVERFLOW_BUCKET_EDGE, 100)],
+            ),
+            QueryResult::MetricName("foo;region=\"us\"".to_string()),
+        ];
+        assert_eq!(
+            format_results(&results),
+            concat!(
+                "{\"results\":[",
+                "{\"type\":\"quantile\",\"start\":0,\"end\":30,\"phi\":0.5,\"count\":100,",
+                "\"approx_value\":50,\"lower_bound\":48,\"upper_bound\":52},",
+                "{\"type\":\"histogram\",\"start\":0,\"end\":30,\"buckets\":",
+                "[{\"le\":50,\"count\":50},{\"le\":\"+Inf\",\"count\":100}]},",
+                "{\"type\":\"metric\",\"name\":\"foo;region=\\\"us\\\"\"}",
+                "]}"
+            )
+        );
+    }
+
+    #[test]
+    fn it_formats_scaled_quantiles_as_json() {
+        let quantile = ApproxQuantile {
+            count: 100,
+            approx_value: 125,
+            lower_bound: 100,
+            upper_bound: 150,
+            scale: 2,
+        };
+        let result = QueryResult::QuantileWindow(TimeWindow::new(0, 30), 0.5, quantile);
+        assert_eq!(
+            format_result(&result),
+            concat!(
+                "{\"type\":\"quantile\",\"start\":0,\"end\":30,\"phi\":0.5,\"count\":100,",
+                "\"approx_value\":1.25,\"lower_bound\":1,\"upper_bound\":1.5}"
+            )
+        );
+    }
+
+    #[test]
+    fn it_formats_labeled_results_as_json() {
+        let result = QueryResult::Labeled(
+            "web".to_string(),
+            Box::new(QueryResult::MetricName("foo".to_string())),
+        );
+        assert_eq!(
+            format_result(&result),
+            "{\"label\":\"web\",\"type\":\"metric\",\"name\":\"foo\"}"
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use query::error::QueryError;
+use serde_json;
+use server::admin::parse_statistics;
+use server::http::url_path;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use storage::error::StorageError;
+use storage::store::MetricStore;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const METRICS_PATH: &str = "/metrics";
+const ROCKSDB_STATS_PATH: &str = "/admin/rocksdb-stats";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+// Counters describing the server's own behavior, shared by the write workers,
+// read workers, HTTP query server, and downsample thread. Counters only increase, as Prometheus expects.
+pub struct ServerMetrics {
+    inserts: AtomicU64,
+    insert_errors: AtomicU64,
+    queries: AtomicU64,
+    query_errors: AtomicU64,
+    query_duration_micros: AtomicU64,
+    database_errors: AtomicU64,
+    downsample_runs: AtomicU64,
+    downsample_errors: AtomicU64,
+    stored_metrics: AtomicUsize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MetricsSnapshot {
+    pub inserts: u64,
+    pub insert_errors: u64,
+    pub queries: u64,
+    pub query_errors: u64,
+    pub query_duration_micros: u64,
+    pub database_errors: u64,
+    pub downsample_runs: u64,
+    pub downsample_errors: u64,
+    pub stored_metrics: usize,
+}
+
+impl ServerMetrics {
+    pub fn new() -> ServerMetrics {
+        ServerMetrics {
+            inserts: AtomicU64::new(0),
+            insert_errors: AtomicU64::new(0),
+            queries: AtomicU64::new(0),
+            query_errors: AtomicU64::new(0),
+            query_duration_micros: AtomicU64::new(0),
+            database_errors: AtomicU64::new(0),
+            downsample_runs: AtomicU64::new(0),
+            downsample_errors: AtomicU64::new(0),
+            stored_metrics: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record_inserts(&self, count: u64) {
+        self.inserts.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_insert_error(&self, err: &StorageError) {
+        self.insert_errors.fetch_add(1, Ordering::Relaxed);
+        self.record_storage_error(err);
+    }
+
+    pub fn record_query(&self, duration: Duration) {
+        let micros = duration.as_secs() * 1_000_000 + duration.subsec_micros() as u64;
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        self.query_duration_micros
+            .fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn record_query_error(&self, err: &QueryError) {
+        self.query_errors.fetch_add(1, Ordering::Relaxed);
+        if let QueryError::StorageError(ref err) = *err {
+            self.record_storage_error(err);
+        }
+    }
+
+    pub fn record_downsample_run(&self) {
+        self.downsample_runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_downsample_error(&self, err: &StorageError) {
+        self.downsample_errors.fetch_add(1, Ordering::Relaxed);
+        self.record_storage_error(err);
+    }
+
+    // Only errors from RocksDB itself count as database errors,
+    // not bad input like invalid metric names or corrupted sketches
+    pub fn record_storage_error(&self, err: &StorageError) {
+        if let StorageError::DatabaseError(_) = *err {
+            self.database_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn set_stored_metrics(&self, count: usize) {
+        self.stored_metrics.store(count, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            inserts: self.inserts.load(Ordering::Relaxed),
+            insert_errors: self.insert_errors.load(Ordering::Relaxed),
+            queries: self.queries.load(Ordering::Relaxed),
+            query_errors: self.query_errors.load(Ordering::Relaxed),
+            query_duration_micros: self.query_duration_micros.load(Ordering::Relaxed),
+            database_errors: self.database_errors.load(Ordering::Relaxed),
+            downsample_runs: self.downsample_runs.load(Ordering::Relaxed),
+            downsample_errors: self.downsample_errors.load(Ordering::Relaxed),
+            stored_metrics: self.stored_metrics.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for ServerMetrics {
+    fn default() -> ServerMetrics {
+        ServerMetrics::new()
+    }
+}
+
+impl MetricsSnapshot {
+    // Renders the snapshot in the Prometheus text exposition format.
+    // Query latency is a summary without quantiles, so the average is `sum / count`.
+    pub fn render(&self) -> String {
+        let counters = [
+            ("caesium_inserts_total", self.inserts),
+            ("caesium_insert_errors_total", self.insert_errors),
+            ("caesium_query_errors_total", self.query_errors),
+            ("caesium_database_errors_total", self.database_errors),
+            ("caesium_downsample_runs_total", self.downsample_runs),
+            ("caesium_downsample_errors_total", self.downsample_errors),
+        ];
+        let mut out = String::new();
+        for &(name, value) in counters.iter() {
+            out.push_str(&format!("# TYPE {} counter\n{} {}\n", name, name, value));
+        }
+        out.push_str(&format!(
+            "# TYPE caesium_query_duration_seconds summary\n\
+             caesium_query_duration_seconds_sum {}\n\
+             caesium_query_duration_seconds_count {}\n",
+            self.query_duration_micros as f64 / 1e6,
+            self.queries
+        ));
+        out.push_str(&format!(
+            "# TYPE caesium_stored_metrics gauge\ncaesium_stored_metrics {}\n",
+            self.stored_metrics
+        ));
+        out
+    }
+}
+
+// Serves the current counters at `GET /metrics`.
+// The number of stored metrics is read from the store's metric cache on each scrape.
+// Also serves RocksDB's internal statistics at `GET /admin/rocksdb-stats`, one JSON object
+// per line, when the server runs with `--expose-rocksdb-stats`.
+pub struct MetricsServer {
+    server: Server,
+    metrics: Arc<ServerMetrics>,
+    db_ref: Arc<MetricStore>,
+}
+
+impl MetricsServer {
+    pub fn new(
+        addr: &SocketAddr,
+        metrics: Arc<ServerMetrics>,
+        db_ref: Arc<MetricStore>,
+    ) -> Result<MetricsServer, io::Error> {
+        let server = Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(MetricsServer {
+            server,
+            metrics,
+            db_ref,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        Ok(self.server.server_addr())
+    }
+
+    pub fn run(self) -> Result<(), io::Error> {
+        info!("Serving server metrics on {}", self.local_addr()?);
+        for request in self.server.incoming_requests() {
+            self.handle_request(request);
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) {
+        let path = url_path(request.url());
+        let (status, content_type, body) = if *request.method() != Method::Get {
+            (405, CONTENT_TYPE, String::new())
+        } else if path == METRICS_PATH {
+            (200, CONTENT_TYPE, self.render_metrics())
+        } else if path == ROCKSDB_STATS_PATH {
+            match self.render_rocksdb_stats() {
+                Some(body) => (200, NDJSON_CONTENT_TYPE, body),
+                None => (404, CONTENT_TYPE, String::new()),
+            }
+        } else {
+            (404, CONTENT_TYPE, String::new())
+        };
+        let header = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+            .expect("Could not construct content type header");
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(err) = request.respond(response) {
+            error!("Could not send response to metrics client: {:?}", err);
+        }
+    }
+
+    fn render_metrics(&self) -> String {
+        match self.db_ref.metric_count() {
+            Ok(count) => self.metrics.set_stored_metrics(count),
+            Err(err) => {
+                // Keep serving the counters, with the last known metric count
+                error!("Could not count stored metrics: {:?}", err);
+                self.metrics.record_storage_error(&err);
+            }
+        }
+        self.metrics.snapshot().render()
+    }
+
+    // Returns `None` if statistics are disabled
+    fn render_rocksdb_stats(&self) -> Option<String> {
+        let stats = match self.db_ref.get_statistics() {
+            Ok(ref stats) if stats.is_empty() => return None,
+            Ok(stats) => stats,
+            Err(err) => {
+                error!("Could not get RocksDB statistics: {:?}", err);
+                return None;
+            }
+        };
+        let mut body = String::new();
+        for stat in parse_statistics(&stats) {
+            let line = serde_json::to_string(&stat).expect("Could not serialize RocksDB stat");
+            body.push_str(&line);
+            body.push('\n');
+        }
+        Some(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn it_counts_concurrent_updates() {
+        let metrics = Arc::new(ServerMetrics::new());
+        let threads: Vec<thread::JoinHandle<()>> = (0..8)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        metrics.record_inserts(2);
+                        metrics.record_query(Duration::from_millis(1));
+                        metrics.record_downsample_run();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().expect("Could not join thread");
+        }
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.inserts, 16000);
+        assert_eq!(snapshot.queries, 8000);
+        assert_eq!(snapshot.query_duration_micros, 8_000_000);
+        assert_eq!(snapshot.downsample_runs, 8000);
+    }
+
+    #[test]
+    fn it_counts_only_database_errors_as_database_errors() {
+        let metrics = ServerMetrics::new();
+        metrics.record_insert_error(&StorageError::InvalidMetricName);
+        metrics.record_downsample_error(&StorageError::InternalError("test"));
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.insert_errors, 1);
+        assert_eq!(snapshot.downsample_errors, 1);
+        assert_eq!(snapshot.database_errors, 0);
+    }
+
+    #[test]
+    fn it_renders_prometheus_text_format() {
+        let metrics = ServerMetrics::new();
+        metrics.record_inserts(3);
+        metrics.record_query(Duration::from_millis(1500));
+        metrics.record_query_error(&QueryError::InvalidWindowSize(0));
+        metrics.set_stored_metrics(7);
+        let body = metrics.snapshot().render();
+        assert!(body.contains("# TYPE caesium_inserts_total counter\ncaesium_inserts_total 3\n"));
+        assert!(body.contains("caesium_query_errors_total 1\n"));
+        assert!(body.contains("caesium_query_duration_seconds_sum 1.5\n"));
+        assert!(body.contains("caesium_query_duration_seconds_count 1\n"));
+        assert!(body.contains("# TYPE caesium_stored_metrics gauge\ncaesium_stored_metrics 7\n"));
+    }
+}
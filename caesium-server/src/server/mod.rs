@@ -1,2 +1,11 @@
+pub mod admin;
+pub mod auth;
+pub mod health;
+pub mod http;
+pub mod metrics;
+pub mod prometheus;
 pub mod read;
+pub mod scrape;
+pub mod shutdown;
+pub mod tls;
 pub mod write;
@@ -0,0 +1,326 @@
+use caesium_core::encode::EncodableError;
+use caesium_core::protocol::messages::{InsertMessage, MetricKind};
+use caesium_core::quantile::scale::MAX_SCALE;
+use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::timestamp::TimeStamp;
+use caesium_core::time::window::TimeWindow;
+use prost::{DecodeError, Message};
+use server::http::url_path;
+use server::prometheus::proto::WriteRequest;
+use snap;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use storage::error::StorageError;
+use storage::store::MetricStore;
+use tiny_http::{Method, Request, Response, Server};
+
+const WRITE_PATH: &str = "/api/v1/write";
+const METRIC_NAME_LABEL: &str = "__name__";
+const MAX_BODY_LEN: usize = 32 * 1024 * 1024;
+
+// Accepts Prometheus remote-write requests over HTTP.
+// Samples are kept to `scale` decimal digits and inserted into one sketch
+// per metric and window.
+pub struct PrometheusWriteServer {
+    server: Server,
+    window_size: u64,
+    scale: u32,
+    db_ref: Arc<MetricStore>,
+}
+
+impl PrometheusWriteServer {
+    pub fn new(
+        addr: &SocketAddr,
+        window_size: u64,
+        scale: u32,
+        db_ref: Arc<MetricStore>,
+    ) -> Result<PrometheusWriteServer, io::Error> {
+        assert!(window_size > 0);
+        assert!(scale <= MAX_SCALE);
+        let server = Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(PrometheusWriteServer {
+            server,
+            window_size,
+            scale,
+            db_ref,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        Ok(self.server.server_addr())
+    }
+
+    pub fn run(self) -> Result<(), io::Error> {
+        info!(
+            "Listening for Prometheus remote writes on {}",
+            self.local_addr()?
+        );
+        for mut request in self.server.incoming_requests() {
+            let status = self.handle_request(&mut request);
+            if let Err(err) = request.respond(Response::empty(status)) {
+                error!("Could not send response to Prometheus client: {:?}", err);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: &mut Request) -> u16 {
+        if url_path(request.url()) != WRITE_PATH {
+            return 404;
+        }
+        if *request.method() != Method::Post {
+            return 405;
+        }
+        match self.handle_write(request) {
+            Ok(_) => 204,
+            Err(WriteError::StorageError(StorageError::InvalidMetricName))
+            | Err(WriteError::StorageError(StorageError::MetricNameTooLong(_))) => 400,
+            Err(WriteError::StorageError(err)) => {
+                error!("Could not store Prometheus samples: {:?}", err);
+                500
+            }
+            Err(err) => {
+                warn!("Could not parse Prometheus write request: {:?}", err);
+                400
+            }
+        }
+    }
+
+    fn handle_write(&self, request: &mut Request) -> Result<(), WriteError> {
+        let mut body = Vec::new();
+        request
+            .as_reader()
+            .take(MAX_BODY_LEN as u64 + 1)
+            .read_to_end(&mut body)?;
+        if body.len() > MAX_BODY_LEN || snap::raw::decompress_len(&body)? > MAX_BODY_LEN {
+            return Err(WriteError::BodyTooLong);
+        }
+        let buf = snap::raw::Decoder::new().decompress_vec(&body)?;
+        let req = WriteRequest::decode(&buf[..])?;
+        let inserts = to_insert_messages(req, self.window_size, self.scale)?;
+        self.db_ref.insert_batch(inserts)?;
+        Ok(())
+    }
+}
+
+// Groups samples into sketches by metric name and window.
+// Series without a metric name and non-finite samples (such as
+// Prometheus staleness markers) are skipped. Values are stored as fixed-point
+// integers with `scale` decimal digits, clamped to the range of the sketch.
+pub fn to_insert_messages(
+    req: WriteRequest,
+    window_size: u64,
+    scale: u32,
+) -> Result<Vec<InsertMessage>, EncodableError> {
+    let mut sketches: BTreeMap<(String, TimeStamp), WritableSketch> = BTreeMap::new();
+    for series in req.timeseries {
+        let metric = match series
+            .labels
+            .iter()
+            .find(|label| label.name == METRIC_NAME_LABEL)
+        {
+            Some(label) => label.value.clone(),
+            None => {
+                debug!("Skipping Prometheus series without a metric name");
+                continue;
+            }
+        };
+        for sample in series.samples {
+            if !sample.value.is_finite() || sample.timestamp < 0 {
+                continue;
+            }
+            let ts = (sample.timestamp / 1000) as TimeStamp;
+            let start = ts - (ts % window_size);
+            sketches
+                .entry((metric.clone(), start))
+                .or_default()
+                .insert_f64(sample.value, scale)?;
+        }
+    }
+    let inserts = sketches
+        .into_iter()
+        .map(|((metric, start), sketch)| InsertMessage {
+            metric,
+            kind: MetricKind::Timer,
+            window: TimeWindow::new(start, start + window_size),
+            sketch,
+        })
+        .collect();
+    Ok(inserts)
+}
+
+#[derive(Debug)]
+enum WriteError {
+    IOError(io::Error),
+    SnappyError(snap::Error),
+    DecodeError(DecodeError),
+    StorageError(StorageError),
+    SketchError(EncodableError),
+    BodyTooLong,
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> WriteError {
+        WriteError::IOError(err)
+    }
+}
+
+impl From<snap::Error> for WriteError {
+    fn from(err: snap::Error) -> WriteError {
+        WriteError::SnappyError(err)
+    }
+}
+
+impl From<DecodeError> for WriteError {
+    fn from(err: DecodeError) -> WriteError {
+        WriteError::DecodeError(err)
+    }
+}
+
+impl From<StorageError> for WriteError {
+    fn from(err: StorageError) -> WriteError {
+        WriteError::StorageError(err)
+    }
+}
+
+impl From<EncodableError> for WriteError {
+    fn from(err: EncodableError) -> WriteError {
+        WriteError::SketchError(err)
+    }
+}
+
+// Subset of the Prometheus remote storage schema (prompb/remote.proto)
+pub mod proto {
+    #[derive(Clone, PartialEq, Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub timeseries: Vec<TimeSeries>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        pub labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        pub samples: Vec<Sample>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Label {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Sample {
+        #[prost(double, tag = "1")]
+        pub value: f64,
+        #[prost(int64, tag = "2")]
+        pub timestamp: i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::*;
+    use super::*;
+
+    #[test]
+    fn it_groups_samples_by_metric_and_window() {
+        let req = WriteRequest {
+            timeseries: vec![
+                build_series(Some("foo"), &[(1.0, 1000), (2.0, 5000), (3.0, 12000)]),
+                build_series(Some("bar"), &[(4.0, 2000)]),
+            ],
+        };
+        let msgs = to_insert_messages(req, 10, 0).unwrap();
+        let actual: Vec<(String, TimeWindow, usize)> = msgs
+            .iter()
+            .map(|m| (m.metric.clone(), m.window, m.sketch.count()))
+            .collect();
+        assert_eq!(
+            actual,
+            vec![
+                ("bar".to_string(), TimeWindow::new(0, 10), 1),
+                ("foo".to_string(), TimeWindow::new(0, 10), 2),
+                ("foo".to_string(), TimeWindow::new(10, 20), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_skips_series_without_metric_name() {
+        let req = WriteRequest {
+            timeseries: vec![build_series(None, &[(1.0, 1000)])],
+        };
+        assert!(to_insert_messages(req, 10, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_skips_non_finite_and_negative_timestamp_samples() {
+        let req = WriteRequest {
+            timeseries: vec![build_series(
+                Some("foo"),
+                &[(f64::NAN, 1000), (f64::INFINITY, 1000), (1.0, -1000)],
+            )],
+        };
+        assert!(to_insert_messages(req, 10, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_rounds_and_clamps_unscaled_sample_values() {
+        let values = [-5.0, 1.4, 1.6, 1e20];
+        assert_eq!(
+            sample_values(&values, 0),
+            vec![0.0, 1.0, 2.0, f64::from(u32::MAX)]
+        );
+    }
+
+    #[test]
+    fn it_keeps_decimal_digits_up_to_scale() {
+        let values = [0.25, 0.5, 1.125];
+        assert_eq!(sample_values(&values, 2), vec![0.25, 0.5, 1.13]);
+    }
+
+    fn sample_values(values: &[f64], scale: u32) -> Vec<f64> {
+        let samples: Vec<(f64, i64)> = values.iter().map(|&v| (v, 1000)).collect();
+        let req = WriteRequest {
+            timeseries: vec![build_series(Some("foo"), &samples)],
+        };
+        let msgs = to_insert_messages(req, 10, scale).unwrap();
+        assert_eq!(msgs.len(), 1);
+        let sketch = msgs[0].sketch.clone();
+        assert_eq!(sketch.scale(), scale);
+        let readable = sketch.to_readable();
+        let mut result = Vec::new();
+        for i in 0..values.len() {
+            let phi = (i as f64 + 0.5) / values.len() as f64;
+            let q = readable.query(phi).expect("Could not query sketch");
+            result.push(q.approx_value_f64());
+        }
+        result
+    }
+
+    fn build_series(metric: Option<&str>, samples: &[(f64, i64)]) -> TimeSeries {
+        let mut labels = vec![Label {
+            name: "job".to_string(),
+            value: "test".to_string(),
+        }];
+        if let Some(m) = metric {
+            labels.push(Label {
+                name: METRIC_NAME_LABEL.to_string(),
+                value: m.to_string(),
+            });
+        }
+        let samples = samples
+            .iter()
+            .map(|&(value, timestamp)| Sample { value, timestamp })
+            .collect();
+        TimeSeries { labels, samples }
+    }
+}
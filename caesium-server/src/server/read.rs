@@ -1,13 +1,27 @@
+use mio::net::TcpListener;
+use mio::{Events, Poll, PollOpt, Ready, Token};
+use rustls::ServerConfig;
+use server::auth::AuthToken;
+use server::metrics::ServerMetrics;
 use server::read::worker::spawn_worker;
 use std::io;
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{sync_channel, SyncSender};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use storage::store::MetricStore;
 
+const LISTENER_TOKEN: Token = Token(0);
+const MAX_NUM_EVENTS: usize = 16;
+const POLL_TIMEOUT_MS: u64 = 100;
+
 pub struct ReadServer {
     listener: TcpListener,
     tx: SyncSender<TcpStream>,
+    workers: Vec<thread::JoinHandle<()>>,
+    shutdown: Arc<AtomicBool>,
 }
 
 impl ReadServer {
@@ -15,67 +29,152 @@ impl ReadServer {
         addr: &SocketAddr,
         num_workers: usize,
         buffer_len: usize,
+        query_timeout: Option<Duration>,
+        slow_query_threshold: Duration,
+        tls_config: Option<Arc<ServerConfig>>,
+        auth_token: Option<AuthToken>,
+        shutdown: Arc<AtomicBool>,
+        metrics: Arc<ServerMetrics>,
         db_ref: Arc<MetricStore>,
     ) -> Result<ReadServer, io::Error> {
         assert!(num_workers > 0);
         let listener = TcpListener::bind(addr)?;
         let (tx, rx) = sync_channel(buffer_len);
         let rx_ref = Arc::new(Mutex::new(rx));
-        for idx in 0..num_workers {
-            spawn_worker(idx, rx_ref.clone(), db_ref.clone())
-        }
-        Ok(ReadServer { listener, tx })
+        let workers = (0..num_workers)
+            .map(|idx| {
+                spawn_worker(
+                    idx,
+                    rx_ref.clone(),
+                    query_timeout,
+                    slow_query_threshold,
+                    tls_config.clone(),
+                    auth_token.clone(),
+                    metrics.clone(),
+                    db_ref.clone(),
+                )
+            })
+            .collect();
+        Ok(ReadServer {
+            listener,
+            tx,
+            workers,
+            shutdown,
+        })
     }
 
     pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
         self.listener.local_addr()
     }
 
+    // Waits for connections with a timeout, so the server can check for shutdown
+    // even when no clients connect
     pub fn run(self) -> Result<(), io::Error> {
+        let poll = Poll::new()?;
+        poll.register(
+            &self.listener,
+            LISTENER_TOKEN,
+            Ready::readable(),
+            PollOpt::edge(),
+        )?;
+        let mut events = Events::with_capacity(MAX_NUM_EVENTS);
+        let timeout = Duration::from_millis(POLL_TIMEOUT_MS);
         info!("Listening for queries on {}", self.local_addr()?);
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(stream) => {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            poll.poll(&mut events, Some(timeout))?;
+            if events.iter().any(|event| event.token() == LISTENER_TOKEN) {
+                self.accept_connections();
+            }
+        }
+
+        // Closing the channel lets workers exit after finishing queued queries
+        info!("Stopped accepting queries, waiting for workers to finish");
+        let ReadServer { tx, workers, .. } = self;
+        drop(tx);
+        for worker in workers {
+            if let Err(err) = worker.join() {
+                error!("Error joining read worker thread: {:?}", err);
+            }
+        }
+        Ok(())
+    }
+
+    // The listener is edge-triggered, so accept until no connections are pending
+    fn accept_connections(&self) {
+        loop {
+            // Accepted streams are blocking, since workers read each query to the end
+            match self.listener.accept_std() {
+                Ok((stream, _)) => {
                     if let Err(err) = self.tx.send(stream) {
                         error!("Error sending to worker threads: {:?}", err);
                     }
                 }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return,
                 Err(err) => {
                     error!("Error accepting connection: {:?}", err);
+                    return;
                 }
             }
         }
-        Ok(())
     }
 }
 
 mod worker {
     use caesium_core::time::timer::Timer;
     use query::error::QueryError;
-    use query::execute::{execute_query, QueryResult};
+    use query::execute::{execute_query_streaming, QueryResult, OVERFLOW_BUCKET_EDGE};
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+    use server::auth::AuthToken;
+    use server::metrics::ServerMetrics;
+    use server::tls::tls_error;
     use std::io;
     use std::io::{Read, Write};
     use std::net::TcpStream;
     use std::sync::mpsc::Receiver;
     use std::sync::{Arc, Mutex};
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
+    use storage::datasource::DataSource;
     use storage::store::MetricStore;
 
     const READ_TIMEOUT_MS: u64 = 10000;
     const WRITE_TIMEOUT_MS: u64 = 10000;
+    const STREAM_HEADER: &str = "stream: true";
+    const REQUEST_ID_HEADER: &str = "request-id:";
+    const AUTH_HEADER: &str = "auth:";
 
     pub fn spawn_worker(
         id: usize,
         rx_lock: Arc<Mutex<Receiver<TcpStream>>>,
+        query_timeout: Option<Duration>,
+        slow_query_threshold: Duration,
+        tls_config: Option<Arc<ServerConfig>>,
+        auth_token: Option<AuthToken>,
+        metrics: Arc<ServerMetrics>,
         db_ref: Arc<MetricStore>,
-    ) {
-        thread::spawn(move || process_messages(id, rx_lock, db_ref));
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            process_messages(
+                id,
+                rx_lock,
+                query_timeout,
+                slow_query_threshold,
+                tls_config,
+                auth_token,
+                metrics,
+                db_ref,
+            )
+        })
     }
 
     fn process_messages(
         id: usize,
         rx_lock: Arc<Mutex<Receiver<TcpStream>>>,
+        query_timeout: Option<Duration>,
+        slow_query_threshold: Duration,
+        tls_config: Option<Arc<ServerConfig>>,
+        auth_token: Option<AuthToken>,
+        metrics: Arc<ServerMetrics>,
         db_ref: Arc<MetricStore>,
     ) {
         let mut query_buf = String::new();
@@ -89,84 +188,596 @@ mod worker {
             match recv_result {
                 Ok(stream) => {
                     debug!("Processing query in worker thread with id {}", id);
-                    if let Err(err) = handle_query(id, stream, &mut query_buf, &mut timer, db) {
+                    if let Err(err) = handle_connection(
+                        id,
+                        stream,
+                        &tls_config,
+                        &auth_token,
+                        &mut query_buf,
+                        &mut timer,
+                        query_timeout,
+                        slow_query_threshold,
+                        &metrics,
+                        db,
+                    ) {
                         error!("Error handling query: {:?}", err);
                     }
                 }
-                Err(err) => {
-                    error!("Error receiving worker msg: {:?}", err);
+                Err(_) => {
+                    debug!("Stopping read worker thread with id {}", id);
+                    break;
                 }
             }
         }
     }
 
-    fn handle_query(
+    fn handle_connection(
         id: usize,
         mut stream: TcpStream,
-        mut query_buf: &mut String,
+        tls_config: &Option<Arc<ServerConfig>>,
+        auth_token: &Option<AuthToken>,
+        query_buf: &mut String,
         timer: &mut Timer,
+        query_timeout: Option<Duration>,
+        slow_query_threshold: Duration,
+        metrics: &ServerMetrics,
         db: &MetricStore,
     ) -> Result<(), io::Error> {
         stream.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)))?;
         stream.set_write_timeout(Some(Duration::from_millis(WRITE_TIMEOUT_MS)))?;
+        match *tls_config {
+            Some(ref config) => {
+                let conn = ServerConnection::new(config.clone()).map_err(tls_error)?;
+                let mut tls_stream = StreamOwned::new(conn, stream);
+                handle_query(
+                    id,
+                    &mut tls_stream,
+                    auth_token,
+                    query_buf,
+                    timer,
+                    query_timeout,
+                    slow_query_threshold,
+                    metrics,
+                    db,
+                )?;
+                tls_stream.conn.send_close_notify();
+                tls_stream.flush()
+            }
+            None => handle_query(
+                id,
+                &mut stream,
+                auth_token,
+                query_buf,
+                timer,
+                query_timeout,
+                slow_query_threshold,
+                metrics,
+                db,
+            ),
+        }
+    }
+
+    fn handle_query<S: Read + Write>(
+        id: usize,
+        stream: &mut S,
+        auth_token: &Option<AuthToken>,
+        mut query_buf: &mut String,
+        timer: &mut Timer,
+        query_timeout: Option<Duration>,
+        slow_query_threshold: Duration,
+        metrics: &ServerMetrics,
+        db: &MetricStore,
+    ) -> Result<(), io::Error> {
         query_buf.clear();
         stream.read_to_string(&mut query_buf)?;
+        let Request {
+            streaming,
+            request_id,
+            auth_token: request_token,
+            query,
+        } = parse_request(&query_buf);
+        if let Some(ref token) = *auth_token {
+            let valid = request_token.is_some_and(|t| token.verify(t.as_bytes()));
+            if !valid {
+                // Close the connection without responding, so an unauthenticated
+                // client learns nothing about the server's contents
+                warn!(
+                    "Rejecting query without a valid auth token in worker thread with id {}",
+                    id
+                );
+                return Ok(());
+            }
+        }
+        if let Some(request_id) = request_id {
+            // Echo the ID before any results, followed by a blank line
+            write!(stream, "{} {}\n\n", REQUEST_ID_HEADER, request_id)?;
+        }
         debug!(
-            "Executing query `{}` in worker thread with id {}",
-            query_buf, id
+            "Executing query `{}` in {} (streaming={})",
+            sanitize_query(query),
+            log_context(id, request_id),
+            streaming
         );
         timer.start();
-        match execute_query(&query_buf, db) {
-            Ok(results) => {
+        if streaming {
+            return stream_query_results(
+                id,
+                request_id,
+                query,
+                stream,
+                timer,
+                query_timeout,
+                slow_query_threshold,
+                metrics,
+                db,
+            );
+        }
+        // Write each result as soon as the query produces it, so memory use
+        // does not grow with the number of results
+        let result = execute_timed_query(
+            id,
+            request_id,
+            query,
+            db,
+            query_timeout,
+            slow_query_threshold,
+            |r| -> Result<(), StreamError> {
+                let mut line = format_result(r);
+                line.push_str(&"\n");
+                stream.write_all(line.as_bytes())?;
+                Ok(())
+            },
+        );
+        match result {
+            Ok(_) => {
                 let duration = timer.stop().unwrap();
                 debug!(
                     "Query in worker thread with id {} executed in {:?}",
                     id, duration
                 );
-                write_query_results(id, results, stream)
+                metrics.record_query(duration);
+                Ok(())
             }
-            Err(err) => write_query_error(id, err, stream),
+            Err(StreamError::QueryError(err)) => {
+                metrics.record_query_error(&err);
+                write_query_error(id, err, stream)
+            }
+            Err(StreamError::IOError(err)) => Err(err),
         }
     }
 
-    fn write_query_results(
+    #[derive(Debug, PartialEq)]
+    struct Request<'a> {
+        streaming: bool,
+        request_id: Option<u64>,
+        auth_token: Option<&'a str>,
+        query: &'a str,
+    }
+
+    // A request may begin with header lines, followed by the query.
+    // The headers are `stream: true` to stream results, `request-id: <u64>`
+    // to correlate the request with server logs (the ID is echoed in the response),
+    // and `auth: <token>` when the server requires an auth token.
+    fn parse_request<'a>(request: &'a str) -> Request<'a> {
+        let mut result = Request {
+            streaming: false,
+            request_id: None,
+            auth_token: None,
+            query: request.trim(),
+        };
+        let mut rest = request;
+        loop {
+            let mut parts = rest.splitn(2, '\n');
+            let line = parts.next().unwrap_or("").trim();
+            let remainder = match parts.next() {
+                Some(r) => r,
+                None => break,
+            };
+            if line == STREAM_HEADER {
+                result.streaming = true;
+            } else if let Some(id) = line.strip_prefix(REQUEST_ID_HEADER) {
+                match id.trim().parse::<u64>() {
+                    Ok(request_id) => result.request_id = Some(request_id),
+                    Err(_) => break,
+                }
+            } else if let Some(token) = line.strip_prefix(AUTH_HEADER) {
+                result.auth_token = Some(token.trim());
+            } else {
+                break;
+            }
+            rest = remainder;
+            result.query = rest.trim();
+        }
+        result
+    }
+
+    // Sends each result as a server-sent event as soon as the query pipeline
+    // produces it. The stream always ends with either an `error` or `end` event,
+    // so clients can tell a complete result set from a truncated one.
+    fn stream_query_results<S: Write>(
         id: usize,
-        mut results: Vec<QueryResult>,
-        mut stream: TcpStream,
+        request_id: Option<u64>,
+        query: &str,
+        stream: &mut S,
+        timer: &mut Timer,
+        query_timeout: Option<Duration>,
+        slow_query_threshold: Duration,
+        metrics: &ServerMetrics,
+        db: &MetricStore,
     ) -> Result<(), io::Error> {
-        debug!("Writing query results in worker thread with id {}", id);
-        results
-            .drain(..)
-            .map(|r| match r {
-                QueryResult::QuantileWindow(window, phi, quantile) => format!(
-                    "start={}, end={}, phi={}, count={}, approx={}, lower={}, upper={}\n",
-                    window.start(),
-                    window.end(),
-                    phi,
-                    quantile.count,
-                    quantile.approx_value,
-                    quantile.lower_bound,
-                    quantile.upper_bound
-                ),
-                QueryResult::MetricName(mut metric) => {
-                    metric.push_str(&"\n");
-                    metric
+        debug!("Streaming query results in worker thread with id {}", id);
+        let result = execute_timed_query(
+            id,
+            request_id,
+            query,
+            db,
+            query_timeout,
+            slow_query_threshold,
+            |r| -> Result<(), StreamError> {
+                write!(stream, "data: {}\n\n", format_result(r))?;
+                stream.flush()?;
+                Ok(())
+            },
+        );
+        match result {
+            Ok(_) => {
+                let duration = timer.stop().unwrap();
+                debug!(
+                    "Streaming query in worker thread with id {} executed in {:?}",
+                    id, duration
+                );
+                metrics.record_query(duration);
+                stream.write_all(b"event: end\ndata:\n\n")
+            }
+            Err(StreamError::QueryError(err)) => {
+                metrics.record_query_error(&err);
+                debug!(
+                    "Streaming query error `{:?}` in worker thread with id {}",
+                    err, id
+                );
+                write!(stream, "event: error\ndata: {}\n\n", format_error(&err))
+            }
+            Err(StreamError::IOError(err)) => Err(err),
+        }
+    }
+
+    // Logs the query with its execution time if it takes longer than `slow_query_threshold`,
+    // whether or not it succeeds. The time includes writing results to the client.
+    fn execute_timed_query<F>(
+        id: usize,
+        request_id: Option<u64>,
+        query: &str,
+        source: &DataSource,
+        query_timeout: Option<Duration>,
+        slow_query_threshold: Duration,
+        emit: F,
+    ) -> Result<(), StreamError>
+    where
+        F: FnMut(QueryResult) -> Result<(), StreamError>,
+    {
+        let start = Instant::now();
+        let result = execute_query_streaming(query, source, query_timeout, emit);
+        let elapsed = start.elapsed();
+        if elapsed > slow_query_threshold {
+            warn!(
+                "Slow query in {} took {} ms: {}",
+                log_context(id, request_id),
+                elapsed.as_millis(),
+                sanitize_query(query)
+            );
+        }
+        result
+    }
+
+    // Names the worker running a query in log messages, along with the
+    // client's request ID when it sent one
+    fn log_context(id: usize, request_id: Option<u64>) -> String {
+        match request_id {
+            Some(request_id) => format!("worker thread with id {} for request {}", id, request_id),
+            None => format!("worker thread with id {}", id),
+        }
+    }
+
+    // Escapes control characters, so a query can't inject fake lines
+    // or terminal escape sequences into the log
+    fn sanitize_query(query: &str) -> String {
+        query
+            .chars()
+            .map(|c| {
+                if c.is_control() {
+                    c.escape_default().to_string()
+                } else {
+                    c.to_string()
                 }
             })
-            .map(|line| stream.write_all(line.as_bytes()))
             .collect()
     }
 
-    fn write_query_error(
+    fn format_result(r: QueryResult) -> String {
+        match r {
+            QueryResult::QuantileWindow(window, phi, quantile) => format!(
+                "start={}, end={}, phi={}, count={}, approx={}, lower={}, upper={}",
+                window.start(),
+                window.end(),
+                phi,
+                quantile.count,
+                quantile.format_value(quantile.approx_value),
+                quantile.format_value(quantile.lower_bound),
+                quantile.format_value(quantile.upper_bound)
+            ),
+            QueryResult::HistogramWindow(window, buckets) => {
+                let buckets: Vec<String> = buckets
+                    .iter()
+                    .map(|&(edge, count)| {
+                        if edge == OVERFLOW_BUCKET_EDGE {
+                            format!("+Inf:{}", count)
+                        } else {
+                            format!("{}:{}", edge, count)
+                        }
+                    })
+                    .collect();
+                format!(
+                    "start={}, end={}, buckets={}",
+                    window.start(),
+                    window.end(),
+                    buckets.join(" ")
+                )
+            }
+            QueryResult::MetricName(metric) => metric,
+            QueryResult::Labeled(label, result) => {
+                format!("label={}, {}", label, format_result(*result))
+            }
+        }
+    }
+
+    fn write_query_error<S: Write>(
         id: usize,
         err: QueryError,
-        mut stream: TcpStream,
+        stream: &mut S,
     ) -> Result<(), io::Error> {
         debug!(
             "Writing query error `{:?}` in worker thread with id {}",
             err, id
         );
-        let err_str = format!("[ERROR] {:?}\n", err);
+        let err_str = format!("[ERROR] {}\n", format_error(&err));
         stream.write_all(err_str.as_bytes())
     }
+
+    // Errors are sent as `<kind>: <message>`, for example
+    // `parse_error: Unexpected end of query`
+    fn format_error(err: &QueryError) -> String {
+        format!("{}: {}", err.kind(), err)
+    }
+
+    enum StreamError {
+        QueryError(QueryError),
+        IOError(io::Error),
+    }
+
+    impl From<QueryError> for StreamError {
+        fn from(err: QueryError) -> StreamError {
+            StreamError::QueryError(err)
+        }
+    }
+
+    impl From<io::Error> for StreamError {
+        fn from(err: io::Error) -> StreamError {
+            StreamError::IOError(err)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use caesium_core::quantile::query::ApproxQuantile;
+        use caesium_core::quantile::writable::WritableSketch;
+        use caesium_core::time::window::TimeWindow;
+        use log;
+        use log::{Level, LevelFilter, Log, Metadata, Record};
+        use storage::datasource::DataRow;
+        use storage::mock::MockDataSource;
+
+        // Records warnings from every test in this binary, so tests should
+        // look for lines containing their own query
+        struct CaptureLogger {
+            lines: Mutex<Vec<String>>,
+        }
+
+        impl Log for CaptureLogger {
+            fn enabled(&self, metadata: &Metadata) -> bool {
+                metadata.level() <= Level::Warn
+            }
+
+            fn log(&self, record: &Record) {
+                if self.enabled(record.metadata()) {
+                    self.lines
+                        .lock()
+                        .expect("Could not acquire lock on captured logs")
+                        .push(format!("{}", record.args()));
+                }
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CaptureLogger = CaptureLogger {
+            lines: Mutex::new(Vec::new()),
+        };
+
+        fn captured_lines_containing(s: &str) -> Vec<String> {
+            LOGGER
+                .lines
+                .lock()
+                .expect("Could not acquire lock on captured logs")
+                .iter()
+                .filter(|line| line.contains(s))
+                .cloned()
+                .collect()
+        }
+
+        fn execute_with_capture(query: &str, source: &MockDataSource, threshold: Duration) {
+            execute_request_with_capture(None, query, source, threshold)
+        }
+
+        fn execute_request_with_capture(
+            request_id: Option<u64>,
+            query: &str,
+            source: &MockDataSource,
+            threshold: Duration,
+        ) {
+            // Only the first call installs the logger, so ignore the error from later calls
+            let _ = log::set_logger(&LOGGER);
+            log::set_max_level(LevelFilter::Warn);
+            let _ = execute_timed_query(0, request_id, query, source, None, threshold, |_| Ok(()));
+        }
+
+        fn build_slow_source(metric: &str) -> MockDataSource {
+            let mut source = MockDataSource::new();
+            for i in 0..3 {
+                source.add_row(
+                    metric,
+                    DataRow {
+                        window: TimeWindow::new(i * 10, (i + 1) * 10),
+                        sketch: WritableSketch::new(),
+                    },
+                );
+            }
+            source.set_row_delay(Duration::from_millis(20));
+            source
+        }
+
+        #[test]
+        fn it_logs_slow_queries() {
+            let source = build_slow_source("slow_logged");
+            let query = "quantile(fetch(\"slow_logged\"), 0.5)";
+            execute_with_capture(query, &source, Duration::from_millis(10));
+            let lines = captured_lines_containing(query);
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].starts_with("Slow query in worker thread with id 0 took "));
+            assert!(lines[0].contains(" ms: "));
+        }
+
+        #[test]
+        fn it_logs_request_id_with_slow_queries() {
+            let source = build_slow_source("slow_request");
+            let query = "quantile(fetch(\"slow_request\"), 0.5)";
+            execute_request_with_capture(Some(42), query, &source, Duration::from_millis(10));
+            let lines = captured_lines_containing(query);
+            assert_eq!(lines.len(), 1);
+            assert!(
+                lines[0].starts_with("Slow query in worker thread with id 0 for request 42 took ")
+            );
+        }
+
+        #[test]
+        fn it_skips_logging_fast_queries() {
+            let source = build_slow_source("slow_skipped");
+            let query = "quantile(fetch(\"slow_skipped\"), 0.5)";
+            execute_with_capture(query, &source, Duration::from_secs(3600));
+            assert!(captured_lines_containing(query).is_empty());
+        }
+
+        #[test]
+        fn it_sanitizes_slow_query_log() {
+            // The injected characters make the query fail to parse, so log every query
+            let source = MockDataSource::new();
+            let query = "quantile(fetch(\"slow_sanitized\"), 0.5)\nINFO fake\u{1b}[2J";
+            execute_with_capture(query, &source, Duration::from_secs(0));
+            let lines = captured_lines_containing("slow_sanitized");
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].ends_with("0.5)\\nINFO fake\\u{1b}[2J"));
+        }
+
+        #[test]
+        fn it_parses_plain_request() {
+            let req = parse_request("quantile(fetch(\"foo\"), 0.5)\n");
+            assert!(!req.streaming);
+            assert_eq!(req.request_id, None);
+            assert_eq!(req.query, "quantile(fetch(\"foo\"), 0.5)");
+        }
+
+        #[test]
+        fn it_parses_streaming_request() {
+            let req = parse_request("stream: true\nquantile(fetch(\"foo\"), 0.5)");
+            assert!(req.streaming);
+            assert_eq!(req.query, "quantile(fetch(\"foo\"), 0.5)");
+        }
+
+        #[test]
+        fn it_ignores_stream_header_without_query() {
+            let req = parse_request("stream: true");
+            assert!(!req.streaming);
+            assert_eq!(req.query, "stream: true");
+        }
+
+        #[test]
+        fn it_parses_request_id() {
+            let req = parse_request("request-id: 1234\nsearch(\"*\")");
+            assert_eq!(
+                req,
+                Request {
+                    streaming: false,
+                    request_id: Some(1234),
+                    auth_token: None,
+                    query: "search(\"*\")",
+                }
+            );
+        }
+
+        #[test]
+        fn it_parses_request_id_with_stream_header() {
+            let req =
+                parse_request("stream: true\nrequest-id: 18446744073709551615\nsearch(\"*\")");
+            assert!(req.streaming);
+            assert_eq!(req.request_id, Some(u64::MAX));
+            assert_eq!(req.query, "search(\"*\")");
+        }
+
+        #[test]
+        fn it_parses_auth_header() {
+            let req = parse_request("auth: secret\nrequest-id: 7\nsearch(\"*\")");
+            assert_eq!(req.auth_token, Some("secret"));
+            assert_eq!(req.request_id, Some(7));
+            assert_eq!(req.query, "search(\"*\")");
+        }
+
+        #[test]
+        fn it_treats_invalid_request_id_as_query() {
+            let req = parse_request("request-id: abc\nsearch(\"*\")");
+            assert_eq!(req.request_id, None);
+            assert_eq!(req.query, "request-id: abc\nsearch(\"*\")");
+        }
+
+        #[test]
+        fn it_formats_quantile_bounds() {
+            let quantile = ApproxQuantile {
+                count: 100,
+                approx_value: 50,
+                lower_bound: 48,
+                upper_bound: 52,
+                scale: 0,
+            };
+            let result = QueryResult::QuantileWindow(TimeWindow::new(0, 30), 0.5, quantile);
+            assert_eq!(
+                format_result(result),
+                "start=0, end=30, phi=0.5, count=100, approx=50, lower=48, upper=52"
+            );
+        }
+
+        #[test]
+        fn it_formats_scaled_quantile_bounds() {
+            let quantile = ApproxQuantile {
+                count: 100,
+                approx_value: 125,
+                lower_bound: 100,
+                upper_bound: 150,
+                scale: 2,
+            };
+            let result = QueryResult::QuantileWindow(TimeWindow::new(0, 30), 0.5, quantile);
+            assert_eq!(
+                format_result(result),
+                "start=0, end=30, phi=0.5, count=100, approx=1.25, lower=1, upper=1.5"
+            );
+        }
+    }
 }
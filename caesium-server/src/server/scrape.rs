@@ -0,0 +1,195 @@
+use caesium_core::metric::MetricName;
+use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::clock::{Clock, SystemClock};
+use server::http::url_path;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use storage::error::StorageError;
+use storage::store::MetricStore;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+// Exposes recent quantiles for every stored metric in the Prometheus
+// text exposition format, as one summary per metric.
+pub struct PrometheusScrapeServer {
+    server: Server,
+    lookback_secs: u64,
+    db_ref: Arc<MetricStore>,
+}
+
+impl PrometheusScrapeServer {
+    pub fn new(
+        addr: &SocketAddr,
+        lookback_secs: u64,
+        db_ref: Arc<MetricStore>,
+    ) -> Result<PrometheusScrapeServer, io::Error> {
+        let server = Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(PrometheusScrapeServer {
+            server,
+            lookback_secs,
+            db_ref,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        Ok(self.server.server_addr())
+    }
+
+    pub fn run(self) -> Result<(), io::Error> {
+        info!("Listening for Prometheus scrapes on {}", self.local_addr()?);
+        let clock = SystemClock::new();
+        for request in self.server.incoming_requests() {
+            self.handle_request(request, &clock);
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request, clock: &Clock) {
+        let result = if url_path(request.url()) != METRICS_PATH {
+            request.respond(Response::empty(404))
+        } else if *request.method() != Method::Get {
+            request.respond(Response::empty(405))
+        } else {
+            let since = clock.now().saturating_sub(self.lookback_secs);
+            match render_metrics(&self.db_ref, since) {
+                Ok(body) => {
+                    let header = Header::from_bytes(&b"Content-Type"[..], CONTENT_TYPE.as_bytes())
+                        .expect("Could not construct content type header");
+                    request.respond(Response::from_string(body).with_header(header))
+                }
+                Err(err) => {
+                    error!("Could not render metrics for Prometheus scrape: {:?}", err);
+                    request.respond(Response::empty(500))
+                }
+            }
+        };
+        if let Err(err) = result {
+            error!("Could not send response to Prometheus client: {:?}", err);
+        }
+    }
+}
+
+pub fn render_metrics(db: &MetricStore, since: u64) -> Result<String, StorageError> {
+    // Group series by Prometheus metric name so each summary has a single TYPE line
+    let mut summaries: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (metric, sketch) in db.summarize_since(since)? {
+        if let Some(name) = MetricName::parse(&metric) {
+            let lines = summaries.entry(sanitize_name(name.base())).or_default();
+            lines.extend(summary_lines(&name, sketch));
+        }
+    }
+    let mut out = String::new();
+    for (name, lines) in summaries {
+        out.push_str(&format!("# TYPE {} summary\n", name));
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+fn summary_lines(name: &MetricName, sketch: WritableSketch) -> Vec<String> {
+    let base = sanitize_name(name.base());
+    let count = sketch.count();
+    let readable = sketch.to_readable();
+    let mut lines = Vec::new();
+    for &phi in QUANTILES.iter() {
+        if let Some(q) = readable.query(phi) {
+            let quantile_label = ("quantile".to_string(), phi.to_string());
+            lines.push(format!(
+                "{}{} {}",
+                base,
+                format_labels(name, Some(quantile_label)),
+                q.approx_value_f64()
+            ));
+        }
+    }
+    lines.push(format!(
+        "{}_count{} {}",
+        base,
+        format_labels(name, None),
+        count
+    ));
+    lines
+}
+
+fn format_labels(name: &MetricName, extra: Option<(String, String)>) -> String {
+    let labels: Vec<String> = name
+        .tags()
+        .iter()
+        .cloned()
+        .chain(extra)
+        .map(|(key, value)| format!("{}=\"{}\"", sanitize_name(&key), value))
+        .collect();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+// Prometheus names may only contain [a-zA-Z0-9_:], so replace anything else
+fn sanitize_name(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_sanitizes_names() {
+        assert_eq!(sanitize_name("http.latency-ms"), "http_latency_ms");
+        assert_eq!(sanitize_name("foo_bar:baz"), "foo_bar:baz");
+    }
+
+    #[test]
+    fn it_formats_summary_lines() {
+        let name = MetricName::parse("http.latency;region=us").unwrap();
+        let sketch = WritableSketch::from_slice(&[1, 2, 3]);
+        let lines = summary_lines(&name, sketch);
+        assert_eq!(
+            lines,
+            vec![
+                "http_latency{region=\"us\",quantile=\"0.5\"} 2",
+                "http_latency{region=\"us\",quantile=\"0.9\"} 3",
+                "http_latency{region=\"us\",quantile=\"0.99\"} 3",
+                "http_latency_count{region=\"us\"} 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_formats_decimal_values_without_scale() {
+        let name = MetricName::parse("foo").unwrap();
+        let mut sketch = WritableSketch::new();
+        for v in [0.25, 0.5, 0.75].iter() {
+            sketch.insert_f64(*v, 2).expect("Could not insert value");
+        }
+        let lines = summary_lines(&name, sketch);
+        assert_eq!(lines[0], "foo{quantile=\"0.5\"} 0.5");
+        assert_eq!(lines[1], "foo{quantile=\"0.9\"} 0.75");
+    }
+
+    #[test]
+    fn it_formats_lines_without_tags() {
+        let name = MetricName::parse("foo").unwrap();
+        let lines = summary_lines(&name, WritableSketch::from_slice(&[5]));
+        assert_eq!(lines[0], "foo{quantile=\"0.5\"} 5");
+        assert_eq!(lines[3], "foo_count 1");
+    }
+}
@@ -0,0 +1,12 @@
+use ctrlc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Sets the shared flag when the process receives SIGINT, SIGTERM, or SIGHUP,
+// so the read and write servers stop accepting connections and drain queued work.
+pub fn install_signal_handler(shutdown: Arc<AtomicBool>) -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(move || {
+        info!("Received shutdown signal");
+        shutdown.store(true, Ordering::SeqCst);
+    })
+}
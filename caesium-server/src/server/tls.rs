@@ -0,0 +1,73 @@
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile;
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::sync::Arc;
+
+// Loads a TLS server configuration from PEM files.
+// If a CA certificate is provided, clients must present a certificate
+// signed by that CA (mutual TLS).
+pub fn load_server_config(
+    cert_path: &str,
+    key_path: &str,
+    ca_path: Option<&str>,
+) -> Result<Arc<ServerConfig>, io::Error> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let config = match ca_path {
+        Some(path) => {
+            let roots = load_root_store(path)?;
+            builder
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+                .with_single_cert(certs, key)
+        }
+        None => builder.with_no_client_auth().with_single_cert(certs, key),
+    }
+    .map_err(tls_error)?;
+    Ok(Arc::new(config))
+}
+
+pub fn load_certs(path: &str) -> Result<Vec<Certificate>, io::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(invalid_data(format!("No certificates found in {}", path)));
+    }
+    Ok(certs)
+}
+
+pub fn load_root_store(path: &str) -> Result<RootCertStore, io::Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(&cert).map_err(tls_error)?;
+    }
+    Ok(roots)
+}
+
+pub fn load_private_key(path: &str) -> Result<PrivateKey, io::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key));
+            }
+            _ => {}
+        }
+    }
+    Err(invalid_data(format!("No private key found in {}", path)))
+}
+
+pub fn tls_error(err: rustls::Error) -> io::Error {
+    invalid_data(err.to_string())
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -1,21 +1,39 @@
 use bytes::Bytes;
 use mio::net::TcpListener;
 use mio::{Events, Poll, PollOpt, Ready, Token};
+use rustls::{ServerConfig, ServerConnection};
+use server::auth::AuthToken;
+use server::metrics::ServerMetrics;
+use server::tls::tls_error;
 use server::write::connection::{Connection, ConnectionState};
 use server::write::worker::spawn_worker;
 use slab::Slab;
+use std::collections::HashMap;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{sync_channel, SyncSender};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use storage::store::MetricStore;
 
 const MAX_NUM_EVENTS: usize = 1024;
+const POLL_TIMEOUT_MS: u64 = 100;
+const IDLE_SWEEP_INTERVAL_MS: u64 = 1000;
 
 pub struct WriteServer {
     listener: TcpListener,
     tx: SyncSender<Bytes>,
+    workers: Vec<thread::JoinHandle<()>>,
+    tls_config: Option<Arc<ServerConfig>>,
+    auth_token: Option<AuthToken>,
+    shutdown: Arc<AtomicBool>,
     connections: Slab<Option<Connection>>,
+    max_connections_per_ip: usize,
+    connections_per_ip: HashMap<IpAddr, usize>,
+    idle_timeout: Duration,
+    max_frame_len: usize,
 }
 
 impl WriteServer {
@@ -23,19 +41,37 @@ impl WriteServer {
         addr: &SocketAddr,
         num_workers: usize,
         buffer_len: usize,
+        max_connections_per_ip: usize,
+        idle_timeout: Duration,
+        max_frame_len: usize,
+        tls_config: Option<Arc<ServerConfig>>,
+        auth_token: Option<AuthToken>,
+        shutdown: Arc<AtomicBool>,
+        metrics: Arc<ServerMetrics>,
         db_ref: Arc<MetricStore>,
     ) -> Result<WriteServer, io::Error> {
         assert!(num_workers > 0);
+        assert!(max_connections_per_ip > 0);
+        assert!(idle_timeout > Duration::from_secs(0));
+        assert!(max_frame_len > 0);
         let listener = TcpListener::bind(addr)?;
         let (tx, rx) = sync_channel(buffer_len);
         let rx_ref = Arc::new(Mutex::new(rx));
-        for idx in 0..num_workers {
-            spawn_worker(idx, rx_ref.clone(), db_ref.clone());
-        }
+        let workers = (0..num_workers)
+            .map(|idx| spawn_worker(idx, rx_ref.clone(), metrics.clone(), db_ref.clone()))
+            .collect();
         Ok(WriteServer {
             listener,
             tx,
+            workers,
+            tls_config,
+            auth_token,
+            shutdown,
             connections: Slab::new(),
+            max_connections_per_ip,
+            connections_per_ip: HashMap::new(),
+            idle_timeout,
+            max_frame_len,
         })
     }
 
@@ -54,8 +90,15 @@ impl WriteServer {
         )?;
         let mut events = Events::with_capacity(MAX_NUM_EVENTS);
         info!("Listening for inserts on {}", self.local_addr()?);
-        loop {
-            poll.poll(&mut events, None)?;
+        let timeout = Duration::from_millis(POLL_TIMEOUT_MS);
+        let sweep_interval = Duration::from_millis(IDLE_SWEEP_INTERVAL_MS);
+        let mut last_sweep = Instant::now();
+        while !self.shutdown.load(Ordering::SeqCst) {
+            poll.poll(&mut events, Some(timeout))?;
+            if last_sweep.elapsed() >= sweep_interval {
+                self.close_idle_connections();
+                last_sweep = Instant::now();
+            }
             for event in events.iter() {
                 match event.token() {
                     Token(t) if t == listener_id => {
@@ -67,22 +110,90 @@ impl WriteServer {
                 }
             }
         }
+        self.drain(listener_id);
+        Ok(())
+    }
+
+    // Reads complete frames already received on open connections, then closes them.
+    // Workers exit once they've processed every queued message.
+    fn drain(mut self, listener_id: usize) {
+        info!("Stopped accepting inserts, draining open connections");
+        let conn_ids: Vec<usize> = self
+            .connections
+            .iter()
+            .filter(|&(id, conn)| id != listener_id && conn.is_some())
+            .map(|(id, _)| id)
+            .collect();
+        for conn_id in conn_ids {
+            self.handle_read_ready(conn_id);
+        }
+        let WriteServer { tx, workers, .. } = self;
+        drop(tx);
+        for worker in workers {
+            if let Err(err) = worker.join() {
+                error!("Error joining write worker thread: {:?}", err);
+            }
+        }
+    }
+
+    // Closes connections without any activity for longer than the idle timeout,
+    // so clients that stop sending don't hold connection slots forever
+    fn close_idle_connections(&mut self) {
+        let idle_timeout = self.idle_timeout;
+        let idle_ids: Vec<usize> = self
+            .connections
+            .iter()
+            .filter_map(|(id, conn)| match conn {
+                Some(conn) if conn.idle_time() >= idle_timeout => Some(id),
+                _ => None,
+            })
+            .collect();
+        for conn_id in idle_ids {
+            if let Some(conn) = self.connections.remove(conn_id) {
+                debug!("Closing idle connection from {}", conn.peer_ip());
+                self.release_connection_slot(conn.peer_ip());
+            }
+        }
     }
 
     fn handle_new_connections(&mut self, poll: &Poll) {
         loop {
             match self.listener.accept() {
-                Ok((stream, _)) => {
+                Ok((stream, peer_addr)) => {
+                    let peer_ip = peer_addr.ip();
+                    if !self.acquire_connection_slot(peer_ip) {
+                        // Dropping the stream closes the connection
+                        warn!(
+                            "Rejecting connection from {}, which already has {} open connections",
+                            peer_ip, self.max_connections_per_ip
+                        );
+                        continue;
+                    }
+                    let tls = match self.new_tls_connection() {
+                        Ok(tls) => tls,
+                        Err(err) => {
+                            error!("Could not create TLS connection: {:?}", err);
+                            self.release_connection_slot(peer_ip);
+                            continue;
+                        }
+                    };
+                    // TLS connections need to flush handshake records once the socket
+                    // becomes writable, so they're registered for both events.
+                    let interest = match tls {
+                        Some(_) => Ready::readable() | Ready::writable(),
+                        None => Ready::readable(),
+                    };
                     let entry = self.connections.vacant_entry();
                     let conn_id = entry.key();
                     let tok = Token(conn_id);
-                    match poll.register(&stream, tok, Ready::readable(), PollOpt::edge()) {
+                    match poll.register(&stream, tok, interest, PollOpt::edge()) {
                         Ok(_) => {
-                            let conn = Connection::new(stream);
+                            let conn = Connection::new(stream, peer_ip, tls, self.max_frame_len);
                             entry.insert(Some(conn));
                         }
                         Err(err) => {
                             error!("Could not register new connection: {:?}", err);
+                            self.release_connection_slot(peer_ip);
                         }
                     }
                 }
@@ -96,30 +207,80 @@ impl WriteServer {
         }
     }
 
+    fn new_tls_connection(&self) -> Result<Option<ServerConnection>, io::Error> {
+        match self.tls_config {
+            Some(ref config) => ServerConnection::new(config.clone())
+                .map(Some)
+                .map_err(tls_error),
+            None => Ok(None),
+        }
+    }
+
+    fn acquire_connection_slot(&mut self, ip: IpAddr) -> bool {
+        let count = self.connections_per_ip.entry(ip).or_insert(0);
+        if *count >= self.max_connections_per_ip {
+            false
+        } else {
+            *count += 1;
+            true
+        }
+    }
+
+    fn release_connection_slot(&mut self, ip: IpAddr) {
+        let remaining = match self.connections_per_ip.get_mut(&ip) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+        if remaining == 0 {
+            self.connections_per_ip.remove(&ip);
+        }
+    }
+
     fn handle_read_ready(&mut self, conn_id: usize) {
-        let mut conn = self
-            .connections
-            .get_mut(conn_id)
-            .expect("Could not retrieve connection")
-            .take()
-            .expect("Connection entry should not be None");
-        match conn.read_until_blocked() {
-            Ok(conn_state) => match conn.output_messages(&self.tx) {
-                Ok(_) => {
-                    if let ConnectionState::Open = conn_state {
-                        let conn_entry = self
-                            .connections
-                            .get_mut(conn_id)
-                            .expect("Could not retrieve connection");
-                        *conn_entry = Some(conn);
-                    }
+        // The entry may already be gone if the connection closed earlier in this batch of events
+        let mut conn = match self.connections.get_mut(conn_id).and_then(|c| c.take()) {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.touch();
+        if self.process_connection(&mut conn) {
+            let conn_entry = self
+                .connections
+                .get_mut(conn_id)
+                .expect("Could not retrieve connection");
+            *conn_entry = Some(conn);
+        } else {
+            self.connections.remove(conn_id);
+            self.release_connection_slot(conn.peer_ip());
+        }
+    }
+
+    // Returns whether the connection is still open.
+    // Reads stop whenever the buffer fills, so frames are sent to the workers
+    // before reading more. Polling is edge-triggered, so reads resume until they block.
+    fn process_connection(&self, conn: &mut Connection) -> bool {
+        loop {
+            let conn_state = match conn.read_until_blocked() {
+                Ok(conn_state) => conn_state,
+                Err(err) => {
+                    error!("Error handling read: {:?}", err);
+                    return false;
                 }
+            };
+            let output_state = match conn.output_messages(&self.tx, self.auth_token.as_ref()) {
+                Ok(output_state) => output_state,
                 Err(err) => {
                     error!("Error sending insert msg to workers: {:?}", err);
+                    return false;
                 }
-            },
-            Err(err) => {
-                error!("Error handling read: {:?}", err);
+            };
+            match (conn_state, output_state) {
+                (ConnectionState::Full, ConnectionState::Open) => continue,
+                (ConnectionState::Open, ConnectionState::Open) => return true,
+                _ => return false,
             }
         }
     }
@@ -128,33 +289,93 @@ impl WriteServer {
 mod connection {
     use bytes::{Bytes, BytesMut};
     use caesium_core::encode::frame::FrameInfo;
+    use caesium_core::encode::{Decodable, EncodableError};
+    use caesium_core::protocol::messages::WriteMessage;
     use mio::net::TcpStream;
+    use rustls::ServerConnection;
+    use server::auth::AuthToken;
+    use server::tls::tls_error;
     use std::io;
     use std::io::Read;
+    use std::net::IpAddr;
     use std::sync::mpsc::SendError;
     use std::sync::mpsc::SyncSender;
+    use std::time::{Duration, Instant};
 
     const INITIAL_BUFSIZE: usize = 4096;
 
     pub enum ConnectionState {
         Open,
         Closed,
+
+        // The buffer reached its limit before the socket would block,
+        // so there may be more to read after outputting messages
+        Full,
+    }
+
+    enum FrameResult {
+        Complete(Bytes),
+        Corrupted,
+        Incomplete,
+        UnsupportedVersion(u8),
+        TooLarge(usize),
     }
 
     pub struct Connection {
         stream: TcpStream,
+        peer_ip: IpAddr,
+        tls: Option<ServerConnection>,
         buf: BytesMut,
+        logged_corrupted_frame: bool,
+        authenticated: bool,
+        last_active: Instant,
+
+        // Frames longer than this close the connection. The buffer never holds more than
+        // one read beyond this, since every complete frame is output before reading more.
+        max_frame_len: usize,
     }
 
     impl Connection {
-        pub fn new(stream: TcpStream) -> Connection {
+        pub fn new(
+            stream: TcpStream,
+            peer_ip: IpAddr,
+            tls: Option<ServerConnection>,
+            max_frame_len: usize,
+        ) -> Connection {
             Connection {
                 stream,
+                peer_ip,
+                tls,
                 buf: BytesMut::with_capacity(INITIAL_BUFSIZE),
+                logged_corrupted_frame: false,
+                authenticated: false,
+                last_active: Instant::now(),
+                max_frame_len,
             }
         }
 
+        pub fn peer_ip(&self) -> IpAddr {
+            self.peer_ip
+        }
+
+        pub fn touch(&mut self) {
+            self.last_active = Instant::now();
+        }
+
+        pub fn idle_time(&self) -> Duration {
+            self.last_active.elapsed()
+        }
+
         pub fn read_until_blocked(&mut self) -> Result<ConnectionState, io::Error> {
+            match self.tls {
+                Some(ref mut tls) => {
+                    read_tls_until_blocked(&mut self.stream, tls, &mut self.buf, self.max_frame_len)
+                }
+                None => self.read_plain_until_blocked(),
+            }
+        }
+
+        fn read_plain_until_blocked(&mut self) -> Result<ConnectionState, io::Error> {
             let mut tmp = [0; 1024];
             loop {
                 match self.stream.read(&mut tmp[..]) {
@@ -163,6 +384,9 @@ mod connection {
                     }
                     Ok(n) => {
                         self.buf.extend_from_slice(&tmp[..n]);
+                        if self.buf.len() >= self.max_frame_len {
+                            return Ok(ConnectionState::Full);
+                        }
                     }
                     Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                         return Ok(ConnectionState::Open);
@@ -174,49 +398,199 @@ mod connection {
             }
         }
 
-        pub fn output_messages(&mut self, tx: &SyncSender<Bytes>) -> Result<(), SendError<Bytes>> {
+        // If the server requires an auth token, the first frame must be an auth message
+        // with a matching token; otherwise the connection is closed before any
+        // messages are sent to the workers.
+        pub fn output_messages(
+            &mut self,
+            tx: &SyncSender<Bytes>,
+            auth_token: Option<&AuthToken>,
+        ) -> Result<ConnectionState, SendError<Bytes>> {
             loop {
                 match self.read_frame() {
-                    Some(msg_bytes) => {
-                        tx.send(msg_bytes)?;
+                    FrameResult::Complete(msg_bytes) => match auth_token {
+                        Some(token) if !self.authenticated => {
+                            if !verify_auth_msg(&msg_bytes, token) {
+                                warn!(
+                                    "Rejecting connection from {} without a valid auth token",
+                                    self.peer_ip
+                                );
+                                self.buf.clear();
+                                return Ok(ConnectionState::Closed);
+                            }
+                            self.authenticated = true;
+                        }
+                        _ => {
+                            tx.send(msg_bytes)?;
+                        }
+                    },
+                    FrameResult::Corrupted if auth_token.is_some() && !self.authenticated => {
+                        warn!(
+                            "Rejecting connection from {} that sent a corrupted auth frame",
+                            self.peer_ip
+                        );
+                        self.buf.clear();
+                        return Ok(ConnectionState::Closed);
                     }
-                    None => {
+                    FrameResult::Corrupted => {
+                        // Log only the first corrupted frame to avoid flooding the logs
+                        // if the client keeps sending bad data.
+                        if !self.logged_corrupted_frame {
+                            error!("Dropping frame with invalid checksum");
+                            self.logged_corrupted_frame = true;
+                        }
+                    }
+                    FrameResult::Incomplete => {
                         break;
                     }
+                    FrameResult::TooLarge(frame_len) => {
+                        // Close before buffering the frame, so a bogus length
+                        // can't make the server allocate without bound
+                        warn!(
+                            "Closing connection from {} that sent a frame of {} bytes, over the limit of {}",
+                            self.peer_ip, frame_len, self.max_frame_len
+                        );
+                        self.buf.clear();
+                        return Ok(ConnectionState::Closed);
+                    }
+                    FrameResult::UnsupportedVersion(version) => {
+                        // We can't interpret the rest of the stream without understanding
+                        // the protocol version, so close the connection.
+                        error!(
+                            "Closing connection that sent unsupported protocol version {}",
+                            version
+                        );
+                        self.buf.clear();
+                        return Ok(ConnectionState::Closed);
+                    }
                 }
             }
-            Ok(())
+            Ok(ConnectionState::Open)
         }
 
-        fn read_frame(&mut self) -> Option<Bytes> {
-            if let Some(frame_info) = FrameInfo::from_bytes(&self.buf) {
-                let frame_len = frame_info.prefix_len + frame_info.msg_len;
-                if self.buf.len() >= frame_len {
-                    self.buf.advance(frame_info.prefix_len);
-                    let msg_buf = self.buf.split_to(frame_info.msg_len).freeze();
-                    return Some(msg_buf);
+        fn read_frame(&mut self) -> FrameResult {
+            let frame_info = match FrameInfo::from_bytes(&self.buf) {
+                Ok(Some(frame_info)) => frame_info,
+                Ok(None) => return FrameResult::Incomplete,
+                Err(EncodableError::LengthTooLong(msg_len)) => {
+                    return FrameResult::TooLarge(msg_len)
+                }
+                Err(_) => return FrameResult::TooLarge(usize::MAX),
+            };
+            if frame_info.check_version().is_err() {
+                return FrameResult::UnsupportedVersion(frame_info.version);
+            }
+            if frame_info.frame_len() > self.max_frame_len {
+                return FrameResult::TooLarge(frame_info.frame_len());
+            }
+            if self.buf.len() >= frame_info.frame_len() {
+                self.buf.advance(frame_info.prefix_len);
+                let msg_buf = self.buf.split_to(frame_info.msg_len).freeze();
+                if frame_info.verify_checksum(&msg_buf) {
+                    return FrameResult::Complete(msg_buf);
+                } else {
+                    return FrameResult::Corrupted;
                 }
             }
-            return None;
+            FrameResult::Incomplete
         }
     }
+
+    fn verify_auth_msg(mut msg_bytes: &[u8], token: &AuthToken) -> bool {
+        match WriteMessage::decode(&mut msg_bytes) {
+            Ok(WriteMessage::Auth(msg)) => token.verify(msg.token.as_bytes()),
+            _ => false,
+        }
+    }
+
+    fn read_tls_until_blocked(
+        stream: &mut TcpStream,
+        tls: &mut ServerConnection,
+        buf: &mut BytesMut,
+        max_buf_len: usize,
+    ) -> Result<ConnectionState, io::Error> {
+        let mut tmp = [0; 1024];
+        loop {
+            match tls.read_tls(stream) {
+                Ok(0) => {
+                    return Ok(ConnectionState::Closed);
+                }
+                Ok(_) => {
+                    let state = tls.process_new_packets().map_err(|err| {
+                        // Try to tell the client why the handshake failed before closing
+                        let _ = tls.write_tls(stream);
+                        tls_error(err)
+                    })?;
+                    loop {
+                        match tls.reader().read(&mut tmp[..]) {
+                            Ok(0) => break,
+                            Ok(n) => buf.extend_from_slice(&tmp[..n]),
+                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    if state.peer_has_closed() {
+                        tls.send_close_notify();
+                        write_tls_until_blocked(stream, tls)?;
+                        return Ok(ConnectionState::Closed);
+                    }
+                    write_tls_until_blocked(stream, tls)?;
+                    if buf.len() >= max_buf_len {
+                        return Ok(ConnectionState::Full);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    write_tls_until_blocked(stream, tls)?;
+                    return Ok(ConnectionState::Open);
+                }
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    fn write_tls_until_blocked(
+        stream: &mut TcpStream,
+        tls: &mut ServerConnection,
+    ) -> Result<(), io::Error> {
+        while tls.wants_write() {
+            match tls.write_tls(stream) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
 }
 
 mod worker {
     use bytes::Bytes;
     use caesium_core::encode::Decodable;
-    use caesium_core::protocol::messages::InsertMessage;
+    use caesium_core::protocol::messages::WriteMessage;
+    use server::metrics::ServerMetrics;
     use std::sync::mpsc::Receiver;
     use std::sync::{Arc, Mutex};
     use std::thread;
     use storage::error::StorageError;
     use storage::store::MetricStore;
 
-    pub fn spawn_worker(id: usize, rx_lock: Arc<Mutex<Receiver<Bytes>>>, db_ref: Arc<MetricStore>) {
-        thread::spawn(move || process_messages(id, rx_lock, db_ref));
+    pub fn spawn_worker(
+        id: usize,
+        rx_lock: Arc<Mutex<Receiver<Bytes>>>,
+        metrics: Arc<ServerMetrics>,
+        db_ref: Arc<MetricStore>,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || process_messages(id, rx_lock, metrics, db_ref))
     }
 
-    fn process_messages(id: usize, rx_lock: Arc<Mutex<Receiver<Bytes>>>, db_ref: Arc<MetricStore>) {
+    fn process_messages(
+        id: usize,
+        rx_lock: Arc<Mutex<Receiver<Bytes>>>,
+        metrics: Arc<ServerMetrics>,
+        db_ref: Arc<MetricStore>,
+    ) {
         let db = &*db_ref;
         loop {
             let recv_result = rx_lock
@@ -226,23 +600,45 @@ mod worker {
             match recv_result {
                 Ok(buf) => {
                     debug!("Processing insert in worker thread with id {}", id);
-                    if let Err(err) = handle_insert(buf, db) {
-                        error!(
-                            "Could not process insert task (worker id {}): {:?}",
-                            id, err
-                        );
+                    match handle_insert(buf, db) {
+                        Ok(count) => metrics.record_inserts(count),
+                        Err(err) => {
+                            error!(
+                                "Could not process insert task (worker id {}): {:?}",
+                                id, err
+                            );
+                            metrics.record_insert_error(&err);
+                        }
                     }
                 }
-                Err(err) => {
-                    error!("Error receiving worker msg: {:?}", err);
+                Err(_) => {
+                    debug!("Stopping write worker thread with id {}", id);
+                    break;
                 }
             }
         }
     }
 
-    fn handle_insert(buf: Bytes, db: &MetricStore) -> Result<(), StorageError> {
+    // Returns the number of sketches inserted
+    fn handle_insert(buf: Bytes, db: &MetricStore) -> Result<u64, StorageError> {
         let mut buf_slice: &[u8] = &buf;
-        let msg = InsertMessage::decode(&mut buf_slice)?;
-        db.insert(&msg.metric, msg.window, msg.sketch)
+        match WriteMessage::decode(&mut buf_slice)? {
+            WriteMessage::Insert(msg) => {
+                db.insert(&msg.metric, msg.window, msg.sketch)?;
+                Ok(1)
+            }
+            WriteMessage::BatchInsert(batch) => {
+                let count = batch.inserts.len() as u64;
+                debug!("Inserting batch of {} sketches", count);
+                db.insert_batch(batch.inserts)?;
+                Ok(count)
+            }
+            WriteMessage::Auth(_) => {
+                // Connections check the token before forwarding any messages,
+                // so an auth message that reaches a worker needs no handling
+                debug!("Ignoring auth message");
+                Ok(0)
+            }
+        }
     }
 }
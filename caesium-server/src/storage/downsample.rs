@@ -1,6 +1,9 @@
+use caesium_core::time::clock::Clock;
 use caesium_core::time::timestamp::TimeStamp;
 use caesium_core::time::window::TimeWindow;
 use std::cmp::max;
+use std::collections::BTreeMap;
+use storage::wildcard::metric_match;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum DownsampleAction {
@@ -13,7 +16,72 @@ pub enum DownsampleAction {
 }
 
 pub trait DownsampleStrategy {
-    fn get_action(&self, window: TimeWindow) -> DownsampleAction;
+    fn get_action(&self, metric: &str, window: TimeWindow) -> DownsampleAction;
+}
+
+// Number of windows per metric that a downsample pass would ignore, discard, or expand
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DownsampleReport {
+    pub metrics: BTreeMap<String, DownsampleCounts>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DownsampleCounts {
+    pub ignore: usize,
+    pub discard: usize,
+    pub expand_window: usize,
+}
+
+impl DownsampleReport {
+    pub fn new() -> DownsampleReport {
+        DownsampleReport::default()
+    }
+
+    pub fn record(&mut self, metric: &str, action: &DownsampleAction) {
+        let counts = self.metrics.entry(metric.to_string()).or_default();
+        match *action {
+            DownsampleAction::Ignore => counts.ignore += 1,
+            DownsampleAction::Discard => counts.discard += 1,
+            DownsampleAction::ExpandWindow(_) => counts.expand_window += 1,
+        }
+    }
+}
+
+// (age_seconds, target_window_seconds), see `TieredStrategy` for how tiers apply
+pub type RetentionTier = (u64, u64);
+
+// Parses a policy of comma-separated `AGE:WINDOW` tiers, for example `30d:1m, 90d:1h`.
+// Durations are in seconds unless followed by a unit: s, m, h, d, or w.
+pub fn parse_retention_policy(s: &str) -> Result<Vec<RetentionTier>, &'static str> {
+    let mut tiers = Vec::new();
+    for tier in s.split(',') {
+        let mut parts = tier.trim().splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(age), Some(size)) => {
+                tiers.push((parse_duration_secs(age)?, parse_duration_secs(size)?))
+            }
+            _ => return Err("Retention tiers must have the form AGE:WINDOW"),
+        }
+    }
+    tiers.sort();
+    strategies::TieredStrategy::check_rules(&tiers)?;
+    Ok(tiers)
+}
+
+fn parse_duration_secs(s: &str) -> Result<u64, &'static str> {
+    let s = s.trim();
+    let (num, unit_secs) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 3600),
+        Some('d') => (&s[..s.len() - 1], 86400),
+        Some('w') => (&s[..s.len() - 1], 604800),
+        _ => (s, 1),
+    };
+    num.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit_secs))
+        .ok_or("Invalid retention duration")
 }
 
 pub mod strategies {
@@ -37,22 +105,104 @@ pub mod strategies {
         31536000, // windows >= 1 hour until 365 days
     ];
 
-    pub struct DefaultStrategy {
-        now: TimeStamp,
+    // Applies the first override whose pattern matches the window's metric,
+    // or the default tiers if none match. Patterns use the same syntax as `search`.
+    pub struct DefaultStrategy<'a> {
+        clock: &'a Clock,
+        tiers: TieredStrategy<'a>,
+        overrides: Vec<(String, TieredStrategy<'a>)>,
+    }
+
+    impl<'a> DefaultStrategy<'a> {
+        pub fn new(clock: &'a Clock) -> DefaultStrategy<'a> {
+            let rules = PARTITION_CUTOFFS
+                .iter()
+                .cloned()
+                .zip(ALIGNED_WINDOW_SIZES.iter().cloned())
+                .collect();
+            DefaultStrategy {
+                clock,
+                tiers: TieredStrategy::new(clock, rules),
+                overrides: Vec::new(),
+            }
+        }
+
+        // Replaces the default tiers for metrics without an override
+        pub fn with_tiers(mut self, rules: Vec<RetentionTier>) -> DefaultStrategy<'a> {
+            self.tiers = TieredStrategy::new(self.clock, rules);
+            self
+        }
+
+        pub fn with_overrides(
+            mut self,
+            overrides: Vec<(String, Vec<RetentionTier>)>,
+        ) -> DefaultStrategy<'a> {
+            let clock = self.clock;
+            self.overrides = overrides
+                .into_iter()
+                .map(|(pattern, rules)| (pattern, TieredStrategy::new(clock, rules)))
+                .collect();
+            self
+        }
     }
 
-    impl DefaultStrategy {
-        pub fn new(now: TimeStamp) -> DefaultStrategy {
-            DefaultStrategy { now }
+    impl<'a> DownsampleStrategy for DefaultStrategy<'a> {
+        fn get_action(&self, metric: &str, window: TimeWindow) -> DownsampleAction {
+            let tiers = self
+                .overrides
+                .iter()
+                .find(|&(pattern, _)| metric_match(metric, pattern))
+                .map(|(_, tiers)| tiers)
+                .unwrap_or(&self.tiers);
+            tiers.get_action(metric, window)
         }
+    }
 
-        fn find_aligned_size(seconds_since: u64) -> Option<u64> {
-            for p in 0..NUM_PARTITIONS {
-                if seconds_since < PARTITION_CUTOFFS[p] {
-                    return Some(ALIGNED_WINDOW_SIZES[p]);
+    // Each rule is (age_seconds, target_window_seconds): windows that started less than
+    // `age_seconds` ago are expanded to align with `target_window_seconds`.
+    // The first matching rule applies, and windows older than every rule are discarded.
+    // Ages are measured from the clock's current time for each window, so a long pass
+    // does not use a stale time.
+    pub struct TieredStrategy<'a> {
+        clock: &'a Clock,
+        rules: Vec<RetentionTier>,
+    }
+
+    impl<'a> TieredStrategy<'a> {
+        pub fn new(clock: &'a Clock, mut rules: Vec<RetentionTier>) -> TieredStrategy<'a> {
+            rules.sort();
+            if let Err(err) = TieredStrategy::check_rules(&rules) {
+                panic!("Invalid downsample rules {:?}: {}", rules, err);
+            }
+            TieredStrategy { clock, rules }
+        }
+
+        // Rules must be sorted by age, and older data must not get finer windows
+        pub fn check_rules(rules: &[RetentionTier]) -> Result<(), &'static str> {
+            if rules.is_empty() {
+                return Err("Expected at least one downsample rule");
+            }
+            if rules.iter().any(|&(_, size)| size == 0) {
+                return Err("Downsample window size must be > 0");
+            }
+            for i in 1..rules.len() {
+                let (prev_age, prev_size) = rules[i - 1];
+                let (age, size) = rules[i];
+                if age <= prev_age {
+                    return Err("Downsample rule ages must be distinct and sorted");
+                }
+                if size < prev_size {
+                    return Err("Older downsample rules must not have smaller windows");
                 }
             }
-            None
+            Ok(())
+        }
+
+        fn find_aligned_size(&self, seconds_since: u64) -> Option<u64> {
+            self.rules
+                .iter()
+                .find(|&&(age, _)| seconds_since < age)
+                .map(|&(_, size)| size)
         }
 
         fn expand_window(window: TimeWindow, aligned_size: u64) -> TimeWindow {
@@ -62,12 +212,12 @@ pub mod strategies {
         }
     }
 
-    impl DownsampleStrategy for DefaultStrategy {
-        fn get_action(&self, window: TimeWindow) -> DownsampleAction {
-            match self.now.checked_sub(window.start()) {
-                Some(seconds_since) => match DefaultStrategy::find_aligned_size(seconds_since) {
+    impl<'a> DownsampleStrategy for TieredStrategy<'a> {
+        fn get_action(&self, _metric: &str, window: TimeWindow) -> DownsampleAction {
+            match self.clock.now().checked_sub(window.start()) {
+                Some(seconds_since) => match self.find_aligned_size(seconds_since) {
                     Some(aligned_size) => {
-                        let new_window = DefaultStrategy::expand_window(window, aligned_size);
+                        let new_window = TieredStrategy::expand_window(window, aligned_size);
                         debug_assert!(new_window.start() <= window.start());
                         debug_assert!(new_window.end() >= window.end());
                         if new_window == window {
@@ -86,12 +236,14 @@ pub mod strategies {
     #[cfg(test)]
     mod tests {
         use super::*;
+        use caesium_core::time::clock::MockClock;
 
         #[test]
         fn it_ignores_window_starts_in_future() {
-            let s = DefaultStrategy::new(3600);
+            let clock = MockClock::new(3600);
+            let s = DefaultStrategy::new(&clock);
             let window = TimeWindow::new(3800, 4000);
-            let action = s.get_action(window);
+            let action = s.get_action("foo", window);
             assert_eq!(action, DownsampleAction::Ignore);
         }
 
@@ -99,9 +251,10 @@ pub mod strategies {
         fn it_ignores_window_in_partition_already_aligned() {
             for p in 0..NUM_PARTITIONS {
                 println!("Testing partition {}", p);
-                let s = DefaultStrategy::new(PARTITION_CUTOFFS[p] - 1);
+                let clock = MockClock::new(PARTITION_CUTOFFS[p] - 1);
+                let s = DefaultStrategy::new(&clock);
                 let window = TimeWindow::new(0, ALIGNED_WINDOW_SIZES[p]);
-                let action = s.get_action(window);
+                let action = s.get_action("foo", window);
                 assert_eq!(action, DownsampleAction::Ignore);
             }
         }
@@ -110,9 +263,10 @@ pub mod strategies {
         fn it_expands_window_in_partition_not_aligned() {
             for p in 1..NUM_PARTITIONS {
                 println!("Testing partition {}", p);
-                let s = DefaultStrategy::new(PARTITION_CUTOFFS[p] - 1);
+                let clock = MockClock::new(PARTITION_CUTOFFS[p] - 1);
+                let s = DefaultStrategy::new(&clock);
                 let window = TimeWindow::new(1, ALIGNED_WINDOW_SIZES[p] - 1);
-                let action = s.get_action(window);
+                let action = s.get_action("foo", window);
                 let expected_action =
                     DownsampleAction::ExpandWindow(TimeWindow::new(0, ALIGNED_WINDOW_SIZES[p]));
                 assert_eq!(action, expected_action);
@@ -122,20 +276,179 @@ pub mod strategies {
         #[test]
         fn it_discards_window_past_last_partition() {
             let last_cutoff = PARTITION_CUTOFFS[NUM_PARTITIONS - 1];
-            let s = DefaultStrategy::new(last_cutoff);
+            let clock = MockClock::new(last_cutoff);
+            let s = DefaultStrategy::new(&clock);
             let window = TimeWindow::new(0, 10);
-            let action = s.get_action(window);
+            let action = s.get_action("foo", window);
             assert_eq!(action, DownsampleAction::Discard);
         }
 
         #[test]
         fn it_expands_window_with_end_past_aligned_window() {
             let p = 3;
-            let s = DefaultStrategy::new(PARTITION_CUTOFFS[p] - 1);
+            let clock = MockClock::new(PARTITION_CUTOFFS[p] - 1);
+            let s = DefaultStrategy::new(&clock);
             let window = TimeWindow::new(1, ALIGNED_WINDOW_SIZES[p] * 2);
-            let action = s.get_action(window);
+            let action = s.get_action("foo", window);
             let expected_action = DownsampleAction::ExpandWindow(TimeWindow::new(0, window.end()));
             assert_eq!(action, expected_action);
         }
+
+        #[test]
+        fn it_applies_each_tier_by_window_age() {
+            let now = 10000;
+            let clock = MockClock::new(now);
+            let s = TieredStrategy::new(&clock, vec![(3600, 60), (60, 1), (7200, 600)]);
+
+            // Less than a minute old, so one-second windows are kept as-is
+            let window = TimeWindow::new(now - 30, now - 29);
+            assert_eq!(s.get_action("foo", window), DownsampleAction::Ignore);
+
+            // Less than an hour old, so expanded to one-minute windows
+            let window = TimeWindow::new(now - 100, now - 90);
+            let expected = TimeWindow::new(9900, 9960);
+            assert_eq!(
+                s.get_action("foo", window),
+                DownsampleAction::ExpandWindow(expected)
+            );
+
+            // Less than two hours old, so expanded to ten-minute windows
+            let window = TimeWindow::new(now - 5000, now - 4990);
+            let expected = TimeWindow::new(4800, 5400);
+            assert_eq!(
+                s.get_action("foo", window),
+                DownsampleAction::ExpandWindow(expected)
+            );
+        }
+
+        #[test]
+        fn it_discards_window_past_last_tier() {
+            let clock = MockClock::new(10000);
+            let s = TieredStrategy::new(&clock, vec![(60, 1), (3600, 60)]);
+            let window = TimeWindow::new(10000 - 3600, 10000 - 3590);
+            assert_eq!(s.get_action("foo", window), DownsampleAction::Discard);
+        }
+
+        #[test]
+        fn it_ignores_future_window_in_tiered_strategy() {
+            let clock = MockClock::new(100);
+            let s = TieredStrategy::new(&clock, vec![(60, 10)]);
+            let window = TimeWindow::new(105, 107);
+            assert_eq!(s.get_action("foo", window), DownsampleAction::Ignore);
+        }
+
+        #[test]
+        fn it_ages_windows_against_current_time() {
+            let clock = MockClock::new(10000);
+            let s = TieredStrategy::new(&clock, vec![(60, 1), (3600, 60)]);
+            let window = TimeWindow::new(9970, 9971);
+            assert_eq!(s.get_action("foo", window), DownsampleAction::Ignore);
+
+            clock.tick(60);
+            let expected = TimeWindow::new(9960, 10020);
+            assert_eq!(
+                s.get_action("foo", window),
+                DownsampleAction::ExpandWindow(expected)
+            );
+
+            clock.tick(3600);
+            assert_eq!(s.get_action("foo", window), DownsampleAction::Discard);
+        }
+
+        #[test]
+        fn it_applies_first_matching_retention_override() {
+            let now = 100 * 86400;
+            let clock = MockClock::new(now);
+            let s = DefaultStrategy::new(&clock)
+                .with_tiers(vec![(86400, 60)])
+                .with_overrides(vec![
+                    (
+                        "payment.*".to_string(),
+                        vec![(30 * 86400, 60), (90 * 86400, 3600)],
+                    ),
+                    ("payment.debug".to_string(), vec![(60, 1)]),
+                    ("debug.*".to_string(), vec![(3600, 10)]),
+                ]);
+            let window = TimeWindow::new(now - 2 * 86400, now - 2 * 86400 + 60);
+
+            // Two days old, so past the default tiers but kept by the payment override
+            assert_eq!(
+                s.get_action("payment.latency", window),
+                DownsampleAction::Ignore
+            );
+            assert_eq!(
+                s.get_action("payment.debug", window),
+                DownsampleAction::Ignore
+            );
+            assert_eq!(
+                s.get_action("api.latency", window),
+                DownsampleAction::Discard
+            );
+            assert_eq!(
+                s.get_action("debug.trace", window),
+                DownsampleAction::Discard
+            );
+
+            // Fifty days old, so expanded to hour windows by the second payment tier
+            let window = TimeWindow::new(now - 50 * 86400 + 60, now - 50 * 86400 + 120);
+            let expected = TimeWindow::new(now - 50 * 86400, now - 50 * 86400 + 3600);
+            assert_eq!(
+                s.get_action("payment.latency", window),
+                DownsampleAction::ExpandWindow(expected)
+            );
+        }
+
+        #[test]
+        fn it_matches_retention_overrides_by_tag() {
+            let clock = MockClock::new(10000);
+            let s = DefaultStrategy::new(&clock)
+                .with_tiers(vec![(60, 1)])
+                .with_overrides(vec![("*;env=prod".to_string(), vec![(3600, 1)])]);
+            let window = TimeWindow::new(9000, 9001);
+            assert_eq!(
+                s.get_action("latency;env=prod", window),
+                DownsampleAction::Ignore
+            );
+            assert_eq!(
+                s.get_action("latency;env=dev", window),
+                DownsampleAction::Discard
+            );
+        }
+
+        #[test]
+        fn it_checks_tiered_rules() {
+            assert!(TieredStrategy::check_rules(&[(60, 1), (3600, 60)]).is_ok());
+            assert!(TieredStrategy::check_rules(&[]).is_err());
+            assert!(TieredStrategy::check_rules(&[(60, 0)]).is_err());
+            assert!(TieredStrategy::check_rules(&[(60, 1), (60, 10)]).is_err());
+            assert!(TieredStrategy::check_rules(&[(60, 10), (3600, 1)]).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_retention_policy() {
+        let tiers = parse_retention_policy("90d:1h, 30d:1m").unwrap();
+        assert_eq!(tiers, vec![(30 * 86400, 60), (90 * 86400, 3600)]);
+    }
+
+    #[test]
+    fn it_parses_retention_durations() {
+        let tiers = parse_retention_policy("300:10,2h:30s,2w:1d").unwrap();
+        assert_eq!(tiers, vec![(300, 10), (7200, 30), (1209600, 86400)]);
+    }
+
+    #[test]
+    fn it_rejects_invalid_retention_policy() {
+        assert!(parse_retention_policy("").is_err());
+        assert!(parse_retention_policy("30d").is_err());
+        assert!(parse_retention_policy("30d:1x").is_err());
+        assert!(parse_retention_policy("30d:m").is_err());
+        assert!(parse_retention_policy("30d:0s").is_err());
+        assert!(parse_retention_policy("30d:1h,90d:1m").is_err());
     }
 }
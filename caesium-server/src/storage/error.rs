@@ -1,12 +1,18 @@
 use caesium_core::encode::EncodableError;
+use caesium_core::time::window::TimeWindow;
 use rocksdb;
+use std::io;
 
 #[derive(Debug)]
 pub enum StorageError {
     EncodableError(EncodableError),
     DatabaseError(rocksdb::Error),
     InvalidMetricName,
+    MetricNameTooLong(usize),
+    OverlappingWindows(TimeWindow),
     InternalError(&'static str),
+    IOError(io::Error),
+    RestoreError(String),
 }
 
 impl From<rocksdb::Error> for StorageError {
@@ -15,6 +21,12 @@ impl From<rocksdb::Error> for StorageError {
     }
 }
 
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> StorageError {
+        StorageError::IOError(err)
+    }
+}
+
 impl From<EncodableError> for StorageError {
     fn from(err: EncodableError) -> StorageError {
         StorageError::EncodableError(err)
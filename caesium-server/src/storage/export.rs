@@ -0,0 +1,452 @@
+use base64;
+use caesium_core::encode::{Decodable, Encodable, EncodableError};
+use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::timestamp::TimeStamp;
+use caesium_core::time::window::TimeWindow;
+use json::json_string;
+use std::io;
+use std::io::{BufRead, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+use storage::datasource::DataSource;
+use storage::error::StorageError;
+use storage::store::MetricStore;
+
+const CSV_HEADER: &str = "metric,start,end,sketch_b64";
+
+// Text formats for exported windows. Each window is written as one record
+// with the metric name, window bounds, and the base64-encoded sketch.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExportFormat {
+    Ndjson,
+    Csv,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    IOError(io::Error),
+    StorageError(StorageError),
+    EncodableError(EncodableError),
+    Base64Error(base64::DecodeError),
+    // Line number (starting from one) and a description of the problem
+    InvalidRecord(usize, &'static str),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> ExportError {
+        ExportError::IOError(err)
+    }
+}
+
+impl From<StorageError> for ExportError {
+    fn from(err: StorageError) -> ExportError {
+        ExportError::StorageError(err)
+    }
+}
+
+impl From<EncodableError> for ExportError {
+    fn from(err: EncodableError) -> ExportError {
+        ExportError::EncodableError(err)
+    }
+}
+
+impl From<base64::DecodeError> for ExportError {
+    fn from(err: base64::DecodeError) -> ExportError {
+        ExportError::Base64Error(err)
+    }
+}
+
+// Writes every window for metrics matching `pattern` (which may contain wildcards).
+// Returns the number of windows written.
+pub fn export_metrics<W: Write>(
+    source: &DataSource,
+    pattern: &str,
+    format: ExportFormat,
+    writer: &mut W,
+) -> Result<usize, ExportError> {
+    if format == ExportFormat::Csv {
+        writeln!(writer, "{}", CSV_HEADER)?;
+    }
+    let mut count = 0;
+    for metric in source.search(pattern.to_string())? {
+        for row in source.fetch(metric.clone(), None, None)? {
+            let mut sketch_bytes = Vec::new();
+            row.sketch.encode(&mut sketch_bytes)?;
+            let sketch_b64 = base64::encode(&sketch_bytes);
+            match format {
+                ExportFormat::Ndjson => writeln!(
+                    writer,
+                    "{{\"metric\":{},\"start\":{},\"end\":{},\"sketch_b64\":\"{}\"}}",
+                    json_string(&metric),
+                    row.window.start(),
+                    row.window.end(),
+                    sketch_b64
+                )?,
+                ExportFormat::Csv => writeln!(
+                    writer,
+                    "{},{},{},{}",
+                    csv_field(&metric),
+                    row.window.start(),
+                    row.window.end(),
+                    sketch_b64
+                )?,
+            }
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// Inserts every window read from an export. Windows that already exist
+// in the store are merged with the imported sketch.
+// Returns the number of windows read.
+pub fn import_metrics<R: BufRead>(
+    db: &MetricStore,
+    format: ExportFormat,
+    reader: R,
+) -> Result<usize, ExportError> {
+    let mut count = 0;
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_num = idx + 1;
+        if line.trim().is_empty() || (format == ExportFormat::Csv && line == CSV_HEADER) {
+            continue;
+        }
+        let record = match format {
+            ExportFormat::Ndjson => parse_ndjson_record(&line),
+            ExportFormat::Csv => parse_csv_record(&line),
+        }
+        .ok_or(ExportError::InvalidRecord(
+            line_num,
+            "Could not parse record",
+        ))?;
+        let window = TimeWindow::try_new(record.start, record.end).map_err(|_| {
+            ExportError::InvalidRecord(line_num, "Window start must be before window end")
+        })?;
+        let sketch_bytes = base64::decode(&record.sketch_b64)?;
+        let sketch = WritableSketch::decode(&mut &sketch_bytes[..])?;
+        db.insert(&record.metric, window, sketch)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[derive(Debug, PartialEq)]
+struct ExportRecord {
+    metric: String,
+    start: TimeStamp,
+    end: TimeStamp,
+    sketch_b64: String,
+}
+
+fn parse_ndjson_record(line: &str) -> Option<ExportRecord> {
+    let mut metric = None;
+    let mut start = None;
+    let mut end = None;
+    let mut sketch_b64 = None;
+    for (key, value) in parse_json_object(line)? {
+        match (key.as_str(), value) {
+            ("metric", JsonValue::Str(s)) => metric = Some(s),
+            ("start", JsonValue::Num(n)) => start = Some(n),
+            ("end", JsonValue::Num(n)) => end = Some(n),
+            ("sketch_b64", JsonValue::Str(s)) => sketch_b64 = Some(s),
+            _ => return None,
+        }
+    }
+    Some(ExportRecord {
+        metric: metric?,
+        start: start?,
+        end: end?,
+        sketch_b64: sketch_b64?,
+    })
+}
+
+fn parse_csv_record(line: &str) -> Option<ExportRecord> {
+    // Only the metric name may be quoted, and base64 never contains commas,
+    // so the last three fields can be split from the right.
+    let mut fields = line.rsplitn(4, ',');
+    let sketch_b64 = fields.next()?.to_string();
+    let end = fields.next()?.parse::<TimeStamp>().ok()?;
+    let start = fields.next()?.parse::<TimeStamp>().ok()?;
+    let metric = parse_csv_field(fields.next()?)?;
+    Some(ExportRecord {
+        metric,
+        start,
+        end,
+        sketch_b64,
+    })
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn parse_csv_field(s: &str) -> Option<String> {
+    if s.starts_with('"') {
+        if s.len() < 2 || !s.ends_with('"') {
+            return None;
+        }
+        Some(s[1..s.len() - 1].replace("\"\"", "\""))
+    } else {
+        Some(s.to_string())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum JsonValue {
+    Str(String),
+    Num(u64),
+}
+
+// Parses a flat JSON object whose values are strings or non-negative integers,
+// which is all the export format uses.
+fn parse_json_object(s: &str) -> Option<Vec<(String, JsonValue)>> {
+    let mut chars = s.trim().chars().peekable();
+    let mut fields = Vec::new();
+    expect_char(&mut chars, '{')?;
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = parse_json_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            expect_char(&mut chars, ':')?;
+            skip_whitespace(&mut chars);
+            let value = match chars.peek() {
+                Some(&'"') => JsonValue::Str(parse_json_string(&mut chars)?),
+                Some(c) if c.is_ascii_digit() => JsonValue::Num(parse_json_number(&mut chars)?),
+                _ => return None,
+            };
+            fields.push((key, value));
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return None,
+            }
+        }
+    }
+    match chars.next() {
+        None => Some(fields),
+        Some(_) => None,
+    }
+}
+
+fn parse_json_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    expect_char(chars, '"')?;
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                '"' => out.push('"'),
+                '\\' => out.push('\\'),
+                '/' => out.push('/'),
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).ok()?;
+                    out.push(::std::char::from_u32(code)?);
+                }
+                _ => return None,
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+fn parse_json_number(chars: &mut Peekable<Chars>) -> Option<u64> {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+    digits.parse::<u64>().ok()
+}
+
+fn expect_char(chars: &mut Peekable<Chars>, expected: char) -> Option<()> {
+    match chars.next() {
+        Some(c) if c == expected => Some(()),
+        _ => None,
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while let Some(&c) = chars.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use storage::datasource::DataRow;
+    use uuid::Uuid;
+
+    #[test]
+    fn it_round_trips_ndjson() {
+        assert_round_trip(ExportFormat::Ndjson);
+    }
+
+    #[test]
+    fn it_round_trips_csv() {
+        assert_round_trip(ExportFormat::Csv);
+    }
+
+    #[test]
+    fn it_exports_ndjson_records() {
+        with_test_db(|db| {
+            db.insert("foo", TimeWindow::new(0, 30), WritableSketch::new())
+                .expect("Could not insert");
+            let mut out = Vec::new();
+            export_metrics(db, "*", ExportFormat::Ndjson, &mut out).expect("Could not export");
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.starts_with("{\"metric\":\"foo\",\"start\":0,\"end\":30,\"sketch_b64\":\""));
+            assert!(out.ends_with("\"}\n"));
+        })
+    }
+
+    #[test]
+    fn it_filters_exported_metrics_by_pattern() {
+        with_test_db(|db| {
+            for metric in ["foo.a", "foo.b", "bar"].iter() {
+                db.insert(metric, TimeWindow::new(0, 30), WritableSketch::new())
+                    .expect("Could not insert");
+            }
+            let mut out = Vec::new();
+            let count =
+                export_metrics(db, "foo.*", ExportFormat::Csv, &mut out).expect("Could not export");
+            assert_eq!(count, 2);
+            let out = String::from_utf8(out).unwrap();
+            let metrics: Vec<&str> = out
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').next().unwrap())
+                .collect();
+            assert_eq!(metrics, vec!["foo.a", "foo.b"]);
+        })
+    }
+
+    #[test]
+    fn it_rejects_invalid_records() {
+        with_test_db(|db| {
+            let input = "{\"metric\":\"foo\",\"start\":0,\"end\":30,\"sketch_b64\":\"\"}\nnope\n";
+            match import_metrics(db, ExportFormat::Ndjson, input.as_bytes()) {
+                Err(ExportError::EncodableError(_)) => {}
+                other => panic!("Unexpected result {:?}", other),
+            }
+            match import_metrics(db, ExportFormat::Ndjson, "nope\n".as_bytes()) {
+                Err(ExportError::InvalidRecord(1, _)) => {}
+                other => panic!("Unexpected result {:?}", other),
+            }
+            let input = "metric,start,end,sketch_b64\nfoo,30,0,AAAA\n";
+            match import_metrics(db, ExportFormat::Csv, input.as_bytes()) {
+                Err(ExportError::InvalidRecord(2, _)) => {}
+                other => panic!("Unexpected result {:?}", other),
+            }
+        })
+    }
+
+    #[test]
+    fn it_parses_json_objects() {
+        assert_eq!(
+            parse_json_object(" { \"a\" : \"x\\\"y\\u00e9\" , \"b\":12 } "),
+            Some(vec![
+                ("a".to_string(), JsonValue::Str("x\"y\u{e9}".to_string())),
+                ("b".to_string(), JsonValue::Num(12)),
+            ])
+        );
+        assert_eq!(parse_json_object("{}"), Some(vec![]));
+        assert_eq!(parse_json_object("{\"a\":-1}"), None);
+        assert_eq!(parse_json_object("{\"a\":1} x"), None);
+        assert_eq!(parse_json_object("{\"a\":\"x}"), None);
+    }
+
+    #[test]
+    fn it_quotes_csv_fields() {
+        assert_eq!(csv_field("foo;region=us"), "foo;region=us");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+        assert_eq!(
+            parse_csv_field(&csv_field("a,\"b\"")),
+            Some("a,\"b\"".to_string())
+        );
+        assert_eq!(
+            parse_csv_record("\"a,b\",0,30,AAAA"),
+            Some(ExportRecord {
+                metric: "a,b".to_string(),
+                start: 0,
+                end: 30,
+                sketch_b64: "AAAA".to_string(),
+            })
+        );
+    }
+
+    fn assert_round_trip(format: ExportFormat) {
+        with_test_db(|src| {
+            let rows = [
+                ("foo", TimeWindow::new(0, 30), vec![1, 2, 3]),
+                ("foo", TimeWindow::new(30, 60), vec![4, 5]),
+                ("bar;region=us", TimeWindow::new(0, 30), (0..1000).collect()),
+            ];
+            for (metric, window, values) in rows.iter() {
+                src.insert(metric, *window, WritableSketch::from_slice(values))
+                    .expect("Could not insert");
+            }
+            let mut out = Vec::new();
+            let exported = export_metrics(src, "*", format, &mut out).expect("Could not export");
+            assert_eq!(exported, 3);
+
+            with_test_db(|dst| {
+                let imported = import_metrics(dst, format, &out[..]).expect("Could not import");
+                assert_eq!(imported, 3);
+                for metric in ["foo", "bar;region=us"].iter() {
+                    assert_eq!(
+                        encode_rows(src, metric),
+                        encode_rows(dst, metric),
+                        "Mismatched rows for {}",
+                        metric
+                    );
+                }
+            })
+        })
+    }
+
+    fn encode_rows(db: &MetricStore, metric: &str) -> Vec<(TimeWindow, Vec<u8>)> {
+        db.fetch(metric.to_string(), None, None)
+            .expect("Could not fetch")
+            .map(|DataRow { window, sketch }| {
+                let mut bytes = Vec::new();
+                sketch.encode(&mut bytes).expect("Could not encode sketch");
+                (window, bytes)
+            })
+            .collect()
+    }
+
+    fn with_test_db<T>(test: T)
+    where
+        T: FnOnce(&MetricStore),
+    {
+        let db_path = env::temp_dir().join(format!("caesium_export_test_{}", Uuid::new_v4()));
+        let db_path = db_path.to_str().expect("Could not convert path");
+        let db = MetricStore::open(db_path).expect("Could not open db");
+        test(&db);
+        drop(db);
+        fs::remove_dir_all(db_path).expect("Could not delete DB directory");
+    }
+}
@@ -1,13 +1,16 @@
 use caesium_core::time::timestamp::TimeStamp;
 use std::collections::{BTreeSet, HashMap};
+use std::thread;
+use std::time::Duration;
 use storage::datasource::{DataRow, DataSource};
 use storage::error::StorageError;
-use storage::wildcard::wildcard_match;
+use storage::wildcard::metric_match;
 
 pub struct MockDataSource {
     data: HashMap<String, Vec<DataRow>>,
     metrics: BTreeSet<String>,
     empty: Vec<DataRow>,
+    row_delay: Option<Duration>,
 }
 
 impl MockDataSource {
@@ -16,15 +19,18 @@ impl MockDataSource {
             data: HashMap::new(),
             metrics: BTreeSet::new(),
             empty: Vec::new(),
+            row_delay: None,
         }
     }
 
+    // Simulate a slow data source by sleeping before returning each fetched row
+    pub fn set_row_delay(&mut self, delay: Duration) {
+        self.row_delay = Some(delay);
+    }
+
     pub fn add_row(&mut self, metric: &str, row: DataRow) {
         self.metrics.insert(metric.to_string());
-        let rows = self
-            .data
-            .entry(metric.to_string())
-            .or_insert_with(|| Vec::new());
+        let rows = self.data.entry(metric.to_string()).or_default();
         rows.push(row);
     }
 }
@@ -37,9 +43,13 @@ impl DataSource for MockDataSource {
         end: Option<TimeStamp>,
     ) -> Result<Box<Iterator<Item = DataRow> + 'a>, StorageError> {
         let start_ts = start.unwrap_or(0);
-        let end_ts = end.unwrap_or(TimeStamp::max_value());
+        let end_ts = end.unwrap_or(TimeStamp::MAX);
         let rows = self.data.get(&metric).unwrap_or(&self.empty);
+        let row_delay = self.row_delay;
         let iter = rows.iter().filter_map(move |r| {
+            if let Some(d) = row_delay {
+                thread::sleep(d);
+            }
             let w = r.window;
             if w.start() >= start_ts && w.end() <= end_ts {
                 Some(r.clone())
@@ -55,7 +65,7 @@ impl DataSource for MockDataSource {
         pattern: String,
     ) -> Result<Box<Iterator<Item = String> + 'a>, StorageError> {
         let iter = self.metrics.iter().filter_map(move |m| {
-            if wildcard_match(m, &pattern) {
+            if metric_match(m, &pattern) {
                 Some(m.to_string())
             } else {
                 None
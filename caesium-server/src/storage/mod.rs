@@ -1,8 +1,10 @@
 pub mod datasource;
 pub mod downsample;
 pub mod error;
+pub mod export;
 mod key;
 pub mod mock;
+mod restore;
 pub mod store;
 mod value;
 pub mod wildcard;
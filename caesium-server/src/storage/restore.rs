@@ -0,0 +1,46 @@
+use libc::{c_char, c_void};
+use librocksdb_sys as ffi;
+use std::ffi::{CStr, CString};
+use std::ptr;
+use storage::error::StorageError;
+
+// rocksdb 0.11's `BackupEngine` can create backups but not restore them,
+// so restoring calls RocksDB's C API directly.
+pub fn restore_from_latest_backup(backup_dir: &str, db_dir: &str) -> Result<(), StorageError> {
+    let backup_dir = to_cstring(backup_dir)?;
+    let db_dir = to_cstring(db_dir)?;
+    unsafe {
+        let mut err: *mut c_char = ptr::null_mut();
+        let opts = ffi::rocksdb_options_create();
+        let engine = ffi::rocksdb_backup_engine_open(opts, backup_dir.as_ptr(), &mut err);
+        ffi::rocksdb_options_destroy(opts);
+        check_error(err)?;
+
+        // The write-ahead log lives in the database directory, as it does for `MetricStore::open`
+        let restore_opts = ffi::rocksdb_restore_options_create();
+        ffi::rocksdb_backup_engine_restore_db_from_latest_backup(
+            engine,
+            db_dir.as_ptr(),
+            db_dir.as_ptr(),
+            restore_opts,
+            &mut err,
+        );
+        ffi::rocksdb_restore_options_destroy(restore_opts);
+        ffi::rocksdb_backup_engine_close(engine);
+        check_error(err)
+    }
+}
+
+fn to_cstring(path: &str) -> Result<CString, StorageError> {
+    CString::new(path).map_err(|_| StorageError::InternalError("Path contains a null byte"))
+}
+
+// Takes ownership of an error message allocated by RocksDB
+unsafe fn check_error(err: *mut c_char) -> Result<(), StorageError> {
+    if err.is_null() {
+        return Ok(());
+    }
+    let message = CStr::from_ptr(err).to_string_lossy().into_owned();
+    ffi::rocksdb_free(err as *mut c_void);
+    Err(StorageError::RestoreError(message))
+}
@@ -1,36 +1,113 @@
-use caesium_core::encode::Decodable;
+use caesium_core::encode::{Decodable, Encodable, EncodableError};
+use caesium_core::metric::canonicalize;
+use caesium_core::protocol::messages::{InsertMessage, MetricKind};
 use caesium_core::quantile::writable::WritableSketch;
 use caesium_core::time::timestamp::TimeStamp;
 use caesium_core::time::window::TimeWindow;
-use regex::Regex;
+use otel::Span;
 use rocksdb;
-use std::cmp::Ordering;
+use rocksdb::backup::{BackupEngine, BackupEngineOptions};
+use std::cmp::{max, Ordering};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::iter;
+use std::mem;
 use std::str;
+use std::sync::{Mutex, RwLock, RwLockWriteGuard};
 use storage::datasource::{DataRow, DataSource};
-use storage::downsample::{DownsampleAction, DownsampleStrategy};
+use storage::downsample::{DownsampleAction, DownsampleReport, DownsampleStrategy};
 use storage::error::StorageError;
 use storage::key::StorageKey;
+use storage::restore::restore_from_latest_backup;
 use storage::value::StorageValue;
-use storage::wildcard::{exact_prefix, wildcard_match};
+pub use storage::value::ValueCompression;
+use storage::wildcard::{metric_match, metric_pattern_prefix};
 
-const WINDOWS_CF_NAME: &'static str = "windows";
-const METRICS_CF_NAME: &'static str = "metrics";
+const WINDOWS_CF_NAME: &str = "windows";
+const METRICS_CF_NAME: &str = "metrics";
+const METADATA_CF_NAME: &str = "metadata";
+
+// Last key processed by an incomplete incremental downsample pass
+const DOWNSAMPLE_BOOKMARK_KEY: &[u8] = b"downsample_bookmark";
+
+// Snapshots start with a magic string, a format version, and the number of entries,
+// followed by each encoded (StorageKey, StorageValue) pair.
+const SNAPSHOT_MAGIC: &[u8] = b"CSNAP";
+const SNAPSHOT_VERSION: u8 = 2;
+const SNAPSHOT_IMPORT_BATCH_SIZE: usize = 1024;
+
+const DEFAULT_MAX_NAME_LEN: usize = 256;
+
+pub struct MetricStoreOptions {
+    // Longest metric name, in bytes, accepted for inserts and queries
+    pub max_name_len: usize,
+
+    // Compression for sketches written to the store.
+    // Values written with any other compression can still be read.
+    pub compression: ValueCompression,
+
+    // Collect RocksDB internal statistics, like block cache hits and compaction bytes.
+    // Costs a few percent of throughput, so it is off by default.
+    pub enable_statistics: bool,
+}
+
+impl Default for MetricStoreOptions {
+    fn default() -> MetricStoreOptions {
+        MetricStoreOptions {
+            max_name_len: DEFAULT_MAX_NAME_LEN,
+            compression: ValueCompression::default(),
+            enable_statistics: false,
+        }
+    }
+}
 
 pub struct MetricStore {
     raw_db: rocksdb::DB,
+    max_name_len: usize,
+    compression: ValueCompression,
+
+    // Names in the metrics column family, so fetches for unknown metrics
+    // can return without reading from RocksDB. Writes that add metrics hold the read lock,
+    // or the write lock for new names, while writing, and writes that remove metrics hold
+    // the write lock, so a metric with windows is never missing from the cache.
+    known_metrics: RwLock<HashSet<String>>,
+
+    // Options the database was opened with. RocksDB collects statistics through these,
+    // so they're kept for the lifetime of the store.
+    db_opts: Mutex<rocksdb::Options>,
 }
 
 impl MetricStore {
     pub fn open(path: &str) -> Result<MetricStore, StorageError> {
+        MetricStore::open_with_options(path, MetricStoreOptions::default())
+    }
+
+    pub fn open_with_options(
+        path: &str,
+        options: MetricStoreOptions,
+    ) -> Result<MetricStore, StorageError> {
         let column_families = vec![
             MetricStore::windows_cf_desc(),
             MetricStore::metrics_cf_desc(),
+            MetricStore::metadata_cf_desc(),
         ];
         let mut opts = rocksdb::Options::default();
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
+        if options.enable_statistics {
+            opts.enable_statistics();
+        }
         let raw_db = rocksdb::DB::open_cf_descriptors(&opts, path, column_families)?;
-        Ok(MetricStore { raw_db })
+        let store = MetricStore {
+            raw_db,
+            max_name_len: options.max_name_len,
+            compression: options.compression,
+            known_metrics: RwLock::new(HashSet::new()),
+            db_opts: Mutex::new(opts),
+        };
+        store.warm_metric_cache()?;
+        Ok(store)
     }
 
     pub fn destroy(path: &str) -> Result<(), StorageError> {
@@ -45,17 +122,41 @@ impl MetricStore {
         window: TimeWindow,
         sketch: WritableSketch,
     ) -> Result<(), StorageError> {
-        MetricStore::validate_metric_name(metric)?;
-        let key = StorageKey::as_bytes(metric, window.start())?;
-        let val = StorageValue::as_bytes(window, sketch)?;
-        debug!(
-            "Inserting key for metric {} and window {:?}",
-            metric, window
-        );
+        let mut span = Span::start("caesium.storage.insert");
+        span.set_str("metric", metric);
         let mut batch = rocksdb::WriteBatch::default();
-        batch.put_cf(self.metrics_cf()?, metric.as_bytes(), &[1u8; 0])?;
-        batch.merge_cf(self.windows_cf()?, &key, &val)?;
-        self.raw_db.write(batch)?;
+        let metric =
+            self.add_insert_to_batch(&mut batch, metric, MetricKind::Timer, window, sketch)?;
+        self.write_adding_metrics(batch, vec![metric])
+    }
+
+    pub fn insert_batch(&self, inserts: Vec<InsertMessage>) -> Result<(), StorageError> {
+        let mut span = Span::start("caesium.storage.insert");
+        span.set_i64("insert.count", inserts.len() as i64);
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut metrics = Vec::with_capacity(inserts.len());
+        for msg in inserts {
+            let metric = self.add_insert_to_batch(
+                &mut batch,
+                &msg.metric,
+                msg.kind,
+                msg.window,
+                msg.sketch,
+            )?;
+            metrics.push(metric);
+        }
+        self.write_adding_metrics(batch, metrics)
+    }
+
+    // Reloads the set of known metrics from the metrics column family.
+    // Call this after the database is modified other than through this store.
+    pub fn warm_metric_cache(&self) -> Result<(), StorageError> {
+        let metrics: HashSet<String> = self.list_metrics()?.collect();
+        let mut known = self
+            .known_metrics
+            .write()
+            .expect("Could not acquire write lock on metric cache");
+        *known = metrics;
         Ok(())
     }
 
@@ -68,39 +169,583 @@ impl MetricStore {
         let kv_iter = snapshot.iterator_cf(cf, rocksdb::IteratorMode::Start)?;
         for (key_bytes, val_bytes) in kv_iter {
             let key = StorageKey::decode(&mut &key_bytes[..])?;
-            let val = StorageValue::decode(&mut &val_bytes[..])?;
-            match strategy.get_action(val.window()) {
-                DownsampleAction::Ignore => {}
-                DownsampleAction::Discard => {
-                    debug!("Deleting key during downsampling: {:?}", key);
-                    let key_bytes = key.to_bytes()?;
-                    self.raw_db.delete_cf(cf, &key_bytes)?;
+            let val = StorageValue::from_bytes(&val_bytes)?;
+            self.downsample_key(cf, strategy, key, val)?;
+        }
+        Ok(())
+    }
+
+    // Counts the actions `downsample` would take for each window, without changing anything.
+    // Like `downsample`, this reads from a snapshot, so expanded windows aren't revisited.
+    pub fn downsample_dry_run<T>(&self, strategy: &T) -> Result<DownsampleReport, StorageError>
+    where
+        T: DownsampleStrategy,
+    {
+        let snapshot = self.raw_db.snapshot();
+        let cf = self.windows_cf()?;
+        let kv_iter = snapshot.iterator_cf(cf, rocksdb::IteratorMode::Start)?;
+        let mut report = DownsampleReport::new();
+        for (key_bytes, val_bytes) in kv_iter {
+            let key = StorageKey::decode(&mut &key_bytes[..])?;
+            let val = StorageValue::from_bytes(&val_bytes)?;
+            let action = strategy.get_action(key.metric(), val.window());
+            report.record(key.metric(), &action);
+        }
+        Ok(report)
+    }
+
+    // Downsamples at most `max_keys` windows, resuming after the last key processed
+    // by the previous call. Returns true once the pass has processed the last key,
+    // in which case the bookmark is cleared and the next call starts a new pass.
+    pub fn downsample_incremental<T>(
+        &self,
+        strategy: &T,
+        max_keys: usize,
+    ) -> Result<bool, StorageError>
+    where
+        T: DownsampleStrategy,
+    {
+        assert!(max_keys > 0);
+        let windows_cf = self.windows_cf()?;
+        let metadata_cf = self.metadata_cf()?;
+        let bookmark_bytes: Option<Vec<u8>> = self
+            .raw_db
+            .get_cf(metadata_cf, DOWNSAMPLE_BOOKMARK_KEY)?
+            .map(|bytes| bytes.to_vec());
+        let (bookmark, kv_iter_mode) = match bookmark_bytes {
+            Some(ref bytes) => (
+                Some(StorageKey::decode(&mut &bytes[..])?),
+                rocksdb::IteratorMode::From(bytes, rocksdb::Direction::Forward),
+            ),
+            None => (None, rocksdb::IteratorMode::Start),
+        };
+
+        let snapshot = self.raw_db.snapshot();
+        let mut last_key_bytes: Option<Vec<u8>> = None;
+        let mut num_processed = 0;
+        let mut reached_end = true;
+        for (key_bytes, val_bytes) in snapshot.iterator_cf(windows_cf, kv_iter_mode)? {
+            let key = StorageKey::decode(&mut &key_bytes[..])?;
+            // Expanded windows are written at or before the key being processed,
+            // so skipping keys up to the bookmark never skips unprocessed windows.
+            if let Some(ref b) = bookmark {
+                if key <= *b {
+                    continue;
+                }
+            }
+            if num_processed == max_keys {
+                reached_end = false;
+                break;
+            }
+            let val = StorageValue::from_bytes(&val_bytes)?;
+            self.downsample_key(windows_cf, strategy, key, val)?;
+            last_key_bytes = Some(key_bytes.to_vec());
+            num_processed += 1;
+        }
+
+        if reached_end {
+            debug!("Finished incremental downsample pass");
+            self.raw_db
+                .delete_cf(metadata_cf, DOWNSAMPLE_BOOKMARK_KEY)?;
+            Ok(true)
+        } else {
+            if let Some(bytes) = last_key_bytes {
+                self.raw_db
+                    .put_cf(metadata_cf, DOWNSAMPLE_BOOKMARK_KEY, &bytes)?;
+            }
+            debug!(
+                "Paused incremental downsample pass after {} keys",
+                num_processed
+            );
+            Ok(false)
+        }
+    }
+
+    fn downsample_key<T>(
+        &self,
+        cf: rocksdb::ColumnFamily,
+        strategy: &T,
+        key: StorageKey,
+        val: StorageValue,
+    ) -> Result<(), StorageError>
+    where
+        T: DownsampleStrategy,
+    {
+        match strategy.get_action(key.metric(), val.window()) {
+            DownsampleAction::Ignore => {}
+            DownsampleAction::Discard => {
+                debug!("Deleting key during downsampling: {:?}", key);
+                let key_bytes = key.to_bytes()?;
+                self.raw_db.delete_cf(cf, &key_bytes)?;
+            }
+            DownsampleAction::ExpandWindow(new_window) => {
+                debug!(
+                    "Expanding window for key {:?} during downsampling: \
+                     old_window={:?}, new_window={:?}",
+                    key,
+                    val.window(),
+                    new_window
+                );
+                let mut batch = rocksdb::WriteBatch::default();
+                let old_key_bytes = key.to_bytes()?;
+                batch.delete_cf(cf, &old_key_bytes)?;
+
+                let new_key = key.with_window_start(new_window.start());
+                let key_bytes = new_key.to_bytes()?;
+                let new_val = val.with_window(new_window);
+                let val_bytes = new_val.to_bytes(self.compression)?;
+                batch.merge_cf(cf, &key_bytes, &val_bytes)?;
+
+                self.raw_db.write(batch)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Deletes every window starting before `cutoff` and returns the number deleted.
+    // Metrics left without any windows are removed from the metrics column family,
+    // so they no longer appear in search results.
+    pub fn purge_before(&self, cutoff: TimeStamp) -> Result<usize, StorageError> {
+        let snapshot = self.raw_db.snapshot();
+        let windows_cf = self.windows_cf()?;
+        let kv_iter = snapshot.iterator_cf(windows_cf, rocksdb::IteratorMode::Start)?;
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut num_deleted = 0;
+        let mut emptied_metrics = Vec::new();
+
+        // Keys are sorted by metric, so we only need to track the current metric
+        // to know whether any of its windows survive the purge.
+        let mut current: Option<(String, bool)> = None;
+        for (key_bytes, _) in kv_iter {
+            let key = StorageKey::decode(&mut &key_bytes[..])?;
+            let is_new_metric = match current {
+                Some((ref metric, _)) => metric != key.metric(),
+                None => true,
+            };
+            if is_new_metric {
+                if let Some((metric, has_windows)) = current.take() {
+                    if !has_windows {
+                        emptied_metrics.push(metric);
+                    }
+                }
+                current = Some((key.metric().to_string(), false));
+            }
+            if key.window_start() < cutoff {
+                debug!("Deleting key during purge: {:?}", key);
+                batch.delete_cf(windows_cf, &key_bytes)?;
+                num_deleted += 1;
+            } else if let Some((_, ref mut has_windows)) = current {
+                *has_windows = true;
+            }
+        }
+        if let Some((metric, has_windows)) = current {
+            if !has_windows {
+                emptied_metrics.push(metric);
+            }
+        }
+        self.raw_db.write(batch)?;
+
+        // Windows may have been inserted since the snapshot, so check again before
+        // removing each metric. Holding the cache lock blocks inserts during the check.
+        let mut known = self.lock_metric_cache();
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut removed_metrics = Vec::new();
+        for metric in emptied_metrics {
+            if !self.has_windows(&metric)? {
+                batch.delete_cf(self.metrics_cf()?, metric.as_bytes())?;
+                removed_metrics.push(metric);
+            }
+        }
+        self.raw_db.write(batch)?;
+        for metric in removed_metrics.iter() {
+            known.remove(metric);
+        }
+        Ok(num_deleted)
+    }
+
+    // Deletes all windows for a metric and removes it from the metrics column family.
+    // Both deletes are applied in one batch, so readers never see a partial delete.
+    pub fn delete_metric(&self, metric: &str) -> Result<(), StorageError> {
+        let metric = self.validate_metric_name(metric)?;
+        // Block inserts until the cache is updated, so none land between the scan and the delete
+        let mut known = self.lock_metric_cache();
+        let snapshot = self.raw_db.snapshot();
+        let windows_cf = self.windows_cf()?;
+        let start_key = StorageKey::as_bytes(&metric, 0)?;
+        let kv_iter_mode = rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward);
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key_bytes, _) in snapshot.iterator_cf(windows_cf, kv_iter_mode)? {
+            let key = StorageKey::decode(&mut &key_bytes[..])?;
+            if key.metric() != metric {
+                break;
+            }
+            debug!("Deleting key for metric {}: {:?}", metric, key);
+            batch.delete_cf(windows_cf, &key_bytes)?;
+        }
+        batch.delete_cf(self.metrics_cf()?, metric.as_bytes())?;
+        self.raw_db.write(batch)?;
+        known.remove(&metric);
+        Ok(())
+    }
+
+    // Moves every window of `from` to `to`. Fails with the first overlapping window
+    // if `to` already has windows overlapping those of `from`.
+    pub fn rename_metric(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.move_metric(from, to, false)
+    }
+
+    // Like `rename_metric`, but merges windows of `from` into existing windows of `to`
+    // that start at the same time, and keeps any other overlapping windows side by side
+    pub fn rename_metric_merging(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.move_metric(from, to, true)
+    }
+
+    // Re-inserts each window through the merge operator and deletes the old keys,
+    // all in one batch, so readers never see a partial rename.
+    fn move_metric(&self, from: &str, to: &str, allow_overlap: bool) -> Result<(), StorageError> {
+        let from = self.validate_metric_name(from)?;
+        let to = self.validate_metric_name(to)?;
+        if from == to {
+            return Ok(());
+        }
+        // Block inserts until the cache is updated, so windows inserted for `from`
+        // after the scan aren't left behind under a name that was unregistered
+        let mut known = self.lock_metric_cache();
+        let snapshot = self.raw_db.snapshot();
+        let windows_cf = self.windows_cf()?;
+        let metrics_cf = self.metrics_cf()?;
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut from_windows = Vec::new();
+        let start_key = StorageKey::as_bytes(&from, 0)?;
+        let kv_iter_mode = rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward);
+        for (key_bytes, val_bytes) in snapshot.iterator_cf(windows_cf, kv_iter_mode)? {
+            let key = StorageKey::decode(&mut &key_bytes[..])?;
+            if key.metric() != from {
+                break;
+            }
+            if !allow_overlap {
+                from_windows.push(StorageValue::from_bytes(&val_bytes)?.window());
+            }
+            let new_key = StorageKey::as_bytes(&to, key.window_start())?;
+            batch.merge_cf(windows_cf, &new_key, &val_bytes)?;
+            batch.delete_cf(windows_cf, &key_bytes)?;
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if !allow_overlap {
+            let mut to_windows = Vec::new();
+            let start_key = StorageKey::as_bytes(&to, 0)?;
+            let kv_iter_mode = rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward);
+            for (key_bytes, val_bytes) in snapshot.iterator_cf(windows_cf, kv_iter_mode)? {
+                let key = StorageKey::decode(&mut &key_bytes[..])?;
+                if key.metric() != to {
+                    break;
+                }
+                to_windows.push(StorageValue::from_bytes(&val_bytes)?.window());
+            }
+            if let Some(window) = find_overlapping_window(&from_windows, &to_windows) {
+                return Err(StorageError::OverlappingWindows(window));
+            }
+        }
+        info!("Renaming metric {} to {}", from, to);
+        let kind_bytes = self
+            .raw_db
+            .get_cf(metrics_cf, from.as_bytes())?
+            .map(|bytes| bytes.to_vec())
+            .unwrap_or_default();
+        batch.put_cf(metrics_cf, to.as_bytes(), &kind_bytes)?;
+        batch.delete_cf(metrics_cf, from.as_bytes())?;
+        self.raw_db.write(batch)?;
+        known.remove(&from);
+        known.insert(to);
+        Ok(())
+    }
+
+    // Returns every stored metric name in sorted order
+    pub fn list_metrics<'a>(&'a self) -> Result<Box<Iterator<Item = String> + 'a>, StorageError> {
+        let kv_iter = self
+            .raw_db
+            .iterator_cf(self.metrics_cf()?, rocksdb::IteratorMode::Start)?;
+        let metric_iter = kv_iter.filter_map(|(key, _)| match str::from_utf8(&key) {
+            Ok(metric) => Some(metric.to_string()),
+            Err(err) => {
+                error!("Could not decode metric name: {:?}", err);
+                None
+            }
+        });
+        Ok(Box::new(metric_iter))
+    }
+
+    // Returns the kind the metric was last inserted as, or `None` if it isn't stored.
+    // Metrics stored before kinds were recorded, or only imported from a snapshot, are timers.
+    pub fn metric_kind(&self, metric: &str) -> Result<Option<MetricKind>, StorageError> {
+        match self.raw_db.get_cf(self.metrics_cf()?, metric.as_bytes())? {
+            None => Ok(None),
+            Some(ref bytes) if bytes.is_empty() => Ok(Some(MetricKind::Timer)),
+            Some(bytes) => Ok(Some(MetricKind::decode(&mut &bytes[..])?)),
+        }
+    }
+
+    // The metric cache mirrors the metrics column family, which has exactly one key
+    // per distinct metric, so counting doesn't need to iterate the column family
+    pub fn metric_count(&self) -> Result<usize, StorageError> {
+        let known = self
+            .known_metrics
+            .read()
+            .expect("Could not acquire read lock on metric cache");
+        Ok(known.len())
+    }
+
+    // Reads a metadata key to confirm the database is open and serving reads
+    pub fn check_readable(&self) -> Result<(), StorageError> {
+        let metadata_cf = self.metadata_cf()?;
+        self.raw_db.get_cf(metadata_cf, DOWNSAMPLE_BOOKMARK_KEY)?;
+        Ok(())
+    }
+
+    // RocksDB's statistics dump, one stat per line, like `rocksdb.block.cache.miss COUNT : 12`.
+    // Empty unless the store was opened with `enable_statistics`.
+    pub fn get_statistics(&self) -> Result<String, StorageError> {
+        let opts = self
+            .db_opts
+            .lock()
+            .map_err(|_| StorageError::InternalError("Could not lock database options"))?;
+        Ok(opts.get_statistics().unwrap_or_default())
+    }
+
+    // Merges SST files for the windows column family, or only for the windows of `metric`,
+    // so later reads touch fewer files. Blocks the calling thread until compaction finishes.
+    pub fn compact_range(&self, metric: Option<&str>) -> Result<(), StorageError> {
+        let cf = self.windows_cf()?;
+        match metric {
+            Some(metric) => {
+                let metric = self.validate_metric_name(metric)?;
+                let start_key = StorageKey::as_bytes(&metric, 0)?;
+                let end_key = StorageKey::as_bytes(&metric, TimeStamp::MAX)?;
+                info!("Compacting windows for metric {}", metric);
+                self.raw_db
+                    .compact_range_cf(cf, Some(&start_key[..]), Some(&end_key[..]));
+            }
+            None => {
+                info!("Compacting all windows");
+                self.raw_db.compact_range_cf(cf, None, None);
+            }
+        }
+        Ok(())
+    }
+
+    // Backs up the database to `backup_dir`. Backups to the same directory are incremental,
+    // so only files added since the previous backup are copied.
+    pub fn backup(&self, backup_dir: &str) -> Result<(), StorageError> {
+        let mut engine = BackupEngine::open(&BackupEngineOptions::default(), backup_dir)?;
+        info!("Backing up database to {}", backup_dir);
+        engine.create_new_backup(&self.raw_db)?;
+        Ok(())
+    }
+
+    // Restores the most recent backup in `backup_dir` to `db_dir`, replacing its contents.
+    // No store may have `db_dir` open while restoring.
+    pub fn restore(backup_dir: &str, db_dir: &str) -> Result<(), StorageError> {
+        info!("Restoring database to {} from {}", db_dir, backup_dir);
+        restore_from_latest_backup(backup_dir, db_dir)
+    }
+
+    // Total size of the files in the database directory
+    pub fn disk_usage_bytes(&self) -> Result<u64, StorageError> {
+        let mut total = 0;
+        for entry in fs::read_dir(self.raw_db.path())? {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                total += metadata.len();
+            }
+        }
+        Ok(total)
+    }
+
+    // Writes every window from a consistent snapshot of the store.
+    // Returns the number of entries written.
+    pub fn export_snapshot<W: Write>(&self, writer: &mut W) -> Result<usize, StorageError> {
+        let snapshot = self.raw_db.snapshot();
+        let cf = self.windows_cf()?;
+        let count = snapshot
+            .iterator_cf(cf, rocksdb::IteratorMode::Start)?
+            .count();
+        writer
+            .write_all(SNAPSHOT_MAGIC)
+            .map_err(EncodableError::from)?;
+        SNAPSHOT_VERSION.encode(writer)?;
+        (count as u64).encode(writer)?;
+        for (key_bytes, val_bytes) in snapshot.iterator_cf(cf, rocksdb::IteratorMode::Start)? {
+            let key = StorageKey::decode(&mut &key_bytes[..])?;
+            let val = StorageValue::from_bytes(&val_bytes)?;
+            writer
+                .write_all(&key.to_bytes()?)
+                .map_err(EncodableError::from)?;
+            val.encode(writer)?;
+        }
+        Ok(count)
+    }
+
+    // Merges every window from a snapshot into the store, so importing into
+    // a store that already has data combines the sketches for matching windows.
+    // Returns the number of entries read.
+    pub fn import_snapshot<R: Read>(&self, reader: &mut R) -> Result<usize, StorageError> {
+        let mut magic = [0u8; 5];
+        reader
+            .read_exact(&mut magic)
+            .map_err(EncodableError::from)?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(StorageError::from(EncodableError::FormatError(
+                "Invalid snapshot header",
+            )));
+        }
+        let version = u8::decode(reader)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(StorageError::from(EncodableError::UnsupportedVersion(
+                version,
+            )));
+        }
+        let count = u64::decode(reader)? as usize;
+        let windows_cf = self.windows_cf()?;
+        let metrics_cf = self.metrics_cf()?;
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut metrics = Vec::new();
+        for i in 0..count {
+            let key = StorageKey::decode(reader)?;
+            let val = StorageValue::decode(reader)?;
+            // Snapshots don't include metric kinds, so keep the kind of metrics already stored
+            if !self.is_known_metric(key.metric()) {
+                batch.put_cf(metrics_cf, key.metric().as_bytes(), &[1u8; 0])?;
+            }
+            batch.merge_cf(
+                windows_cf,
+                &key.to_bytes()?,
+                &val.to_bytes(self.compression)?,
+            )?;
+            metrics.push(key.metric().to_string());
+            if (i + 1) % SNAPSHOT_IMPORT_BATCH_SIZE == 0 {
+                self.write_adding_metrics(batch, mem::take(&mut metrics))?;
+                batch = rocksdb::WriteBatch::default();
+            }
+        }
+        self.write_adding_metrics(batch, metrics)?;
+        Ok(count)
+    }
+
+    // Merges each metric's sketches for windows starting at or after `since`.
+    // Reads from a snapshot, so concurrent inserts are not partially visible.
+    pub fn summarize_since(
+        &self,
+        since: TimeStamp,
+    ) -> Result<Vec<(String, WritableSketch)>, StorageError> {
+        let snapshot = self.raw_db.snapshot();
+        let windows_cf = self.windows_cf()?;
+        let metrics_iter =
+            snapshot.iterator_cf(self.metrics_cf()?, rocksdb::IteratorMode::Start)?;
+        let mut result = Vec::new();
+        for (metric_bytes, _) in metrics_iter {
+            let metric = match str::from_utf8(&metric_bytes) {
+                Ok(m) => m,
+                Err(err) => {
+                    error!("Could not decode metric name: {:?}", err);
+                    continue;
                 }
-                DownsampleAction::ExpandWindow(new_window) => {
-                    debug!(
-                        "Expanding window for key {:?} during downsampling: \
-                         old_window={:?}, new_window={:?}",
-                        key,
-                        val.window(),
-                        new_window
-                    );
-                    let mut batch = rocksdb::WriteBatch::default();
-                    let old_key_bytes = key.to_bytes()?;
-                    batch.delete_cf(cf, &old_key_bytes)?;
-
-                    let new_key = key.with_window_start(new_window.start());
-                    let key_bytes = new_key.to_bytes()?;
-                    let new_val = val.with_window(new_window);
-                    let val_bytes = new_val.to_bytes()?;
-                    batch.merge_cf(cf, &key_bytes, &val_bytes)?;
-
-                    self.raw_db.write(batch)?;
+            };
+            let start_key = StorageKey::as_bytes(metric, since)?;
+            let kv_iter_mode = rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward);
+            let mut merged: Option<WritableSketch> = None;
+            for (key_bytes, val_bytes) in snapshot.iterator_cf(windows_cf, kv_iter_mode)? {
+                let key = StorageKey::decode(&mut &key_bytes[..])?;
+                if key.metric() != metric {
+                    break;
                 }
+                let sketch = StorageValue::from_bytes(&val_bytes)?.to_data_row().sketch;
+                merged = match merged {
+                    None => Some(sketch),
+                    Some(m) => Some(m.merge(sketch)?),
+                };
+            }
+            if let Some(sketch) = merged {
+                result.push((metric.to_string(), sketch));
+            }
+        }
+        Ok(result)
+    }
+
+    // Returns the canonical name of the inserted metric.
+    // The metric's kind is stored as the value of its key in the metrics column family.
+    fn add_insert_to_batch(
+        &self,
+        batch: &mut rocksdb::WriteBatch,
+        metric: &str,
+        kind: MetricKind,
+        window: TimeWindow,
+        sketch: WritableSketch,
+    ) -> Result<String, StorageError> {
+        let metric = self.validate_metric_name(metric)?;
+        let key = StorageKey::as_bytes(&metric, window.start())?;
+        let val = StorageValue::as_bytes(window, sketch, self.compression)?;
+        debug!(
+            "Inserting key for metric {} and window {:?}",
+            metric, window
+        );
+        let mut kind_bytes = Vec::with_capacity(1);
+        kind.encode(&mut kind_bytes)?;
+        batch.put_cf(self.metrics_cf()?, metric.as_bytes(), &kind_bytes)?;
+        batch.merge_cf(self.windows_cf()?, &key, &val)?;
+        Ok(metric)
+    }
+
+    fn is_known_metric(&self, metric: &str) -> bool {
+        self.known_metrics
+            .read()
+            .expect("Could not acquire read lock on metric cache")
+            .contains(metric)
+    }
+
+    // Writes the batch while holding the cache lock, so a concurrent delete can't remove
+    // the metrics from the cache after this write lands. Only new metrics need the write lock.
+    fn write_adding_metrics(
+        &self,
+        batch: rocksdb::WriteBatch,
+        metrics: Vec<String>,
+    ) -> Result<(), StorageError> {
+        {
+            let known = self
+                .known_metrics
+                .read()
+                .expect("Could not acquire read lock on metric cache");
+            if metrics.iter().all(|m| known.contains(m)) {
+                self.raw_db.write(batch)?;
+                return Ok(());
             }
         }
+        let mut known = self.lock_metric_cache();
+        self.raw_db.write(batch)?;
+        known.extend(metrics);
         Ok(())
     }
 
+    fn lock_metric_cache(&self) -> RwLockWriteGuard<'_, HashSet<String>> {
+        self.known_metrics
+            .write()
+            .expect("Could not acquire write lock on metric cache")
+    }
+
+    fn has_windows(&self, metric: &str) -> Result<bool, StorageError> {
+        let start_key = StorageKey::as_bytes(metric, 0)?;
+        let kv_iter_mode = rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward);
+        let mut kv_iter = self.raw_db.iterator_cf(self.windows_cf()?, kv_iter_mode)?;
+        match kv_iter.next() {
+            Some((key_bytes, _)) => {
+                let key = StorageKey::decode(&mut &key_bytes[..])?;
+                Ok(key.metric() == metric)
+            }
+            None => Ok(false),
+        }
+    }
+
     fn windows_cf_desc() -> rocksdb::ColumnFamilyDescriptor {
         let mut opts = rocksdb::Options::default();
         opts.set_comparator("key_comparator", MetricStore::compare_keys);
@@ -113,6 +758,11 @@ impl MetricStore {
         rocksdb::ColumnFamilyDescriptor::new(METRICS_CF_NAME, opts)
     }
 
+    fn metadata_cf_desc() -> rocksdb::ColumnFamilyDescriptor {
+        let opts = rocksdb::Options::default();
+        rocksdb::ColumnFamilyDescriptor::new(METADATA_CF_NAME, opts)
+    }
+
     fn windows_cf(&self) -> Result<rocksdb::ColumnFamily, StorageError> {
         self.raw_db
             .cf_handle(WINDOWS_CF_NAME)
@@ -129,6 +779,14 @@ impl MetricStore {
             ))
     }
 
+    fn metadata_cf(&self) -> Result<rocksdb::ColumnFamily, StorageError> {
+        self.raw_db
+            .cf_handle(METADATA_CF_NAME)
+            .ok_or(StorageError::InternalError(
+                "Could not open metadata column family",
+            ))
+    }
+
     fn compare_keys(mut x: &[u8], mut y: &[u8]) -> Ordering {
         let k1 = StorageKey::decode(&mut x).expect("Could not decode storage key");
         let k2 = StorageKey::decode(&mut y).expect("Could not decode storage key");
@@ -140,8 +798,13 @@ impl MetricStore {
         existing_val: Option<&[u8]>,
         operands: &mut rocksdb::MergeOperands,
     ) -> Option<Vec<u8>> {
+        // Operands are written with the store's current compression,
+        // so recompress the merged value the same way as the latest operand.
+        let mut compression = existing_val
+            .map(ValueCompression::of_bytes)
+            .unwrap_or_default();
         let mut value_opt: Option<StorageValue> =
-            existing_val.and_then(|mut bytes| match StorageValue::decode(&mut bytes) {
+            existing_val.and_then(|bytes| match StorageValue::from_bytes(bytes) {
                 Ok(v) => Some(v),
                 Err(err) => {
                     error!("Could not deserialize existing value: {:?}", err);
@@ -149,11 +812,17 @@ impl MetricStore {
                 }
             });
 
-        for mut bytes in operands {
-            value_opt = match StorageValue::decode(&mut bytes) {
+        for bytes in operands {
+            compression = ValueCompression::of_bytes(bytes);
+            value_opt = match StorageValue::from_bytes(bytes) {
                 Ok(v1) => match value_opt {
                     None => Some(v1),
-                    Some(v2) => Some(v1.merge(v2)),
+                    Some(mut v2) => {
+                        if let Err(err) = v2.merge_from(v1) {
+                            error!("Dropping operand that could not be merged: {:?}", err);
+                        }
+                        Some(v2)
+                    }
                 },
                 Err(err) => {
                     error!("Could not deserialize operand value: {:?}", err);
@@ -162,7 +831,7 @@ impl MetricStore {
             }
         }
 
-        let result = value_opt.and_then(|v| match v.to_bytes() {
+        let result = value_opt.and_then(|v| match v.to_bytes(compression) {
             Ok(bytes) => Some(bytes),
             Err(err) => {
                 error!("Could not serialize merged value to bytes: {:?}", err);
@@ -180,19 +849,35 @@ impl MetricStore {
         result
     }
 
-    fn validate_metric_name(s: &str) -> Result<(), StorageError> {
-        lazy_static! {
-            static ref METRIC_RE: Regex =
-                Regex::new("^[a-zA-Z][a-zA-Z0-9._-]*$").expect("Could not compile regex");
-        }
-        if METRIC_RE.is_match(s) {
-            Ok(())
-        } else {
-            Err(StorageError::InvalidMetricName)
+    fn validate_metric_name(&self, s: &str) -> Result<String, StorageError> {
+        if s.len() > self.max_name_len {
+            return Err(StorageError::MetricNameTooLong(s.len()));
         }
+        MetricStore::canonical_metric_name(s)
+    }
+
+    // Metrics are stored under their canonical name, with tags sorted by key
+    fn canonical_metric_name(s: &str) -> Result<String, StorageError> {
+        canonicalize(s).ok_or(StorageError::InvalidMetricName)
     }
 }
 
+// Returns the first window in `windows` that overlaps any window in `others`.
+// Both slices are sorted by start, as they are in the windows column family, so the
+// windows of `others` that start before a window ends form a prefix. A window overlaps
+// one of them if the latest end in that prefix is after its start.
+fn find_overlapping_window(windows: &[TimeWindow], others: &[TimeWindow]) -> Option<TimeWindow> {
+    let mut max_ends = Vec::with_capacity(others.len());
+    for w in others.iter() {
+        let max_end = max_ends.last().map_or(w.end(), |&end| max(end, w.end()));
+        max_ends.push(max_end);
+    }
+    windows.iter().cloned().find(|w| {
+        let n = others.partition_point(|o| o.start() < w.end());
+        n > 0 && max_ends[n - 1] > w.start()
+    })
+}
+
 impl DataSource for MetricStore {
     fn fetch<'a>(
         &'a self,
@@ -200,9 +885,14 @@ impl DataSource for MetricStore {
         start: Option<TimeStamp>,
         end: Option<TimeStamp>,
     ) -> Result<Box<Iterator<Item = DataRow> + 'a>, StorageError> {
-        MetricStore::validate_metric_name(&metric)?;
+        let mut span = Span::start("caesium.storage.fetch");
+        span.set_str("metric", &metric);
+        let metric = self.validate_metric_name(&metric)?;
+        if !self.is_known_metric(&metric) {
+            return Ok(Box::new(iter::empty()));
+        }
         let ts = start.unwrap_or(0);
-        let end_ts = end.unwrap_or(u64::max_value());
+        let end_ts = end.unwrap_or(u64::MAX);
         let start_key = StorageKey::as_bytes(&metric, ts)?;
         let cf = self.windows_cf()?;
         let kv_iter_mode = rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward);
@@ -219,14 +909,18 @@ impl DataSource for MetricStore {
             )
             .take_while(move |(key, _)| key.metric() == metric && key.window_start() < end_ts)
             .filter_map(
-                |(_, val_bytes)| match StorageValue::decode(&mut &val_bytes[..]) {
+                |(_, val_bytes)| match StorageValue::from_bytes(&val_bytes) {
                     Ok(val) => Some(val.to_data_row()),
                     Err(err) => {
                         error!("Error decoding value: {:?}", err);
                         None
                     }
                 },
-            );
+            )
+            // The span ends when the caller drops the iterator, so it covers reading every row
+            .inspect(move |_| {
+                let _ = &span;
+            });
         Ok(Box::new(iter))
     }
 
@@ -234,16 +928,19 @@ impl DataSource for MetricStore {
         &'a self,
         pattern: String,
     ) -> Result<Box<Iterator<Item = String> + 'a>, StorageError> {
-        let prefix_str = exact_prefix(&pattern);
+        if pattern == "*" {
+            return self.list_metrics();
+        }
+        let prefix_str = metric_pattern_prefix(&pattern);
         let kv_iter_mode =
             rocksdb::IteratorMode::From(prefix_str.as_bytes(), rocksdb::Direction::Forward);
         let prefix_bytes = prefix_str.as_bytes().to_vec();
         let kv_iter = self.raw_db.iterator_cf(self.metrics_cf()?, kv_iter_mode)?;
         let metric_iter = kv_iter
             .take_while(move |(key, _)| key.starts_with(&prefix_bytes))
-            .filter_map(move |(key, _)| match str::from_utf8(&*key) {
+            .filter_map(move |(key, _)| match str::from_utf8(&key) {
                 Ok(metric) => {
-                    if wildcard_match(metric, &pattern) {
+                    if metric_match(metric, &pattern) {
                         Some(metric.to_string())
                     } else {
                         None
@@ -261,8 +958,16 @@ impl DataSource for MetricStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use caesium_core::protocol::messages::MetricKind;
     use caesium_core::quantile::writable::WritableSketch;
+    use caesium_core::time::clock::MockClock;
+    use std::cell::RefCell;
     use std::panic;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use storage::downsample::strategies::{DefaultStrategy, TieredStrategy};
+    use storage::downsample::DownsampleCounts;
     use uuid::Uuid;
 
     #[test]
@@ -377,6 +1082,102 @@ mod tests {
         })
     }
 
+    #[test]
+    fn it_stores_batch_of_sketches() {
+        with_test_store(|store| {
+            let inserts = vec![
+                InsertMessage {
+                    metric: "foo".to_string(),
+                    kind: MetricKind::Timer,
+                    window: TimeWindow::new(0, 30),
+                    sketch: build_sketch(),
+                },
+                InsertMessage {
+                    metric: "foo".to_string(),
+                    kind: MetricKind::Timer,
+                    window: TimeWindow::new(30, 60),
+                    sketch: build_sketch(),
+                },
+                InsertMessage {
+                    metric: "bar".to_string(),
+                    kind: MetricKind::Timer,
+                    window: TimeWindow::new(0, 30),
+                    sketch: build_sketch(),
+                },
+            ];
+            store.insert_batch(inserts).expect("Could not insert batch");
+            let foo_rows: Vec<DataRow> = store
+                .fetch("foo".to_string(), None, None)
+                .expect("Could not fetch range")
+                .collect();
+            assert_rows(foo_rows, vec![(0, 30, 50), (30, 60, 50)]);
+            let bar_rows: Vec<DataRow> = store
+                .fetch("bar".to_string(), None, None)
+                .expect("Could not fetch range")
+                .collect();
+            assert_rows(bar_rows, vec![(0, 30, 50)]);
+        })
+    }
+
+    #[test]
+    fn it_stores_metric_kind() {
+        with_test_store(|store| {
+            let inserts = vec![
+                InsertMessage {
+                    metric: "foo".to_string(),
+                    kind: MetricKind::Counter,
+                    window: TimeWindow::new(0, 30),
+                    sketch: build_sketch(),
+                },
+                InsertMessage {
+                    metric: "bar".to_string(),
+                    kind: MetricKind::Gauge,
+                    window: TimeWindow::new(0, 30),
+                    sketch: build_sketch(),
+                },
+            ];
+            store.insert_batch(inserts).expect("Could not insert batch");
+            store
+                .insert(&"baz", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch baz");
+            store
+                .rename_metric(&"foo", &"qux")
+                .expect("Could not rename metric");
+
+            let kind = |metric| store.metric_kind(metric).expect("Could not read kind");
+            assert_eq!(kind("qux"), Some(MetricKind::Counter));
+            assert_eq!(kind("bar"), Some(MetricKind::Gauge));
+            assert_eq!(kind("baz"), Some(MetricKind::Timer));
+            assert_eq!(kind("foo"), None);
+        })
+    }
+
+    #[test]
+    fn it_rejects_batch_with_invalid_metric_name() {
+        with_test_store(|store| {
+            let inserts = vec![
+                InsertMessage {
+                    metric: "foo".to_string(),
+                    kind: MetricKind::Timer,
+                    window: TimeWindow::new(0, 30),
+                    sketch: build_sketch(),
+                },
+                InsertMessage {
+                    metric: "!invalid".to_string(),
+                    kind: MetricKind::Timer,
+                    window: TimeWindow::new(0, 30),
+                    sketch: build_sketch(),
+                },
+            ];
+            assert!(store.insert_batch(inserts).is_err());
+            let rows: Vec<DataRow> = store
+                .fetch("foo".to_string(), None, None)
+                .expect("Could not fetch range")
+                .collect();
+            assert_rows(rows, vec![]);
+        })
+    }
+
     #[test]
     fn it_merges_sketches_in_same_time_window() {
         with_test_store(|store| {
@@ -403,6 +1204,74 @@ mod tests {
         })
     }
 
+    #[test]
+    fn it_drops_merge_operands_with_different_scales() {
+        with_test_store(|store| {
+            let metric = "foo".to_string();
+            let mut scaled = WritableSketch::new();
+            scaled.insert_f64(500.0, 2).expect("Could not insert value");
+            store
+                .insert(
+                    &metric,
+                    TimeWindow::new(0, 30),
+                    build_sketch_with_values(vec![1, 2, 3]),
+                )
+                .expect("Could not insert first sketch");
+            store
+                .insert(&metric, TimeWindow::new(0, 30), scaled)
+                .expect("Could not insert scaled sketch");
+            let rows: Vec<DataRow> = store
+                .fetch(metric, None, None)
+                .expect("Could not fetch range")
+                .collect();
+            assert_rows(rows, vec![(0, 30, 2)]);
+        })
+    }
+
+    #[test]
+    fn it_merges_legacy_and_compressed_values() {
+        let path = format!("testdb_{}", Uuid::new_v4());
+        MetricStore::destroy(&path).expect("Setup: could not destroy old test DB");
+        let result = panic::catch_unwind(|| {
+            let metric = "foo".to_string();
+            {
+                let store = MetricStore::open(&path).expect("Setup: could not open test DB");
+                store
+                    .insert(
+                        &metric,
+                        TimeWindow::new(0, 30),
+                        build_sketch_with_values(vec![1, 2]),
+                    )
+                    .expect("Could not insert uncompressed sketch");
+            }
+
+            let options = MetricStoreOptions {
+                compression: ValueCompression::Lz4,
+                ..MetricStoreOptions::default()
+            };
+            let store = MetricStore::open_with_options(&path, options)
+                .expect("Setup: could not reopen test DB");
+            store
+                .insert(
+                    &metric,
+                    TimeWindow::new(0, 30),
+                    build_sketch_with_values(vec![3]),
+                )
+                .expect("Could not insert compressed sketch");
+
+            let key = StorageKey::as_bytes(&metric, 0).unwrap();
+            let stored = store
+                .raw_db
+                .get_cf(store.windows_cf().unwrap(), &key)
+                .expect("Could not read stored value")
+                .expect("Missing stored value");
+            assert_eq!(ValueCompression::of_bytes(&stored), ValueCompression::Lz4);
+            assert_eq!(fetch_windows(&store, &metric), vec![(0, 30, 3)]);
+        });
+        MetricStore::destroy(&path).expect("Teardown: could not destroy test DB");
+        assert!(result.is_ok())
+    }
+
     #[test]
     fn it_merges_sketches_with_overlapping_time_windows() {
         with_test_store(|store| {
@@ -447,40 +1316,134 @@ mod tests {
         })
     }
 
+    #[test]
+    fn it_accepts_metric_name_at_max_length() {
+        with_test_store(|store| {
+            let metric = "a".repeat(DEFAULT_MAX_NAME_LEN);
+            store
+                .insert(&metric, TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch");
+            let rows: Vec<DataRow> = store
+                .fetch(metric, None, None)
+                .expect("Could not fetch range")
+                .collect();
+            assert_rows(rows, vec![(0, 30, 50)]);
+        })
+    }
+
+    #[test]
+    fn it_rejects_metric_name_over_max_length() {
+        with_test_store(|store| {
+            let metric = "a".repeat(DEFAULT_MAX_NAME_LEN + 1);
+            match store.insert(&metric, TimeWindow::new(0, 30), build_sketch()) {
+                Err(StorageError::MetricNameTooLong(len)) => {
+                    assert_eq!(len, DEFAULT_MAX_NAME_LEN + 1)
+                }
+                r => panic!("Expected metric name too long error, got {:?}", r),
+            }
+            match store.fetch(metric, None, None) {
+                Err(StorageError::MetricNameTooLong(_)) => {}
+                _ => panic!("Expected metric name too long error"),
+            }
+        })
+    }
+
+    #[test]
+    fn it_rejects_metric_name_over_configured_max_length() {
+        let path = format!("testdb_{}", Uuid::new_v4());
+        MetricStore::destroy(&path).expect("Setup: could not destroy old test DB");
+        let options = MetricStoreOptions {
+            max_name_len: 8,
+            ..MetricStoreOptions::default()
+        };
+        let result = panic::catch_unwind(|| {
+            let store = MetricStore::open_with_options(&path, options)
+                .expect("Setup: could not open test DB");
+            store
+                .insert(&"abcdefgh", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch at max length");
+            match store.insert(&"abcdefghi", TimeWindow::new(0, 30), build_sketch()) {
+                Err(StorageError::MetricNameTooLong(9)) => {}
+                r => panic!("Expected metric name too long error, got {:?}", r),
+            }
+        });
+        MetricStore::destroy(&path).expect("Teardown: could not destroy test DB");
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    fn it_collects_statistics_when_enabled() {
+        let path = format!("testdb_{}", Uuid::new_v4());
+        MetricStore::destroy(&path).expect("Setup: could not destroy old test DB");
+        let options = MetricStoreOptions {
+            enable_statistics: true,
+            ..MetricStoreOptions::default()
+        };
+        let result = panic::catch_unwind(|| {
+            let store = MetricStore::open_with_options(&path, options)
+                .expect("Setup: could not open test DB");
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch");
+            assert_eq!(store.list_metrics().unwrap().count(), 1);
+            let stats = store.get_statistics().expect("Could not get statistics");
+            assert!(!stats.is_empty());
+            for line in stats.lines() {
+                assert!(line.starts_with("rocksdb."), "unexpected line {}", line);
+                assert!(line.contains(" : "), "unexpected line {}", line);
+            }
+        });
+        MetricStore::destroy(&path).expect("Teardown: could not destroy test DB");
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    fn it_returns_empty_statistics_when_disabled() {
+        with_test_store(|store| {
+            assert_eq!(store.get_statistics().unwrap(), "");
+        })
+    }
+
     #[test]
     fn it_accepts_metric_name_with_number() {
-        assert!(MetricStore::validate_metric_name("foo123").is_ok());
+        assert!(MetricStore::canonical_metric_name("foo123").is_ok());
     }
 
     #[test]
     fn it_accepts_metric_name_with_period() {
-        assert!(MetricStore::validate_metric_name("foo.bar").is_ok());
+        assert!(MetricStore::canonical_metric_name("foo.bar").is_ok());
     }
 
     #[test]
     fn it_accepts_metric_name_with_hyphen() {
-        assert!(MetricStore::validate_metric_name("foo-bar").is_ok());
+        assert!(MetricStore::canonical_metric_name("foo-bar").is_ok());
     }
 
     #[test]
     fn it_accepts_metric_name_with_underscore() {
-        assert!(MetricStore::validate_metric_name("foo_bar").is_ok());
+        assert!(MetricStore::canonical_metric_name("foo_bar").is_ok());
     }
 
     #[test]
     fn it_accepts_metric_name_with_capitals() {
-        assert!(MetricStore::validate_metric_name("FooBar").is_ok());
+        assert!(MetricStore::canonical_metric_name("FooBar").is_ok());
     }
 
     #[test]
     fn it_rejects_invalid_metric_names() {
-        assert_eq!(MetricStore::validate_metric_name("").is_ok(), false);
-        assert_eq!(MetricStore::validate_metric_name("1").is_ok(), false);
-        assert_eq!(MetricStore::validate_metric_name("1foo").is_ok(), false);
-        assert_eq!(MetricStore::validate_metric_name("foo&bar").is_ok(), false);
-        assert_eq!(MetricStore::validate_metric_name(".foo").is_ok(), false);
-        assert_eq!(MetricStore::validate_metric_name("_foo").is_ok(), false);
-        assert_eq!(MetricStore::validate_metric_name("-foo").is_ok(), false);
+        assert!(MetricStore::canonical_metric_name("").is_err());
+        assert!(MetricStore::canonical_metric_name("1").is_err());
+        assert!(MetricStore::canonical_metric_name("1foo").is_err());
+        assert!(MetricStore::canonical_metric_name("foo&bar").is_err());
+        assert!(MetricStore::canonical_metric_name(".foo").is_err());
+        assert!(MetricStore::canonical_metric_name("_foo").is_err());
+        assert!(MetricStore::canonical_metric_name("-foo").is_err());
+    }
+
+    #[test]
+    fn it_accepts_metric_name_with_tags() {
+        assert!(MetricStore::canonical_metric_name("foo;region=us").is_ok());
+        assert!(MetricStore::canonical_metric_name("foo;region").is_err());
     }
 
     #[test]
@@ -633,6 +1596,988 @@ mod tests {
         })
     }
 
+    #[test]
+    fn it_stores_tagged_metric_under_canonical_name() {
+        with_test_store(|store| {
+            store
+                .insert(
+                    &"foo;region=us;endpoint=/login",
+                    TimeWindow::new(0, 30),
+                    build_sketch(),
+                )
+                .expect("Could not insert sketch");
+            let rows: Vec<DataRow> = store
+                .fetch("foo;endpoint=/login;region=us".to_string(), None, None)
+                .expect("Could not fetch rows")
+                .collect();
+            assert_rows(rows, vec![(0, 30, 50)]);
+            let results: Vec<String> = store
+                .search("*".to_string())
+                .expect("Could not search")
+                .collect();
+            assert_eq!(results, vec!["foo;endpoint=/login;region=us"]);
+        })
+    }
+
+    #[test]
+    fn it_searches_metric_names_by_tag() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo;region=us", TimeWindow::new(0, 1), build_sketch())
+                .expect("Could not insert sketch foo (us)");
+            store
+                .insert(&"foo;region=eu", TimeWindow::new(0, 1), build_sketch())
+                .expect("Could not insert sketch foo (eu)");
+            store
+                .insert(&"bar;region=us", TimeWindow::new(0, 1), build_sketch())
+                .expect("Could not insert sketch bar (us)");
+            store
+                .insert(&"foobar", TimeWindow::new(0, 1), build_sketch())
+                .expect("Could not insert sketch foobar");
+
+            let results: Vec<String> = store
+                .search("foo;region=us".to_string())
+                .expect("Could not search (first)")
+                .collect();
+            assert_eq!(results, vec!["foo;region=us"]);
+
+            let results: Vec<String> = store
+                .search("*;region=us".to_string())
+                .expect("Could not search (second)")
+                .collect();
+            assert_eq!(results, vec!["bar;region=us", "foo;region=us"]);
+
+            let results: Vec<String> = store
+                .search("foo".to_string())
+                .expect("Could not search (third)")
+                .collect();
+            assert_eq!(results, vec!["foo;region=eu", "foo;region=us"]);
+        })
+    }
+
+    #[test]
+    fn it_summarizes_recent_windows() {
+        with_test_store(|store| {
+            store
+                .insert(
+                    &"foo",
+                    TimeWindow::new(0, 30),
+                    build_sketch_with_values(vec![1]),
+                )
+                .expect("Could not insert sketch foo (first)");
+            store
+                .insert(
+                    &"foo",
+                    TimeWindow::new(30, 60),
+                    build_sketch_with_values(vec![2]),
+                )
+                .expect("Could not insert sketch foo (second)");
+            store
+                .insert(
+                    &"foo",
+                    TimeWindow::new(60, 90),
+                    build_sketch_with_values(vec![3]),
+                )
+                .expect("Could not insert sketch foo (third)");
+            store
+                .insert(
+                    &"bar",
+                    TimeWindow::new(0, 30),
+                    build_sketch_with_values(vec![4]),
+                )
+                .expect("Could not insert sketch bar");
+            let summary: Vec<(String, usize)> = store
+                .summarize_since(30)
+                .expect("Could not summarize")
+                .iter()
+                .map(|(metric, sketch)| (metric.clone(), sketch.count()))
+                .collect();
+            assert_eq!(summary, vec![("foo".to_string(), 2)]);
+        })
+    }
+
+    #[test]
+    fn it_purges_windows_before_cutoff() {
+        with_test_store(|store| {
+            for start in [0, 30, 60, 90].iter() {
+                store
+                    .insert(&"foo", TimeWindow::new(*start, *start + 30), build_sketch())
+                    .expect("Could not insert sketch foo");
+            }
+            store
+                .insert(&"bar", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar");
+            store
+                .insert(&"baz", TimeWindow::new(90, 120), build_sketch())
+                .expect("Could not insert sketch baz");
+
+            let num_deleted = store.purge_before(60).expect("Could not purge");
+            assert_eq!(num_deleted, 3);
+
+            let foo_rows: Vec<DataRow> = store
+                .fetch("foo".to_string(), None, None)
+                .expect("Could not fetch foo")
+                .collect();
+            assert_rows(foo_rows, vec![(60, 90, 50), (90, 120, 50)]);
+
+            let bar_rows: Vec<DataRow> = store
+                .fetch("bar".to_string(), None, None)
+                .expect("Could not fetch bar")
+                .collect();
+            assert!(bar_rows.is_empty());
+
+            let baz_rows: Vec<DataRow> = store
+                .fetch("baz".to_string(), None, None)
+                .expect("Could not fetch baz")
+                .collect();
+            assert_rows(baz_rows, vec![(90, 120, 50)]);
+        })
+    }
+
+    #[test]
+    fn it_removes_metrics_without_windows_after_purge() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo");
+            store
+                .insert(&"bar", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar (first)");
+            store
+                .insert(&"bar", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch bar (second)");
+
+            store.purge_before(30).expect("Could not purge");
+            let mut metrics: Vec<String> = store
+                .search("*".to_string())
+                .expect("Could not search metrics")
+                .collect();
+            metrics.sort();
+            assert_eq!(metrics, vec!["bar"]);
+        })
+    }
+
+    #[test]
+    fn it_purges_nothing_before_zero() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch");
+            assert_eq!(store.purge_before(0).expect("Could not purge"), 0);
+            let rows: Vec<DataRow> = store
+                .fetch("foo".to_string(), None, None)
+                .expect("Could not fetch range")
+                .collect();
+            assert_rows(rows, vec![(0, 30, 50)]);
+        })
+    }
+
+    #[test]
+    fn it_deletes_metric() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo (first)");
+            store
+                .insert(&"foo", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch foo (second)");
+            store
+                .insert(&"bar", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar");
+
+            store
+                .delete_metric(&"foo")
+                .expect("Could not delete metric");
+
+            let foo_rows: Vec<DataRow> = store
+                .fetch("foo".to_string(), None, None)
+                .expect("Could not fetch foo")
+                .collect();
+            assert!(foo_rows.is_empty());
+
+            let bar_rows: Vec<DataRow> = store
+                .fetch("bar".to_string(), None, None)
+                .expect("Could not fetch bar")
+                .collect();
+            assert_rows(bar_rows, vec![(0, 30, 50)]);
+
+            let metrics: Vec<String> = store
+                .search("*".to_string())
+                .expect("Could not search metrics")
+                .collect();
+            assert_eq!(metrics, vec!["bar"]);
+        })
+    }
+
+    #[test]
+    fn it_fetches_after_compaction() {
+        with_test_store(|store| {
+            for i in 0..500 {
+                for metric in ["foo", "foo.bar", "bar"].iter() {
+                    store
+                        .insert(
+                            metric,
+                            TimeWindow::new(i * 30, (i + 1) * 30),
+                            build_sketch(),
+                        )
+                        .expect("Could not insert sketch");
+                }
+            }
+            store
+                .compact_range(Some("foo"))
+                .expect("Could not compact metric");
+            store.compact_range(None).expect("Could not compact store");
+
+            for metric in ["foo", "foo.bar", "bar"].iter() {
+                let windows = fetch_windows(&store, metric);
+                assert_eq!(windows.len(), 500);
+                assert_eq!(windows[0], (0, 30, 100));
+                assert_eq!(windows[499], (14970, 15000, 100));
+            }
+        })
+    }
+
+    #[test]
+    fn it_rejects_compaction_with_invalid_metric_name() {
+        with_test_store(|store| match store.compact_range(Some("1foo")) {
+            Err(StorageError::InvalidMetricName) => {}
+            r => panic!("Expected invalid metric name error, got {:?}", r),
+        })
+    }
+
+    #[test]
+    fn it_renames_metric() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo (first)");
+            store
+                .insert(&"foo", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch foo (second)");
+            store
+                .insert(&"bar", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar");
+
+            store
+                .rename_metric(&"foo", &"baz")
+                .expect("Could not rename metric");
+
+            assert!(fetch_windows(&store, "foo").is_empty());
+            assert_eq!(
+                fetch_windows(&store, "baz"),
+                vec![(0, 30, 100), (30, 60, 100)]
+            );
+            assert_eq!(fetch_windows(&store, "bar"), vec![(0, 30, 100)]);
+            let metrics: Vec<String> = store
+                .list_metrics()
+                .expect("Could not list metrics")
+                .collect();
+            assert_eq!(metrics, vec!["bar", "baz"]);
+        })
+    }
+
+    #[test]
+    fn it_renames_metric_into_existing_metric_without_overlap() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo");
+            store
+                .insert(&"bar", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch bar");
+
+            store
+                .rename_metric(&"foo", &"bar")
+                .expect("Could not rename metric");
+
+            assert_eq!(
+                fetch_windows(&store, "bar"),
+                vec![(0, 30, 100), (30, 60, 100)]
+            );
+        })
+    }
+
+    #[test]
+    fn it_rejects_rename_into_overlapping_windows() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch foo");
+            store
+                .insert(&"bar", TimeWindow::new(0, 40), build_sketch())
+                .expect("Could not insert sketch bar");
+
+            match store.rename_metric(&"foo", &"bar") {
+                Err(StorageError::OverlappingWindows(w)) => assert_eq!(w, TimeWindow::new(30, 60)),
+                r => panic!("Expected overlapping windows error, got {:?}", r),
+            }
+
+            // Neither metric changes when the rename is rejected
+            assert_eq!(fetch_windows(&store, "foo"), vec![(30, 60, 100)]);
+            assert_eq!(fetch_windows(&store, "bar"), vec![(0, 40, 100)]);
+        })
+    }
+
+    #[test]
+    fn it_merges_renamed_metric_into_existing_metric() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo (first)");
+            store
+                .insert(&"foo", TimeWindow::new(60, 90), build_sketch())
+                .expect("Could not insert sketch foo (second)");
+            store
+                .insert(&"bar", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar");
+
+            store
+                .rename_metric_merging(&"foo", &"bar")
+                .expect("Could not rename metric");
+
+            assert!(fetch_windows(&store, "foo").is_empty());
+            assert_eq!(
+                fetch_windows(&store, "bar"),
+                vec![(0, 30, 200), (60, 90, 100)]
+            );
+            let metrics: Vec<String> = store
+                .list_metrics()
+                .expect("Could not list metrics")
+                .collect();
+            assert_eq!(metrics, vec!["bar"]);
+        })
+    }
+
+    #[test]
+    fn it_rejects_rename_with_invalid_metric_name() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo");
+            match store.rename_metric(&"foo", &"1foo") {
+                Err(StorageError::InvalidMetricName) => {}
+                r => panic!("Expected invalid metric name error, got {:?}", r),
+            }
+            match store.rename_metric(&"1foo", &"foo") {
+                Err(StorageError::InvalidMetricName) => {}
+                r => panic!("Expected invalid metric name error, got {:?}", r),
+            }
+        })
+    }
+
+    #[test]
+    fn it_finds_overlapping_windows() {
+        let windows = vec![TimeWindow::new(0, 10), TimeWindow::new(50, 60)];
+        let others = vec![TimeWindow::new(10, 20), TimeWindow::new(60, 70)];
+        assert_eq!(find_overlapping_window(&windows, &others), None);
+
+        // An early long window overlaps even though later windows don't
+        let others = vec![TimeWindow::new(5, 100), TimeWindow::new(20, 30)];
+        assert_eq!(
+            find_overlapping_window(&windows, &others),
+            Some(TimeWindow::new(0, 10))
+        );
+        let others = vec![TimeWindow::new(20, 55), TimeWindow::new(30, 40)];
+        assert_eq!(
+            find_overlapping_window(&windows, &others),
+            Some(TimeWindow::new(50, 60))
+        );
+        assert_eq!(find_overlapping_window(&windows, &[]), None);
+    }
+
+    #[test]
+    fn it_caches_metrics_after_insert() {
+        with_test_store(|store| {
+            assert!(!store.is_known_metric("foo;region=us"));
+            store
+                .insert(&"foo;region=us", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch");
+            store
+                .insert_batch(vec![InsertMessage {
+                    metric: "bar".to_string(),
+                    kind: MetricKind::Timer,
+                    window: TimeWindow::new(0, 30),
+                    sketch: build_sketch(),
+                }])
+                .expect("Could not insert batch");
+            assert!(store.is_known_metric("foo;region=us"));
+            assert!(store.is_known_metric("bar"));
+            assert!(!store.is_known_metric("baz"));
+        })
+    }
+
+    #[test]
+    fn it_removes_cached_metrics_after_delete_and_purge() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo");
+            store
+                .insert(&"bar", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar");
+            store
+                .insert(&"baz", TimeWindow::new(60, 90), build_sketch())
+                .expect("Could not insert sketch baz");
+            store
+                .delete_metric(&"foo")
+                .expect("Could not delete metric");
+            assert!(!store.is_known_metric("foo"));
+            store.purge_before(30).expect("Could not purge");
+            assert!(!store.is_known_metric("bar"));
+            assert!(store.is_known_metric("baz"));
+            assert_eq!(fetch_windows(&store, "bar"), vec![]);
+            assert_eq!(fetch_windows(&store, "baz"), vec![(60, 90, 100)]);
+        })
+    }
+
+    #[test]
+    fn it_caches_metrics_inserted_during_concurrent_deletes() {
+        with_test_store(|store| {
+            let store = Arc::new(store);
+            for _ in 0..50 {
+                let inserter = {
+                    let store = store.clone();
+                    thread::spawn(move || {
+                        store
+                            .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                            .expect("Could not insert sketch");
+                    })
+                };
+                store
+                    .delete_metric(&"foo")
+                    .expect("Could not delete metric");
+                inserter.join().expect("Could not join inserter");
+                let has_windows = store.has_windows("foo").expect("Could not read windows");
+                assert_eq!(store.is_known_metric("foo"), has_windows);
+                store
+                    .delete_metric(&"foo")
+                    .expect("Could not delete metric");
+            }
+        })
+    }
+
+    #[test]
+    fn it_keeps_metrics_inserted_during_concurrent_purges() {
+        with_test_store(|store| {
+            let store = Arc::new(store);
+            for _ in 0..50 {
+                store
+                    .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                    .expect("Could not insert sketch");
+                let inserter = {
+                    let store = store.clone();
+                    thread::spawn(move || {
+                        store
+                            .insert(&"foo", TimeWindow::new(60, 90), build_sketch())
+                            .expect("Could not insert sketch");
+                    })
+                };
+                store.purge_before(30).expect("Could not purge");
+                inserter.join().expect("Could not join inserter");
+                let listed = store
+                    .list_metrics()
+                    .expect("Could not list metrics")
+                    .any(|m| m == "foo");
+                assert!(store.has_windows("foo").expect("Could not read windows"));
+                assert!(store.is_known_metric("foo"));
+                assert!(listed);
+                store
+                    .delete_metric(&"foo")
+                    .expect("Could not delete metric");
+            }
+        })
+    }
+
+    #[test]
+    fn it_caches_metrics_inserted_during_concurrent_renames() {
+        with_test_store(|store| {
+            let store = Arc::new(store);
+            for _ in 0..50 {
+                store
+                    .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                    .expect("Could not insert sketch");
+                let inserter = {
+                    let store = store.clone();
+                    thread::spawn(move || {
+                        store
+                            .insert(&"foo", TimeWindow::new(60, 90), build_sketch())
+                            .expect("Could not insert sketch");
+                    })
+                };
+                store
+                    .rename_metric_merging(&"foo", &"bar")
+                    .expect("Could not rename metric");
+                inserter.join().expect("Could not join inserter");
+                let has_windows = store.has_windows("foo").expect("Could not read windows");
+                assert_eq!(store.is_known_metric("foo"), has_windows);
+                assert!(store.is_known_metric("bar"));
+                store
+                    .delete_metric(&"foo")
+                    .expect("Could not delete metric");
+                store
+                    .delete_metric(&"bar")
+                    .expect("Could not delete metric");
+            }
+        })
+    }
+
+    #[test]
+    fn it_warms_metric_cache() {
+        with_test_store(|store| {
+            // Write directly to RocksDB, bypassing the cache
+            let mut batch = rocksdb::WriteBatch::default();
+            store
+                .add_insert_to_batch(
+                    &mut batch,
+                    "foo",
+                    MetricKind::Timer,
+                    TimeWindow::new(0, 30),
+                    build_sketch(),
+                )
+                .expect("Could not add insert to batch");
+            store.raw_db.write(batch).expect("Could not write batch");
+            assert_eq!(fetch_windows(&store, "foo"), vec![]);
+
+            store
+                .warm_metric_cache()
+                .expect("Could not warm metric cache");
+            assert!(store.is_known_metric("foo"));
+            assert_eq!(fetch_windows(&store, "foo"), vec![(0, 30, 100)]);
+        })
+    }
+
+    #[test]
+    fn it_deletes_metric_by_canonical_name() {
+        with_test_store(|store| {
+            store
+                .insert(
+                    &"foo;region=us;az=b",
+                    TimeWindow::new(0, 30),
+                    build_sketch(),
+                )
+                .expect("Could not insert sketch");
+            store
+                .delete_metric(&"foo;az=b;region=us")
+                .expect("Could not delete metric");
+            let metrics: Vec<String> = store
+                .search("*".to_string())
+                .expect("Could not search metrics")
+                .collect();
+            assert!(metrics.is_empty());
+        })
+    }
+
+    #[test]
+    fn it_rejects_delete_with_invalid_metric_name() {
+        with_test_store(|store| match store.delete_metric(&"1foo") {
+            Err(StorageError::InvalidMetricName) => {}
+            r => panic!("Expected invalid metric name error, got {:?}", r),
+        })
+    }
+
+    #[test]
+    fn it_counts_no_metrics() {
+        with_test_store(|store| {
+            assert_eq!(store.metric_count().expect("Could not count metrics"), 0);
+            let metrics: Vec<String> = store
+                .list_metrics()
+                .expect("Could not list metrics")
+                .collect();
+            assert!(metrics.is_empty());
+        })
+    }
+
+    #[test]
+    fn it_counts_distinct_metrics() {
+        with_test_store(|store| {
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo (first)");
+            store
+                .insert(&"foo", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch foo (second)");
+            store
+                .insert(&"bar;region=us", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar");
+            store
+                .insert(&"baz", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch baz");
+            assert_eq!(store.metric_count().expect("Could not count metrics"), 3);
+            store
+                .delete_metric(&"baz")
+                .expect("Could not delete metric");
+            assert_eq!(store.metric_count().expect("Could not count metrics"), 2);
+        })
+    }
+
+    #[test]
+    fn it_lists_all_metrics_in_sorted_order() {
+        with_test_store(|store| {
+            for metric in ["zoo", "foo;region=us", "bar", "foo"].iter() {
+                store
+                    .insert(metric, TimeWindow::new(0, 30), build_sketch())
+                    .expect("Could not insert sketch");
+            }
+            let metrics: Vec<String> = store
+                .list_metrics()
+                .expect("Could not list metrics")
+                .collect();
+            assert_eq!(metrics, vec!["bar", "foo", "foo;region=us", "zoo"]);
+            assert_eq!(
+                store.metric_count().expect("Could not count metrics"),
+                metrics.len()
+            );
+        })
+    }
+
+    #[test]
+    fn it_round_trips_snapshot() {
+        with_test_store(|src| {
+            src.insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo (first)");
+            src.insert(&"foo", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch foo (second)");
+            src.insert(&"bar;region=us", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch bar");
+            let mut buf = Vec::new();
+            let count = src
+                .export_snapshot(&mut buf)
+                .expect("Could not export snapshot");
+            assert_eq!(count, 3);
+
+            with_test_store(move |dst| {
+                let count = dst
+                    .import_snapshot(&mut &buf[..])
+                    .expect("Could not import snapshot");
+                assert_eq!(count, 3);
+                for metric in ["foo", "bar;region=us"].iter() {
+                    let expected = fetch_windows(&src, metric);
+                    let actual = fetch_windows(&dst, metric);
+                    assert!(!actual.is_empty());
+                    assert_eq!(actual, expected);
+                }
+                let metrics: Vec<String> = dst
+                    .list_metrics()
+                    .expect("Could not list metrics")
+                    .collect();
+                assert_eq!(metrics, vec!["bar;region=us", "foo"]);
+            })
+        })
+    }
+
+    #[test]
+    fn it_backs_up_incrementally() {
+        with_test_store(|store| {
+            let backup_dir = format!("testbackup_{}", Uuid::new_v4());
+            store
+                .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+                .expect("Could not insert sketch foo (first)");
+            store
+                .backup(&backup_dir)
+                .expect("Could not back up (first)");
+            store
+                .insert(&"foo", TimeWindow::new(30, 60), build_sketch())
+                .expect("Could not insert sketch foo (second)");
+            store
+                .backup(&backup_dir)
+                .expect("Could not back up (second)");
+
+            // RocksDB writes one metadata file per backup
+            let meta_dir = Path::new(&backup_dir).join("meta");
+            let backup_count = fs::read_dir(&meta_dir)
+                .expect("Could not read backup metadata")
+                .count();
+            assert_eq!(backup_count, 2);
+
+            fs::remove_dir_all(&backup_dir).expect("Could not delete backup directory");
+        })
+    }
+
+    #[test]
+    fn it_restores_backup_over_modified_data() {
+        let path = format!("testdb_{}", Uuid::new_v4());
+        let backup_dir = format!("testbackup_{}", Uuid::new_v4());
+        let store = MetricStore::open(&path).expect("Could not open DB");
+        store
+            .insert(&"foo", TimeWindow::new(0, 30), build_sketch())
+            .expect("Could not insert sketch foo");
+        store.backup(&backup_dir).expect("Could not back up");
+
+        // Changes after the latest backup are lost on restore
+        store
+            .delete_metric(&"foo")
+            .expect("Could not delete metric");
+        store
+            .insert(&"bar", TimeWindow::new(0, 30), build_sketch())
+            .expect("Could not insert sketch bar");
+        drop(store);
+
+        MetricStore::restore(&backup_dir, &path).expect("Could not restore");
+        let restored = MetricStore::open(&path).expect("Could not open restored DB");
+        assert_eq!(fetch_windows(&restored, "foo"), vec![(0, 30, 100)]);
+        assert!(fetch_windows(&restored, "bar").is_empty());
+        let metrics: Vec<String> = restored
+            .list_metrics()
+            .expect("Could not list metrics")
+            .collect();
+        assert_eq!(metrics, vec!["foo"]);
+
+        drop(restored);
+        MetricStore::destroy(&path).expect("Could not destroy DB");
+        fs::remove_dir_all(&backup_dir).expect("Could not delete backup directory");
+    }
+
+    #[test]
+    fn it_rejects_restore_without_backup() {
+        let backup_dir = format!("testbackup_{}", Uuid::new_v4());
+        let path = format!("testdb_{}", Uuid::new_v4());
+        match MetricStore::restore(&backup_dir, &path) {
+            Err(StorageError::RestoreError(_)) => {}
+            r => panic!("Expected restore error, got {:?}", r),
+        }
+        let _ = fs::remove_dir_all(&backup_dir);
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn it_round_trips_empty_snapshot() {
+        with_test_store(|store| {
+            let mut buf = Vec::new();
+            let count = store
+                .export_snapshot(&mut buf)
+                .expect("Could not export snapshot");
+            assert_eq!(count, 0);
+            let count = store
+                .import_snapshot(&mut &buf[..])
+                .expect("Could not import snapshot");
+            assert_eq!(count, 0);
+        })
+    }
+
+    #[test]
+    fn it_rejects_snapshot_with_invalid_header() {
+        with_test_store(|store| {
+            let buf = b"NOTASNAPSHOT".to_vec();
+            match store.import_snapshot(&mut &buf[..]) {
+                Err(StorageError::EncodableError(EncodableError::FormatError(_))) => {}
+                r => panic!("Expected format error, got {:?}", r),
+            }
+        })
+    }
+
+    #[test]
+    fn it_resumes_capped_downsample_passes() {
+        with_test_store(|store| {
+            insert_windows_for_downsampling(&store);
+            let full_pass = RecordingStrategy::new(DownsampleAction::Ignore);
+            store.downsample(&full_pass).expect("Could not downsample");
+
+            let capped_pass = RecordingStrategy::new(DownsampleAction::Ignore);
+            let done = store
+                .downsample_incremental(&capped_pass, 3)
+                .expect("Could not downsample first chunk");
+            assert!(!done);
+            assert_eq!(capped_pass.windows().len(), 3);
+            let done = store
+                .downsample_incremental(&capped_pass, 3)
+                .expect("Could not downsample second chunk");
+            assert!(done);
+            assert_eq!(capped_pass.windows(), full_pass.windows());
+        })
+    }
+
+    #[test]
+    fn it_finishes_pass_that_ends_at_max_keys() {
+        with_test_store(|store| {
+            insert_windows_for_downsampling(&store);
+            let strategy = RecordingStrategy::new(DownsampleAction::Ignore);
+            let done = store
+                .downsample_incremental(&strategy, 5)
+                .expect("Could not downsample");
+            assert!(done);
+            assert_eq!(strategy.windows().len(), 5);
+
+            // The next call starts a new pass from the first key
+            let next_pass = RecordingStrategy::new(DownsampleAction::Ignore);
+            let done = store
+                .downsample_incremental(&next_pass, 5)
+                .expect("Could not downsample");
+            assert!(done);
+            assert_eq!(next_pass.windows(), strategy.windows());
+        })
+    }
+
+    #[test]
+    fn it_resumes_interrupted_downsample_pass_after_reopen() {
+        let path = format!("testdb_{}", Uuid::new_v4());
+        MetricStore::destroy(&path).expect("Setup: could not destroy old test DB");
+        let result = panic::catch_unwind(|| {
+            let full_pass = RecordingStrategy::new(DownsampleAction::Ignore);
+            let first_chunk = RecordingStrategy::new(DownsampleAction::Ignore);
+            {
+                let store = MetricStore::open(&path).expect("Could not open test DB");
+                insert_windows_for_downsampling(&store);
+                store.downsample(&full_pass).expect("Could not downsample");
+                let done = store
+                    .downsample_incremental(&first_chunk, 2)
+                    .expect("Could not downsample first chunk");
+                assert!(!done);
+            }
+
+            // Reopening the store simulates a restart in the middle of the pass
+            let store = MetricStore::open(&path).expect("Could not reopen test DB");
+            let rest = RecordingStrategy::new(DownsampleAction::Ignore);
+            let done = store
+                .downsample_incremental(&rest, 10)
+                .expect("Could not downsample rest of pass");
+            assert!(done);
+            let mut windows = first_chunk.windows();
+            windows.extend(rest.windows());
+            assert_eq!(windows, full_pass.windows());
+        });
+        MetricStore::destroy(&path).expect("Teardown: could not destroy test DB");
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    fn it_ages_windows_against_clock_advancing_mid_pass() {
+        with_test_store(|store| {
+            insert_windows_for_downsampling(&store);
+            let clock = MockClock::new(60);
+            let strategy = TieredStrategy::new(&clock, vec![(100, 30)]);
+
+            // Every window is less than 100 seconds old, so the first chunk keeps bar
+            let done = store
+                .downsample_incremental(&strategy, 2)
+                .expect("Could not downsample first chunk");
+            assert!(!done);
+
+            // By the second chunk, every foo window is too old and is discarded
+            clock.tick(100);
+            while !store
+                .downsample_incremental(&strategy, 2)
+                .expect("Could not downsample")
+            {}
+            assert_eq!(fetch_windows(&store, "bar").len(), 2);
+            assert!(fetch_windows(&store, "foo").is_empty());
+        })
+    }
+
+    #[test]
+    fn it_applies_retention_override_by_metric_name() {
+        with_test_store(|store| {
+            insert_windows_for_downsampling(&store);
+            let clock = MockClock::new(200);
+            let strategy = DefaultStrategy::new(&clock)
+                .with_tiers(vec![(100, 30)])
+                .with_overrides(vec![("b*".to_string(), vec![(1000, 60)])]);
+            store.downsample(&strategy).expect("Could not downsample");
+            assert!(fetch_windows(&store, "foo").is_empty());
+            assert_eq!(fetch_windows(&store, "bar"), vec![(0, 60, 200)]);
+        })
+    }
+
+    #[test]
+    fn it_reports_downsample_actions_without_changing_windows() {
+        with_test_store(|store| {
+            insert_windows_for_downsampling(&store);
+            store
+                .insert(&"foo", TimeWindow::new(150, 180), build_sketch())
+                .expect("Could not insert sketch foo");
+            let clock = MockClock::new(200);
+            let strategy = DefaultStrategy::new(&clock)
+                .with_tiers(vec![(100, 30)])
+                .with_overrides(vec![("b*".to_string(), vec![(1000, 60)])]);
+
+            let report = store
+                .downsample_dry_run(&strategy)
+                .expect("Could not run dry run");
+            let foo_counts = DownsampleCounts {
+                ignore: 1,
+                discard: 3,
+                expand_window: 0,
+            };
+            let bar_counts = DownsampleCounts {
+                ignore: 0,
+                discard: 0,
+                expand_window: 2,
+            };
+            assert_eq!(report.metrics.len(), 2);
+            assert_eq!(report.metrics["foo"], foo_counts);
+            assert_eq!(report.metrics["bar"], bar_counts);
+            assert_eq!(fetch_windows(&store, "foo").len(), 4);
+            assert_eq!(fetch_windows(&store, "bar").len(), 2);
+
+            let reporting = ReportingStrategy::new(&strategy);
+            store.downsample(&reporting).expect("Could not downsample");
+            assert_eq!(reporting.report(), report);
+            assert_eq!(fetch_windows(&store, "foo"), vec![(150, 180, 100)]);
+            assert_eq!(fetch_windows(&store, "bar"), vec![(0, 60, 200)]);
+        })
+    }
+
+    #[test]
+    fn it_restarts_downsample_after_completed_pass() {
+        with_test_store(|store| {
+            insert_windows_for_downsampling(&store);
+            let first_pass = RecordingStrategy::new(DownsampleAction::Ignore);
+            assert!(store
+                .downsample_incremental(&first_pass, 10)
+                .expect("Could not downsample"));
+            let second_pass = RecordingStrategy::new(DownsampleAction::Ignore);
+            assert!(store
+                .downsample_incremental(&second_pass, 10)
+                .expect("Could not downsample"));
+            assert_eq!(first_pass.windows().len(), 5);
+            assert_eq!(second_pass.windows(), first_pass.windows());
+        })
+    }
+
+    #[test]
+    fn it_expands_each_window_once_across_capped_passes() {
+        with_test_store(|store| {
+            insert_windows_for_downsampling(&store);
+            let strategy =
+                RecordingStrategy::new(DownsampleAction::ExpandWindow(TimeWindow::new(0, 1000)));
+            while !store
+                .downsample_incremental(&strategy, 2)
+                .expect("Could not downsample")
+            {}
+            // Keys are ordered by metric, and expanded windows are never revisited
+            assert_eq!(
+                strategy.windows(),
+                vec![
+                    TimeWindow::new(0, 30),
+                    TimeWindow::new(30, 60),
+                    TimeWindow::new(0, 30),
+                    TimeWindow::new(30, 60),
+                    TimeWindow::new(60, 90),
+                ]
+            );
+
+            let foo_rows: Vec<DataRow> = store
+                .fetch("foo".to_string(), None, None)
+                .expect("Could not fetch foo")
+                .collect();
+            assert_eq!(foo_rows.len(), 1);
+            assert_eq!(foo_rows[0].window, TimeWindow::new(0, 1000));
+            assert_eq!(foo_rows[0].sketch.count(), 300);
+        })
+    }
+
+    fn insert_windows_for_downsampling(store: &MetricStore) {
+        for start in [0, 30, 60].iter() {
+            store
+                .insert(&"foo", TimeWindow::new(*start, *start + 30), build_sketch())
+                .expect("Could not insert sketch foo");
+        }
+        for start in [0, 30].iter() {
+            store
+                .insert(&"bar", TimeWindow::new(*start, *start + 30), build_sketch())
+                .expect("Could not insert sketch bar");
+        }
+    }
+
     fn with_test_store<T>(test: T) -> ()
     where
         T: FnOnce(MetricStore) -> () + panic::UnwindSafe,
@@ -645,6 +2590,14 @@ mod tests {
         assert!(result.is_ok())
     }
 
+    fn fetch_windows(store: &MetricStore, metric: &str) -> Vec<(TimeStamp, TimeStamp, usize)> {
+        store
+            .fetch(metric.to_string(), None, None)
+            .expect("Could not fetch range")
+            .map(|row| (row.window.start(), row.window.end(), row.sketch.count()))
+            .collect()
+    }
+
     fn build_sketch_with_values(values: Vec<u32>) -> WritableSketch {
         let mut s = WritableSketch::new();
         for &i in values.iter() {
@@ -684,8 +2637,60 @@ mod tests {
     }
 
     impl DownsampleStrategy for MockStrategy {
-        fn get_action(&self, _: TimeWindow) -> DownsampleAction {
+        fn get_action(&self, _: &str, _: TimeWindow) -> DownsampleAction {
             self.action.clone()
         }
     }
+
+    struct RecordingStrategy {
+        action: DownsampleAction,
+        windows: RefCell<Vec<TimeWindow>>,
+    }
+
+    impl RecordingStrategy {
+        fn new(action: DownsampleAction) -> RecordingStrategy {
+            RecordingStrategy {
+                action,
+                windows: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn windows(&self) -> Vec<TimeWindow> {
+            self.windows.borrow().clone()
+        }
+    }
+
+    impl DownsampleStrategy for RecordingStrategy {
+        fn get_action(&self, _: &str, window: TimeWindow) -> DownsampleAction {
+            self.windows.borrow_mut().push(window);
+            self.action.clone()
+        }
+    }
+
+    // Records the actions actually taken by a downsample pass
+    struct ReportingStrategy<'a, T: 'a> {
+        inner: &'a T,
+        report: RefCell<DownsampleReport>,
+    }
+
+    impl<'a, T: DownsampleStrategy> ReportingStrategy<'a, T> {
+        fn new(inner: &'a T) -> ReportingStrategy<'a, T> {
+            ReportingStrategy {
+                inner,
+                report: RefCell::new(DownsampleReport::new()),
+            }
+        }
+
+        fn report(self) -> DownsampleReport {
+            self.report.into_inner()
+        }
+    }
+
+    impl<'a, T: DownsampleStrategy> DownsampleStrategy for ReportingStrategy<'a, T> {
+        fn get_action(&self, metric: &str, window: TimeWindow) -> DownsampleAction {
+            let action = self.inner.get_action(metric, window);
+            self.report.borrow_mut().record(metric, &action);
+            action
+        }
+    }
 }
@@ -1,10 +1,100 @@
 use caesium_core::encode::{Decodable, Encodable, EncodableError};
 use caesium_core::quantile::writable::WritableSketch;
 use caesium_core::time::window::TimeWindow;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use lz4_flex;
 use std::cmp::{max, min};
 use std::io::{Read, Write};
+use std::str::FromStr;
 use storage::datasource::DataRow;
 
+// Compressed values start with this marker, followed by a one-byte codec tag
+// and the compressed bytes.  Uncompressed values start with the window start,
+// which is always less than the window end, so it can never equal the marker.
+const COMPRESSED_MARKER: u64 = u64::MAX;
+const LZ4_TAG: u8 = 1;
+const GZIP_TAG: u8 = 2;
+
+// Each byte of an lz4 block expands to at most this many bytes, so a size prefix
+// larger than this multiple of the compressed length can only come from a corrupt value
+const MAX_LZ4_RATIO: usize = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ValueCompression {
+    #[default]
+    None,
+    Lz4,
+    Gzip,
+}
+
+impl FromStr for ValueCompression {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<ValueCompression, &'static str> {
+        match s {
+            "none" => Ok(ValueCompression::None),
+            "lz4" => Ok(ValueCompression::Lz4),
+            "gzip" => Ok(ValueCompression::Gzip),
+            _ => Err("Invalid VALUE_COMPRESSION"),
+        }
+    }
+}
+
+impl ValueCompression {
+    // Returns the compression used for a stored value
+    pub fn of_bytes(bytes: &[u8]) -> ValueCompression {
+        match split_compressed(bytes) {
+            Some((LZ4_TAG, _)) => ValueCompression::Lz4,
+            Some((GZIP_TAG, _)) => ValueCompression::Gzip,
+            _ => ValueCompression::None,
+        }
+    }
+
+    fn compress(&self, raw: Vec<u8>) -> Result<Vec<u8>, EncodableError> {
+        let tag = match *self {
+            ValueCompression::None => return Ok(raw),
+            ValueCompression::Lz4 => LZ4_TAG,
+            ValueCompression::Gzip => GZIP_TAG,
+        };
+        let mut buf = Vec::with_capacity(raw.len() / 2);
+        COMPRESSED_MARKER.encode(&mut buf)?;
+        tag.encode(&mut buf)?;
+        match *self {
+            ValueCompression::Lz4 => buf.extend_from_slice(&lz4_flex::compress_prepend_size(&raw)),
+            _ => {
+                let mut encoder = GzEncoder::new(buf, Compression::fast());
+                encoder.write_all(&raw)?;
+                buf = encoder.finish()?;
+            }
+        }
+        Ok(buf)
+    }
+}
+
+fn split_compressed(bytes: &[u8]) -> Option<(u8, &[u8])> {
+    let mut reader = bytes;
+    match (u64::decode(&mut reader), u8::decode(&mut reader)) {
+        (Ok(COMPRESSED_MARKER), Ok(tag)) => Some((tag, reader)),
+        _ => None,
+    }
+}
+
+// Checks the size prefix before decompressing, since it decides how much memory
+// lz4 allocates for the output
+fn decompress_lz4(compressed: &[u8]) -> Result<Vec<u8>, EncodableError> {
+    let mut reader = compressed;
+    let raw_len = u32::decode(&mut reader)? as usize;
+    if raw_len > reader.len().saturating_mul(MAX_LZ4_RATIO) {
+        return Err(EncodableError::FormatError(
+            "Decompressed lz4 value length too large",
+        ));
+    }
+    lz4_flex::decompress_size_prepended(compressed)
+        .map_err(|_| EncodableError::FormatError("Could not decompress lz4 value"))
+}
+
 pub struct StorageValue {
     window: TimeWindow,
     sketch: WritableSketch,
@@ -15,21 +105,37 @@ impl StorageValue {
         StorageValue { window, sketch }
     }
 
-    pub fn as_bytes(window: TimeWindow, sketch: WritableSketch) -> Result<Vec<u8>, EncodableError> {
-        let mut buf = Vec::new();
-        let val = StorageValue::new(window, sketch);
-        val.encode(&mut buf)?;
-        Ok(buf)
+    pub fn as_bytes(
+        window: TimeWindow,
+        sketch: WritableSketch,
+        compression: ValueCompression,
+    ) -> Result<Vec<u8>, EncodableError> {
+        StorageValue::new(window, sketch).to_bytes(compression)
+    }
+
+    // Decodes a stored value, which may or may not be compressed
+    pub fn from_bytes(bytes: &[u8]) -> Result<StorageValue, EncodableError> {
+        match split_compressed(bytes) {
+            None => StorageValue::decode(&mut &bytes[..]),
+            Some((LZ4_TAG, compressed)) => {
+                let raw = decompress_lz4(compressed)?;
+                StorageValue::decode(&mut &raw[..])
+            }
+            Some((GZIP_TAG, compressed)) => StorageValue::decode(&mut GzDecoder::new(compressed)),
+            Some(_) => Err(EncodableError::FormatError(
+                "Unknown compression for stored value",
+            )),
+        }
     }
 
     pub fn with_window(self, new_window: TimeWindow) -> StorageValue {
         StorageValue::new(new_window, self.sketch)
     }
 
-    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodableError> {
+    pub fn to_bytes(&self, compression: ValueCompression) -> Result<Vec<u8>, EncodableError> {
         let mut buf = Vec::new();
         self.encode(&mut buf)?;
-        Ok(buf)
+        compression.compress(buf)
     }
 
     pub fn to_data_row(self) -> DataRow {
@@ -39,12 +145,13 @@ impl StorageValue {
         }
     }
 
-    pub fn merge(self, other: StorageValue) -> StorageValue {
+    // Leaves this value unchanged if the sketches have different scales
+    pub fn merge_from(&mut self, other: StorageValue) -> Result<(), EncodableError> {
+        self.sketch.merge_from(other.sketch)?;
         let start = min(self.window.start(), other.window.start());
         let end = max(self.window.end(), other.window.end());
-        let window = TimeWindow::new(start, end);
-        let sketch = self.sketch.merge(other.sketch);
-        StorageValue::new(window, sketch)
+        self.window = TimeWindow::new(start, end);
+        Ok(())
     }
 
     pub fn window(&self) -> TimeWindow {
@@ -74,3 +181,101 @@ where
         Ok(val)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_uncompressed_value() {
+        assert_round_trips(ValueCompression::None);
+    }
+
+    #[test]
+    fn it_round_trips_lz4_value() {
+        assert_round_trips(ValueCompression::Lz4);
+    }
+
+    #[test]
+    fn it_round_trips_gzip_value() {
+        assert_round_trips(ValueCompression::Gzip);
+    }
+
+    #[test]
+    fn it_decodes_legacy_uncompressed_value() {
+        let val = build_value();
+        let mut legacy = Vec::new();
+        val.encode(&mut legacy).expect("Could not encode value");
+        assert_eq!(ValueCompression::of_bytes(&legacy), ValueCompression::None);
+        let decoded = StorageValue::from_bytes(&legacy).expect("Could not decode value");
+        assert_same_value(&decoded, &val);
+    }
+
+    #[test]
+    fn it_rejects_unknown_compression_tag() {
+        let mut bytes = Vec::new();
+        COMPRESSED_MARKER.encode(&mut bytes).unwrap();
+        99u8.encode(&mut bytes).unwrap();
+        match StorageValue::from_bytes(&bytes) {
+            Err(EncodableError::FormatError(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|v| v.window())),
+        }
+    }
+
+    #[test]
+    fn it_rejects_lz4_value_with_oversized_length() {
+        let val = build_value();
+        let mut bytes = val.to_bytes(ValueCompression::Lz4).unwrap();
+        // The lz4 length prefix follows the marker and the codec tag
+        let prefix_start = 8 + 1;
+        bytes[prefix_start..prefix_start + 4].copy_from_slice(&[0xff; 4]);
+        match StorageValue::from_bytes(&bytes) {
+            Err(EncodableError::FormatError(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|v| v.window())),
+        }
+    }
+
+    #[test]
+    fn it_parses_value_compression() {
+        assert_eq!("lz4".parse(), Ok(ValueCompression::Lz4));
+        assert_eq!("gzip".parse(), Ok(ValueCompression::Gzip));
+        assert_eq!("none".parse(), Ok(ValueCompression::None));
+        assert!("zstd".parse::<ValueCompression>().is_err());
+    }
+
+    #[test]
+    fn it_compresses_large_sketch() {
+        let val = build_value();
+        let raw = val.to_bytes(ValueCompression::None).unwrap();
+        let lz4 = val.to_bytes(ValueCompression::Lz4).unwrap();
+        let gzip = val.to_bytes(ValueCompression::Gzip).unwrap();
+        assert!(lz4.len() < raw.len());
+        assert!(gzip.len() < raw.len());
+    }
+
+    fn assert_round_trips(compression: ValueCompression) {
+        let val = build_value();
+        let bytes = val.to_bytes(compression).expect("Could not encode value");
+        assert_eq!(ValueCompression::of_bytes(&bytes), compression);
+        let decoded = StorageValue::from_bytes(&bytes).expect("Could not decode value");
+        assert_same_value(&decoded, &val);
+    }
+
+    fn assert_same_value(actual: &StorageValue, expected: &StorageValue) {
+        assert_eq!(actual.window, expected.window);
+        assert_eq!(actual.sketch.count(), expected.sketch.count());
+        let mut actual_bytes = Vec::new();
+        let mut expected_bytes = Vec::new();
+        actual.sketch.encode(&mut actual_bytes).unwrap();
+        expected.sketch.encode(&mut expected_bytes).unwrap();
+        assert_eq!(actual_bytes, expected_bytes);
+    }
+
+    fn build_value() -> StorageValue {
+        let mut sketch = WritableSketch::new();
+        for i in 0..10000 {
+            sketch.insert(i % 100);
+        }
+        StorageValue::new(TimeWindow::new(30, 60), sketch)
+    }
+}
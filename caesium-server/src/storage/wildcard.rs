@@ -1,3 +1,40 @@
+use caesium_core::metric::{MetricName, TAG_ASSIGN, TAG_SEPARATOR};
+
+// Matches a metric name against a pattern of the form `base;key=value;...`.
+// The base and tag values may contain wildcards, and a metric matches
+// if its base matches and it has every tag in the pattern.
+pub fn metric_match(candidate: &str, pattern: &str) -> bool {
+    let metric = match MetricName::parse(candidate) {
+        Some(m) => m,
+        None => return false,
+    };
+    let mut parts = pattern.split(TAG_SEPARATOR);
+    let base_pattern = parts.next().unwrap_or("");
+    if !wildcard_match(metric.base(), base_pattern) {
+        return false;
+    }
+    parts.all(|tag_pattern| {
+        let mut kv = tag_pattern.splitn(2, TAG_ASSIGN);
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value_pattern)) => metric
+                .tag(key)
+                .map(|value| wildcard_match(value, value_pattern))
+                .unwrap_or(false),
+            _ => false,
+        }
+    })
+}
+
+// Every metric matching the pattern starts with this prefix
+pub fn metric_pattern_prefix(pattern: &str) -> String {
+    let base_pattern = pattern.split(TAG_SEPARATOR).next().unwrap_or("");
+    exact_prefix(base_pattern)
+}
+
+pub fn has_wildcard(pattern: &str) -> bool {
+    pattern.contains('*')
+}
+
 pub fn wildcard_match(candidate: &str, pattern: &str) -> bool {
     // Value at table[i][j] represents
     // whether the string candidate[..i] matches pattern p[..j]
@@ -169,6 +206,44 @@ mod tests {
         assert_prefix("*bar", "");
     }
 
+    #[test]
+    fn it_matches_metric_by_base_name() {
+        assert!(metric_match("foo", "foo"));
+        assert!(metric_match("foo;region=us", "foo"));
+        assert!(metric_match("foo;region=us", "f*"));
+        assert!(!metric_match("foo;region=us", "bar"));
+    }
+
+    #[test]
+    fn it_matches_metric_by_tag() {
+        assert!(metric_match(
+            "foo;endpoint=/login;region=us",
+            "foo;region=us"
+        ));
+        assert!(metric_match("foo;endpoint=/login;region=us", "*;region=us"));
+        assert!(metric_match(
+            "foo;endpoint=/login;region=us",
+            "foo;region=u*"
+        ));
+        assert!(metric_match(
+            "foo;endpoint=/login;region=us",
+            "foo;region=us;endpoint=/login"
+        ));
+        assert!(!metric_match(
+            "foo;endpoint=/login;region=us",
+            "foo;region=eu"
+        ));
+        assert!(!metric_match("foo;endpoint=/login", "foo;region=us"));
+        assert!(!metric_match("foo;region=us", "foo;region"));
+    }
+
+    #[test]
+    fn it_extracts_prefix_from_metric_pattern() {
+        assert_eq!(metric_pattern_prefix("foo;region=us"), "foo");
+        assert_eq!(metric_pattern_prefix("fo*;region=us"), "fo");
+        assert_eq!(metric_pattern_prefix("*;region=us"), "");
+    }
+
     fn assert_no_match(candidate: &str, pattern: &str) {
         println!(
             "assert no match for candidate '{}' using pattern '{}'",
@@ -1,30 +1,178 @@
 extern crate caesium_core;
 extern crate caesium_server;
+extern crate prost;
+extern crate rcgen;
 extern crate regex;
+extern crate reqwest;
+extern crate rustls;
+extern crate snap;
 extern crate uuid;
 
 #[macro_use]
 extern crate lazy_static;
 
 use caesium_core::encode::frame::FrameEncoder;
-use caesium_core::protocol::messages::InsertMessage;
+use caesium_core::encode::Encodable;
+use caesium_core::get_sketch_type;
+use caesium_core::protocol::messages::{AuthMessage, InsertMessage, MetricKind, WriteMessage};
+use caesium_core::protocol::PROTOCOL_VERSION;
 use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::clock::{Clock, SystemClock};
 use caesium_core::time::timestamp::TimeStamp;
 use caesium_core::time::window::TimeWindow;
+use caesium_server::server::auth::AuthToken;
+use caesium_server::server::health::HealthServer;
+use caesium_server::server::http::HttpQueryServer;
+use caesium_server::server::metrics::{MetricsServer, ServerMetrics};
+use caesium_server::server::prometheus::proto::{Label, Sample, TimeSeries, WriteRequest};
+use caesium_server::server::prometheus::PrometheusWriteServer;
 use caesium_server::server::read::ReadServer;
+use caesium_server::server::scrape::PrometheusScrapeServer;
+use caesium_server::server::tls::{load_certs, load_private_key, load_server_config};
 use caesium_server::server::write::WriteServer;
-use caesium_server::storage::store::MetricStore;
+use caesium_server::storage::store::{MetricStore, MetricStoreOptions};
+use prost::Message;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
 use regex::Regex;
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerName, StreamOwned,
+};
+use std::convert::TryFrom;
 use std::env;
 use std::fs;
-use std::io::{Read, Write};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::net::{Shutdown, SocketAddr, TcpStream};
 use std::panic;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use uuid::Uuid;
 
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+#[test]
+fn it_queries_metrics_over_http() {
+    with_http_query_server(|mut insert_client, http_client| {
+        insert_client.insert(&"m1", 0, 30);
+        insert_client.insert(&"m1", 30, 60);
+        thread::sleep(Duration::from_millis(500));
+        let (status, body) = http_client.query("quantile(fetch(\"m1\"), 0.5)");
+        assert_eq!(status, 200);
+        assert!(body.starts_with("{\"results\":["));
+        assert!(body.contains("\"type\":\"quantile\",\"start\":0,\"end\":30,\"phi\":0.5"));
+        assert!(body.contains("\"type\":\"quantile\",\"start\":30,\"end\":60,\"phi\":0.5"));
+        let (status, body) = http_client.query("search(\"*\")");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            "{\"results\":[{\"type\":\"metric\",\"name\":\"m1\"}]}"
+        );
+        let (status, body) = http_client.query("nope(");
+        assert_eq!(status, 400);
+        assert_eq!(
+            body,
+            "{\"error\":\"Unexpected token `(`\",\"kind\":\"parse_error\"}"
+        );
+    });
+}
+
+#[test]
+fn it_exposes_server_metrics() {
+    with_metrics_server(|mut insert_client, query_client, metrics_client| {
+        let before = metrics_client.scrape();
+        assert!(before.contains("caesium_inserts_total 0\n"));
+        assert!(before.contains("caesium_stored_metrics 0\n"));
+        insert_client.insert(&"m1", 0, 30);
+        insert_client.insert(&"m2", 30, 60);
+        thread::sleep(Duration::from_millis(500));
+        query_client.query(&"search(\"*\")");
+        query_client.query(&"nope(");
+        let after = metrics_client.scrape();
+        assert!(after.contains("caesium_inserts_total 2\n"));
+        assert!(after.contains("caesium_query_duration_seconds_count 1\n"));
+        assert!(after.contains("caesium_query_errors_total 1\n"));
+        assert!(after.contains("caesium_stored_metrics 2\n"));
+    });
+}
+
+#[test]
+fn it_counts_http_queries_in_server_metrics() {
+    let server = start_server();
+    let http_client = HttpQueryClient::new(server.http_query_addr);
+    let metrics_client = ScrapeClient::new(server.metrics_addr);
+    let result = panic::catch_unwind(move || {
+        assert_eq!(http_client.query("search(\"*\")").0, 200);
+        assert_eq!(http_client.query("nope(").0, 400);
+        let body = metrics_client.scrape();
+        assert!(body.contains("caesium_query_duration_seconds_count 1\n"));
+        assert!(body.contains("caesium_query_errors_total 1\n"));
+    });
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_exposes_rocksdb_stats() {
+    let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let db_path = unique_tmp_db_path();
+    let options = MetricStoreOptions {
+        enable_statistics: true,
+        ..MetricStoreOptions::default()
+    };
+    let db = MetricStore::open_with_options(&db_path, options).expect("Could not open db");
+    let metrics_server =
+        MetricsServer::new(&server_addr, Arc::new(ServerMetrics::new()), Arc::new(db))
+            .expect("Could not start metrics server");
+    let client = HealthClient::new(
+        metrics_server
+            .local_addr()
+            .expect("Could not retrieve metrics server address"),
+    );
+    thread::spawn(move || metrics_server.run());
+    let result = panic::catch_unwind(|| {
+        let (status, body) = client.get("/admin/rocksdb-stats");
+        assert_eq!(status, 200);
+        assert!(!body.is_empty());
+        for line in body.lines() {
+            assert!(
+                line.starts_with("{\"name\":\"rocksdb."),
+                "unexpected line {}",
+                line
+            );
+            assert!(line.ends_with('}'), "unexpected line {}", line);
+        }
+    });
+    fs::remove_dir_all(&db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_hides_rocksdb_stats_when_disabled() {
+    let server = start_server();
+    let client = HealthClient::new(server.metrics_addr);
+    let result = panic::catch_unwind(move || {
+        assert_eq!(client.get("/admin/rocksdb-stats").0, 404);
+    });
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_serves_health_checks() {
+    with_health_server(|health_client| {
+        let expected = format!(
+            "{{\"status\":\"ok\",\"sketch_type\":\"{}\"}}",
+            get_sketch_type().name()
+        );
+        assert_eq!(health_client.get("/healthz"), (200, expected.clone()));
+        assert_eq!(health_client.get("/readyz"), (200, expected.clone()));
+        assert_eq!(health_client.get("/readyz?probe=1"), (200, expected));
+        assert_eq!(health_client.get("/livez").0, 404);
+    });
+}
+
 #[test]
 fn it_queries_metrics() {
     with_server(|mut insert_client, query_client| {
@@ -36,15 +184,530 @@ fn it_queries_metrics() {
         let r1 = query_client.query(&"search(\"*\")");
         assert_metric_names(&r1, &[&"m1", &"m2"]);
         let r2 = query_client.query(&"quantile(fetch(\"m2\"), 0.5)");
-        assert_windows(
-            &r2,
-            &vec![TimeWindow::new(60, 90), TimeWindow::new(90, 100)],
-        );
+        assert_windows(&r2, &[TimeWindow::new(60, 90), TimeWindow::new(90, 100)]);
         let r3 = query_client.query(&"quantile(fetch(\"m1\", 25, 70), 0.5)");
-        assert_windows(&r3, &vec![TimeWindow::new(30, 60)]);
+        assert_windows(&r3, &[TimeWindow::new(30, 60)]);
     })
 }
 
+#[test]
+fn it_returns_quantile_bounds() {
+    with_server(|mut insert_client, query_client| {
+        let mut sketch = WritableSketch::new();
+        for i in 0..10000 {
+            sketch
+                .insert_f64(i as f64 / 100.0, 2)
+                .expect("Could not insert value");
+        }
+        insert_client.insert_sketch(&"bounded", 0, 30, sketch);
+        thread::sleep(Duration::from_millis(500));
+        let resp = query_client.query(&"quantile(fetch(\"bounded\"), 0.1, 0.5, 0.9)");
+        let lines: Vec<&str> = resp.trim().split("\n").collect();
+        assert_eq!(lines.len(), 3, "Unexpected response: {}", resp);
+        for line in lines {
+            let approx = parse_result_field(line, "approx");
+            let lower = parse_result_field(line, "lower");
+            let upper = parse_result_field(line, "upper");
+            assert!(lower <= approx && approx <= upper, "{}", line);
+
+            // Values are sent unscaled, so they fall within the inserted range
+            assert!(0.0 <= lower && upper < 100.0, "{}", line);
+        }
+    })
+}
+
+#[test]
+fn it_returns_wide_range_results_in_order() {
+    with_server(|mut insert_client, query_client| {
+        let num_windows = 500;
+        for i in 0..num_windows {
+            insert_client.insert(&"m1", i * 30, (i + 1) * 30);
+        }
+        thread::sleep(Duration::from_millis(1000));
+        let expected: Vec<TimeWindow> = (0..num_windows)
+            .map(|i| TimeWindow::new(i * 30, (i + 1) * 30))
+            .collect();
+
+        let resp = query_client.query(&"quantile(fetch(\"m1\"), 0.5)");
+        let windows: Vec<TimeWindow> = resp.lines().filter_map(parse_window).collect();
+        assert_eq!(windows, expected);
+
+        let events = query_client.query_stream(&"quantile(fetch(\"m1\"), 0.5)");
+        let (last_event, _) = events.last().cloned().expect("Expected stream events");
+        assert_eq!(last_event, "end");
+        let streamed: Vec<TimeWindow> = events
+            .iter()
+            .filter(|(event, _)| event == "message")
+            .filter_map(|(_, data)| parse_window(data))
+            .collect();
+        assert_eq!(streamed, expected);
+    })
+}
+
+#[test]
+fn it_streams_query_results() {
+    with_server(|mut insert_client, query_client| {
+        insert_client.insert(&"m1", 0, 30);
+        insert_client.insert(&"m1", 30, 60);
+        insert_client.insert(&"m1", 60, 90);
+        thread::sleep(Duration::from_millis(500));
+        let events = query_client.query_stream(&"quantile(fetch(\"m1\"), 0.5)");
+        let (last_event, _) = events.last().cloned().expect("Expected stream events");
+        assert_eq!(last_event, "end");
+        let windows: Vec<TimeWindow> = events
+            .iter()
+            .filter(|(event, _)| event == "message")
+            .filter_map(|(_, data)| parse_window(data))
+            .collect();
+        assert_eq!(
+            windows,
+            vec![
+                TimeWindow::new(0, 30),
+                TimeWindow::new(30, 60),
+                TimeWindow::new(60, 90),
+            ]
+        );
+
+        let err_events = query_client.query_stream(&"quantile(fetch(\"m1\"), 2.0)");
+        let (err_event, err_data) = err_events.last().cloned().expect("Expected error event");
+        assert_eq!(err_event, "error");
+        assert_eq!(
+            err_data,
+            "phi_out_of_range: Phi must be between 0 and 1 (inclusive), got 2"
+        );
+    })
+}
+
+#[test]
+fn it_echoes_request_ids() {
+    with_server(|mut insert_client, query_client| {
+        insert_client.insert(&"m1", 0, 30);
+        thread::sleep(Duration::from_millis(500));
+        let r1 = query_client.query(&"request-id: 42\nsearch(\"*\")");
+        assert_eq!(r1, "request-id: 42\n\nm1\n");
+        let r2 = query_client.query(&"request-id: 7\nquantile(fetch(\"m1\"), 2.0)");
+        assert!(r2.starts_with("request-id: 7\n\n[ERROR] phi_out_of_range: "));
+        let r3 = query_client.query(&"search(\"*\")");
+        assert_eq!(r3, "m1\n");
+    })
+}
+
+#[test]
+fn it_returns_query_errors() {
+    with_server(|_insert_client, query_client| {
+        let r1 = query_client.query(&"quantile(fetch(\"foo\")");
+        assert_eq!(r1, "[ERROR] parse_error: Unexpected end of query\n");
+        let r2 = query_client.query(&"quantile(fetch(\"foo\")))");
+        assert_eq!(r2, "[ERROR] parse_error: Unexpected token `)`\n");
+        let r3 = query_client.query(&"nope(fetch(\"foo\"))");
+        assert_eq!(
+            r3,
+            "[ERROR] unrecognized_function: Unrecognized function `nope`\n"
+        );
+    })
+}
+
+#[test]
+fn it_rejects_unsupported_protocol_version() {
+    with_server(|mut insert_client, query_client| {
+        let mut bad_client = InsertClient::new(insert_client.stream.peer_addr().unwrap());
+        bad_client.insert_with_version(&"m1", 0, 30, PROTOCOL_VERSION + 1);
+        insert_client.insert(&"m2", 30, 60);
+        thread::sleep(Duration::from_millis(500));
+        let r = query_client.query(&"search(\"*\")");
+        assert_metric_names(&r, &[&"m2"]);
+    })
+}
+
+#[test]
+fn it_accepts_prometheus_remote_writes() {
+    with_prometheus_server(|prometheus_client, query_client, _| {
+        let req = WriteRequest {
+            timeseries: vec![
+                build_series("m1", &[(1.0, 1000), (2.0, 35000)]),
+                build_series("m2", &[(3.0, 61000)]),
+            ],
+        };
+        assert_eq!(prometheus_client.write(&req), 204);
+        let r1 = query_client.query(&"search(\"*\")");
+        assert_metric_names(&r1, &[&"m1", &"m2"]);
+        let r2 = query_client.query(&"quantile(fetch(\"m1\"), 0.5)");
+        assert_windows(&r2, &[TimeWindow::new(0, 10), TimeWindow::new(30, 40)]);
+    })
+}
+
+#[test]
+fn it_rejects_invalid_prometheus_remote_writes() {
+    with_prometheus_server(|prometheus_client, query_client, _| {
+        assert_eq!(prometheus_client.write_raw(b"not snappy".to_vec()), 400);
+        let not_proto = snap::raw::Encoder::new()
+            .compress_vec(&[0xff, 0xff, 0xff])
+            .expect("Could not compress body");
+        assert_eq!(prometheus_client.write_raw(not_proto), 400);
+        let r = query_client.query(&"search(\"*\")");
+        assert_eq!(r.trim(), "");
+    })
+}
+
+#[test]
+fn it_scrapes_decimal_prometheus_samples_in_original_units() {
+    with_prometheus_server(|prometheus_client, _, scrape_client| {
+        let now = SystemClock::new().now() as i64 * 1000;
+        let req = WriteRequest {
+            timeseries: vec![build_series(
+                "latency",
+                &[(0.25, now), (0.5, now), (0.75, now)],
+            )],
+        };
+        assert_eq!(prometheus_client.write(&req), 204);
+        let samples = parse_scrape(&scrape_client.scrape());
+        assert_eq!(
+            samples,
+            vec![
+                ("latency{quantile=\"0.5\"}".to_string(), 0.5),
+                ("latency{quantile=\"0.9\"}".to_string(), 0.75),
+                ("latency{quantile=\"0.99\"}".to_string(), 0.75),
+                ("latency_count".to_string(), 3.0),
+            ]
+        );
+    })
+}
+
+#[test]
+fn it_exposes_recent_quantiles_for_prometheus_scrapes() {
+    with_scrape_server(|mut insert_client, scrape_client| {
+        let now = SystemClock::new().now();
+        insert_client.insert(&"m1", 0, 30);
+        insert_client.insert(&"m1", now - 60, now - 30);
+        insert_client.insert(&"m2;region=us", now - 60, now - 30);
+        thread::sleep(Duration::from_millis(500));
+        let body = scrape_client.scrape();
+        let samples = parse_scrape(&body);
+        assert_eq!(
+            samples,
+            vec![
+                ("m1{quantile=\"0.5\"}".to_string(), 5.0),
+                ("m1{quantile=\"0.9\"}".to_string(), 9.0),
+                ("m1{quantile=\"0.99\"}".to_string(), 9.0),
+                ("m1_count".to_string(), 10.0),
+                ("m2{region=\"us\",quantile=\"0.5\"}".to_string(), 5.0),
+                ("m2{region=\"us\",quantile=\"0.9\"}".to_string(), 9.0),
+                ("m2{region=\"us\",quantile=\"0.99\"}".to_string(), 9.0),
+                ("m2_count{region=\"us\"}".to_string(), 10.0),
+            ]
+        );
+        assert!(body.contains("# TYPE m1 summary"));
+        assert!(body.contains("# TYPE m2 summary"));
+    })
+}
+
+#[test]
+fn it_inserts_and_queries_over_tls() {
+    let certs = TestCerts::generate();
+    let tls_config = load_server_config(&certs.server_cert_path, &certs.server_key_path, None)
+        .expect("Could not load TLS config");
+    let server = start_server_with_tls(Some(tls_config));
+    let client = TlsClient::new(&certs, false);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        client.insert(server.write_addr, &"m1", 0, 30);
+        client.insert(server.write_addr, &"m2", 30, 60);
+        thread::sleep(Duration::from_millis(500));
+        let r1 = client
+            .query(server.read_addr, &"search(\"*\")")
+            .expect("Could not query over TLS");
+        assert_metric_names(&r1, &[&"m1", &"m2"]);
+        let r2 = client
+            .query(server.read_addr, &"quantile(fetch(\"m2\"), 0.5)")
+            .expect("Could not query over TLS");
+        assert_windows(&r2, &[TimeWindow::new(30, 60)]);
+    }));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    fs::remove_dir_all(&certs.dir).expect("Could not delete cert directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_rejects_plaintext_inserts_to_tls_server() {
+    let certs = TestCerts::generate();
+    let tls_config = load_server_config(&certs.server_cert_path, &certs.server_key_path, None)
+        .expect("Could not load TLS config");
+    let server = start_server_with_tls(Some(tls_config));
+    let client = TlsClient::new(&certs, false);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut plaintext_client = InsertClient::new(server.write_addr);
+        // Send the frame in one write, since the server may close the connection
+        // as soon as it reads the start of the frame
+        let msg = InsertClient::build_msg(&"m1", 0, 30);
+        let mut buf = Vec::new();
+        FrameEncoder::new()
+            .encode_framed_msg(&msg, &mut buf)
+            .expect("Could not encode framed message");
+        let _ = plaintext_client.stream.write_all(&buf);
+
+        // The server replies with a TLS alert, then closes the connection
+        let mut resp = Vec::new();
+        let _ = plaintext_client.stream.read_to_end(&mut resp);
+        assert!(!resp.is_empty());
+        let r = client
+            .query(server.read_addr, &"search(\"*\")")
+            .expect("Could not query over TLS");
+        assert_eq!(r, "");
+    }));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    fs::remove_dir_all(&certs.dir).expect("Could not delete cert directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_requires_client_certificates_for_mutual_tls() {
+    let certs = TestCerts::generate();
+    let tls_config = load_server_config(
+        &certs.server_cert_path,
+        &certs.server_key_path,
+        Some(&certs.ca_cert_path),
+    )
+    .expect("Could not load TLS config");
+    let server = start_server_with_tls(Some(tls_config));
+    let client = TlsClient::new(&certs, true);
+    let anonymous_client = TlsClient::new(&certs, false);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        client.insert(server.write_addr, &"m1", 0, 30);
+        thread::sleep(Duration::from_millis(500));
+        let r = client
+            .query(server.read_addr, &"search(\"*\")")
+            .expect("Could not query over mutual TLS");
+        assert_metric_names(&r, &[&"m1"]);
+        assert!(anonymous_client
+            .query(server.read_addr, &"search(\"*\")")
+            .is_err());
+    }));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    fs::remove_dir_all(&certs.dir).expect("Could not delete cert directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_requires_auth_token() {
+    let server = start_server_with_options(None, Some(AuthToken::new("secret")));
+    let query_client = QueryClient::new(server.read_addr);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        // Inserts sent before a valid auth message are never stored
+        let mut unauthenticated = InsertClient::new(server.write_addr);
+        unauthenticated.insert(&"m0", 0, 30);
+        assert!(is_closed_by_server(&unauthenticated.stream));
+
+        let mut wrong_token = InsertClient::new(server.write_addr);
+        wrong_token.authenticate("wrong");
+        assert!(is_closed_by_server(&wrong_token.stream));
+
+        let mut client = InsertClient::new(server.write_addr);
+        client.authenticate("secret");
+        client.insert(&"m1", 0, 30);
+        thread::sleep(Duration::from_millis(500));
+        assert!(!is_closed_by_server(&client.stream));
+
+        assert_eq!(query_client.query("search(\"*\")"), "");
+        assert_eq!(query_client.query("auth: wrong\nsearch(\"*\")"), "");
+        let r = query_client.query("auth: secret\nsearch(\"*\")");
+        assert_metric_names(&r, &[&"m1"]);
+    }));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_limits_insert_connections_per_ip() {
+    let db_path = unique_tmp_db_path();
+    let db_ref = Arc::new(MetricStore::open(&db_path).expect("Could not open db"));
+    let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let idle_timeout = Duration::from_secs(300);
+    let write_server = WriteServer::new(
+        &server_addr,
+        1,
+        4096,
+        2,
+        idle_timeout,
+        MAX_FRAME_LEN,
+        None,
+        None,
+        shutdown,
+        Arc::new(ServerMetrics::new()),
+        db_ref,
+    )
+    .expect("Could not start write server");
+    let addr = write_server
+        .local_addr()
+        .expect("Could not retrieve write server addr");
+    thread::spawn(move || write_server.run());
+
+    let result = panic::catch_unwind(|| {
+        let first = connect_with_read_timeout(addr);
+        let second = connect_with_read_timeout(addr);
+        let third = connect_with_read_timeout(addr);
+        assert!(is_closed_by_server(&third));
+        assert!(!is_closed_by_server(&first));
+        assert!(!is_closed_by_server(&second));
+
+        // Closing a connection frees a slot for the same IP
+        drop(first);
+        thread::sleep(Duration::from_millis(200));
+        let fourth = connect_with_read_timeout(addr);
+        assert!(!is_closed_by_server(&fourth));
+    });
+    fs::remove_dir_all(&db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_closes_insert_connections_with_oversized_frames() {
+    let db_path = unique_tmp_db_path();
+    let db_ref = Arc::new(MetricStore::open(&db_path).expect("Could not open db"));
+    let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let write_server = WriteServer::new(
+        &server_addr,
+        1,
+        4096,
+        10,
+        Duration::from_secs(300),
+        1024,
+        None,
+        None,
+        shutdown,
+        Arc::new(ServerMetrics::new()),
+        db_ref.clone(),
+    )
+    .expect("Could not start write server");
+    let addr = write_server
+        .local_addr()
+        .expect("Could not retrieve write server addr");
+    thread::spawn(move || write_server.run());
+
+    let result = panic::catch_unwind(|| {
+        // A prefix claiming a terabyte-long message, with no message behind it
+        let mut huge_prefix = connect_with_read_timeout(addr);
+        let mut prefix = Vec::new();
+        Encodable::encode(&PROTOCOL_VERSION, &mut prefix).expect("Could not encode version");
+        Encodable::encode(&(1usize << 40), &mut prefix).expect("Could not encode length");
+        Encodable::encode(&0u32, &mut prefix).expect("Could not encode checksum");
+        huge_prefix
+            .write_all(&prefix)
+            .expect("Could not send frame prefix");
+        assert!(is_closed_by_server(&huge_prefix));
+
+        // A complete frame just over the limit
+        let mut too_long = InsertClient::new(addr);
+        too_long.insert(&"a".repeat(1024), 0, 30);
+        assert!(is_closed_by_server(&too_long.stream));
+
+        // Frames under the limit are accepted, even when more than the limit
+        // arrives at once on a single connection
+        let mut buf = Vec::new();
+        let mut encoder = FrameEncoder::new();
+        for i in 0..50 {
+            let msg = InsertClient::build_msg(&format!("m{}", i), 0, 30);
+            encoder
+                .encode_framed_msg(&msg, &mut buf)
+                .expect("Could not encode framed message");
+        }
+        assert!(buf.len() > 1024);
+        let mut within_limit = connect_with_read_timeout(addr);
+        within_limit
+            .write_all(&buf)
+            .expect("Could not send framed messages");
+        assert!(!is_closed_by_server(&within_limit));
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(db_ref.metric_count().expect("Could not count metrics"), 50);
+    });
+    fs::remove_dir_all(&db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+#[test]
+fn it_closes_idle_insert_connections() {
+    let db_path = unique_tmp_db_path();
+    let db_ref = Arc::new(MetricStore::open(&db_path).expect("Could not open db"));
+    let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let idle_timeout = Duration::from_secs(1);
+    let write_server = WriteServer::new(
+        &server_addr,
+        1,
+        4096,
+        1,
+        idle_timeout,
+        MAX_FRAME_LEN,
+        None,
+        None,
+        shutdown,
+        Arc::new(ServerMetrics::new()),
+        db_ref,
+    )
+    .expect("Could not start write server");
+    let addr = write_server
+        .local_addr()
+        .expect("Could not retrieve write server addr");
+    thread::spawn(move || write_server.run());
+
+    let result = panic::catch_unwind(|| {
+        let idle = connect_with_read_timeout(addr);
+        assert!(!is_closed_by_server(&idle));
+        thread::sleep(Duration::from_millis(2500));
+        assert!(is_closed_by_server(&idle));
+
+        // Reaping the idle connection frees its slot for the same IP
+        let next = connect_with_read_timeout(addr);
+        assert!(!is_closed_by_server(&next));
+    });
+    fs::remove_dir_all(&db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+fn connect_with_read_timeout(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).expect("Could not connect to server");
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .expect("Could not set read timeout");
+    stream
+}
+
+// The write server never sends data, so a read only returns once the server closes the connection
+fn is_closed_by_server(mut stream: &TcpStream) -> bool {
+    let mut buf = [0; 1];
+    match stream.read(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => true,
+        Err(_) => false,
+    }
+}
+
+fn parse_scrape(body: &str) -> Vec<(String, f64)> {
+    body.lines()
+        .filter(|line| !line.starts_with("#"))
+        .map(|line| {
+            let mut parts = line.rsplitn(2, ' ');
+            let value = parts.next().unwrap().parse::<f64>().unwrap();
+            let series = parts.next().unwrap().to_string();
+            (series, value)
+        })
+        .collect()
+}
+
+fn build_series(metric: &str, samples: &[(f64, i64)]) -> TimeSeries {
+    TimeSeries {
+        labels: vec![Label {
+            name: "__name__".to_string(),
+            value: metric.to_string(),
+        }],
+        samples: samples
+            .iter()
+            .map(|&(value, timestamp)| Sample { value, timestamp })
+            .collect(),
+    }
+}
+
 struct InsertClient {
     stream: TcpStream,
     frame_encoder: FrameEncoder,
@@ -58,6 +721,9 @@ impl InsertClient {
         stream
             .set_write_timeout(Some(timeout))
             .expect("Could not set write timeout");
+        stream
+            .set_read_timeout(Some(timeout))
+            .expect("Could not set read timeout");
         InsertClient {
             stream,
             frame_encoder: FrameEncoder::new(),
@@ -65,16 +731,59 @@ impl InsertClient {
     }
 
     fn insert(&mut self, metric: &str, start: TimeStamp, end: TimeStamp) {
+        self.insert_sketch(metric, start, end, InsertClient::build_sketch());
+    }
+
+    fn insert_sketch(
+        &mut self,
+        metric: &str,
+        start: TimeStamp,
+        end: TimeStamp,
+        sketch: WritableSketch,
+    ) {
+        let msg = InsertClient::build_sketch_msg(metric, start, end, sketch);
+        self.frame_encoder
+            .encode_framed_msg(&msg, &mut self.stream)
+            .expect("Could not send framed message");
+    }
+
+    fn authenticate(&mut self, token: &str) {
+        let msg = WriteMessage::Auth(AuthMessage {
+            token: token.to_string(),
+        });
+        self.frame_encoder
+            .encode_framed_msg(&msg, &mut self.stream)
+            .expect("Could not send framed message");
+    }
+
+    fn insert_with_version(&mut self, metric: &str, start: TimeStamp, end: TimeStamp, version: u8) {
+        let mut buf = Vec::new();
+        self.frame_encoder
+            .encode_framed_msg(&InsertClient::build_msg(metric, start, end), &mut buf)
+            .expect("Could not encode framed message");
+        buf[0] = version;
+        self.stream
+            .write_all(&buf)
+            .expect("Could not send framed message");
+    }
+
+    fn build_msg(metric: &str, start: TimeStamp, end: TimeStamp) -> WriteMessage {
+        InsertClient::build_sketch_msg(metric, start, end, InsertClient::build_sketch())
+    }
+
+    fn build_sketch_msg(
+        metric: &str,
+        start: TimeStamp,
+        end: TimeStamp,
+        sketch: WritableSketch,
+    ) -> WriteMessage {
         let window = TimeWindow::new(start, end);
-        let sketch = InsertClient::build_sketch();
-        let msg = InsertMessage {
+        WriteMessage::Insert(Box::new(InsertMessage {
             metric: metric.to_string(),
+            kind: MetricKind::Timer,
             window,
             sketch,
-        };
-        self.frame_encoder
-            .encode_framed_msg(&msg, &mut self.stream)
-            .expect("Could not send framed message");
+        }))
     }
 
     fn build_sketch() -> WritableSketch {
@@ -86,6 +795,218 @@ impl InsertClient {
     }
 }
 
+// PEM files for a test CA, a server certificate for "localhost",
+// and a client certificate, all signed by the CA.
+struct TestCerts {
+    dir: String,
+    ca_cert_path: String,
+    server_cert_path: String,
+    server_key_path: String,
+    client_cert_path: String,
+    client_key_path: String,
+}
+
+impl TestCerts {
+    fn generate() -> TestCerts {
+        let mut dir = env::temp_dir();
+        dir.push(format!("testcerts_{}", Uuid::new_v4()));
+        fs::create_dir(&dir).expect("Could not create cert directory");
+        let path_for = |name: &str| {
+            let mut p = dir.clone();
+            p.push(name);
+            p.to_str()
+                .expect("Could not construct cert path")
+                .to_string()
+        };
+
+        let mut ca_params = CertificateParams::new(Vec::new());
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "caesium test CA");
+        let ca = Certificate::from_params(ca_params).expect("Could not generate CA");
+        let server =
+            Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+                .expect("Could not generate server certificate");
+        let client = Certificate::from_params(CertificateParams::new(vec!["client".to_string()]))
+            .expect("Could not generate client certificate");
+
+        let certs = TestCerts {
+            dir: dir.to_str().unwrap().to_string(),
+            ca_cert_path: path_for("ca.pem"),
+            server_cert_path: path_for("server.pem"),
+            server_key_path: path_for("server.key"),
+            client_cert_path: path_for("client.pem"),
+            client_key_path: path_for("client.key"),
+        };
+        let files = vec![
+            (&certs.ca_cert_path, ca.serialize_pem().unwrap()),
+            (
+                &certs.server_cert_path,
+                server.serialize_pem_with_signer(&ca).unwrap(),
+            ),
+            (&certs.server_key_path, server.serialize_private_key_pem()),
+            (
+                &certs.client_cert_path,
+                client.serialize_pem_with_signer(&ca).unwrap(),
+            ),
+            (&certs.client_key_path, client.serialize_private_key_pem()),
+        ];
+        for (path, contents) in files {
+            fs::write(path, contents).expect("Could not write cert file");
+        }
+        certs
+    }
+}
+
+struct TlsClient {
+    config: Arc<ClientConfig>,
+}
+
+impl TlsClient {
+    fn new(certs: &TestCerts, with_client_cert: bool) -> TlsClient {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&certs.ca_cert_path).expect("Could not load CA cert") {
+            roots.add(&cert).expect("Could not add CA cert");
+        }
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config = if with_client_cert {
+            let chain = load_certs(&certs.client_cert_path).expect("Could not load client cert");
+            let key = load_private_key(&certs.client_key_path).expect("Could not load client key");
+            builder
+                .with_client_auth_cert(chain, key)
+                .expect("Could not configure client cert")
+        } else {
+            builder.with_no_client_auth()
+        };
+        TlsClient {
+            config: Arc::new(config),
+        }
+    }
+
+    fn connect(&self, addr: SocketAddr) -> StreamOwned<ClientConnection, TcpStream> {
+        let timeout = Duration::from_millis(1000);
+        let stream =
+            TcpStream::connect_timeout(&addr, timeout).expect("Could not connect to server");
+        stream
+            .set_read_timeout(Some(timeout))
+            .expect("Could not set read timeout");
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let conn = ClientConnection::new(self.config.clone(), server_name)
+            .expect("Could not create TLS connection");
+        StreamOwned::new(conn, stream)
+    }
+
+    fn insert(&self, addr: SocketAddr, metric: &str, start: TimeStamp, end: TimeStamp) {
+        let mut stream = self.connect(addr);
+        FrameEncoder::new()
+            .encode_framed_msg(&InsertClient::build_msg(metric, start, end), &mut stream)
+            .expect("Could not send framed message");
+        // Wait for the server to close its side, so unread session tickets
+        // don't cause our socket to reset the connection before the insert is read.
+        stream.conn.send_close_notify();
+        stream.flush().expect("Could not close stream");
+        let _ = stream.read_to_end(&mut Vec::new());
+    }
+
+    fn query(&self, addr: SocketAddr, q: &str) -> Result<String, io::Error> {
+        let mut stream = self.connect(addr);
+        stream.write_all(q.as_bytes())?;
+        stream.conn.send_close_notify();
+        stream.flush()?;
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp)?;
+        Ok(resp)
+    }
+}
+
+struct PrometheusClient {
+    url: String,
+}
+
+impl PrometheusClient {
+    fn new(addr: SocketAddr) -> PrometheusClient {
+        let url = format!("http://{}/api/v1/write", addr);
+        PrometheusClient { url }
+    }
+
+    fn write(&self, req: &WriteRequest) -> u16 {
+        let mut buf = Vec::new();
+        req.encode(&mut buf)
+            .expect("Could not encode write request");
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&buf)
+            .expect("Could not compress write request");
+        self.write_raw(body)
+    }
+
+    fn write_raw(&self, body: Vec<u8>) -> u16 {
+        let resp = reqwest::Client::new()
+            .post(&self.url)
+            .body(body)
+            .send()
+            .expect("Could not send write request");
+        resp.status().as_u16()
+    }
+}
+
+struct ScrapeClient {
+    url: String,
+}
+
+impl ScrapeClient {
+    fn new(addr: SocketAddr) -> ScrapeClient {
+        let url = format!("http://{}/metrics", addr);
+        ScrapeClient { url }
+    }
+
+    fn scrape(&self) -> String {
+        let mut resp = reqwest::get(&self.url).expect("Could not send scrape request");
+        assert!(resp.status().is_success());
+        resp.text().expect("Could not read scrape response")
+    }
+}
+
+struct HttpQueryClient {
+    addr: SocketAddr,
+}
+
+impl HttpQueryClient {
+    fn new(addr: SocketAddr) -> HttpQueryClient {
+        HttpQueryClient { addr }
+    }
+
+    fn query(&self, q: &str) -> (u16, String) {
+        let url =
+            reqwest::Url::parse_with_params(&format!("http://{}/query", self.addr), &[("q", q)])
+                .expect("Could not construct query URL");
+        let mut resp = reqwest::get(url).expect("Could not send HTTP query");
+        let status = resp.status().as_u16();
+        let body = resp.text().expect("Could not read HTTP query response");
+        (status, body)
+    }
+}
+
+struct HealthClient {
+    addr: SocketAddr,
+}
+
+impl HealthClient {
+    fn new(addr: SocketAddr) -> HealthClient {
+        HealthClient { addr }
+    }
+
+    fn get(&self, path: &str) -> (u16, String) {
+        let url = format!("http://{}{}", self.addr, path);
+        let mut resp = reqwest::get(&url).expect("Could not send health check");
+        let status = resp.status().as_u16();
+        let body = resp.text().expect("Could not read health check response");
+        (status, body)
+    }
+}
+
 struct QueryClient {
     addr: SocketAddr,
 }
@@ -111,6 +1032,33 @@ impl QueryClient {
             .expect("Could not read query result");
         resp
     }
+
+    // Returns (event type, data) for each server-sent event in the response
+    fn query_stream(&self, q: &str) -> Vec<(String, String)> {
+        let timeout = Duration::from_millis(1000);
+        let mut stream = TcpStream::connect_timeout(&self.addr, timeout)
+            .expect("Could not connect to read server");
+        write!(stream, "stream: true\n{}", q).expect("Could not write query");
+        stream
+            .shutdown(Shutdown::Write)
+            .expect("Could not close stream");
+        let mut events = Vec::new();
+        let mut event = "message".to_string();
+        let mut data = String::new();
+        for line in BufReader::new(stream).lines() {
+            let line = line.expect("Could not read query result");
+            if line.is_empty() {
+                events.push((event, data));
+                event = "message".to_string();
+                data = String::new();
+            } else if let Some(name) = line.strip_prefix("event: ") {
+                event = name.to_string();
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data = value.trim().to_string();
+            }
+        }
+        events
+    }
 }
 
 fn assert_metric_names(resp: &str, expected: &[&str]) {
@@ -119,11 +1067,7 @@ fn assert_metric_names(resp: &str, expected: &[&str]) {
 }
 
 fn assert_windows(resp: &str, expected: &[TimeWindow]) {
-    let actual: Vec<TimeWindow> = resp
-        .trim()
-        .split("\n")
-        .filter_map(|line| parse_window(line))
-        .collect();
+    let actual: Vec<TimeWindow> = resp.trim().split("\n").filter_map(parse_window).collect();
     assert_eq!(actual, expected);
 }
 
@@ -144,40 +1088,206 @@ fn parse_window(line: &str) -> Option<TimeWindow> {
         })
 }
 
+fn parse_result_field(line: &str, key: &str) -> f64 {
+    line.split(", ")
+        .filter_map(|part| {
+            let mut kv = part.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k == key => v.parse().ok(),
+                _ => None,
+            }
+        })
+        .next()
+        .unwrap_or_else(|| panic!("Could not find {} in result {}", key, line))
+}
+
 fn with_server<T>(test: T) -> ()
 where
     T: FnOnce(InsertClient, QueryClient) -> () + panic::UnwindSafe,
 {
-    let (write_addr, read_addr, db_path) = start_server();
-    let insert_client = InsertClient::new(write_addr);
-    let query_client = QueryClient::new(read_addr);
+    let server = start_server();
+    let insert_client = InsertClient::new(server.write_addr);
+    let query_client = QueryClient::new(server.read_addr);
     let result = panic::catch_unwind(move || test(insert_client, query_client));
-    fs::remove_dir_all(&db_path).expect("Could not delete DB directory");
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+fn with_prometheus_server<T>(test: T) -> ()
+where
+    T: FnOnce(PrometheusClient, QueryClient, ScrapeClient) -> () + panic::UnwindSafe,
+{
+    let server = start_server();
+    let prometheus_client = PrometheusClient::new(server.prometheus_write_addr);
+    let query_client = QueryClient::new(server.read_addr);
+    let scrape_client = ScrapeClient::new(server.prometheus_scrape_addr);
+    let result = panic::catch_unwind(move || test(prometheus_client, query_client, scrape_client));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+fn with_scrape_server<T>(test: T) -> ()
+where
+    T: FnOnce(InsertClient, ScrapeClient) -> () + panic::UnwindSafe,
+{
+    let server = start_server();
+    let insert_client = InsertClient::new(server.write_addr);
+    let scrape_client = ScrapeClient::new(server.prometheus_scrape_addr);
+    let result = panic::catch_unwind(move || test(insert_client, scrape_client));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+fn with_http_query_server<T>(test: T) -> ()
+where
+    T: FnOnce(InsertClient, HttpQueryClient) -> () + panic::UnwindSafe,
+{
+    let server = start_server();
+    let insert_client = InsertClient::new(server.write_addr);
+    let http_query_client = HttpQueryClient::new(server.http_query_addr);
+    let result = panic::catch_unwind(move || test(insert_client, http_query_client));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+fn with_metrics_server<T>(test: T) -> ()
+where
+    T: FnOnce(InsertClient, QueryClient, ScrapeClient) -> () + panic::UnwindSafe,
+{
+    let server = start_server();
+    let insert_client = InsertClient::new(server.write_addr);
+    let query_client = QueryClient::new(server.read_addr);
+    let metrics_client = ScrapeClient::new(server.metrics_addr);
+    let result = panic::catch_unwind(move || test(insert_client, query_client, metrics_client));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
+    assert!(result.is_ok())
+}
+
+fn with_health_server<T>(test: T) -> ()
+where
+    T: FnOnce(HealthClient) -> () + panic::UnwindSafe,
+{
+    let server = start_server();
+    let health_client = HealthClient::new(server.health_addr);
+    let result = panic::catch_unwind(move || test(health_client));
+    fs::remove_dir_all(&server.db_path).expect("Could not delete DB directory");
     assert!(result.is_ok())
 }
 
-fn start_server() -> (SocketAddr, SocketAddr, String) {
+struct TestServer {
+    write_addr: SocketAddr,
+    read_addr: SocketAddr,
+    prometheus_write_addr: SocketAddr,
+    prometheus_scrape_addr: SocketAddr,
+    http_query_addr: SocketAddr,
+    metrics_addr: SocketAddr,
+    health_addr: SocketAddr,
+    db_path: String,
+}
+
+fn start_server() -> TestServer {
+    start_server_with_tls(None)
+}
+
+fn start_server_with_tls(tls_config: Option<Arc<ServerConfig>>) -> TestServer {
+    start_server_with_options(tls_config, None)
+}
+
+fn start_server_with_options(
+    tls_config: Option<Arc<ServerConfig>>,
+    auth_token: Option<AuthToken>,
+) -> TestServer {
     let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
 
     let db_path = unique_tmp_db_path();
     let db = MetricStore::open(&db_path).expect("Could not open db");
     let db_ref = Arc::new(db);
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let metrics = Arc::new(ServerMetrics::new());
 
-    let write_server = WriteServer::new(&server_addr, 1, 4096, db_ref.clone())
-        .expect("Could not start write server");
+    let write_server = WriteServer::new(
+        &server_addr,
+        1,
+        4096,
+        10,
+        Duration::from_secs(300),
+        MAX_FRAME_LEN,
+        tls_config.clone(),
+        auth_token.clone(),
+        shutdown.clone(),
+        metrics.clone(),
+        db_ref.clone(),
+    )
+    .expect("Could not start write server");
     let write_addr = write_server
         .local_addr()
         .expect("Could not retrieve write server addr");
     thread::spawn(move || write_server.run());
 
-    let read_server = ReadServer::new(&server_addr, 1, 4096, db_ref.clone())
-        .expect("Could not start read server");
+    let read_server = ReadServer::new(
+        &server_addr,
+        1,
+        4096,
+        None,
+        Duration::from_secs(1),
+        tls_config,
+        auth_token,
+        shutdown.clone(),
+        metrics.clone(),
+        db_ref.clone(),
+    )
+    .expect("Could not start read server");
     let read_addr = read_server
         .local_addr()
         .expect("Could not retrieve read server address");
     thread::spawn(move || read_server.run());
 
-    (write_addr, read_addr, db_path)
+    let prometheus_write_server = PrometheusWriteServer::new(&server_addr, 10, 2, db_ref.clone())
+        .expect("Could not start Prometheus write server");
+    let prometheus_write_addr = prometheus_write_server
+        .local_addr()
+        .expect("Could not retrieve Prometheus write server address");
+    thread::spawn(move || prometheus_write_server.run());
+
+    let prometheus_scrape_server = PrometheusScrapeServer::new(&server_addr, 300, db_ref.clone())
+        .expect("Could not start Prometheus scrape server");
+    let prometheus_scrape_addr = prometheus_scrape_server
+        .local_addr()
+        .expect("Could not retrieve Prometheus scrape server address");
+    thread::spawn(move || prometheus_scrape_server.run());
+
+    let http_query_server =
+        HttpQueryServer::new(&server_addr, None, metrics.clone(), db_ref.clone())
+            .expect("Could not start HTTP query server");
+    let http_query_addr = http_query_server
+        .local_addr()
+        .expect("Could not retrieve HTTP query server address");
+    thread::spawn(move || http_query_server.run());
+
+    let metrics_server = MetricsServer::new(&server_addr, metrics, db_ref.clone())
+        .expect("Could not start metrics server");
+    let metrics_addr = metrics_server
+        .local_addr()
+        .expect("Could not retrieve metrics server address");
+    thread::spawn(move || metrics_server.run());
+
+    let health_server =
+        HealthServer::new(&server_addr, db_ref.clone()).expect("Could not start health server");
+    let health_addr = health_server
+        .local_addr()
+        .expect("Could not retrieve health server address");
+    thread::spawn(move || health_server.run());
+
+    TestServer {
+        write_addr,
+        read_addr,
+        prometheus_write_addr,
+        prometheus_scrape_addr,
+        http_query_addr,
+        metrics_addr,
+        health_addr,
+        db_path,
+    }
 }
 
 fn unique_tmp_db_path() -> String {
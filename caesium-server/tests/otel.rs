@@ -0,0 +1,75 @@
+extern crate caesium_core;
+extern crate caesium_server;
+extern crate opentelemetry;
+extern crate opentelemetry_sdk;
+extern crate uuid;
+
+use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::window::TimeWindow;
+use caesium_server::query::execute::execute_query;
+use caesium_server::storage::store::MetricStore;
+use opentelemetry::global;
+use opentelemetry::Value;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use std::env;
+use uuid::Uuid;
+
+// Only built with `--features otel-testing`, since the in-memory exporter
+// needs the OpenTelemetry SDK's testing feature.
+#[test]
+fn it_records_query_and_storage_spans() {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    global::set_tracer_provider(provider);
+
+    let path = unique_tmp_db_path();
+    let store = MetricStore::open(&path).expect("Could not open test DB");
+    store
+        .insert(
+            "otel_test",
+            TimeWindow::new(0, 30),
+            WritableSketch::from_slice(&[1, 2, 3]),
+        )
+        .expect("Could not insert sketch");
+    let query = "quantile(fetch(\"otel_test\"), 0.5)";
+    let results = execute_query(query, &store, None).expect("Could not execute query");
+    assert_eq!(results.len(), 1);
+    drop(store);
+    MetricStore::destroy(&path).expect("Could not destroy test DB");
+
+    let spans = exporter
+        .get_finished_spans()
+        .expect("Could not retrieve spans");
+    let query_span = find_span(&spans, "caesium.query", "query.string", query);
+    assert_eq!(
+        attribute(query_span, "query.result_count"),
+        Some(Value::I64(1))
+    );
+    assert!(attribute(query_span, "query.duration_ms").is_some());
+    find_span(&spans, "caesium.storage.insert", "metric", "otel_test");
+    find_span(&spans, "caesium.storage.fetch", "metric", "otel_test");
+}
+
+fn find_span<'a>(spans: &'a [SpanData], name: &str, key: &str, value: &str) -> &'a SpanData {
+    spans
+        .iter()
+        .find(|s| s.name == name && attribute(s, key) == Some(Value::from(value.to_string())))
+        .unwrap_or_else(|| panic!("Could not find span {} with {}={}", name, key, value))
+}
+
+fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| kv.value.clone())
+}
+
+fn unique_tmp_db_path() -> String {
+    let mut path = env::temp_dir();
+    path.push(format!("testdb_{}", Uuid::new_v4()));
+    path.to_str()
+        .expect("Could not construct DB path")
+        .to_string()
+}
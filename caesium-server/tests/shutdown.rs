@@ -0,0 +1,118 @@
+extern crate caesium_core;
+extern crate caesium_server;
+extern crate uuid;
+
+use caesium_core::encode::frame::FrameEncoder;
+use caesium_core::protocol::messages::{InsertMessage, MetricKind, WriteMessage};
+use caesium_core::quantile::writable::WritableSketch;
+use caesium_core::time::window::TimeWindow;
+use caesium_server::server::metrics::ServerMetrics;
+use caesium_server::server::shutdown::install_signal_handler;
+use caesium_server::server::write::WriteServer;
+use caesium_server::storage::store::MetricStore;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::process;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+// Kept in its own test binary, since the signal handler is installed process-wide.
+#[test]
+fn it_drains_complete_inserts_on_sigterm() {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    install_signal_handler(shutdown.clone()).expect("Could not install signal handler");
+
+    let db_path = unique_tmp_db_path();
+    let db_ref = Arc::new(MetricStore::open(&db_path).expect("Could not open db"));
+    let server_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let idle_timeout = Duration::from_secs(300);
+    let server = WriteServer::new(
+        &server_addr,
+        1,
+        4096,
+        10,
+        idle_timeout,
+        MAX_FRAME_LEN,
+        None,
+        None,
+        shutdown,
+        Arc::new(ServerMetrics::new()),
+        db_ref.clone(),
+    )
+    .expect("Could not start write server");
+    let addr = server
+        .local_addr()
+        .expect("Could not retrieve write server addr");
+    let server_thread = thread::spawn(move || server.run());
+
+    let mut stream = TcpStream::connect(addr).expect("Could not connect to server");
+    let mut frame_encoder = FrameEncoder::new();
+    for i in 0..50 {
+        frame_encoder
+            .encode_framed_msg(&build_msg(&format!("m{}", i)), &mut stream)
+            .expect("Could not send framed message");
+    }
+
+    // Send only the first half of a frame, so the server never receives the full insert
+    let mut partial = Vec::new();
+    frame_encoder
+        .encode_framed_msg(&build_msg("partial"), &mut partial)
+        .expect("Could not encode framed message");
+    stream
+        .write_all(&partial[..partial.len() / 2])
+        .expect("Could not send partial frame");
+    stream.flush().expect("Could not flush stream");
+    thread::sleep(Duration::from_millis(100));
+
+    let status = Command::new("kill")
+        .arg("-TERM")
+        .arg(process::id().to_string())
+        .status()
+        .expect("Could not send SIGTERM");
+    assert!(status.success());
+    server_thread
+        .join()
+        .expect("Could not join server thread")
+        .expect("Error running write server");
+
+    let stored = db_ref.summarize_since(0).expect("Could not read db");
+    let mut metrics: Vec<String> = stored.iter().map(|(m, _)| m.clone()).collect();
+    let mut expected: Vec<String> = (0..50).map(|i| format!("m{}", i)).collect();
+    metrics.sort();
+    expected.sort();
+    assert_eq!(metrics, expected);
+    for (_, sketch) in stored {
+        assert_eq!(sketch.count(), 10);
+    }
+    drop(db_ref);
+    fs::remove_dir_all(&db_path).expect("Could not delete DB directory");
+}
+
+fn build_msg(metric: &str) -> WriteMessage {
+    let mut sketch = WritableSketch::new();
+    for i in 0..10 {
+        sketch.insert(i as u32);
+    }
+    WriteMessage::Insert(Box::new(InsertMessage {
+        metric: metric.to_string(),
+        kind: MetricKind::Timer,
+        window: TimeWindow::new(0, 30),
+        sketch,
+    }))
+}
+
+fn unique_tmp_db_path() -> String {
+    let mut path = env::temp_dir();
+    path.push(format!("testdb_{}", Uuid::new_v4()));
+    path.to_str()
+        .expect("Could not construct DB path")
+        .to_string()
+}
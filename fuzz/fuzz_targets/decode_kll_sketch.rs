@@ -0,0 +1,19 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate caesium_core;
+
+use caesium_core::encode::{Decodable, EncodableError};
+use caesium_core::quantile::kll::KllSketch;
+
+// Decoding arbitrary bytes must return an error, never panic or abort.
+fuzz_target!(|data: &[u8]| {
+    match KllSketch::decode(&mut &data[..]) {
+        Ok(_) => {}
+        Err(EncodableError::IOError(_))
+        | Err(EncodableError::FromUtf8Error(_))
+        | Err(EncodableError::FormatError(_))
+        | Err(EncodableError::LengthTooLong(_))
+        | Err(EncodableError::UnsupportedVersion(_)) => {}
+    }
+});